@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind};
+
+use crate::proof::Proof;
+use crate::root::RootHash;
+use crate::HashTree;
+
+/// How the entries of an `Allowlist` are turned into the bytes of a leaf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LeafEncoding {
+    /// The bytes of the entry as given, the default.
+    #[default]
+    Raw,
+    /// The entry is hex, optionally `0x`-prefixed and surrounded by
+    /// whitespace, e.g. an address, and the leaf is the decoded bytes.
+    Hex,
+}
+
+impl LeafEncoding {
+    /// Returns the leaf bytes of `entry`.
+    /// Returns an `Error` value if `entry` is not valid in this encoding.
+    pub fn encode(self, entry: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            LeafEncoding::Raw => Ok(entry.to_vec()),
+            LeafEncoding::Hex => {
+                let text = entry.trim_ascii();
+                let text = text.strip_prefix(b"0x").unwrap_or(text);
+                hex::decode(text).map_err(|e| {
+                    Error::new(ErrorKind::InvalidData, format!("invalid hex entry {:?}: {}", String::from_utf8_lossy(entry), e))
+                })
+            }
+        }
+    }
+}
+
+/// A tree with one leaf per entry of a list, as used for airdrops and
+/// allowlists: publish the root, then hand every member the proof for their
+/// entry.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{Allowlist, LeafEncoding};
+///
+/// let list = Allowlist::builder()
+///     .encoding(LeafEncoding::Hex)
+///     .sorted(true)
+///     .build(vec!["0x02aa", "0x01bb", "0x02aa"])
+///     .unwrap();
+/// assert_eq!(list.entries(), &[vec![0x01, 0xbb], vec![0x02, 0xaa]]);
+///
+/// let root = list.root_hash().unwrap().into_bytes();
+/// let proof = list.proof("0x02aa").unwrap();
+/// assert!(proof.verify(&root, &list.leaf_hash("0x02aa").unwrap()));
+/// assert!(list.proof("0x03cc").is_none());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Allowlist {
+    tree: HashTree,
+    entries: Vec<Vec<u8>>,
+    encoding: LeafEncoding,
+    sorted: bool,
+}
+
+impl Allowlist {
+    /// Returns a builder for an `Allowlist`.
+    pub fn builder() -> AllowlistBuilder {
+        AllowlistBuilder::new()
+    }
+
+    /// Returns the tree over the entries.
+    pub fn tree(&self) -> &HashTree {
+        &self.tree
+    }
+
+    /// Returns the root hash, or `None` if the list is empty.
+    pub fn root_hash(&self) -> Option<RootHash> {
+        self.tree.root_hash()
+    }
+
+    /// Returns the encoded entries, in leaf order.
+    pub fn entries(&self) -> &[Vec<u8>] {
+        &self.entries
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the list has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the leaf index of `entry`, or `None` if it is not listed or
+    /// not valid in the list's encoding. Listed more than once, the first
+    /// index is returned.
+    pub fn index_of<E: AsRef<[u8]>>(&self, entry: E) -> Option<usize> {
+        let leaf = self.encoding.encode(entry.as_ref()).ok()?;
+        if self.sorted {
+            let index = self.entries.partition_point(|listed| *listed < leaf);
+            (self.entries.get(index) == Some(&leaf)).then_some(index)
+        } else {
+            self.entries.iter().position(|listed| *listed == leaf)
+        }
+    }
+
+    /// Returns the inclusion proof for `entry`, or `None` if it is not listed.
+    pub fn proof<E: AsRef<[u8]>>(&self, entry: E) -> Option<Proof> {
+        self.tree.proof(self.index_of(entry)?)
+    }
+
+    /// Returns the leaf hash of `entry`, to verify its proof with.
+    /// Returns an `Error` value if `entry` is not valid in the list's encoding.
+    pub fn leaf_hash<E: AsRef<[u8]>>(&self, entry: E) -> Result<Vec<u8>, Error> {
+        Ok(self.tree.get_hasher().hash_leaf(&self.encoding.encode(entry.as_ref())?))
+    }
+}
+
+/// Configures and builds an `Allowlist`, see `Allowlist::builder`.
+#[derive(Debug, Clone)]
+pub struct AllowlistBuilder {
+    tree: HashTree,
+    encoding: LeafEncoding,
+    sorted: bool,
+    dedup: bool,
+}
+
+impl AllowlistBuilder {
+    /// Constructs a new builder for a binary SHA-256 tree over the raw
+    /// entries, in the given order and with duplicates kept.
+    pub fn new() -> Self {
+        Self { tree: HashTree::new(0), encoding: LeafEncoding::Raw, sorted: false, dedup: false }
+    }
+
+    /// Sets the empty tree to build, to choose its arity or hasher.
+    pub fn tree(mut self, tree: HashTree) -> Self {
+        self.tree = tree;
+        self
+    }
+
+    /// Sets how entries are encoded into leaves.
+    pub fn encoding(mut self, encoding: LeafEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Sorts the encoded entries before building, so the root does not
+    /// depend on the order of the input. Sorting also drops duplicates.
+    pub fn sorted(mut self, sorted: bool) -> Self {
+        self.sorted = sorted;
+        self
+    }
+
+    /// Drops repeated entries, keeping the first occurrence.
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Encodes `entries` and builds the tree over them.
+    /// Returns an `Error` value if an entry is not valid in the encoding or
+    /// the tree could not be built.
+    pub fn build<I>(self, entries: I) -> Result<Allowlist, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut leaves = entries
+            .into_iter()
+            .map(|entry| self.encoding.encode(entry.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if self.sorted {
+            leaves.sort_unstable();
+            leaves.dedup();
+        } else if self.dedup {
+            let mut seen = HashSet::new();
+            leaves.retain(|leaf| seen.insert(leaf.clone()));
+        }
+        let tree = self.tree.from_blocks(&leaves)?;
+        Ok(Allowlist { tree, entries: leaves, encoding: self.encoding, sorted: self.sorted })
+    }
+}
+
+impl Default for AllowlistBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,166 @@
+use alloc::vec::Vec;
+
+use crate::hasher::{PairHasher, Sha256Hasher};
+use crate::io::Error;
+use crate::proof::Proof;
+use crate::store::NodeStore;
+use crate::{HashTree, RootHash};
+
+/// Whether the metadata of the leaves of an `AnnotatedTree` is committed to
+/// by the root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MetadataMode {
+    /// The metadata is only stored next to the leaves, whose hashes are
+    /// those of their data alone, the default.
+    #[default]
+    Stored,
+    /// The metadata is mixed into the leaf hash, which is the hash of the
+    /// metadata length as a big-endian `u64`, the metadata and the data, so
+    /// it is verified along with the data.
+    Hashed,
+}
+
+impl MetadataMode {
+    /// Returns the leaf hash of `data` annotated with `metadata` under `hasher`.
+    pub fn leaf_hash(self, hasher: &dyn PairHasher, metadata: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            MetadataMode::Stored => hasher.hash_leaf(data),
+            MetadataMode::Hashed => {
+                let mut framed = Vec::with_capacity(8 + metadata.len() + data.len());
+                framed.extend_from_slice(&(metadata.len() as u64).to_be_bytes());
+                framed.extend_from_slice(metadata);
+                framed.extend_from_slice(data);
+                hasher.hash_leaf(&framed)
+            }
+        }
+    }
+}
+
+/// A `HashTree` whose leaves each carry a small user payload, e.g. the name
+/// of the file a record came from or how a chunk is compressed, handed out
+/// together with their proofs.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, MetadataMode};
+///
+/// let records = [(&b"zstd"[..], &b"chunk one"[..]), (b"raw", b"chunk two")];
+/// let tree = HashTree::new(0).from_annotated(records, MetadataMode::Hashed).unwrap();
+/// assert_eq!(tree.metadata(1), Some(&b"raw"[..]));
+///
+/// let root = tree.root_hash().unwrap().into_bytes();
+/// let proof = tree.proof(0).unwrap();
+/// assert_eq!(proof.metadata, b"zstd");
+/// assert!(proof.verify(&root, b"chunk one"));
+/// ```
+#[derive(Debug)]
+pub struct AnnotatedTree<S = Vec<Vec<u8>>> {
+    tree: HashTree<S>,
+    metadata: Vec<Vec<u8>>,
+    mode: MetadataMode,
+}
+
+/// An inclusion proof for a leaf of an `AnnotatedTree`, together with the
+/// leaf's metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnotatedProof {
+    /// Proof of the leaf within the tree.
+    pub proof: Proof,
+    /// Metadata of the leaf.
+    pub metadata: Vec<u8>,
+    /// Whether the metadata is part of the leaf hash.
+    pub mode: MetadataMode,
+}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Builds `self`, which should be empty, with one leaf per `(metadata,
+    /// data)` pair of `leaves`, keeping the metadata next to the leaves.
+    /// Returns an `Error` value if the store could not be written.
+    pub fn from_annotated<I, M, D>(self, leaves: I, mode: MetadataMode) -> Result<AnnotatedTree<S>, Error>
+    where
+        I: IntoIterator<Item = (M, D)>,
+        M: AsRef<[u8]>,
+        D: AsRef<[u8]>,
+    {
+        let mut metadata = Vec::new();
+        let mut hashes = Vec::new();
+        let mut data_len = 0;
+        for (meta, data) in leaves {
+            let hash = match mode {
+                MetadataMode::Stored => self.hash_block(data.as_ref())?,
+                MetadataMode::Hashed => mode.leaf_hash(self.get_hasher(), meta.as_ref(), data.as_ref()),
+            };
+            hashes.push(hash);
+            metadata.push(meta.as_ref().to_vec());
+            data_len += data.as_ref().len() as u64;
+        }
+        let mut tree = self.from_leaf_hashes(hashes)?;
+        tree.set_data_len(Some(data_len));
+        Ok(AnnotatedTree { tree, metadata, mode })
+    }
+}
+
+impl<S: NodeStore> AnnotatedTree<S> {
+    /// Returns the tree over the leaves.
+    pub fn tree(&self) -> &HashTree<S> {
+        &self.tree
+    }
+
+    /// Returns the root hash, or `None` if the tree is empty.
+    pub fn root_hash(&self) -> Option<RootHash> {
+        self.tree.root_hash()
+    }
+
+    /// Returns whether the metadata is part of the leaf hashes.
+    pub fn mode(&self) -> MetadataMode {
+        self.mode
+    }
+
+    /// Returns the metadata of the leaf at `index`, or `None` if there is no
+    /// such leaf.
+    pub fn metadata(&self, index: usize) -> Option<&[u8]> {
+        self.metadata.as_slice().get(index).map(Vec::as_slice)
+    }
+
+    /// Returns the number of leaves.
+    pub fn len(&self) -> usize {
+        self.metadata.len()
+    }
+
+    /// Returns `true` if the tree has no leaves.
+    pub fn is_empty(&self) -> bool {
+        self.metadata.is_empty()
+    }
+
+    /// Returns the inclusion proof for the leaf at `index` together with its
+    /// metadata, or `None` if there is no such leaf.
+    pub fn proof(&self, index: usize) -> Option<AnnotatedProof> {
+        Some(AnnotatedProof { proof: self.tree.proof(index)?, metadata: self.metadata(index)?.to_vec(), mode: self.mode })
+    }
+
+    /// Consumes the tree, returning the underlying `HashTree` and the
+    /// metadata of every leaf.
+    pub fn into_parts(self) -> (HashTree<S>, Vec<Vec<u8>>) {
+        (self.tree, self.metadata)
+    }
+}
+
+impl AnnotatedProof {
+    /// Returns the leaf hash of `data` with this proof's metadata under `hasher`.
+    pub fn leaf_hash(&self, hasher: &dyn PairHasher, data: &[u8]) -> Vec<u8> {
+        self.mode.leaf_hash(hasher, &self.metadata, data)
+    }
+
+    /// Returns `true` if the leaf holding `data` is included under `root`,
+    /// assuming the default SHA-256 hasher. Unless the mode is
+    /// `MetadataMode::Hashed`, the metadata itself is not verified.
+    pub fn verify(&self, root: &[u8], data: &[u8]) -> bool {
+        self.verify_with(&Sha256Hasher::new(), root, data)
+    }
+
+    /// Returns `true` if the leaf holding `data` is included under `root` using `hasher`.
+    pub fn verify_with(&self, hasher: &dyn PairHasher, root: &[u8], data: &[u8]) -> bool {
+        self.proof.verify_with(hasher, root, &self.leaf_hash(hasher, data))
+    }
+}
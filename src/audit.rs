@@ -0,0 +1,265 @@
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{BufReader, Error, ErrorKind};
+use std::path::Path;
+use sha2::{Digest, Sha256};
+
+use crate::signer::{Signer, Verifier};
+use crate::store::NodeStore;
+use crate::HashTree;
+
+/// Prefix of the message signed for an `AuditRecord`, which keeps its
+/// signatures from being valid for any other kind of message.
+const DOMAIN: &[u8] = b"hashtree audit record v1\0";
+
+/// One checkpoint of an `AuditLog`: the root of a tree at some time, chained
+/// to the record before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// Hash of the previous record, all zeroes for the first one.
+    pub prev: [u8; 32],
+    /// Time of the checkpoint, in milliseconds since the epoch.
+    pub timestamp: u64,
+    /// Number of leaves of the tree.
+    pub tree_size: u64,
+    /// Root hash of the tree.
+    pub root: Vec<u8>,
+    /// Signature over `message()`, if the record was signed.
+    pub signature: Option<Vec<u8>>,
+}
+
+impl AuditRecord {
+    /// Returns the message that is signed: a domain separator, the hash of
+    /// the previous record, the timestamp and tree size as big-endian `u64`s,
+    /// the length of the root as one byte and then the root.
+    pub fn message(&self) -> Vec<u8> {
+        let mut message = DOMAIN.to_vec();
+        message.extend_from_slice(&self.prev);
+        message.extend_from_slice(&self.timestamp.to_be_bytes());
+        message.extend_from_slice(&self.tree_size.to_be_bytes());
+        message.push(self.root.len() as u8);
+        message.extend_from_slice(&self.root);
+        message
+    }
+
+    /// Encodes the record: `message()` without the domain separator, then the
+    /// length of the signature as a big-endian `u16` (0 if unsigned) and the
+    /// signature.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.message().split_off(DOMAIN.len());
+        let signature = self.signature.as_deref().unwrap_or_default();
+        bytes.extend_from_slice(&(signature.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(signature);
+        bytes
+    }
+
+    /// Decodes a record encoded by `to_bytes`.
+    /// Returns an `Error` value if `bytes` is not a valid encoding.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let invalid = || Error::new(ErrorKind::InvalidData, "malformed audit record");
+        let field = |start: usize| -> Result<u64, Error> {
+            let bytes = bytes.get(start..start + 8).ok_or_else(invalid)?;
+            Ok(bytes.iter().fold(0u64, |n, &b| n << 8 | b as u64))
+        };
+        let timestamp = field(32)?;
+        let tree_size = field(40)?;
+        let root_len = *bytes.get(48).ok_or_else(invalid)? as usize;
+        let root = bytes.get(49..49 + root_len).ok_or_else(invalid)?;
+        let rest = &bytes[49 + root_len..];
+        if rest.len() < 2 || rest.len() - 2 != u16::from_be_bytes([rest[0], rest[1]]) as usize {
+            return Err(invalid());
+        }
+        let mut prev = [0u8; 32];
+        prev.copy_from_slice(&bytes[..32]);
+        Ok(Self {
+            prev,
+            timestamp,
+            tree_size,
+            root: root.to_vec(),
+            signature: if rest.len() > 2 { Some(rest[2..].to_vec()) } else { None },
+        })
+    }
+
+    /// Returns the hash of the record, which the next record chains to.
+    pub fn hash(&self) -> [u8; 32] {
+        Sha256::digest(self.to_bytes()).into()
+    }
+
+    /// Returns `true` if the record is signed and the signature is valid.
+    pub fn verify(&self, verifier: &dyn Verifier) -> bool {
+        match &self.signature {
+            Some(signature) => verifier.verify(&self.message(), signature),
+            None => false,
+        }
+    }
+}
+
+/// An append-only audit file of root checkpoints, for long-running
+/// integrity monitors.
+///
+/// Each `AuditRecord` holds the hash of the record before it, so records
+/// cannot be altered, dropped or reordered without breaking the chain, and
+/// may be signed so they cannot be forged either. Records must not go back
+/// in time. Since the end of the file could still be cut off, keep the
+/// `head()` hash, or the latest signed record, somewhere else to compare
+/// with when verifying.
+///
+/// Records are stored one after the other, each prefixed with its length as
+/// a big-endian `u32`.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{AuditLog, HashTree, HmacSigner};
+///
+/// let path = std::env::temp_dir().join(format!("hashtree-audit-doc-{}", std::process::id()));
+/// let signer = HmacSigner::new(b"monitor key");
+/// let mut log = AuditLog::open(&path).unwrap();
+/// let tree = HashTree::new(4).from_slice(b"watched data").unwrap();
+/// log.checkpoint(&tree, 1_700_000_000_000, Some(&signer)).unwrap();
+/// log.checkpoint(&tree, 1_700_000_060_000, Some(&signer)).unwrap();
+///
+/// let records = AuditLog::verify_file(&path, Some(&signer)).unwrap();
+/// assert_eq!(records.len(), 2);
+/// assert_eq!(records[1].hash(), log.head());
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct AuditLog {
+    file: File,
+    head: [u8; 32],
+    last_timestamp: Option<u64>,
+    len: u64,
+}
+
+impl AuditLog {
+    /// Opens the audit file at `path`, creating it if it does not exist.
+    /// Returns an `Error` value if the file could not be opened or its chain
+    /// is broken.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let records = read_chain(&file, None)?;
+        let last = records.last();
+        Ok(Self {
+            head: last.map_or([0u8; 32], AuditRecord::hash),
+            last_timestamp: last.map(|record| record.timestamp),
+            len: records.len() as u64,
+            file,
+        })
+    }
+
+    /// Appends a record of `root` of a tree of `tree_size` leaves at
+    /// `timestamp`, signed by `signer` if given, and returns it.
+    /// Returns an `Error` value if the root is longer than 255 bytes, the
+    /// timestamp is older than the last record's, or signing or writing fails.
+    pub fn append(&mut self, root: &[u8], tree_size: u64, timestamp: u64, signer: Option<&dyn Signer>) -> Result<AuditRecord, Error> {
+        if root.len() > usize::from(u8::MAX) {
+            return Err(Error::new(ErrorKind::InvalidInput, "root hash is longer than 255 bytes"));
+        }
+        if self.last_timestamp.is_some_and(|last| timestamp < last) {
+            return Err(Error::new(ErrorKind::InvalidInput, "timestamp is older than the last record"));
+        }
+        let mut record = AuditRecord { prev: self.head, timestamp, tree_size, root: root.to_vec(), signature: None };
+        if let Some(signer) = signer {
+            let signature = signer.sign(&record.message())?;
+            if signature.len() > usize::from(u16::MAX) {
+                return Err(Error::new(ErrorKind::InvalidInput, "signature is longer than 65535 bytes"));
+            }
+            record.signature = Some(signature).filter(|signature| !signature.is_empty());
+        }
+
+        let bytes = record.to_bytes();
+        let mut entry = (bytes.len() as u32).to_be_bytes().to_vec();
+        entry.extend_from_slice(&bytes);
+        self.file.write_all(&entry)?;
+        self.file.flush()?;
+        self.head = record.hash();
+        self.last_timestamp = Some(timestamp);
+        self.len += 1;
+        Ok(record)
+    }
+
+    /// Appends a record of the root and number of leaves of `tree`, see
+    /// `append`. Returns an `Error` value if the tree is empty.
+    pub fn checkpoint<S: NodeStore>(&mut self, tree: &HashTree<S>, timestamp: u64, signer: Option<&dyn Signer>) -> Result<AuditRecord, Error> {
+        let root = tree.root_hash_bytes().ok_or_else(|| Error::new(ErrorKind::InvalidInput, "tree is empty"))?;
+        self.append(root, tree.num_blocks(), timestamp, signer)
+    }
+
+    /// Like `checkpoint`, but only appends a record if at least `interval`
+    /// milliseconds passed since the last one, so a monitor can call it on
+    /// every pass. Returns the record if one was appended.
+    pub fn checkpoint_if_due<S: NodeStore>(
+        &mut self,
+        tree: &HashTree<S>,
+        timestamp: u64,
+        interval: u64,
+        signer: Option<&dyn Signer>,
+    ) -> Result<Option<AuditRecord>, Error> {
+        match self.last_timestamp {
+            Some(last) if timestamp.saturating_sub(last) < interval => Ok(None),
+            _ => self.checkpoint(tree, timestamp, signer).map(Some),
+        }
+    }
+
+    /// Returns the hash of the last record, all zeroes if there is none.
+    pub fn head(&self) -> [u8; 32] {
+        self.head
+    }
+
+    /// Returns the number of records.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if there are no records.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads and checks the chain of the audit file at `path`, returning its
+    /// records. If `verifier` is given, every record must carry a valid
+    /// signature.
+    /// Returns an `Error` value if the file could not be read or the chain
+    /// is broken.
+    pub fn verify_file<P: AsRef<Path>>(path: P, verifier: Option<&dyn Verifier>) -> Result<Vec<AuditRecord>, Error> {
+        read_chain(&File::open(path)?, verifier)
+    }
+}
+
+/// Reads the records of an audit file, checking that each chains to the one
+/// before it, that time does not go back and, given a `verifier`, that
+/// every record is validly signed.
+fn read_chain(file: &File, verifier: Option<&dyn Verifier>) -> Result<Vec<AuditRecord>, Error> {
+    let invalid = |index: usize, message: &str| Error::new(ErrorKind::InvalidData, format!("audit record {}: {}", index, message));
+    let end = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut records: Vec<AuditRecord> = Vec::new();
+    let mut offset = 0;
+    while offset < end {
+        let index = records.len();
+        let mut len = [0u8; 4];
+        reader.read_exact(&mut len).map_err(|_| invalid(index, "truncated"))?;
+        let len = u32::from_be_bytes(len) as u64;
+        if end - offset - 4 < len {
+            return Err(invalid(index, "truncated"));
+        }
+        let mut bytes = vec![0u8; len as usize];
+        reader.read_exact(&mut bytes)?;
+        offset += 4 + len;
+
+        let record = AuditRecord::from_bytes(&bytes)?;
+        let prev = records.last();
+        if record.prev != prev.map_or([0u8; 32], AuditRecord::hash) {
+            return Err(invalid(index, "does not chain to the previous record"));
+        }
+        if prev.is_some_and(|prev| record.timestamp < prev.timestamp) {
+            return Err(invalid(index, "is older than the previous record"));
+        }
+        if verifier.is_some_and(|verifier| !record.verify(verifier)) {
+            return Err(invalid(index, "has no valid signature"));
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
@@ -0,0 +1,98 @@
+use std::io::Error;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+#[cfg(not(all(target_family = "wasm", not(target_feature = "atomics"))))]
+use std::thread;
+use std::vec::IntoIter;
+
+use crate::HashTree;
+
+/// The result of hashing one file with `HashTree::hash_files`.
+pub type FileResult = (PathBuf, Result<HashTree, Error>);
+
+impl HashTree {
+    /// Hashes the files at `paths` on up to `workers` threads, each into a
+    /// tree with the parameters of `self`, which should be empty. Results
+    /// arrive on the returned channel as files complete, which is not
+    /// necessarily the order of `paths`; the channel closes once every file
+    /// has been hashed. Dropping the receiver stops the workers after the
+    /// files they are hashing.
+    ///
+    /// On targets without threads, such as wasm32-wasi, every file is hashed
+    /// on the calling thread before the channel is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `workers` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let dir = std::env::temp_dir().join(format!("hashtree-doc-batch-{}", std::process::id()));
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// let paths: Vec<_> = (0..8).map(|i| dir.join(i.to_string())).collect();
+    /// for (i, path) in paths.iter().enumerate() {
+    ///     std::fs::write(path, vec![i as u8; 1000]).unwrap();
+    /// }
+    ///
+    /// let results = HashTree::new(64).hash_files(paths.clone(), 3);
+    /// assert_eq!(results.iter().filter(|(_, tree)| tree.is_ok()).count(), 8);
+    /// std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn hash_files<I>(&self, paths: I, workers: usize) -> Receiver<FileResult>
+    where
+        I: IntoIterator,
+        I::Item: Into<PathBuf>,
+    {
+        assert!(workers > 0, "at least one worker is needed");
+        let paths: Vec<PathBuf> = paths.into_iter().map(Into::into).collect();
+        let workers = workers.min(paths.len());
+        let queue = Arc::new(Mutex::new(paths.into_iter()));
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..workers {
+            let (queue, sender, template) = (queue.clone(), sender.clone(), self.clone());
+            let worker = move || hash_queued(&queue, &sender, &template);
+            #[cfg(not(all(target_family = "wasm", not(target_feature = "atomics"))))]
+            thread::spawn(worker);
+            #[cfg(all(target_family = "wasm", not(target_feature = "atomics")))]
+            {
+                worker();
+                break;
+            }
+        }
+        receiver
+    }
+
+    /// Hashes the files at `paths` like `hash_files` and calls `on_file`
+    /// on the calling thread with each result as it completes. Returns once
+    /// every file has been hashed.
+    pub fn hash_files_each<I, F>(&self, paths: I, workers: usize, mut on_file: F)
+    where
+        I: IntoIterator,
+        I::Item: Into<PathBuf>,
+        F: FnMut(PathBuf, Result<HashTree, Error>),
+    {
+        for (path, tree) in self.hash_files(paths, workers) {
+            on_file(path, tree);
+        }
+    }
+}
+
+/// Hashes the files taken from `queue` until it is empty or the receiver
+/// of `sender` is dropped.
+fn hash_queued(queue: &Mutex<IntoIter<PathBuf>>, sender: &mpsc::Sender<FileResult>, template: &HashTree) {
+    loop {
+        let next = queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).next();
+        let path = match next {
+            Some(path) => path,
+            None => return,
+        };
+        let tree = template.clone().from_file(&path);
+        if sender.send((path, tree)).is_err() {
+            return;
+        }
+    }
+}
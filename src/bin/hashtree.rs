@@ -0,0 +1,208 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Args, Parser, Subcommand};
+use hashtree::{HashTree, Proof, ProofLevel, RootHash, Sha256Hasher};
+use serde_json::{json, Value};
+
+/// Computes and checks Merkle trees of files.
+#[derive(Parser)]
+#[command(name = "hashtree", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Parameters of the trees built from files.
+#[derive(Args)]
+struct TreeOptions {
+    /// Size of the blocks the file is split into.
+    #[arg(short, long, default_value_t = 4096)]
+    block_size: usize,
+    /// Number of children of every interior node.
+    #[arg(short, long, default_value_t = 2, value_parser = clap::value_parser!(u32).range(2..))]
+    arity: u32,
+}
+
+impl TreeOptions {
+    fn tree(&self) -> HashTree {
+        HashTree::new(self.block_size).arity(self.arity as usize)
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Prints the root hash of a file, optionally saving its tree.
+    Hash {
+        /// File to hash.
+        file: PathBuf,
+        #[command(flatten)]
+        options: TreeOptions,
+        /// Writes the serialized tree to this path.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Checks a file against a tree saved by `hash --output`.
+    Verify {
+        /// File to check.
+        file: PathBuf,
+        /// Serialized tree to check against.
+        tree: PathBuf,
+    },
+    /// Prints the parameters and root of a saved tree.
+    Info {
+        /// Serialized tree.
+        tree: PathBuf,
+    },
+    /// Prints the inclusion proof of one block of a file as JSON.
+    Proof {
+        /// File containing the block.
+        file: PathBuf,
+        /// Index of the block to prove.
+        #[arg(long)]
+        block: usize,
+        #[command(flatten)]
+        options: TreeOptions,
+    },
+    /// Checks a JSON proof printed by `proof` against a root.
+    ProofVerify {
+        /// Expected root hash, in hex.
+        #[arg(long)]
+        root: RootHash,
+        /// File holding the proof. Read from standard input if omitted.
+        proof: Option<PathBuf>,
+    },
+    /// Prints the ranges of blocks that differ between two files as JSON.
+    Diff {
+        /// First file.
+        file_a: PathBuf,
+        /// Second file.
+        file_b: PathBuf,
+        #[command(flatten)]
+        options: TreeOptions,
+    },
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse().command) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("hashtree: {}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Runs `command`. Returns `false` if a verification failed.
+fn run(command: Command) -> Result<bool, Error> {
+    match command {
+        Command::Hash { file, options, output } => {
+            let tree = options.tree().from_file(&file)?;
+            if let Some(output) = output {
+                tree.write_to(BufWriter::new(File::create(output)?))?;
+            }
+            println!("{}  {}", root_hex(&tree), file.display());
+        }
+        Command::Verify { file, tree } => {
+            let expected = load(&tree)?;
+            let actual = HashTree::new(expected.block_size()).arity(expected.get_arity()).from_file(&file)?;
+            if actual != expected {
+                let blocks: Vec<String> = expected.diff(&actual).iter().map(|r| format!("{}..{}", r.start, r.end)).collect();
+                println!("{}: FAILED (blocks {})", file.display(), blocks.join(", "));
+                return Ok(false);
+            }
+            println!("{}: OK", file.display());
+        }
+        Command::Info { tree } => {
+            let tree = load(&tree)?;
+            println!("root        {}", root_hex(&tree));
+            println!("block size  {}", tree.block_size());
+            println!("arity       {}", tree.get_arity());
+            println!("blocks      {}", tree.num_blocks());
+            println!("nodes       {}", tree.num_nodes());
+        }
+        Command::Proof { file, block, options } => {
+            let tree = options.tree().from_file(&file)?;
+            let proof = tree.proof(block).ok_or_else(|| {
+                invalid(format!("block {} out of range, {} has {} blocks", block, file.display(), tree.num_blocks()))
+            })?;
+            let levels: Vec<Value> = proof
+                .levels
+                .iter()
+                .map(|level| json!({ "position": level.position, "siblings": level.siblings.iter().map(hex::encode).collect::<Vec<_>>() }))
+                .collect();
+            let output = json!({
+                "root": root_hex(&tree),
+                "leaf_index": proof.leaf_index,
+                "leaf": hex::encode(&tree.store()[block]),
+                "levels": levels,
+            });
+            println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        }
+        Command::ProofVerify { root, proof } => {
+            let mut text = String::new();
+            match proof {
+                Some(path) => File::open(path)?.read_to_string(&mut text)?,
+                None => std::io::stdin().read_to_string(&mut text)?,
+            };
+            let (leaf, proof) = parse_proof(&text)?;
+            let computed = proof.compute_root_with(&Sha256Hasher::new(), &leaf);
+            let valid = root == *computed.as_slice();
+            let output = json!({ "valid": valid, "leaf_index": proof.leaf_index, "computed_root": hex::encode(computed) });
+            println!("{}", serde_json::to_string_pretty(&output).unwrap());
+            return Ok(valid);
+        }
+        Command::Diff { file_a, file_b, options } => {
+            let a = options.tree().from_file(&file_a)?;
+            let b = options.tree().from_file(&file_b)?;
+            let ranges: Vec<[u64; 2]> = a.diff(&b).iter().map(|range| [range.start, range.end]).collect();
+            let output = json!({
+                "equal": ranges.is_empty(),
+                "block_size": options.block_size,
+                "blocks_a": a.num_blocks(),
+                "blocks_b": b.num_blocks(),
+                "ranges": ranges,
+            });
+            println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        }
+    }
+    Ok(true)
+}
+
+/// Parses a proof printed by the `proof` subcommand into the leaf hash and the proof.
+fn parse_proof(text: &str) -> Result<(Vec<u8>, Proof), Error> {
+    let value: Value = serde_json::from_str(text).map_err(|e| invalid(format!("invalid proof: {}", e)))?;
+    let hash = |value: &Value| -> Result<Vec<u8>, Error> {
+        let text = value.as_str().ok_or_else(|| invalid("expected a hex string".to_string()))?;
+        hex::decode(text).map_err(|e| invalid(format!("invalid hash {:?}: {}", text, e)))
+    };
+    let number = |value: &Value| value.as_u64().map(|n| n as usize).ok_or_else(|| invalid("expected a number".to_string()));
+
+    let leaf = hash(&value["leaf"])?;
+    let leaf_index = value["leaf_index"].as_u64().ok_or_else(|| invalid("expected a number".to_string()))?;
+    let mut levels = Vec::new();
+    for level in value["levels"].as_array().ok_or_else(|| invalid("expected a list of levels".to_string()))? {
+        let siblings = level["siblings"].as_array().ok_or_else(|| invalid("expected a list of siblings".to_string()))?;
+        levels.push(ProofLevel {
+            position: number(&level["position"])?,
+            siblings: siblings.iter().map(hash).collect::<Result<_, _>>()?,
+        });
+    }
+    Ok((leaf, Proof { leaf_index, levels }))
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Reads a tree written by `HashTree::write_to`.
+fn load(path: &PathBuf) -> Result<HashTree, Error> {
+    HashTree::new(0).read_from(BufReader::new(File::open(path)?))
+}
+
+fn root_hex(tree: &HashTree) -> String {
+    tree.root_hash().map(|root| root.to_hex()).unwrap_or_else(|| "-".to_string())
+}
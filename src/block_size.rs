@@ -0,0 +1,56 @@
+/// Smallest block size chosen by `BlockSize::Auto`.
+const AUTO_MIN: usize = 1 << 10;
+/// Largest block size chosen by `BlockSize::Auto`.
+const AUTO_MAX: usize = 1 << 24;
+/// Block size chosen by `BlockSize::Auto` when the input length is unknown.
+const AUTO_UNKNOWN: usize = 1 << 16;
+/// Number of leaves `BlockSize::Auto` stays within when it can.
+const AUTO_TARGET_LEAVES: u64 = 1 << 16;
+
+/// The size of the blocks a `HashTree` splits its input into, see
+/// `HashTree::with_block_size`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockSize {
+    /// Blocks of exactly this many bytes.
+    Fixed(usize),
+    /// The smallest power of two from 1 KiB to 16 MiB that keeps the tree
+    /// within 65536 leaves, so proofs stay at most 16 levels deep. Picked
+    /// from the input length once it is known, or 64 KiB for input read
+    /// from a stream of unknown length.
+    Auto,
+}
+
+impl BlockSize {
+    /// Returns the block size in bytes for an input of `len` bytes, or of
+    /// unknown length if `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::BlockSize;
+    ///
+    /// assert_eq!(BlockSize::Fixed(4096).resolve(Some(1 << 30)), 4096);
+    /// assert_eq!(BlockSize::Auto.resolve(Some(1 << 20)), 1024);
+    /// assert_eq!(BlockSize::Auto.resolve(Some(1 << 30)), 16 * 1024);
+    /// assert_eq!(BlockSize::Auto.resolve(None), 64 * 1024);
+    /// ```
+    pub fn resolve(self, len: Option<u64>) -> usize {
+        match (self, len) {
+            (BlockSize::Fixed(block_size), _) => block_size,
+            (BlockSize::Auto, None) => AUTO_UNKNOWN,
+            (BlockSize::Auto, Some(len)) => {
+                let mut block_size = AUTO_MIN;
+                while block_size < AUTO_MAX && len.div_ceil(block_size as u64) > AUTO_TARGET_LEAVES {
+                    block_size *= 2;
+                }
+                block_size
+            }
+        }
+    }
+}
+
+impl From<usize> for BlockSize {
+    fn from(block_size: usize) -> Self {
+        BlockSize::Fixed(block_size)
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::store::NodeStore;
+use crate::HashTree;
+
+/// Storage for the contents of blocks, keyed by their leaf hash.
+///
+/// Set one with `HashTree::block_store` to keep the blocks a tree is built
+/// from, then read them back with `HashTree::get_block` or reassemble the
+/// whole input with `HashTree::write_blocks`. Blocks with the same contents
+/// share a key, so duplicated blocks are stored once. Methods take `&self`
+/// so a store can be shared between trees; implementations synchronize
+/// internally.
+pub trait BlockStore: fmt::Debug + Send + Sync {
+    /// Stores `data` under its leaf hash `hash`. Storing a hash that is
+    /// already present may be skipped.
+    fn put(&self, hash: &[u8], data: &[u8]) -> Result<(), Error>;
+
+    /// Returns the contents stored under `hash`, or `None` if there are none.
+    fn get(&self, hash: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Returns `true` if contents are stored under `hash`.
+    fn contains(&self, hash: &[u8]) -> Result<bool, Error> {
+        Ok(self.get(hash)?.is_some())
+    }
+
+    /// Removes the contents stored under `hash`. Returns `true` if there
+    /// were any. The default implementation returns an `Unsupported` error,
+    /// so stores that cannot delete are never garbage collected.
+    fn remove(&self, hash: &[u8]) -> Result<bool, Error> {
+        let _ = hash;
+        Err(Error::new(ErrorKind::Unsupported, "block store does not support removal"))
+    }
+
+    /// Returns the hash of every block stored. The default implementation
+    /// returns an `Unsupported` error.
+    fn hashes(&self) -> Result<Vec<Vec<u8>>, Error> {
+        Err(Error::new(ErrorKind::Unsupported, "block store does not support listing"))
+    }
+}
+
+/// The outcome of `collect_garbage`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GcStats {
+    /// Number of distinct blocks referenced by the live trees.
+    pub live: usize,
+    /// Number of blocks found in the store.
+    pub scanned: usize,
+    /// Number of blocks removed.
+    pub removed: usize,
+}
+
+/// Removes every block of `store` that no tree in `live` references, e.g.
+/// after dropping superseded generations of a backup.
+///
+/// The mark phase collects the leaf hashes of the live trees, the sweep
+/// phase lists the store and removes the rest. Blocks are only ever looked
+/// up by leaf hash, so a block no live tree references can never be read
+/// again. Trees must not be built into the store while it is collected: a
+/// tree storing a block that already exists skips the write, and the block
+/// could be swept before that tree is counted as live.
+///
+/// Returns an `Error` value if the store cannot list or remove blocks; blocks
+/// removed before the failure stay removed.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{collect_garbage, HashTree, MemoryBlockStore};
+/// use std::sync::Arc;
+///
+/// let store = Arc::new(MemoryBlockStore::new());
+/// let old = HashTree::new(4).block_store(store.clone()).from_slice(b"aaaabbbb").unwrap();
+/// let new = HashTree::new(4).block_store(store.clone()).from_slice(b"aaaacccc").unwrap();
+/// assert_eq!(store.len(), 3);
+///
+/// drop(old);
+/// let stats = collect_garbage(&store, [&new]).unwrap();
+/// assert_eq!((stats.live, stats.removed), (2, 1));
+/// assert_eq!(new.get_block(1).unwrap(), b"cccc");
+/// ```
+pub fn collect_garbage<'a, B, S, I>(store: &B, live: I) -> Result<GcStats, Error>
+where
+    B: BlockStore + ?Sized,
+    S: NodeStore + 'a,
+    I: IntoIterator<Item = &'a HashTree<S>>,
+{
+    let marked: HashSet<Vec<u8>> = live.into_iter().flat_map(HashTree::leaf_hashes).collect();
+    let mut stats = GcStats { live: marked.len(), ..GcStats::default() };
+    for hash in store.hashes()? {
+        stats.scanned += 1;
+        if !marked.contains(&hash) && store.remove(&hash)? {
+            stats.removed += 1;
+        }
+    }
+    Ok(stats)
+}
+
+/// A `BlockStore` keeping blocks in memory.
+#[derive(Debug, Default)]
+pub struct MemoryBlockStore {
+    blocks: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl MemoryBlockStore {
+    /// Constructs a new empty `MemoryBlockStore`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of distinct blocks stored.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Returns `true` if no blocks are stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Vec<u8>, Vec<u8>>> {
+        self.blocks.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl BlockStore for MemoryBlockStore {
+    fn put(&self, hash: &[u8], data: &[u8]) -> Result<(), Error> {
+        self.lock().entry(hash.to_vec()).or_insert_with(|| data.to_vec());
+        Ok(())
+    }
+
+    fn get(&self, hash: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.lock().get(hash).cloned())
+    }
+
+    fn contains(&self, hash: &[u8]) -> Result<bool, Error> {
+        Ok(self.lock().contains_key(hash))
+    }
+
+    fn remove(&self, hash: &[u8]) -> Result<bool, Error> {
+        Ok(self.lock().remove(hash).is_some())
+    }
+
+    fn hashes(&self) -> Result<Vec<Vec<u8>>, Error> {
+        Ok(self.lock().keys().cloned().collect())
+    }
+}
+
+/// A `BlockStore` keeping every block in its own file, named after the hex
+/// leaf hash, in a directory.
+///
+/// Files are spread over subdirectories named after the first two hex
+/// digits of the hash, as git does for loose objects, so no directory grows
+/// too large. New blocks are written to a temporary file and renamed into
+/// place, so a crash never leaves a truncated block behind.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{FileBlockStore, HashTree};
+///
+/// let dir = std::env::temp_dir().join(format!("hashtree-doc-blocks-{}", std::process::id()));
+/// let store = FileBlockStore::open(&dir).unwrap();
+/// let tree = HashTree::new(4).block_store(store).from_data(&mut &b"abcdefgh"[..]).unwrap();
+/// assert_eq!(tree.get_block(1).unwrap(), b"efgh");
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct FileBlockStore {
+    dir: PathBuf,
+}
+
+impl FileBlockStore {
+    /// Uses the directory at `dir`, creating it if needed.
+    /// Returns an `Error` value if the directory could not be created.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir: dir.as_ref().to_path_buf() })
+    }
+
+    /// Returns the directory holding the blocks.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the path of the file holding the block with hash `hash`.
+    pub fn path(&self, hash: &[u8]) -> PathBuf {
+        let name = hex::encode(hash);
+        self.dir.join(&name[..name.len().min(2)]).join(&name)
+    }
+}
+
+/// Returns an id telling concurrent writers of a `FileBlockStore` apart in
+/// the names of their temporary files: the process id, or on WASI, which
+/// has none, the current time.
+fn writer_id() -> u128 {
+    #[cfg(not(target_os = "wasi"))]
+    return std::process::id().into();
+    #[cfg(target_os = "wasi")]
+    return std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |time| time.as_nanos());
+}
+
+impl BlockStore for FileBlockStore {
+    fn put(&self, hash: &[u8], data: &[u8]) -> Result<(), Error> {
+        let path = self.path(hash);
+        if path.exists() {
+            return Ok(());
+        }
+        let parent = path.parent().expect("block paths are inside the store directory");
+        fs::create_dir_all(parent)?;
+        let temp = path.with_extension(format!("tmp{}", writer_id()));
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&temp, &path)
+    }
+
+    fn get(&self, hash: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        match fs::read(self.path(hash)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn contains(&self, hash: &[u8]) -> Result<bool, Error> {
+        Ok(self.path(hash).exists())
+    }
+
+    fn remove(&self, hash: &[u8]) -> Result<bool, Error> {
+        match fs::remove_file(self.path(hash)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn hashes(&self) -> Result<Vec<Vec<u8>>, Error> {
+        let mut hashes = Vec::new();
+        for subdir in fs::read_dir(&self.dir)? {
+            let subdir = subdir?;
+            if !subdir.file_type()?.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(subdir.path())? {
+                // Temporary files of blocks being written are not valid hex
+                if let Some(hash) = file?.file_name().to_str().and_then(|name| hex::decode(name).ok()) {
+                    hashes.push(hash);
+                }
+            }
+        }
+        Ok(hashes)
+    }
+}
+
+impl<B: BlockStore + ?Sized> BlockStore for std::sync::Arc<B> {
+    fn put(&self, hash: &[u8], data: &[u8]) -> Result<(), Error> {
+        (**self).put(hash, data)
+    }
+
+    fn get(&self, hash: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        (**self).get(hash)
+    }
+
+    fn contains(&self, hash: &[u8]) -> Result<bool, Error> {
+        (**self).contains(hash)
+    }
+
+    fn remove(&self, hash: &[u8]) -> Result<bool, Error> {
+        (**self).remove(hash)
+    }
+
+    fn hashes(&self) -> Result<Vec<Vec<u8>>, Error> {
+        (**self).hashes()
+    }
+}
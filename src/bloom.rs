@@ -0,0 +1,129 @@
+use std::io::{Error, ErrorKind};
+
+use sha2::{Digest, Sha256};
+
+use crate::store::NodeStore;
+use crate::HashTree;
+
+const MAGIC: &[u8; 4] = b"HTBF";
+const HEADER_LEN: usize = 13;
+
+/// A Bloom filter over the leaf hashes of a `HashTree`, see
+/// `HashTree::bloom_filter`.
+///
+/// `maybe_contains` never misses a hash that was added, but may report a
+/// hash that was not, at about the false positive rate the filter was sized
+/// for. A peer holding the filter can thus skip requesting proofs for
+/// blocks that are definitely not in the tree.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{BloomFilter, HashTree, PairHasher, Sha256Hasher};
+///
+/// let tree = HashTree::new(4).from_slice(b"some blocks of data").unwrap();
+/// let filter = tree.bloom_filter(0.01);
+/// assert!(filter.maybe_contains(&Sha256Hasher::new().hash_leaf(b"some")));
+///
+/// let received = BloomFilter::from_bytes(&filter.to_bytes()).unwrap();
+/// assert_eq!(received, filter);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    num_bits: u64,
+    num_hashes: u8,
+}
+
+impl BloomFilter {
+    /// Constructs an empty `BloomFilter` sized for `expected` hashes at the
+    /// given false positive rate, which is clamped to at least `1e-9`.
+    pub fn new(expected: usize, false_positive_rate: f64) -> Self {
+        let rate = false_positive_rate.clamp(1e-9, 1.0);
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = ((-(expected.max(1) as f64) * rate.ln() / (ln2 * ln2)).ceil() as u64).max(8);
+        let num_hashes = ((num_bits as f64 / expected.max(1) as f64) * ln2).round().clamp(1.0, 32.0) as u8;
+        Self { bits: vec![0; num_bits.div_ceil(8) as usize], num_bits, num_hashes }
+    }
+
+    /// Adds `hash` to the filter.
+    pub fn insert(&mut self, hash: &[u8]) {
+        for bit in self.bit_indices(hash) {
+            self.bits[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Returns `false` if `hash` was definitely not added to the filter, and
+    /// `true` if it probably was.
+    pub fn maybe_contains(&self, hash: &[u8]) -> bool {
+        self.bit_indices(hash).all(|bit| self.bits[(bit / 8) as usize] & 1 << (bit % 8) != 0)
+    }
+
+    /// Returns the number of bits of the filter.
+    pub fn num_bits(&self) -> u64 {
+        self.num_bits
+    }
+
+    /// Returns the number of bits set per hash.
+    pub fn num_hashes(&self) -> u8 {
+        self.num_hashes
+    }
+
+    /// Encodes the filter for sending to a peer: the magic `HTBF`, the
+    /// number of bits per hash, the number of bits as a big-endian `u64` and
+    /// then the bits.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.bits.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(self.num_hashes);
+        bytes.extend_from_slice(&self.num_bits.to_be_bytes());
+        bytes.extend_from_slice(&self.bits);
+        bytes
+    }
+
+    /// Decodes a filter encoded by `to_bytes`.
+    /// Returns an `Error` value if `bytes` is not a valid encoding.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return Err(invalid("not a Bloom filter"));
+        }
+        let num_hashes = bytes[4];
+        let mut num_bits = [0u8; 8];
+        num_bits.copy_from_slice(&bytes[5..HEADER_LEN]);
+        let num_bits = u64::from_be_bytes(num_bits);
+        if num_hashes == 0 || num_bits == 0 || num_bits.div_ceil(8) != (bytes.len() - HEADER_LEN) as u64 {
+            return Err(invalid("malformed Bloom filter"));
+        }
+        Ok(Self { bits: bytes[HEADER_LEN..].to_vec(), num_bits, num_hashes })
+    }
+
+    /// Returns the bits set for `hash`, by double hashing. Leaf hashes are
+    /// uniformly distributed already, so their first 16 bytes are used
+    /// directly; shorter hashes are hashed first.
+    fn bit_indices(&self, hash: &[u8]) -> impl Iterator<Item = u64> {
+        let digest;
+        let bytes = if hash.len() >= 16 {
+            hash
+        } else {
+            digest = Sha256::digest(hash);
+            &digest[..]
+        };
+        let word = |range: core::ops::Range<usize>| bytes[range].iter().fold(0u64, |n, &b| n << 8 | b as u64);
+        let (h1, h2) = (word(0..8), word(8..16) | 1);
+        let num_bits = self.num_bits;
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Returns a `BloomFilter` over the leaf hashes, sized for the given
+    /// false positive rate, e.g. `0.01` for 1%.
+    pub fn bloom_filter(&self, false_positive_rate: f64) -> BloomFilter {
+        let mut filter = BloomFilter::new(self.stored_leaves(), false_positive_rate);
+        for hash in self.leaf_hashes() {
+            filter.insert(&hash);
+        }
+        filter
+    }
+}
@@ -0,0 +1,147 @@
+use std::io::{Error, ErrorKind, Read};
+
+use crate::chunker::{Chunker, FixedChunker};
+use crate::hasher::{block_buf, fingerprint};
+use crate::io::to_usize;
+use crate::store::NodeStore;
+use crate::HashTree;
+
+const MAGIC: &[u8; 4] = b"HTCP";
+const FORMAT_VERSION: u16 = 1;
+const HEADER_LEN: usize = 48;
+
+/// Builds a `HashTree` over a long input in steps that can be saved with
+/// `checkpoint` and picked up again with `HashTree::resume`, e.g. after the
+/// process restarts.
+///
+/// A checkpoint holds the leaf hashes computed so far and the number of
+/// input bytes they cover; the interior nodes are only built by `finish`.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::HashTree;
+///
+/// let data = vec![7u8; 10_000];
+/// let mut builder = HashTree::new(1024).resumable();
+/// builder.hash_blocks(&mut &data[..], 4).unwrap();
+/// let saved = builder.checkpoint().unwrap();
+/// drop(builder);
+///
+/// let mut builder = HashTree::new(1024).resume(&saved).unwrap();
+/// let offset = builder.offset() as usize;
+/// builder.hash_blocks(&mut &data[offset..], usize::MAX).unwrap();
+/// assert_eq!(builder.finish().unwrap(), HashTree::new(1024).from_slice(&data).unwrap());
+/// ```
+#[derive(Debug)]
+pub struct ResumableBuilder<S = Vec<Vec<u8>>> {
+    tree: HashTree<S>,
+    leaves: Vec<Vec<u8>>,
+    offset: u64,
+    complete: bool,
+}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Returns a `ResumableBuilder` that builds `self`, which should be
+    /// empty, from input fed in steps.
+    pub fn resumable(self) -> ResumableBuilder<S> {
+        ResumableBuilder { tree: self, leaves: Vec::new(), offset: 0, complete: false }
+    }
+
+    /// Resumes building `self`, which should be empty and use the block
+    /// size, arity and hasher of the saved builder, from a `checkpoint`.
+    /// Returns an `Error` value if the checkpoint is malformed or was taken
+    /// with other parameters.
+    pub fn resume(self, checkpoint: &[u8]) -> Result<ResumableBuilder<S>, Error> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+        if checkpoint.len() < HEADER_LEN || &checkpoint[..4] != MAGIC {
+            return Err(invalid("not a hash tree checkpoint"));
+        }
+        let field = |start: usize, len: usize| checkpoint[start..start + len].iter().fold(0u64, |n, &b| n << 8 | b as u64);
+        if field(4, 2) != FORMAT_VERSION as u64 {
+            return Err(invalid("unsupported checkpoint version"));
+        }
+        if checkpoint[8..16] != fingerprint(self.get_hasher()) {
+            return Err(invalid("checkpoint was taken with a different hash algorithm"));
+        }
+        if field(16, 8) != self.block_size() as u64 || field(24, 4) != self.get_arity() as u64 {
+            return Err(invalid("checkpoint was taken with a different block size or arity"));
+        }
+        let (hash_len, num_leaves, offset) = (field(28, 4) as usize, field(32, 8), field(40, 8));
+        let leaves_len = to_usize(num_leaves)?.checked_mul(hash_len);
+        if leaves_len != Some(checkpoint.len() - HEADER_LEN) || (hash_len == 0 && num_leaves > 0) {
+            return Err(invalid("checkpoint is truncated"));
+        }
+        let leaves = checkpoint[HEADER_LEN..].chunks(hash_len.max(1)).map(|leaf| leaf.to_vec()).collect();
+        Ok(ResumableBuilder { tree: self, leaves, offset, complete: checkpoint[6] == 1 })
+    }
+}
+
+impl<S: NodeStore> ResumableBuilder<S> {
+    /// Hashes up to `max_blocks` blocks from `reader`, which must continue
+    /// the input at `offset`. Returns the number of blocks hashed; fewer than
+    /// `max_blocks` means the input ended.
+    /// Returns an `Error` value if `reader` could not be read or the input
+    /// already ended with a partial block.
+    pub fn hash_blocks<R: Read>(&mut self, reader: R, max_blocks: usize) -> Result<usize, Error> {
+        let block_size = self.tree.block_size();
+        let mut chunker = FixedChunker::new(reader, block_size);
+        let mut buf = block_buf(block_size);
+        let mut hashed = 0;
+        while hashed < max_blocks && chunker.next_chunk(&mut buf)? {
+            if self.complete {
+                return Err(Error::new(ErrorKind::InvalidInput, "the input already ended with a partial block"));
+            }
+            self.leaves.push(self.tree.hash_block(&buf)?);
+            self.offset += buf.len() as u64;
+            self.complete = buf.len() < block_size;
+            hashed += 1;
+        }
+        Ok(hashed)
+    }
+
+    /// Returns the number of input bytes hashed so far, where the next call
+    /// to `hash_blocks` must continue.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the number of blocks hashed so far.
+    pub fn num_blocks(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Saves the state of the builder: a 48-byte header holding the magic
+    /// `HTCP`, the format version, whether the input ended with a partial
+    /// block, the hasher fingerprint, block size, arity, hash length, number
+    /// of leaves and offset, followed by the leaf hashes.
+    /// Returns an `Error` value if the leaf hashes differ in length.
+    pub fn checkpoint(&self) -> Result<Vec<u8>, Error> {
+        let hash_len = self.leaves.first().map_or(0, |leaf| leaf.len());
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.leaves.len() * hash_len);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+        bytes.extend_from_slice(&[self.complete as u8, 0]);
+        bytes.extend_from_slice(&fingerprint(self.tree.get_hasher()));
+        bytes.extend_from_slice(&(self.tree.block_size() as u64).to_be_bytes());
+        bytes.extend_from_slice(&(self.tree.get_arity() as u32).to_be_bytes());
+        bytes.extend_from_slice(&(hash_len as u32).to_be_bytes());
+        bytes.extend_from_slice(&(self.leaves.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(&self.offset.to_be_bytes());
+        for leaf in &self.leaves {
+            if leaf.len() != hash_len {
+                return Err(Error::new(ErrorKind::InvalidData, "leaf hashes differ in length"));
+            }
+            bytes.extend_from_slice(leaf);
+        }
+        Ok(bytes)
+    }
+
+    /// Builds the tree over the blocks hashed so far.
+    /// Returns an `Error` value if the store could not be written.
+    pub fn finish(self) -> Result<HashTree<S>, Error> {
+        let mut tree = self.tree.from_leaf_hashes(self.leaves)?;
+        tree.set_data_len(Some(self.offset));
+        Ok(tree)
+    }
+}
@@ -0,0 +1,411 @@
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::ops::Range;
+
+/// A source of leaf data for a `HashTree`.
+///
+/// Each call to `next_chunk` yields the bytes of exactly one leaf. The
+/// default chunker used by `HashTree::from_data` splits its input into
+/// fixed-size blocks, while `RecordChunker` yields one leaf per record.
+pub trait Chunker {
+    /// Reads the next chunk into `buf`, which is cleared beforehand.
+    /// Returns `false` once the input is exhausted.
+    fn next_chunk(&mut self, buf: &mut Vec<u8>) -> Result<bool, std::io::Error>;
+}
+
+/// Splits a reader into fixed-size blocks. The final block may be shorter.
+#[derive(Debug)]
+pub struct FixedChunker<R> {
+    reader: R,
+    block_size: usize,
+}
+
+impl<R: Read> FixedChunker<R> {
+    /// Constructs a new `FixedChunker` reading `block_size` bytes at a time.
+    pub fn new(reader: R, block_size: usize) -> Self {
+        Self { reader, block_size }
+    }
+}
+
+impl<R: Read> Chunker for FixedChunker<R> {
+    fn next_chunk(&mut self, buf: &mut Vec<u8>) -> Result<bool, std::io::Error> {
+        buf.clear();
+        let mut chunk = (&mut self.reader).take(self.block_size as u64);
+        Ok(chunk.read_to_end(buf)? != 0)
+    }
+}
+
+/// Splits a reader into newline-delimited records, one leaf per record.
+///
+/// The line terminator (`\n` or `\r\n`) is not part of the record, so the
+/// leaf hash of a log line is simply the hash of its text. Blank lines are
+/// kept as empty records so leaf indices line up with line numbers.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, RecordChunker};
+///
+/// let log = "GET /\nPOST /login\nGET /logout\n";
+/// let tree = HashTree::new(0).from_chunker(RecordChunker::lines(log.as_bytes())).unwrap();
+/// assert_eq!(tree.num_blocks(), 3);
+/// ```
+#[derive(Debug)]
+pub struct RecordChunker<R> {
+    reader: R,
+    quoted: bool,
+}
+
+impl<R: BufRead> RecordChunker<R> {
+    /// Constructs a `RecordChunker` that treats every line as one record.
+    /// Suitable for plain logs and JSON Lines.
+    pub fn lines(reader: R) -> Self {
+        Self { reader, quoted: false }
+    }
+
+    /// Constructs a `RecordChunker` for CSV data. Newlines inside
+    /// double-quoted fields do not terminate a record.
+    pub fn csv(reader: R) -> Self {
+        Self { reader, quoted: true }
+    }
+}
+
+impl<R: BufRead> Chunker for RecordChunker<R> {
+    fn next_chunk(&mut self, buf: &mut Vec<u8>) -> Result<bool, std::io::Error> {
+        buf.clear();
+        let mut in_quotes = false;
+        loop {
+            let start = buf.len();
+            if self.reader.read_until(b'\n', buf)? == 0 {
+                return Ok(!buf.is_empty());
+            }
+            if self.quoted {
+                let quotes = buf[start..].iter().filter(|&&b| b == b'"').count();
+                in_quotes ^= quotes % 2 == 1;
+            }
+            if in_quotes {
+                continue;
+            }
+            if buf.last() == Some(&b'\n') {
+                buf.pop();
+                if buf.last() == Some(&b'\r') {
+                    buf.pop();
+                }
+            }
+            return Ok(true);
+        }
+    }
+}
+
+impl<C: Chunker + ?Sized> Chunker for &mut C {
+    fn next_chunk(&mut self, buf: &mut Vec<u8>) -> Result<bool, std::io::Error> {
+        (**self).next_chunk(buf)
+    }
+}
+
+/// Size of a tar header and the unit tar pads member contents to.
+const TAR_BLOCK: usize = 512;
+/// Largest PAX or GNU long name header accepted, which guards against
+/// buffering huge amounts of data for a corrupted header.
+const MAX_TAR_EXTENSION: u64 = 1024 * 1024;
+
+/// A member of a tar archive read by a `TarChunker`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TarEntry {
+    /// Path of the member, taken from a PAX or GNU long name header if the
+    /// member has one.
+    pub path: String,
+    /// Type flag of the member's header, e.g. `b'0'` for a regular file or
+    /// `b'5'` for a directory.
+    pub kind: u8,
+    /// Byte range of the member in the archive, from its first header to
+    /// the end of its padded contents.
+    pub range: Range<u64>,
+    /// Byte range of the member's contents in the archive.
+    pub data: Range<u64>,
+    /// Indices of the leaves covering the member.
+    pub leaves: Range<usize>,
+}
+
+/// Splits a tar archive into leaves that never straddle two members.
+///
+/// Every member, i.e. its headers (including PAX and GNU long name headers)
+/// followed by its padded contents, is split into leaves of at most
+/// `block_size` bytes, the last one possibly shorter; a `block_size` of zero
+/// yields one leaf per member. The end-of-archive marker and any padding after
+/// it form the final leaves. A damaged member thus maps to the leaves listed
+/// in its `TarEntry` and can be repaired without touching its neighbours.
+///
+/// Pass the chunker by reference to `HashTree::from_chunker` to look up the
+/// entries afterwards.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, TarChunker};
+///
+/// // An archive holding just the end-of-archive marker
+/// let archive = vec![0; 1024];
+/// let mut chunker = TarChunker::new(&archive[..], 4096);
+/// let tree = HashTree::new(4096).from_chunker(&mut chunker).unwrap();
+/// assert_eq!(tree.num_blocks(), 1);
+/// assert!(chunker.entries().is_empty());
+/// ```
+#[derive(Debug)]
+pub struct TarChunker<R> {
+    reader: R,
+    block_size: usize,
+    /// Headers of the current member, handed out before its contents.
+    headers: Vec<u8>,
+    headers_pos: usize,
+    /// Bytes of the current member left to read after its headers.
+    remaining: u64,
+    offset: u64,
+    num_leaves: usize,
+    entries: Vec<TarEntry>,
+    in_trailer: bool,
+}
+
+impl<R: Read> TarChunker<R> {
+    /// Constructs a new `TarChunker` reading a tar archive from `reader`.
+    pub fn new(reader: R, block_size: usize) -> Self {
+        Self {
+            reader,
+            block_size,
+            headers: Vec::new(),
+            headers_pos: 0,
+            remaining: 0,
+            offset: 0,
+            num_leaves: 0,
+            entries: Vec::new(),
+            in_trailer: false,
+        }
+    }
+
+    /// Returns the members read so far, in archive order.
+    pub fn entries(&self) -> &[TarEntry] {
+        &self.entries
+    }
+
+    /// Returns the member covering leaf `index`, or `None` if the leaf
+    /// belongs to the end-of-archive marker or has not been read yet.
+    pub fn entry_for_leaf(&self, index: usize) -> Option<&TarEntry> {
+        let position = self.entries.partition_point(|entry| entry.leaves.end <= index);
+        self.entries.get(position).filter(|entry| entry.leaves.contains(&index))
+    }
+
+    /// Consumes the chunker, returning the members it read.
+    pub fn into_entries(self) -> Vec<TarEntry> {
+        self.entries
+    }
+
+    /// Reads the headers of the next member. Returns `false` at the end of
+    /// the input.
+    fn start_member(&mut self) -> Result<bool, std::io::Error> {
+        self.headers.clear();
+        self.headers_pos = 0;
+        let start = self.offset;
+        let (mut path, mut size) = (None, None);
+        loop {
+            let mut header = [0; TAR_BLOCK];
+            let len = read_full(&mut self.reader, &mut header)?;
+            if len == 0 && self.headers.is_empty() {
+                return Ok(false);
+            }
+            if len < TAR_BLOCK {
+                return Err(truncated());
+            }
+            self.headers.extend_from_slice(&header);
+
+            if header.iter().all(|&b| b == 0) {
+                if self.headers.len() > TAR_BLOCK {
+                    return Err(invalid("tar extension header is not followed by a member"));
+                }
+                self.in_trailer = true;
+                return Ok(true);
+            }
+            if !tar_checksum_matches(&header)? {
+                return Err(invalid("tar header checksum mismatch"));
+            }
+
+            let kind = header[156];
+            let header_size = parse_tar_number(&header[124..136])?;
+            match kind {
+                b'x' | b'L' | b'K' => {
+                    if header_size > MAX_TAR_EXTENSION {
+                        return Err(invalid("tar extension header is too large"));
+                    }
+                    let mut data = vec![0; padded(header_size) as usize];
+                    if read_full(&mut self.reader, &mut data)? < data.len() {
+                        return Err(truncated());
+                    }
+                    self.headers.extend_from_slice(&data);
+                    let data = &data[..header_size as usize];
+                    if kind == b'x' {
+                        for (key, value) in pax_records(data)? {
+                            match key {
+                                "path" => path = Some(value.to_string()),
+                                "size" => size = Some(value.parse().map_err(|_| invalid("invalid PAX size"))?),
+                                _ => {}
+                            }
+                        }
+                    } else if kind == b'L' {
+                        path = Some(c_string(data));
+                    }
+                }
+                _ => {
+                    // Links, devices, directories and FIFOs have no contents
+                    // whatever their size field says
+                    let data_len = match kind {
+                        b'1'..=b'6' => 0,
+                        _ => size.unwrap_or(header_size),
+                    };
+                    self.remaining = padded(data_len);
+                    let data_start = start + self.headers.len() as u64;
+                    self.entries.push(TarEntry {
+                        path: path.unwrap_or_else(|| tar_header_path(&header)),
+                        kind,
+                        range: start..data_start + self.remaining,
+                        data: data_start..data_start + data_len,
+                        leaves: self.num_leaves..self.num_leaves,
+                    });
+                    return Ok(true);
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> Chunker for TarChunker<R> {
+    fn next_chunk(&mut self, buf: &mut Vec<u8>) -> Result<bool, std::io::Error> {
+        buf.clear();
+        let member_done = self.headers_pos == self.headers.len() && self.remaining == 0;
+        if member_done && (self.in_trailer || !self.start_member()?) {
+            // After the end-of-archive marker, whatever padding follows is
+            // split into plain blocks
+            if self.in_trailer && self.headers_pos == self.headers.len() {
+                let limit = if self.block_size == 0 { u64::MAX } else { self.block_size as u64 };
+                (&mut self.reader).take(limit).read_to_end(buf)?;
+            }
+            if buf.is_empty() {
+                return Ok(false);
+            }
+        }
+        let limit = if self.block_size == 0 { usize::MAX } else { self.block_size };
+
+        let headers = &self.headers[self.headers_pos..];
+        let take = headers.len().min(limit - buf.len());
+        buf.extend_from_slice(&headers[..take]);
+        self.headers_pos += take;
+
+        if self.in_trailer {
+            let rest = (limit - buf.len()) as u64;
+            (&mut self.reader).take(rest).read_to_end(buf)?;
+        } else {
+            let want = self.remaining.min((limit - buf.len()) as u64);
+            if (&mut self.reader).take(want).read_to_end(buf)? < want as usize {
+                return Err(truncated());
+            }
+            self.remaining -= want;
+            self.entries.last_mut().expect("a member is being read").leaves.end = self.num_leaves + 1;
+        }
+        self.offset += buf.len() as u64;
+        self.num_leaves += 1;
+        Ok(true)
+    }
+}
+
+/// Reads into `buf` until it is full or the reader is exhausted. Returns the
+/// number of bytes read.
+pub(crate) fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(len) => filled += len,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(filled)
+}
+
+fn padded(len: u64) -> u64 {
+    len.div_ceil(TAR_BLOCK as u64) * TAR_BLOCK as u64
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn truncated() -> std::io::Error {
+    std::io::Error::new(ErrorKind::UnexpectedEof, "tar archive is truncated")
+}
+
+/// Parses a numeric header field, which is either octal text or, if the high
+/// bit of its first byte is set, a big-endian base-256 number.
+fn parse_tar_number(field: &[u8]) -> Result<u64, std::io::Error> {
+    if field[0] & 0x80 != 0 {
+        if field[0] & 0x40 != 0 {
+            return Err(invalid("negative tar header number"));
+        }
+        let mut value = u64::from(field[0] & 0x3f);
+        for &b in &field[1..] {
+            value = value.checked_mul(256).ok_or_else(|| invalid("tar header number overflows"))? | u64::from(b);
+        }
+        return Ok(value);
+    }
+    let text: Vec<u8> = field.iter().copied().skip_while(|&b| b == b' ').take_while(|&b| b != 0 && b != b' ').collect();
+    if text.is_empty() {
+        return Ok(0);
+    }
+    let text = std::str::from_utf8(&text).map_err(|_| invalid("invalid tar header number"))?;
+    u64::from_str_radix(text, 8).map_err(|_| invalid("invalid tar header number"))
+}
+
+/// Checks the header checksum, accepting both the standard unsigned sum and
+/// the signed sum some old implementations wrote.
+fn tar_checksum_matches(header: &[u8; TAR_BLOCK]) -> Result<bool, std::io::Error> {
+    let expected = parse_tar_number(&header[148..156])?;
+    let field = 148..156;
+    let (mut unsigned, mut signed) = (0u64, 0i64);
+    for (i, &b) in header.iter().enumerate() {
+        let b = if field.contains(&i) { b' ' } else { b };
+        unsigned += u64::from(b);
+        signed += i64::from(b as i8);
+    }
+    Ok(expected == unsigned || expected as i64 == signed)
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Returns the path in a ustar header, joining its prefix and name fields.
+fn tar_header_path(header: &[u8; TAR_BLOCK]) -> String {
+    let name = c_string(&header[..100]);
+    if &header[257..262] != b"ustar" || header[345] == 0 {
+        return name;
+    }
+    format!("{}/{}", c_string(&header[345..500]), name)
+}
+
+/// Splits PAX extended header data into its `length key=value\n` records.
+fn pax_records(mut data: &[u8]) -> Result<Vec<(&str, &str)>, std::io::Error> {
+    let mut records = Vec::new();
+    while !data.is_empty() && data[0] != 0 {
+        let space = data.iter().position(|&b| b == b' ').ok_or_else(|| invalid("invalid PAX record"))?;
+        let len: usize = std::str::from_utf8(&data[..space])
+            .ok()
+            .and_then(|len| len.parse().ok())
+            .filter(|&len| len > space + 1 && len <= data.len())
+            .ok_or_else(|| invalid("invalid PAX record"))?;
+        let record = std::str::from_utf8(&data[space + 1..len - 1]).map_err(|_| invalid("invalid PAX record"))?;
+        if let Some((key, value)) = record.split_once('=') {
+            records.push((key, value));
+        }
+        data = &data[len..];
+    }
+    Ok(records)
+}
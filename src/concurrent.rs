@@ -0,0 +1,103 @@
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+
+use crate::hasher::PairHasher;
+use crate::store::NodeStore;
+use crate::HashTree;
+
+/// Collects the blocks of a `HashTree` from many threads in any order.
+///
+/// Each producer hashes the blocks it submits on its own thread; only storing
+/// the leaf hash takes a lock. Once every block has arrived, `finish` builds
+/// the interior nodes. Share the builder between threads by reference, e.g.
+/// with `std::thread::scope`, or wrap it in an `Arc`.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::HashTree;
+///
+/// let data = b"abcdefgh";
+/// let builder = HashTree::new(2).concurrent(4);
+/// std::thread::scope(|scope| {
+///     for index in (0..4).rev() {
+///         let builder = &builder;
+///         scope.spawn(move || builder.submit(index, &data[index * 2..index * 2 + 2]).unwrap());
+///     }
+/// });
+/// let tree = builder.finish().unwrap();
+/// assert_eq!(tree, HashTree::new(2).from_data(&mut &data[..]).unwrap());
+/// ```
+#[derive(Debug)]
+pub struct ConcurrentBuilder<S = Vec<Vec<u8>>> {
+    tree: HashTree<S>,
+    hasher: Arc<dyn PairHasher>,
+    leaves: Mutex<Leaves>,
+}
+
+#[derive(Debug)]
+struct Leaves {
+    hashes: Vec<Option<Vec<u8>>>,
+    missing: usize,
+}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Returns a `ConcurrentBuilder` that builds `self`, which should be
+    /// empty, over exactly `num_blocks` blocks submitted from any thread.
+    pub fn concurrent(self, num_blocks: usize) -> ConcurrentBuilder<S> {
+        let leaves = Leaves { hashes: vec![None; num_blocks], missing: num_blocks };
+        ConcurrentBuilder { hasher: self.shared_hasher(), tree: self, leaves: Mutex::new(leaves) }
+    }
+}
+
+impl<S: NodeStore> ConcurrentBuilder<S> {
+    /// Hashes `data` as the block at `index`. The data of every block but
+    /// the last should be exactly one block size long.
+    /// Returns an `Error` value if `index` is out of range or was already submitted.
+    pub fn submit(&self, index: usize, data: &[u8]) -> Result<(), Error> {
+        self.submit_hash(index, self.hasher.hash_leaf(data))
+    }
+
+    /// Stores the already computed leaf hash of the block at `index`.
+    /// Returns an `Error` value if `index` is out of range or was already submitted.
+    pub fn submit_hash(&self, index: usize, hash: Vec<u8>) -> Result<(), Error> {
+        let mut leaves = self.lock();
+        let slot = leaves
+            .hashes
+            .get_mut(index)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("block index {} out of range", index)))?;
+        if slot.is_some() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("block {} was already submitted", index)));
+        }
+        *slot = Some(hash);
+        leaves.missing -= 1;
+        Ok(())
+    }
+
+    /// Returns the number of blocks that have not been submitted yet.
+    pub fn missing(&self) -> usize {
+        self.lock().missing
+    }
+
+    /// Returns `true` once every block has been submitted.
+    pub fn is_complete(&self) -> bool {
+        self.missing() == 0
+    }
+
+    /// Builds the tree from the submitted blocks.
+    /// Returns an `Error` value if blocks are missing or the store could not be written.
+    pub fn finish(self) -> Result<HashTree<S>, Error> {
+        let leaves = self.leaves.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if leaves.missing > 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} blocks have not been submitted", leaves.missing),
+            ));
+        }
+        self.tree.from_leaf_hashes(leaves.hashes.into_iter().flatten())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Leaves> {
+        self.leaves.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -0,0 +1,160 @@
+use std::io::{Error, ErrorKind, Read};
+use std::sync::Arc;
+
+use hmac::{Mac, SimpleHmac};
+use sha2::Sha256;
+
+use crate::chunker::{Chunker, FixedChunker};
+use crate::hasher::PairHasher;
+use crate::HashTree;
+
+/// Derives the key encrypting a block from its plaintext leaf hash, as
+/// HMAC-SHA256 keyed with `secret`.
+///
+/// Identical blocks get identical keys, and so identical ciphertexts, which
+/// lets a backup store deduplicate encrypted blocks. Anyone who can guess a
+/// block can also confirm that it was stored; a `secret` shared only by the
+/// parties that should deduplicate against each other limits this to them,
+/// while an empty one deduplicates across everyone.
+pub fn block_key(secret: &[u8], leaf_hash: &[u8]) -> [u8; 32] {
+    let mut mac = <SimpleHmac<Sha256> as Mac>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(leaf_hash);
+    mac.finalize().into_bytes().into()
+}
+
+/// A pair of trees over the same data before and after convergent
+/// encryption, built by `HashTree::from_data_convergent`.
+///
+/// The ciphertext tree is what an untrusted store holds and can check; its
+/// leaves are the hashes of the encrypted blocks. The plaintext tree is kept
+/// by the owner: its leaves, together with the secret, are all that is needed
+/// to derive the block keys again with `block_key`, and they check the blocks
+/// after decryption.
+#[derive(Debug, Clone)]
+pub struct ConvergentTree {
+    plaintext: HashTree,
+    ciphertext: HashTree,
+    keys: Vec<[u8; 32]>,
+}
+
+impl ConvergentTree {
+    /// Returns the tree over the plaintext blocks.
+    pub fn plaintext(&self) -> &HashTree {
+        &self.plaintext
+    }
+
+    /// Returns the tree over the encrypted blocks.
+    pub fn ciphertext(&self) -> &HashTree {
+        &self.ciphertext
+    }
+
+    /// Returns the key of every block, in order.
+    pub fn keys(&self) -> &[[u8; 32]] {
+        &self.keys
+    }
+
+    /// Returns the key of block `index`, or `None` if there is no such block.
+    pub fn key(&self, index: usize) -> Option<&[u8; 32]> {
+        self.keys.get(index)
+    }
+
+    /// Checks that `plaintext`, e.g. a block just decrypted with its key,
+    /// hashes to leaf `index` of the plaintext tree.
+    /// Returns an `Error` value if it does not or there is no such leaf.
+    pub fn check_block(&self, index: usize, plaintext: &[u8]) -> Result<(), Error> {
+        let leaf = self.plaintext.leaf_hashes().nth(index).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, format!("block {} is out of range", index))
+        })?;
+        if self.plaintext.get_hasher().hash_leaf(plaintext) != leaf {
+            return Err(Error::new(ErrorKind::InvalidData, format!("block {} does not match its leaf hash", index)));
+        }
+        Ok(())
+    }
+
+    /// Consumes the pair, returning the plaintext and ciphertext trees.
+    pub fn into_trees(self) -> (HashTree, HashTree) {
+        (self.plaintext, self.ciphertext)
+    }
+}
+
+/// Splits a reader into blocks and hands out their ciphertexts, keeping the
+/// plaintext leaf hashes and keys.
+struct Encrypting<'a, R, F> {
+    blocks: FixedChunker<R>,
+    block: Vec<u8>,
+    hasher: Arc<dyn PairHasher>,
+    secret: &'a [u8],
+    encrypt: F,
+    leaves: Vec<(Vec<u8>, u64)>,
+    keys: Vec<[u8; 32]>,
+}
+
+impl<R, F> Chunker for Encrypting<'_, R, F>
+where
+    R: Read,
+    F: FnMut(&[u8; 32], &[u8]) -> Result<Vec<u8>, Error>,
+{
+    fn next_chunk(&mut self, buf: &mut Vec<u8>) -> Result<bool, Error> {
+        buf.clear();
+        if !self.blocks.next_chunk(&mut self.block)? {
+            return Ok(false);
+        }
+        let leaf = self.hasher.hash_leaf(&self.block);
+        let key = block_key(self.secret, &leaf);
+        buf.extend_from_slice(&(self.encrypt)(&key, &self.block)?);
+        self.leaves.push((leaf, self.block.len() as u64));
+        self.keys.push(key);
+        Ok(true)
+    }
+}
+
+impl HashTree {
+    /// Splits `data` into blocks and encrypts each with `encrypt` under a key
+    /// derived from the block's own leaf hash, see `block_key`. Returns a
+    /// tree over the ciphertexts with the parameters of `self`, including its
+    /// block store, which receives the encrypted blocks, next to a tree over
+    /// the plaintext.
+    ///
+    /// Since every key encrypts a single plaintext, `encrypt` may use a fixed
+    /// nonce, and it must be deterministic for identical blocks to
+    /// deduplicate. Errors returned by `encrypt` abort the build.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// // A stand-in for a real cipher such as AES-256-GCM-SIV
+    /// let encrypt = |key: &[u8; 32], block: &[u8]| {
+    ///     Ok(block.iter().zip(key.iter().cycle()).map(|(b, k)| b ^ k).collect())
+    /// };
+    /// let data = [7; 12];
+    /// let tree = HashTree::new(4).from_data_convergent(&data[..], b"team secret", encrypt).unwrap();
+    ///
+    /// // Identical blocks encrypt identically and can be deduplicated
+    /// assert_eq!(tree.ciphertext().num_blocks(), 3);
+    /// assert_eq!(tree.key(0), tree.key(2));
+    /// assert_ne!(tree.plaintext().root_hash(), tree.ciphertext().root_hash());
+    /// assert!(tree.check_block(1, &[7; 4]).is_ok());
+    /// ```
+    pub fn from_data_convergent<R, F>(mut self, data: R, secret: &[u8], encrypt: F) -> Result<ConvergentTree, Error>
+    where
+        R: Read,
+        F: FnMut(&[u8; 32], &[u8]) -> Result<Vec<u8>, Error>,
+    {
+        self.resolve_block_size(None);
+        let template = self.clone();
+        let mut chunker = Encrypting {
+            blocks: FixedChunker::new(data, self.block_size()),
+            block: Vec::new(),
+            hasher: self.shared_hasher(),
+            secret,
+            encrypt,
+            leaves: Vec::new(),
+            keys: Vec::new(),
+        };
+        let ciphertext = self.from_chunker(&mut chunker)?;
+        let plaintext = template.with_sized_leaves(chunker.leaves)?;
+        Ok(ConvergentTree { plaintext, ciphertext, keys: chunker.keys })
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io::Error;
+use std::path::Path;
+
+use crate::store::NodeStore;
+
+const DEFAULT_BATCH_SIZE: usize = 4096;
+
+/// A `NodeStore` backed by a `sled` embedded database, for trees that must
+/// survive a restart.
+///
+/// Nodes are keyed by their big-endian index. Writes are buffered and
+/// applied in batches of `batch_size` nodes, so building a tree does not
+/// cost one database write per node. Buffered writes are applied when the
+/// tree finishes building, on `flush` and when the store is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, SledStore};
+///
+/// let db = sled::Config::new().temporary(true).open().unwrap();
+/// let store = SledStore::from_tree(db.open_tree("nodes").unwrap()).unwrap();
+/// let tree = HashTree::with_store(1, store).from_data(&mut &b"abcdef"[..]).unwrap();
+/// let num_blocks = tree.num_blocks();
+/// drop(tree);
+///
+/// let store = SledStore::from_tree(db.open_tree("nodes").unwrap()).unwrap();
+/// let tree = HashTree::with_store(1, store).open(num_blocks).unwrap();
+/// assert_eq!(tree, HashTree::new(1).from_data(&mut &b"abcdef"[..]).unwrap());
+/// ```
+#[derive(Debug)]
+pub struct SledStore {
+    tree: sled::Tree,
+    len: usize,
+    pending: BTreeMap<usize, Vec<u8>>,
+    batch_size: usize,
+}
+
+impl SledStore {
+    /// Opens or creates the database at `path` and uses its `hashtree-nodes` tree.
+    /// The database stays locked for a short while after the store is dropped;
+    /// to reopen a tree within the same process, keep the `sled::Db` and use
+    /// `from_tree` instead.
+    /// Returns an `Error` value if the database could not be opened.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let db = sled::open(path)?;
+        Self::from_tree(db.open_tree("hashtree-nodes")?)
+    }
+
+    /// Uses the given `sled` tree, which must only hold nodes written by a `SledStore`.
+    /// Returns an `Error` value if the tree could not be read.
+    pub fn from_tree(tree: sled::Tree) -> Result<Self, Error> {
+        let len = match tree.last()? {
+            Some((key, _)) => index(&key) + 1,
+            None => 0,
+        };
+        Ok(Self { tree, len, pending: BTreeMap::new(), batch_size: DEFAULT_BATCH_SIZE })
+    }
+
+    /// Sets the number of buffered writes applied in one batch.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Applies the buffered writes to the database.
+    fn write_batch(&mut self) -> Result<(), Error> {
+        let mut batch = sled::Batch::default();
+        for (index, hash) in std::mem::take(&mut self.pending) {
+            batch.insert(&(index as u64).to_be_bytes(), hash);
+        }
+        self.tree.apply_batch(batch)?;
+        Ok(())
+    }
+}
+
+impl NodeStore for SledStore {
+    fn get(&self, index: usize) -> Option<Vec<u8>> {
+        if index >= self.len {
+            return None;
+        }
+        if let Some(hash) = self.pending.get(&index) {
+            return Some(hash.clone());
+        }
+        self.tree.get((index as u64).to_be_bytes()).ok().flatten().map(|hash| hash.to_vec())
+    }
+
+    fn put(&mut self, index: usize, hash: Vec<u8>) -> Result<(), Error> {
+        if index > self.len {
+            return Err(Error::new(std::io::ErrorKind::InvalidInput, "node index out of bounds"));
+        }
+        self.pending.insert(index, hash);
+        self.len = self.len.max(index + 1);
+        if self.pending.len() >= self.batch_size {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<(), Error> {
+        if len >= self.len {
+            return Ok(());
+        }
+        self.pending.split_off(&len);
+        let mut batch = sled::Batch::default();
+        for key in self.tree.range((len as u64).to_be_bytes()..).keys() {
+            batch.remove(key?);
+        }
+        self.tree.apply_batch(batch)?;
+        self.len = len;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.write_batch()?;
+        self.tree.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for SledStore {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+fn index(key: &[u8]) -> usize {
+    u64::from_be_bytes(key.try_into().unwrap_or([0xff; 8])) as usize
+}
@@ -0,0 +1,584 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use sha2::{Digest, Sha256};
+
+use crate::exclude::Excludes;
+use crate::HashTree;
+
+/// Git-style file mode of a symbolic link recorded with `Symlinks::Record`.
+pub(crate) const SYMLINK_MODE: u32 = 0o120000;
+
+/// A file that is part of a `DirTree`.
+#[derive(Debug, Clone)]
+pub struct FileEntry {
+    /// Path of the file relative to the root directory, using `/` as separator.
+    pub path: String,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Git-style file mode: `0o100644`, or `0o100755` for files executable
+    /// by their owner, and `0o120000` for symbolic links recorded with
+    /// `Symlinks::Record`.
+    pub mode: u32,
+    /// Permission bits of the file, e.g. `0o640`. Outside Unix, `0o444` for
+    /// read-only files and `0o644` for others.
+    pub permissions: u32,
+    /// Modification time of the file, if the platform records one.
+    pub mtime: Option<SystemTime>,
+    /// The `HashTree` of the file contents.
+    pub tree: HashTree,
+    /// The Git blob id of the file, if the `DirTree` is in Git mode.
+    pub git_id: Option<Vec<u8>>,
+}
+
+impl FileEntry {
+    /// Returns the root hash of the file, or the digest of the empty
+    /// string if the file is empty.
+    pub fn root(&self) -> Vec<u8> {
+        file_root(&self.tree)
+    }
+}
+
+/// Returns the root of a file's tree, using the digest of the empty string for empty files.
+pub(crate) fn file_root(tree: &HashTree) -> Vec<u8> {
+    match tree.root() {
+        Some(root) => root,
+        None => Sha256::digest(b"").to_vec(),
+    }
+}
+
+/// Returns the target path of the symbolic link at `path`, as recorded by
+/// `Symlinks::Record`.
+pub(crate) fn link_target(path: &Path) -> Result<String, std::io::Error> {
+    Ok(fs::read_link(path)?.to_string_lossy().into_owned())
+}
+
+pub(crate) fn entry_hash(path: &str, root: &[u8]) -> Vec<u8> {
+    entry_hash_with_metadata(path, root, &[])
+}
+
+/// Returns the leaf hash committing to a file and the encoded `metadata` of
+/// it, see `DirTree::include_permissions`.
+fn entry_hash_with_metadata(path: &str, root: &[u8], metadata: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update((path.len() as u64).to_be_bytes());
+    hasher.update(path.as_bytes());
+    hasher.update(root);
+    hasher.update(metadata);
+    hasher.finalize().to_vec()
+}
+
+/// How a `DirTree` treats symbolic links below its root directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Symlinks {
+    /// Links to files are hashed as the files they point to; links to
+    /// directories and dangling links are left out.
+    #[default]
+    FollowFiles,
+    /// Links are followed to files and directories alike. Links leading
+    /// back to a directory being walked are left out, so cycles end.
+    Follow,
+    /// Links are not followed but recorded as entries whose contents are
+    /// their target paths, with mode `0o120000`, as Git does.
+    Record,
+    /// Links are left out.
+    Skip,
+}
+
+/// A Merkle-tree over a whole directory.
+///
+/// Every regular file below the root directory is hashed into its own
+/// `HashTree`. The directory root is then the root of a `HashTree` whose
+/// leaves commit to each file's relative path and root hash, in sorted
+/// path order.
+///
+/// In Git mode (see `DirTree::git`) the root is instead the id of the Git
+/// tree object for the same directory contents.
+#[derive(Debug, Clone)]
+pub struct DirTree {
+    files: Vec<FileEntry>,
+    tree: HashTree,
+    root: Option<Vec<u8>>,
+    block_size: usize,
+    symlinks: Symlinks,
+    permissions: bool,
+    mtime: bool,
+    excludes: Excludes,
+    #[cfg(feature = "git")]
+    git: bool,
+}
+
+impl DirTree {
+    /// Constructs a new empty `DirTree` that hashes files in blocks of `block_size` bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::DirTree;
+    ///
+    /// const BLOCK_SIZE: usize = 4096;
+    /// let tree = DirTree::new(BLOCK_SIZE);
+    /// assert!(tree.is_empty());
+    /// ```
+    pub fn new(block_size: usize) -> Self {
+        Self {
+            files: Vec::new(),
+            tree: HashTree::new(block_size),
+            root: None,
+            block_size,
+            symlinks: Symlinks::default(),
+            permissions: false,
+            mtime: false,
+            excludes: Excludes::default(),
+            #[cfg(feature = "git")]
+            git: false,
+        }
+    }
+
+    /// Sets how symbolic links are treated. The default is
+    /// `Symlinks::FollowFiles`. Git mode always uses `Symlinks::Record`.
+    pub fn symlinks(mut self, symlinks: Symlinks) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Commits to the permission bits of every file in its entry, so that
+    /// e.g. making a file executable changes the directory root. Manifests
+    /// do not record permissions and cannot be built for such a tree.
+    pub fn include_permissions(mut self) -> Self {
+        self.permissions = true;
+        self
+    }
+
+    /// Commits to the modification time of every file, in whole seconds, in
+    /// its entry. Copies that do not preserve times then have other roots.
+    /// Manifests do not record modification times and cannot be built for
+    /// such a tree.
+    pub fn include_mtime(mut self) -> Self {
+        self.mtime = true;
+        self
+    }
+
+    /// Leaves out the files and directories matching `patterns`, given one
+    /// per line as in a `.gitignore` file, e.g. its contents. Patterns are
+    /// matched against paths relative to the root directory; may be called
+    /// several times, later patterns taking precedence.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::DirTree;
+    ///
+    /// let dir = std::env::temp_dir().join(format!("hashtree-doc-exclude-{}", std::process::id()));
+    /// std::fs::create_dir_all(dir.join("target")).unwrap();
+    /// std::fs::write(dir.join("main.rs"), b"fn main() {}").unwrap();
+    /// std::fs::write(dir.join("debug.log"), b"noise").unwrap();
+    /// std::fs::write(dir.join("keep.log"), b"kept").unwrap();
+    /// std::fs::write(dir.join("target/app"), b"binary").unwrap();
+    ///
+    /// let tree = DirTree::new(4096).exclude("target/\n*.log\n!keep.log").from_path(&dir).unwrap();
+    /// let paths: Vec<&str> = tree.files().iter().map(|file| file.path.as_str()).collect();
+    /// assert_eq!(paths, ["keep.log", "main.rs"]);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn exclude(mut self, patterns: &str) -> Self {
+        self.excludes.add(patterns);
+        self
+    }
+
+    /// Switches the `DirTree` to Git mode, in which files are hashed as Git
+    /// blobs and the directory root equals the id of the Git tree object for
+    /// the same contents. Per-file `HashTree`s are still computed. Symbolic
+    /// links are recorded as Git records them, see `Symlinks::Record`,
+    /// whatever is set with `symlinks`.
+    /// Requires the `git` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[cfg(feature = "git")] {
+    /// use hashtree::DirTree;
+    ///
+    /// let dir = std::env::temp_dir().join("hashtree-doc-git-empty");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// let tree = DirTree::new(4096).git().from_path(&dir).unwrap();
+    /// assert_eq!(tree.root_hash().unwrap(), "4b825dc642cb6eb9a060e54bf8d69288fbee4904");
+    /// # }
+    /// ```
+    #[cfg(feature = "git")]
+    pub fn git(mut self) -> Self {
+        self.git = true;
+        self
+    }
+
+    /// Constructs a new `DirTree` by recursively hashing every file below `path`.
+    /// Symbolic links are treated as set with `symlinks`, by default only
+    /// those to files are followed.
+    /// Returns an `Error` value if the directory could not be walked or a file could not be read.
+    pub fn from_path<P: AsRef<Path>>(mut self, path: P) -> Result<Self, std::io::Error> {
+        let root = path.as_ref();
+        let mut files = Vec::new();
+        self.walk(root, root, &mut files)?;
+
+        for (relative, full) in files {
+            let entry = self.hash_file(relative, &full)?;
+            self.files.push(entry);
+        }
+
+        self.rebuild()?;
+        Ok(self)
+    }
+
+    /// Rehashes the single file at `path` below the root directory `root` and
+    /// recomputes the directory root, leaving all other files untouched. If the
+    /// file no longer exists or is now excluded it is removed from the tree.
+    /// If `path` is a directory, the files below it are rehashed and those no
+    /// longer there are removed.
+    /// Returns `true` if the directory root changed.
+    pub fn update_path<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, root: P, path: Q) -> Result<bool, std::io::Error> {
+        let before = self.root.clone();
+        self.apply_path(root.as_ref(), path.as_ref())?;
+        self.rebuild()?;
+        Ok(before != self.root)
+    }
+
+    fn hash_file(&self, path: String, full: &Path) -> Result<FileEntry, std::io::Error> {
+        let link = fs::symlink_metadata(full)?;
+        if self.link_policy() == Symlinks::Record && link.file_type().is_symlink() {
+            return self.hash_link(path, full, &link);
+        }
+        let tree = HashTree::new(self.block_size).from_file(full)?;
+        let metadata = fs::metadata(full)?;
+        #[cfg(feature = "git")]
+        let git_id = if self.git { Some(crate::git::blob_id(full)?) } else { None };
+        #[cfg(not(feature = "git"))]
+        let git_id = None;
+        Ok(FileEntry {
+            path,
+            size: metadata.len(),
+            mode: file_mode(&metadata),
+            permissions: permissions(&metadata),
+            mtime: self.modified(&metadata)?,
+            tree,
+            git_id,
+        })
+    }
+
+    /// Hashes the symbolic link at `full` as an entry holding its target.
+    fn hash_link(&self, path: String, full: &Path, metadata: &fs::Metadata) -> Result<FileEntry, std::io::Error> {
+        let target = link_target(full)?;
+        let tree = HashTree::new(self.block_size).from_slice(target.as_bytes())?;
+        #[cfg(feature = "git")]
+        let git_id = if self.git { Some(crate::git::blob_id_of(target.as_bytes())) } else { None };
+        #[cfg(not(feature = "git"))]
+        let git_id = None;
+        Ok(FileEntry {
+            path,
+            size: target.len() as u64,
+            mode: SYMLINK_MODE,
+            permissions: 0o777,
+            mtime: self.modified(metadata)?,
+            tree,
+            git_id,
+        })
+    }
+
+    /// Returns how symbolic links are treated: as set with `symlinks`, or
+    /// recorded in Git mode, since Git stores links rather than their targets.
+    fn link_policy(&self) -> Symlinks {
+        #[cfg(feature = "git")]
+        if self.git {
+            return Symlinks::Record;
+        }
+        self.symlinks
+    }
+
+    /// Returns the modification time of a file, which must be known if it
+    /// is committed to, see `include_mtime`.
+    fn modified(&self, metadata: &fs::Metadata) -> Result<Option<SystemTime>, std::io::Error> {
+        match metadata.modified() {
+            Ok(mtime) => Ok(Some(mtime)),
+            Err(error) if self.mtime => Err(error),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Returns the leaf hash committing to `entry` and, if they are included,
+    /// its permissions and modification time.
+    fn entry_hash(&self, entry: &FileEntry) -> Vec<u8> {
+        let mut metadata = Vec::new();
+        if self.permissions {
+            metadata.extend_from_slice(&entry.permissions.to_be_bytes());
+        }
+        if self.mtime {
+            metadata.extend_from_slice(&mtime_secs(entry.mtime).to_be_bytes());
+        }
+        entry_hash_with_metadata(&entry.path, &entry.root(), &metadata)
+    }
+
+    /// Returns `true` if `a` and `b`, entries for the same path, differ in
+    /// metadata the tree commits to.
+    fn metadata_differs(&self, a: &FileEntry, b: &FileEntry) -> bool {
+        (self.permissions && a.permissions != b.permissions) || (self.mtime && mtime_secs(a.mtime) != mtime_secs(b.mtime))
+    }
+
+    fn apply_path(&mut self, root: &Path, path: &Path) -> Result<(), std::io::Error> {
+        let relative = relative_path(root, path);
+        let link = fs::symlink_metadata(path).ok();
+        let is_link = link.as_ref().is_some_and(|metadata| metadata.file_type().is_symlink());
+        let is_dir = path.is_dir() && (!is_link || self.link_policy() == Symlinks::Follow);
+        let is_file = link.is_some() && !is_dir && self.includes_file(is_link, path);
+
+        if self.excludes.excludes(&relative, is_dir) {
+            self.remove_below(&relative);
+        } else if is_file {
+            let entry = self.hash_file(relative, path)?;
+            match self.files.binary_search_by(|e| e.path.cmp(&entry.path)) {
+                Ok(i) => self.files[i] = entry,
+                Err(i) => self.files.insert(i, entry),
+            }
+        } else if is_dir {
+            // Files below that are gone or now excluded are not walked
+            let mut files = Vec::new();
+            self.walk(root, path, &mut files)?;
+            self.remove_below(&relative);
+            for (_, full) in files {
+                self.apply_path(root, &full)?;
+            }
+        } else {
+            // The path is gone or left out; drop the file itself or everything below it
+            self.remove_below(&relative);
+        }
+        Ok(())
+    }
+
+    /// Removes the file at `relative` or every file below it, all of them if
+    /// `relative` is the root directory.
+    fn remove_below(&mut self, relative: &str) {
+        if relative.is_empty() {
+            self.files.clear();
+            return;
+        }
+        let prefix = format!("{}/", relative);
+        self.files.retain(|entry| entry.path != relative && !entry.path.starts_with(&prefix));
+    }
+
+    /// Returns `true` if the entry at `path`, which is not a directory to
+    /// walk, is hashed as a file: a regular file, or a link the symlink
+    /// policy keeps.
+    fn includes_file(&self, is_link: bool, path: &Path) -> bool {
+        match (is_link, self.link_policy()) {
+            (true, Symlinks::Record) => true,
+            (true, Symlinks::Skip) => false,
+            _ => path.is_file(),
+        }
+    }
+
+    /// Collects `(relative path, full path)` pairs for every file below `dir`
+    /// that is not excluded, following links as set with `symlinks`.
+    fn walk(&self, root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) -> Result<(), std::io::Error> {
+        let mut walking = Vec::new();
+        if self.link_policy() == Symlinks::Follow {
+            walking.push(fs::canonicalize(dir)?);
+        }
+        self.walk_dir(root, dir, files, &mut walking)
+    }
+
+    /// Walks `dir`; with `Symlinks::Follow`, `walking` holds the canonical
+    /// paths of `dir` and the directories above it, to detect cycles.
+    fn walk_dir(&self, root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>, walking: &mut Vec<PathBuf>) -> Result<(), std::io::Error> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let is_link = entry.file_type()?.is_symlink();
+            let relative = relative_path(root, &path);
+            let is_dir = if is_link { self.link_policy() == Symlinks::Follow && path.is_dir() } else { entry.file_type()?.is_dir() };
+
+            if self.excludes.excludes(&relative, is_dir) {
+                continue;
+            }
+            if is_dir {
+                if self.link_policy() == Symlinks::Follow {
+                    let canonical = fs::canonicalize(&path)?;
+                    if walking.contains(&canonical) {
+                        continue;
+                    }
+                    walking.push(canonical);
+                    self.walk_dir(root, &path, files, walking)?;
+                    walking.pop();
+                } else {
+                    self.walk_dir(root, &path, files, walking)?;
+                }
+            } else if self.includes_file(is_link, &path) {
+                files.push((relative, path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Recomputes the directory-level tree from the file entries.
+    pub(crate) fn rebuild(&mut self) -> Result<(), std::io::Error> {
+        self.files.sort_by(|a, b| a.path.cmp(&b.path));
+        let leaves: Vec<Vec<u8>> = self.files.iter().map(|entry| self.entry_hash(entry)).collect();
+        self.tree = HashTree::new(self.block_size).from_leaf_hashes(leaves)?;
+        self.root = self.tree.root();
+        #[cfg(feature = "git")]
+        if self.git {
+            self.root = Some(crate::git::tree_id(&self.files));
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the root commits only to the paths and contents of
+    /// the files, as a `Manifest` records them: not in Git mode and without
+    /// permissions or modification times.
+    pub(crate) fn commits_to_contents_only(&self) -> bool {
+        #[cfg(feature = "git")]
+        if self.git {
+            return false;
+        }
+        !self.permissions && !self.mtime
+    }
+
+    /// Returns `true` if the `DirTree` contains no files.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Returns the root hash of the `DirTree` as an `Option<String>`.
+    pub fn root_hash(&self) -> Option<String> {
+        self.root.as_ref().map(hex::encode)
+    }
+
+    /// Returns the block size used to hash the files.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Returns the number of files in the `DirTree`.
+    pub fn num_files(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Returns the file entries, sorted by relative path.
+    pub fn files(&self) -> &[FileEntry] {
+        &self.files
+    }
+
+    /// Returns the entry for the file at the given relative path.
+    pub fn file(&self, path: &str) -> Option<&FileEntry> {
+        self.files
+            .binary_search_by(|entry| entry.path.as_str().cmp(path))
+            .ok()
+            .map(|i| &self.files[i])
+    }
+}
+
+/// A file that differs between two `DirTree`s.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileDiff {
+    /// Relative path of the file.
+    pub path: String,
+    /// Ranges of block indices whose contents differ.
+    pub blocks: Vec<Range<u64>>,
+}
+
+/// The differences between two `DirTree`s, as returned by `DirTree::diff`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DirDiff {
+    /// Files present only in the other tree.
+    pub added: Vec<String>,
+    /// Files present only in this tree.
+    pub removed: Vec<String>,
+    /// Files present in both trees with different contents.
+    pub modified: Vec<FileDiff>,
+}
+
+impl DirDiff {
+    /// Returns `true` if the two trees contained the same files with the same contents.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl DirTree {
+    /// Compares `self` (the old snapshot) against `other` (the new snapshot)
+    /// and reports added, removed and modified files. For modified files the
+    /// differing block ranges are reported as well; files whose contents are
+    /// equal but whose committed metadata, see `include_permissions`, differs
+    /// are reported without blocks.
+    pub fn diff(&self, other: &DirTree) -> DirDiff {
+        let mut diff = DirDiff::default();
+        let (mut old, mut new) = (self.files.iter().peekable(), other.files.iter().peekable());
+
+        loop {
+            match (old.peek(), new.peek()) {
+                (Some(a), Some(b)) if a.path == b.path => {
+                    if a.root() != b.root() || a.size != b.size || self.metadata_differs(a, b) {
+                        let blocks = a.tree.diff(&b.tree);
+                        diff.modified.push(FileDiff { path: a.path.clone(), blocks });
+                    }
+                    old.next();
+                    new.next();
+                }
+                (Some(a), Some(b)) if a.path < b.path => {
+                    diff.removed.push(a.path.clone());
+                    old.next();
+                }
+                (Some(a), None) => {
+                    diff.removed.push(a.path.clone());
+                    old.next();
+                }
+                (_, Some(b)) => {
+                    diff.added.push(b.path.clone());
+                    new.next();
+                }
+                (None, None) => break,
+            }
+        }
+        diff
+    }
+}
+
+pub(crate) fn relative_path(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    // Git only looks at the owner's execute bit
+    if metadata.permissions().mode() & 0o100 != 0 { 0o100755 } else { 0o100644 }
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &fs::Metadata) -> u32 {
+    0o100644
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn permissions(metadata: &fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() { 0o444 } else { 0o644 }
+}
+
+/// Returns a modification time in whole seconds since the Unix epoch,
+/// negative before it and 0 if unknown.
+fn mtime_secs(mtime: Option<SystemTime>) -> i64 {
+    match mtime.map(|mtime| mtime.duration_since(UNIX_EPOCH)) {
+        Some(Ok(after)) => after.as_secs() as i64,
+        Some(Err(before)) => -(before.duration().as_secs() as i64),
+        None => 0,
+    }
+}
@@ -0,0 +1,58 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Error, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use crate::hasher::{block_buf, BlockBuf};
+
+/// Alignment of the buffers, offsets and lengths of direct reads, which
+/// covers the logical block size of common devices.
+const ALIGN: usize = 4096;
+/// Size of every direct read.
+const READ_SIZE: usize = 1024 * 1024;
+
+/// Opens `path` for reading with `O_DIRECT`, bypassing the page cache.
+/// Returns `None` if the file system does not support direct I/O.
+pub(crate) fn open_direct(path: &Path) -> Result<Option<File>, Error> {
+    match OpenOptions::new().read(true).custom_flags(libc::O_DIRECT).open(path) {
+        Ok(file) => Ok(Some(file)),
+        Err(error) if error.raw_os_error() == Some(libc::EINVAL) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Reads a file opened with `O_DIRECT` in aligned chunks into an aligned
+/// buffer, handing out its contents in reads of any size.
+pub(crate) struct DirectReader {
+    file: File,
+    buf: BlockBuf,
+    start: usize,
+    pos: usize,
+    filled: usize,
+}
+
+impl DirectReader {
+    pub(crate) fn new(file: File) -> Self {
+        let mut buf = block_buf(READ_SIZE + ALIGN);
+        buf.resize(READ_SIZE + ALIGN, 0);
+        let start = buf.as_ptr().align_offset(ALIGN);
+        Self { file, buf, start, pos: 0, filled: 0 }
+    }
+}
+
+impl Read for DirectReader {
+    fn read(&mut self, out: &mut [u8]) -> Result<usize, Error> {
+        if self.pos == self.filled {
+            // Every read but the last one at the end of the file is full, so
+            // the file offset stays aligned
+            let chunk = &mut self.buf[self.start..self.start + READ_SIZE];
+            self.filled = self.file.read(chunk)?;
+            self.pos = 0;
+        }
+        let available = &self.buf[self.start + self.pos..self.start + self.filled];
+        let len = available.len().min(out.len());
+        out[..len].copy_from_slice(&available[..len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
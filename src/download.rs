@@ -0,0 +1,296 @@
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+
+use crate::hasher::{PairHasher, Sha256Hasher};
+use crate::partial::PartialTree;
+use crate::proof::Proof;
+use crate::root::constant_time_eq;
+use crate::HashTree;
+
+/// Verifies the blocks of a download against a trusted root as they arrive,
+/// in any order, and tracks which blocks are still missing.
+///
+/// A block is verified either with an inclusion proof supplied alongside it,
+/// or against a leaf hash that is already authenticated: one from a tree
+/// given to `from_tree`, e.g. a serialized tree fetched before the data, or
+/// one that appeared as a sibling in an earlier proof. Every block is checked
+/// against the root before it counts as received, so a corrupt block is
+/// rejected immediately rather than when the download completes.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{DownloadVerifier, HashTree};
+///
+/// let data = b"abcdefgh";
+/// let tree = HashTree::new(2).from_slice(data).unwrap();
+/// let root = tree.root_hash().unwrap().into_bytes();
+///
+/// let mut verifier = DownloadVerifier::new(root, 4);
+/// assert!(verifier.accept_with_proof(2, b"ef", &tree.proof(2).unwrap()).unwrap());
+/// // The proof of block 2 holds the hash of block 3
+/// assert!(verifier.accept(3, b"gh").unwrap());
+/// assert!(verifier.accept(0, b"ab").is_err());
+/// assert_eq!(verifier.missing().collect::<Vec<_>>(), [0, 1]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct DownloadVerifier {
+    hasher: Arc<dyn PairHasher>,
+    known: PartialTree,
+    tree: Option<HashTree>,
+    tracker: PieceTracker,
+}
+
+impl DownloadVerifier {
+    /// Constructs a new `DownloadVerifier` for a download of `num_blocks`
+    /// blocks whose tree has the trusted `root`. Blocks are verified with
+    /// proofs until their leaf hashes are known.
+    pub fn new(root: Vec<u8>, num_blocks: usize) -> Self {
+        let hasher: Arc<dyn PairHasher> = Arc::new(Sha256Hasher::new());
+        Self {
+            known: PartialTree::new(root).shared_hasher(hasher.clone()),
+            hasher,
+            tree: None,
+            tracker: PieceTracker::new(num_blocks),
+        }
+    }
+
+    /// Constructs a new `DownloadVerifier` that verifies every block against
+    /// the leaf hashes of `tree`, without proofs. The tree, typically read
+    /// from an untrusted source with `HashTree::read_from`, need not be
+    /// consistent: the hashes taken from it are checked against `root`.
+    /// Returns an `Error` value if the root of `tree` is not `root`.
+    pub fn from_tree(root: Vec<u8>, tree: HashTree) -> Result<Self, Error> {
+        if !tree.root_hash_bytes().is_some_and(|tree_root| constant_time_eq(tree_root, &root)) {
+            return Err(Error::new(ErrorKind::InvalidData, "tree does not have the expected root"));
+        }
+        let hasher = tree.shared_hasher();
+        Ok(Self {
+            known: PartialTree::new(root).shared_hasher(hasher.clone()),
+            hasher,
+            tracker: PieceTracker::new(tree.stored_leaves()),
+            tree: Some(tree),
+        })
+    }
+
+    /// Sets the hash function the blocks and proofs are verified with. The
+    /// default is SHA-256; trees given to `from_tree` bring their own.
+    pub fn hasher<H: PairHasher + 'static>(mut self, hasher: H) -> Self {
+        self.hasher = Arc::new(hasher);
+        self.known = PartialTree::new(self.known.root().to_vec()).shared_hasher(self.hasher.clone());
+        self
+    }
+
+    /// Returns the trusted root.
+    pub fn root(&self) -> &[u8] {
+        self.known.root()
+    }
+
+    /// Verifies `block` as the block at `index` against its authenticated
+    /// leaf hash and marks it received. Returns `true` if the block had not
+    /// been received before. Returns an `Error` value if `index` is out of
+    /// range, the block does not match, or its leaf hash is not known yet,
+    /// in which case it needs a proof.
+    pub fn accept(&mut self, index: usize, block: &[u8]) -> Result<bool, Error> {
+        self.check_index(index)?;
+        let leaf_hash = self.hasher.hash_leaf(block);
+        if self.known.leaf_hash(index as u64).is_none() {
+            let proof = match self.tree.as_ref().and_then(|tree| tree.proof(index)) {
+                Some(proof) => proof,
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("the hash of block {} is not known, it needs a proof", index),
+                    ))
+                }
+            };
+            return self.insert_proof(index, block.len(), &leaf_hash, &proof);
+        }
+        if !self.known.contains(index as u64, &leaf_hash) {
+            return Err(mismatch(index));
+        }
+        Ok(self.tracker.mark_verified(index, block.len()))
+    }
+
+    /// Verifies `block` as the block at `index` with `proof` against the
+    /// root and marks it received. The hashes in the proof become known, so
+    /// the blocks they cover can then be accepted without proofs. Returns
+    /// `true` if the block had not been received before. Returns an `Error`
+    /// value if `index` is out of range or the block and proof do not verify.
+    pub fn accept_with_proof(&mut self, index: usize, block: &[u8], proof: &Proof) -> Result<bool, Error> {
+        self.check_index(index)?;
+        let leaf_hash = self.hasher.hash_leaf(block);
+        self.insert_proof(index, block.len(), &leaf_hash, proof)
+    }
+
+    /// Returns `true` if the block at `index` has been received and verified.
+    pub fn is_received(&self, index: usize) -> bool {
+        self.tracker.is_verified(index)
+    }
+
+    /// Returns the number of blocks of the download.
+    pub fn num_blocks(&self) -> usize {
+        self.tracker.num_blocks()
+    }
+
+    /// Returns the number of distinct blocks received and verified.
+    pub fn num_received(&self) -> usize {
+        self.tracker.num_verified()
+    }
+
+    /// Returns `true` once every block has been received and verified.
+    pub fn is_complete(&self) -> bool {
+        self.tracker.is_complete()
+    }
+
+    /// Returns the indices of the blocks not received yet, in order.
+    pub fn missing(&self) -> impl Iterator<Item = usize> + '_ {
+        self.tracker.missing_blocks()
+    }
+
+    /// Returns the tracker of the verified blocks, e.g. to schedule
+    /// requests for the missing ones.
+    pub fn tracker(&self) -> &PieceTracker {
+        &self.tracker
+    }
+
+    fn check_index(&self, index: usize) -> Result<(), Error> {
+        if index >= self.tracker.num_blocks() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("block {} is out of range for {} blocks", index, self.tracker.num_blocks()),
+            ));
+        }
+        Ok(())
+    }
+
+    fn insert_proof(&mut self, index: usize, len: usize, leaf_hash: &[u8], proof: &Proof) -> Result<bool, Error> {
+        if proof.leaf_index != index as u64 || !self.known.insert_proof(leaf_hash, proof) {
+            return Err(mismatch(index));
+        }
+        Ok(self.tracker.mark_verified(index, len))
+    }
+}
+
+/// A bitmap of the blocks of a download that have been verified, with the
+/// bookkeeping a download scheduler needs to request the missing ones and
+/// retransmit corrupt ones: which blocks are missing, which one to request
+/// next and how many bytes have been verified so far.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::PieceTracker;
+///
+/// let mut tracker = PieceTracker::new(4);
+/// assert!(tracker.mark_verified(0, 1024));
+/// assert!(tracker.mark_verified(2, 1024));
+/// assert!(!tracker.mark_verified(2, 1024));
+/// assert_eq!(tracker.verified_bytes(), 2048);
+/// assert_eq!(tracker.next_needed(), Some(1));
+/// assert_eq!(tracker.next_needed_from(2), Some(3));
+/// assert_eq!(tracker.next_needed_from(4), Some(1));
+/// assert_eq!(tracker.missing_blocks().collect::<Vec<_>>(), [1, 3]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PieceTracker {
+    words: Vec<u64>,
+    num_blocks: usize,
+    num_verified: usize,
+    verified_bytes: u64,
+}
+
+impl PieceTracker {
+    /// Constructs a new `PieceTracker` for `num_blocks` blocks, none of them
+    /// verified.
+    pub fn new(num_blocks: usize) -> Self {
+        Self { words: vec![0; num_blocks.div_ceil(64)], num_blocks, num_verified: 0, verified_bytes: 0 }
+    }
+
+    /// Marks the block at `index`, `len` bytes long, verified. Returns `true`
+    /// if it was not verified before; the bytes of a block are counted once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of range.
+    pub fn mark_verified(&mut self, index: usize, len: usize) -> bool {
+        assert!(index < self.num_blocks, "block {} is out of range for {} blocks", index, self.num_blocks);
+        let (word, bit) = (index / 64, 1 << (index % 64));
+        if self.words[word] & bit != 0 {
+            return false;
+        }
+        self.words[word] |= bit;
+        self.num_verified += 1;
+        self.verified_bytes += len as u64;
+        true
+    }
+
+    /// Returns `true` if the block at `index` has been verified.
+    pub fn is_verified(&self, index: usize) -> bool {
+        index < self.num_blocks && self.words[index / 64] & 1 << (index % 64) != 0
+    }
+
+    /// Returns the number of blocks tracked.
+    pub fn num_blocks(&self) -> usize {
+        self.num_blocks
+    }
+
+    /// Returns the number of distinct blocks verified.
+    pub fn num_verified(&self) -> usize {
+        self.num_verified
+    }
+
+    /// Returns the total length of the verified blocks.
+    pub fn verified_bytes(&self) -> u64 {
+        self.verified_bytes
+    }
+
+    /// Returns `true` once every block has been verified.
+    pub fn is_complete(&self) -> bool {
+        self.num_verified == self.num_blocks
+    }
+
+    /// Returns the indices of the blocks not verified yet, in order.
+    pub fn missing_blocks(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut next = self.missing_from(0);
+        std::iter::from_fn(move || {
+            let index = next?;
+            next = self.missing_from(index + 1);
+            Some(index)
+        })
+    }
+
+    /// Returns the first block not verified yet, or `None` once the
+    /// download is complete.
+    pub fn next_needed(&self) -> Option<usize> {
+        self.missing_from(0)
+    }
+
+    /// Returns the first block not verified yet at or after `index`,
+    /// wrapping around to the start, so that a scheduler can walk the
+    /// missing blocks round-robin. Returns `None` once the download is
+    /// complete.
+    pub fn next_needed_from(&self, index: usize) -> Option<usize> {
+        self.missing_from(index).or_else(|| self.missing_from(0))
+    }
+
+    /// Returns the first block not verified at or after `start`.
+    fn missing_from(&self, start: usize) -> Option<usize> {
+        if start >= self.num_blocks {
+            return None;
+        }
+        let mut word = start / 64;
+        // Bits below `start` in its word count as verified
+        let mut missing = !self.words[word] & (u64::MAX << (start % 64));
+        while missing == 0 {
+            word += 1;
+            missing = !*self.words.get(word)?;
+        }
+        let index = word * 64 + missing.trailing_zeros() as usize;
+        (index < self.num_blocks).then_some(index)
+    }
+}
+
+fn mismatch(index: usize) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("block {} does not match the expected root", index))
+}
@@ -0,0 +1,110 @@
+use core::mem::size_of;
+
+use alloc::vec::Vec;
+
+use crate::store::NodeStore;
+use crate::tree::{Padding, HEADER_LEN};
+use crate::HashTree;
+
+/// The expected shape and cost of a tree, computed by `estimate` or
+/// `HashTree::estimate` without hashing anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TreeEstimate {
+    /// Number of leaves, one per block.
+    pub leaves: u64,
+    /// Number of nodes, including the copies padding every level.
+    pub nodes: u64,
+    /// Number of levels above the leaves, which is the number of levels of
+    /// every proof.
+    pub depth: usize,
+    /// Length of every hash in bytes.
+    pub hash_len: usize,
+    /// Approximate memory held by the nodes in the default in-memory store.
+    pub memory_bytes: u64,
+    /// Size of the tree as written by `HashTree::write_to`.
+    pub serialized_bytes: u64,
+    /// Total size of the sibling hashes of a single proof.
+    pub proof_bytes: u64,
+}
+
+/// Estimates the binary SHA-256 tree over `len` bytes split into blocks of
+/// `block_size` bytes, see `HashTree::estimate`.
+///
+/// # Examples
+///
+/// ```
+/// let estimate = hashtree::estimate(1 << 30, 4096);
+/// assert_eq!(estimate.leaves, 262_144);
+/// assert_eq!(estimate.depth, 18);
+/// assert_eq!(estimate.proof_bytes, 18 * 32);
+/// ```
+pub fn estimate(len: u64, block_size: usize) -> TreeEstimate {
+    HashTree::new(block_size).estimate(len)
+}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Estimates the tree that `from_slice` would build over `len` bytes with
+    /// the block size, arity and hasher of `self`, to compare block sizes
+    /// before hashing anything. Only the hash length is measured, by hashing
+    /// an empty leaf.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let tree = HashTree::new(4).arity(3);
+    /// let estimate = tree.estimate(10);
+    /// let built = tree.from_slice(&[0; 10]).unwrap();
+    /// assert_eq!(estimate.leaves, built.num_blocks());
+    /// assert_eq!(estimate.nodes, built.num_nodes() as u64);
+    /// ```
+    pub fn estimate(&self, len: u64) -> TreeEstimate {
+        let arity = self.get_arity() as u64;
+        let hash_len = self.get_hasher().hash_leaf(&[]).len();
+        let leaves = match self.block_size_for(len) {
+            0 => 0,
+            block_size => len.div_ceil(block_size as u64),
+        };
+
+        let (nodes, depth) = match (self.get_padding(), leaves) {
+            // A single leaf is its own root unless it is padded with a copy
+            (Padding::Zero | Padding::Promote | Padding::DuplicateUnlessSingle, 1) => (1, 0),
+            _ => count_nodes(leaves, arity),
+        };
+        TreeEstimate {
+            leaves,
+            nodes,
+            depth,
+            hash_len,
+            memory_bytes: memory_bytes(nodes, hash_len),
+            serialized_bytes: HEADER_LEN as u64 + nodes * hash_len as u64,
+            proof_bytes: depth as u64 * (arity - 1) * hash_len as u64,
+        }
+    }
+}
+
+/// Returns the number of nodes and the depth of a tree over `leaves` leaves.
+pub(crate) fn count_nodes(leaves: u64, arity: u64) -> (u64, usize) {
+    // Mirrors the padding of every level to a multiple of the arity
+    let (mut nodes, mut depth) = (0, 0);
+    let mut level = leaves;
+    while level > 0 {
+        if level > 1 || depth == 0 {
+            level = level.div_ceil(arity) * arity;
+        }
+        nodes += level;
+        if level == 1 {
+            break;
+        }
+        level /= arity;
+        depth += 1;
+    }
+    (nodes, depth)
+}
+
+/// Returns the approximate memory held by `nodes` hashes of `hash_len`
+/// bytes in the default in-memory store.
+pub(crate) fn memory_bytes(nodes: u64, hash_len: usize) -> u64 {
+    nodes * (hash_len + size_of::<Vec<u8>>()) as u64
+}
@@ -0,0 +1,135 @@
+/// Gitignore-style exclude patterns of a `DirTree`, matched against paths
+/// relative to its root directory with `/` as separator.
+///
+/// Patterns follow `.gitignore`: blank lines and lines starting with `#` are
+/// ignored, `*` and `?` match within a path component, `**` across them, and
+/// `[...]` a character class. A pattern with a `/` other than a trailing one
+/// is matched against the whole path, others against the last component
+/// only. A trailing `/` restricts a pattern to directories, and a leading `!`
+/// includes again what earlier patterns exclude, except below an excluded
+/// directory. The last matching pattern decides.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Excludes {
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    glob: Vec<u8>,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl Excludes {
+    /// Adds the patterns in `text`, one per line.
+    pub(crate) fn add(&mut self, text: &str) {
+        self.patterns.extend(text.lines().filter_map(Pattern::parse));
+    }
+
+    /// Returns `true` if there are no patterns.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Returns `true` if the entry at `path`, or a directory above it, is
+    /// excluded.
+    pub(crate) fn excludes(&self, path: &str, is_dir: bool) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let mut parents = path.match_indices('/').map(|(end, _)| &path[..end]);
+        parents.any(|parent| self.decide(parent, true)) || self.decide(path, is_dir)
+    }
+
+    /// Returns whether the last pattern matching `path` itself excludes it.
+    fn decide(&self, path: &str, is_dir: bool) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| {
+                (is_dir || !pattern.dir_only)
+                    && glob_match(&pattern.glob, if pattern.anchored { path } else { name }.as_bytes())
+            })
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Self> {
+        let mut line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let negated = line.starts_with('!');
+        // A leading `!` negates, and a backslash escapes a literal `!` or `#`
+        if negated || line.starts_with("\\!") || line.starts_with("\\#") {
+            line = &line[1..];
+        }
+        let dir_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        let anchored = line.contains('/');
+        let line = line.trim_start_matches('/');
+        if line.is_empty() {
+            return None;
+        }
+        Some(Self { glob: line.as_bytes().to_vec(), negated, dir_only, anchored })
+    }
+}
+
+/// Matches `text` against the glob `pattern`.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*'] => true,
+        // `**/` matches any number of whole components, including none
+        [b'*', b'*', b'/', rest @ ..] => {
+            glob_match(rest, text) || text.iter().enumerate().any(|(i, &b)| b == b'/' && glob_match(rest, &text[i + 1..]))
+        }
+        [b'*', rest @ ..] => {
+            let run = text.iter().position(|&b| b == b'/').unwrap_or(text.len());
+            (0..=run).any(|skip| glob_match(rest, &text[skip..]))
+        }
+        [b'?', rest @ ..] => matches!(text, [b, tail @ ..] if *b != b'/' && glob_match(rest, tail)),
+        [b'[', class @ ..] => match (text, class_match(class, text.first().copied())) {
+            ([_, tail @ ..], Some((true, rest))) => glob_match(rest, tail),
+            (_, Some(_)) => false,
+            // An unclosed bracket is literal
+            ([b'[', tail @ ..], None) => glob_match(class, tail),
+            _ => false,
+        },
+        [b'\\', escaped, rest @ ..] => matches!(text, [b, tail @ ..] if b == escaped && glob_match(rest, tail)),
+        [literal, rest @ ..] => matches!(text, [b, tail @ ..] if b == literal && glob_match(rest, tail)),
+    }
+}
+
+/// Matches `byte` against the character class at the start of `class`,
+/// just after its `[`. Returns whether it matched and the pattern after the
+/// class, or `None` if the class is not closed.
+fn class_match(class: &[u8], byte: Option<u8>) -> Option<(bool, &[u8])> {
+    let (negated, mut rest) = match class {
+        [b'!' | b'^', rest @ ..] => (true, rest),
+        _ => (false, class),
+    };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match rest {
+            [b']', tail @ ..] if !first => {
+                let matched = matched != negated && byte.is_some_and(|b| b != b'/');
+                return Some((matched, tail));
+            }
+            [low, b'-', high, tail @ ..] if *high != b']' => {
+                matched |= byte.is_some_and(|b| (*low..=*high).contains(&b));
+                rest = tail;
+            }
+            [c, tail @ ..] => {
+                matched |= byte == Some(*c);
+                rest = tail;
+            }
+            [] => return None,
+        }
+        first = false;
+    }
+}
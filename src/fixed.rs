@@ -0,0 +1,237 @@
+//! A fixed-capacity tree that never allocates, for firmware on
+//! microcontrollers that validates update images against a signed root.
+//!
+//! `FixedTree` holds up to `N` SHA-256 leaf hashes in an array and computes
+//! the same root and proofs as a default binary `HashTree`, whose levels are
+//! padded with copies of their last node. Only the leaves are stored: nodes
+//! above them are recomputed on demand, with stack space proportional to the
+//! depth of the tree, so a tree costs `32 * N` bytes. `FixedProof` carries up
+//! to `D` sibling hashes and verifies a block without the tree.
+//!
+//! # Examples
+//!
+//! ```
+//! use hashtree::fixed::{hash_leaf, FixedProof, FixedTree};
+//! use hashtree::HashTree;
+//!
+//! let image = b"firmware update image";
+//! let mut tree = FixedTree::<8>::new();
+//! for block in image.chunks(4) {
+//!     tree.push_block(block).unwrap();
+//! }
+//! let root = tree.root().unwrap();
+//! assert_eq!(&root[..], HashTree::new(4).from_slice(image).unwrap().root_hash_bytes().unwrap());
+//!
+//! let proof: FixedProof<3> = tree.proof(1).unwrap();
+//! assert!(proof.verify(&root, &hash_leaf(b"ware")));
+//! assert!(!proof.verify(&root, &hash_leaf(b"evil")));
+//! ```
+
+use sha2::{Digest, Sha256};
+
+use crate::root::constant_time_eq;
+
+/// Length of the SHA-256 hashes of a `FixedTree`.
+pub const HASH_LEN: usize = 32;
+
+/// A SHA-256 hash.
+pub type Hash = [u8; HASH_LEN];
+
+/// Returns the leaf hash of a block, as `HashTree` computes it by default.
+pub fn hash_leaf(data: &[u8]) -> Hash {
+    Sha256::digest(data).into()
+}
+
+/// Returns the hash of the parent of `left` and `right`.
+fn hash_pair(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// A binary SHA-256 tree over at most `N` leaves, backed by a fixed array.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedTree<const N: usize> {
+    leaves: [Hash; N],
+    len: usize,
+}
+
+impl<const N: usize> Default for FixedTree<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> FixedTree<N> {
+    /// Constructs a new empty `FixedTree`.
+    pub const fn new() -> Self {
+        Self { leaves: [[0; HASH_LEN]; N], len: 0 }
+    }
+
+    /// Constructs a `FixedTree` from the given leaf hashes.
+    /// Returns the first hash that does not fit as an `Err` value if there
+    /// are more than `N`.
+    pub fn from_leaf_hashes<I: IntoIterator<Item = Hash>>(hashes: I) -> Result<Self, Hash> {
+        let mut tree = Self::new();
+        for hash in hashes {
+            tree.push(hash)?;
+        }
+        Ok(tree)
+    }
+
+    /// Appends a leaf hash.
+    /// Returns it back as an `Err` value if the tree already has `N` leaves.
+    pub fn push(&mut self, hash: Hash) -> Result<(), Hash> {
+        if self.len == N {
+            return Err(hash);
+        }
+        self.leaves[self.len] = hash;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Hashes `block` and appends it as a leaf.
+    /// Returns its hash as an `Err` value if the tree already has `N` leaves.
+    pub fn push_block(&mut self, block: &[u8]) -> Result<(), Hash> {
+        self.push(hash_leaf(block))
+    }
+
+    /// Returns the number of leaves.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the tree has no leaves.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the maximum number of leaves, `N`.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns the leaf hashes.
+    pub fn leaves(&self) -> &[Hash] {
+        &self.leaves[..self.len]
+    }
+
+    /// Returns the number of levels above the leaves: a single leaf is
+    /// paired with a copy of itself, as in `HashTree`.
+    pub fn depth(&self) -> usize {
+        match self.len {
+            0 => 0,
+            1 => 1,
+            len => (usize::BITS - (len - 1).leading_zeros()) as usize,
+        }
+    }
+
+    /// Returns the root hash, or `None` if the tree is empty.
+    pub fn root(&self) -> Option<Hash> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(self.node(self.depth(), 0))
+    }
+
+    /// Returns the proof for the leaf at `index`, or `None` if there is no
+    /// such leaf or the tree is deeper than `D` levels.
+    pub fn proof<const D: usize>(&self, index: usize) -> Option<FixedProof<D>> {
+        let depth = self.depth();
+        if index >= self.len || depth > D {
+            return None;
+        }
+        let mut proof = FixedProof { leaf_index: index, depth, siblings: [[0; HASH_LEN]; D] };
+        let mut offset = index;
+        for level in 0..depth {
+            // A missing sibling is the padding copy of the node itself
+            let sibling = offset ^ 1;
+            let sibling = if sibling < self.level_len(level) { sibling } else { offset };
+            proof.siblings[level] = self.node(level, sibling);
+            offset /= 2;
+        }
+        Some(proof)
+    }
+
+    /// Returns the number of nodes of `level`, without padding.
+    fn level_len(&self, level: usize) -> usize {
+        (0..level).fold(self.len, |len, _| len.div_ceil(2))
+    }
+
+    /// Computes the hash of the node at `offset` of `level`, which must
+    /// exist, from the leaves below it.
+    fn node(&self, level: usize, offset: usize) -> Hash {
+        if level == 0 {
+            return self.leaves[offset];
+        }
+        let left = self.node(level - 1, 2 * offset);
+        let right = if 2 * offset + 1 < self.level_len(level - 1) { self.node(level - 1, 2 * offset + 1) } else { left };
+        hash_pair(&left, &right)
+    }
+}
+
+/// An inclusion proof of a leaf of a binary SHA-256 tree with at most `D`
+/// levels above the leaves, which verifies without allocating.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedProof<const D: usize> {
+    leaf_index: usize,
+    depth: usize,
+    siblings: [Hash; D],
+}
+
+impl<const D: usize> FixedProof<D> {
+    /// Constructs the proof for the leaf at `leaf_index` from its sibling
+    /// hashes from the leaves up, e.g. as received alongside a block of an
+    /// update image. Returns `None` if there are more than `D` siblings or
+    /// too few for `leaf_index`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::fixed::{hash_leaf, FixedProof};
+    /// use hashtree::HashTree;
+    /// use std::convert::TryInto;
+    ///
+    /// let tree = HashTree::new(1).from_slice(b"abcde").unwrap();
+    /// let root: [u8; 32] = tree.root_hash_bytes().unwrap().try_into().unwrap();
+    /// let siblings: Vec<[u8; 32]> =
+    ///     tree.proof(4).unwrap().levels.iter().map(|level| level.siblings[0].as_slice().try_into().unwrap()).collect();
+    /// let proof = FixedProof::<8>::from_siblings(4, &siblings).unwrap();
+    /// assert!(proof.verify(&root, &hash_leaf(b"e")));
+    /// ```
+    pub fn from_siblings(leaf_index: usize, siblings: &[Hash]) -> Option<Self> {
+        let too_few = siblings.len() < usize::BITS as usize && leaf_index >> siblings.len() != 0;
+        if siblings.len() > D || too_few {
+            return None;
+        }
+        let mut proof = Self { leaf_index, depth: siblings.len(), siblings: [[0; HASH_LEN]; D] };
+        proof.siblings[..siblings.len()].copy_from_slice(siblings);
+        Some(proof)
+    }
+
+    /// Returns the index of the proven leaf.
+    pub fn leaf_index(&self) -> usize {
+        self.leaf_index
+    }
+
+    /// Returns the sibling hashes from the leaves up.
+    pub fn siblings(&self) -> &[Hash] {
+        &self.siblings[..self.depth]
+    }
+
+    /// Returns the root implied by this proof for the given leaf hash.
+    pub fn compute_root(&self, leaf_hash: &Hash) -> Hash {
+        let mut hash = *leaf_hash;
+        for (level, sibling) in self.siblings().iter().enumerate() {
+            hash = if (self.leaf_index >> level) & 1 == 0 { hash_pair(&hash, sibling) } else { hash_pair(sibling, &hash) };
+        }
+        hash
+    }
+
+    /// Returns `true` if the leaf with hash `leaf_hash` is included under
+    /// `root`. The roots are compared in constant time.
+    pub fn verify(&self, root: &Hash, leaf_hash: &Hash) -> bool {
+        constant_time_eq(&self.compute_root(leaf_hash), root)
+    }
+}
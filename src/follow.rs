@@ -0,0 +1,151 @@
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read};
+use std::path::Path;
+
+use crate::store::NodeStore;
+use crate::{HashTree, RootHash};
+
+/// Builds the `HashTree` of an append-only input, such as a log file, as it
+/// grows.
+///
+/// Every complete block is appended to the tree as soon as it arrives,
+/// rehashing only the right spine, while a trailing partial block is
+/// buffered until it fills up. The tree and its root therefore only cover
+/// complete blocks; `finish` adds the partial tail once the input ends.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::HashTree;
+///
+/// let mut follower = HashTree::new(4).follow();
+/// assert_eq!(follower.push(b"abc").unwrap(), None);
+/// let root = follower.push(b"defgh").unwrap().unwrap();
+/// assert_eq!(root, HashTree::new(4).from_slice(b"abcdefgh").unwrap().root_hash().unwrap());
+/// assert_eq!(follower.push(b"ij").unwrap(), None);
+/// assert_eq!(follower.tail(), b"ij");
+/// assert_eq!(follower.finish().unwrap(), HashTree::new(4).from_slice(b"abcdefghij").unwrap());
+/// ```
+#[derive(Debug)]
+pub struct TailFollower<S = Vec<Vec<u8>>> {
+    tree: HashTree<S>,
+    tail: Vec<u8>,
+    len: u64,
+    file: Option<File>,
+}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Returns a `TailFollower` that builds `self`, which should be empty,
+    /// from bytes pushed as they are appended to the input.
+    pub fn follow(self) -> TailFollower<S> {
+        TailFollower { tree: self, tail: Vec::new(), len: 0, file: None }
+    }
+
+    /// Returns a `TailFollower` that builds `self`, which should be empty,
+    /// from the file at `path`, reading what the file already holds.
+    /// Call `poll` to pick up what is appended later.
+    /// Returns an `Error` value if the file could not be read.
+    pub fn follow_file<P: AsRef<Path>>(self, path: P) -> Result<TailFollower<S>, Error> {
+        let mut follower = self.follow();
+        follower.file = Some(File::open(path)?);
+        follower.poll()?;
+        Ok(follower)
+    }
+}
+
+impl<S: NodeStore> TailFollower<S> {
+    /// Appends `data` to the input. Returns the new root hash if at least
+    /// one block was completed, or `None` if the data was only buffered.
+    /// Returns an `Error` value if the block size is zero or the store could
+    /// not be written.
+    pub fn push(&mut self, data: &[u8]) -> Result<Option<RootHash>, Error> {
+        let block_size = self.tree.block_size();
+        if block_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "block size must be greater than zero"));
+        }
+        self.len += data.len() as u64;
+        self.tail.extend_from_slice(data);
+        let complete = self.tail.len() / block_size * block_size;
+        if complete == 0 {
+            return Ok(None);
+        }
+
+        let leaves = self.tail[..complete]
+            .chunks(block_size)
+            .map(|block| self.tree.hash_block(block))
+            .collect::<Result<Vec<_>, _>>()?;
+        let lengths = vec![block_size as u64; leaves.len()];
+        self.tail.drain(..complete);
+        let data_len = self.len - self.tail.len() as u64;
+        self.tree.replace_tail(self.tree.stored_leaves(), leaves, Some(lengths), Some(data_len))?;
+        self.tree.record_version();
+        Ok(self.tree.root_hash())
+    }
+
+    /// Appends everything `reader` yields until it reports the end of the
+    /// input. Returns the new root hash if at least one block was completed.
+    /// Returns an `Error` value if `reader` could not be read or the store
+    /// could not be written.
+    pub fn read_from<R: Read>(&mut self, mut reader: R) -> Result<Option<RootHash>, Error> {
+        let mut buf = vec![0; self.tree.block_size().clamp(1, 1 << 16)];
+        let mut root = None;
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => return Ok(root),
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            root = self.push(&buf[..n])?.or(root);
+        }
+    }
+
+    /// Reads what was appended to the followed file since the last call.
+    /// Returns the new root hash if at least one block was completed.
+    /// Returns an `Error` value if the follower was not created by
+    /// `HashTree::follow_file` or the file could not be read.
+    pub fn poll(&mut self) -> Result<Option<RootHash>, Error> {
+        let mut file = self
+            .file
+            .take()
+            .ok_or_else(|| Error::new(ErrorKind::Unsupported, "the follower does not follow a file"))?;
+        let root = self.read_from(&mut file);
+        self.file = Some(file);
+        root
+    }
+
+    /// Returns the tree over the complete blocks received so far.
+    pub fn tree(&self) -> &HashTree<S> {
+        &self.tree
+    }
+
+    /// Returns the buffered bytes of the trailing partial block.
+    pub fn tail(&self) -> &[u8] {
+        &self.tail
+    }
+
+    /// Returns the number of bytes received so far, including the tail.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if no bytes were received yet.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Ends the input, appending the partial tail block if there is one, and
+    /// returns the tree over all of it.
+    /// Returns an `Error` value if the store could not be written.
+    pub fn finish(mut self) -> Result<HashTree<S>, Error> {
+        if self.tree.stored_leaves() == 0 {
+            return self.tree.from_slice(&self.tail);
+        }
+        if !self.tail.is_empty() {
+            let leaf = self.tree.hash_block(&self.tail)?;
+            self.tree.replace_tail(self.tree.stored_leaves(), Some(leaf), Some(vec![self.tail.len() as u64]), Some(self.len))?;
+            self.tree.record_version();
+        }
+        Ok(self.tree)
+    }
+}
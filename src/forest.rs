@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+
+use crate::hasher::{PairHasher, Sha256Hasher};
+use crate::multiproof::{ProofElement, StreamingVerifier};
+use crate::proof::Proof;
+use crate::root::constant_time_eq;
+use crate::store::NodeStore;
+use crate::{HashTree, RootHash};
+
+/// Commits to the roots of many trees, e.g. one per file of a dataset,
+/// under a single super-root.
+///
+/// The super-root is the root of a `HashTree` whose leaves are the leaf
+/// hashes of the tree roots, in order, so a root can never be mistaken for
+/// an interior node. A `ForestProof` shows that a block belongs to one of
+/// the trees and that the tree belongs to the forest.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{Forest, HashTree, PairHasher, Sha256Hasher};
+///
+/// let files = [&b"first file"[..], b"second file", b"third"];
+/// let trees: Vec<HashTree> = files.iter().map(|data| HashTree::new(4).from_slice(data).unwrap()).collect();
+/// let forest = Forest::from_trees(HashTree::new(0), &trees).unwrap();
+///
+/// let proof = forest.proof(1, &trees[1], 2).unwrap();
+/// let root = forest.root_hash().unwrap().into_bytes();
+/// assert_eq!(proof.tree_index(), 1);
+/// assert!(proof.verify(&root, &Sha256Hasher::new().hash_leaf(b"ile")));
+/// assert!(!proof.verify(&root, &Sha256Hasher::new().hash_leaf(b"nd f")));
+/// ```
+#[derive(Debug)]
+pub struct Forest<S = Vec<Vec<u8>>> {
+    tree: HashTree<S>,
+    roots: Vec<Vec<u8>>,
+}
+
+/// A two-level inclusion proof: a block in one tree of a `Forest`, and that
+/// tree's root in the forest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForestProof {
+    /// Root hash of the tree holding the block.
+    pub tree_root: Vec<u8>,
+    /// Proof of the block within its tree.
+    pub block_proof: Proof,
+    /// Proof of the tree root within the forest. Its `leaf_index` is the
+    /// index of the tree.
+    pub tree_proof: Proof,
+}
+
+/// One proof of many blocks drawn from several trees of a `Forest`: a
+/// multiproof of the proven trees' roots within the forest and, for each of
+/// those trees, a multiproof of its proven blocks, so hashes shared by the
+/// paths of several blocks are included once.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{Forest, HashTree, PairHasher, Sha256Hasher};
+///
+/// let files = [&b"first file"[..], b"second file", b"third"];
+/// let trees: Vec<HashTree> = files.iter().map(|data| HashTree::new(4).from_slice(data).unwrap()).collect();
+/// let forest = Forest::from_trees(HashTree::new(0), &trees).unwrap();
+///
+/// let proof = forest.aggregate_proof(&[(0, &trees[0], &[0, 2]), (2, &trees[2], &[1])]).unwrap();
+/// let root = forest.root_hash().unwrap().into_bytes();
+/// let leaf = |block: &[u8]| Sha256Hasher::new().hash_leaf(block);
+/// let (first, file, e) = (leaf(b"firs"), leaf(b"le"), leaf(b"d"));
+/// assert!(proof.verify(&root, &[(0, 0, &first), (0, 2, &file), (2, 1, &e)]));
+/// assert!(!proof.verify(&root, &[(0, 0, &first), (0, 2, &e), (2, 1, &e)]));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AggregateProof {
+    /// Arity of the tree over the roots.
+    pub forest_arity: usize,
+    /// Multiproof of the leaf hashes of the proven trees' roots within the
+    /// forest, see `HashTree::multiproof_stream`.
+    pub forest: Vec<ProofElement>,
+    /// The multiproofs of the proven trees, in order of their index.
+    pub trees: Vec<TreeMultiproof>,
+}
+
+/// The part of an `AggregateProof` covering the blocks of one tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeMultiproof {
+    /// Index of the tree in the forest.
+    pub tree_index: usize,
+    /// Root hash of the tree.
+    pub tree_root: Vec<u8>,
+    /// Arity of the tree.
+    pub arity: usize,
+    /// Multiproof of the proven blocks within the tree.
+    pub elements: Vec<ProofElement>,
+}
+
+impl<S: NodeStore> Forest<S> {
+    /// Constructs a `Forest` over the given tree `roots`, building `tree`,
+    /// which should be empty, as the tree over them. Its block size is unused.
+    /// Returns an `Error` value if the store could not be written.
+    pub fn from_roots<I>(tree: HashTree<S>, roots: I) -> Result<Self, Error>
+    where
+        I: IntoIterator,
+        I::Item: Into<Vec<u8>>,
+    {
+        let roots: Vec<Vec<u8>> = roots.into_iter().map(Into::into).collect();
+        let hasher = tree.shared_hasher();
+        let tree = tree.from_leaf_hashes(roots.iter().map(|root| hasher.hash_leaf(root)))?;
+        Ok(Self { tree, roots })
+    }
+
+    /// Constructs a `Forest` over the roots of `trees`.
+    /// Returns an `Error` value if one of the trees is empty or the store
+    /// could not be written.
+    pub fn from_trees<'a, T, I>(tree: HashTree<S>, trees: I) -> Result<Self, Error>
+    where
+        T: NodeStore + 'a,
+        I: IntoIterator<Item = &'a HashTree<T>>,
+    {
+        let roots = trees
+            .into_iter()
+            .enumerate()
+            .map(|(index, tree)| {
+                tree.root().ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("tree {} is empty", index)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::from_roots(tree, roots)
+    }
+
+    /// Returns the super-root committing to every tree, or `None` if the
+    /// forest is empty.
+    pub fn root_hash(&self) -> Option<RootHash> {
+        self.tree.root_hash()
+    }
+
+    /// Returns the roots of the trees, in order.
+    pub fn roots(&self) -> &[Vec<u8>] {
+        &self.roots
+    }
+
+    /// Returns the number of trees in the forest.
+    pub fn len(&self) -> usize {
+        self.roots.len()
+    }
+
+    /// Returns `true` if the forest holds no trees.
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    /// Returns the tree over the roots.
+    pub fn tree(&self) -> &HashTree<S> {
+        &self.tree
+    }
+
+    /// Returns the proof that the root of the tree at `index` is part of the
+    /// forest, or `None` if there is no such tree.
+    pub fn root_proof(&self, index: usize) -> Option<Proof> {
+        self.tree.proof(index)
+    }
+
+    /// Returns the proof of the block at `block` of `tree`, the tree at
+    /// `index`. Returns `None` if there is no such tree or block, or if the
+    /// root of `tree` is not the one recorded at `index`.
+    pub fn proof<T: NodeStore>(&self, index: usize, tree: &HashTree<T>, block: usize) -> Option<ForestProof> {
+        let tree_root = tree.root()?;
+        if *self.roots.get(index)? != tree_root {
+            return None;
+        }
+        Some(ForestProof { tree_root, block_proof: tree.proof(block)?, tree_proof: self.root_proof(index)? })
+    }
+
+    /// Returns one proof of the blocks at the given indices of several trees,
+    /// each given as `(index, tree, blocks)` with `tree` the tree at `index`.
+    /// Returns `None` if a tree is given twice or has no blocks, or for the
+    /// reasons of `proof`.
+    pub fn aggregate_proof<T: NodeStore>(&self, trees: &[(usize, &HashTree<T>, &[usize])]) -> Option<AggregateProof> {
+        let mut parts = Vec::with_capacity(trees.len());
+        for &(index, tree, blocks) in trees {
+            let tree_root = tree.root()?;
+            if blocks.is_empty() || *self.roots.get(index)? != tree_root {
+                return None;
+            }
+            let elements = tree.multiproof_stream(blocks)?;
+            parts.push(TreeMultiproof { tree_index: index, tree_root, arity: tree.get_arity(), elements });
+        }
+        parts.sort_by_key(|part| part.tree_index);
+        if parts.windows(2).any(|pair| pair[0].tree_index == pair[1].tree_index) {
+            return None;
+        }
+        let indices: Vec<usize> = parts.iter().map(|part| part.tree_index).collect();
+        Some(AggregateProof {
+            forest_arity: self.tree.get_arity(),
+            forest: self.tree.multiproof_stream(&indices)?,
+            trees: parts,
+        })
+    }
+}
+
+impl AggregateProof {
+    /// Returns `true` if exactly the blocks given as `(tree index, block
+    /// index, leaf hash)` are proven under the forest's super-root `root`,
+    /// assuming both levels use the default SHA-256 hasher.
+    pub fn verify(&self, root: &[u8], blocks: &[(usize, usize, &[u8])]) -> bool {
+        self.verify_with(&Sha256Hasher::new(), root, blocks)
+    }
+
+    /// Returns `true` if exactly the blocks given as `(tree index, block
+    /// index, leaf hash)` are proven under the forest's super-root `root`,
+    /// with both levels hashed by `hasher`. As with `ForestProof`, a tree
+    /// whose multiproof is a lone leaf is rejected, since it would only show
+    /// that the leaf hash is the tree root itself.
+    pub fn verify_with(&self, hasher: &dyn PairHasher, root: &[u8], blocks: &[(usize, usize, &[u8])]) -> bool {
+        let mut claimed: BTreeMap<(usize, usize), &[u8]> = BTreeMap::new();
+        for &(tree, block, hash) in blocks {
+            if claimed.insert((tree, block), hash).is_some() {
+                return false;
+            }
+        }
+
+        // The proven roots, in order, are the leaves of the forest multiproof
+        let mut valid = true;
+        let mut parts = self.trees.iter();
+        let mut verifier = StreamingVerifier::new(hasher, self.forest_arity);
+        for element in &self.forest {
+            if let ProofElement::Leaf { index, hash } = element {
+                match parts.next() {
+                    Some(part) if part.tree_index == *index => {
+                        valid &= constant_time_eq(hash, &hasher.hash_leaf(&part.tree_root));
+                    }
+                    _ => return false,
+                }
+            }
+            valid &= verifier.push(element).is_ok();
+        }
+        if parts.next().is_some() {
+            return false;
+        }
+        valid &= verifier.finish(root);
+
+        for part in &self.trees {
+            let mut verifier = StreamingVerifier::new(hasher, part.arity);
+            for element in &part.elements {
+                if let ProofElement::Leaf { index, hash } = element {
+                    match claimed.remove(&(part.tree_index, *index)) {
+                        Some(expected) => valid &= constant_time_eq(hash, expected),
+                        None => return false,
+                    }
+                }
+                valid &= verifier.push(element).is_ok();
+            }
+            valid &= part.elements.len() > 1 && verifier.finish(&part.tree_root);
+        }
+        valid && claimed.is_empty()
+    }
+
+    /// Returns the number of hashes in the proof, tree roots included.
+    pub fn num_hashes(&self) -> usize {
+        self.forest.len() + self.trees.iter().map(|part| 1 + part.elements.len()).sum::<usize>()
+    }
+}
+
+impl ForestProof {
+    /// Returns the index of the tree holding the block.
+    pub fn tree_index(&self) -> u64 {
+        self.tree_proof.leaf_index
+    }
+
+    /// Returns `true` if the block with hash `leaf_hash` is included under the
+    /// forest's super-root `root`, assuming both levels use the default
+    /// SHA-256 hasher.
+    pub fn verify(&self, root: &[u8], leaf_hash: &[u8]) -> bool {
+        self.verify_with(&Sha256Hasher::new(), root, leaf_hash)
+    }
+
+    /// Returns `true` if the block with hash `leaf_hash` is included under the
+    /// forest's super-root `root`, with both levels hashed by `hasher`.
+    pub fn verify_with(&self, hasher: &dyn PairHasher, root: &[u8], leaf_hash: &[u8]) -> bool {
+        // Leaves are always padded below a parent, so a proof without levels
+        // would only show that `leaf_hash` is the tree root itself. Evaluate
+        // both levels so the running time does not reveal which failed
+        let in_tree = !self.block_proof.levels.is_empty()
+            & self.block_proof.verify_with(hasher, &self.tree_root, leaf_hash);
+        let in_forest = self.tree_proof.verify_with(hasher, root, &hasher.hash_leaf(&self.tree_root));
+        in_tree & in_forest
+    }
+}
@@ -0,0 +1,123 @@
+use alloc::vec::Vec;
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::metrics::Phase;
+use crate::proof::{Proof, ProofLevel};
+use crate::root::{Encoding, RootHash};
+use crate::HashTree;
+
+/// Largest block size of arbitrary trees, kept small so that trees built
+/// from fuzzer input have many blocks.
+const MAX_BLOCK_SIZE: usize = 64;
+
+/// Largest arity of arbitrary trees and proofs.
+const MAX_ARITY: usize = 8;
+
+/// Length of the hashes in arbitrary proofs and roots, that of SHA-256.
+const HASH_LEN: usize = 32;
+
+fn hash(u: &mut Unstructured<'_>) -> Result<Vec<u8>> {
+    Ok(<[u8; HASH_LEN]>::arbitrary(u)?.to_vec())
+}
+
+/// Builds a SHA-256 tree with an arbitrary block size and arity over
+/// arbitrary data, so its nodes are always consistent with each other.
+impl<'a> Arbitrary<'a> for HashTree {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let block_size = u.int_in_range(1..=MAX_BLOCK_SIZE)?;
+        let arity = u.int_in_range(2..=MAX_ARITY)?;
+        let data: &[u8] = u.arbitrary()?;
+        let tree = HashTree::new(block_size).arity(arity);
+        Ok(tree.from_slice(data).expect("the in-memory store cannot fail"))
+    }
+}
+
+/// Generates `HASH_LEN` byte roots.
+impl<'a> Arbitrary<'a> for RootHash {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(RootHash::new(hash(u)?))
+    }
+
+    fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+        (HASH_LEN, Some(HASH_LEN))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Encoding {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(*u.choose(&[Encoding::Hex, Encoding::UpperHex, Encoding::Base64, Encoding::Base64Url, Encoding::Base32])?)
+    }
+}
+
+impl<'a> Arbitrary<'a> for Phase {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(*u.choose(&[Phase::Leaves, Phase::Build])?)
+    }
+}
+
+/// Generates a level of a binary tree proof with a `HASH_LEN` byte sibling.
+impl<'a> Arbitrary<'a> for ProofLevel {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(ProofLevel { position: u.int_in_range(0..=1)?, siblings: alloc::vec![hash(u)?] })
+    }
+}
+
+/// Generates a well-formed proof: every level has `arity - 1` siblings of
+/// `HASH_LEN` bytes and positions matching `leaf_index`, for an arbitrary
+/// arity. The hashes are random, so the proof almost never verifies; use
+/// `ProvenBlock` for proofs that do.
+impl<'a> Arbitrary<'a> for Proof {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let arity = u.int_in_range(2..=MAX_ARITY)?;
+        let depth = u.int_in_range(0..=16u32)?;
+        let leaf_index = u.int_in_range(0..=(arity as u64).pow(depth) - 1)?;
+        let mut siblings = Vec::new();
+        for _ in 0..depth as usize * (arity - 1) {
+            siblings.push(hash(u)?);
+        }
+        Ok(Proof::from_siblings(leaf_index, arity, siblings).expect("siblings form whole levels"))
+    }
+}
+
+/// A block of an arbitrary `HashTree` together with its valid inclusion
+/// proof, for testing code that consumes proofs. Requires the `arbitrary`
+/// feature.
+///
+/// # Examples
+///
+/// ```
+/// use arbitrary::{Arbitrary, Unstructured};
+/// use hashtree::fuzz::ProvenBlock;
+/// use hashtree::{PairHasher, Sha256Hasher};
+///
+/// let mut u = Unstructured::new(b"some fuzzer input, long enough for a few blocks");
+/// let block = ProvenBlock::arbitrary(&mut u).unwrap();
+/// let root = block.tree.root_hash().unwrap().into_bytes();
+/// assert!(block.proof.verify(&root, &Sha256Hasher::new().hash_leaf(&block.data)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProvenBlock {
+    /// The tree containing the block.
+    pub tree: HashTree,
+    /// The contents of the block.
+    pub data: Vec<u8>,
+    /// The proof of the block under the root of `tree`.
+    pub proof: Proof,
+}
+
+impl<'a> Arbitrary<'a> for ProvenBlock {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let block_size = u.int_in_range(1..=MAX_BLOCK_SIZE)?;
+        let arity = u.int_in_range(2..=MAX_ARITY)?;
+        let mut data: Vec<u8> = u.arbitrary()?;
+        if data.is_empty() {
+            data.push(u.arbitrary()?);
+        }
+        let tree = HashTree::new(block_size).arity(arity).from_slice(&data).expect("the in-memory store cannot fail");
+        let index = u.choose_index(tree.stored_leaves())?;
+        let proof = tree.proof(index).expect("index is in range");
+        let data = data.chunks(block_size).nth(index).expect("index is in range").to_vec();
+        Ok(ProvenBlock { tree, data, proof })
+    }
+}
@@ -0,0 +1,151 @@
+use std::io::{Error, ErrorKind};
+use std::ops::Range;
+
+use crate::{HashTree, LeafIndex};
+
+/// One tree in a `GenerationChain`, linked to the generation before it by
+/// that generation's root hash.
+#[derive(Debug, Clone)]
+pub struct Generation {
+    number: usize,
+    parent: Option<Vec<u8>>,
+    tree: HashTree,
+}
+
+impl Generation {
+    /// Returns the number of the generation, counting from 0.
+    pub fn number(&self) -> usize {
+        self.number
+    }
+
+    /// Returns the root hash of the previous generation, or `None` for the
+    /// first generation or if the previous tree was empty.
+    pub fn parent_root(&self) -> Option<&[u8]> {
+        self.parent.as_deref()
+    }
+
+    /// Returns the tree of the generation.
+    pub fn tree(&self) -> &HashTree {
+        &self.tree
+    }
+}
+
+/// Successive trees of the same dataset, e.g. one per backup run, with the
+/// blocks that changed between any two of them.
+///
+/// Every generation must be built with the block size and arity of the first
+/// one, so that equal block indices cover the same bytes of the dataset.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{GenerationChain, HashTree};
+///
+/// let mut chain = GenerationChain::new();
+/// chain.push(HashTree::new(4).from_slice(b"aaaabbbbcccc").unwrap()).unwrap();
+/// chain.push(HashTree::new(4).from_slice(b"aaaaBBBBcccc").unwrap()).unwrap();
+/// chain.push(HashTree::new(4).from_slice(b"aaaaBBBBcccCdd").unwrap()).unwrap();
+///
+/// assert_eq!(chain.changed_since(1).unwrap(), vec![2..4]);
+/// assert_eq!(chain.changed_since(0).unwrap(), vec![1..4]);
+/// assert_eq!(chain.incremental_size(1).unwrap(), 6);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct GenerationChain {
+    generations: Vec<Generation>,
+}
+
+impl GenerationChain {
+    /// Constructs a new empty `GenerationChain`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `tree` as the next generation and returns it.
+    /// Returns an `Error` value if its block size or arity differ from those
+    /// of the first generation.
+    pub fn push(&mut self, tree: HashTree) -> Result<&Generation, Error> {
+        if let Some(first) = self.generations.first() {
+            if tree.block_size() != first.tree.block_size() || tree.get_arity() != first.tree.get_arity() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "generations must share the block size and arity of the first generation",
+                ));
+            }
+        }
+        let parent = self.latest().and_then(|latest| latest.tree.root_hash_bytes()).map(<[u8]>::to_vec);
+        self.generations.push(Generation { number: self.generations.len(), parent, tree });
+        Ok(&self.generations[self.generations.len() - 1])
+    }
+
+    /// Returns generation `number`, or `None` if there is no such generation.
+    pub fn get(&self, number: usize) -> Option<&Generation> {
+        self.generations.get(number)
+    }
+
+    /// Returns the most recent generation, or `None` if the chain is empty.
+    pub fn latest(&self) -> Option<&Generation> {
+        self.generations.last()
+    }
+
+    /// Returns the number of generations.
+    pub fn len(&self) -> usize {
+        self.generations.len()
+    }
+
+    /// Returns `true` if the chain has no generations.
+    pub fn is_empty(&self) -> bool {
+        self.generations.is_empty()
+    }
+
+    /// Returns an iterator over the generations, oldest first.
+    pub fn iter(&self) -> std::slice::Iter<'_, Generation> {
+        self.generations.iter()
+    }
+
+    /// Returns the ranges of block indices that differ between generations
+    /// `from` and `to`, see `HashTree::diff`.
+    /// Returns an `Error` value if either generation does not exist.
+    pub fn changed_between(&self, from: usize, to: usize) -> Result<Vec<Range<u64>>, Error> {
+        Ok(self.generation(from)?.tree.diff(&self.generation(to)?.tree))
+    }
+
+    /// Returns the ranges of block indices that changed in the latest
+    /// generation since generation `number`.
+    /// Returns an `Error` value if there is no such generation.
+    pub fn changed_since(&self, number: usize) -> Result<Vec<Range<u64>>, Error> {
+        self.changed_between(number, self.generations.len().saturating_sub(1))
+    }
+
+    /// Estimates the number of bytes an incremental backup of the latest
+    /// generation on top of generation `number` has to copy: the length of
+    /// every block that changed and is still present. Blocks that cannot be
+    /// located, see `HashTree::leaf_range`, count as full blocks.
+    /// Returns an `Error` value if there is no such generation.
+    pub fn incremental_size(&self, number: usize) -> Result<u64, Error> {
+        let changed = self.changed_since(number)?;
+        let latest = &self.generation(self.generations.len() - 1)?.tree;
+        let size = changed
+            .into_iter()
+            .flatten()
+            .filter(|&index| index < latest.num_blocks())
+            .map(|index| latest.leaf_range(LeafIndex(index)).map_or(latest.block_size() as u64, |range| range.end - range.start))
+            .sum();
+        Ok(size)
+    }
+
+    fn generation(&self, number: usize) -> Result<&Generation, Error> {
+        self.generations.get(number).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, format!("generation {} does not exist", number))
+        })
+    }
+}
+
+impl<'a> IntoIterator for &'a GenerationChain {
+    type Item = &'a Generation;
+    type IntoIter = std::slice::Iter<'a, Generation>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.generations.iter()
+    }
+}
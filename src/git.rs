@@ -0,0 +1,93 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::Path;
+use sha1::{Digest, Sha1};
+
+use crate::FileEntry;
+
+/// Returns the Git object id of the file at `path` hashed as a blob.
+pub fn blob_id<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, std::io::Error> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", len));
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 { break; }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Returns the Git object id of `data` hashed as a blob.
+pub(crate) fn blob_id_of(data: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", data.len()));
+    hasher.update(data);
+    hasher.finalize().to_vec()
+}
+
+/// Returns the Git object id of a tree with the given `(name, mode, id)` entries.
+/// Entries are sorted the way Git sorts them, with directory names compared as
+/// if they had a trailing `/`.
+pub fn tree_object_id(entries: &mut [(String, u32, Vec<u8>)]) -> Vec<u8> {
+    entries.sort_by_key(|(name, mode, _)| sort_key(name, *mode));
+
+    let mut body = Vec::new();
+    for (name, mode, id) in entries.iter() {
+        body.extend_from_slice(format!("{:o} {}\0", mode, name).as_bytes());
+        body.extend_from_slice(id);
+    }
+
+    let mut hasher = Sha1::new();
+    hasher.update(format!("tree {}\0", body.len()));
+    hasher.update(&body);
+    hasher.finalize().to_vec()
+}
+
+/// Returns the Git tree id of the directory containing `files`, whose paths
+/// are relative to that directory. Every file must carry its blob id.
+pub(crate) fn tree_id(files: &[FileEntry]) -> Vec<u8> {
+    let entries: Vec<(&str, u32, &[u8])> = files
+        .iter()
+        .map(|f| (f.path.as_str(), f.mode, f.git_id.as_deref().expect("missing Git blob id")))
+        .collect();
+    subtree_id(&entries)
+}
+
+fn subtree_id(files: &[(&str, u32, &[u8])]) -> Vec<u8> {
+    let mut entries = Vec::new();
+    let mut i = 0;
+    while i < files.len() {
+        let (path, mode, id) = files[i];
+        match path.split_once('/') {
+            None => {
+                entries.push((path.to_string(), mode, id.to_vec()));
+                i += 1;
+            }
+            Some((dir, _)) => {
+                let prefix = format!("{}/", dir);
+                let children: Vec<(&str, u32, &[u8])> = files[i..]
+                    .iter()
+                    .take_while(|(p, _, _)| p.starts_with(&prefix))
+                    .map(|&(p, m, id)| (&p[prefix.len()..], m, id))
+                    .collect();
+                i += children.len();
+                entries.push((dir.to_string(), 0o40000, subtree_id(&children)));
+            }
+        }
+    }
+    tree_object_id(&mut entries)
+}
+
+fn sort_key(name: &str, mode: u32) -> Vec<u8> {
+    let mut key = name.as_bytes().to_vec();
+    if mode == 0o40000 {
+        key.push(b'/');
+    }
+    key
+}
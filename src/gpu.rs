@@ -0,0 +1,391 @@
+use std::ffi::{c_void, CString};
+use std::fmt;
+use std::mem;
+use std::os::raw::c_char;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+use libloading::Library;
+
+use crate::hasher::PairHasher;
+use crate::simd::{MultiSha256Hasher, IV, K};
+
+/// Fewest equally long blocks worth a round trip to the GPU.
+const MIN_GPU_BATCH: usize = 64;
+/// Most blocks passed to `hash_leaves` at once.
+const MAX_GPU_BATCH: usize = 1 << 16;
+/// Most bytes of blocks passed to `hash_leaves` at once.
+const GPU_BATCH_BYTES: usize = 64 * 1024 * 1024;
+
+#[cfg(target_os = "windows")]
+const LIBRARIES: &[&str] = &["OpenCL.dll"];
+#[cfg(target_os = "macos")]
+const LIBRARIES: &[&str] = &["/System/Library/Frameworks/OpenCL.framework/OpenCL"];
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const LIBRARIES: &[&str] = &["libOpenCL.so.1", "libOpenCL.so"];
+
+const CL_SUCCESS: i32 = 0;
+const CL_TRUE: u32 = 1;
+const CL_DEVICE_TYPE_GPU: u64 = 1 << 2;
+const CL_MEM_WRITE_ONLY: u64 = 1 << 1;
+const CL_MEM_READ_ONLY: u64 = 1 << 2;
+const CL_MEM_COPY_HOST_PTR: u64 = 1 << 5;
+
+/// An OpenCL object: platform, device, context, queue, program, kernel or
+/// buffer.
+type Handle = *mut c_void;
+
+/// The OpenCL kernel hashing one block per work item. The blocks are `len`
+/// bytes each and stored back to back; the state words of every digest are
+/// written to `out`. The round constants `K` and initial hash value `IV` are
+/// prepended by `kernel_source`.
+const KERNEL: &str = r#"
+#define ROTR(x, n) rotate((x), (uint)(32 - (n)))
+
+__kernel void sha256_leaves(__global const uchar *data, ulong len, __global uint *out) {
+    size_t id = get_global_id(0);
+    __global const uchar *message = data + id * len;
+    ulong blocks = (len + 72) / 64;
+    ulong bits = len * 8;
+    uint h[8];
+    uint w[64];
+    for (int i = 0; i < 8; i++) {
+        h[i] = IV[i];
+    }
+    for (ulong block = 0; block < blocks; block++) {
+        for (int t = 0; t < 16; t++) {
+            uint word = 0;
+            for (int j = 0; j < 4; j++) {
+                ulong i = block * 64 + t * 4 + j;
+                uint byte = 0;
+                if (i < len) {
+                    byte = message[i];
+                } else if (i == len) {
+                    byte = 0x80;
+                } else if (i >= blocks * 64 - 8) {
+                    byte = (uint)(bits >> ((blocks * 64 - 1 - i) * 8)) & 0xff;
+                }
+                word = (word << 8) | byte;
+            }
+            w[t] = word;
+        }
+        for (int t = 16; t < 64; t++) {
+            uint s0 = ROTR(w[t - 15], 7) ^ ROTR(w[t - 15], 18) ^ (w[t - 15] >> 3);
+            uint s1 = ROTR(w[t - 2], 17) ^ ROTR(w[t - 2], 19) ^ (w[t - 2] >> 10);
+            w[t] = w[t - 16] + s0 + w[t - 7] + s1;
+        }
+        uint a = h[0], b = h[1], c = h[2], d = h[3], e = h[4], f = h[5], g = h[6], hh = h[7];
+        for (int t = 0; t < 64; t++) {
+            uint t1 = hh + (ROTR(e, 6) ^ ROTR(e, 11) ^ ROTR(e, 25)) + ((e & f) ^ (~e & g)) + K[t] + w[t];
+            uint t2 = (ROTR(a, 2) ^ ROTR(a, 13) ^ ROTR(a, 22)) + ((a & b) ^ (a & c) ^ (b & c));
+            hh = g;
+            g = f;
+            f = e;
+            e = d + t1;
+            d = c;
+            c = b;
+            b = a;
+            a = t1 + t2;
+        }
+        h[0] += a;
+        h[1] += b;
+        h[2] += c;
+        h[3] += d;
+        h[4] += e;
+        h[5] += f;
+        h[6] += g;
+        h[7] += hh;
+    }
+    for (int i = 0; i < 8; i++) {
+        out[id * 8 + i] = h[i];
+    }
+}
+"#;
+
+/// Returns the source of the OpenCL program: the SHA-256 constants followed
+/// by `KERNEL`.
+fn kernel_source() -> String {
+    let table = |words: &[u32]| words.iter().map(|word| format!("{:#010x}", word)).collect::<Vec<_>>().join(", ");
+    format!("__constant uint K[64] = {{{}}};\n__constant uint IV[8] = {{{}}};\n{}", table(&K), table(&IV), KERNEL)
+}
+
+macro_rules! opencl_api {
+    ($($field:ident = $symbol:literal: fn($($arg:ty),*) -> $ret:ty;)*) => {
+        /// The OpenCL functions used, loaded from the platform's library.
+        struct Api {
+            $($field: unsafe extern "system" fn($($arg),*) -> $ret,)*
+            _library: Library,
+        }
+
+        impl Api {
+            /// Loads the OpenCL library, returning `None` if it or one of
+            /// the functions is missing.
+            fn load() -> Option<Self> {
+                // SAFETY: the OpenCL library has no initialisation routines
+                // with preconditions
+                let library = LIBRARIES.iter().find_map(|name| unsafe { Library::new(name) }.ok())?;
+                $(
+                    // SAFETY: the signature matches the OpenCL 1.2 headers
+                    let $field: unsafe extern "system" fn($($arg),*) -> $ret = *unsafe { library.get(concat!($symbol, "\0").as_bytes()) }.ok()?;
+                )*
+                Some(Self { $($field,)* _library: library })
+            }
+        }
+    };
+}
+
+opencl_api! {
+    get_platform_ids = "clGetPlatformIDs": fn(u32, *mut Handle, *mut u32) -> i32;
+    get_device_ids = "clGetDeviceIDs": fn(Handle, u64, u32, *mut Handle, *mut u32) -> i32;
+    create_context = "clCreateContext": fn(*const isize, u32, *const Handle, *const c_void, *mut c_void, *mut i32) -> Handle;
+    create_command_queue = "clCreateCommandQueue": fn(Handle, Handle, u64, *mut i32) -> Handle;
+    create_program_with_source = "clCreateProgramWithSource": fn(Handle, u32, *const *const c_char, *const usize, *mut i32) -> Handle;
+    build_program = "clBuildProgram": fn(Handle, u32, *const Handle, *const c_char, *const c_void, *mut c_void) -> i32;
+    create_kernel = "clCreateKernel": fn(Handle, *const c_char, *mut i32) -> Handle;
+    create_buffer = "clCreateBuffer": fn(Handle, u64, usize, *mut c_void, *mut i32) -> Handle;
+    set_kernel_arg = "clSetKernelArg": fn(Handle, u32, usize, *const c_void) -> i32;
+    enqueue_nd_range_kernel = "clEnqueueNDRangeKernel": fn(Handle, Handle, u32, *const usize, *const usize, *const usize, u32, *const Handle, *mut Handle) -> i32;
+    enqueue_read_buffer = "clEnqueueReadBuffer": fn(Handle, Handle, u32, usize, usize, *mut c_void, u32, *const Handle, *mut Handle) -> i32;
+    release_mem_object = "clReleaseMemObject": fn(Handle) -> i32;
+    release_kernel = "clReleaseKernel": fn(Handle) -> i32;
+    release_program = "clReleaseProgram": fn(Handle) -> i32;
+    release_command_queue = "clReleaseCommandQueue": fn(Handle) -> i32;
+    release_context = "clReleaseContext": fn(Handle) -> i32;
+}
+
+/// Returns `Some(value)` if an OpenCL call reported `error` as success.
+fn checked<T>(value: T, error: i32) -> Option<T> {
+    (error == CL_SUCCESS).then_some(value)
+}
+
+/// A GPU with the SHA-256 kernel built for it.
+struct Device {
+    api: Api,
+    context: Handle,
+    queue: Handle,
+    program: Handle,
+    kernel: Handle,
+}
+
+// SAFETY: OpenCL objects may be used from any thread. The kernel, whose
+// arguments are shared state, is only used behind the mutex of
+// `GpuSha256Hasher`.
+unsafe impl Send for Device {}
+
+impl Device {
+    /// Sets up the first GPU of the first OpenCL platform that has one.
+    /// Returns `None` if there is none or setting it up fails.
+    fn open() -> Option<Self> {
+        let api = Api::load()?;
+        let mut platforms = [ptr::null_mut(); 16];
+        let mut count = 0;
+        // SAFETY: the buffers passed are live locals of the stated sizes.
+        let error = unsafe { (api.get_platform_ids)(platforms.len() as u32, platforms.as_mut_ptr(), &mut count) };
+        checked((), error)?;
+        let device = platforms[..(count as usize).min(platforms.len())].iter().find_map(|&platform| {
+            let (mut device, mut found) = (ptr::null_mut(), 0);
+            // SAFETY: as above
+            let error = unsafe { (api.get_device_ids)(platform, CL_DEVICE_TYPE_GPU, 1, &mut device, &mut found) };
+            checked(device, error).filter(|_| found > 0)
+        })?;
+
+        // Objects created so far are released when `this` is dropped early
+        let mut this = Self {
+            api,
+            context: ptr::null_mut(),
+            queue: ptr::null_mut(),
+            program: ptr::null_mut(),
+            kernel: ptr::null_mut(),
+        };
+        let source = CString::new(kernel_source()).ok()?;
+        let mut error = CL_SUCCESS;
+        // SAFETY: every pointer passed is either null where OpenCL allows it
+        // or points to a live value of the stated size.
+        unsafe {
+            this.context = (this.api.create_context)(ptr::null(), 1, &device, ptr::null(), ptr::null_mut(), &mut error);
+            checked((), error)?;
+            this.queue = (this.api.create_command_queue)(this.context, device, 0, &mut error);
+            checked((), error)?;
+            this.program = (this.api.create_program_with_source)(this.context, 1, &source.as_ptr(), ptr::null(), &mut error);
+            checked((), error)?;
+            let error = (this.api.build_program)(this.program, 1, &device, ptr::null(), ptr::null(), ptr::null_mut());
+            checked((), error)?;
+            let mut error = CL_SUCCESS;
+            this.kernel = (this.api.create_kernel)(this.program, b"sha256_leaves\0".as_ptr() as *const c_char, &mut error);
+            checked((), error)?;
+        }
+        Some(this)
+    }
+
+    /// Hashes the blocks of `len` bytes stored back to back in `data`.
+    /// Returns `None` if the GPU reports an error.
+    fn hash(&mut self, data: &[u8], len: usize) -> Option<Vec<Vec<u8>>> {
+        let count = data.len() / len;
+        let mut words = vec![0u32; count * 8];
+        let mut error = CL_SUCCESS;
+        // SAFETY: `data` is copied into the input buffer when it is created
+        // and `words` is large enough for the output, which is read back
+        // before returning. The kernel arguments point to live values of the
+        // stated sizes.
+        unsafe {
+            let flags = CL_MEM_READ_ONLY | CL_MEM_COPY_HOST_PTR;
+            let input = (self.api.create_buffer)(self.context, flags, data.len(), data.as_ptr() as *mut c_void, &mut error);
+            let input = Buffer { api: &self.api, mem: checked(input, error)? };
+            let output = (self.api.create_buffer)(self.context, CL_MEM_WRITE_ONLY, count * 32, ptr::null_mut(), &mut error);
+            let output = Buffer { api: &self.api, mem: checked(output, error)? };
+
+            let len = len as u64;
+            let args: [(usize, *const c_void); 3] = [
+                (mem::size_of::<Handle>(), &input.mem as *const Handle as *const c_void),
+                (mem::size_of::<u64>(), &len as *const u64 as *const c_void),
+                (mem::size_of::<Handle>(), &output.mem as *const Handle as *const c_void),
+            ];
+            for (index, (size, value)) in args.iter().enumerate() {
+                checked((), (self.api.set_kernel_arg)(self.kernel, index as u32, *size, *value))?;
+            }
+            let error = (self.api.enqueue_nd_range_kernel)(
+                self.queue,
+                self.kernel,
+                1,
+                ptr::null(),
+                &count,
+                ptr::null(),
+                0,
+                ptr::null(),
+                ptr::null_mut(),
+            );
+            checked((), error)?;
+            // The queue runs in order, so this blocking read waits for the kernel
+            let error = (self.api.enqueue_read_buffer)(
+                self.queue,
+                output.mem,
+                CL_TRUE,
+                0,
+                count * 32,
+                words.as_mut_ptr() as *mut c_void,
+                0,
+                ptr::null(),
+                ptr::null_mut(),
+            );
+            checked((), error)?;
+        }
+        let hashes = words.chunks_exact(8).map(|state| state.iter().flat_map(|word| word.to_be_bytes()).collect());
+        Some(hashes.collect())
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        let api = &self.api;
+        let objects = [
+            (self.kernel, api.release_kernel),
+            (self.program, api.release_program),
+            (self.queue, api.release_command_queue),
+            (self.context, api.release_context),
+        ];
+        for (object, release) in objects {
+            if !object.is_null() {
+                // SAFETY: the object was created by this device and is released once.
+                unsafe { release(object) };
+            }
+        }
+    }
+}
+
+/// An OpenCL buffer, released when dropped.
+struct Buffer<'a> {
+    api: &'a Api,
+    mem: Handle,
+}
+
+impl Drop for Buffer<'_> {
+    fn drop(&mut self) {
+        // SAFETY: the buffer was created successfully and is released once.
+        unsafe { (self.api.release_mem_object)(self.mem) };
+    }
+}
+
+/// A `PairHasher` computing the same SHA-256 digests as `Sha256Hasher` that
+/// offloads large batches of equally long leaves to a GPU through OpenCL,
+/// loaded at runtime. Without an OpenCL GPU, or if a batch fails on it,
+/// leaves are hashed on the CPU with a `MultiSha256Hasher`; interior nodes
+/// are always hashed on the CPU.
+///
+/// Offloading is experimental. It pays off for bulk ingestion with small to
+/// medium blocks, where batches of thousands of leaves keep the GPU busy;
+/// blocks over 1 MiB are always hashed on the CPU.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{GpuSha256Hasher, HashTree};
+///
+/// let data = vec![7u8; 1_000_000];
+/// let tree = HashTree::new(1024).hasher(GpuSha256Hasher::new()).from_slice(&data).unwrap();
+/// assert_eq!(tree, HashTree::new(1024).from_slice(&data).unwrap());
+/// ```
+#[derive(Clone)]
+pub struct GpuSha256Hasher {
+    device: Option<Arc<Mutex<Device>>>,
+    cpu: MultiSha256Hasher,
+}
+
+impl GpuSha256Hasher {
+    /// Constructs a new `GpuSha256Hasher`, setting up the first GPU found.
+    pub fn new() -> Self {
+        Self { device: Device::open().map(|device| Arc::new(Mutex::new(device))), cpu: MultiSha256Hasher::new() }
+    }
+
+    /// Returns `true` if leaves are offloaded to a GPU, `false` if they are
+    /// all hashed on the CPU.
+    pub fn is_gpu_available(&self) -> bool {
+        self.device.is_some()
+    }
+}
+
+impl Default for GpuSha256Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for GpuSha256Hasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GpuSha256Hasher").field("gpu", &self.is_gpu_available()).field("cpu", &self.cpu).finish()
+    }
+}
+
+impl PairHasher for GpuSha256Hasher {
+    fn hash_leaf(&self, data: &[u8]) -> Vec<u8> {
+        self.cpu.hash_leaf(data)
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        self.cpu.hash_pair(left, right)
+    }
+
+    fn hash_children(&self, children: &[&[u8]]) -> Vec<u8> {
+        self.cpu.hash_children(children)
+    }
+
+    fn hash_leaves(&self, blocks: &[&[u8]]) -> Vec<Vec<u8>> {
+        let len = blocks.first().map_or(0, |block| block.len());
+        let same = blocks.iter().take_while(|block| block.len() == len).count();
+        if let (Some(device), true) = (&self.device, len > 0 && same >= MIN_GPU_BATCH) {
+            let data = blocks[..same].concat();
+            let hashes = device.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).hash(&data, len);
+            if let Some(mut hashes) = hashes {
+                hashes.extend(self.cpu.hash_leaves(&blocks[same..]));
+                return hashes;
+            }
+        }
+        self.cpu.hash_leaves(blocks)
+    }
+
+    fn leaf_batch(&self, block_size: usize) -> usize {
+        if self.device.is_none() {
+            return self.cpu.leaf_batch(block_size);
+        }
+        (GPU_BATCH_BYTES / block_size.max(1)).clamp(1, MAX_GPU_BATCH)
+    }
+}
@@ -0,0 +1,661 @@
+use alloc::borrow::Cow;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+use hmac::{Mac, SimpleHmac};
+use sha2::digest::core_api::BlockSizeUser;
+use sha2::{Digest, Sha256};
+
+/// A buffer holding block contents. With the `zeroize` feature it is wiped,
+/// including its spare capacity, when dropped.
+#[cfg(feature = "zeroize")]
+pub(crate) type BlockBuf = zeroize::Zeroizing<Vec<u8>>;
+#[cfg(not(feature = "zeroize"))]
+pub(crate) type BlockBuf = Vec<u8>;
+
+/// Returns an empty `BlockBuf` with room for `capacity` bytes.
+pub(crate) fn block_buf(capacity: usize) -> BlockBuf {
+    BlockBuf::from(Vec::with_capacity(capacity))
+}
+
+/// The hash function used to build a `HashTree`.
+///
+/// Implementing this trait allows plugging in hashes that do not fit the
+/// `digest` crate's byte-oriented interface, e.g. arithmetic-friendly
+/// hashes such as Poseidon or Rescue for zero-knowledge circuits. Digests
+/// are opaque byte strings; a hasher may use any fixed output length.
+pub trait PairHasher: fmt::Debug + Send + Sync {
+    /// Hashes the contents of a block into a leaf.
+    fn hash_leaf(&self, data: &[u8]) -> Vec<u8>;
+
+    /// Hashes two child digests into their parent.
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Vec<u8>;
+
+    /// Hashes the children of a node of a k-ary tree into their parent.
+    /// The default implementation calls `hash_pair` for two children and
+    /// folds `hash_pair` from the left otherwise.
+    fn hash_children(&self, children: &[&[u8]]) -> Vec<u8> {
+        let mut hash = self.hash_pair(children[0], children[1]);
+        for child in &children[2..] {
+            hash = self.hash_pair(&hash, child);
+        }
+        hash
+    }
+
+    /// Hashes the contents of several blocks into their leaves, in order.
+    /// The default implementation calls `hash_leaf` for every block;
+    /// hashers that can digest several messages at once, such as
+    /// `MultiSha256Hasher`, override it.
+    fn hash_leaves(&self, blocks: &[&[u8]]) -> Vec<Vec<u8>> {
+        blocks.iter().map(|block| self.hash_leaf(block)).collect()
+    }
+
+    /// Returns the number of blocks of up to `block_size` bytes, 0 if their
+    /// size varies, to pass to `hash_leaves` at once. The default is up to
+    /// eight blocks, but no more than about 1 MiB of data.
+    fn leaf_batch(&self, block_size: usize) -> usize {
+        (1024 * 1024 / block_size.max(1)).clamp(1, 8)
+    }
+}
+
+/// Hashes the children of a node into their parent with `hasher`, calling
+/// `hash_pair` for two children and `hash_children` otherwise. Empty
+/// children are placeholders of `Padding::Promote` and are skipped; a node
+/// with a single remaining child takes its hash unchanged.
+pub(crate) fn hash_group(hasher: &dyn PairHasher, children: &[&[u8]]) -> Vec<u8> {
+    if children.iter().any(|child| child.is_empty()) {
+        let present: Vec<&[u8]> = children.iter().copied().filter(|child| !child.is_empty()).collect();
+        return match present.len() {
+            0 => Vec::new(),
+            1 => present[0].to_vec(),
+            _ => hash_group(hasher, &present),
+        };
+    }
+    if children.len() == 2 {
+        hasher.hash_pair(children[0], children[1])
+    } else {
+        hasher.hash_children(children)
+    }
+}
+
+/// A `PairHasher` over any `digest` crate hash function. Leaves are
+/// `H(data)` and parents `H(child_0 || ... || child_k)`.
+pub struct DigestHasher<D> {
+    marker: PhantomData<fn() -> D>,
+}
+
+impl<D> DigestHasher<D> {
+    /// Constructs a new `DigestHasher`.
+    pub fn new() -> Self {
+        Self { marker: PhantomData }
+    }
+}
+
+impl<D> Default for DigestHasher<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> Clone for DigestHasher<D> {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+impl<D> fmt::Debug for DigestHasher<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DigestHasher<{}>", core::any::type_name::<D>())
+    }
+}
+
+impl<D: Digest> PairHasher for DigestHasher<D> {
+    fn hash_leaf(&self, data: &[u8]) -> Vec<u8> {
+        D::digest(data).to_vec()
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        let mut hasher = D::new();
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize().to_vec()
+    }
+
+    fn hash_children(&self, children: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = D::new();
+        for child in children {
+            hasher.update(child);
+        }
+        hasher.finalize().to_vec()
+    }
+}
+
+/// The default hasher of a `HashTree`: plain SHA-256.
+pub type Sha256Hasher = DigestHasher<Sha256>;
+
+/// A `PairHasher` over any `digest` crate hash function with the domain
+/// separation of RFC 6962 and THEX: leaves are `D(0x00 || data)` and
+/// parents `D(0x01 || child_0 || ... || child_k)`, so no leaf can be passed
+/// off as a parent.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{PairHasher, PrefixedHasher};
+/// use sha2::{Digest, Sha256};
+///
+/// let hasher = PrefixedHasher::<Sha256>::new();
+/// assert_eq!(hasher.hash_leaf(b""), Sha256::digest([0u8]).to_vec());
+/// ```
+pub struct PrefixedHasher<D> {
+    marker: PhantomData<fn() -> D>,
+}
+
+impl<D> PrefixedHasher<D> {
+    /// Constructs a new `PrefixedHasher`.
+    pub fn new() -> Self {
+        Self { marker: PhantomData }
+    }
+}
+
+impl<D> Default for PrefixedHasher<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> Clone for PrefixedHasher<D> {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+impl<D> fmt::Debug for PrefixedHasher<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PrefixedHasher<{}>", core::any::type_name::<D>())
+    }
+}
+
+impl<D: Digest> PairHasher for PrefixedHasher<D> {
+    fn hash_leaf(&self, data: &[u8]) -> Vec<u8> {
+        D::new().chain_update([0x00]).chain_update(data).finalize().to_vec()
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        D::new().chain_update([0x01]).chain_update(left).chain_update(right).finalize().to_vec()
+    }
+
+    fn hash_children(&self, children: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = D::new().chain_update([0x01]);
+        for child in children {
+            hasher.update(child);
+        }
+        hasher.finalize().to_vec()
+    }
+}
+
+/// A `PairHasher` hashing leaves and parents alike with double SHA-256,
+/// `SHA-256(SHA-256(x))`, as the transaction trees of Bitcoin do. The leaf
+/// of a serialized transaction is its txid in internal byte order.
+#[derive(Debug, Clone, Default)]
+pub struct DoubleSha256Hasher;
+
+impl DoubleSha256Hasher {
+    /// Constructs a new `DoubleSha256Hasher`.
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl PairHasher for DoubleSha256Hasher {
+    fn hash_leaf(&self, data: &[u8]) -> Vec<u8> {
+        Sha256::digest(Sha256::digest(data)).to_vec()
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        Sha256::digest(Sha256::new().chain_update(left).chain_update(right).finalize()).to_vec()
+    }
+
+    fn hash_children(&self, children: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        for child in children {
+            hasher.update(child);
+        }
+        Sha256::digest(hasher.finalize()).to_vec()
+    }
+}
+
+/// A `PairHasher` using BLAKE2b with its native key, salt and
+/// personalization parameters for leaves and parents alike, e.g. to match
+/// roots computed with libsodium's `crypto_generichash_blake2b_salt_personal`.
+/// Requires the `blake2` feature.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{Blake2bHasher, HashTree};
+///
+/// let hasher = Blake2bHasher::new(32).key(b"secret key").personal(b"backup-v1");
+/// let tree = HashTree::new(2).hasher(hasher).from_data(&mut &b"abcd"[..]).unwrap();
+/// assert_eq!(tree.root_hash().unwrap().as_bytes().len(), 32);
+/// ```
+#[cfg(feature = "blake2")]
+#[derive(Clone)]
+pub struct Blake2bHasher {
+    params: blake2b_simd::Params,
+    hash_length: usize,
+    personal: Vec<u8>,
+}
+
+#[cfg(feature = "blake2")]
+impl Blake2bHasher {
+    /// Constructs a new unkeyed `Blake2bHasher` producing `hash_length`-byte digests.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hash_length` is 0 or larger than 64.
+    pub fn new(hash_length: usize) -> Self {
+        let mut params = blake2b_simd::Params::new();
+        params.hash_length(hash_length);
+        Self { params, hash_length, personal: Vec::new() }
+    }
+
+    /// Sets the secret key, so that only holders of the key can compute roots.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is longer than 64 bytes.
+    pub fn key(mut self, key: &[u8]) -> Self {
+        self.params.key(key);
+        self
+    }
+
+    /// Sets the salt.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `salt` is longer than 16 bytes.
+    pub fn salt(mut self, salt: &[u8]) -> Self {
+        self.params.salt(salt);
+        self
+    }
+
+    /// Sets the personalization string, which separates the hashes of
+    /// different applications.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `personal` is longer than 16 bytes.
+    pub fn personal(mut self, personal: &[u8]) -> Self {
+        self.params.personal(personal);
+        self.personal = personal.to_vec();
+        self
+    }
+
+    fn hash(&self, parts: &[&[u8]]) -> Vec<u8> {
+        let mut state = self.params.to_state();
+        for part in parts {
+            state.update(part);
+        }
+        state.finalize().as_bytes().to_vec()
+    }
+}
+
+#[cfg(feature = "blake2")]
+impl fmt::Debug for Blake2bHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The key and salt are deliberately left out
+        write!(f, "Blake2bHasher<{}, {}>", self.hash_length, hex::encode(&self.personal))
+    }
+}
+
+#[cfg(feature = "blake2")]
+impl PairHasher for Blake2bHasher {
+    fn hash_leaf(&self, data: &[u8]) -> Vec<u8> {
+        self.hash(&[data])
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        self.hash(&[left, right])
+    }
+
+    fn hash_children(&self, children: &[&[u8]]) -> Vec<u8> {
+        self.hash(children)
+    }
+}
+
+/// A `PairHasher` using BLAKE3 for leaves and parents alike, unkeyed or in
+/// BLAKE3's keyed mode. Requires the `blake3` feature.
+///
+/// Leaves are the BLAKE3 hashes of the blocks and parents the BLAKE3 hashes
+/// of their concatenated children, so the root is not the BLAKE3 hash of the
+/// whole input: BLAKE3's own tree mode threads chunk counters and flags
+/// through its compression function, which a `PairHasher` cannot express.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{Blake3Hasher, HashTree, PairHasher};
+///
+/// let tree = HashTree::new(2).hasher(Blake3Hasher::new()).from_slice(b"abcd").unwrap();
+/// let hasher = Blake3Hasher::new();
+/// let root = hasher.hash_pair(&hasher.hash_leaf(b"ab"), &hasher.hash_leaf(b"cd"));
+/// assert_eq!(tree.root_hash_bytes(), Some(&root[..]));
+/// ```
+#[cfg(feature = "blake3")]
+#[derive(Clone, Default)]
+pub struct Blake3Hasher {
+    key: Option<[u8; blake3::KEY_LEN]>,
+}
+
+#[cfg(feature = "blake3")]
+impl Blake3Hasher {
+    /// Constructs a new unkeyed `Blake3Hasher`.
+    pub fn new() -> Self {
+        Self { key: None }
+    }
+
+    /// Constructs a `Blake3Hasher` in keyed mode, whose hashes are MACs
+    /// under `key`.
+    pub fn keyed(key: &[u8; blake3::KEY_LEN]) -> Self {
+        Self { key: Some(*key) }
+    }
+
+    fn hash(&self, parts: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = match &self.key {
+            Some(key) => blake3::Hasher::new_keyed(key),
+            None => blake3::Hasher::new(),
+        };
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().as_bytes().to_vec()
+    }
+}
+
+#[cfg(feature = "blake3")]
+impl fmt::Debug for Blake3Hasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The key is deliberately left out
+        f.write_str(if self.key.is_some() { "Blake3Hasher<keyed>" } else { "Blake3Hasher" })
+    }
+}
+
+#[cfg(feature = "blake3")]
+impl PairHasher for Blake3Hasher {
+    fn hash_leaf(&self, data: &[u8]) -> Vec<u8> {
+        self.hash(&[data])
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        self.hash(&[left, right])
+    }
+
+    fn hash_children(&self, children: &[&[u8]]) -> Vec<u8> {
+        self.hash(children)
+    }
+}
+
+/// A `PairHasher` prefixing the contents of every block with a salt before
+/// hashing it with another hasher, as dm-verity does. Identical data then has
+/// different roots under different salts, so roots published by separate
+/// deployments cannot be correlated. Parents are hashed by the inner hasher
+/// unchanged.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::HashTree;
+///
+/// let salted = |salt: &[u8]| HashTree::new(2).salt(salt).from_data(&mut &b"abcd"[..]).unwrap();
+/// assert_eq!(salted(b"site-a"), salted(b"site-a"));
+/// assert_ne!(salted(b"site-a"), salted(b"site-b"));
+/// ```
+#[derive(Clone)]
+pub struct SaltedHasher {
+    inner: Arc<dyn PairHasher>,
+    salt: Vec<u8>,
+}
+
+impl SaltedHasher {
+    /// Constructs a new `SaltedHasher` prefixing blocks with `salt` before
+    /// hashing them with `inner`.
+    pub fn new<H: PairHasher + 'static>(inner: H, salt: &[u8]) -> Self {
+        Self::wrap(Arc::new(inner), salt)
+    }
+
+    pub(crate) fn wrap(inner: Arc<dyn PairHasher>, salt: &[u8]) -> Self {
+        Self { inner, salt: salt.to_vec() }
+    }
+
+    /// Returns the salt.
+    pub fn salt(&self) -> &[u8] {
+        &self.salt
+    }
+}
+
+impl fmt::Debug for SaltedHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SaltedHasher<{:?}, {}>", self.inner, hex::encode(&self.salt))
+    }
+}
+
+impl PairHasher for SaltedHasher {
+    fn hash_leaf(&self, data: &[u8]) -> Vec<u8> {
+        let mut salted = block_buf(self.salt.len() + data.len());
+        salted.extend_from_slice(&self.salt);
+        salted.extend_from_slice(data);
+        self.inner.hash_leaf(&salted)
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        self.inner.hash_pair(left, right)
+    }
+
+    fn hash_children(&self, children: &[&[u8]]) -> Vec<u8> {
+        self.inner.hash_children(children)
+    }
+}
+
+/// A `PairHasher` prefixing the contents of every block with its length, as
+/// a 64-bit big-endian integer, before hashing it with another hasher. Leaves
+/// are then `H(len || data)`, so a short final block can never hash like a
+/// full block that happens to start with the same bytes plus padding.
+/// Parents are hashed by the inner hasher unchanged.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, LengthPrefixedHasher, PairHasher, Sha256Hasher};
+/// use sha2::{Digest, Sha256};
+///
+/// let leaf = Sha256::digest([&3u64.to_be_bytes()[..], b"abc"].concat()).to_vec();
+/// assert_eq!(LengthPrefixedHasher::new(Sha256Hasher::new()).hash_leaf(b"abc"), leaf);
+///
+/// let framed = |data: &[u8]| HashTree::new(4).length_prefixed().from_data(&mut &data[..]).unwrap();
+/// assert_ne!(framed(b"abcdab"), framed(b"abcdab\0\0"));
+/// ```
+#[derive(Clone)]
+pub struct LengthPrefixedHasher {
+    inner: Arc<dyn PairHasher>,
+}
+
+impl LengthPrefixedHasher {
+    /// Constructs a new `LengthPrefixedHasher` hashing framed blocks with `inner`.
+    pub fn new<H: PairHasher + 'static>(inner: H) -> Self {
+        Self::wrap(Arc::new(inner))
+    }
+
+    pub(crate) fn wrap(inner: Arc<dyn PairHasher>) -> Self {
+        Self { inner }
+    }
+}
+
+impl fmt::Debug for LengthPrefixedHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LengthPrefixedHasher<{:?}>", self.inner)
+    }
+}
+
+impl PairHasher for LengthPrefixedHasher {
+    fn hash_leaf(&self, data: &[u8]) -> Vec<u8> {
+        let mut framed = block_buf(8 + data.len());
+        framed.extend_from_slice(&(data.len() as u64).to_be_bytes());
+        framed.extend_from_slice(data);
+        self.inner.hash_leaf(&framed)
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        self.inner.hash_pair(left, right)
+    }
+
+    fn hash_children(&self, children: &[&[u8]]) -> Vec<u8> {
+        self.inner.hash_children(children)
+    }
+}
+
+/// A transform applied to the contents of every block before it is hashed,
+/// see `PreprocessingHasher`.
+pub type Preprocess = dyn Fn(&[u8]) -> Cow<'_, [u8]> + Send + Sync;
+
+/// A `PairHasher` transforming the contents of every block, e.g. to strip
+/// timestamps or normalize line endings, before hashing it with another
+/// hasher, so trees over semantically equal text artifacts have equal
+/// roots. Blocks the transform returns borrowed are hashed without copying.
+/// Parents are hashed by the inner hasher unchanged.
+///
+/// Blocks are split before they are transformed, so transforms that change
+/// the length of the contents should be combined with a chunker splitting
+/// on content, such as `RecordChunker`, rather than with fixed-size blocks.
+///
+/// # Examples
+///
+/// ```
+/// use std::borrow::Cow;
+/// use hashtree::{HashTree, RecordChunker};
+///
+/// // Drops the timestamp before the first space of every log line
+/// fn strip(line: &[u8]) -> Cow<'_, [u8]> {
+///     Cow::Borrowed(line.splitn(2, |&b| b == b' ').last().unwrap())
+/// }
+/// let log = |text: &str| HashTree::new(0).preprocess(strip).from_chunker(RecordChunker::lines(text.as_bytes())).unwrap();
+/// assert_eq!(log("09:00:01 GET /\n09:00:02 GET /a\n"), log("17:30:00 GET /\n17:30:04 GET /a\n"));
+/// assert_ne!(log("09:00:01 GET /\n"), log("09:00:01 GET /b\n"));
+/// ```
+#[derive(Clone)]
+pub struct PreprocessingHasher {
+    inner: Arc<dyn PairHasher>,
+    preprocess: Arc<Preprocess>,
+}
+
+impl PreprocessingHasher {
+    /// Constructs a new `PreprocessingHasher` hashing blocks transformed by
+    /// `preprocess` with `inner`.
+    pub fn new<H, F>(inner: H, preprocess: F) -> Self
+    where
+        H: PairHasher + 'static,
+        F: Fn(&[u8]) -> Cow<'_, [u8]> + Send + Sync + 'static,
+    {
+        Self::wrap(Arc::new(inner), Arc::new(preprocess))
+    }
+
+    pub(crate) fn wrap(inner: Arc<dyn PairHasher>, preprocess: Arc<Preprocess>) -> Self {
+        Self { inner, preprocess }
+    }
+}
+
+impl fmt::Debug for PreprocessingHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PreprocessingHasher<{:?}>", self.inner)
+    }
+}
+
+impl PairHasher for PreprocessingHasher {
+    fn hash_leaf(&self, data: &[u8]) -> Vec<u8> {
+        self.inner.hash_leaf(&(self.preprocess)(data))
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        self.inner.hash_pair(left, right)
+    }
+
+    fn hash_children(&self, children: &[&[u8]]) -> Vec<u8> {
+        self.inner.hash_children(children)
+    }
+
+    fn hash_leaves(&self, blocks: &[&[u8]]) -> Vec<Vec<u8>> {
+        let transformed: Vec<Cow<'_, [u8]>> = blocks.iter().map(|block| (self.preprocess)(block)).collect();
+        let blocks: Vec<&[u8]> = transformed.iter().map(|block| block.as_ref()).collect();
+        self.inner.hash_leaves(&blocks)
+    }
+
+    fn leaf_batch(&self, block_size: usize) -> usize {
+        self.inner.leaf_batch(block_size)
+    }
+}
+
+/// A `PairHasher` computing leaves as HMAC-`D` under a secret key, so that
+/// only holders of the key can compute or forge roots, even for known
+/// content. Parents are `D(child_0 || ... || child_k)` as with `DigestHasher`.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, HmacHasher};
+///
+/// let keyed = |key: &[u8]| {
+///     HashTree::new(2).hasher(HmacHasher::<sha2::Sha256>::new(key)).from_data(&mut &b"abcd"[..]).unwrap()
+/// };
+/// assert_eq!(keyed(b"secret"), keyed(b"secret"));
+/// assert_ne!(keyed(b"secret"), keyed(b"other"));
+/// assert_ne!(keyed(b"secret"), HashTree::new(2).from_data(&mut &b"abcd"[..]).unwrap());
+/// ```
+#[derive(Clone)]
+pub struct HmacHasher<D: Digest + BlockSizeUser> {
+    mac: SimpleHmac<D>,
+}
+
+impl<D: Digest + BlockSizeUser> HmacHasher<D> {
+    /// Constructs a new `HmacHasher` keyed with `key`, which may have any length.
+    pub fn new(key: &[u8]) -> Self {
+        let mac = <SimpleHmac<D> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+        Self { mac }
+    }
+}
+
+impl<D: Digest + BlockSizeUser> fmt::Debug for HmacHasher<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The key is deliberately left out
+        write!(f, "HmacHasher<{}>", core::any::type_name::<D>())
+    }
+}
+
+impl<D> PairHasher for HmacHasher<D>
+where
+    D: Digest + BlockSizeUser + Clone + Send + Sync,
+{
+    fn hash_leaf(&self, data: &[u8]) -> Vec<u8> {
+        self.mac.clone().chain_update(data).finalize().into_bytes().to_vec()
+    }
+
+    fn hash_pair(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        DigestHasher::<D>::new().hash_pair(left, right)
+    }
+
+    fn hash_children(&self, children: &[&[u8]]) -> Vec<u8> {
+        DigestHasher::<D>::new().hash_children(children)
+    }
+}
+
+/// Returns a short fingerprint identifying the hash function of `hasher`,
+/// derived from hashing a fixed input through all of its methods.
+pub(crate) fn fingerprint(hasher: &dyn PairHasher) -> [u8; 8] {
+    let leaf = hasher.hash_leaf(b"hashtree");
+    let mut digest = Sha256::new();
+    digest.update(hasher.hash_pair(&leaf, &leaf));
+    digest.update(hasher.hash_children(&[&leaf, &leaf, &leaf]));
+    let mut fingerprint = [0u8; 8];
+    fingerprint.copy_from_slice(&digest.finalize()[..8]);
+    fingerprint
+}
@@ -0,0 +1,250 @@
+use sha2::{Digest, Sha256};
+
+use crate::log::{leaf_hash, node_hash};
+use crate::root::constant_time_eq;
+
+/// A Crosby–Wallach history tree, the tamper-evident log of "Efficient Data
+/// Structures for Tamper-Evident Logging" (USENIX Security 2009).
+///
+/// Events are appended as leaves of a complete binary tree that grows a
+/// level whenever it fills up. Version `v` of the tree holds events `0..=v`
+/// and its root commits to exactly those; subtrees past the last event are
+/// left out, so a node with only a left child hashes it on its own. Leaves
+/// and parents are hashed with the domain separation of RFC 6962.
+///
+/// A `MembershipProof` shows an event to be in any version from its own on,
+/// and an `IncrementalProof` shows a version to be a prefix of a later one,
+/// so an auditor holding any later root can check old events without the
+/// log being rebuilt. Hashes of complete subtrees are cached, so appends
+/// and proofs cost `O(log n)` hashes.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::HistoryTree;
+///
+/// let mut tree = HistoryTree::new();
+/// for event in ["login", "read", "logout"].iter() {
+///     tree.append(event.as_bytes());
+/// }
+/// let proof = tree.membership_proof(1, 2).unwrap();
+/// assert!(proof.verify(&tree.root().unwrap(), b"read"));
+///
+/// let old_root = tree.root_at(0).unwrap();
+/// let proof = tree.incremental_proof(0, 2).unwrap();
+/// assert!(proof.verify(&old_root, &tree.root().unwrap()));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryTree {
+    levels: Vec<Vec<[u8; 32]>>,
+}
+
+impl HistoryTree {
+    /// Constructs a new empty `HistoryTree`.
+    pub fn new() -> Self {
+        Self { levels: vec![Vec::new()] }
+    }
+
+    /// Appends `event` and returns the new version, which is also the index
+    /// of the event.
+    pub fn append(&mut self, event: &[u8]) -> u64 {
+        self.append_leaf(leaf_hash(event))
+    }
+
+    /// Appends an already computed leaf hash and returns the new version.
+    pub fn append_leaf(&mut self, leaf: [u8; 32]) -> u64 {
+        self.levels[0].push(leaf);
+        let mut layer = 0;
+        while self.levels[layer].len().is_multiple_of(2) {
+            let level = &self.levels[layer];
+            let parent = node_hash(&level[level.len() - 2], &level[level.len() - 1]);
+            if self.levels.len() == layer + 1 {
+                self.levels.push(Vec::new());
+            }
+            self.levels[layer + 1].push(parent);
+            layer += 1;
+        }
+        self.len() - 1
+    }
+
+    /// Returns the leaf hash of `event`.
+    pub fn leaf_hash(event: &[u8]) -> [u8; 32] {
+        leaf_hash(event)
+    }
+
+    /// Returns the number of events in the tree.
+    pub fn len(&self) -> u64 {
+        self.levels[0].len() as u64
+    }
+
+    /// Returns `true` if the tree has no events.
+    pub fn is_empty(&self) -> bool {
+        self.levels[0].is_empty()
+    }
+
+    /// Returns the latest version, or `None` if the tree is empty.
+    pub fn version(&self) -> Option<u64> {
+        self.len().checked_sub(1)
+    }
+
+    /// Returns the root of the latest version, or `None` if the tree is empty.
+    pub fn root(&self) -> Option<[u8; 32]> {
+        self.root_at(self.version()?)
+    }
+
+    /// Returns the root of `version`, or `None` if it is not reached yet.
+    pub fn root_at(&self, version: u64) -> Option<[u8; 32]> {
+        if version >= self.len() {
+            return None;
+        }
+        self.node(depth(version), 0, version)
+    }
+
+    /// Returns a proof that the event at `index` is in `version`, which must
+    /// be at least `index`.
+    pub fn membership_proof(&self, index: u64, version: u64) -> Option<MembershipProof> {
+        if index > version || version >= self.len() {
+            return None;
+        }
+        Some(MembershipProof { index, version, path: self.path(index, version) })
+    }
+
+    /// Returns a proof that `old_version` is a prefix of `new_version`.
+    pub fn incremental_proof(&self, old_version: u64, new_version: u64) -> Option<IncrementalProof> {
+        if old_version > new_version || new_version >= self.len() {
+            return None;
+        }
+        let leaf = self.levels[0][old_version as usize];
+        Some(IncrementalProof { old_version, new_version, leaf, path: self.path(old_version, new_version) })
+    }
+
+    /// Returns the hash of node `index` of `layer` (0 for leaves) in
+    /// `version`, or `None` if all its leaves come after the version.
+    fn node(&self, layer: u32, index: u64, version: u64) -> Option<[u8; 32]> {
+        let start = index << layer;
+        if start > version {
+            return None;
+        }
+        if start + (1 << layer) - 1 <= version {
+            return Some(self.levels[layer as usize][index as usize]);
+        }
+        let left = self.node(layer - 1, 2 * index, version)?;
+        Some(match self.node(layer - 1, 2 * index + 1, version) {
+            Some(right) => node_hash(&left, &right),
+            None => lone_hash(&left),
+        })
+    }
+
+    /// Returns the hashes of the siblings present in `version` on the path
+    /// of the leaf at `index`, from the leaf up.
+    fn path(&self, index: u64, version: u64) -> Vec<[u8; 32]> {
+        (0..depth(version)).filter_map(|layer| self.node(layer, (index >> layer) ^ 1, version)).collect()
+    }
+}
+
+/// A proof that an event is in a version of a `HistoryTree`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MembershipProof {
+    /// Index of the proven event.
+    pub index: u64,
+    /// Version the proof was generated for.
+    pub version: u64,
+    /// Hashes of the siblings present in the version, from the leaf up.
+    pub path: Vec<[u8; 32]>,
+}
+
+impl MembershipProof {
+    /// Returns `true` if `event` is at `index` in the version with `root`.
+    pub fn verify(&self, root: &[u8; 32], event: &[u8]) -> bool {
+        self.verify_leaf(root, &leaf_hash(event))
+    }
+
+    /// Like `verify`, but takes the already computed leaf hash.
+    pub fn verify_leaf(&self, root: &[u8; 32], leaf: &[u8; 32]) -> bool {
+        if self.index > self.version {
+            return false;
+        }
+        let mut path = self.path.iter();
+        let computed = climb(*leaf, self.index, self.version, &mut path);
+        path.next().is_none() && computed.is_some_and(|computed| constant_time_eq(&computed, root))
+    }
+}
+
+/// A proof that a version of a `HistoryTree` is a prefix of a later one:
+/// the last event of the old version and its membership path in the new one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncrementalProof {
+    /// The older version.
+    pub old_version: u64,
+    /// The newer version.
+    pub new_version: u64,
+    /// Leaf hash of the last event of the older version.
+    pub leaf: [u8; 32],
+    /// Hashes of the siblings of that leaf present in the newer version.
+    pub path: Vec<[u8; 32]>,
+}
+
+impl IncrementalProof {
+    /// Returns `true` if the version with `old_root` is a prefix of the
+    /// version with `new_root`.
+    ///
+    /// The siblings to the left of the path are complete subtrees in both
+    /// versions, and those to the right are absent from the older one, so
+    /// the same path yields both roots.
+    pub fn verify(&self, old_root: &[u8; 32], new_root: &[u8; 32]) -> bool {
+        if self.old_version > self.new_version {
+            return false;
+        }
+        let left: Vec<[u8; 32]> = (0..depth(self.new_version))
+            .filter(|&layer| present((self.old_version >> layer) ^ 1, layer, self.new_version))
+            .zip(&self.path)
+            .filter(|&(layer, _)| (self.old_version >> layer) & 1 == 1)
+            .map(|(_, hash)| *hash)
+            .collect();
+        let old = climb(self.leaf, self.old_version, self.old_version, &mut left.iter());
+        let mut path = self.path.iter();
+        let new = climb(self.leaf, self.old_version, self.new_version, &mut path);
+        match (old, new) {
+            (Some(old), Some(new)) if path.next().is_none() => {
+                constant_time_eq(&old, old_root) & constant_time_eq(&new, new_root)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Returns the root of `version` from the `leaf` at `index` and the hashes
+/// of its siblings present in the version, taken from `path`. Returns
+/// `None` if `path` runs out.
+fn climb<'a>(leaf: [u8; 32], index: u64, version: u64, path: &mut impl Iterator<Item = &'a [u8; 32]>) -> Option<[u8; 32]> {
+    let mut hash = leaf;
+    for layer in 0..depth(version) {
+        let position = index >> layer;
+        hash = if position & 1 == 1 {
+            node_hash(path.next()?, &hash)
+        } else if present(position ^ 1, layer, version) {
+            node_hash(&hash, path.next()?)
+        } else {
+            lone_hash(&hash)
+        };
+    }
+    Some(hash)
+}
+
+/// Returns `true` if node `index` of `layer` has leaves in `version`.
+fn present(index: u64, layer: u32, version: u64) -> bool {
+    index << layer <= version
+}
+
+/// Returns the number of layers above the leaves in `version`.
+fn depth(version: u64) -> u32 {
+    64 - version.leading_zeros()
+}
+
+/// Returns the hash of a node whose right subtree is past the version.
+fn lone_hash(left: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0x01]);
+    hasher.update(left);
+    hasher.finalize().into()
+}
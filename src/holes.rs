@@ -0,0 +1,35 @@
+use std::fs::File;
+use std::io::Error;
+use std::ops::Range;
+use std::os::unix::io::AsRawFd;
+
+/// Returns the first region of data of `file`, `len` bytes long, that
+/// starts at or after `from`, so that the bytes from `from` up to the start
+/// of the region are a hole reading as zeros. Returns `len..len` if only a
+/// hole is left. File systems without hole support report all of the file
+/// as data.
+pub(crate) fn data_region(file: &File, from: u64, len: u64) -> Result<Range<u64>, Error> {
+    let start = match seek(file, from, libc::SEEK_DATA)? {
+        Some(start) => start.min(len),
+        None => return Ok(len..len),
+    };
+    let end = seek(file, start, libc::SEEK_HOLE)?.unwrap_or(len).min(len);
+    Ok(start..end.max(start))
+}
+
+/// Seeks `file` with `whence`, returning `None` if there is no such offset.
+fn seek(file: &File, offset: u64, whence: libc::c_int) -> Result<Option<u64>, Error> {
+    // SAFETY: `lseek` only reads its arguments; the descriptor is owned by `file`.
+    let result = unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) };
+    if result >= 0 {
+        return Ok(Some(result as u64));
+    }
+    let error = Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::ENXIO) => Ok(None),
+        // SEEK_DATA and SEEK_HOLE are not supported, all of the file is data
+        Some(libc::EINVAL) if whence == libc::SEEK_DATA => Ok(Some(offset)),
+        Some(libc::EINVAL) => Ok(None),
+        _ => Err(error),
+    }
+}
@@ -0,0 +1,124 @@
+use sha2::{Digest, Sha256};
+
+/// A fixed-depth, append-only Merkle tree with constant-size state.
+///
+/// This is the structure used by the Ethereum deposit contract: only one
+/// "branch" hash per level and the leaf count are kept, and empty subtrees
+/// are represented by a precomputed ladder of zero hashes, so an append or
+/// a root computation costs `depth` hashes regardless of how many leaves
+/// have been appended.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::IncrementalTree;
+///
+/// let tree = IncrementalTree::new(32);
+/// assert_eq!(hex::encode(tree.deposit_root()),
+///     "d70a234731285c6804c2a4f56711ddb8c82c99740f207854891028af34e27e5e");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncrementalTree {
+    branch: Vec<[u8; 32]>,
+    zero_hashes: Vec<[u8; 32]>,
+    count: u64,
+}
+
+impl IncrementalTree {
+    /// Constructs a new empty `IncrementalTree` with room for `2^depth - 1` leaves.
+    /// As in the deposit contract, the last leaf slot is never filled so the
+    /// branch hashes always suffice to compute the root.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `depth` is larger than 64.
+    pub fn new(depth: usize) -> Self {
+        assert!(depth <= 64, "depth must not exceed 64");
+        let mut zero_hashes = vec![[0u8; 32]];
+        for height in 0..depth {
+            let below = zero_hashes[height];
+            zero_hashes.push(hash_pair(&below, &below));
+        }
+        Self {
+            branch: vec![[0u8; 32]; depth],
+            zero_hashes,
+            count: 0,
+        }
+    }
+
+    /// Returns the depth of the tree.
+    pub fn depth(&self) -> usize {
+        self.branch.len()
+    }
+
+    /// Returns the number of appended leaves.
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns `true` if no leaf has been appended.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns the maximum number of leaves the tree can hold.
+    pub fn capacity(&self) -> u128 {
+        (1u128 << self.depth()) - 1
+    }
+
+    /// Appends a leaf hash and returns its index, or `None` if the tree is full.
+    pub fn append(&mut self, leaf: [u8; 32]) -> Option<u64> {
+        if self.count as u128 >= self.capacity() {
+            return None;
+        }
+        let index = self.count;
+        self.count += 1;
+
+        let mut node = leaf;
+        let mut size = self.count;
+        for height in 0..self.depth() {
+            if size & 1 == 1 {
+                self.branch[height] = node;
+                break;
+            }
+            node = hash_pair(&self.branch[height], &node);
+            size >>= 1;
+        }
+        Some(index)
+    }
+
+    /// Returns the root of the full-depth tree, with all unused leaves set to zero.
+    pub fn root(&self) -> [u8; 32] {
+        let mut node = [0u8; 32];
+        let mut size = self.count;
+        for height in 0..self.depth() {
+            node = if size & 1 == 1 {
+                hash_pair(&self.branch[height], &node)
+            } else {
+                hash_pair(&node, &self.zero_hashes[height])
+            };
+            size >>= 1;
+        }
+        node
+    }
+
+    /// Returns the root with the leaf count mixed in, as the Ethereum deposit
+    /// contract's `get_deposit_root` does: `H(root || le64(count) || 0^24)`.
+    pub fn deposit_root(&self) -> [u8; 32] {
+        let mut count = [0u8; 32];
+        count[..8].copy_from_slice(&self.count.to_le_bytes());
+        hash_pair(&self.root(), &count)
+    }
+
+    /// Returns the hash of an empty subtree of the given height.
+    pub fn zero_hash(&self, height: usize) -> Option<[u8; 32]> {
+        self.zero_hashes.get(height).copied()
+    }
+}
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
@@ -0,0 +1,91 @@
+use core::convert::TryFrom;
+use core::fmt;
+use core::num::TryFromIntError;
+
+/// The index of a leaf of a `HashTree`, i.e. of a block, counting from 0.
+///
+/// Accessors taking a leaf accept a `LeafIndex` or a plain `usize`, but not
+/// a `NodeIndex`, so the storage index of an interior node cannot be passed
+/// where a leaf is expected. Leaves are counted in `u64`, like
+/// `HashTree::num_blocks`, so indices past 2^32 are the same on every
+/// platform. A leaf converts into the `NodeIndex` of its node, since leaves
+/// are stored first, if it fits in a `usize`.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, LeafIndex, NodeIndex};
+/// use std::convert::TryFrom;
+///
+/// let tree = HashTree::new(1).from_slice(b"abcd").unwrap();
+/// let leaf = LeafIndex(2);
+/// assert_eq!(tree.proof(leaf), tree.proof(2));
+/// assert_eq!(tree.node_at(NodeIndex::try_from(leaf).unwrap()), tree.leaf_hash(leaf));
+/// assert_eq!(tree.leaf_of(NodeIndex(5)), None);
+/// ```
+///
+/// ```compile_fail
+/// use hashtree::{HashTree, NodeIndex};
+///
+/// let tree = HashTree::new(1).from_slice(b"abcd").unwrap();
+/// tree.proof(NodeIndex(5));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct LeafIndex(pub u64);
+
+/// The storage index of a node of a `HashTree`: leaves first, then every
+/// level above them up to the root, the order of `HashTree::store`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct NodeIndex(pub usize);
+
+impl From<usize> for LeafIndex {
+    fn from(index: usize) -> Self {
+        Self(index as u64)
+    }
+}
+
+impl From<LeafIndex> for u64 {
+    fn from(index: LeafIndex) -> Self {
+        index.0
+    }
+}
+
+impl TryFrom<LeafIndex> for usize {
+    type Error = TryFromIntError;
+
+    fn try_from(index: LeafIndex) -> Result<Self, Self::Error> {
+        usize::try_from(index.0)
+    }
+}
+
+impl From<usize> for NodeIndex {
+    fn from(index: usize) -> Self {
+        Self(index)
+    }
+}
+
+impl From<NodeIndex> for usize {
+    fn from(index: NodeIndex) -> Self {
+        index.0
+    }
+}
+
+impl TryFrom<LeafIndex> for NodeIndex {
+    type Error = TryFromIntError;
+
+    fn try_from(index: LeafIndex) -> Result<Self, Self::Error> {
+        usize::try_from(index).map(Self)
+    }
+}
+
+impl fmt::Display for LeafIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl fmt::Display for NodeIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
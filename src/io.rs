@@ -0,0 +1,63 @@
+//! The error type of fallible tree operations.
+//!
+//! With the `std` feature this is `std::io::Error`. Without it, in
+//! `no_std + alloc` builds, a minimal stand-in with the same constructor is
+//! used so that `NodeStore` implementations and the tree share one signature.
+
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind};
+
+#[cfg(not(feature = "std"))]
+mod shim {
+    use alloc::string::String;
+    use core::fmt;
+
+    /// The kind of an `Error`, a subset of `std::io::ErrorKind`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ErrorKind {
+        /// A parameter was incorrect.
+        InvalidInput,
+        /// Data was not valid for the operation.
+        InvalidData,
+        /// Any other error.
+        Other,
+    }
+
+    /// An error of a tree or `NodeStore` operation.
+    #[derive(Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+        message: String,
+    }
+
+    impl Error {
+        /// Constructs a new `Error` of the given kind.
+        pub fn new<M: Into<String>>(kind: ErrorKind, message: M) -> Self {
+            Self { kind, message: message.into() }
+        }
+
+        /// Returns the kind of the error.
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(&self.message)
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+pub use shim::{Error, ErrorKind};
+
+use core::convert::TryFrom;
+
+/// Converts a count or index stored as a `u64` to a `usize`, failing on
+/// targets where it does not fit instead of silently truncating it.
+pub(crate) fn to_usize(n: u64) -> Result<usize, Error> {
+    usize::try_from(n).map_err(|_| {
+        Error::new(ErrorKind::InvalidData, alloc::format!("{} is too large for this platform", n))
+    })
+}
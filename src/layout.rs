@@ -0,0 +1,186 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::root::constant_time_eq;
+use crate::store::NodeStore;
+use crate::HashTree;
+
+const COMBINED_MAGIC: &[u8; 4] = b"HTRC";
+
+/// Returns the path of the outboard tree of the file at `data`: the same
+/// path with `.htree` appended, e.g. `disk.img.htree` for `disk.img`.
+pub fn outboard_path<P: AsRef<Path>>(data: P) -> PathBuf {
+    let mut path = OsString::from(data.as_ref().as_os_str());
+    path.push(".htree");
+    PathBuf::from(path)
+}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Writes the tree, as `write_level_order` does, to the outboard file next
+    /// to the data file at `data`, see `outboard_path`. The data file is left
+    /// untouched.
+    /// Returns an `Error` value if the outboard file could not be written.
+    pub fn write_outboard<P: AsRef<Path>>(&self, data: P) -> Result<(), Error> {
+        self.write_level_order(BufWriter::new(File::create(outboard_path(data))?))
+    }
+
+    /// Reads the outboard tree of the data file at `data`, written by
+    /// `write_outboard`, into `self`, checking every node against the trusted
+    /// `root` as `read_level_order` does. Read verified blocks of the data
+    /// with an `OutboardReader` over the returned tree.
+    /// Returns an `Error` value if the outboard file could not be read or does
+    /// not match `root`, see `read_level_order`.
+    pub fn read_outboard<P: AsRef<Path>>(self, data: P, root: &[u8]) -> Result<Self, Error> {
+        self.read_level_order(BufReader::new(File::open(outboard_path(data))?), root)
+    }
+
+    /// Writes `data`, which must be the data the tree was built from, and
+    /// the tree to `writer` as a single combined file: the magic `HTRC`, the
+    /// tree as written by `write_level_order`, the length of the data as a big-endian
+    /// `u64` and then the data itself. Putting the tree first lets
+    /// `read_combined` check every block before passing it on.
+    /// Returns an `Error` value if `data` does not match the size of the
+    /// tree or reading or writing fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use hashtree::HashTree;
+    ///
+    /// let data = b"combined data and hashes";
+    /// let tree = HashTree::new(4).from_data(&data[..]).unwrap();
+    /// let mut combined = Vec::new();
+    /// tree.write_combined(Cursor::new(&data[..]), &mut combined).unwrap();
+    ///
+    /// let mut output = Vec::new();
+    /// let root = tree.root_hash().unwrap().into_bytes();
+    /// HashTree::new(0).read_combined(&combined[..], &root, &mut output).unwrap();
+    /// assert_eq!(output, data);
+    /// ```
+    pub fn write_combined<R: Read + Seek, W: Write>(&self, mut data: R, mut writer: W) -> Result<(), Error> {
+        let data_len = data.seek(SeekFrom::End(0))?;
+        if !self.covers_len(data_len) {
+            return Err(Error::new(ErrorKind::InvalidInput, "data does not match the size of the tree"));
+        }
+        data.seek(SeekFrom::Start(0))?;
+        writer.write_all(COMBINED_MAGIC)?;
+        self.write_level_order(&mut writer)?;
+        writer.write_all(&data_len.to_be_bytes())?;
+        let copied = std::io::copy(&mut data.take(data_len), &mut writer)?;
+        if copied != data_len {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "data ended early"));
+        }
+        writer.flush()
+    }
+
+    /// Reads a combined file written by `write_combined`, checks every node of
+    /// its tree against the trusted `root` and copies the data to `writer`, one
+    /// verified block at a time. Returns the tree, read into `self`.
+    /// Returns an `Error` value if the tree does not match `root`, a block
+    /// does not match its leaf hash, the file is malformed, or reading or
+    /// writing fails. Blocks before a corrupt one have already been written.
+    pub fn read_combined<R: Read, W: Write>(self, mut reader: R, root: &[u8], mut writer: W) -> Result<Self, Error> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != COMBINED_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a combined hash tree file"));
+        }
+        let tree = self.read_level_order(&mut reader, root)?;
+        let mut len = [0u8; 8];
+        reader.read_exact(&mut len)?;
+        let data_len = u64::from_be_bytes(len);
+        if !tree.covers_len(data_len) {
+            return Err(Error::new(ErrorKind::InvalidData, "data length does not match the size of the tree"));
+        }
+
+        let mut block = Vec::new();
+        for index in 0..tree.stored_leaves() {
+            let extent = tree.leaf_extent(data_len, index);
+            block.resize((extent.end - extent.start) as usize, 0);
+            reader.read_exact(&mut block)?;
+            tree.check_block(index, &block)?;
+            writer.write_all(&block)?;
+        }
+        writer.flush()?;
+        Ok(tree)
+    }
+
+    /// Checks `data` against the leaf hash of the block at `index`.
+    fn check_block(&self, index: usize, data: &[u8]) -> Result<(), Error> {
+        if !constant_time_eq(&self.get_hasher().hash_leaf(data), &self.node_hash(index)) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("block {} does not match its hash", index)));
+        }
+        Ok(())
+    }
+}
+
+/// Reads blocks of an untouched data file, checking each against an
+/// outboard tree, see `HashTree::read_outboard`.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+/// use hashtree::{HashTree, OutboardReader};
+///
+/// let data = b"outboard hashes";
+/// let tree = HashTree::new(4).from_data(&data[..]).unwrap();
+/// let mut reader = OutboardReader::new(tree, Cursor::new(&data[..]));
+/// assert_eq!(reader.read_block(1).unwrap(), b"oard");
+///
+/// let tampered = HashTree::new(4).from_data(&data[..]).unwrap();
+/// let mut reader = OutboardReader::new(tampered, Cursor::new(&b"outboard hasher"[..]));
+/// assert!(reader.read_block(3).is_err());
+/// ```
+#[derive(Debug)]
+pub struct OutboardReader<R, S = Vec<Vec<u8>>> {
+    tree: HashTree<S>,
+    data: R,
+}
+
+impl<R: Read + Seek, S: NodeStore> OutboardReader<R, S> {
+    /// Constructs a new `OutboardReader` checking `data` against `tree`,
+    /// which must have been verified against a trusted root, e.g. by
+    /// `HashTree::read_outboard`. Only the leaf hashes are checked here.
+    pub fn new(tree: HashTree<S>, data: R) -> Self {
+        Self { tree, data }
+    }
+
+    /// Returns the tree blocks are checked against.
+    pub fn tree(&self) -> &HashTree<S> {
+        &self.tree
+    }
+
+    /// Reads the block at `index` and checks it against its leaf hash.
+    /// Returns an `Error` value if there is no such block, the data could
+    /// not be read or does not match.
+    pub fn read_block(&mut self, index: usize) -> Result<Vec<u8>, Error> {
+        if index >= self.tree.stored_leaves() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("block index {} out of range", index)));
+        }
+        // Without recorded lengths the last block simply ends with the data
+        let extent = self.tree.leaf_extent(u64::MAX, index);
+        self.data.seek(SeekFrom::Start(extent.start))?;
+        let mut block = Vec::new();
+        (&mut self.data).take(extent.end - extent.start).read_to_end(&mut block)?;
+        self.tree.check_block(index, &block)?;
+        Ok(block)
+    }
+
+    /// Reads the whole data, checking every block, and writes it to `writer`.
+    /// Returns an `Error` value if a block could not be read or does not match.
+    pub fn copy_to<W: Write>(&mut self, mut writer: W) -> Result<(), Error> {
+        for index in 0..self.tree.stored_leaves() {
+            writer.write_all(&self.read_block(index)?)?;
+        }
+        writer.flush()
+    }
+
+    /// Consumes the reader, returning the tree and the data.
+    pub fn into_inner(self) -> (HashTree<S>, R) {
+        (self.tree, self.data)
+    }
+}
@@ -1,9 +1,258 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod allowlist;
+pub mod annotated;
+#[cfg(feature = "std")]
+pub mod audit;
+#[cfg(feature = "std")]
+pub mod batch;
+pub mod block_size;
+#[cfg(feature = "std")]
+pub mod blocks;
+#[cfg(feature = "std")]
+pub mod bloom;
+#[cfg(feature = "std")]
+pub mod checkpoint;
+#[cfg(feature = "std")]
+pub mod chunker;
+#[cfg(feature = "std")]
+pub mod concurrent;
+#[cfg(feature = "std")]
+pub mod convergent;
+#[cfg(feature = "db")]
+pub mod db;
+#[cfg(all(feature = "direct", target_os = "linux"))]
+mod direct;
+#[cfg(feature = "std")]
+pub mod dir;
+#[cfg(feature = "std")]
+pub mod download;
+#[cfg(feature = "std")]
+mod exclude;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+pub mod estimate;
+pub mod fixed;
+#[cfg(feature = "std")]
+pub mod follow;
+#[cfg(feature = "std")]
+pub mod forest;
+#[cfg(feature = "std")]
+pub mod generation;
+#[cfg(feature = "git")]
+pub mod git;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+pub mod hasher;
+#[cfg(feature = "std")]
+pub mod history;
+#[cfg(all(feature = "sparse", unix))]
+mod holes;
+#[cfg(feature = "std")]
+pub mod incremental;
+pub mod index;
+pub mod io;
+#[cfg(feature = "std")]
+pub mod layout;
+#[cfg(feature = "std")]
+pub mod log;
+#[cfg(feature = "std")]
+pub mod manifest;
+#[cfg(feature = "std")]
+pub mod map;
+pub mod metrics;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+#[cfg(feature = "std")]
+pub mod mmr;
+#[cfg(feature = "std")]
+pub mod mpt;
+#[cfg(feature = "std")]
+pub mod multipart;
+pub mod multiproof;
+mod navigation;
+#[cfg(feature = "std")]
+pub mod nar;
+#[cfg(feature = "std")]
+pub mod nested;
+#[cfg(feature = "node")]
+pub mod node;
+pub mod observer;
+#[cfg(feature = "std")]
+pub mod oplog;
+#[cfg(feature = "parallel")]
+mod parallel;
+#[cfg(feature = "std")]
+pub mod partial;
+#[cfg(feature = "std")]
+pub mod persistent;
+pub mod proof;
+#[cfg(feature = "std")]
+pub mod pruned;
+pub mod render;
+#[cfg(feature = "std")]
+pub mod rfc9162;
+pub mod root;
+pub mod selftest;
+pub mod set;
+#[cfg(feature = "std")]
+pub mod shard;
+#[cfg(feature = "std")]
+pub mod shared;
+#[cfg(feature = "std")]
+pub mod signer;
+#[cfg(feature = "std")]
+pub mod simd;
+#[cfg(feature = "std")]
+pub mod slice;
+#[cfg(feature = "std")]
+pub mod sparse;
+pub mod store;
+#[cfg(feature = "std")]
+pub mod throttle;
 pub mod tree;
-pub use tree::HashTree;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod uring;
+#[cfg(feature = "verkle")]
+pub mod verkle;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "watch")]
+pub mod watch;
+pub mod wire;
+#[cfg(feature = "std")]
+pub mod witness;
+#[cfg(feature = "std")]
+pub mod writer;
+#[cfg(feature = "zstd")]
+pub mod zstd;
+#[cfg(feature = "std")]
+pub use allowlist::{Allowlist, AllowlistBuilder, LeafEncoding};
+pub use annotated::{AnnotatedProof, AnnotatedTree, MetadataMode};
+#[cfg(feature = "std")]
+pub use audit::{AuditLog, AuditRecord};
+#[cfg(feature = "std")]
+pub use batch::FileResult;
+pub use block_size::BlockSize;
+#[cfg(feature = "std")]
+pub use blocks::{collect_garbage, BlockStore, FileBlockStore, GcStats, MemoryBlockStore};
+#[cfg(feature = "std")]
+pub use bloom::BloomFilter;
+#[cfg(feature = "std")]
+pub use checkpoint::ResumableBuilder;
+#[cfg(feature = "std")]
+pub use chunker::{Chunker, FixedChunker, RecordChunker, TarChunker, TarEntry};
+#[cfg(feature = "std")]
+pub use concurrent::ConcurrentBuilder;
+#[cfg(feature = "std")]
+pub use convergent::ConvergentTree;
+#[cfg(feature = "db")]
+pub use db::SledStore;
+#[cfg(feature = "std")]
+pub use dir::{DirDiff, DirTree, FileDiff, FileEntry, Symlinks};
+#[cfg(feature = "std")]
+pub use download::{DownloadVerifier, PieceTracker};
+pub use estimate::{estimate, TreeEstimate};
+pub use fixed::{FixedProof, FixedTree};
+#[cfg(feature = "std")]
+pub use follow::TailFollower;
+#[cfg(feature = "std")]
+pub use forest::{AggregateProof, Forest, ForestProof, TreeMultiproof};
+#[cfg(feature = "std")]
+pub use generation::{Generation, GenerationChain};
+#[cfg(feature = "gpu")]
+pub use gpu::GpuSha256Hasher;
+#[cfg(feature = "blake2")]
+pub use hasher::Blake2bHasher;
+#[cfg(feature = "blake3")]
+pub use hasher::Blake3Hasher;
+pub use hasher::{
+    DigestHasher, DoubleSha256Hasher, HmacHasher, LengthPrefixedHasher, PairHasher, PrefixedHasher, Preprocess, PreprocessingHasher,
+    SaltedHasher, Sha256Hasher,
+};
+#[cfg(feature = "std")]
+pub use history::{HistoryTree, IncrementalProof, MembershipProof};
+#[cfg(feature = "std")]
+pub use incremental::IncrementalTree;
+pub use index::{LeafIndex, NodeIndex};
+#[cfg(feature = "std")]
+pub use layout::{outboard_path, OutboardReader};
+#[cfg(feature = "std")]
+pub use log::{ConsistencyProof, FileLogStore, InclusionProof, LogStore, MerkleLog};
+#[cfg(feature = "std")]
+pub use manifest::{Manifest, ManifestEntry};
+#[cfg(feature = "std")]
+pub use map::AuthenticatedMap;
+pub use metrics::{CounterMetrics, Metrics, Phase};
+#[cfg(feature = "mmap")]
+pub use mmap::{MmapStore, MmapTree};
+#[cfg(feature = "std")]
+pub use mmr::{Mmr, MmrProof};
+#[cfg(feature = "std")]
+pub use mpt::PatriciaTrie;
+#[cfg(feature = "std")]
+pub use partial::PartialTree;
+#[cfg(feature = "std")]
+pub use persistent::PersistentTree;
+#[cfg(feature = "std")]
+pub use multipart::MultipartTree;
+pub use multiproof::{ProofElement, StreamingVerifier};
+#[cfg(feature = "std")]
+pub use nested::{NestedBuilder, NestedProof, NestedTree};
+pub use observer::{BuildEvent, BuildObserver};
+#[cfg(feature = "std")]
+pub use oplog::TreeOp;
+pub use proof::{verify_proofs, verify_proofs_with, Proof, ProofLevel, Proofs};
+#[cfg(feature = "std")]
+pub use pruned::PrunedTree;
+#[cfg(feature = "std")]
+pub use rfc9162::{SignedTreeHead, TreeHead};
+pub use root::{Encoding, RootHash};
+pub use set::{NonMembershipProof, SortedSet};
+#[cfg(feature = "std")]
+pub use shard::Shard;
+#[cfg(feature = "std")]
+pub use shared::SharedHashTree;
+#[cfg(feature = "std")]
+pub use signer::{HmacSigner, SignedRoot, Signer, Verifier};
+#[cfg(feature = "std")]
+pub use simd::{MultiSha256Hasher, Sha256Backend};
+#[cfg(feature = "std")]
+pub use sparse::{SparseMerkleTree, SparseProof};
+#[cfg(feature = "std")]
+pub use store::CachedStore;
+pub use store::NodeStore;
+#[cfg(feature = "std")]
+pub use throttle::{Throttle, ThrottledReader};
+#[cfg(feature = "std")]
+pub use tree::RootSnapshot;
+pub use tree::{HashTree, Leaf, Nodes, Padding, Preset};
+#[cfg(feature = "verkle")]
+pub use verkle::{MerkleCommitment, VectorCommitment, VerkleProof, VerkleStep, VerkleTree};
+#[cfg(feature = "watch")]
+pub use watch::{DirWatcher, FileWatcher};
+#[cfg(feature = "std")]
+pub use witness::Witness;
+#[cfg(feature = "std")]
+pub use writer::HashTreeWriter;
+#[cfg(feature = "zstd")]
+pub use zstd::{CompressionStats, ZstdBlockStore};
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
-    use crate::HashTree;
+    use crate::{AuthenticatedMap, DirTree, HashTree, IncrementalTree, Manifest, MerkleLog, Mmr, RecordChunker, SparseMerkleTree};
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hashtree-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
 
     #[test]
     fn one_byte_block_size() {
@@ -40,5 +289,3878 @@ mod tests {
             assert!(tree.num_nodes() == 7);
         }
     }
-}
 
+    #[test]
+    fn odd_interior_level() {
+        use sha2::{Digest, Sha256};
+
+        // Five leaves give three parents, an odd interior level, on which
+        // the original build panicked; it is padded like the leaves
+        const BLOCK_SIZE: usize = 1;
+        let data = vec![0u8, 1, 2, 3, 4];
+        let tree = HashTree::new(BLOCK_SIZE).from_data(&mut data.as_slice()).unwrap();
+        assert!(tree.num_blocks() == 5);
+        assert!(tree.num_nodes() == 13);
+
+        let pair = |a: &[u8], b: &[u8]| Sha256::digest([a, b].concat()).to_vec();
+        let leaves: Vec<Vec<u8>> = data.iter().map(|&byte| Sha256::digest([byte]).to_vec()).collect();
+        let parents = [pair(&leaves[0], &leaves[1]), pair(&leaves[2], &leaves[3]), pair(&leaves[4], &leaves[4])];
+        let root = pair(&pair(&parents[0], &parents[1]), &pair(&parents[2], &parents[2]));
+        assert_eq!(tree.root(), Some(root));
+    }
+
+    #[test]
+    fn empty_input() {
+        // The original build recursed without end on empty input; it gives
+        // an empty tree without a root instead
+        let data: Vec<u8> = vec![];
+        let tree = HashTree::new(4096).from_data(&mut data.as_slice()).unwrap();
+        assert!(tree.is_empty());
+        assert_eq!((tree.num_blocks(), tree.num_nodes()), (0, 0));
+        assert_eq!(tree.root_hash(), None);
+        assert_eq!(tree.proof(0), None);
+        assert_eq!(HashTree::new(1).from_slice(b"").unwrap(), tree);
+    }
+
+    #[test]
+    fn record_chunking() {
+        let lines = "alpha\nbeta\r\n\ngamma";
+        let tree = HashTree::new(0).from_chunker(RecordChunker::lines(lines.as_bytes())).unwrap();
+        assert!(tree.num_blocks() == 4);
+
+        let blocks = "alpha\nbeta\n\ngamma";
+        let expected = HashTree::new(0).from_chunker(RecordChunker::lines(blocks.as_bytes())).unwrap();
+        assert!(tree == expected);
+
+        let csv = "id,note\n1,\"multi\nline\"\n2,plain\n";
+        let tree = HashTree::new(0).from_chunker(RecordChunker::csv(csv.as_bytes())).unwrap();
+        assert!(tree.num_blocks() == 3);
+    }
+
+    #[test]
+    fn directory_tree() {
+        let dir = temp_dir("directory_tree");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), b"hello").unwrap();
+        fs::write(dir.join("sub/b.txt"), vec![7u8; 10]).unwrap();
+        fs::write(dir.join("empty"), b"").unwrap();
+
+        let tree = DirTree::new(4).from_path(&dir).unwrap();
+        assert!(tree.num_files() == 3);
+        assert!(tree.file("sub/b.txt").unwrap().tree.num_blocks() == 3);
+        assert!(tree.file("empty").unwrap().size == 0);
+
+        let again = DirTree::new(4).from_path(&dir).unwrap();
+        assert_eq!(tree.root_hash(), again.root_hash());
+
+        fs::write(dir.join("sub/b.txt"), vec![8u8; 10]).unwrap();
+        let changed = DirTree::new(4).from_path(&dir).unwrap();
+        assert_ne!(tree.root_hash(), changed.root_hash());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn throttled_file_reads() {
+        use crate::Throttle;
+        use std::io::Read;
+        use std::time::{Duration, Instant};
+
+        let dir = temp_dir("throttle");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data");
+        let data: Vec<u8> = (0..32 * 1024u32).map(|i| i as u8).collect();
+        fs::write(&path, &data).unwrap();
+
+        // Two trees sharing a throttle of 256 KiB/s read 64 KiB in a quarter second
+        let throttle = Throttle::new(256 * 1024);
+        let start = Instant::now();
+        let tree = HashTree::new(1024).throttle(throttle.clone()).from_file(&path).unwrap();
+        let again = HashTree::new(1024).throttle(throttle.clone()).from_file(&path).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(240));
+        assert_eq!(tree, HashTree::new(1024).from_slice(&data).unwrap());
+        assert_eq!(again, tree);
+
+        let mut read = Vec::new();
+        Throttle::new(u64::MAX).reader(data.as_slice()).read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let dir = temp_dir("manifest_round_trip");
+        fs::write(dir.join("a.txt"), b"hello world").unwrap();
+        fs::write(dir.join("b with space.txt"), b"").unwrap();
+
+        let tree = DirTree::new(4).from_path(&dir).unwrap();
+        let manifest = tree.manifest(true).unwrap();
+        let mut out = Vec::new();
+        manifest.write_to(&mut out).unwrap();
+
+        let parsed = Manifest::read_from(out.as_slice()).unwrap();
+        assert!(parsed == manifest);
+        assert_eq!(parsed.root_hash(), tree.root_hash());
+        assert!(parsed.entries()[0].leaves.as_ref().unwrap().len() == 3);
+        assert!(parsed.verify(&dir).unwrap().is_empty());
+
+        fs::write(dir.join("a.txt"), b"hello there").unwrap();
+        assert_eq!(parsed.verify(&dir).unwrap(), vec!["a.txt".to_string()]);
+        assert_eq!(parsed.verify_throttled(&dir, &crate::Throttle::new(1 << 20)).unwrap(), vec!["a.txt".to_string()]);
+
+        let tampered = String::from_utf8(out.clone()).unwrap().replace(" 11 a.txt", " 12 a.txt");
+        assert!(Manifest::read_from(tampered.as_bytes()).is_ok());
+        let tampered = tampered.replace("a.txt", "c.txt");
+        assert!(Manifest::read_from(tampered.as_bytes()).is_err());
+
+        // Leaf lines must hash up to the root of their file
+        let text = String::from_utf8(out).unwrap();
+        let leaf = format!("leaf {}", hex::encode(&manifest.entries()[0].leaves.as_ref().unwrap()[0]));
+        let tampered = text.replacen(&leaf, &format!("leaf {}", hex::encode([0u8; 32])), 1);
+        assert!(Manifest::read_from(tampered.as_bytes()).is_err());
+        let dropped = text.replacen(&format!("{}\n", leaf), "", 1);
+        assert!(Manifest::read_from(dropped.as_bytes()).is_err());
+
+        // Roots committing to metadata cannot be recorded
+        assert!(DirTree::new(4).include_permissions().from_path(&dir).unwrap().manifest(false).is_err());
+        assert!(DirTree::new(4).include_mtime().from_path(&dir).unwrap().manifest(false).is_err());
+        #[cfg(feature = "git")]
+        assert!(DirTree::new(4).git().from_path(&dir).unwrap().manifest(false).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directory_diff() {
+        let dir = temp_dir("directory_diff");
+        fs::write(dir.join("kept.txt"), b"unchanged").unwrap();
+        fs::write(dir.join("gone.txt"), b"bye").unwrap();
+        fs::write(dir.join("edited.txt"), b"aaaabbbbccccdddd").unwrap();
+        let old = DirTree::new(4).from_path(&dir).unwrap();
+
+        fs::remove_file(dir.join("gone.txt")).unwrap();
+        fs::write(dir.join("new.txt"), b"hi").unwrap();
+        fs::write(dir.join("edited.txt"), b"aaaaXXXXccccdddd++").unwrap();
+        let new = DirTree::new(4).from_path(&dir).unwrap();
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, vec!["new.txt".to_string()]);
+        assert_eq!(diff.removed, vec!["gone.txt".to_string()]);
+        assert!(diff.modified.len() == 1);
+        assert_eq!(diff.modified[0].path, "edited.txt");
+        assert_eq!(diff.modified[0].blocks, vec![1..2, 4..5]);
+        assert!(old.diff(&old).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directory_update_path() {
+        let dir = temp_dir("directory_update_path");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), b"one").unwrap();
+        fs::write(dir.join("sub/b.txt"), b"two").unwrap();
+        let mut tree = DirTree::new(4).from_path(&dir).unwrap();
+
+        fs::write(dir.join("a.txt"), b"uno").unwrap();
+        assert!(tree.update_path(&dir, dir.join("a.txt")).unwrap());
+        assert!(!tree.update_path(&dir, dir.join("a.txt")).unwrap());
+
+        fs::remove_dir_all(dir.join("sub")).unwrap();
+        assert!(tree.update_path(&dir, dir.join("sub")).unwrap());
+        assert!(tree.num_files() == 1);
+        assert_eq!(tree.root_hash(), DirTree::new(4).from_path(&dir).unwrap().root_hash());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directory_excludes() {
+        let dir = temp_dir("directory_excludes");
+        for path in ["build/out.o", "src/build/gen.rs", "src/main.rs", "src/x.tmp", "docs/a.md", "docs/b.md", "logs/keep.log"] {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, b"data").unwrap();
+        }
+        let patterns = "# build output\n/build/\n*.tmp\ndocs/*\n!docs/b.md\nlogs/\n!logs/keep.log\nsrc/**/gen.[a-r]s\n";
+        let tree = DirTree::new(4).exclude(patterns).from_path(&dir).unwrap();
+        let paths: Vec<&str> = tree.files().iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, ["docs/b.md", "src/main.rs"]);
+
+        // Updates skip excluded paths, and drop files that became excluded
+        fs::write(dir.join("src/y.tmp"), b"data").unwrap();
+        let mut tree = tree.exclude("main.*");
+        assert!(!tree.update_path(&dir, dir.join("src/y.tmp")).unwrap());
+        assert!(tree.update_path(&dir, dir.join("src")).unwrap());
+        assert_eq!(tree.num_files(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directory_metadata() {
+        let dir = temp_dir("directory_metadata");
+        fs::write(dir.join("a.sh"), b"echo").unwrap();
+        let plain = DirTree::new(4).from_path(&dir).unwrap();
+        let with_permissions = DirTree::new(4).include_permissions().from_path(&dir).unwrap();
+        let with_mtime = DirTree::new(4).include_mtime().from_path(&dir).unwrap();
+        assert_ne!(plain.root_hash(), with_permissions.root_hash());
+        assert_ne!(plain.root_hash(), with_mtime.root_hash());
+        assert_eq!(plain.files()[0].mtime, with_mtime.files()[0].mtime);
+
+        let mut readonly = fs::metadata(dir.join("a.sh")).unwrap().permissions();
+        readonly.set_readonly(true);
+        fs::set_permissions(dir.join("a.sh"), readonly).unwrap();
+        let changed = DirTree::new(4).include_permissions().from_path(&dir).unwrap();
+        assert_ne!(with_permissions.root_hash(), changed.root_hash());
+        assert_eq!(plain.root_hash(), DirTree::new(4).from_path(&dir).unwrap().root_hash());
+        let diff = with_permissions.diff(&changed);
+        assert_eq!(diff.modified, [crate::FileDiff { path: "a.sh".into(), blocks: vec![] }]);
+        assert!(plain.diff(&DirTree::new(4).from_path(&dir).unwrap()).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn directory_symlinks() {
+        use crate::Symlinks;
+        use std::os::unix::fs::symlink;
+
+        let dir = temp_dir("directory_symlinks");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/a.txt"), b"one").unwrap();
+        symlink("sub/a.txt", dir.join("link.txt")).unwrap();
+        symlink("sub", dir.join("linked")).unwrap();
+        symlink("..", dir.join("sub/up")).unwrap();
+        symlink("missing", dir.join("dangling")).unwrap();
+
+        let paths = |symlinks| {
+            let tree = DirTree::new(4).symlinks(symlinks).from_path(&dir).unwrap();
+            tree.files().iter().map(|file| file.path.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(paths(Symlinks::FollowFiles), ["link.txt", "sub/a.txt"]);
+        assert_eq!(paths(Symlinks::Skip), ["sub/a.txt"]);
+        // The link back up to the root ends the walk instead of looping
+        assert_eq!(paths(Symlinks::Follow), ["link.txt", "linked/a.txt", "sub/a.txt"]);
+        assert_eq!(paths(Symlinks::Record), ["dangling", "link.txt", "linked", "sub/a.txt", "sub/up"]);
+
+        let recorded = DirTree::new(4).symlinks(Symlinks::Record).from_path(&dir).unwrap();
+        let link = recorded.file("link.txt").unwrap();
+        assert_eq!((link.mode, link.size), (0o120000, 9));
+        assert_eq!(link.tree, HashTree::new(4).from_slice(b"sub/a.txt").unwrap());
+
+        // Recorded links are verified by their targets, not the files behind them
+        let manifest = recorded.manifest(false).unwrap();
+        let mut out = Vec::new();
+        manifest.write_to(&mut out).unwrap();
+        let parsed = Manifest::read_from(out.as_slice()).unwrap();
+        assert!(parsed == manifest && parsed.entries()[0].link);
+        assert!(parsed.verify(&dir).unwrap().is_empty());
+        fs::remove_file(dir.join("link.txt")).unwrap();
+        symlink("sub/b.txt", dir.join("link.txt")).unwrap();
+        assert_eq!(parsed.verify(&dir).unwrap(), ["link.txt"]);
+        fs::remove_file(dir.join("link.txt")).unwrap();
+        fs::write(dir.join("link.txt"), b"sub/a.txt").unwrap();
+        assert_eq!(parsed.verify(&dir).unwrap(), ["link.txt"]);
+        fs::remove_file(dir.join("link.txt")).unwrap();
+        symlink("sub/a.txt", dir.join("link.txt")).unwrap();
+
+        let mut tree = DirTree::new(4).symlinks(Symlinks::Follow).from_path(&dir).unwrap();
+        fs::write(dir.join("sub/b.txt"), b"two").unwrap();
+        assert!(tree.update_path(&dir, dir.join("linked")).unwrap());
+        assert!(tree.file("linked/b.txt").is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn directory_watcher() {
+        use std::time::Duration;
+
+        let dir = temp_dir("directory_watcher");
+        fs::write(dir.join("a.txt"), b"one").unwrap();
+        let tree = DirTree::new(4).from_path(&dir).unwrap();
+        let mut watcher = crate::DirWatcher::new(tree, &dir).unwrap();
+
+        fs::write(dir.join("b.txt"), b"two").unwrap();
+        let expected = DirTree::new(4).from_path(&dir).unwrap().root_hash();
+        let mut root = watcher.wait_timeout(Duration::from_secs(5)).unwrap();
+        while root.is_some() && root != expected {
+            root = watcher.wait_timeout(Duration::from_secs(5)).unwrap();
+        }
+        assert_eq!(root, expected);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn file_watcher() {
+        use std::time::Duration;
+
+        let dir = temp_dir("file_watcher");
+        let path = dir.join("log.txt");
+        fs::write(&path, b"first entry\n").unwrap();
+        let configured = || HashTree::new(4).arity(3).salt(b"watch");
+        let mut watcher = crate::FileWatcher::new(configured().from_file(&path).unwrap(), &path).unwrap();
+
+        // The rebuilt tree keeps the arity and salt, whether blocks are
+        // appended or changed in place
+        for contents in [&b"first entry\nsecond entry\n"[..], b"first ENTRY\nsecond entry\n"] {
+            fs::write(&path, contents).unwrap();
+            let expected = configured().from_slice(contents).unwrap();
+            let mut root = watcher.wait_timeout(Duration::from_secs(5)).unwrap();
+            while root.is_some() && root != expected.root_hash() {
+                root = watcher.wait_timeout(Duration::from_secs(5)).unwrap();
+            }
+            assert_eq!(root, expected.root_hash());
+            assert_eq!(watcher.tree(), &expected);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(all(feature = "git", unix))]
+    #[test]
+    fn git_tree_id() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("git_tree_id");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("hello.txt"), b"hello\n").unwrap();
+        fs::write(dir.join("sub/a"), b"x").unwrap();
+        fs::write(dir.join("sub.txt"), b"").unwrap();
+        fs::write(dir.join("run.sh"), b"#!/bin/sh\n").unwrap();
+        fs::set_permissions(dir.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+
+        let tree = DirTree::new(4096).git().from_path(&dir).unwrap();
+        assert_eq!(tree.root_hash().unwrap(), "5a66edef2881b478818ad075709bdd556bfdbbf6");
+        assert_eq!(hex::encode(tree.file("hello.txt").unwrap().git_id.as_ref().unwrap()),
+            "ce013625030ba8dba906f756967f9e9ca394464a");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(all(feature = "git", unix))]
+    #[test]
+    fn git_modes_and_links() {
+        use crate::Symlinks;
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = temp_dir("git_modes_and_links");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("hello.txt"), b"hello\n").unwrap();
+        fs::write(dir.join("run.sh"), b"#!/bin/sh\n").unwrap();
+        fs::set_permissions(dir.join("run.sh"), fs::Permissions::from_mode(0o744)).unwrap();
+        fs::write(dir.join("group.sh"), b"data\n").unwrap();
+        fs::set_permissions(dir.join("group.sh"), fs::Permissions::from_mode(0o654)).unwrap();
+        fs::write(dir.join("sub/a"), b"x").unwrap();
+        symlink("hello.txt", dir.join("link")).unwrap();
+        symlink("sub", dir.join("dirlink")).unwrap();
+        symlink("missing", dir.join("dangling")).unwrap();
+
+        // `git add -A && git write-tree` and `git ls-files -s` of the same
+        // directory; links are recorded whatever `symlinks` is set to
+        for symlinks in [Symlinks::FollowFiles, Symlinks::Follow, Symlinks::Skip] {
+            let tree = DirTree::new(4096).symlinks(symlinks).git().from_path(&dir).unwrap();
+            assert_eq!(tree.root_hash().unwrap(), "0ad379109603b61d09f5f181e88629c22ad40180");
+            let entries: Vec<(&str, u32, String)> =
+                tree.files().iter().map(|f| (f.path.as_str(), f.mode, hex::encode(f.git_id.as_ref().unwrap()))).collect();
+            assert_eq!(entries, [
+                ("dangling", 0o120000, "6eab79a6ce25b19851f591e3e974e192c6858cf6".to_string()),
+                ("dirlink", 0o120000, "3de0f365ba57c94daac626bf53a7da269b65f57c".to_string()),
+                ("group.sh", 0o100644, "1269488f7fb1f4b56a8c0e5eb48cecbfadfa9219".to_string()),
+                ("hello.txt", 0o100644, "ce013625030ba8dba906f756967f9e9ca394464a".to_string()),
+                ("link", 0o120000, "a5162f80d4a6782b7cb2a0a197f834e683cb9eb1".to_string()),
+                ("run.sh", 0o100755, "1a2485251c33a70432394c93fb89330ef214bfc9".to_string()),
+                ("sub/a", 0o100644, "c1b0730e0133447badcfd47fd144e254807b06e1".to_string()),
+            ]);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nar_serialization() {
+        use sha2::{Digest, Sha256};
+
+        assert_eq!(crate::nar::to_nix_base32(&Sha256::digest(b"")),
+            "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73");
+
+        let dir = temp_dir("nar_serialization");
+        fs::write(dir.join("file"), b"hello").unwrap();
+        let mut nar = Vec::new();
+        crate::nar::serialize(dir.join("file"), &mut nar).unwrap();
+
+        let mut expected = Vec::new();
+        for s in ["nix-archive-1", "(", "type", "regular", "contents", "hello", ")"].iter() {
+            expected.extend_from_slice(&(s.len() as u64).to_le_bytes());
+            expected.extend_from_slice(s.as_bytes());
+            expected.resize(expected.len().div_ceil(8) * 8, 0);
+        }
+        assert_eq!(nar, expected);
+        assert_eq!(crate::nar::hash(dir.join("file")).unwrap(), Sha256::digest(&expected).to_vec());
+
+        // NAR has no representation for sockets, FIFOs or device nodes
+        #[cfg(unix)]
+        {
+            let _listener = std::os::unix::net::UnixListener::bind(dir.join("socket")).unwrap();
+            let error = crate::nar::hash(&dir).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+            assert!(error.to_string().contains("socket"));
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sparse_tree_order_independent() {
+        let keys: Vec<[u8; 32]> = (0u8..20).map(|i| [i.wrapping_mul(37); 32]).collect();
+
+        let mut forward = SparseMerkleTree::new();
+        for key in &keys {
+            forward.insert(*key, key);
+        }
+        let mut backward = SparseMerkleTree::new();
+        for key in keys.iter().rev() {
+            backward.insert(*key, key);
+        }
+        assert_eq!(forward.root(), backward.root());
+        assert!(forward.len() == 20);
+
+        let empty = SparseMerkleTree::new();
+        for key in &keys {
+            assert!(forward.remove(key).is_some());
+        }
+        assert!(forward.is_empty());
+        assert_eq!(forward.root(), empty.root());
+        assert!(forward.remove(&keys[0]).is_none());
+    }
+
+    #[test]
+    fn authenticated_map_proofs() {
+        let mut map = AuthenticatedMap::new();
+        for i in 0..50u32 {
+            map.insert(i.to_be_bytes().to_vec(), format!("value-{}", i));
+        }
+        assert_eq!(map.get(&7u32.to_be_bytes().to_vec()).unwrap(), "value-7");
+
+        let root = map.root();
+        let key = 42u32.to_be_bytes().to_vec();
+        let proof = map.proof(&key);
+        assert!(AuthenticatedMap::verify(&root, &key, &"value-42".to_string(), &proof));
+        assert!(!AuthenticatedMap::verify(&root, &key, &"value-41".to_string(), &proof));
+
+        assert_eq!(map.remove(&key).unwrap(), "value-42");
+        assert_ne!(map.root(), root);
+        assert!(!AuthenticatedMap::verify(&map.root(), &key, &"value-42".to_string(), &proof));
+        map.insert(key.clone(), "value-42".to_string());
+        assert_eq!(map.root(), root);
+    }
+
+    #[test]
+    fn sparse_absence_proofs() {
+        let mut map = AuthenticatedMap::new();
+        map.insert("alice", "allowed");
+        map.insert("bob", "allowed");
+        let root = map.root();
+
+        let proof = map.absence_proof(&"mallory").unwrap();
+        assert!(AuthenticatedMap::<&str, &str>::verify_absence(&root, &"mallory", &proof));
+        assert!(!AuthenticatedMap::<&str, &str>::verify_absence(&root, &"alice", &proof));
+        assert!(map.absence_proof(&"alice").is_none());
+
+        let membership = map.proof(&"alice");
+        assert!(!AuthenticatedMap::<&str, &str>::verify_absence(&root, &"alice", &membership));
+
+        map.insert("mallory", "allowed");
+        assert!(!AuthenticatedMap::<&str, &str>::verify_absence(&map.root(), &"mallory", &proof));
+    }
+
+    #[test]
+    fn mmr_proofs() {
+        use crate::MmrProof;
+
+        let mut mmr = Mmr::new();
+        assert!(mmr.root().is_none());
+
+        for n in 0..20u64 {
+            mmr.append(&n.to_be_bytes());
+            let root = mmr.root().unwrap();
+            assert!(mmr.peaks().len() == (n + 1).count_ones() as usize);
+
+            for i in 0..=n {
+                let proof = mmr.proof(i).unwrap();
+                assert!(proof.verify(&root, &Mmr::leaf_hash(&i.to_be_bytes())));
+                assert!(!proof.verify(&root, &Mmr::leaf_hash(b"other")));
+            }
+            assert!(mmr.proof(n + 1).is_none());
+        }
+        assert!(mmr.num_nodes() == 2 * 20 - 20u64.count_ones() as u64);
+
+        // The first peak, over the first 16 leaves at position 30, is not a leaf
+        let proof = mmr.proof(0).unwrap();
+        let peak = proof.peaks[0];
+        let forged = MmrProof { position: 30, siblings: Vec::new(), ..proof };
+        assert!(!forged.verify(&mmr.root().unwrap(), &peak));
+    }
+
+    #[test]
+    fn merkle_log_proofs() {
+        let entries: Vec<Vec<u8>> = vec![
+            vec![], vec![0x00], vec![0x10], vec![0x20, 0x21], vec![0x30, 0x31],
+            vec![0x40, 0x41, 0x42, 0x43], (0x50..0x58).collect(), (0x60..0x70).collect(),
+        ];
+        let mut log = MerkleLog::new();
+        for entry in &entries {
+            log.append(entry).unwrap();
+        }
+        assert_eq!(hex::encode(log.root_at(1).unwrap()),
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d");
+        assert_eq!(hex::encode(log.root()),
+            "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328");
+
+        for size in 1..=8u64 {
+            let root = log.root_at(size).unwrap();
+            for index in 0..size {
+                let proof = log.inclusion_proof(index, size).unwrap();
+                assert!(proof.verify(&root, &entries[index as usize]));
+                assert!(!proof.verify(&root, b"forged"));
+            }
+            for old in 1..=size {
+                let proof = log.consistency_proof(old, size).unwrap();
+                assert!(proof.verify(&log.root_at(old).unwrap(), &root));
+                if old < size {
+                    assert!(!proof.verify(&log.root_at(old - 1).unwrap(), &root));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn merkle_log_file_store() {
+        let dir = temp_dir("merkle_log_file_store");
+        let path = dir.join("log");
+
+        let mut log = MerkleLog::open(crate::FileLogStore::open(&path).unwrap()).unwrap();
+        for i in 0..10u32 {
+            log.append(&i.to_be_bytes()).unwrap();
+        }
+        let root = log.root();
+        drop(log);
+
+        let mut reopened = MerkleLog::open(crate::FileLogStore::open(&path).unwrap()).unwrap();
+        assert!(reopened.len() == 10);
+        assert_eq!(reopened.root(), root);
+        assert_eq!(reopened.get(3).unwrap().unwrap(), 3u32.to_be_bytes().to_vec());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn incremental_tree_matches_full_tree() {
+        let mut tree = IncrementalTree::new(3);
+        let mut leaves = vec![vec![0u8; 32]; 8];
+        for i in 0..7u8 {
+            let leaf = [i + 1; 32];
+            assert!(tree.append(leaf) == Some(i as u64));
+            leaves[i as usize] = leaf.to_vec();
+
+            let full = HashTree::new(32).from_leaf_hashes(leaves.clone()).unwrap();
+            assert_eq!(full.root_hash().unwrap(), tree.root()[..]);
+        }
+        assert!(tree.append([8u8; 32]).is_none());
+        assert!(tree.len() == 7);
+    }
+
+    #[test]
+    fn block_proofs() {
+        use sha2::{Digest, Sha256};
+
+        for &arity in [2usize, 4, 8, 16].iter() {
+            for len in 1..40u8 {
+                let data: Vec<u8> = (0..len).collect();
+                let tree = HashTree::new(1).arity(arity).from_data(&mut data.as_slice()).unwrap();
+                let root = tree.root_hash().unwrap().into_bytes();
+
+                for (index, byte) in data.iter().enumerate() {
+                    let proof = tree.proof(index).unwrap();
+                    assert!(proof.arity() == arity);
+                    assert!(proof.levels.iter().all(|level| level.siblings.len() == arity - 1));
+                    assert!(proof.verify(&root, &Sha256::digest([*byte])));
+                    assert!(!proof.verify(&root, &Sha256::digest([byte.wrapping_add(1)])));
+                }
+                assert!(tree.proof(len as usize).is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn arity_changes_depth() {
+        let data = vec![1u8; 64];
+        let binary = HashTree::new(1).from_data(&mut data.as_slice()).unwrap();
+        let wide = HashTree::new(1).arity(16).from_data(&mut data.as_slice()).unwrap();
+        assert!(binary.proof(0).unwrap().levels.len() == 6);
+        assert!(wide.proof(0).unwrap().levels.len() == 2);
+        assert!(wide.num_nodes() == 64 + 16 + 1);
+        assert!(binary != wide);
+    }
+
+    #[test]
+    fn custom_pair_hasher() {
+        use crate::PairHasher;
+
+        // A toy arithmetic "hash" standing in for a field-friendly hash like Poseidon
+        #[derive(Debug)]
+        struct SumHasher;
+
+        impl PairHasher for SumHasher {
+            fn hash_leaf(&self, data: &[u8]) -> Vec<u8> {
+                vec![data.iter().fold(1u8, |acc, b| acc.wrapping_mul(31).wrapping_add(*b))]
+            }
+
+            fn hash_pair(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+                vec![left[0].wrapping_mul(7).wrapping_add(right[0])]
+            }
+        }
+
+        let data: Vec<u8> = (0..9).collect();
+        for &arity in [2usize, 3].iter() {
+            let tree = HashTree::new(1).arity(arity).hasher(SumHasher).from_data(&mut data.as_slice()).unwrap();
+            let root = tree.root_hash().unwrap().into_bytes();
+            assert!(root.len() == 1);
+
+            let proof = tree.proof(4).unwrap();
+            assert!(proof.verify_with(&SumHasher, &root, &SumHasher.hash_leaf(&[4])));
+            assert!(!proof.verify(&root, &SumHasher.hash_leaf(&[4])));
+        }
+    }
+
+    #[test]
+    fn partial_tree_from_proofs() {
+        use crate::PartialTree;
+        use sha2::{Digest, Sha256};
+
+        let data: Vec<u8> = (0..11).collect();
+        let tree = HashTree::new(1).from_data(&mut data.as_slice()).unwrap();
+        let root = tree.root_hash().unwrap().into_bytes();
+        let mut partial = PartialTree::new(root);
+
+        assert!(partial.insert_proof(&Sha256::digest([3u8]), &tree.proof(3).unwrap()));
+        assert!(partial.contains(3, &Sha256::digest([3u8])));
+        assert!(partial.contains(2, &Sha256::digest([2u8])));
+        assert!(partial.proof(9).is_none());
+
+        let mut forged = tree.proof(9).unwrap();
+        assert!(!partial.insert_proof(&Sha256::digest([10u8]), &forged));
+        forged.leaf_index = 8;
+        assert!(!partial.insert_proof(&Sha256::digest([9u8]), &forged));
+
+        assert!(partial.insert_proof(&Sha256::digest([9u8]), &tree.proof(9).unwrap()));
+        assert_eq!(partial.proven_leaves().collect::<Vec<_>>(), vec![3, 9]);
+        assert_eq!(partial.proof(9), tree.proof(9));
+        assert_eq!(partial.proof(8), tree.proof(8));
+        assert!(partial.proof(5).is_none());
+    }
+
+    #[test]
+    fn pruned_tree_proofs() {
+        use std::io::Cursor;
+
+        for &arity in [2usize, 4].iter() {
+            for len in [1usize, 5, 17, 40].iter() {
+                let data: Vec<u8> = (0..*len as u8 * 3).collect();
+                let tree = HashTree::new(3).arity(arity).from_data(&mut data.as_slice()).unwrap();
+                for keep in 1..5 {
+                    let pruned = tree.clone().into_pruned(keep);
+                    assert_eq!(pruned.root_hash(), tree.root_hash());
+                    for index in 0..*len {
+                        let proof = pruned.proof(index, &mut Cursor::new(&data)).unwrap();
+                        assert_eq!(proof, tree.proof(index));
+                    }
+                    assert!(pruned.proof(*len, &mut Cursor::new(&data)).unwrap().is_none());
+                }
+            }
+        }
+
+        let data = vec![1u8; 64];
+        let pruned = HashTree::new(4).from_data(&mut data.as_slice()).unwrap().into_pruned(2);
+        let mut tampered = data.clone();
+        tampered[0] = 2;
+        assert!(pruned.proof(0, &mut Cursor::new(&tampered)).is_err());
+    }
+
+    #[test]
+    fn merge_matches_whole_tree() {
+        let data: Vec<u8> = (0..=255).collect();
+        for &arity in [2usize, 3].iter() {
+            for split in (0..=64).step_by(7) {
+                let left = HashTree::new(4).arity(arity).from_data(&mut &data[..split * 4]).unwrap();
+                let right = HashTree::new(4).arity(arity).from_data(&mut &data[split * 4..]).unwrap();
+                let whole = HashTree::new(4).arity(arity).from_data(&mut data.as_slice()).unwrap();
+                let merged = left.merge(right).unwrap();
+                assert_eq!(merged.root_hash(), whole.root_hash());
+                assert_eq!(merged.num_nodes(), whole.num_nodes());
+                assert_eq!(merged.proof(63), whole.proof(63));
+            }
+        }
+
+        let a = HashTree::new(4).from_data(&mut &data[..]).unwrap();
+        let b = HashTree::new(8).from_data(&mut &data[..]).unwrap();
+        assert!(a.clone().merge(b).is_err());
+        let salted = HashTree::new(4).salt(b"salt").from_data(&mut &data[..]).unwrap();
+        assert!(a.clone().merge(salted).is_err());
+        let hasher = HashTree::new(4).hasher(crate::DigestHasher::<sha2::Sha512>::new()).from_data(&mut &data[..]).unwrap();
+        assert!(a.clone().merge(hasher).is_err());
+        let promoted = HashTree::new(4).padding(crate::Padding::Promote).from_data(&mut &data[..]).unwrap();
+        assert!(a.merge(promoted).is_err());
+    }
+
+    #[test]
+    fn split_then_merge() {
+        use crate::LeafIndex;
+
+        let data: Vec<u8> = (0..100).collect();
+        let tree = HashTree::new(5).arity(3).from_data(&mut data.as_slice()).unwrap();
+        for index in 0..=tree.num_blocks() {
+            let (left, right) = tree.split_at(LeafIndex(index));
+            assert_eq!(left.num_blocks(), index);
+            assert_eq!(right.num_blocks(), tree.num_blocks() - index);
+            let expected = HashTree::new(5).arity(3).from_data(&mut &data[index as usize * 5..]).unwrap();
+            assert_eq!(right.root_hash(), expected.root_hash());
+            assert_eq!(left.merge(right).unwrap().root_hash(), tree.root_hash());
+        }
+    }
+
+    #[test]
+    fn update_records_history() {
+        let mut tree = HashTree::new(2).arity(3).track_history();
+        let mut data = Vec::new();
+        for round in 1..=5u8 {
+            let chunk = vec![round; 2 * round as usize];
+            tree.insert(&mut chunk.as_slice()).unwrap();
+            data.extend_from_slice(&chunk);
+            tree.update().unwrap();
+            assert_eq!(tree, HashTree::new(2).arity(3).from_data(&mut data.as_slice()).unwrap());
+        }
+
+        let history = tree.history();
+        assert_eq!(tree.version(), 5);
+        assert_eq!(history.iter().map(|s| s.leaf_count).collect::<Vec<_>>(), vec![1, 3, 6, 10, 15]);
+        assert_eq!(history.last().unwrap().root_hash, tree.root_hash());
+        assert!(history.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+        assert!(HashTree::new(2).history().is_empty());
+    }
+
+    #[test]
+    fn persistent_tree_versions() {
+        use sha2::{Digest, Sha256};
+
+        for &arity in [2usize, 3].iter() {
+            let mut data: Vec<u8> = (0..23).collect();
+            let tree = HashTree::new(1).arity(arity).from_data(&mut data.as_slice()).unwrap();
+            let mut current = tree.to_persistent();
+            let mut versions = vec![(current.snapshot(), tree.root_hash())];
+
+            for &index in [22usize, 0, 11, 22].iter() {
+                data[index] = data[index].wrapping_add(100);
+                assert!(current.set_leaf(index, Sha256::digest([data[index]]).to_vec()));
+                let expected = HashTree::new(1).arity(arity).from_data(&mut data.as_slice()).unwrap();
+                assert_eq!(current.root_hash(), expected.root_hash());
+                assert_eq!(current.proof(index), expected.proof(index));
+                versions.push((current.snapshot(), expected.root_hash()));
+            }
+            for (version, root) in &versions {
+                assert_eq!(&version.root_hash(), root);
+            }
+            assert_eq!(current.leaf_hash(11).unwrap(), &Sha256::digest([data[11]])[..]);
+            assert!(!current.set_leaf(23, vec![]));
+        }
+    }
+
+    #[test]
+    fn custom_node_store() {
+        use crate::NodeStore;
+
+        #[derive(Debug, Default)]
+        struct CountingStore {
+            nodes: Vec<Vec<u8>>,
+            writes: usize,
+        }
+
+        impl NodeStore for CountingStore {
+            fn get(&self, index: usize) -> Option<Vec<u8>> {
+                NodeStore::get(&self.nodes, index)
+            }
+
+            fn put(&mut self, index: usize, hash: Vec<u8>) -> Result<(), std::io::Error> {
+                self.writes += 1;
+                NodeStore::put(&mut self.nodes, index, hash)
+            }
+
+            fn len(&self) -> usize {
+                self.nodes.len()
+            }
+
+            fn truncate(&mut self, len: usize) -> Result<(), std::io::Error> {
+                self.nodes.truncate(len);
+                Ok(())
+            }
+        }
+
+        let data: Vec<u8> = (0..50).collect();
+        let expected = HashTree::new(4).from_data(&mut data.as_slice()).unwrap();
+        let tree = HashTree::with_store(4, CountingStore::default()).from_data(&mut data.as_slice()).unwrap();
+        assert_eq!(tree, expected);
+        assert_eq!(tree.store().writes, expected.num_nodes());
+        assert_eq!(tree.proof(7), expected.proof(7));
+        assert_eq!(tree.store().iter_nodes().count(), expected.store().len());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap_store_round_trip() {
+        use crate::{MmapStore, NodeStore};
+
+        let dir = temp_dir("mmap-store");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("nodes");
+
+        // Enough nodes to grow the file past its initial capacity
+        let data: Vec<u8> = (0..3000u32).flat_map(|i| i.to_be_bytes()).collect();
+        let expected = HashTree::new(4).from_data(&mut data.as_slice()).unwrap();
+        let store = MmapStore::create(&path, 32).unwrap();
+        let tree = HashTree::with_store(4, store).from_data(&mut data.as_slice()).unwrap();
+        assert_eq!(tree, expected);
+        assert_eq!(tree.proof(2999), expected.proof(2999));
+        drop(tree);
+
+        let mut store = MmapStore::open(&path).unwrap();
+        assert_eq!(store.len(), expected.num_nodes());
+        assert_eq!(store.iter_nodes().last(), expected.root_hash().map(|r| r.into_bytes()));
+        assert!(store.put(0, vec![0u8; 20]).is_err());
+        fs::write(&path, b"not a store").unwrap();
+        assert!(MmapStore::open(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap_tree_serves_proofs() {
+        use crate::{MmapTree, Padding, Preset};
+
+        let dir = temp_dir("mmap-tree");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tree");
+        let data: Vec<u8> = (0..1000u32).flat_map(|i| i.to_be_bytes()).collect();
+        let trees = [
+            HashTree::new(4).from_slice(&data).unwrap(),
+            HashTree::new(4).arity(3).padding(Padding::Zero).from_slice(&data).unwrap(),
+            HashTree::new(4).preset(Preset::Rfc6962).from_slice(&data[..4 * 37]).unwrap(),
+        ];
+        for tree in &trees {
+            for level_order in [false, true] {
+                let file = fs::File::create(&path).unwrap();
+                if level_order { tree.write_level_order(file) } else { tree.write_to(file) }.unwrap();
+                let mapped = MmapTree::open(&path).unwrap();
+                assert_eq!(mapped.root_hash(), tree.root_hash());
+                assert_eq!(mapped.num_blocks() as u64, tree.num_blocks());
+                assert!(mapped.is_hashed_with(tree.get_hasher()));
+                for (index, leaf) in tree.leaf_hashes().enumerate() {
+                    assert_eq!(mapped.leaf_hash(index), Some(&leaf[..]));
+                    assert_eq!(mapped.proof(index), tree.proof(index));
+                }
+                assert_eq!(mapped.proof(mapped.num_blocks()), None);
+            }
+        }
+
+        let mut bytes = Vec::new();
+        trees[0].write_to(&mut bytes).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(MmapTree::open(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "db")]
+    #[test]
+    fn sled_store_survives_reopen() {
+        use crate::{NodeStore, SledStore};
+
+        let dir = temp_dir("sled-store");
+        let db = sled::open(&dir).unwrap();
+        let data: Vec<u8> = (0..200).collect();
+        let expected = HashTree::new(3).arity(4).from_data(&mut data.as_slice()).unwrap();
+        {
+            let store = SledStore::from_tree(db.open_tree("nodes").unwrap()).unwrap().batch_size(7);
+            let mut tree = HashTree::with_store(3, store).arity(4).from_data(&mut &data[..150]).unwrap();
+            tree.insert(&mut &data[150..]).unwrap();
+            tree.update().unwrap();
+            assert_eq!(tree, expected);
+        }
+
+        let store = SledStore::from_tree(db.open_tree("nodes").unwrap()).unwrap();
+        assert_eq!(store.len(), expected.num_nodes());
+        let tree = HashTree::with_store(3, store).arity(4).open(expected.num_blocks()).unwrap();
+        assert_eq!(tree, expected);
+        assert_eq!(tree.proof(66), expected.proof(66));
+        drop(tree);
+
+        let store = SledStore::from_tree(db.open_tree("nodes").unwrap()).unwrap();
+        assert!(HashTree::with_store(3, store).arity(4).open(expected.num_blocks() * 2).is_err());
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cached_store_evicts_least_recently_used() {
+        use crate::{CachedStore, NodeStore};
+
+        let data: Vec<u8> = (0..=255).collect();
+        let expected = HashTree::new(1).from_data(&mut data.as_slice()).unwrap();
+        let store = CachedStore::new(Vec::new(), 16);
+        let mut tree = HashTree::with_store(1, store).from_data(&mut data.as_slice()).unwrap();
+        assert!(tree.store().cached() <= 16);
+
+        for index in [0usize, 100, 255, 100].iter() {
+            assert_eq!(tree.proof(*index), expected.proof(*index));
+        }
+        let misses = tree.store().misses();
+        assert_eq!(tree.proof(100), expected.proof(100));
+        assert_eq!(tree.store().misses(), misses);
+
+        tree.insert(&mut &b"more"[..]).unwrap();
+        tree.update().unwrap();
+        let expected = expected.merge(HashTree::new(1).from_data(&mut &b"more"[..]).unwrap()).unwrap();
+        assert_eq!(tree, expected);
+        assert_eq!(tree.store().len(), expected.num_nodes());
+    }
+
+    #[test]
+    fn serialization_round_trip() {
+        use crate::{CachedStore, DigestHasher};
+
+        let data: Vec<u8> = (0..100).collect();
+        let tree = HashTree::new(3).arity(5).from_data(&mut data.as_slice()).unwrap();
+        let mut bytes = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 48 + 32 * tree.num_nodes());
+
+        let copy = HashTree::with_store(0, CachedStore::new(Vec::new(), 8)).read_from(bytes.as_slice()).unwrap();
+        assert_eq!(copy.num_blocks(), tree.num_blocks());
+        assert_eq!(copy.proof(42), tree.proof(42));
+
+        let mut empty = Vec::new();
+        HashTree::new(8).write_to(&mut empty).unwrap();
+        assert!(HashTree::new(0).read_from(empty.as_slice()).unwrap().is_empty());
+
+        let error = |bytes: &[u8]| HashTree::new(0).read_from(bytes).unwrap_err().to_string();
+        let sha512 = HashTree::new(0).hasher(DigestHasher::<sha2::Sha512>::new()).read_from(bytes.as_slice());
+        assert!(sha512.unwrap_err().to_string().contains("different hash algorithm"));
+        assert!(HashTree::new(0).read_from(&bytes[..bytes.len() - 1]).is_err());
+        let mut bad = bytes.clone();
+        bad[5] = 9;
+        assert_eq!(error(&bad), "unsupported format version 9");
+        let mut bad = bytes.clone();
+        bad[6] = 4;
+        assert_eq!(error(&bad), "unsupported padding strategy 4");
+        let mut bad = bytes;
+        bad[47] -= 1;
+        assert!(error(&bad[..bad.len() - 32]).contains("number of blocks"));
+    }
+
+    #[test]
+    fn root_hash_parsing_and_equality() {
+        use crate::RootHash;
+
+        let tree = HashTree::new(1).from_data(&mut &[0u8, 1][..]).unwrap();
+        let root = tree.root_hash().unwrap();
+        let hex = "30e1867424e66e8b6d159246db94e3486778136f7e386ff5f001859d6b8484ab";
+        assert_eq!(root, hex);
+        assert_eq!(root.to_string(), hex);
+        assert_eq!(format!("{:?}", root), format!("RootHash({})", hex));
+        assert_eq!(hex.to_uppercase().parse::<RootHash>().unwrap(), root);
+        assert!("zz".parse::<RootHash>().is_err());
+        assert_ne!(root, RootHash::from([0u8; 32]));
+        assert_ne!(root, RootHash::new(root.as_bytes()[..31].to_vec()));
+        assert_ne!(root, "not hex");
+    }
+
+    #[test]
+    fn raw_root_accessors() {
+        use crate::DigestHasher;
+
+        let empty = HashTree::new(1);
+        assert!(empty.root_hash_bytes().is_none() && empty.root_hash_array().is_none());
+
+        let mut tree = HashTree::new(1).from_data(&mut &b"abc"[..]).unwrap();
+        let before = tree.root_hash_array().unwrap();
+        tree.insert(&mut &b"d"[..]).unwrap();
+        tree.update().unwrap();
+        assert_ne!(tree.root_hash_array().unwrap(), before);
+        assert_eq!(tree.root_hash_bytes().unwrap(), &tree.root_hash().unwrap().into_bytes()[..]);
+
+        let sha512 = HashTree::new(1).hasher(DigestHasher::<sha2::Sha512>::new()).from_data(&mut &b"abc"[..]).unwrap();
+        assert_eq!(sha512.root_hash_bytes().unwrap().len(), 64);
+        assert!(sha512.root_hash_array().is_none());
+    }
+
+    #[test]
+    fn root_hash_encodings() {
+        use crate::{Encoding, RootHash};
+
+        // RFC 4648 test vectors
+        let vectors = [
+            ("", "", "", ""),
+            ("f", "Zg==", "Zg", "MY"),
+            ("fo", "Zm8=", "Zm8", "MZXQ"),
+            ("foo", "Zm9v", "Zm9v", "MZXW6"),
+            ("foob", "Zm9vYg==", "Zm9vYg", "MZXW6YQ"),
+            ("fooba", "Zm9vYmE=", "Zm9vYmE", "MZXW6YTB"),
+            ("foobar", "Zm9vYmFy", "Zm9vYmFy", "MZXW6YTBOI"),
+        ];
+        for (input, base64, base64url, base32) in vectors.iter() {
+            let root = RootHash::new(input.as_bytes().to_vec());
+            assert_eq!(root.encode(Encoding::Base64), *base64);
+            assert_eq!(root.encode(Encoding::Base64Url), *base64url);
+            assert_eq!(root.encode(Encoding::Base32), *base32);
+        }
+
+        let root = RootHash::new(vec![0xfb, 0xff, 0x0a]);
+        assert_eq!(root.encode(Encoding::Base64), "+/8K");
+        assert_eq!(root.encode(Encoding::Base64Url), "-_8K");
+        assert_eq!(root.encode(Encoding::Hex), "fbff0a");
+        assert_eq!(format!("{:X}", root), "FBFF0A");
+        assert_eq!(format!("{:x}", root), root.to_string());
+    }
+
+    #[test]
+    fn structural_equality() {
+        let data: Vec<u8> = (0..64).collect();
+        let tree = HashTree::new(4).from_data(&mut data.as_slice()).unwrap();
+        let copy = HashTree::new(4).from_data(&mut data.as_slice()).unwrap();
+        assert!(tree.eq_structure(&copy) && tree.same_commitment(&copy));
+
+        let quad = HashTree::new(4).arity(4).from_data(&mut data.as_slice()).unwrap();
+        assert!(!tree.eq_structure(&quad) && !tree.same_commitment(&quad));
+
+        let leaves: Vec<Vec<u8>> = (0..3u8).map(|i| vec![i; 32]).collect();
+        let a = HashTree::new(4).from_leaf_hashes(leaves.clone()).unwrap();
+        let b = HashTree::new(8).from_leaf_hashes(leaves).unwrap();
+        assert_eq!(a, b);
+        assert!(!a.eq_structure(&b));
+        assert!(HashTree::new(4).eq_structure(&HashTree::new(4)));
+    }
+
+    #[test]
+    fn trees_and_roots_as_keys() {
+        use std::collections::{HashMap, HashSet};
+
+        let trees: Vec<HashTree> = [&b"abc"[..], b"abd", b"abc", b""]
+            .iter()
+            .map(|data| HashTree::new(1).from_data(&mut &data[..]).unwrap())
+            .collect();
+        // Trees hash and compare by their root alone; the throttle and thread
+        // pool they may share are interior mutability that never affects it
+        #[allow(clippy::mutable_key_type)]
+        let unique: HashSet<&HashTree> = trees.iter().collect();
+        assert_eq!(unique.len(), 3);
+
+        let mut names = HashMap::new();
+        for (i, tree) in trees.iter().enumerate() {
+            if let Some(root) = tree.root_hash() {
+                names.entry(root).or_insert(i);
+            }
+        }
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[&trees[2].root_hash().unwrap()], 0);
+    }
+
+    #[test]
+    fn default_and_with_capacity() {
+        let tree = HashTree::default();
+        assert!(tree.is_empty());
+        assert_eq!(tree.block_size(), 4096);
+
+        for blocks in 0..40 {
+            let data = vec![7u8; blocks * 2];
+            let tree = HashTree::with_capacity(2, blocks).from_data(&mut data.as_slice()).unwrap();
+            assert!(HashTree::with_capacity(2, blocks).store().capacity() >= tree.num_nodes());
+            assert_eq!(tree, HashTree::new(2).from_data(&mut data.as_slice()).unwrap());
+        }
+    }
+
+    #[test]
+    fn from_blocks_matches_from_data() {
+        let data: Vec<u8> = (0..=255).collect();
+        for block_size in [1, 3, 64, 300] {
+            let tree = HashTree::new(block_size).from_blocks(data.chunks(block_size)).unwrap();
+            assert!(tree.eq_structure(&HashTree::new(block_size).from_data(&mut data.as_slice()).unwrap()));
+        }
+        let empty = HashTree::new(4).from_blocks(std::iter::empty::<&[u8]>()).unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn metrics_count_blocks_and_nodes() {
+        use crate::{CounterMetrics, Phase};
+        use std::sync::Arc;
+
+        let metrics = Arc::new(CounterMetrics::new());
+        let mut tree = HashTree::new(2).metrics(metrics.clone()).from_data(&mut &b"abcde"[..]).unwrap();
+        assert_eq!((metrics.bytes(), metrics.blocks(), metrics.nodes()), (5, 3, 7));
+
+        tree.insert(&mut &b"fgh"[..]).unwrap();
+        tree.update().unwrap();
+        assert_eq!((metrics.bytes(), metrics.blocks()), (8, 5));
+        assert_eq!(metrics.nodes(), 7 + tree.num_nodes() as u64);
+        assert!(metrics.elapsed(Phase::Leaves) > std::time::Duration::ZERO);
+    }
+
+    #[test]
+    fn shared_tree_concurrent_readers() {
+        use crate::SharedHashTree;
+        use sha2::{Digest, Sha256};
+
+        let shared = SharedHashTree::new(HashTree::new(1).from_data(&mut &b"a"[..]).unwrap());
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..200 {
+                        let (root, proof) = shared.proof_with_root(0).unwrap();
+                        assert!(proof.verify(root.as_bytes(), &Sha256::digest(b"a")));
+                    }
+                })
+            })
+            .collect();
+        for _ in 0..50 {
+            shared.append(&mut &b"xy"[..]).unwrap();
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(shared.num_blocks(), 101);
+        assert_eq!(shared.read().version(), 50);
+    }
+
+    #[test]
+    fn concurrent_builder_out_of_order() {
+        let data: Vec<u8> = (0..200).collect();
+        let builder = HashTree::new(7).arity(3).concurrent(data.chunks(7).count());
+        std::thread::scope(|scope| {
+            for worker in 0..4 {
+                let (builder, data) = (&builder, &data);
+                scope.spawn(move || {
+                    for (index, block) in data.chunks(7).enumerate().rev().filter(|(i, _)| i % 4 == worker) {
+                        builder.submit(index, block).unwrap();
+                    }
+                });
+            }
+        });
+        assert!(builder.is_complete());
+        assert!(builder.submit(0, b"again").is_err());
+        assert!(builder.submit(99, b"").is_err());
+        let tree = builder.finish().unwrap();
+        assert!(tree.eq_structure(&HashTree::new(7).arity(3).from_data(&mut data.as_slice()).unwrap()));
+
+        let incomplete = HashTree::new(1).concurrent(2);
+        incomplete.submit(1, b"b").unwrap();
+        assert_eq!(incomplete.missing(), 1);
+        assert!(incomplete.finish().is_err());
+    }
+
+    #[test]
+    fn hmac_keyed_leaves() {
+        use crate::{HmacHasher, PairHasher};
+
+        // RFC 4231, test case 2
+        let hasher = HmacHasher::<sha2::Sha256>::new(b"Jefe");
+        assert_eq!(
+            hex::encode(hasher.hash_leaf(b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert!(!format!("{:?}", hasher).contains("Jefe"));
+
+        let tree = HashTree::new(1).hasher(hasher.clone()).from_data(&mut &b"abc"[..]).unwrap();
+        let proof = tree.proof(2).unwrap();
+        let root = tree.root_hash().unwrap().into_bytes();
+        assert!(proof.verify_with(&hasher, &root, &hasher.hash_leaf(b"c")));
+        let other = HmacHasher::<sha2::Sha256>::new(b"Jeff");
+        assert!(!proof.verify_with(&other, &root, &other.hash_leaf(b"c")));
+
+        let mut bytes = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+        assert!(HashTree::new(0).hasher(other).read_from(bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn salted_leaves() {
+        use crate::{PairHasher, SaltedHasher, Sha256Hasher};
+        use sha2::{Digest, Sha256};
+
+        let tree = HashTree::new(2).salt(b"salt").from_data(&mut &b"abcde"[..]).unwrap();
+        let plain = HashTree::new(2).from_leaf_hashes(
+            [&b"ab"[..], b"cd", b"e"].iter().map(|block| Sha256::digest([&b"salt"[..], block].concat()).to_vec()),
+        );
+        assert!(tree.eq_structure(&plain.unwrap()));
+
+        let hasher = SaltedHasher::new(Sha256Hasher::new(), b"salt");
+        let root = tree.root_hash().unwrap().into_bytes();
+        assert!(tree.proof(2).unwrap().verify_with(&hasher, &root, &hasher.hash_leaf(b"e")));
+        assert_eq!(tree.get_hasher().hash_leaf(b"e"), hasher.hash_leaf(b"e"));
+    }
+
+    #[cfg(feature = "blake2")]
+    #[test]
+    fn blake2b_keyed_personalized() {
+        use crate::{Blake2bHasher, PairHasher};
+
+        // RFC 7693, appendix A
+        assert_eq!(
+            hex::encode(Blake2bHasher::new(64).hash_leaf(b"abc")),
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+             7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+        );
+
+        let hasher = Blake2bHasher::new(32).key(b"key").personal(b"persona");
+        let tree = HashTree::new(1).hasher(hasher.clone()).from_data(&mut &b"abc"[..]).unwrap();
+        let root = tree.root_hash().unwrap().into_bytes();
+        assert!(tree.proof(1).unwrap().verify_with(&hasher, &root, &hasher.hash_leaf(b"b")));
+
+        let other = HashTree::new(1).hasher(Blake2bHasher::new(32).key(b"key").personal(b"other"));
+        assert_ne!(other.from_data(&mut &b"abc"[..]).unwrap(), tree);
+        assert!(!format!("{:?}", hasher).contains(&hex::encode(b"key")));
+    }
+
+    #[test]
+    fn constant_time_verification() {
+        let tree = HashTree::new(1).from_data(&mut &b"abc"[..]).unwrap();
+        let root = tree.root_hash().unwrap().into_bytes();
+        let proof = tree.proof(0).unwrap();
+        let leaf = tree.store()[0].clone();
+        assert!(proof.verify(&root, &leaf));
+        assert!(!proof.verify(&root[..31], &leaf));
+        let mut flipped = root.clone();
+        flipped[31] ^= 1;
+        assert!(!proof.verify(&flipped, &leaf));
+
+        assert!(HashTree::new(1).same_commitment(&HashTree::new(2)));
+        assert!(!HashTree::new(1).same_commitment(&tree));
+        assert!(!tree.same_commitment(&HashTree::new(1)));
+    }
+
+    #[test]
+    fn length_prefixed_leaves() {
+        let framed = |data: &[u8]| HashTree::new(4).length_prefixed().from_data(&mut &data[..]).unwrap();
+        assert_ne!(framed(b"abcdab"), framed(b"abcdab\0\0"));
+        assert_ne!(framed(b"ab"), HashTree::new(4).from_data(&mut &b"ab"[..]).unwrap());
+
+        let salted = HashTree::new(4).salt(b"s").length_prefixed().from_data(&mut &b"abcdef"[..]).unwrap();
+        let hasher = salted.get_hasher();
+        let root = salted.root_hash().unwrap().into_bytes();
+        assert!(salted.proof(1).unwrap().verify_with(hasher, &root, &hasher.hash_leaf(b"ef")));
+        assert!(!salted.proof(1).unwrap().verify_with(hasher, &root, &hasher.hash_leaf(b"ef\0\0")));
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn arbitrary_trees_and_proofs() {
+        use crate::fuzz::ProvenBlock;
+        use crate::{PairHasher, Proof, Sha256Hasher};
+        use arbitrary::{Arbitrary, Unstructured};
+
+        let input: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        let mut u = Unstructured::new(&input);
+        for _ in 0..8 {
+            let tree = HashTree::arbitrary(&mut u).unwrap();
+            assert_eq!(tree.root_hash().is_some(), tree.num_blocks() > 0);
+
+            let proof = Proof::arbitrary(&mut u).unwrap();
+            let arity = proof.levels.first().map_or(2, |level| level.siblings.len() + 1);
+            let siblings = proof.levels.iter().flat_map(|level| level.siblings.clone()).collect();
+            assert_eq!(Proof::from_siblings(proof.leaf_index, arity, siblings).as_ref(), Some(&proof));
+
+            let block = ProvenBlock::arbitrary(&mut u).unwrap();
+            let root = block.tree.root_hash().unwrap().into_bytes();
+            assert!(block.proof.verify(&root, &Sha256Hasher::new().hash_leaf(&block.data)));
+        }
+    }
+
+    #[test]
+    fn logical_and_padded_leaves() {
+        use crate::Padding;
+
+        let mut tree = HashTree::new(1).from_data(&mut &b"abc"[..]).unwrap();
+        assert_eq!((tree.logical_leaves(), tree.padded_leaves(), tree.num_nodes()), (3, 4, 7));
+
+        // Appending to an odd leaf level replaces the padding copy instead of following it
+        tree.insert(&mut &b"d"[..]).unwrap();
+        tree.update().unwrap();
+        assert_eq!((tree.num_blocks(), tree.padded_leaves(), tree.num_nodes()), (4, 4, 7));
+        assert!(tree.eq_structure(&HashTree::new(1).from_data(&mut &b"abcd"[..]).unwrap()));
+
+        let single = HashTree::new(1).from_data(&mut &b"a"[..]).unwrap();
+        assert_eq!((single.logical_leaves(), single.padded_leaves()), (1, 2));
+        assert_eq!(HashTree::new(1).padded_leaves(), 0);
+
+        // Only duplicating pads a single leaf, to the arity
+        for (padding, single_padded) in
+            [(Padding::Duplicate, 4), (Padding::Zero, 1), (Padding::Promote, 1), (Padding::DuplicateUnlessSingle, 1)]
+        {
+            let build = |data: &[u8]| HashTree::new(1).arity(4).padding(padding).from_slice(data).unwrap();
+            assert_eq!(build(b"a").padded_leaves(), single_padded, "{:?}", padding);
+            assert_eq!((build(b"abc").padded_leaves(), build(b"abcde").padded_leaves()), (4, 8), "{:?}", padding);
+            assert_eq!(build(b"").padded_leaves(), 0, "{:?}", padding);
+        }
+    }
+
+    #[test]
+    fn slice_and_vec_constructors() {
+        let data = b"abcdefghij";
+        let expected = HashTree::new(3).from_data(&mut &data[..]).unwrap();
+        assert!(HashTree::new(3).from_slice(data).unwrap().eq_structure(&expected));
+        assert!(HashTree::new(3).from_vec(data.to_vec()).unwrap().eq_structure(&expected));
+        assert!(HashTree::new(3).from_data(&data[..]).unwrap().eq_structure(&expected));
+        assert!(HashTree::new(0).from_slice(data).unwrap().is_empty());
+
+        // A reader passed by reference can be used again afterwards
+        use std::io::Read;
+        let mut reader = &data[..];
+        HashTree::new(4).from_data(&mut (&mut reader).take(4)).unwrap();
+        assert_eq!(reader, &data[4..]);
+    }
+
+    #[test]
+    fn conversions() {
+        use std::convert::TryFrom;
+
+        let dir = temp_dir("conversions");
+        let path = dir.join("data");
+        fs::write(&path, vec![7u8; 10000]).unwrap();
+        let expected = HashTree::default().from_file(&path).unwrap();
+        assert!(HashTree::try_from(path.as_path()).unwrap().eq_structure(&expected));
+        assert!(HashTree::try_from(fs::File::open(&path).unwrap()).unwrap().eq_structure(&expected));
+        assert!(HashTree::from(&fs::read(&path).unwrap()[..]).eq_structure(&expected));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(HashTree::try_from(path.as_path()).is_err());
+    }
+
+    #[test]
+    fn build_events() {
+        use crate::BuildEvent;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Debug, PartialEq)]
+        enum Event {
+            Leaf(usize, Vec<u8>),
+            Level(usize),
+            Root(Vec<u8>),
+        }
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let mut tree = HashTree::new(1)
+            .observer(move |event: &BuildEvent<'_>| {
+                sink.lock().unwrap().push(match *event {
+                    BuildEvent::LeafHashed { index, hash } => Event::Leaf(index, hash.to_vec()),
+                    BuildEvent::LevelCompleted { level } => Event::Level(level),
+                    BuildEvent::RootComputed { hash } => Event::Root(hash.to_vec()),
+                })
+            })
+            .from_data(&mut &b"abc"[..])
+            .unwrap();
+        let leaf = |index| tree.node_hash(index);
+        let root = tree.root_hash().unwrap().into_bytes();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                Event::Leaf(0, leaf(0)),
+                Event::Leaf(1, leaf(1)),
+                Event::Leaf(2, leaf(2)),
+                Event::Level(0),
+                Event::Level(1),
+                Event::Level(2),
+                Event::Root(root),
+            ]
+        );
+
+        events.lock().unwrap().clear();
+        tree.insert(&mut &b"d"[..]).unwrap();
+        tree.update().unwrap();
+        let events = events.lock().unwrap();
+        assert_eq!(events[0], Event::Leaf(3, tree.node_hash(3)));
+        assert_eq!(events.last(), Some(&Event::Root(tree.root_hash().unwrap().into_bytes())));
+    }
+
+    #[test]
+    fn dedup_index() {
+        let mut tree = HashTree::new(4).dedup_index().from_data(&mut &b"aaaabbbbaaaacc"[..]).unwrap();
+        let a = tree.node_hash(0);
+        assert_eq!(tree.blocks_with_hash(&a), &[0, 2]);
+        assert_eq!(tree.duplicate_blocks(), vec![(&a[..], &[0, 2][..])]);
+
+        // Appending keeps the index, the padding copy of the last leaf is not indexed
+        tree.insert(&mut &b"bbbb"[..]).unwrap();
+        tree.update().unwrap();
+        let b = tree.node_hash(1);
+        assert_eq!(tree.blocks_with_hash(&b), &[1, 4]);
+        assert_eq!(tree.duplicate_blocks().len(), 2);
+
+        let mut bytes = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+        let read = HashTree::new(0).dedup_index().read_from(&bytes[..]).unwrap();
+        assert_eq!(read.duplicate_blocks(), tree.duplicate_blocks());
+
+        assert!(HashTree::new(4).from_data(&mut &b"aaaaaaaa"[..]).unwrap().duplicate_blocks().is_empty());
+    }
+
+    #[test]
+    fn block_store() {
+        use crate::{BlockStore, FileBlockStore, MemoryBlockStore};
+        use std::sync::Arc;
+
+        let store = Arc::new(MemoryBlockStore::new());
+        let mut tree = HashTree::new(2).block_store(store.clone()).from_data(&mut &b"ababcd"[..]).unwrap();
+        assert_eq!(store.len(), 2);
+        tree.insert(&mut &b"e"[..]).unwrap();
+        tree.update().unwrap();
+        assert_eq!(tree.get_block(3).unwrap(), b"e");
+        let mut data = Vec::new();
+        tree.write_blocks(&mut data).unwrap();
+        assert_eq!(data, b"ababcde");
+        assert!(tree.get_block(4).is_err());
+
+        // Tampered blocks are rejected
+        let tampered = MemoryBlockStore::new();
+        tampered.put(&tree.node_hash(2), b"xy").unwrap();
+        let tree = HashTree::new(2).block_store(tampered).from_leaf_hashes(tree.leaf_hashes()).unwrap();
+        assert_eq!(tree.get_block(2).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(tree.get_block(0).unwrap_err().kind(), std::io::ErrorKind::NotFound);
+        assert!(HashTree::new(2).from_data(&mut &b"ab"[..]).unwrap().get_block(0).is_err());
+
+        let dir = temp_dir("block_store");
+        let files = FileBlockStore::open(&dir).unwrap();
+        let tree = HashTree::new(2).block_store(files).from_data(&mut &b"abab"[..]).unwrap();
+        let reopened = FileBlockStore::open(&dir).unwrap();
+        assert!(reopened.contains(&tree.node_hash(0)).unwrap());
+        assert_eq!(tree.get_block(1).unwrap(), b"ab");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn web3_json_proof() {
+        use crate::{PairHasher, Sha256Hasher};
+
+        let tree = HashTree::new(1).arity(3).from_data(&mut &b"abcde"[..]).unwrap();
+        let proof = tree.proof(4).unwrap();
+        let leaf = Sha256Hasher::new().hash_leaf(b"e");
+        let root = tree.root_hash().unwrap().into_bytes();
+        let siblings: Vec<String> = proof
+            .levels
+            .iter()
+            .flat_map(|level| level.siblings.iter().map(|sibling| format!("\"0x{}\"", hex::encode(sibling))))
+            .collect();
+        assert_eq!(siblings.len(), 4);
+        assert_eq!(
+            proof.to_web3_json(&leaf, &root),
+            format!(
+                "{{\"root\":\"0x{}\",\"leaf\":\"0x{}\",\"index\":4,\"arity\":3,\"proof\":[{}]}}",
+                hex::encode(&root),
+                hex::encode(&leaf),
+                siblings.join(",")
+            )
+        );
+    }
+
+    #[test]
+    fn allowlist() {
+        use crate::{Allowlist, LeafEncoding, Sha256Hasher};
+
+        let entries = ["carol", "alice", "bob", "alice"];
+        let list = Allowlist::builder().dedup(true).build(entries.iter()).unwrap();
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.index_of("bob"), Some(2));
+        let root = list.root_hash().unwrap().into_bytes();
+        assert!(list.proof("alice").unwrap().verify(&root, &list.leaf_hash("alice").unwrap()));
+
+        // Sorted lists have the same root whatever the input order
+        let sorted = |entries: &[&str]| Allowlist::builder().sorted(true).build(entries).unwrap().root_hash();
+        assert_eq!(sorted(&entries), sorted(&["bob", "alice", "carol"]));
+
+        let keyed = Allowlist::builder()
+            .tree(HashTree::new(0).arity(4).hasher(Sha256Hasher::new()))
+            .encoding(LeafEncoding::Hex)
+            .build(["0x01", " 02\n", "03"])
+            .unwrap();
+        assert_eq!(keyed.entries(), &[vec![1], vec![2], vec![3]]);
+        assert_eq!(keyed.index_of("0x02"), Some(1));
+        assert_eq!(keyed.tree().get_arity(), 4);
+        assert!(Allowlist::builder().encoding(LeafEncoding::Hex).build(["0xzz"]).is_err());
+    }
+
+    #[test]
+    fn batch_proof_verification() {
+        use crate::proof::{verify_proofs, verify_proofs_with};
+        use crate::{PairHasher, Sha256Hasher};
+
+        let data: Vec<u8> = (0..100).collect();
+        for arity in 2..5 {
+            let tree = HashTree::new(1).arity(arity).from_data(&data[..]).unwrap();
+            let root = tree.root_hash().unwrap().into_bytes();
+            let leaves: Vec<Vec<u8>> = data.iter().map(|byte| Sha256Hasher::new().hash_leaf(&[*byte])).collect();
+            let proofs: Vec<_> = (0..data.len()).map(|index| tree.proof(index).unwrap()).collect();
+
+            let mut batch: Vec<_> = (0..data.len()).map(|index| (&leaves[index][..], index as u64, &proofs[index])).collect();
+            assert!(verify_proofs_with(tree.get_hasher(), &root, &batch).iter().all(|valid| *valid));
+
+            // A wrong leaf, a mismatched index and a proof for another block
+            // fail without affecting the proofs around them
+            batch[10].0 = &leaves[11];
+            batch[20].1 = 21;
+            batch[30].2 = &proofs[31];
+            let results = verify_proofs(&root, &batch);
+            let expected: Vec<bool> = (0..data.len()).map(|index| ![10, 20, 30].contains(&index)).collect();
+            assert_eq!(results, expected);
+        }
+
+        // A proof relabelled with another leaf index does not prove its
+        // block at that index
+        let tree = HashTree::new(1).from_data(&b"abcdefgh"[..]).unwrap();
+        let root = tree.root_hash().unwrap().into_bytes();
+        let mut forged = tree.proof(1).unwrap();
+        forged.leaf_index = 0;
+        let leaf = Sha256Hasher::new().hash_leaf(b"b");
+        assert_eq!(verify_proofs(&root, &[(&leaf, 0, &forged)]), vec![false]);
+    }
+
+    #[test]
+    fn compact_proof_encoding() {
+        use crate::Proof;
+
+        let data: Vec<u8> = (0..=255).collect();
+        for arity in [2, 3, 4, 7] {
+            let tree = HashTree::new(1).arity(arity).from_data(&data[..]).unwrap();
+            for index in [0, 1, 130, 255] {
+                let proof = tree.proof(index).unwrap();
+                let bytes = proof.to_compact_bytes().unwrap();
+                assert!(bytes.len() < proof.levels.len() * (arity - 1) * 32 + 12);
+                assert_eq!(Proof::from_compact_bytes(&bytes).unwrap(), proof);
+                assert!(Proof::from_compact_bytes(&bytes[..bytes.len() - 1]).is_err());
+                assert!(Proof::from_compact_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+            }
+        }
+
+        let mut uneven = HashTree::new(1).from_data(&mut &b"abcd"[..]).unwrap().proof(0).unwrap();
+        uneven.levels[1].siblings[0].pop();
+        assert!(uneven.to_compact_bytes().is_err());
+        assert!(Proof::from_compact_bytes(&[0, 1, 32, 1]).is_err());
+        assert!(Proof::from_compact_bytes(&[0xff; 12]).is_err());
+        // An arity of 2^62 with empty hashes must not allocate its siblings
+        let huge_arity = [&[0][..], &[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x40], &[0, 1], &[0; 8]].concat();
+        assert_eq!(huge_arity.len(), 20);
+        assert!(Proof::from_compact_bytes(&huge_arity).is_err());
+    }
+
+    #[test]
+    fn wire_proof_encoding() {
+        use crate::{wire, Preset};
+
+        let data: Vec<u8> = (0..=255).collect();
+        let trees = [
+            HashTree::new(1).arity(3).from_data(&data[..]).unwrap(),
+            // Promoted nodes leave empty siblings in the proofs
+            HashTree::new(1).preset(Preset::Rfc6962).from_slice(&data[..7]).unwrap(),
+        ];
+        for tree in trees {
+            for index in [0, 6] {
+                let proof = tree.proof(index).unwrap();
+                let bytes = wire::encode_proof(&proof).unwrap();
+                assert_eq!(wire::decode_proof(&bytes).unwrap(), proof);
+                assert!(wire::decode_proof(&bytes[..bytes.len() - 1]).is_err());
+                assert!(wire::decode_multiproof(&bytes).is_err());
+            }
+            let elements = tree.multiproof_stream(&[1, 2, 6]).unwrap();
+            let bytes = wire::encode_multiproof(&elements).unwrap();
+            assert_eq!(wire::decode_multiproof(&bytes).unwrap(), elements);
+            assert!(wire::decode_multiproof(&[&[wire::VERSION + 1], &bytes[1..]].concat()).is_err());
+        }
+
+        let mut log = MerkleLog::new();
+        for entry in 0..10u8 {
+            log.append(&[entry]).unwrap();
+        }
+        let proof = log.consistency_proof(3, 10).unwrap();
+        let bytes = wire::encode_consistency_proof(&proof).unwrap();
+        assert_eq!(bytes.len(), 2 + 8 + 8 + 4 + 32 * proof.path.len());
+        assert_eq!(wire::decode_consistency_proof(&bytes).unwrap(), proof);
+        assert!(wire::decode_consistency_proof(&[bytes.as_slice(), &[0]].concat()).is_err());
+    }
+
+    #[test]
+    fn streaming_multiproof() {
+        use crate::{ProofElement, StreamingVerifier};
+
+        let data: Vec<u8> = (0..50).collect();
+        for arity in 2..5 {
+            let tree = HashTree::new(1).arity(arity).from_data(&data[..]).unwrap();
+            let root = tree.root_hash_bytes().unwrap();
+            let verify = |elements: &[ProofElement]| {
+                let mut verifier = StreamingVerifier::new(tree.get_hasher(), arity);
+                elements.iter().all(|element| verifier.push(element).is_ok()) && verifier.finish(root)
+            };
+            for indices in [vec![0], vec![49], vec![3, 4, 5], vec![48, 1, 17, 1], (0..50).collect()] {
+                let elements = tree.multiproof_stream(&indices).unwrap();
+                let leaves = elements.iter().filter(|element| matches!(element, ProofElement::Leaf { .. })).count();
+                let mut unique = indices.clone();
+                unique.sort_unstable();
+                unique.dedup();
+                assert_eq!(leaves, unique.len());
+                assert!(verify(&elements));
+
+                // Tampering with any element or truncating the stream fails
+                let mut tampered = elements.clone();
+                match &mut tampered[0] {
+                    ProofElement::Leaf { hash, .. } | ProofElement::Node { hash, .. } => hash[0] ^= 1,
+                }
+                assert!(!verify(&tampered));
+                assert!(!verify(&elements[..elements.len() - 1]));
+            }
+
+            // A leaf claiming another index is rejected
+            let mut moved = tree.multiproof_stream(&[7]).unwrap();
+            for element in &mut moved {
+                if let ProofElement::Leaf { index, .. } = element {
+                    *index = 8;
+                }
+            }
+            assert!(!verify(&moved));
+        }
+        let tree = HashTree::new(1).from_data(&mut &b"ab"[..]).unwrap();
+        assert!(tree.multiproof_stream(&[2]).is_none());
+        assert!(HashTree::new(1).multiproof_stream(&[]).is_none());
+    }
+
+    #[test]
+    fn verified_slices() {
+        use std::io::Cursor;
+
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        for (block_size, arity) in [(16, 2), (10, 3), (100, 4)] {
+            let tree = HashTree::new(block_size).arity(arity).from_data(&data[..]).unwrap();
+            let root = tree.root_hash().unwrap().into_bytes();
+            let receiver = HashTree::new(block_size).arity(arity);
+            for range in [0..1, 5..37, 990..1000, 999..5000, 0..1000] {
+                let encoded = tree.encode_slice(Cursor::new(&data), range.clone()).unwrap();
+                let slice = receiver.decode_slice(&encoded, &root, data.len() as u64, range.clone()).unwrap();
+                let end = (range.end as usize).min(data.len());
+                assert_eq!(slice, &data[range.start as usize..end]);
+
+                let mut tampered = encoded.clone();
+                let last = tampered.len() - 1;
+                tampered[last] ^= 1;
+                assert!(receiver.decode_slice(&tampered, &root, data.len() as u64, range.clone()).is_err());
+                assert!(receiver.decode_slice(&encoded[..last], &root, data.len() as u64, range.clone()).is_err());
+            }
+            // An encoding for another range does not cover this one
+            let encoded = tree.encode_slice(Cursor::new(&data), 0..10).unwrap();
+            assert!(receiver.decode_slice(&encoded, &root, data.len() as u64, 500..510).is_err());
+        }
+        let tree = HashTree::new(16).from_data(&data[..]).unwrap();
+        assert!(tree.encode_slice(Cursor::new(&data), 1000..1001).is_err());
+        assert!(tree.encode_slice(Cursor::new(&data[..900]), 0..1).is_err());
+    }
+
+    #[test]
+    fn combined_and_outboard_layouts() {
+        use crate::{outboard_path, OutboardReader};
+        use std::io::Cursor;
+
+        let data: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 256) as u8).collect();
+        let dir = temp_dir("layouts");
+        let path = dir.join("data.bin");
+        fs::write(&path, &data).unwrap();
+
+        // Outboard: the data file stays untouched next to its tree
+        let tree = HashTree::new(512).arity(4).from_file(&path).unwrap();
+        tree.write_outboard(&path).unwrap();
+        assert!(outboard_path(&path).ends_with("data.bin.htree"));
+        let root = tree.root_hash().unwrap().into_bytes();
+        let loaded = HashTree::new(0).read_outboard(&path, &root).unwrap();
+        assert!(loaded.eq_structure(&tree));
+        assert!(HashTree::new(0).read_outboard(&path, &[0u8; 32]).is_err());
+        let mut reader = OutboardReader::new(loaded, fs::File::open(&path).unwrap());
+        let mut copy = Vec::new();
+        reader.copy_to(&mut copy).unwrap();
+        assert_eq!(copy, data);
+        assert!(reader.read_block(tree.num_blocks() as usize).is_err());
+
+        // Combined: a single artifact holding both
+        let mut combined = Vec::new();
+        tree.write_combined(Cursor::new(&data), &mut combined).unwrap();
+        let mut output = Vec::new();
+        let read = HashTree::new(0).read_combined(&combined[..], &root, &mut output).unwrap();
+        assert_eq!(output, data);
+        assert!(read.eq_structure(&tree));
+
+        // A tampered node below the root is caught even though the root itself
+        // still matches
+        let mut tampered = combined.clone();
+        tampered[4 + 48 + 32] ^= 1;
+        assert!(HashTree::new(0).read_combined(&tampered[..], &root, Vec::new()).is_err());
+        let mut outboard = fs::read(outboard_path(&path)).unwrap();
+        // Root, then the four nodes of the level above the leaves
+        outboard[48 + 32 * 5] ^= 1;
+        fs::write(outboard_path(&path), &outboard).unwrap();
+        assert!(HashTree::new(0).read_outboard(&path, &root).is_err());
+
+        let last = combined.len() - 1;
+        combined[last] ^= 1;
+        assert!(HashTree::new(0).read_combined(&combined[..], &root, Vec::new()).is_err());
+        assert!(HashTree::new(0).read_combined(&combined[..], &[0u8; 32], Vec::new()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_and_resume() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 253) as u8).collect();
+        let expected = HashTree::new(64).arity(3).from_slice(&data).unwrap();
+
+        // Hash a few blocks at a time, restarting from a checkpoint each time
+        let mut saved = HashTree::new(64).arity(3).resumable().checkpoint().unwrap();
+        loop {
+            let mut builder = HashTree::new(64).arity(3).resume(&saved).unwrap();
+            let offset = builder.offset() as usize;
+            let hashed = builder.hash_blocks(&data[offset..], 7).unwrap();
+            saved = builder.checkpoint().unwrap();
+            if hashed < 7 {
+                break;
+            }
+        }
+        let builder = HashTree::new(64).arity(3).resume(&saved).unwrap();
+        assert_eq!(builder.offset(), data.len() as u64);
+        assert!(builder.finish().unwrap().eq_structure(&expected));
+
+        // The input cannot continue after a partial block
+        let mut builder = HashTree::new(64).arity(3).resume(&saved).unwrap();
+        assert!(builder.hash_blocks(&b"more"[..], 1).is_err());
+
+        assert!(HashTree::new(32).arity(3).resume(&saved).is_err());
+        assert!(HashTree::new(64).resume(&saved).is_err());
+        assert!(HashTree::new(64).arity(3).resume(&saved[..saved.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn extend_growing_file() {
+        use std::io::Cursor;
+
+        let mut data: Vec<u8> = Vec::new();
+        let mut tree = HashTree::new(16).arity(3).dedup_index().from_slice(&data).unwrap();
+        for step in 0..40u32 {
+            let appended: Vec<u8> = (0..step * 3 % 37).map(|i| (i + step) as u8).collect();
+            data.extend_from_slice(&appended);
+            assert_eq!(tree.extend_from_reader(Cursor::new(&data)).unwrap(), appended.len() as u64);
+            assert!(tree.eq_structure(&HashTree::new(16).arity(3).from_slice(&data).unwrap()));
+            assert_eq!(tree.data_len(), Some(data.len() as u64));
+            assert!(tree.duplicate_blocks().iter().all(|(_, indices)| indices.iter().all(|&i| i < tree.num_blocks())));
+        }
+
+        assert!(tree.extend_from_reader(Cursor::new(&data[..data.len() - 1])).is_err());
+        let mut hashes = HashTree::new(16).from_leaf_hashes(vec![vec![0; 32]]).unwrap();
+        assert!(hashes.extend_from_reader(Cursor::new(&data)).is_err());
+
+        // Inserting after a partial block leaves the data length unknown
+        let mut inserted = HashTree::new(16).from_slice(b"partial").unwrap();
+        inserted.insert(&mut &b"more"[..]).unwrap();
+        inserted.update().unwrap();
+        assert_eq!(inserted.data_len(), None);
+        let mut aligned = HashTree::new(4).from_slice(b"full").unwrap();
+        aligned.insert(&mut &b"more!"[..]).unwrap();
+        aligned.update().unwrap();
+        assert_eq!(aligned.data_len(), Some(9));
+    }
+
+    #[test]
+    fn tail_follower() {
+        let data: Vec<u8> = (0..200u32).map(|i| (i * 7) as u8).collect();
+        let mut follower = HashTree::new(16).arity(3).follow();
+        let mut offset = 0;
+        for step in 0..30 {
+            let end = (offset + step % 11).min(data.len());
+            let root = follower.push(&data[offset..end]).unwrap();
+            let complete = end / 16 * 16;
+            assert_eq!(root.is_some(), complete > offset / 16 * 16);
+            assert!(follower.tree().eq_structure(&HashTree::new(16).arity(3).from_slice(&data[..complete]).unwrap()));
+            assert_eq!(follower.tail(), &data[complete..end]);
+            offset = end;
+        }
+        assert_eq!(follower.len(), offset as u64);
+        assert_eq!(follower.finish().unwrap(), HashTree::new(16).arity(3).from_slice(&data[..offset]).unwrap());
+        assert_eq!(HashTree::new(16).follow().finish().unwrap(), HashTree::new(16).from_slice(b"").unwrap());
+        assert!(HashTree::new(16).follow().poll().is_err());
+
+        use std::io::Write;
+
+        let path = temp_dir("tail_follower").join("log");
+        fs::write(&path, &data[..40]).unwrap();
+        let mut follower = HashTree::new(16).follow_file(&path).unwrap();
+        assert_eq!(follower.tree().num_blocks(), 2);
+        assert_eq!(follower.poll().unwrap(), None);
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(&data[40..]).unwrap();
+        let root = follower.poll().unwrap().unwrap();
+        assert_eq!(root, HashTree::new(16).from_slice(&data[..192]).unwrap().root_hash().unwrap());
+        assert_eq!(follower.finish().unwrap(), HashTree::new(16).from_slice(&data).unwrap());
+    }
+
+    #[test]
+    fn forest() {
+        use crate::{Forest, PairHasher, Sha256Hasher};
+
+        let hasher = Sha256Hasher::new();
+        let files: Vec<Vec<u8>> = (0..7u8).map(|i| vec![i; 3 + i as usize * 5]).collect();
+        let trees: Vec<HashTree> = files.iter().map(|data| HashTree::new(4).from_slice(data).unwrap()).collect();
+        let forest = Forest::from_trees(HashTree::new(0).arity(3), &trees).unwrap();
+        assert_eq!(forest.len(), 7);
+        let root = forest.root_hash().unwrap().into_bytes();
+
+        for (index, (tree, data)) in trees.iter().zip(&files).enumerate() {
+            for (block, chunk) in data.chunks(4).enumerate() {
+                let proof = forest.proof(index, tree, block).unwrap();
+                assert_eq!(proof.tree_index(), index as u64);
+                assert!(proof.verify(&root, &hasher.hash_leaf(chunk)));
+                assert!(!proof.verify(&root, &hasher.hash_leaf(b"????")));
+            }
+            assert!(forest.proof(index, tree, data.len().div_ceil(4)).is_none());
+        }
+        assert!(forest.proof(0, &trees[1], 0).is_none());
+        assert!(forest.proof(7, &trees[0], 0).is_none());
+
+        // A tree root cannot stand in for a block
+        let proof = forest.proof(2, &trees[2], 0).unwrap();
+        let forged = crate::ForestProof { tree_proof: forest.root_proof(2).unwrap(), ..proof.clone() };
+        assert!(!crate::ForestProof { block_proof: crate::Proof { leaf_index: 0, levels: Vec::new() }, ..forged }
+            .verify(&root, &forest.roots()[2]));
+
+        let same = Forest::from_roots(HashTree::new(0).arity(3), forest.roots().to_vec()).unwrap();
+        assert_eq!(same.root_hash(), forest.root_hash());
+        assert!(Forest::from_trees(HashTree::new(0), &[HashTree::new(4)]).is_err());
+        assert!(Forest::from_roots(HashTree::new(0), Vec::<Vec<u8>>::new()).unwrap().root_hash().is_none());
+    }
+
+    #[test]
+    fn variable_size_leaves() {
+        use crate::{LeafIndex, OutboardReader};
+        use std::io::Cursor;
+
+        let data: Vec<u8> = (0..500u32).map(|i| (i * 31 % 251) as u8).collect();
+        let mut chunks = Vec::new();
+        let mut rest = &data[..];
+        for size in [17, 3, 64, 1, 40, 99].iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let (chunk, tail) = rest.split_at((*size as usize).min(rest.len()));
+            chunks.push(chunk);
+            rest = tail;
+        }
+        let tree = HashTree::new(8).arity(3).record_lengths().from_blocks(chunks.iter()).unwrap();
+        let lengths: Vec<u64> = chunks.iter().map(|chunk| chunk.len() as u64).collect();
+        assert_eq!(tree.leaf_lengths(), Some(lengths));
+        assert_eq!(tree.data_len(), Some(500));
+        let mut offset = 0;
+        for (index, chunk) in chunks.iter().enumerate() {
+            let range = tree.leaf_range(index).unwrap();
+            assert_eq!(&data[range.start as usize..range.end as usize], *chunk);
+            assert_eq!(tree.leaf_at_offset(offset), Some(LeafIndex::from(index)));
+            assert_eq!(tree.leaf_at_offset(range.end - 1), Some(LeafIndex::from(index)));
+            offset = range.end;
+        }
+        assert_eq!(tree.leaf_at_offset(500), None);
+        assert_eq!(tree.leaf_range(chunks.len()), None);
+
+        // Lengths survive serialization and locate verified slices
+        let mut bytes = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+        let copy = HashTree::new(0).read_from(bytes.as_slice()).unwrap();
+        assert_eq!(copy.leaf_lengths(), tree.leaf_lengths());
+        let root = tree.root_hash().unwrap().into_bytes();
+        for range in [0..1, 15..25, 100..101, 150..480, 499..600] {
+            let encoded = tree.encode_slice(Cursor::new(&data), range.clone()).unwrap();
+            let slice = copy.decode_slice(&encoded, &root, 500, range.clone()).unwrap();
+            assert_eq!(slice, &data[range.start as usize..(range.end as usize).min(500)]);
+        }
+        assert!(copy.decode_slice(&tree.encode_slice(Cursor::new(&data), 0..10).unwrap(), &root, 499, 0..10).is_err());
+
+        let mut combined = Vec::new();
+        tree.write_combined(Cursor::new(&data), &mut combined).unwrap();
+        let mut output = Vec::new();
+        HashTree::new(0).read_combined(&combined[..], &root, &mut output).unwrap();
+        assert_eq!(output, data);
+        let mut reader = OutboardReader::new(copy, Cursor::new(&data));
+        assert_eq!(reader.read_block(2).unwrap(), chunks[2]);
+
+        // Splitting, merging and inserting keep the lengths
+        let (left, right) = tree.split_at(4);
+        assert_eq!(left.leaf_range(3), tree.leaf_range(3));
+        assert_eq!(right.leaf_range(0), Some(0..chunks[4].len() as u64));
+        let mut merged = left.merge(right).unwrap();
+        assert_eq!(merged.leaf_lengths(), tree.leaf_lengths());
+        merged.insert(&mut &b"abc"[..]).unwrap();
+        merged.update().unwrap();
+        assert_eq!(merged.leaf_range(chunks.len()), Some(500..503));
+        assert_eq!(merged.data_len(), Some(503));
+
+        // Fixed-size blocks are located without recorded lengths
+        let fixed = HashTree::new(16).from_slice(&data).unwrap();
+        assert_eq!(fixed.leaf_lengths(), None);
+        assert_eq!(fixed.leaf_range(31), Some(496..500));
+        assert_eq!(fixed.leaf_at_offset(33), Some(LeafIndex(2)));
+        let hashes = HashTree::new(16).record_lengths().from_leaf_hashes(fixed.leaf_hashes()).unwrap();
+        assert_eq!((hashes.leaf_lengths(), hashes.leaf_range(0)), (None, None));
+    }
+
+    #[test]
+    fn leaf_metadata() {
+        use crate::{MetadataMode, PairHasher, Sha256Hasher};
+
+        let records: Vec<(String, Vec<u8>)> = (0..9).map(|i| (format!("file-{}.log", i), vec![i as u8; i * 3])).collect();
+        let stored = HashTree::new(0).arity(3).from_annotated(records.clone(), MetadataMode::Stored).unwrap();
+        let plain = HashTree::new(0).arity(3).from_blocks(records.iter().map(|(_, data)| data)).unwrap();
+        assert_eq!(stored.tree(), &plain);
+        assert_eq!(stored.len(), 9);
+
+        let hashed = HashTree::new(0).arity(3).from_annotated(records.clone(), MetadataMode::Hashed).unwrap();
+        assert_ne!(hashed.root_hash(), stored.root_hash());
+        for tree in [&stored, &hashed] {
+            let root = tree.root_hash().unwrap().into_bytes();
+            for (index, (name, data)) in records.iter().enumerate() {
+                let proof = tree.proof(index).unwrap();
+                assert_eq!(proof.metadata, name.as_bytes());
+                assert_eq!(tree.metadata(index), Some(name.as_bytes()));
+                assert!(proof.verify(&root, data));
+                assert!(!proof.verify(&root, b"other"));
+
+                // Only hashed metadata is bound to the root
+                let renamed = crate::AnnotatedProof { metadata: b"renamed".to_vec(), ..proof };
+                assert_eq!(renamed.verify(&root, data), tree.mode() == MetadataMode::Stored);
+            }
+            assert!(tree.proof(9).is_none());
+        }
+
+        // Moving bytes between metadata and data changes the leaf
+        let hasher = Sha256Hasher::new();
+        assert_ne!(MetadataMode::Hashed.leaf_hash(&hasher, b"ab", b"c"), MetadataMode::Hashed.leaf_hash(&hasher, b"a", b"bc"));
+        assert_eq!(MetadataMode::Stored.leaf_hash(&hasher, b"ab", b"c"), hasher.hash_leaf(b"c"));
+    }
+
+    #[test]
+    fn typed_indices() {
+        use crate::{LeafIndex, NodeIndex};
+        use std::convert::TryFrom;
+
+        let a = HashTree::new(1).arity(3).from_slice(b"abcdefg").unwrap();
+        let b = HashTree::new(1).arity(3).from_slice(b"abXdefgh").unwrap();
+        for leaf in (0..a.num_blocks()).map(LeafIndex) {
+            assert_eq!(a.proof(leaf), a.proof(leaf.0 as usize));
+            assert_eq!(a.proof(leaf).unwrap().leaf(), leaf);
+            assert_eq!(a.leaf_of(NodeIndex::try_from(leaf).unwrap()), Some(leaf));
+            assert_eq!(a.leaf_hash(leaf), a.node_at(NodeIndex::try_from(leaf).unwrap()));
+        }
+        assert_eq!(a.leaf_hash(7), None);
+        // The padding after the last leaf and interior nodes are no leaves
+        assert_eq!(a.leaf_of(NodeIndex(7)), None);
+        assert!(a.node_at(NodeIndex(7)).is_some());
+        assert_eq!(a.node_at(NodeIndex(a.num_nodes() - 1)), a.root());
+        assert_eq!(a.node_at(NodeIndex(a.num_nodes())), None);
+        assert_eq!(a.changed_leaves(&b), [LeafIndex(2), LeafIndex(7)]);
+    }
+
+    #[test]
+    fn node_iteration() {
+        use crate::{NodeStore, Padding};
+
+        let tree = HashTree::new(1).arity(3).from_slice(b"abcdefg").unwrap();
+        let nodes: Vec<(usize, usize, &[u8])> = tree.iter().collect();
+        assert_eq!(tree.iter().len(), tree.num_nodes());
+        assert!(nodes.iter().map(|node| node.2.to_vec()).eq(tree.store().iter_nodes()));
+        let widths: Vec<usize> = (0..3).map(|level| nodes.iter().filter(|node| node.0 == level).count()).collect();
+        assert_eq!(widths, [9, 3, 1]);
+        assert_eq!(nodes.last().unwrap(), &(2, 0, tree.root_hash_bytes().unwrap()));
+        assert_eq!(HashTree::new(1).iter().next(), None);
+
+        let promoted = HashTree::new(1).padding(Padding::Promote).from_slice(b"abc").unwrap();
+        assert_eq!((&promoted).into_iter().filter(|node| node.2.is_empty()).count(), 1);
+    }
+
+    #[test]
+    fn node_navigation() {
+        use crate::{LeafIndex, NodeIndex};
+
+        let tree = HashTree::new(1).arity(3).from_slice(b"abcdefg").unwrap();
+        // Proofs are the siblings of the path to the root
+        for leaf in (0..tree.num_blocks()).map(LeafIndex) {
+            let path = tree.path_to_root(leaf);
+            assert_eq!(path.last().copied(), Some(NodeIndex(tree.num_nodes() - 1)));
+            let proof = tree.proof(leaf).unwrap();
+            for (node, level) in path.iter().zip(&proof.levels) {
+                let siblings: Vec<Vec<u8>> = tree.siblings(*node).into_iter().map(|node| tree.node_at(node).unwrap()).collect();
+                assert_eq!(siblings, level.siblings);
+            }
+        }
+        for node in (0..tree.num_nodes()).map(NodeIndex) {
+            for child in tree.children(node) {
+                assert_eq!(tree.parent(child), Some(node));
+            }
+        }
+        assert_eq!(tree.children(NodeIndex(12)), [NodeIndex(9), NodeIndex(10), NodeIndex(11)]);
+        // Padding copies have no children of their own
+        let padded = HashTree::new(1).arity(3).from_slice(b"abcd").unwrap();
+        assert_eq!(padded.position(NodeIndex(8)), Some((1, 2)));
+        assert!(padded.children(NodeIndex(8)).is_empty());
+        assert_eq!(padded.parent(NodeIndex(8)), Some(NodeIndex(9)));
+        assert_eq!(tree.sibling(NodeIndex(0)), None);
+        assert!(tree.path_to_root(7).is_empty());
+        assert_eq!(tree.position(NodeIndex(13)), None);
+    }
+
+    #[test]
+    fn build_against_expected_root() {
+        let data: Vec<u8> = (0..100).collect();
+        let expected = HashTree::new(8).arity(4).from_slice(&data).unwrap();
+        let root = expected.root_hash_bytes().unwrap();
+        let tree = HashTree::new(8).arity(4).from_data_expecting(data.as_slice(), root).unwrap();
+        assert_eq!(tree, expected);
+        assert!(HashTree::new(8).from_data_expecting(data.as_slice(), root).is_err());
+        assert!(HashTree::new(8).arity(4).from_data_expecting(&[][..], root).is_err());
+
+        let mut corrupt = data.clone();
+        corrupt[50] ^= 1;
+        corrupt.push(0);
+        let error = HashTree::new(8).arity(4).from_data_expecting_tree(corrupt.as_slice(), &expected).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "blocks 6..7 do not match the expected tree");
+        assert!(HashTree::new(8).arity(4).from_data_expecting_tree(data.as_slice(), &expected).is_ok());
+    }
+
+    #[test]
+    fn fixed_tree_matches_hash_tree() {
+        use crate::fixed::{hash_leaf, FixedProof, FixedTree};
+
+        let data: Vec<u8> = (0..13).collect();
+        for len in 1..=data.len() {
+            let tree = HashTree::new(1).from_slice(&data[..len]).unwrap();
+            let fixed = FixedTree::<13>::from_leaf_hashes(data[..len].iter().map(|byte| hash_leaf(&[*byte]))).unwrap();
+            assert_eq!(fixed.depth(), tree.levels().len() - 1);
+            let root = fixed.root().unwrap();
+            assert_eq!(&root[..], tree.root_hash_bytes().unwrap());
+            for (index, byte) in data[..len].iter().enumerate() {
+                let proof: FixedProof<4> = fixed.proof(index).unwrap();
+                let expected = tree.proof(index).unwrap();
+                assert!(proof.siblings().iter().map(|sibling| &sibling[..]).eq(expected.levels.iter().map(|level| &level.siblings[0][..])));
+                assert!(proof.verify(&root, &hash_leaf(&[*byte])));
+                assert!(!proof.verify(&root, &hash_leaf(b"x")));
+            }
+        }
+
+        let mut full = FixedTree::<2>::new();
+        assert_eq!(full.root(), None);
+        full.push_block(b"a").unwrap();
+        full.push_block(b"b").unwrap();
+        assert_eq!(full.push_block(b"c"), Err(hash_leaf(b"c")));
+        assert!(full.proof::<0>(0).is_none());
+        assert!(FixedProof::<1>::from_siblings(2, &[[0; 32]]).is_none());
+        assert!(FixedProof::<1>::from_siblings(0, &[[0; 32]; 2]).is_none());
+    }
+
+    #[test]
+    fn preprocessed_leaves() {
+        use crate::{MultiSha256Hasher, PairHasher, PreprocessingHasher};
+        use std::borrow::Cow;
+
+        fn unix_newlines(block: &[u8]) -> Cow<'_, [u8]> {
+            if block.contains(&b'\r') {
+                Cow::Owned(block.iter().copied().filter(|&b| b != b'\r').collect())
+            } else {
+                Cow::Borrowed(block)
+            }
+        }
+        let unix = HashTree::new(0).preprocess(unix_newlines).from_blocks([&b"a\n"[..], b"bc\n"]).unwrap();
+        let windows = HashTree::new(0).preprocess(unix_newlines).from_blocks([&b"a\r\n"[..], b"bc\r\n"]).unwrap();
+        assert_eq!(unix, windows);
+        assert_eq!(unix, HashTree::new(0).from_blocks([&b"a\n"[..], b"bc\n"]).unwrap());
+        let leaf = windows.get_hasher().hash_leaf(b"bc\r\n");
+        assert!(windows.proof(1).unwrap().verify_with(windows.get_hasher(), windows.root_hash_bytes().unwrap(), &leaf));
+
+        // Batches still reach a multi-buffer hasher, transformed
+        let hasher = PreprocessingHasher::new(MultiSha256Hasher::new(), unix_newlines);
+        assert_eq!(hasher.leaf_batch(4096), MultiSha256Hasher::new().leaf_batch(4096));
+        let blocks = [&b"x\r\n"[..], b"y", b"z\r"];
+        let expected: Vec<Vec<u8>> = blocks.iter().map(|block| hasher.hash_leaf(block)).collect();
+        assert_eq!(hasher.hash_leaves(&blocks), expected);
+        assert_eq!(expected[2], MultiSha256Hasher::new().hash_leaf(b"z"));
+    }
+
+    #[test]
+    fn tree_writer() {
+        use std::io::Write;
+
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let expected = HashTree::new(64).record_lengths().from_slice(&data).unwrap();
+        for sizes in [&[1000][..], &[1, 63, 64, 65, 127, 680], &[7; 143], &[0, 128, 0, 872]] {
+            let mut writer = HashTree::new(64).record_lengths().writer();
+            let mut rest = &data[..];
+            for &size in sizes {
+                let (chunk, tail) = rest.split_at(size.min(rest.len()));
+                writer.write_all(chunk).unwrap();
+                writer.flush().unwrap();
+                rest = tail;
+            }
+            writer.write_all(rest).unwrap();
+            assert_eq!(writer.num_blocks(), 15);
+            let tree = writer.finish().unwrap();
+            assert!(tree.eq_structure(&expected));
+            assert_eq!(tree.data_len(), Some(1000));
+            assert_eq!(tree.leaf_range(15), expected.leaf_range(15));
+        }
+
+        let mut copied = HashTree::new(64).writer();
+        std::io::copy(&mut &data[..640], &mut copied).unwrap();
+        assert_eq!(copied.finish().unwrap(), HashTree::new(64).from_slice(&data[..640]).unwrap());
+        assert!(HashTree::new(64).writer().finish().unwrap().is_empty());
+        assert_eq!(HashTree::new(0).writer().write(b"a").unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn sharded_build() {
+        use crate::{NodeIndex, Padding, Shard};
+
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        for (arity, shard_blocks) in [(2, 4usize), (2, 1), (3, 9), (4, 16)] {
+            for padding in [Padding::Duplicate, Padding::Zero, Padding::Promote] {
+                let params = || HashTree::new(10).arity(arity).padding(padding).record_lengths();
+                for len in [0usize, 5, 10, 40, 41, 395, 1000] {
+                    let segment = shard_blocks * 10;
+                    let shards: Vec<Shard> = (0..len.max(1).div_ceil(segment))
+                        .rev()
+                        .map(|index| {
+                            let chunk = &data[(index * segment).min(len)..((index + 1) * segment).min(len)];
+                            let shard = params().hash_shard(shard_blocks as u64, index as u64, chunk).unwrap();
+                            assert_eq!(shard.offset(), (index * segment) as u64);
+                            Shard::from_bytes(&shard.to_bytes()).unwrap()
+                        })
+                        .collect();
+                    let tree = params().from_shards(shards).unwrap();
+                    let expected = params().from_slice(&data[..len]).unwrap();
+                    assert!(tree.eq_structure(&expected), "arity {} padding {:?} len {}", arity, padding, len);
+                    assert_eq!(tree.data_len(), Some(len as u64));
+                }
+            }
+        }
+
+        let tree = HashTree::new(10).from_slice(&data[..80]).unwrap();
+        let shard = HashTree::new(10).hash_shard(4, 1, &data[40..80]).unwrap();
+        assert!(shard.is_complete());
+        assert_eq!(shard.root(), tree.node_at(NodeIndex(13)).as_deref());
+
+        let shard = |index: u64, chunk: &[u8]| HashTree::new(10).hash_shard(4, index, chunk).unwrap();
+        let kind = |shards: Vec<Shard>| HashTree::new(10).from_shards(shards).unwrap_err().kind();
+        assert_eq!(kind(vec![shard(0, &data[..40]), shard(2, &data[80..90])]), std::io::ErrorKind::InvalidInput);
+        assert_eq!(kind(vec![shard(0, &data[..35]), shard(1, &data[40..50])]), std::io::ErrorKind::InvalidInput);
+        let other = HashTree::new(10).arity(4).hash_shard(4, 1, &data[40..50]).unwrap();
+        assert_eq!(kind(vec![shard(0, &data[..40]), other]), std::io::ErrorKind::InvalidInput);
+        assert!(HashTree::new(10).hash_shard(3, 0, &data[..]).is_err());
+        let bytes = shard(0, &data[..40]).to_bytes();
+        assert!(Shard::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn replicated_ops() {
+        use crate::TreeOp;
+        use std::io::Cursor;
+
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let mut primary = HashTree::new(64).record_lengths().record_ops().from_slice(&data[..300]).unwrap();
+        let mut replica = HashTree::new(64).record_lengths().from_slice(&data[..300]).unwrap();
+        assert!(primary.ops().is_empty());
+
+        primary.extend_from_reader(Cursor::new(&data[..700])).unwrap();
+        primary.insert(&mut &data[..10]).unwrap();
+        primary.update().unwrap();
+        primary.truncate(6).unwrap();
+        primary = primary.merge(HashTree::new(64).record_lengths().from_slice(&data[..100]).unwrap()).unwrap();
+        let ops = primary.take_ops();
+        assert!(matches!(ops[0], TreeOp::Replace { index: 4, .. }));
+        assert!(matches!(ops[1], TreeOp::Append { .. }));
+        assert_eq!(ops[2], TreeOp::Truncate { num_blocks: 6, data_len: Some(384) });
+        assert_eq!(ops.len(), 4);
+        assert!(primary.ops().is_empty());
+
+        let stream: Vec<u8> = ops.iter().flat_map(TreeOp::to_bytes).collect();
+        let mut stream = &stream[..];
+        let mut received = Vec::new();
+        while let Some(op) = TreeOp::read_from(&mut stream).unwrap() {
+            received.push(op);
+        }
+        assert_eq!(received, ops);
+        replica.apply_ops(received).unwrap();
+        assert!(replica.eq_structure(&primary));
+        assert_eq!(replica.leaf_lengths(), primary.leaf_lengths());
+        assert_eq!(replica.data_len(), Some(484));
+
+        let bytes = ops[1].to_bytes();
+        assert_eq!(TreeOp::from_bytes(&bytes).unwrap(), ops[1]);
+        assert!(TreeOp::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let beyond = TreeOp::Truncate { num_blocks: 100, data_len: None };
+        assert_eq!(replica.apply_ops(Some(beyond)).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        assert!(replica.truncate(100).is_err());
+    }
+
+    #[test]
+    fn root_change_callbacks() {
+        use std::sync::{Arc, Mutex};
+
+        let roots = Arc::new(Mutex::new(Vec::new()));
+        let calls = Arc::new(Mutex::new(0));
+        let (sink, counter) = (roots.clone(), calls.clone());
+        let mut tree = HashTree::new(4)
+            .on_root_changed(move |root, version| sink.lock().unwrap().push((version, root)))
+            .on_root_changed(move |_, _| *counter.lock().unwrap() += 1)
+            .from_slice(b"abcdefgh")
+            .unwrap();
+        tree.insert(&mut &b"ijkl"[..]).unwrap();
+        tree.update().unwrap();
+        let after_update = tree.root_hash().unwrap();
+        tree.truncate(1).unwrap();
+        let after_truncate = tree.root_hash().unwrap();
+        tree.truncate(0).unwrap();
+        assert_eq!(*roots.lock().unwrap(), [(1, after_update), (2, after_truncate)]);
+        assert_eq!(*calls.lock().unwrap(), 2);
+
+        let mut follower = HashTree::new(4).on_root_changed(move |root, version| roots.lock().unwrap().push((version, root))).follow();
+        follower.push(b"abcdefg").unwrap();
+        follower.push(b"h").unwrap();
+        let tree = follower.finish().unwrap();
+        assert_eq!(tree.version(), 2);
+    }
+
+    #[test]
+    fn double_buffered_reads() {
+        use crate::{Chunker, CounterMetrics, FixedChunker};
+        use std::sync::Arc;
+
+        // Batches of 8 blocks, ending on and off a batch boundary
+        let data: Vec<u8> = (0..=255).cycle().take(100).collect();
+        for len in [0, 1, 7, 8, 9, 16, 17, 100] {
+            let metrics = Arc::new(CounterMetrics::new());
+            let tree = HashTree::new(4).metrics(metrics.clone()).double_buffered().from_data(&data[..len]).unwrap();
+            assert!(tree.eq_structure(&HashTree::new(4).from_slice(&data[..len]).unwrap()));
+            assert_eq!(tree.data_len(), Some(len as u64));
+            assert_eq!((metrics.bytes(), metrics.blocks()), (len as u64, len.div_ceil(4) as u64));
+        }
+
+        struct Failing(FixedChunker<&'static [u8]>, usize);
+        impl Chunker for Failing {
+            fn next_chunk(&mut self, buf: &mut Vec<u8>) -> std::io::Result<bool> {
+                self.1 += 1;
+                if self.1 == 12 {
+                    return Err(std::io::Error::other("disk failed"));
+                }
+                self.0.next_chunk(buf)
+            }
+        }
+        let chunker = Failing(FixedChunker::new(&[0u8; 100][..], 4), 0);
+        let error = HashTree::new(4).double_buffered().from_chunker(chunker).unwrap_err();
+        assert_eq!(error.to_string(), "disk failed");
+    }
+
+    #[test]
+    fn leaf_labels() {
+        let mut tree = HashTree::new(1).arity(3).from_slice(b"abcdefg").unwrap().with_labels(["first"]);
+        assert!(tree.set_label(6, "last"));
+        assert!(!tree.set_label(7, "none"));
+        let labels: Vec<Option<&str>> = tree.leaves().map(|leaf| leaf.label).collect();
+        assert_eq!(labels, [Some("first"), None, None, None, None, None, Some("last")]);
+        assert!(tree.leaves().map(|leaf| leaf.hash).eq(tree.leaf_hashes()));
+
+        // Every node is drawn once, padding without children
+        let text = tree.to_string();
+        assert_eq!(text.lines().count(), tree.num_nodes());
+        assert!(text.contains("── first "));
+        assert!(text.contains("── B1 "));
+        assert!(text.contains("── last "));
+        assert_eq!(text.matches("(padding)").count(), 2);
+        assert_eq!(HashTree::new(1).to_string(), "(empty)\n");
+
+        let dot = tree.to_dot();
+        assert_eq!(dot.matches(" [label=").count(), tree.num_nodes());
+        assert_eq!(dot.matches(" -> ").count(), 12);
+        assert_eq!(dot.matches("style=dashed").count(), 2);
+
+        // Labels follow their leaves when the tree is split or cut back
+        let (left, right) = tree.split_at(4);
+        assert_eq!((left.label(0), right.label(2)), (Some("first"), Some("last")));
+        tree.set_label(3, "quote \" and \\");
+        assert!(tree.to_dot().contains("quote \\\" and \\\\"));
+    }
+
+    #[test]
+    fn mermaid_export() {
+        let mut tree = HashTree::new(1).arity(3).from_slice(b"abcdefg").unwrap().with_labels(["first"]);
+        let root = hex::encode(tree.root_hash_bytes().unwrap());
+
+        // Every node is drawn once, padding without children
+        let mermaid = tree.to_mermaid(usize::MAX, 8);
+        assert_eq!(mermaid.matches("[\"").count(), tree.num_nodes());
+        assert_eq!(mermaid.matches(" --> ").count(), 12);
+        assert_eq!(mermaid.matches(":::padding").count(), 2);
+        assert!(mermaid.contains("first"));
+        assert!(mermaid.contains(&root[..8]) && !mermaid.contains(&root[..9]));
+        // A depth of 1 stops at the children of the root
+        assert_eq!(tree.to_mermaid(1, 8).matches(" --> ").count(), 3);
+        assert_eq!(tree.to_mermaid(1, 8).matches("[\"").count(), 4);
+        assert!(tree.to_mermaid(usize::MAX, 64).contains(&root));
+
+        tree.set_label(3, "quote \" and \\");
+        assert!(tree.to_mermaid(usize::MAX, 8).contains("quote #quot; and \\"));
+    }
+
+    #[test]
+    fn size_estimates() {
+        use crate::estimate;
+
+        for arity in 2..6 {
+            for len in [0u64, 1, 7, 8, 9, 100, 1000] {
+                let tree = HashTree::new(4).arity(arity);
+                let estimate = tree.estimate(len);
+                let built = tree.from_slice(&vec![1; len as usize]).unwrap();
+                assert_eq!(estimate.leaves, built.num_blocks());
+                assert_eq!(estimate.nodes, built.num_nodes() as u64);
+                let mut bytes = Vec::new();
+                built.write_to(&mut bytes).unwrap();
+                assert_eq!(estimate.serialized_bytes, bytes.len() as u64);
+                if let Some(proof) = built.proof(0) {
+                    assert_eq!(estimate.depth, proof.levels.len());
+                    let siblings: usize = proof.levels.iter().flat_map(|level| &level.siblings).map(Vec::len).sum();
+                    assert_eq!(estimate.proof_bytes, siblings as u64);
+                }
+            }
+        }
+        assert_eq!(estimate(100, 0).leaves, 0);
+        #[cfg(feature = "blake2")]
+        assert_eq!(HashTree::new(4).hasher(crate::Blake2bHasher::new(64)).estimate(8).hash_len, 64);
+    }
+
+    #[test]
+    fn sorted_set() {
+        use crate::{PairHasher, Sha256Hasher};
+
+        let hasher = Sha256Hasher::new();
+        let items: Vec<String> = (0..20).map(|i| format!("item-{}", i)).collect();
+        let set = HashTree::new(0).arity(3).from_set(&items).unwrap();
+        let reversed = HashTree::new(0).arity(3).from_set(items.iter().rev()).unwrap();
+        assert_eq!(set.root_hash(), reversed.root_hash());
+        assert!(set.leaves().windows(2).all(|pair| pair[0] < pair[1]));
+        let root = set.root_hash().unwrap().into_bytes();
+
+        for item in &items {
+            assert!(set.contains(item));
+            assert!(set.proof(item).unwrap().verify(&root, &set.leaf_hash(item)));
+            assert!(set.non_membership_proof(item).is_none());
+        }
+        for absent in (20..60).map(|i| format!("item-{}", i)) {
+            assert!(!set.contains(&absent));
+            let proof = set.non_membership_proof(&absent).unwrap();
+            let hash = hasher.hash_leaf(absent.as_bytes());
+            assert!(proof.verify(&root, 20, &hash));
+            // The proof does not cover a present item
+            assert!(!proof.verify(&root, 20, &set.leaves()[0]));
+        }
+
+        // Neighbors that are not adjacent do not prove absence
+        let hash = [0x80u8; 32];
+        let proof = set.non_membership_proof_for_hash(&hash).unwrap();
+        let (left, right) = (proof.left.clone().unwrap(), proof.right.clone().unwrap());
+        let index = right.1.leaf_index as usize;
+        let gap = crate::NonMembershipProof { left: Some(left), right: Some((set.leaves()[index + 1].clone(), set.tree().proof(index + 1).unwrap())) };
+        assert!(!gap.verify(&root, 20, &hash));
+        // Padding copies of the last leaf do not pass for leaves past the end
+        let last = crate::NonMembershipProof { left: Some((set.leaves()[19].clone(), set.tree().proof(19).unwrap())), right: None };
+        assert!(last.verify(&root, 20, &[0xff; 32]));
+        assert!(!last.verify(&root, 21, &[0xff; 32]));
+
+        // Inserting and removing keeps the root canonical
+        let mut growing = HashTree::new(0).arity(3).from_set(&items[..5]).unwrap();
+        for item in items[5..].iter().rev() {
+            assert!(growing.insert(item).unwrap());
+        }
+        assert!(!growing.insert(&items[0]).unwrap());
+        assert!(growing.tree().eq_structure(set.tree()));
+        for item in &items[1..] {
+            assert!(growing.remove(item).unwrap());
+        }
+        assert!(!growing.remove(&items[1]).unwrap());
+        assert!(growing.tree().eq_structure(HashTree::new(0).arity(3).from_set(&items[..1]).unwrap().tree()));
+        assert!(growing.remove(&items[0]).unwrap());
+        assert!(growing.is_empty() && growing.root_hash().is_none());
+        assert!(growing.non_membership_proof("x").is_none());
+    }
+
+    #[test]
+    fn bloom_filter() {
+        use crate::{BloomFilter, PairHasher, Sha256Hasher};
+
+        let data: Vec<u8> = (0..4000u32).map(|i| (i * 7 % 256) as u8 ^ (i / 256) as u8).collect();
+        let tree = HashTree::new(4).from_slice(&data).unwrap();
+        let filter = tree.bloom_filter(0.01);
+        assert!(tree.leaf_hashes().all(|hash| filter.maybe_contains(&hash)));
+
+        let hasher = Sha256Hasher::new();
+        let false_positives = (0..10_000u32).filter(|i| filter.maybe_contains(&hasher.hash_leaf(&i.to_be_bytes()))).count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+
+        let bytes = filter.to_bytes();
+        assert_eq!(BloomFilter::from_bytes(&bytes).unwrap(), filter);
+        assert!(BloomFilter::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(BloomFilter::from_bytes(b"HTBF").is_err());
+
+        // Short hashes are spread by hashing them first
+        let mut small = BloomFilter::new(0, 0.5);
+        small.insert(b"ab");
+        assert!(small.maybe_contains(b"ab"));
+        assert!(!HashTree::new(4).bloom_filter(0.01).maybe_contains(&[0; 32]));
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        let data: Vec<u8> = (0..100u8).collect();
+        let expected = HashTree::new(4).from_slice(&data).unwrap();
+        let mut tree = HashTree::with_capacity(4, 10_000).dedup_index().from_slice(&data).unwrap();
+        assert!(tree.store().capacity() > tree.store().len());
+        tree.shrink_to_fit().unwrap();
+        assert_eq!(tree.store().capacity(), tree.store().len());
+        assert!(tree.store().iter().all(|hash| hash.capacity() == hash.len()));
+        assert_eq!(tree, expected);
+        assert_eq!(tree.proof(24), expected.proof(24));
+
+        #[cfg(feature = "mmap")]
+        {
+            use crate::{MmapStore, NodeStore};
+
+            let dir = temp_dir("shrink-mmap");
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("nodes");
+            let mut tree = HashTree::with_store(4, MmapStore::create(&path, 32).unwrap()).from_slice(&data).unwrap();
+            let before = fs::metadata(&path).unwrap().len();
+            tree.shrink_to_fit().unwrap();
+            assert!(fs::metadata(&path).unwrap().len() < before);
+            assert_eq!(tree.root_hash(), expected.root_hash());
+            drop(tree);
+            let store = MmapStore::open(&path).unwrap();
+            assert_eq!(store.len(), expected.num_nodes());
+            assert_eq!(store.iter_nodes().last(), expected.root_hash().map(|r| r.into_bytes()));
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn size_limits() {
+        use std::io::ErrorKind;
+
+        let data = vec![7u8; 1000];
+        let error = HashTree::new(1).max_leaves(999).from_data(data.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("999 leaves"));
+        assert!(HashTree::new(1).max_leaves(1000).from_data(data.as_slice()).is_ok());
+        assert!(HashTree::new(1).max_leaves(999).from_slice(&data).is_err());
+
+        // Rejected up front from the file size, before anything is hashed
+        let dir = temp_dir("size-limits");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data");
+        fs::write(&path, &data).unwrap();
+        let metrics = std::sync::Arc::new(crate::CounterMetrics::new());
+        let tree = HashTree::new(1).max_memory(1024).metrics(metrics.clone());
+        assert_eq!(tree.from_file(&path).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(metrics.blocks(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let budget = HashTree::new(100).estimate(1000).memory_bytes;
+        assert!(HashTree::new(100).max_memory(budget).from_data(data.as_slice()).is_ok());
+        assert!(HashTree::new(100).max_memory(budget - 1).from_data(data.as_slice()).is_err());
+
+        // Growing a tree is limited too
+        let mut tree = HashTree::new(1).max_leaves(4).from_slice(b"abc").unwrap();
+        assert!(tree.insert(&mut &b"d"[..]).is_ok());
+        assert!(tree.insert(&mut &b"e"[..]).is_err());
+        tree.update().unwrap();
+        assert_eq!(tree.num_blocks(), 4);
+    }
+
+    #[test]
+    fn u64_counts() {
+        use crate::io::to_usize;
+        use crate::{LeafIndex, NodeIndex, TreeOp};
+        use std::convert::TryFrom;
+
+        assert_eq!(to_usize(5).unwrap(), 5);
+        assert_eq!(to_usize(u64::MAX).is_ok(), usize::BITS == 64);
+
+        // A header claiming more nodes than the platform can index is
+        // rejected instead of being truncated
+        let tree = HashTree::new(1).from_slice(b"abc").unwrap();
+        let mut bytes = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+        bytes[40..48].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(HashTree::new(0).read_from(bytes.as_slice()).is_err());
+
+        // Leaf indices past what the platform can index, which would alias
+        // leaf 1 if truncated to 32 bits, are out of range
+        let wrapped = LeafIndex((1 << 32) + 1);
+        assert_eq!((tree.proof(wrapped), tree.leaf_hash(wrapped), tree.leaf_range(wrapped)), (None, None, None));
+        assert!(tree.path_to_root(wrapped).is_empty());
+        assert_eq!(NodeIndex::try_from(LeafIndex(u64::MAX)).is_ok(), usize::BITS == 64);
+        let mut copy = tree.clone();
+        assert!(copy.truncate(u64::MAX).is_err());
+        assert!(copy.apply_ops(Some(TreeOp::Truncate { num_blocks: (1 << 32) + 1, data_len: None })).is_err());
+        assert_eq!(copy, tree);
+        assert!(HashTree::new(1).open(u64::MAX).is_err());
+
+        // Proofs carry their leaf index as a u64 on every platform
+        let deep = crate::Proof::from_siblings((1 << 40) + 1, 2, vec![vec![7u8; 32]; 41]).unwrap();
+        assert_eq!(deep.leaf(), LeafIndex((1 << 40) + 1));
+        assert_eq!(crate::Proof::from_compact_bytes(&deep.to_compact_bytes().unwrap()).unwrap(), deep);
+    }
+
+    #[test]
+    fn auto_block_size() {
+        use crate::BlockSize;
+
+        assert_eq!(BlockSize::Auto.resolve(Some(0)), 1024);
+        assert_eq!(BlockSize::Auto.resolve(Some((1 << 26) + 1)), 2048);
+        assert_eq!(BlockSize::Auto.resolve(Some(1 << 50)), 1 << 24);
+        assert_eq!(BlockSize::from(100), BlockSize::Fixed(100));
+
+        let data = vec![3u8; 3 << 20];
+        let tree = HashTree::with_block_size(BlockSize::Auto);
+        assert_eq!(tree.estimate(data.len() as u64).leaves, 3072);
+        let tree = tree.from_slice(&data).unwrap();
+        assert_eq!(tree.block_size(), 1024);
+        assert_eq!(tree, HashTree::new(1024).from_slice(&data).unwrap());
+
+        // The chosen size is written with the tree
+        let mut bytes = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+        let read = HashTree::with_block_size(BlockSize::Auto).read_from(bytes.as_slice()).unwrap();
+        assert_eq!(read.block_size(), 1024);
+
+        let dir = temp_dir("auto-block-size");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data");
+        fs::File::create(&path).unwrap().set_len((64 << 20) + 1).unwrap();
+        let tree = HashTree::with_block_size(BlockSize::Auto).from_file(&path).unwrap();
+        assert_eq!(tree.block_size(), 2048);
+        assert_eq!(tree.num_blocks(), 32_769);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Streams of unknown length fall back to 64 KiB blocks
+        let tree = HashTree::with_block_size(BlockSize::Auto).from_data(&data[..]).unwrap();
+        assert_eq!(tree.block_size(), 64 * 1024);
+        assert_eq!(HashTree::with_block_size(4096).block_size(), 4096);
+    }
+
+    #[test]
+    fn zero_padding() {
+        use crate::{PairHasher, Padding, Sha256Hasher};
+
+        // The root of a BEP 52 piece tree over three blocks
+        let hasher = Sha256Hasher::new();
+        let data: Vec<u8> = (0..40u8).collect();
+        let leaves: Vec<Vec<u8>> = data.chunks(16).map(|block| hasher.hash_leaf(block)).collect();
+        let zero = vec![0u8; 32];
+        let expected = hasher.hash_pair(&hasher.hash_pair(&leaves[0], &leaves[1]), &hasher.hash_pair(&leaves[2], &zero));
+        let tree = HashTree::new(16).padding(Padding::Zero).from_slice(&data).unwrap();
+        assert_eq!(tree.root_hash().unwrap().as_bytes(), &expected[..]);
+        assert_eq!(tree.get_padding(), Padding::Zero);
+        let proof = tree.proof(2).unwrap();
+        assert_eq!(proof.levels[0].siblings[0], zero);
+        assert!(proof.verify(&expected, &leaves[2]));
+
+        // Padding above the leaves uses the zero hash of each level
+        let tree = HashTree::new(1).padding(Padding::Zero).from_slice(b"abcde").unwrap();
+        let mut padded: Vec<Vec<u8>> = b"abcde".iter().map(|b| hasher.hash_leaf(&[*b])).collect();
+        padded.resize(8, zero.clone());
+        assert_eq!(tree.root_hash(), HashTree::new(1).from_leaf_hashes(padded).unwrap().root_hash());
+        let four = HashTree::new(1).arity(4).padding(Padding::Zero).from_slice(b"abcde").unwrap();
+        let mut padded: Vec<Vec<u8>> = b"abcde".iter().map(|b| hasher.hash_leaf(&[*b])).collect();
+        padded.resize(16, zero.clone());
+        assert_eq!(four.root_hash(), HashTree::new(1).arity(4).from_leaf_hashes(padded).unwrap().root_hash());
+
+        // A single leaf is its own root
+        let single = HashTree::new(16).padding(Padding::Zero).from_slice(b"abc").unwrap();
+        assert_eq!(single.num_nodes(), 1);
+        assert_eq!(single.root_hash().unwrap().as_bytes(), &hasher.hash_leaf(b"abc")[..]);
+        assert!(single.proof(0).unwrap().levels.is_empty());
+        assert_eq!(single.estimate(3).nodes, 1);
+
+        // The padding survives serialization, growth and the other representations
+        let mut bytes = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+        let read = HashTree::new(0).read_from(bytes.as_slice()).unwrap();
+        assert_eq!(read.get_padding(), Padding::Zero);
+        assert_eq!(read.root_hash(), tree.root_hash());
+        let mut grown = tree.clone();
+        grown.insert(&mut &b"fghi"[..]).unwrap();
+        grown.update().unwrap();
+        assert_eq!(grown, HashTree::new(1).padding(Padding::Zero).from_slice(b"abcdefghi").unwrap());
+        let (left, right) = grown.split_at(5);
+        assert_eq!(left, tree);
+        assert_eq!(right.get_padding(), Padding::Zero);
+
+        let mut persistent = read.to_persistent();
+        assert_eq!(persistent.proof(4), tree.proof(4));
+        persistent.set_leaf(4, hasher.hash_leaf(b"X"));
+        assert_eq!(persistent.root_hash(), HashTree::new(1).padding(Padding::Zero).from_slice(b"abcdX").unwrap().root_hash());
+        let pruned = read.into_pruned(2);
+        let proof = pruned.proof(4, &mut std::io::Cursor::new(b"abcde")).unwrap();
+        assert_eq!(proof, tree.proof(4));
+    }
+
+    #[cfg(all(feature = "sparse", unix))]
+    #[test]
+    fn sparse_files() {
+        use std::convert::TryFrom;
+        use std::io::{Seek, SeekFrom, Write};
+
+        let dir = temp_dir("sparse-files");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("image");
+        let mut file = fs::File::create(&path).unwrap();
+        file.set_len(3 << 20).unwrap();
+        file.seek(SeekFrom::Start((1 << 20) + 100)).unwrap();
+        file.write_all(&[7u8; 10_000]).unwrap();
+        file.set_len((3 << 20) + 1234).unwrap();
+        drop(file);
+
+        let contents = fs::read(&path).unwrap();
+        let expected = HashTree::new(4096).from_slice(&contents).unwrap();
+        let tree = HashTree::new(4096).from_file(&path).unwrap();
+        assert_eq!(tree, expected);
+        assert_eq!(tree.data_len(), Some(contents.len() as u64));
+        let holes = crate::holes::data_region(&fs::File::open(&path).unwrap(), 0, contents.len() as u64).unwrap();
+        assert!(holes.start <= (1 << 20) + 100 && holes.end >= (1 << 20) + 10_100);
+
+        // Holes read as zeros whether or not they end on a block boundary
+        let odd = HashTree::new(3000).from_file(&path).unwrap();
+        assert_eq!(odd, HashTree::new(3000).from_slice(&contents).unwrap());
+        let mut file = fs::File::open(&path).unwrap();
+        file.seek(SeekFrom::Start(5000)).unwrap();
+        assert_eq!(HashTree::try_from(file).unwrap(), HashTree::default().from_slice(&contents[5000..]).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    #[test]
+    fn uring_reads() {
+        let dir = temp_dir("uring-reads");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data");
+        let data: Vec<u8> = (0..5_000_000u32).map(|i| (i * 31 % 251) as u8).collect();
+        fs::write(&path, &data).unwrap();
+
+        // Block sizes below, across and above the size of a single read
+        for block_size in [4096, 1000, 300_000] {
+            let tree = HashTree::new(block_size).from_file(&path).unwrap();
+            assert_eq!(tree, HashTree::new(block_size).from_slice(&data).unwrap());
+            assert_eq!(tree.data_len(), Some(data.len() as u64));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(all(feature = "direct", target_os = "linux"))]
+    #[test]
+    fn direct_io() {
+        use std::io::Read;
+
+        let dir = temp_dir("direct-io");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data");
+        let data: Vec<u8> = (0..3_000_001u32).map(|i| (i * 13 % 241) as u8).collect();
+        fs::write(&path, &data).unwrap();
+
+        for block_size in [4096, 1000] {
+            let tree = HashTree::new(block_size).direct_io().from_file(&path).unwrap();
+            assert_eq!(tree, HashTree::new(block_size).from_slice(&data).unwrap());
+        }
+
+        if let Some(file) = crate::direct::open_direct(&path).unwrap() {
+            let mut read = Vec::new();
+            crate::direct::DirectReader::new(file).read_to_end(&mut read).unwrap();
+            assert!(read == data);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hash_many_files() {
+        use std::collections::HashMap;
+
+        let dir = temp_dir("hash-many-files");
+        fs::create_dir_all(&dir).unwrap();
+        let mut paths: Vec<PathBuf> = (0..20u8).map(|i| dir.join(format!("file-{}", i))).collect();
+        for (i, path) in paths.iter().enumerate() {
+            fs::write(path, vec![i as u8; 100 + i * 37]).unwrap();
+        }
+        paths.push(dir.join("missing"));
+
+        let template = HashTree::new(16).arity(4);
+        let results: HashMap<PathBuf, _> = template.hash_files(paths.clone(), 4).into_iter().collect();
+        assert_eq!(results.len(), 21);
+        for path in &paths[..20] {
+            let expected = template.clone().from_file(path).unwrap();
+            assert_eq!(results[path].as_ref().unwrap(), &expected);
+            assert_eq!(results[path].as_ref().unwrap().get_arity(), 4);
+        }
+        assert!(results[&paths[20]].is_err());
+
+        let mut seen = 0;
+        template.hash_files_each(&paths[..5], 8, |_, tree| seen += tree.unwrap().num_blocks());
+        assert_eq!(seen, (0..5).map(|i: u64| (100 + i * 37).div_ceil(16)).sum::<u64>());
+        assert_eq!(template.hash_files(Vec::<PathBuf>::new(), 2).iter().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn multi_buffer_sha256() {
+        use crate::{MultiSha256Hasher, PairHasher, Sha256Hasher};
+        use sha2::{Digest, Sha256};
+
+        let hasher = MultiSha256Hasher::new();
+        for len in [0, 1, 55, 56, 63, 64, 65, 119, 120, 1000, 4096] {
+            let blocks: Vec<Vec<u8>> = (0..11u8).map(|i| (0..len).map(|j| (j * 7) as u8 ^ i).collect()).collect();
+            let refs: Vec<&[u8]> = blocks.iter().map(Vec::as_slice).collect();
+            let expected: Vec<Vec<u8>> = blocks.iter().map(|block| Sha256::digest(block).to_vec()).collect();
+            assert_eq!(hasher.hash_leaves(&refs), expected, "length {}", len);
+        }
+        #[cfg(target_arch = "x86_64")]
+        for len in [0, 55, 56, 64, 200] {
+            let blocks: Vec<Vec<u8>> = (0..8u8).map(|i| vec![i; len]).collect();
+            let expected: Vec<[u8; 32]> = blocks.iter().map(|block| Sha256::digest(block).into()).collect();
+            if std::is_x86_feature_detected!("avx2") {
+                let batch = [0, 1, 2, 3, 4, 5, 6, 7].map(|i| blocks[i].as_slice());
+                assert_eq!(unsafe { crate::simd::avx2::sha256x8(&batch) }.to_vec(), expected);
+            }
+            if std::is_x86_feature_detected!("sha") && std::is_x86_feature_detected!("sse4.1") {
+                let batch = [0, 1, 2, 3].map(|i| blocks[i].as_slice());
+                assert_eq!(unsafe { crate::simd::shani::sha256xn(&batch) }.to_vec(), expected[..4].to_vec());
+            }
+        }
+        let mixed: Vec<Vec<u8>> = (0..20).map(|i| vec![i as u8; i * 13]).collect();
+        let refs: Vec<&[u8]> = mixed.iter().map(Vec::as_slice).collect();
+        assert_eq!(hasher.hash_leaves(&refs), Sha256Hasher::new().hash_leaves(&refs));
+
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        for block_size in [64, 1000, 4096] {
+            let tree = HashTree::new(block_size).hasher(hasher).from_slice(&data).unwrap();
+            assert_eq!(tree, HashTree::new(block_size).from_slice(&data).unwrap());
+            let streamed = HashTree::new(block_size).hasher(hasher).from_data(&data[..]).unwrap();
+            assert_eq!(streamed, tree);
+        }
+    }
+
+    #[test]
+    fn sha256_backends() {
+        use crate::{MultiSha256Hasher, PairHasher, Sha256Backend};
+        use sha2::{Digest, Sha256};
+
+        assert!(Sha256Backend::detect().is_supported());
+        assert_eq!(MultiSha256Hasher::new().backend(), Sha256Backend::detect());
+        let data: Vec<u8> = (0..50_000u32).map(|i| (i % 253) as u8).collect();
+        let expected = HashTree::new(512).arity(3).from_slice(&data).unwrap();
+        for backend in [Sha256Backend::Plain, Sha256Backend::ShaNi, Sha256Backend::MultiBuffer] {
+            let hasher = match MultiSha256Hasher::with_backend(backend) {
+                Ok(hasher) => hasher,
+                Err(error) => {
+                    assert!(!backend.is_supported());
+                    assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+                    continue;
+                }
+            };
+            assert_eq!(hasher.backend(), backend);
+            for len in [0, 3, 55, 56, 64, 127, 128, 1000] {
+                assert_eq!(hasher.hash_leaf(&data[..len]), Sha256::digest(&data[..len]).to_vec());
+            }
+            assert_eq!(hasher.hash_pair(b"left", b"right"), Sha256::digest(b"leftright").to_vec());
+            let tree = HashTree::new(512).arity(3).hasher(hasher).from_slice(&data).unwrap();
+            assert_eq!(tree, expected, "{:?}", backend);
+        }
+    }
+
+    #[cfg(feature = "gpu")]
+    #[test]
+    fn gpu_leaf_hashing() {
+        use crate::{GpuSha256Hasher, PairHasher, Sha256Hasher};
+
+        let hasher = GpuSha256Hasher::new();
+        if !hasher.is_gpu_available() {
+            assert_eq!(hasher.leaf_batch(4096), Sha256Hasher::new().leaf_batch(4096));
+        }
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 241) as u8).collect();
+        for block_size in [64, 1000] {
+            let tree = HashTree::new(block_size).hasher(hasher.clone()).from_slice(&data).unwrap();
+            assert_eq!(tree, HashTree::new(block_size).from_slice(&data).unwrap());
+        }
+        let blocks: Vec<&[u8]> = data.chunks(100).collect();
+        assert_eq!(hasher.hash_leaves(&blocks), Sha256Hasher::new().hash_leaves(&blocks));
+    }
+
+    #[test]
+    fn download_verifier() {
+        use crate::{DownloadVerifier, Padding};
+        use std::io::ErrorKind;
+
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 31 % 256) as u8).collect();
+        let blocks: Vec<&[u8]> = data.chunks(64).collect();
+        for arity in [2, 3] {
+            for padding in [Padding::Duplicate, Padding::Zero] {
+                let tree = HashTree::new(64).arity(arity).padding(padding).from_slice(&data).unwrap();
+                let root = tree.root_hash().unwrap().into_bytes();
+
+                let mut verifier = DownloadVerifier::new(root.clone(), blocks.len());
+                for index in [9, 0, 15, 4] {
+                    assert!(verifier.accept_with_proof(index, blocks[index], &tree.proof(index).unwrap()).unwrap());
+                }
+                assert!(!verifier.accept_with_proof(9, blocks[9], &tree.proof(9).unwrap()).unwrap());
+                let error = verifier.accept_with_proof(5, blocks[6], &tree.proof(5).unwrap()).unwrap_err();
+                assert_eq!(error.kind(), ErrorKind::InvalidData);
+                assert!(verifier.accept_with_proof(6, blocks[6], &tree.proof(5).unwrap()).is_err());
+                assert_eq!(verifier.accept(0, b"other").unwrap_err().kind(), ErrorKind::InvalidData);
+                assert_eq!(verifier.accept(99, blocks[0]).unwrap_err().kind(), ErrorKind::InvalidInput);
+                // Siblings of the proven blocks are known without proofs
+                assert!(verifier.accept(1, blocks[1]).unwrap());
+                assert_eq!(verifier.num_received(), 5);
+                assert!(!verifier.is_complete());
+                assert!(verifier.is_received(15) && !verifier.is_received(7));
+
+                let mut from_tree = DownloadVerifier::from_tree(root.clone(), tree.clone()).unwrap();
+                for index in (0..blocks.len()).rev() {
+                    assert!(from_tree.accept(index, blocks[index]).unwrap());
+                    assert_eq!(from_tree.missing().count(), index);
+                }
+                assert!(from_tree.is_complete());
+                assert!(from_tree.accept(3, blocks[4]).is_err());
+            }
+        }
+
+        let other = HashTree::new(64).from_slice(&data[1..]).unwrap();
+        let root = HashTree::new(64).from_slice(&data).unwrap().root_hash().unwrap().into_bytes();
+        assert!(DownloadVerifier::from_tree(root.clone(), other).is_err());
+        let mut empty = DownloadVerifier::new(root, blocks.len());
+        assert_eq!(empty.accept(2, blocks[2]).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(empty.missing().count(), blocks.len());
+    }
+
+    #[test]
+    fn piece_tracker() {
+        use crate::{DownloadVerifier, PieceTracker};
+
+        for num_blocks in [0, 1, 63, 64, 65, 200] {
+            let mut tracker = PieceTracker::new(num_blocks);
+            assert_eq!(tracker.missing_blocks().count(), num_blocks);
+            assert_eq!(tracker.is_complete(), num_blocks == 0);
+            let order: Vec<usize> = (0..num_blocks).map(|i| i * 37 % num_blocks.max(1)).collect();
+            let mut verified = std::collections::BTreeSet::new();
+            for &index in &order {
+                assert_eq!(tracker.mark_verified(index, index + 1), verified.insert(index));
+                let missing: Vec<usize> = (0..num_blocks).filter(|i| !verified.contains(i)).collect();
+                assert_eq!(tracker.missing_blocks().collect::<Vec<_>>(), missing);
+                assert_eq!(tracker.next_needed(), missing.first().copied());
+                let after = missing.iter().find(|&&i| i >= index).or(missing.first()).copied();
+                assert_eq!(tracker.next_needed_from(index), after);
+                assert_eq!(tracker.verified_bytes(), verified.iter().map(|i| *i as u64 + 1).sum::<u64>());
+            }
+            assert_eq!(tracker.is_complete(), verified.len() == num_blocks);
+        }
+
+        let data = vec![3u8; 1000];
+        let tree = HashTree::new(64).from_slice(&data).unwrap();
+        let root = tree.root_hash().unwrap().into_bytes();
+        let mut verifier = DownloadVerifier::from_tree(root, tree).unwrap();
+        verifier.accept(15, &data[960..]).unwrap();
+        verifier.accept(0, &data[..64]).unwrap();
+        assert_eq!(verifier.tracker().verified_bytes(), 40 + 64);
+        assert_eq!(verifier.tracker().next_needed(), Some(1));
+        assert_eq!(verifier.tracker().next_needed_from(15), Some(1));
+    }
+
+    #[test]
+    fn range_responses() {
+        use std::io::ErrorKind;
+
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 13 % 256) as u8).collect();
+        let len = data.len() as u64;
+        for arity in [2, 4] {
+            let tree = HashTree::new(64).arity(arity).from_slice(&data).unwrap();
+            let root = tree.root_hash().unwrap().into_bytes();
+            let client = HashTree::new(64).arity(arity);
+            for (start, end) in [(0, 1), (63, 65), (100, 700), (960, 2000), (0, 1000)] {
+                let range = tree.block_range(len, start..end).unwrap();
+                assert!(range.start <= start && range.end >= end.min(len));
+                let proof = tree.range_proof(len, range.clone()).unwrap();
+                let response = &data[range.start as usize..range.end as usize];
+                let blocks = client.verify_range_response(&root, len, range.start, response, &proof).unwrap();
+                assert_eq!(blocks.start as u64, range.start / 64);
+                assert_eq!(blocks.end as u64, range.end.div_ceil(64));
+
+                let mut corrupt = response.to_vec();
+                corrupt[0] ^= 1;
+                let error = client.verify_range_response(&root, len, range.start, &corrupt, &proof).unwrap_err();
+                assert_eq!(error.kind(), ErrorKind::InvalidData);
+                if response.len() > 64 {
+                    let short = &response[..response.len() - 64];
+                    assert!(client.verify_range_response(&root, len, range.start, short, &proof).is_err());
+                }
+            }
+            let proof = tree.range_proof(len, 64..128).unwrap();
+            let error = client.verify_range_response(&root, len, 65, &data[65..128], &proof).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+            assert!(tree.range_proof(len + 64, 0..10).is_err());
+            assert!(tree.block_range(len, 1000..1001).is_err());
+        }
+
+        // Leaves of different sizes are located by their recorded lengths
+        let sizes = [3, 9, 5, 20, 1];
+        let mut chunks = Vec::new();
+        let mut rest = &data[..38];
+        for size in sizes {
+            let (chunk, tail) = rest.split_at(size);
+            chunks.push(chunk);
+            rest = tail;
+        }
+        let tree = HashTree::new(0).record_lengths().from_blocks(&chunks).unwrap();
+        let root = tree.root_hash().unwrap().into_bytes();
+        let range = tree.block_range(38, 5..14).unwrap();
+        assert_eq!(range, 3..17);
+        let proof = tree.range_proof(38, range).unwrap();
+        assert_eq!(tree.verify_range_response(&root, 38, 3, &data[3..17], &proof).unwrap(), 1..3);
+    }
+
+    #[test]
+    fn multipart_upload() {
+        use crate::MultipartTree;
+
+        let parts: Vec<Vec<u8>> = (1..=5u8).map(|i| vec![i; 1000 * i as usize]).collect();
+        let mut upload = MultipartTree::new(HashTree::new(0).arity(3));
+        for number in [3, 1, 5, 2] {
+            upload.add_part(number, &parts[number as usize - 1]).unwrap();
+        }
+        let hash = HashTree::new(0).get_hasher().hash_leaf(&parts[3]);
+        upload.add_part_hash(4, parts[3].len() as u64, hash).unwrap();
+        upload.add_part(2, &parts[1]).unwrap();
+        assert!(upload.add_part(0, b"").is_err());
+        assert!(upload.add_part(10_001, b"").is_err());
+        assert_eq!(upload.num_parts(), 5);
+
+        let tree = upload.finish().unwrap();
+        let expected = HashTree::new(0).arity(3).record_lengths().from_blocks(&parts).unwrap();
+        assert_eq!(tree, expected);
+        assert_eq!(tree.leaf_lengths(), expected.leaf_lengths());
+        assert_eq!(tree.get_arity(), 3);
+
+        let object = parts.concat();
+        let root = tree.root_hash().unwrap().into_bytes();
+        let mut buf = Vec::new();
+        tree.write_to(&mut buf).unwrap();
+        let sidecar = HashTree::new(0).arity(3).read_from(buf.as_slice()).unwrap();
+        for number in 1..=5 {
+            let range = sidecar.part_range(number).unwrap();
+            let part = &object[range.start as usize..range.end as usize];
+            sidecar.verify_part(&root, number, part).unwrap();
+            assert!(sidecar.verify_part(&root, number, &part[1..]).is_err());
+        }
+        assert!(sidecar.part_range(0).is_none() && sidecar.part_range(6).is_none());
+        assert!(sidecar.verify_part(&root, 6, b"").is_err());
+        assert!(sidecar.verify_part(&[0; 32], 1, &parts[0]).is_err());
+
+        let mut gaps = MultipartTree::new(HashTree::new(0));
+        gaps.add_part(1, b"a").unwrap();
+        gaps.add_part(3, b"c").unwrap();
+        assert!(gaps.finish().is_err());
+        assert!(MultipartTree::new(HashTree::new(0)).finish().is_err());
+    }
+
+    #[test]
+    fn tar_chunking() {
+        use crate::{TarChunker, TarEntry};
+
+        fn header(name: &str, kind: u8, size: u64) -> Vec<u8> {
+            let mut header = vec![0; 512];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[100..108].copy_from_slice(b"0000644\0");
+            header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+            header[156] = kind;
+            header[257..263].copy_from_slice(b"ustar\0");
+            header[148..156].copy_from_slice(b"        ");
+            let sum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+            header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+            header
+        }
+        fn member(archive: &mut Vec<u8>, name: &str, kind: u8, data: &[u8]) {
+            archive.extend(header(name, kind, data.len() as u64));
+            archive.extend(data);
+            archive.resize(archive.len().div_ceil(512) * 512, 0);
+        }
+
+        let long_name = format!("{}/b.bin", "x".repeat(120));
+        let record = format!("path={}\n", long_name);
+        let pax = format!("{} {}", record.len() + 4, record);
+        let mut archive = Vec::new();
+        member(&mut archive, "a.txt", b'0', &[1; 1000]);
+        member(&mut archive, "d/", b'5', &[]);
+        member(&mut archive, "PaxHeader", b'x', pax.as_bytes());
+        member(&mut archive, "b.bin", b'0', &[2; 5000]);
+        archive.resize(archive.len() + 1536, 0);
+
+        let mut chunker = TarChunker::new(&archive[..], 1024);
+        let tree = HashTree::new(1024).from_chunker(&mut chunker).unwrap();
+        assert_eq!(tree.num_blocks(), 12);
+        let entries = chunker.entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0],
+            TarEntry { path: "a.txt".into(), kind: b'0', range: 0..1536, data: 512..1512, leaves: 0..2 }
+        );
+        assert_eq!((entries[1].path.as_str(), entries[1].leaves.clone()), ("d/", 2..3));
+        assert_eq!(entries[2].path, long_name);
+        assert_eq!((entries[2].range.clone(), entries[2].data.clone()), (2048..8704, 3584..8584));
+        assert_eq!(entries[2].leaves, 3..10);
+        assert_eq!(chunker.entry_for_leaf(4).unwrap().path, long_name);
+        assert!(chunker.entry_for_leaf(10).is_none());
+
+        // Corrupting a member only changes the leaves of that member
+        let mut damaged = archive.clone();
+        damaged[600] ^= 1;
+        let other = HashTree::new(1024).from_chunker(TarChunker::new(&damaged[..], 1024)).unwrap();
+        let changed: Vec<usize> = (tree.leaf_hashes().zip(other.leaf_hashes()))
+            .enumerate()
+            .filter(|(_, (a, b))| a != b)
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(changed, vec![0]);
+
+        // One leaf per member with a block size of zero
+        let mut chunker = TarChunker::new(&archive[..], 0);
+        assert_eq!(HashTree::new(0).from_chunker(&mut chunker).unwrap().num_blocks(), 4);
+        assert_eq!(chunker.into_entries()[2].leaves, 2..3);
+
+        let error = HashTree::new(1024).from_chunker(TarChunker::new(&archive[..3000], 1024)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+        let mut damaged = archive.clone();
+        damaged[1536] ^= 1;
+        let error = HashTree::new(1024).from_chunker(TarChunker::new(&damaged[..], 1024)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn convergent_encryption() {
+        use crate::convergent::block_key;
+        use crate::MemoryBlockStore;
+
+        let encrypt = |key: &[u8; 32], block: &[u8]| -> Result<Vec<u8>, std::io::Error> {
+            let mut ciphertext: Vec<u8> = block.iter().zip(key.iter().cycle()).map(|(b, k)| b ^ k).collect();
+            ciphertext.extend_from_slice(&key[..4]);
+            Ok(ciphertext)
+        };
+        let data: Vec<u8> = [[1; 64], [2; 64], [1; 64]].concat();
+        let store = std::sync::Arc::new(MemoryBlockStore::new());
+        let tree = HashTree::new(64).block_store(store.clone()).record_lengths();
+        let tree = tree.from_data_convergent(&data[..], b"secret", encrypt).unwrap();
+
+        let plaintext = HashTree::new(64).from_slice(&data).unwrap();
+        assert_eq!(tree.plaintext().root_hash(), plaintext.root_hash());
+        assert_eq!(tree.plaintext().leaf_lengths(), Some(vec![64; 3]));
+        assert_eq!(tree.ciphertext().leaf_lengths(), Some(vec![68; 3]));
+
+        // Keys can be derived again from the plaintext leaves and the secret
+        let leaves: Vec<Vec<u8>> = plaintext.leaf_hashes().collect();
+        assert_eq!(tree.key(1), Some(&block_key(b"secret", &leaves[1])));
+        assert_eq!(tree.key(0), tree.key(2));
+        assert_ne!(block_key(b"other", &leaves[0]), tree.keys()[0]);
+
+        // The store holds the ciphertexts, deduplicated
+        let ciphertexts: Vec<Vec<u8>> = tree.ciphertext().leaf_hashes().collect();
+        assert_eq!(ciphertexts[0], ciphertexts[2]);
+        let stored = tree.ciphertext().get_block(2).unwrap();
+        assert_eq!(stored, encrypt(tree.key(2).unwrap(), &[1; 64]).unwrap());
+        assert_eq!(store.len(), 2);
+
+        assert!(tree.check_block(1, &[2; 64]).is_ok());
+        assert_eq!(tree.check_block(1, &[1; 64]).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(tree.check_block(3, &[1; 64]).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+
+        let failing = |_: &[u8; 32], _: &[u8]| Err(std::io::Error::other("cipher failed"));
+        assert!(HashTree::new(64).from_data_convergent(&data[..], b"", failing).is_err());
+    }
+
+    #[test]
+    fn generation_chain() {
+        use crate::GenerationChain;
+
+        let mut chain = GenerationChain::new();
+        assert!(chain.changed_since(0).is_err());
+        let first = chain.push(HashTree::new(4).from_slice(b"aaaabbbbccccdddd").unwrap()).unwrap();
+        assert_eq!((first.number(), first.parent_root()), (0, None));
+        let root = first.tree().root_hash_bytes().unwrap().to_vec();
+        let second = chain.push(HashTree::new(4).from_slice(b"aaaaXbbbccccdddd").unwrap()).unwrap();
+        assert_eq!(second.parent_root(), Some(&root[..]));
+        chain.push(HashTree::new(4).from_slice(b"aaaaXbbbccccdd").unwrap()).unwrap();
+        assert!(chain.push(HashTree::new(8).from_slice(b"aaaa").unwrap()).is_err());
+        assert_eq!(chain.len(), 3);
+
+        assert_eq!(chain.changed_since(2).unwrap(), vec![]);
+        assert_eq!(chain.changed_since(1).unwrap(), vec![3..4]);
+        assert_eq!(chain.changed_since(0).unwrap(), vec![1..2, 3..4]);
+        assert_eq!(chain.changed_between(0, 1).unwrap(), vec![1..2]);
+        assert_eq!(chain.incremental_size(0).unwrap(), 6);
+        assert_eq!(chain.incremental_size(2).unwrap(), 0);
+        assert!(chain.incremental_size(3).is_err());
+
+        // Blocks dropped from the latest generation cost nothing
+        chain.push(HashTree::new(4).from_slice(b"aaaaXbbb").unwrap()).unwrap();
+        assert_eq!(chain.changed_since(1).unwrap(), vec![2..4]);
+        assert_eq!(chain.incremental_size(1).unwrap(), 0);
+        assert_eq!(chain.iter().map(|generation| generation.number()).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn block_store_gc() {
+        use crate::{collect_garbage, BlockStore, FileBlockStore, GenerationChain};
+
+        let dir = std::env::temp_dir().join(format!("hashtree-test-gc-{}", std::process::id()));
+        let store = std::sync::Arc::new(FileBlockStore::open(&dir).unwrap());
+        let mut chain = GenerationChain::new();
+        for data in [&b"aaaabbbbcccc"[..], b"aaaaBBBBcccc", b"aaaaBBBBCCCC"] {
+            chain.push(HashTree::new(4).block_store(store.clone()).from_slice(data).unwrap()).unwrap();
+        }
+        let leaf = chain.get(0).unwrap().tree().leaf_hashes().next().unwrap();
+        std::fs::write(store.path(&leaf).with_extension("tmp1"), b"partial").unwrap();
+        assert_eq!(store.hashes().unwrap().len(), 5);
+
+        // Keep the last two generations
+        let live: Vec<&HashTree> = chain.iter().skip(1).map(|generation| generation.tree()).collect();
+        let stats = collect_garbage(&store, live.iter().copied()).unwrap();
+        assert_eq!((stats.live, stats.scanned, stats.removed), (4, 5, 1));
+        for tree in &live {
+            let mut data = Vec::new();
+            tree.write_blocks(&mut data).unwrap();
+            assert_eq!(data.len(), 12);
+        }
+        assert!(chain.get(0).unwrap().tree().get_block(1).is_err());
+        assert_eq!(collect_garbage(&store, live).unwrap().removed, 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        #[derive(Debug)]
+        struct AppendOnly;
+        impl BlockStore for AppendOnly {
+            fn put(&self, _: &[u8], _: &[u8]) -> Result<(), std::io::Error> {
+                Ok(())
+            }
+            fn get(&self, _: &[u8]) -> Result<Option<Vec<u8>>, std::io::Error> {
+                Ok(None)
+            }
+        }
+        let error = collect_garbage(&AppendOnly, std::iter::empty::<&HashTree>()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn rfc9162_encoding() {
+        use crate::rfc9162::{self, SignedTreeHead, TreeHead};
+        use crate::MerkleLog;
+
+        let mut log = MerkleLog::new();
+        for entry in 0..7u8 {
+            log.append(&[entry]).unwrap();
+        }
+        let log_id = [0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37];
+
+        let proof = log.inclusion_proof(2, 7).unwrap();
+        let item = rfc9162::encode_inclusion_proof(&log_id, &proof).unwrap();
+        assert_eq!(&item[..2], &[0, 7]);
+        assert_eq!(&item[2..10], &[7, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37]);
+        assert_eq!(&item[10..26], &[0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0, 2]);
+        assert_eq!(item.len(), 28 + 33 * proof.path.len());
+        assert_eq!(rfc9162::decode_inclusion_proof(&item).unwrap(), (log_id.to_vec(), proof));
+        assert!(rfc9162::decode_consistency_proof(&item).is_err());
+        assert!(rfc9162::decode_inclusion_proof(&item[..item.len() - 1]).is_err());
+        assert!(rfc9162::encode_inclusion_proof(&[1], &log.inclusion_proof(0, 1).unwrap()).is_err());
+
+        let old_root = log.root_at(3).unwrap();
+        let item = rfc9162::encode_consistency_proof(&log_id, &log.consistency_proof(3, 7).unwrap()).unwrap();
+        let (id, proof) = rfc9162::decode_consistency_proof(&item).unwrap();
+        assert_eq!(id, log_id);
+        assert!(proof.verify(&old_root, &log.root()));
+
+        let mut head = log.tree_head(1_700_000_000_000);
+        head.extensions.push((1, b"ext".to_vec()));
+        let bytes = head.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 8 + 8 + 33 + 2 + 2 + 2 + 3);
+        assert_eq!(TreeHead::from_bytes(&bytes).unwrap(), head);
+
+        let sth = SignedTreeHead { log_id: log_id.to_vec(), tree_head: head, signature: vec![9; 64] };
+        let item = sth.to_bytes().unwrap();
+        assert_eq!(&item[..2], &[0, 5]);
+        assert_eq!(SignedTreeHead::from_bytes(&item).unwrap(), sth);
+        let mut trailing = item.clone();
+        trailing.push(0);
+        assert!(SignedTreeHead::from_bytes(&trailing).is_err());
+    }
+
+    #[test]
+    fn signed_roots() {
+        use crate::{HmacSigner, MerkleLog, SignedRoot, Signer, Verifier};
+
+        let signer = HmacSigner::new(b"key");
+        let tree = HashTree::new(4).from_slice(b"abcdefghij").unwrap();
+        let signed = tree.sign_root(&signer, 42).unwrap();
+        assert_eq!((signed.tree_size, signed.timestamp), (3, 42));
+        assert!(signed.verify(&signer));
+        assert!(signed.verify_tree(&signer, &tree));
+        assert!(!signed.verify(&HmacSigner::new(b"other key")));
+        assert!(!signed.verify_tree(&signer, &HashTree::new(4).from_slice(b"abcdefghiJ").unwrap()));
+
+        let decoded = SignedRoot::from_bytes(&signed.to_bytes()).unwrap();
+        assert_eq!(decoded, signed);
+        let mut later = decoded.clone();
+        later.timestamp += 1;
+        assert!(!later.verify(&signer));
+        assert!(SignedRoot::from_bytes(&signed.to_bytes()[..40]).is_err());
+        assert!(HashTree::new(4).from_slice(b"").unwrap().sign_root(&signer, 0).is_err());
+
+        // Signers in other processes plug in through the traits
+        #[derive(Debug)]
+        struct Remote;
+        impl Signer for Remote {
+            fn sign(&self, _: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+                Err(std::io::Error::other("signing service unreachable"))
+            }
+        }
+        assert!(tree.sign_root(&Remote, 0).is_err());
+
+        let mut log = MerkleLog::new();
+        log.append(b"entry").unwrap();
+        let sth = log.tree_head(7).sign(&[1, 2, 3], &signer).unwrap();
+        assert!(sth.verify(&signer));
+        assert!(signer.verify(&sth.tree_head.to_bytes().unwrap(), &sth.signature));
+        let mut forged = sth.clone();
+        forged.tree_head.tree_size = 2;
+        assert!(!forged.verify(&signer));
+    }
+
+    #[cfg(feature = "ed25519")]
+    #[test]
+    fn ed25519_signed_roots() {
+        use crate::{SignedRoot, Signer};
+        use ed25519_dalek::SigningKey;
+
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let tree = HashTree::new(4).from_slice(b"release artifact").unwrap();
+        let signed = tree.sign_root(&key, 1_700_000_000_000).unwrap();
+        assert_eq!(signed.signature.len(), 64);
+        let received = SignedRoot::from_bytes(&signed.to_bytes()).unwrap();
+        assert!(received.verify_tree(&key.verifying_key(), &tree));
+        assert!(!received.verify(&SigningKey::from_bytes(&[8; 32]).verifying_key()));
+        let mut forged = received.clone();
+        forged.tree_size += 1;
+        assert!(!forged.verify(&key.verifying_key()));
+        assert!(!crate::Verifier::verify(&key.verifying_key(), b"message", &key.sign(b"message").unwrap()[..63]));
+    }
+
+    #[cfg(feature = "ecdsa")]
+    #[test]
+    fn ecdsa_signed_roots() {
+        use crate::{SignedRoot, Signer};
+        use p256::ecdsa::SigningKey;
+
+        let key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let tree = HashTree::new(4).from_slice(b"release artifact").unwrap();
+        let signed = tree.sign_root(&key, 1_700_000_000_000).unwrap();
+        assert_eq!(signed.signature.len(), 64);
+        // Nonces are deterministic
+        assert_eq!(tree.sign_root(&key, 1_700_000_000_000).unwrap(), signed);
+        let received = SignedRoot::from_bytes(&signed.to_bytes()).unwrap();
+        assert!(received.verify_tree(key.verifying_key(), &tree));
+        assert!(!received.verify(SigningKey::from_slice(&[8; 32]).unwrap().verifying_key()));
+        let mut forged = received.clone();
+        forged.timestamp += 1;
+        assert!(!forged.verify(key.verifying_key()));
+        assert!(!crate::Verifier::verify(key.verifying_key(), b"message", &key.sign(b"message").unwrap()[..63]));
+    }
+
+    #[test]
+    fn nested_trees() {
+        use crate::{NestedTree, PairHasher, Sha256Hasher};
+
+        let hasher = Sha256Hasher::new();
+        let leaf = |data: &[u8]| hasher.hash_leaf(data);
+
+        // Three layers: dataset -> shard -> file
+        let file = HashTree::new(2).from_slice(b"abcdef").unwrap();
+        let mut shard = NestedTree::builder(HashTree::new(0).arity(3));
+        shard.push_block(b"shard header");
+        assert_eq!(shard.push_tree(file.clone()).unwrap(), 1);
+        assert!(shard.push_tree(HashTree::new(2).from_slice(b"").unwrap()).is_err());
+        let shard = shard.finish().unwrap();
+        let mut dataset = NestedTree::builder(HashTree::new(0).arity(3));
+        dataset.push_subtree_root(vec![7; 32]);
+        dataset.push_nested(shard).unwrap();
+        let dataset = dataset.finish().unwrap();
+        let root = dataset.root_hash().unwrap().into_bytes();
+        assert_eq!(dataset.depth(), 3);
+        assert!(dataset.is_subtree(0) && dataset.subtree(0).is_none());
+
+        let proof = dataset.proof(&[1, 1, 2]).unwrap();
+        assert_eq!(proof.path(), vec![1, 1, 2]);
+        assert_eq!(proof.roots[1], file.root_hash_bytes().unwrap());
+        assert!(proof.verify(&root, &leaf(b"ef")));
+        assert!(!proof.verify(&root, &leaf(b"cd")));
+        assert!(dataset.proof(&[1, 0]).unwrap().verify(&root, &leaf(b"shard header")));
+
+        // Paths must end at a block of a tree that is held
+        assert!(dataset.proof(&[1, 1]).is_none());
+        assert!(dataset.proof(&[0, 0]).is_none());
+        assert!(dataset.proof(&[1, 1, 3]).is_none());
+
+        // A subtree root cannot pass as a block, nor a block as a subtree
+        let mut forged = dataset.proof(&[1, 1, 2]).unwrap();
+        let file_root = forged.roots.pop().unwrap();
+        forged.proofs.pop();
+        assert!(!forged.verify(&root, &leaf(&file_root)));
+        let mut block = NestedTree::builder(HashTree::new(0));
+        block.push_block(&file_root);
+        let mut tree = NestedTree::builder(HashTree::new(0));
+        tree.push_tree(file).unwrap();
+        assert_ne!(block.finish().unwrap().root_hash(), tree.finish().unwrap().root_hash());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_block_store() {
+        use crate::{BlockStore, MemoryBlockStore, ZstdBlockStore};
+        use sha2::{Digest, Sha256};
+        use std::sync::Arc;
+
+        let store = Arc::new(ZstdBlockStore::new(MemoryBlockStore::new(), 3));
+        let random: Vec<u8> = (0..128u32).flat_map(|i| Sha256::digest(i.to_be_bytes())).collect();
+        let data = [vec![b'a'; 8192], random.clone(), vec![b'b'; 100]].concat();
+        let tree = HashTree::new(4096).block_store(store.clone()).from_slice(&data).unwrap();
+
+        let mut copy = Vec::new();
+        tree.write_blocks(&mut copy).unwrap();
+        assert_eq!(copy, data);
+        // The two equal blocks are stored once, the random one uncompressed
+        let stats = store.stats();
+        assert_eq!((stats.blocks, stats.plain_bytes), (3, 4096 + 4096 + 100));
+        assert!(stats.stored_bytes < 4096 + 100);
+        let leaves: Vec<Vec<u8>> = tree.leaf_hashes().collect();
+        assert_eq!(store.stored_len(&leaves[2]).unwrap(), Some(4097));
+        assert_eq!(store.inner().get(&leaves[2]).unwrap().unwrap()[1..], random[..]);
+        assert!(store.stored_len(&leaves[0]).unwrap().unwrap() < 100);
+
+        store.inner().remove(&leaves[3]).unwrap();
+        store.inner().put(&leaves[3], &[1, 0xff, 0xff]).unwrap();
+        assert_eq!(tree.get_block(3).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(store.hashes().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn presets() {
+        use crate::{DoubleSha256Hasher, Padding, PairHasher, Preset, PrefixedHasher};
+        use sha2::{Digest, Sha256};
+
+        // RFC 6962 roots and proofs for every size, checked against the log
+        let hasher = PrefixedHasher::<Sha256>::new();
+        let entries: Vec<Vec<u8>> = (0..20u8).map(|i| vec![i; i as usize]).collect();
+        let mut log = MerkleLog::new();
+        for size in 1..=entries.len() {
+            log.append(&entries[size - 1]).unwrap();
+            let tree = HashTree::new(0).preset(Preset::Rfc6962).from_blocks(&entries[..size]).unwrap();
+            assert_eq!(tree.root_hash_array(), Some(log.root()), "size {}", size);
+            let root = tree.root_hash_bytes().unwrap();
+            for (index, entry) in entries[..size].iter().enumerate() {
+                assert!(tree.proof(index).unwrap().verify_with(&hasher, root, &hasher.hash_leaf(entry)));
+            }
+
+            let mut persistent = tree.to_persistent();
+            persistent.set_leaf(size - 1, hasher.hash_leaf(b"changed"));
+            let mut changed = entries[..size].to_vec();
+            changed[size - 1] = b"changed".to_vec();
+            let rebuilt = HashTree::new(0).preset(Preset::Rfc6962).from_blocks(&changed).unwrap();
+            assert_eq!(persistent.root_hash(), rebuilt.root_hash());
+        }
+
+        // Placeholders survive serialization
+        let tree = HashTree::new(0).preset(Preset::Rfc6962).from_blocks(&entries[..7]).unwrap();
+        let mut bytes = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+        let copy = HashTree::new(0).preset(Preset::Rfc6962).read_from(bytes.as_slice()).unwrap();
+        assert_eq!(copy.get_padding(), Padding::Promote);
+        assert_eq!(copy, tree);
+
+        // BitTorrent v2: 16 KiB blocks, zero-padded to a power of two
+        let data = vec![7u8; 3 * 16384 + 5];
+        let torrent = HashTree::new(0).preset(Preset::BitTorrentV2).from_slice(&data).unwrap();
+        let mut leaves: Vec<Vec<u8>> = data.chunks(16384).map(|block| Sha256::digest(block).to_vec()).collect();
+        leaves.push(vec![0u8; 32]);
+        let pair = |a: &[u8], b: &[u8]| Sha256::new().chain_update(a).chain_update(b).finalize().to_vec();
+        let expected = pair(&pair(&leaves[0], &leaves[1]), &pair(&leaves[2], &leaves[3]));
+        assert_eq!(torrent.root_hash_bytes(), Some(&expected[..]));
+
+        // Bitcoin block 100000, whose txids and Merkle root are shown reversed
+        let reversed = |hex: &str| hex::decode(hex).unwrap().into_iter().rev().collect::<Vec<u8>>();
+        let txids = [
+            "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+            "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+            "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+            "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+        ];
+        let block = HashTree::new(0).preset(Preset::Bitcoin).from_leaf_hashes(txids.iter().map(|txid| reversed(txid))).unwrap();
+        let root = reversed("f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766");
+        assert_eq!(block.root_hash_bytes(), Some(&root[..]));
+        let three = HashTree::new(0).preset(Preset::Bitcoin).from_leaf_hashes(txids[..3].iter().map(|txid| reversed(txid))).unwrap();
+        let hasher = DoubleSha256Hasher::new();
+        let (a, b, c) = (reversed(txids[0]), reversed(txids[1]), reversed(txids[2]));
+        let expected = hasher.hash_pair(&hasher.hash_pair(&a, &b), &hasher.hash_pair(&c, &c));
+        assert_eq!(three.root_hash_bytes(), Some(&expected[..]));
+
+        // The genesis block has only its coinbase, whose txid is the Merkle root
+        let coinbase = reversed("4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b");
+        let genesis = HashTree::new(0).preset(Preset::Bitcoin).from_leaf_hashes(vec![coinbase.clone()]).unwrap();
+        assert_eq!(genesis.root_hash_bytes(), Some(&coinbase[..]));
+        let pair = HashTree::new(0).preset(Preset::Bitcoin).from_leaf_hashes(vec![coinbase.clone(), coinbase.clone()]).unwrap();
+        assert_eq!(pair.root_hash_bytes(), Some(&hasher.hash_pair(&coinbase, &coinbase)[..]));
+        let mut written = Vec::new();
+        genesis.write_to(&mut written).unwrap();
+        assert!(HashTree::new(0).preset(Preset::Bitcoin).read_from(&written[..]).unwrap().eq_structure(&genesis));
+
+        // THEX promotes the odd last segment, and a single segment is the root
+        let thex = HashTree::new(0).preset(Preset::Thex).from_slice(&[1u8; 2500]).unwrap();
+        let hasher = PrefixedHasher::<Sha256>::new();
+        let segments: Vec<Vec<u8>> = [1u8; 2500].chunks(1024).map(|segment| hasher.hash_leaf(segment)).collect();
+        let expected = hasher.hash_pair(&hasher.hash_pair(&segments[0], &segments[1]), &segments[2]);
+        assert_eq!(thex.root_hash_bytes(), Some(&expected[..]));
+        let single = HashTree::new(0).preset(Preset::Thex).from_slice(b"abc").unwrap();
+        assert_eq!(single.root_hash_bytes(), Some(&hasher.hash_leaf(b"abc")[..]));
+        assert_eq!(single.num_nodes(), 1);
+
+        // BLAKE3 leaves are plain BLAKE3 hashes of the blocks
+        #[cfg(feature = "blake3")]
+        {
+            use crate::Blake3Hasher;
+            assert_eq!(
+                hex::encode(Blake3Hasher::new().hash_leaf(b"abc")),
+                "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+            );
+            let tree = HashTree::new(2).preset(Preset::Blake3).from_slice(b"abc").unwrap();
+            let hasher = Blake3Hasher::new();
+            let expected = hasher.hash_pair(&hasher.hash_leaf(b"ab"), &hasher.hash_leaf(b"c"));
+            assert_eq!(tree.root_hash_bytes(), Some(&expected[..]));
+            assert_ne!(Blake3Hasher::keyed(&[7; 32]).hash_leaf(b"abc"), hasher.hash_leaf(b"abc"));
+            Preset::Blake3.known_answer_test().unwrap();
+        }
+
+        // Embedded known answers
+        Preset::self_test().unwrap();
+        for preset in Preset::ALL {
+            preset.known_answer_test().unwrap();
+        }
+    }
+
+    #[test]
+    fn level_order_serialization() {
+        use crate::{Padding, Preset};
+
+        let data: Vec<u8> = (0..100).collect();
+        for (preset, tree) in [
+            (Preset::Plain, HashTree::new(3).arity(3).record_lengths().from_slice(&data).unwrap()),
+            (Preset::Plain, HashTree::new(7).padding(Padding::Zero).from_slice(&data).unwrap()),
+            (Preset::Rfc6962, HashTree::new(7).preset(Preset::Rfc6962).from_blocks(data.chunks(7)).unwrap()),
+        ] {
+            let template = || HashTree::new(0).preset(preset);
+            let root = tree.root_hash_bytes().unwrap().to_vec();
+            let mut bytes = Vec::new();
+            tree.write_level_order(&mut bytes).unwrap();
+            let copy = template().read_level_order(bytes.as_slice(), &root).unwrap();
+            assert_eq!(copy, tree);
+            assert_eq!(copy.leaf_lengths(), tree.leaf_lengths());
+            assert_eq!(copy.get_padding(), tree.get_padding());
+            assert_eq!(template().read_from(bytes.as_slice()).unwrap(), tree);
+
+            // A corrupt node is caught at its level, before the leaves are read
+            let hash_len = root.len();
+            let mut corrupt = bytes.clone();
+            corrupt[48 + 2 * hash_len] ^= 1;
+            let error = template().read_level_order(corrupt.as_slice(), &root).unwrap_err();
+            assert!(error.to_string().contains("does not match the level above"), "{}", error);
+            let mut reader = &corrupt[..];
+            assert!(template().read_level_order(&mut reader, &root).is_err());
+            assert!(!reader.is_empty());
+
+            let error = template().read_level_order(bytes.as_slice(), &[0u8; 32]).unwrap_err();
+            assert_eq!(error.to_string(), "tree does not match the root");
+            let mut plain = Vec::new();
+            tree.write_to(&mut plain).unwrap();
+            let error = template().read_level_order(plain.as_slice(), &root).unwrap_err();
+            assert_eq!(error.to_string(), "tree is not in level order");
+        }
+    }
+
+    #[test]
+    fn forest_aggregate_proofs() {
+        use crate::{Forest, PairHasher, Sha256Hasher};
+
+        let hasher = Sha256Hasher::new();
+        let files: Vec<Vec<u8>> = (0..9u8).map(|i| (0..40).map(|j| i ^ j).collect()).collect();
+        let trees: Vec<HashTree> = files.iter().map(|data| HashTree::new(4).from_slice(data).unwrap()).collect();
+        let forest = Forest::from_trees(HashTree::new(0), &trees).unwrap();
+        let root = forest.root_hash().unwrap().into_bytes();
+
+        let picks: [(usize, &[usize]); 3] = [(7, &[0, 1, 9]), (1, &[3]), (2, &[4, 5])];
+        let request: Vec<_> = picks.iter().map(|&(index, blocks)| (index, &trees[index], blocks)).collect();
+        let proof = forest.aggregate_proof(&request).unwrap();
+        assert_eq!(proof.trees.iter().map(|part| part.tree_index).collect::<Vec<_>>(), [1, 2, 7]);
+
+        let leaves: Vec<(usize, usize, Vec<u8>)> = picks
+            .iter()
+            .flat_map(|&(index, blocks)| blocks.iter().map(move |&block| (index, block)))
+            .map(|(index, block)| (index, block, hasher.hash_leaf(&files[index][block * 4..block * 4 + 4])))
+            .collect();
+        let claims: Vec<(usize, usize, &[u8])> = leaves.iter().map(|(tree, block, hash)| (*tree, *block, hash.as_slice())).collect();
+        assert!(proof.verify(&root, &claims));
+
+        // Shared paths make it smaller than separate proofs
+        let separate: usize = claims
+            .iter()
+            .map(|&(tree, block, _)| {
+                let proof = forest.proof(tree, &trees[tree], block).unwrap();
+                1 + proof.block_proof.levels.len() + proof.tree_proof.levels.len()
+            })
+            .sum();
+        assert!(proof.num_hashes() < separate, "{} >= {}", proof.num_hashes(), separate);
+
+        // Every claim must be proven, and nothing else
+        assert!(!proof.verify(&root, &claims[1..]));
+        let mut extra = claims.clone();
+        extra.push((1, 4, claims[0].2));
+        assert!(!proof.verify(&root, &extra));
+        let mut wrong = claims.clone();
+        wrong[2].2 = claims[0].2;
+        assert!(!proof.verify(&root, &wrong));
+        assert!(!proof.verify(&[0u8; 32], &claims));
+        let mut moved = proof.clone();
+        moved.trees[0].tree_index = 3;
+        assert!(!moved.verify(&root, &claims));
+        let mut swapped = proof.clone();
+        swapped.trees[1].tree_root = proof.trees[0].tree_root.clone();
+        assert!(!swapped.verify(&root, &claims));
+
+        assert!(forest.aggregate_proof(&[(1, &trees[1], &[0]), (1, &trees[1], &[1])]).is_none());
+        assert!(forest.aggregate_proof(&[(1, &trees[2], &[0])]).is_none());
+        assert!(forest.aggregate_proof(&[(1, &trees[1], &[])]).is_none());
+    }
+
+    #[test]
+    fn patricia_trie() {
+        use crate::mpt::keccak256;
+        use crate::PatriciaTrie;
+
+        let empty = PatriciaTrie::new();
+        assert_eq!(hex::encode(empty.root()), "56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421");
+        assert_eq!(PatriciaTrie::verify_proof(&empty.root(), b"key", &empty.proof(b"key")).unwrap(), None);
+
+        let mut trie = PatriciaTrie::new();
+        trie.insert(b"foo", b"bar");
+        trie.insert(b"food", b"bass");
+        assert_eq!(hex::encode(trie.root()), "17beaa1648bafa633cda809c90c04af50fc8aed3cb40d16efbddee6fdf63c4c3");
+
+        // Values long enough that nodes are referenced by hash, keys that are
+        // prefixes of others, and an order-independent root
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..200u32)
+            .map(|i| {
+                let key = keccak256(&i.to_be_bytes())[..(i % 5) as usize + 1].to_vec();
+                (key, vec![i as u8 | 1; (i % 40) as usize + 1])
+            })
+            .collect();
+        let mut trie = PatriciaTrie::new();
+        let mut reversed = PatriciaTrie::new();
+        for (key, value) in &entries {
+            trie.insert(key, value);
+        }
+        for (key, value) in entries.iter().rev() {
+            if trie.get(key) == Some(&value[..]) {
+                reversed.insert(key, value);
+            }
+        }
+        let root = trie.root();
+        assert_eq!(reversed.root(), root);
+
+        for (key, _) in &entries {
+            let proof = trie.proof(key);
+            assert_eq!(PatriciaTrie::verify_proof(&root, key, &proof).unwrap().as_deref(), trie.get(key));
+        }
+        for i in 0..50u32 {
+            let absent = keccak256(&(i + 1000).to_be_bytes());
+            let proof = trie.proof(&absent);
+            assert_eq!(PatriciaTrie::verify_proof(&root, &absent, &proof).unwrap(), None);
+        }
+
+        let (key, _) = &entries[7];
+        let mut proof = trie.proof(key);
+        assert!(proof.len() > 1);
+        let last = proof.len() - 1;
+        let byte = proof[last].len() - 1;
+        proof[last][byte] ^= 1;
+        assert!(PatriciaTrie::verify_proof(&root, key, &proof).is_err());
+        proof.truncate(1);
+        assert!(PatriciaTrie::verify_proof(&root, key, &proof).is_err());
+
+        // Removing keys brings back earlier roots
+        let before = trie.root();
+        trie.insert(b"extra", b"value");
+        assert_ne!(trie.root(), before);
+        trie.insert(b"extra", b"");
+        assert_eq!(trie.root(), before);
+    }
+
+    #[cfg(feature = "verkle")]
+    #[test]
+    fn verkle_tree() {
+        use crate::VerkleTree;
+        use sha2::{Digest, Sha256};
+
+        let key = |i: u32| -> [u8; 32] { Sha256::digest(i.to_be_bytes()).into() };
+        let empty = VerkleTree::new().root();
+        let mut tree = VerkleTree::new();
+        let mut reversed = VerkleTree::new();
+        for i in 0..300 {
+            tree.insert(key(i), &i.to_le_bytes());
+        }
+        for i in (0..300).rev() {
+            reversed.insert(key(i), &i.to_le_bytes());
+        }
+        // Keys sharing a first byte force inner nodes below the root
+        let mut near = key(0);
+        near[31] ^= 1;
+        tree.insert(near, b"near");
+        reversed.insert(near, b"near");
+        assert_eq!(tree.len(), 301);
+        assert_eq!(tree.root(), reversed.root());
+
+        let root = tree.root();
+        let scheme = tree.scheme();
+        for i in 0..300 {
+            let proof = tree.proof(&key(i));
+            assert!(proof.verify(scheme, &root, &key(i), Some(&i.to_le_bytes())));
+            assert!(!proof.verify(scheme, &root, &key(i), Some(b"other")));
+            assert!(!proof.verify(scheme, &root, &key(i), None));
+        }
+        let proof = tree.proof(&near);
+        assert!(proof.steps.len() > 30);
+        assert!(proof.verify(scheme, &root, &near, Some(b"near")));
+        for i in 300..350 {
+            let proof = tree.proof(&key(i));
+            assert!(proof.verify(scheme, &root, &key(i), None));
+            assert!(!proof.verify(scheme, &root, &key(i), Some(&i.to_le_bytes())));
+        }
+
+        let mut proof = tree.proof(&key(7));
+        proof.steps[0].slot[0] ^= 1;
+        assert!(!proof.verify(scheme, &root, &key(7), Some(&7u32.to_le_bytes())));
+
+        // Removing keys collapses inner nodes, bringing back earlier roots
+        tree.remove(&near);
+        reversed.remove(&near);
+        assert_eq!(tree.root(), reversed.root());
+        for i in 0..300 {
+            assert_eq!(tree.remove(&key(i)), Some(i.to_le_bytes().to_vec()));
+        }
+        assert!(tree.is_empty());
+        assert_eq!(tree.root(), empty);
+    }
+
+    #[test]
+    fn history_tree() {
+        use crate::HistoryTree;
+
+        let mut tree = HistoryTree::new();
+        assert!(tree.root().is_none());
+        let mut roots = Vec::new();
+        for i in 0..40u32 {
+            assert_eq!(tree.append(&i.to_be_bytes()), i as u64);
+            roots.push(tree.root().unwrap());
+        }
+        // Roots of past versions do not change as the tree grows
+        for (version, root) in roots.iter().enumerate() {
+            assert_eq!(tree.root_at(version as u64).as_ref(), Some(root));
+        }
+        assert_eq!(tree.root_at(0), Some(HistoryTree::leaf_hash(&0u32.to_be_bytes())));
+
+        for version in 0..40u64 {
+            for index in 0..=version {
+                let proof = tree.membership_proof(index, version).unwrap();
+                assert!(proof.verify(&roots[version as usize], &(index as u32).to_be_bytes()));
+                assert!(!proof.verify(&roots[version as usize], b"forged"));
+            }
+            for old in 0..=version {
+                let proof = tree.incremental_proof(old, version).unwrap();
+                assert!(proof.verify(&roots[old as usize], &roots[version as usize]));
+                if old != version {
+                    assert!(!proof.verify(&roots[version as usize], &roots[version as usize]));
+                }
+            }
+        }
+        assert!(tree.membership_proof(5, 4).is_none());
+        assert!(tree.incremental_proof(3, 40).is_none());
+
+        let mut proof = tree.membership_proof(9, 30).unwrap();
+        proof.version = 31;
+        assert!(!proof.verify(&roots[31], &9u32.to_be_bytes()));
+        proof.version = 30;
+        proof.path.pop();
+        assert!(!proof.verify(&roots[30], &9u32.to_be_bytes()));
+    }
+
+    #[test]
+    fn audit_log_chain() {
+        use crate::{AuditLog, HmacSigner};
+
+        let dir = temp_dir("audit_log_chain");
+        let path = dir.join("audit");
+        let signer = HmacSigner::new(b"key");
+        let tree = HashTree::new(4).from_slice(b"monitored").unwrap();
+        {
+            let mut log = AuditLog::open(&path).unwrap();
+            log.checkpoint(&tree, 1000, Some(&signer)).unwrap();
+            assert!(log.checkpoint_if_due(&tree, 1500, 1000, Some(&signer)).unwrap().is_none());
+            assert!(log.checkpoint_if_due(&tree, 2000, 1000, Some(&signer)).unwrap().is_some());
+            assert!(log.append(b"root", 1, 1999, None).is_err());
+        }
+        // Reopening continues the chain
+        let mut log = AuditLog::open(&path).unwrap();
+        assert_eq!(log.len(), 2);
+        log.append(&[7u8; 32], 9, 3000, None).unwrap();
+        let records = AuditLog::verify_file(&path, None).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2].hash(), log.head());
+        assert_eq!(records[1].prev, records[0].hash());
+        assert!(records[..2].iter().all(|record| record.verify(&signer)));
+        // The unsigned record fails when signatures are required
+        assert!(AuditLog::verify_file(&path, Some(&signer)).is_err());
+
+        // Tampering with a record breaks the chain
+        let mut bytes = fs::read(&path).unwrap();
+        let offset = 4 + 32 + 16 + 1;
+        bytes[offset] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        assert!(AuditLog::verify_file(&path, None).is_err());
+        bytes[offset] ^= 1;
+        bytes.truncate(bytes.len() - 1);
+        fs::write(&path, &bytes).unwrap();
+        assert!(AuditLog::open(&path).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn witness_checks_proofs() {
+        use crate::{MerkleLog, Preset, Witness};
+
+        let data: Vec<u8> = (0..200).collect();
+        for tree in [
+            HashTree::new(7).from_slice(&data).unwrap(),
+            HashTree::new(7).arity(3).from_slice(&data).unwrap(),
+            HashTree::new(7).preset(Preset::Rfc6962).from_blocks(data.chunks(7)).unwrap(),
+        ] {
+            let bytes = tree.witness().unwrap().to_bytes().unwrap();
+            let witness = match tree.get_padding() {
+                crate::Padding::Promote => Witness::from_bytes_with(&bytes, crate::PrefixedHasher::<sha2::Sha256>::new()).unwrap(),
+                _ => Witness::from_bytes(&bytes).unwrap(),
+            };
+            assert_eq!(witness, tree.witness().unwrap());
+            assert_eq!(witness.num_blocks(), tree.num_blocks());
+            for (index, block) in data.chunks(7).enumerate() {
+                let proof = tree.proof(index).unwrap();
+                assert!(witness.verify_block(index as u64, block, &proof));
+                assert!(!witness.verify_block(index as u64, b"other", &proof));
+            }
+            // A proof cannot be replayed for another index
+            let mut proof = tree.proof(3).unwrap();
+            proof.leaf_index = 4;
+            assert!(!witness.verify_block(4, &data[21..28], &proof));
+            assert!(witness.verify_stream(&tree.multiproof_stream(&[0, 5, 28]).unwrap()));
+        }
+        assert!(Witness::from_bytes_with(&HashTree::new(7).from_slice(&data).unwrap().witness().unwrap().to_bytes().unwrap(),
+            crate::DoubleSha256Hasher::new()).is_err());
+
+        // A witness of an RFC 6962 tree follows a growing log
+        let mut log = MerkleLog::new();
+        for entry in data.chunks(7) {
+            log.append(entry).unwrap();
+        }
+        let old = HashTree::new(0).preset(Preset::Rfc6962).from_blocks(data.chunks(7).take(10)).unwrap();
+        let mut witness = old.witness().unwrap();
+        let proof = log.consistency_proof(10, log.len()).unwrap();
+        assert!(!witness.advance(&proof, &[0u8; 32]));
+        assert!(witness.advance(&proof, &log.root()));
+        assert_eq!(witness.root(), &log.root()[..]);
+        assert_eq!(witness.num_blocks(), log.len());
+        assert!(!HashTree::new(7).from_blocks(data.chunks(7).take(10)).unwrap().witness().unwrap().verify_consistency(&proof, &log.root()));
+    }
+
+    #[test]
+    fn batch_proof_export() {
+        use crate::{LeafIndex, Padding, Preset};
+
+        let data: Vec<u8> = (0..100).collect();
+        for tree in [
+            HashTree::new(3).from_slice(&data).unwrap(),
+            HashTree::new(3).arity(4).from_slice(&data).unwrap(),
+            HashTree::new(3).padding(Padding::Zero).from_slice(&data).unwrap(),
+            HashTree::new(3).preset(Preset::Rfc6962).from_blocks(data.chunks(3)).unwrap(),
+            HashTree::new(1).from_slice(b"a").unwrap(),
+        ] {
+            let proofs: Vec<_> = tree.proofs().collect();
+            assert_eq!(proofs.len() as u64, tree.num_blocks());
+            for (expected, (index, proof)) in proofs.into_iter().enumerate() {
+                assert_eq!(index, LeafIndex::from(expected));
+                assert_eq!(Some(proof), tree.proof(index));
+            }
+        }
+        assert_eq!(HashTree::new(1).from_slice(b"").unwrap().proofs().count(), 0);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_proofs() {
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        for tree in [HashTree::new(3).from_slice(&data).unwrap(), HashTree::new(5).arity(4).from_slice(&data).unwrap()] {
+            assert_eq!(tree.proofs_par(), tree.proofs().collect::<Vec<_>>());
+            let num_blocks = tree.num_blocks() as usize;
+            for indices in [vec![0], (0..num_blocks).step_by(13).collect(), vec![num_blocks - 1, 3, 3, 2000]] {
+                assert_eq!(tree.multiproof_par(&indices), tree.multiproof_stream(&indices));
+            }
+            assert!(tree.multiproof_par(&[num_blocks]).is_none());
+        }
+        assert!(HashTree::new(1).from_slice(b"").unwrap().proofs_par().is_empty());
+
+        // Work runs on the pool passed in, not the global one
+        let pool = std::sync::Arc::new(rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap());
+        let tree = HashTree::new(3).thread_pool(pool.clone()).from_slice(&data).unwrap();
+        let global = HashTree::new(3).from_slice(&data).unwrap();
+        assert_eq!(tree.proofs_par(), global.proofs_par());
+        assert_eq!(tree.install(rayon::current_thread_index), Some(0));
+        assert_eq!(global.install(|| pool.current_thread_index()), None);
+        assert_eq!(tree.multiproof_par(&[1, 2000]), global.multiproof_stream(&[1, 2000]));
+    }
+}
@@ -0,0 +1,413 @@
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{BufReader, Error, ErrorKind, SeekFrom};
+use std::path::Path;
+use sha2::{Digest, Sha256};
+
+use crate::rfc9162::TreeHead;
+use crate::root::constant_time_eq;
+
+/// Storage for the entries of a `MerkleLog`.
+pub trait LogStore {
+    /// Appends an entry to the end of the store.
+    fn append(&mut self, entry: &[u8]) -> Result<(), Error>;
+
+    /// Returns the entry at `index`, or `None` if there is no such entry.
+    fn get(&mut self, index: u64) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Returns the number of stored entries.
+    fn len(&self) -> u64;
+
+    /// Returns `true` if the store holds no entries.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl LogStore for Vec<Vec<u8>> {
+    fn append(&mut self, entry: &[u8]) -> Result<(), Error> {
+        self.push(entry.to_vec());
+        Ok(())
+    }
+
+    fn get(&mut self, index: u64) -> Result<Option<Vec<u8>>, Error> {
+        Ok(<[Vec<u8>]>::get(self, index as usize).cloned())
+    }
+
+    fn len(&self) -> u64 {
+        Vec::len(self) as u64
+    }
+}
+
+/// A `LogStore` persisting entries to an append-only file, each entry
+/// prefixed with its length as a big-endian `u32`.
+#[derive(Debug)]
+pub struct FileLogStore {
+    file: File,
+    offsets: Vec<u64>,
+    end: u64,
+}
+
+impl FileLogStore {
+    /// Opens the log file at `path`, creating it if it does not exist.
+    /// Returns an `Error` value if the file could not be opened or is truncated.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let end = file.metadata()?.len();
+
+        let mut offsets = Vec::new();
+        let mut reader = BufReader::new(&file);
+        let mut offset = 0;
+        while offset < end {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            offsets.push(offset);
+            let len = u32::from_be_bytes(len) as u64;
+            reader.seek(SeekFrom::Current(len as i64))?;
+            offset += 4 + len;
+        }
+        if offset != end {
+            return Err(Error::new(ErrorKind::InvalidData, "log file ends with a truncated entry"));
+        }
+
+        Ok(Self { file, offsets, end })
+    }
+}
+
+impl LogStore for FileLogStore {
+    fn append(&mut self, entry: &[u8]) -> Result<(), Error> {
+        if entry.len() > u32::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "log entry is too large"));
+        }
+        let mut record = (entry.len() as u32).to_be_bytes().to_vec();
+        record.extend_from_slice(entry);
+        self.file.write_all(&record)?;
+        self.file.flush()?;
+        self.offsets.push(self.end);
+        self.end += record.len() as u64;
+        Ok(())
+    }
+
+    fn get(&mut self, index: u64) -> Result<Option<Vec<u8>>, Error> {
+        let offset = match self.offsets.get(index as usize) {
+            Some(offset) => *offset,
+            None => return Ok(None),
+        };
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut len = [0u8; 4];
+        self.file.read_exact(&mut len)?;
+        let mut entry = vec![0u8; u32::from_be_bytes(len) as usize];
+        self.file.read_exact(&mut entry)?;
+        Ok(Some(entry))
+    }
+
+    fn len(&self) -> u64 {
+        self.offsets.len() as u64
+    }
+}
+
+/// An append-only, Certificate Transparency style Merkle log.
+///
+/// Hashing follows RFC 6962: leaves are `SHA-256(0x00 || entry)` and
+/// interior nodes `SHA-256(0x01 || left || right)`, with the tree split at
+/// the largest power of two smaller than the number of leaves. Entries are
+/// kept in a `LogStore`; complete subtree hashes are cached in memory so
+/// appends and proofs cost `O(log n)` hashes.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::MerkleLog;
+///
+/// let mut log = MerkleLog::new();
+/// for entry in ["a", "b", "c", "d", "e"].iter() {
+///     log.append(entry.as_bytes()).unwrap();
+/// }
+/// let old_root = log.root_at(3).unwrap();
+/// let proof = log.consistency_proof(3, 5).unwrap();
+/// assert!(proof.verify(&old_root, &log.root()));
+/// ```
+#[derive(Debug)]
+pub struct MerkleLog<S = Vec<Vec<u8>>> {
+    store: S,
+    levels: Vec<Vec<[u8; 32]>>,
+}
+
+impl MerkleLog {
+    /// Constructs a new empty `MerkleLog` keeping its entries in memory.
+    pub fn new() -> Self {
+        Self { store: Vec::new(), levels: vec![Vec::new()] }
+    }
+}
+
+impl Default for MerkleLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: LogStore> MerkleLog<S> {
+    /// Constructs a `MerkleLog` over the entries already present in `store`.
+    /// Returns an `Error` value if the entries could not be read.
+    pub fn open(mut store: S) -> Result<Self, Error> {
+        let mut leaves = Vec::with_capacity(store.len() as usize);
+        for index in 0..store.len() {
+            let entry = store.get(index)?.ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "missing log entry"))?;
+            leaves.push(leaf_hash(&entry));
+        }
+
+        let mut log = Self { store, levels: vec![Vec::new()] };
+        for leaf in leaves {
+            log.push_leaf(leaf);
+        }
+        Ok(log)
+    }
+
+    /// Appends `entry` to the log and returns its index.
+    pub fn append(&mut self, entry: &[u8]) -> Result<u64, Error> {
+        self.store.append(entry)?;
+        self.push_leaf(leaf_hash(entry));
+        Ok(self.len() - 1)
+    }
+
+    fn push_leaf(&mut self, leaf: [u8; 32]) {
+        self.levels[0].push(leaf);
+        let mut height = 0;
+        while self.levels[height].len().is_multiple_of(2) {
+            let level = &self.levels[height];
+            let parent = node_hash(&level[level.len() - 2], &level[level.len() - 1]);
+            if self.levels.len() == height + 1 {
+                self.levels.push(Vec::new());
+            }
+            self.levels[height + 1].push(parent);
+            height += 1;
+        }
+    }
+
+    /// Returns the entry at `index`.
+    pub fn get(&mut self, index: u64) -> Result<Option<Vec<u8>>, Error> {
+        self.store.get(index)
+    }
+
+    /// Returns the number of entries in the log.
+    pub fn len(&self) -> u64 {
+        self.levels[0].len() as u64
+    }
+
+    /// Returns `true` if the log has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.levels[0].is_empty()
+    }
+
+    /// Returns the underlying store.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Returns the current root. The root of an empty log is the hash of the empty string.
+    pub fn root(&self) -> [u8; 32] {
+        self.root_at(self.len()).unwrap()
+    }
+
+    /// Returns the root of the log as it was when it held `size` entries.
+    pub fn root_at(&self, size: u64) -> Option<[u8; 32]> {
+        match size {
+            0 => Some(Sha256::digest(b"").into()),
+            _ if size <= self.len() => Some(self.subtree(0, size)),
+            _ => None,
+        }
+    }
+
+    /// Returns the RFC 9162 tree head of the log at its current size, for
+    /// the log to sign. `timestamp` is in milliseconds since the epoch.
+    pub fn tree_head(&self, timestamp: u64) -> TreeHead {
+        TreeHead { timestamp, tree_size: self.len(), root_hash: self.root(), extensions: Vec::new() }
+    }
+
+    /// Returns a proof that the entry at `index` is included in the log of `size` entries.
+    pub fn inclusion_proof(&self, index: u64, size: u64) -> Option<InclusionProof> {
+        if index >= size || size > self.len() {
+            return None;
+        }
+        let mut path = Vec::new();
+        self.path(index, 0, size, &mut path);
+        Some(InclusionProof { leaf_index: index, tree_size: size, path })
+    }
+
+    /// Returns a proof that the log of `old_size` entries is a prefix of the log of `new_size` entries.
+    pub fn consistency_proof(&self, old_size: u64, new_size: u64) -> Option<ConsistencyProof> {
+        if old_size == 0 || old_size > new_size || new_size > self.len() {
+            return None;
+        }
+        let mut path = Vec::new();
+        self.subproof(old_size, 0, new_size, true, &mut path);
+        Some(ConsistencyProof { old_size, new_size, path })
+    }
+
+    /// Returns the hash of the leaves in `start..end`.
+    fn subtree(&self, start: u64, end: u64) -> [u8; 32] {
+        let n = end - start;
+        if n.is_power_of_two() && start.is_multiple_of(n) {
+            let height = n.trailing_zeros() as usize;
+            return self.levels[height][(start / n) as usize];
+        }
+        let k = split(n);
+        node_hash(&self.subtree(start, start + k), &self.subtree(start + k, end))
+    }
+
+    fn path(&self, index: u64, start: u64, end: u64, path: &mut Vec<[u8; 32]>) {
+        let n = end - start;
+        if n == 1 {
+            return;
+        }
+        let k = split(n);
+        if index < k {
+            self.path(index, start, start + k, path);
+            path.push(self.subtree(start + k, end));
+        } else {
+            self.path(index - k, start + k, end, path);
+            path.push(self.subtree(start, start + k));
+        }
+    }
+
+    fn subproof(&self, m: u64, start: u64, end: u64, complete: bool, path: &mut Vec<[u8; 32]>) {
+        let n = end - start;
+        if m == n {
+            if !complete {
+                path.push(self.subtree(start, end));
+            }
+            return;
+        }
+        let k = split(n);
+        if m <= k {
+            self.subproof(m, start, start + k, complete, path);
+            path.push(self.subtree(start + k, end));
+        } else {
+            self.subproof(m - k, start + k, end, false, path);
+            path.push(self.subtree(start, start + k));
+        }
+    }
+}
+
+/// A proof that an entry is included in a `MerkleLog` of a given size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InclusionProof {
+    /// Index of the proven entry.
+    pub leaf_index: u64,
+    /// Size of the log the proof was generated for.
+    pub tree_size: u64,
+    /// Sibling hashes from the leaf up to the root.
+    pub path: Vec<[u8; 32]>,
+}
+
+impl InclusionProof {
+    /// Returns `true` if `entry` is included at `leaf_index` in the log with the given `root`.
+    pub fn verify(&self, root: &[u8; 32], entry: &[u8]) -> bool {
+        self.verify_leaf(root, &leaf_hash(entry))
+    }
+
+    /// Like `verify`, but takes the already computed leaf hash.
+    pub fn verify_leaf(&self, root: &[u8; 32], leaf: &[u8; 32]) -> bool {
+        if self.leaf_index >= self.tree_size {
+            return false;
+        }
+        let (mut fn_, mut sn) = (self.leaf_index, self.tree_size - 1);
+        let mut r = *leaf;
+        for p in &self.path {
+            if sn == 0 {
+                return false;
+            }
+            if fn_ & 1 == 1 || fn_ == sn {
+                r = node_hash(p, &r);
+                while fn_ & 1 == 0 && fn_ != 0 {
+                    fn_ >>= 1;
+                    sn >>= 1;
+                }
+            } else {
+                r = node_hash(&r, p);
+            }
+            fn_ >>= 1;
+            sn >>= 1;
+        }
+        sn == 0 && constant_time_eq(&r, root)
+    }
+}
+
+/// A proof that one `MerkleLog` size is a prefix of a larger one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsistencyProof {
+    /// Size of the older log.
+    pub old_size: u64,
+    /// Size of the newer log.
+    pub new_size: u64,
+    /// The proof hashes.
+    pub path: Vec<[u8; 32]>,
+}
+
+impl ConsistencyProof {
+    /// Returns `true` if the log with root `old_root` at `old_size` entries is a
+    /// prefix of the log with root `new_root` at `new_size` entries.
+    pub fn verify(&self, old_root: &[u8; 32], new_root: &[u8; 32]) -> bool {
+        if self.old_size == 0 || self.old_size > self.new_size {
+            return false;
+        }
+        if self.old_size == self.new_size {
+            return self.path.is_empty() && constant_time_eq(old_root, new_root);
+        }
+
+        let mut path = self.path.clone();
+        if self.old_size.is_power_of_two() {
+            path.insert(0, *old_root);
+        }
+        let (mut fn_, mut sn) = (self.old_size - 1, self.new_size - 1);
+        while fn_ & 1 == 1 {
+            fn_ >>= 1;
+            sn >>= 1;
+        }
+
+        let mut iter = path.iter();
+        let first = match iter.next() {
+            Some(first) => *first,
+            None => return false,
+        };
+        let (mut fr, mut sr) = (first, first);
+        for c in iter {
+            if sn == 0 {
+                return false;
+            }
+            if fn_ & 1 == 1 || fn_ == sn {
+                fr = node_hash(c, &fr);
+                sr = node_hash(c, &sr);
+                while fn_ & 1 == 0 && fn_ != 0 {
+                    fn_ >>= 1;
+                    sn >>= 1;
+                }
+            } else {
+                sr = node_hash(&sr, c);
+            }
+            fn_ >>= 1;
+            sn >>= 1;
+        }
+        sn == 0 && constant_time_eq(&fr, old_root) & constant_time_eq(&sr, new_root)
+    }
+}
+
+/// Returns the largest power of two smaller than `n`.
+fn split(n: u64) -> u64 {
+    1 << (63 - (n - 1).leading_zeros())
+}
+
+pub(crate) fn leaf_hash(entry: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0x00]);
+    hasher.update(entry);
+    hasher.finalize().into()
+}
+
+pub(crate) fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0x01]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
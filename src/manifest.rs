@@ -0,0 +1,261 @@
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use crate::dir::{entry_hash, file_root, link_target, SYMLINK_MODE};
+use crate::{DirTree, HashTree, Throttle};
+
+const MAGIC: &str = "hashtree-manifest";
+const VERSION: u32 = 1;
+
+/// A file recorded in a `Manifest`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    /// Path of the file relative to the root directory, using `/` as separator.
+    pub path: String,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Root hash of the file's `HashTree`.
+    pub root: Vec<u8>,
+    /// Leaf hashes of the file, if they were included in the manifest.
+    /// Always `None` for empty files.
+    pub leaves: Option<Vec<Vec<u8>>>,
+    /// `true` for a symbolic link recorded with `Symlinks::Record`, whose
+    /// contents are its target path.
+    pub link: bool,
+}
+
+/// A serializable description of a `DirTree`.
+///
+/// The manifest is a line-based text format:
+///
+/// ```text
+/// hashtree-manifest 1
+/// block-size 4096
+/// root <hex>
+/// file <hex root> <size> <path>
+/// leaf <hex>
+/// link <hex root> <size> <path>
+/// ```
+///
+/// `leaf` lines are optional and belong to the preceding `file` or `link`
+/// line. `link` lines record symbolic links kept with `Symlinks::Record`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    block_size: usize,
+    root: Option<Vec<u8>>,
+    entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Constructs a `Manifest` describing `tree`. Per-file leaf hashes are
+    /// included only if `include_leaves` is `true`.
+    /// Returns an `Error` value if the root of `tree` commits to more than the
+    /// manifest records: in Git mode, or with `DirTree::include_permissions`
+    /// or `DirTree::include_mtime`.
+    pub fn from_dir_tree(tree: &DirTree, include_leaves: bool) -> Result<Self, Error> {
+        if !tree.commits_to_contents_only() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "manifests record neither Git trees nor file metadata",
+            ));
+        }
+        let entries = tree
+            .files()
+            .iter()
+            .map(|file| ManifestEntry {
+                path: file.path.clone(),
+                size: file.size,
+                root: file.root(),
+                leaves: if include_leaves && file.size > 0 {
+                    Some(file.tree.leaf_hashes().collect())
+                } else {
+                    None
+                },
+                link: file.mode == SYMLINK_MODE,
+            })
+            .collect();
+
+        Ok(Self {
+            block_size: tree.block_size(),
+            root: tree.root_hash().map(|root| hex::decode(root).unwrap()),
+            entries,
+        })
+    }
+
+    /// Returns the block size the files were hashed with.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Returns the directory root hash as an `Option<String>`.
+    pub fn root_hash(&self) -> Option<String> {
+        self.root.as_ref().map(hex::encode)
+    }
+
+    /// Returns the recorded files, sorted by path.
+    pub fn entries(&self) -> &[ManifestEntry] {
+        &self.entries
+    }
+
+    /// Writes the manifest to `writer`.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        writeln!(writer, "{} {}", MAGIC, VERSION)?;
+        writeln!(writer, "block-size {}", self.block_size)?;
+        if let Some(root) = &self.root {
+            writeln!(writer, "root {}", hex::encode(root))?;
+        }
+        for entry in &self.entries {
+            if entry.path.contains('\n') {
+                return Err(invalid(format!("path contains a newline: {:?}", entry.path)));
+            }
+            let key = if entry.link { "link" } else { "file" };
+            writeln!(writer, "{} {} {} {}", key, hex::encode(&entry.root), entry.size, entry.path)?;
+            for leaf in entry.leaves.iter().flatten() {
+                writeln!(writer, "leaf {}", hex::encode(leaf))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a manifest from `reader`.
+    /// Returns an `Error` value if the manifest is malformed, its root does
+    /// not match the recorded files or the leaf hashes of a file do not match
+    /// its root.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::Manifest;
+    ///
+    /// let text = "hashtree-manifest 1\nblock-size 4096\n";
+    /// let manifest = Manifest::read_from(text.as_bytes()).unwrap();
+    /// assert!(manifest.entries().is_empty());
+    /// ```
+    pub fn read_from<R: BufRead>(reader: R) -> Result<Self, Error> {
+        let mut lines = reader.lines();
+
+        let header = lines.next().ok_or_else(|| invalid("empty manifest"))??;
+        if header != format!("{} {}", MAGIC, VERSION) {
+            return Err(invalid(format!("unsupported manifest header: {:?}", header)));
+        }
+
+        let mut block_size = None;
+        let mut root = None;
+        let mut entries: Vec<ManifestEntry> = Vec::new();
+
+        for line in lines {
+            let line = line?;
+            let (key, value) = line.split_once(' ').ok_or_else(|| invalid(format!("malformed line: {:?}", line)))?;
+            match key {
+                "block-size" => block_size = Some(value.parse().map_err(|_| invalid("invalid block size"))?),
+                "root" => root = Some(decode_hex(value)?),
+                "file" | "link" => {
+                    let mut parts = value.splitn(3, ' ');
+                    let (root, size, path) = match (parts.next(), parts.next(), parts.next()) {
+                        (Some(root), Some(size), Some(path)) => (root, size, path),
+                        _ => return Err(invalid(format!("malformed file line: {:?}", line))),
+                    };
+                    entries.push(ManifestEntry {
+                        path: path.to_string(),
+                        size: size.parse().map_err(|_| invalid("invalid file size"))?,
+                        root: decode_hex(root)?,
+                        leaves: None,
+                        link: key == "link",
+                    });
+                }
+                "leaf" => {
+                    let entry = entries.last_mut().ok_or_else(|| invalid("leaf line before any file"))?;
+                    entry.leaves.get_or_insert_with(Vec::new).push(decode_hex(value)?);
+                }
+                _ => return Err(invalid(format!("unknown manifest key: {:?}", key))),
+            }
+        }
+
+        let manifest = Self {
+            block_size: block_size.ok_or_else(|| invalid("missing block size"))?,
+            root,
+            entries,
+        };
+        if manifest.computed_root()? != manifest.root {
+            return Err(invalid("manifest root does not match its entries"));
+        }
+        for entry in &manifest.entries {
+            if let Some(leaves) = &entry.leaves {
+                let tree = HashTree::new(manifest.block_size).from_leaf_hashes(leaves.iter().cloned())?;
+                if entry.size == 0 || file_root(&tree) != entry.root {
+                    return Err(invalid(format!("leaf hashes of {:?} do not match its root", entry.path)));
+                }
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Recomputes the directory root from the recorded entries.
+    fn computed_root(&self) -> Result<Option<Vec<u8>>, Error> {
+        let leaves = self.entries.iter().map(|e| entry_hash(&e.path, &e.root));
+        let tree = HashTree::new(self.block_size).from_leaf_hashes(leaves)?;
+        Ok(tree.root())
+    }
+
+    /// Verifies the directory at `path` against the manifest and returns the
+    /// relative paths of the files that are missing or whose contents differ.
+    /// Files not listed in the manifest are ignored.
+    pub fn verify<P: AsRef<Path>>(&self, path: P) -> Result<Vec<String>, Error> {
+        self.verify_files(path.as_ref(), None)
+    }
+
+    /// Like `verify`, but reads the files no faster than `throttle` allows,
+    /// so a directory can be checked in the background.
+    pub fn verify_throttled<P: AsRef<Path>>(&self, path: P, throttle: &Throttle) -> Result<Vec<String>, Error> {
+        self.verify_files(path.as_ref(), Some(throttle))
+    }
+
+    fn verify_files(&self, path: &Path, throttle: Option<&Throttle>) -> Result<Vec<String>, Error> {
+        let mut mismatches = Vec::new();
+        for entry in &self.entries {
+            let full = path.join(&entry.path);
+            let (root, size) = if entry.link {
+                if !std::fs::symlink_metadata(&full).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+                    mismatches.push(entry.path.clone());
+                    continue;
+                }
+                let target = link_target(&full)?;
+                let tree = HashTree::new(self.block_size).from_slice(target.as_bytes())?;
+                (file_root(&tree), target.len() as u64)
+            } else {
+                if !full.is_file() {
+                    mismatches.push(entry.path.clone());
+                    continue;
+                }
+                let mut tree = HashTree::new(self.block_size);
+                if let Some(throttle) = throttle {
+                    tree = tree.throttle(throttle.clone());
+                }
+                let tree = tree.from_file(&full)?;
+                (file_root(&tree), std::fs::metadata(&full)?.len())
+            };
+            if root != entry.root || size != entry.size {
+                mismatches.push(entry.path.clone());
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+impl DirTree {
+    /// Returns a `Manifest` describing this `DirTree`.
+    /// Returns an `Error` value if the root commits to more than the manifest
+    /// records, see `Manifest::from_dir_tree`.
+    pub fn manifest(&self, include_leaves: bool) -> Result<Manifest, Error> {
+        Manifest::from_dir_tree(self, include_leaves)
+    }
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, Error> {
+    hex::decode(value).map_err(|e| invalid(format!("invalid hex {:?}: {}", value, e)))
+}
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> Error {
+    Error::new(ErrorKind::InvalidData, error)
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use sha2::{Digest, Sha256};
+
+use crate::sparse::{Key, SparseMerkleTree, SparseProof};
+
+/// A key-value map whose contents are committed to by a single root hash.
+///
+/// Keys are placed in a `SparseMerkleTree` at the SHA-256 digest of their
+/// bytes, and the leaf for a key is the SHA-256 digest of its value, so any
+/// party holding the root can check a `(key, value)` pair against a proof.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::AuthenticatedMap;
+///
+/// let mut map = AuthenticatedMap::new();
+/// map.insert("alice", "100");
+/// map.insert("bob", "250");
+///
+/// let root = map.root();
+/// let proof = map.proof(&"bob");
+/// assert!(AuthenticatedMap::<&str, &str>::verify(&root, &"bob", &"250", &proof));
+/// assert!(!AuthenticatedMap::<&str, &str>::verify(&root, &"bob", &"999", &proof));
+/// ```
+#[derive(Debug, Clone)]
+pub struct AuthenticatedMap<K, V> {
+    entries: HashMap<K, V>,
+    tree: SparseMerkleTree,
+}
+
+impl<K, V> AuthenticatedMap<K, V>
+where
+    K: AsRef<[u8]> + Hash + Eq,
+    V: AsRef<[u8]>,
+{
+    /// Constructs a new empty `AuthenticatedMap`.
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            tree: SparseMerkleTree::new(),
+        }
+    }
+
+    /// Inserts a key-value pair, returning the previous value for the key.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.tree.insert_leaf(tree_key(&key), leaf_hash(&value));
+        self.entries.insert(key, value)
+    }
+
+    /// Returns the value stored for `key`.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key)
+    }
+
+    /// Removes `key` from the map, returning its value.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let value = self.entries.remove(key)?;
+        self.tree.remove(&tree_key(key));
+        Some(value)
+    }
+
+    /// Returns `true` if the map contains `key`.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns an iterator over the entries in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter()
+    }
+
+    /// Returns the root commitment of the map.
+    pub fn root(&self) -> [u8; 32] {
+        self.tree.root()
+    }
+
+    /// Returns the root commitment of the map as a hex `String`.
+    pub fn root_hash(&self) -> String {
+        self.tree.root_hash()
+    }
+
+    /// Returns a proof for the entry stored at `key`.
+    pub fn proof(&self, key: &K) -> SparseProof {
+        self.tree.proof(&tree_key(key))
+    }
+
+    /// Returns a proof that `key` is not in the map, or `None` if it is.
+    pub fn absence_proof(&self, key: &K) -> Option<SparseProof> {
+        self.tree.absence_proof(&tree_key(key))
+    }
+
+    /// Returns `true` if `proof` shows that `key` is not in the map committed to by `root`.
+    pub fn verify_absence(root: &[u8; 32], key: &K, proof: &SparseProof) -> bool {
+        proof.verify_absence(root, &tree_key(key))
+    }
+
+    /// Returns `true` if `proof` shows that `key` maps to `value` under `root`.
+    pub fn verify(root: &[u8; 32], key: &K, value: &V, proof: &SparseProof) -> bool {
+        proof.verify(root, &tree_key(key), leaf_hash(value))
+    }
+}
+
+impl<K, V> Default for AuthenticatedMap<K, V>
+where
+    K: AsRef<[u8]> + Hash + Eq,
+    V: AsRef<[u8]>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn tree_key<K: AsRef<[u8]>>(key: &K) -> Key {
+    Sha256::digest(key.as_ref()).into()
+}
+
+fn leaf_hash<V: AsRef<[u8]>>(value: &V) -> [u8; 32] {
+    Sha256::digest(value.as_ref()).into()
+}
@@ -0,0 +1,151 @@
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+/// A phase of building a `HashTree`, reported to `Metrics::phase_completed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Reading the input and hashing its blocks into leaves.
+    Leaves,
+    /// Hashing the interior nodes above the leaves.
+    Build,
+}
+
+/// Receives statistics while a `HashTree` is built, e.g. to export hashing
+/// throughput to a monitoring system.
+///
+/// Every method has an empty default implementation, so implementors only
+/// override what they record. Methods are called from the building thread,
+/// `bytes_hashed` and `blocks_processed` once per block, so they should be
+/// cheap. Phase times are only measured with the `std` feature.
+pub trait Metrics: fmt::Debug + Send + Sync {
+    /// Called with the length of every block hashed into a leaf.
+    fn bytes_hashed(&self, _bytes: u64) {}
+
+    /// Called with the number of blocks hashed into leaves.
+    fn blocks_processed(&self, _blocks: u64) {}
+
+    /// Called once a tree is built with its total number of nodes.
+    fn nodes_built(&self, _nodes: u64) {}
+
+    /// Called when a build phase ends, with the time it took.
+    fn phase_completed(&self, _phase: Phase, _elapsed: Duration) {}
+}
+
+/// A `Metrics` implementation summing everything it receives in atomic
+/// counters.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Arc;
+/// use hashtree::{CounterMetrics, HashTree};
+///
+/// let metrics = Arc::new(CounterMetrics::new());
+/// HashTree::new(4).metrics(metrics.clone()).from_data(&mut &b"abcdefghij"[..]).unwrap();
+/// assert_eq!(metrics.bytes(), 10);
+/// assert_eq!(metrics.blocks(), 3);
+/// assert_eq!(metrics.nodes(), 7);
+/// ```
+#[derive(Debug, Default)]
+pub struct CounterMetrics {
+    bytes: AtomicU64,
+    blocks: AtomicU64,
+    nodes: AtomicU64,
+    leaves_nanos: AtomicU64,
+    build_nanos: AtomicU64,
+}
+
+impl CounterMetrics {
+    /// Constructs a new `CounterMetrics` with all counters at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of bytes hashed into leaves.
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of blocks hashed into leaves.
+    pub fn blocks(&self) -> u64 {
+        self.blocks.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of nodes of the trees built.
+    pub fn nodes(&self) -> u64 {
+        self.nodes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the total time spent in `phase`.
+    pub fn elapsed(&self, phase: Phase) -> Duration {
+        let nanos = match phase {
+            Phase::Leaves => &self.leaves_nanos,
+            Phase::Build => &self.build_nanos,
+        };
+        Duration::from_nanos(nanos.load(Ordering::Relaxed))
+    }
+}
+
+impl Metrics for CounterMetrics {
+    fn bytes_hashed(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn blocks_processed(&self, blocks: u64) {
+        self.blocks.fetch_add(blocks, Ordering::Relaxed);
+    }
+
+    fn nodes_built(&self, nodes: u64) {
+        self.nodes.fetch_add(nodes, Ordering::Relaxed);
+    }
+
+    fn phase_completed(&self, phase: Phase, elapsed: Duration) {
+        let nanos = match phase {
+            Phase::Leaves => &self.leaves_nanos,
+            Phase::Build => &self.build_nanos,
+        };
+        nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl<M: Metrics + ?Sized> Metrics for alloc::sync::Arc<M> {
+    fn bytes_hashed(&self, bytes: u64) {
+        (**self).bytes_hashed(bytes)
+    }
+
+    fn blocks_processed(&self, blocks: u64) {
+        (**self).blocks_processed(blocks)
+    }
+
+    fn nodes_built(&self, nodes: u64) {
+        (**self).nodes_built(nodes)
+    }
+
+    fn phase_completed(&self, phase: Phase, elapsed: Duration) {
+        (**self).phase_completed(phase, elapsed)
+    }
+}
+
+/// Measures the duration of a build phase. Without the `std` feature there
+/// is no clock and nothing is measured.
+pub(crate) struct Timer {
+    #[cfg(feature = "std")]
+    start: std::time::Instant,
+}
+
+impl Timer {
+    pub(crate) fn start() -> Self {
+        Self {
+            #[cfg(feature = "std")]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    pub(crate) fn elapsed(&self) -> Option<Duration> {
+        #[cfg(feature = "std")]
+        return Some(self.start.elapsed());
+        #[cfg(not(feature = "std"))]
+        return None;
+    }
+}
@@ -0,0 +1,388 @@
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind};
+use std::ops::Range;
+use std::path::Path;
+
+use memmap2::{Mmap, MmapMut};
+
+use crate::hasher::{fingerprint, PairHasher};
+use crate::io::to_usize;
+use crate::proof::{Proof, ProofLevel};
+use crate::root::RootHash;
+use crate::store::NodeStore;
+use crate::tree::{
+    FLAG_LEAF_LENGTHS, FLAG_LEVEL_ORDER, FORMAT_VERSION, HEADER_LEN as TREE_HEADER_LEN, MAGIC as TREE_MAGIC, PADDING_DUPLICATE,
+    PADDING_DUPLICATE_UNLESS_SINGLE, PADDING_PROMOTE, PADDING_ZERO,
+};
+use crate::{HashTree, Padding};
+
+const MAGIC: &[u8; 8] = b"hashtree";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 24;
+const INITIAL_CAPACITY: usize = 1024;
+
+/// A `NodeStore` laying out fixed-size node hashes in a memory-mapped file.
+///
+/// The file starts with a 24-byte header holding the magic `hashtree`, the
+/// format version, the hash length and the number of stored nodes, all
+/// big-endian, followed by the hashes in storage order. Only the pages that
+/// are touched are held in memory, so trees much larger than RAM can be built
+/// and queried. The file grows geometrically as nodes are appended.
+///
+/// The file must not be modified by other processes while it is mapped.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, MmapStore};
+///
+/// let path = std::env::temp_dir().join(format!("mmap-doc-{}", std::process::id()));
+/// let store = MmapStore::create(&path, 32).unwrap();
+/// let tree = HashTree::with_store(1, store).from_data(&mut &b"abcdef"[..]).unwrap();
+/// assert_eq!(tree, HashTree::new(1).from_data(&mut &b"abcdef"[..]).unwrap());
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct MmapStore {
+    file: File,
+    map: MmapMut,
+    hash_len: usize,
+    len: usize,
+    capacity: usize,
+}
+
+impl MmapStore {
+    /// Creates an empty store at `path` for hashes of `hash_len` bytes,
+    /// replacing any existing file.
+    /// Returns an `Error` value if the file could not be created or mapped.
+    pub fn create<P: AsRef<Path>>(path: P, hash_len: usize) -> Result<Self, Error> {
+        if hash_len == 0 || hash_len > u32::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid hash length"));
+        }
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        file.set_len((HEADER_LEN + INITIAL_CAPACITY * hash_len) as u64)?;
+        let mut store = Self {
+            map: map(&file)?,
+            file,
+            hash_len,
+            len: 0,
+            capacity: INITIAL_CAPACITY,
+        };
+        store.map[..8].copy_from_slice(MAGIC);
+        store.map[8..12].copy_from_slice(&VERSION.to_be_bytes());
+        store.map[12..16].copy_from_slice(&(hash_len as u32).to_be_bytes());
+        store.write_len();
+        Ok(store)
+    }
+
+    /// Opens an existing store at `path`.
+    /// Returns an `Error` value if the file could not be opened or is not a valid store.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let file_len = to_usize(file.metadata()?.len())?;
+        if file_len < HEADER_LEN {
+            return Err(invalid("node store file is too short"));
+        }
+        let map = map(&file)?;
+        if &map[..8] != MAGIC {
+            return Err(invalid("not a node store file"));
+        }
+        if u32::from_be_bytes(map[8..12].try_into().unwrap()) != VERSION {
+            return Err(invalid("unsupported node store version"));
+        }
+        let hash_len = u32::from_be_bytes(map[12..16].try_into().unwrap()) as usize;
+        let len = to_usize(u64::from_be_bytes(map[16..24].try_into().unwrap()))?;
+        if hash_len == 0 {
+            return Err(invalid("invalid hash length"));
+        }
+        let capacity = (file_len - HEADER_LEN) / hash_len;
+        if len > capacity {
+            return Err(invalid("node store file is truncated"));
+        }
+        Ok(Self { file, map, hash_len, len, capacity })
+    }
+
+    /// Returns the length of the stored hashes.
+    pub fn hash_len(&self) -> usize {
+        self.hash_len
+    }
+
+    fn write_len(&mut self) {
+        self.map[16..24].copy_from_slice(&(self.len as u64).to_be_bytes());
+    }
+
+    fn offset(&self, index: usize) -> usize {
+        HEADER_LEN + index * self.hash_len
+    }
+
+    /// Grows the file so that it can hold at least `capacity` nodes.
+    fn reserve(&mut self, capacity: usize) -> Result<(), Error> {
+        if capacity <= self.capacity {
+            return Ok(());
+        }
+        let capacity = capacity.max(self.capacity * 2);
+        self.map.flush()?;
+        self.file.set_len((HEADER_LEN + capacity * self.hash_len) as u64)?;
+        self.map = map(&self.file)?;
+        self.capacity = capacity;
+        Ok(())
+    }
+}
+
+impl NodeStore for MmapStore {
+    fn get(&self, index: usize) -> Option<Vec<u8>> {
+        if index >= self.len {
+            return None;
+        }
+        let offset = self.offset(index);
+        Some(self.map[offset..offset + self.hash_len].to_vec())
+    }
+
+    fn put(&mut self, index: usize, hash: Vec<u8>) -> Result<(), Error> {
+        if hash.len() != self.hash_len {
+            return Err(Error::new(ErrorKind::InvalidInput, "hash has the wrong length for this store"));
+        }
+        if index > self.len {
+            return Err(Error::new(ErrorKind::InvalidInput, "node index out of bounds"));
+        }
+        if index == self.len {
+            self.reserve(self.len + 1)?;
+            self.len += 1;
+            self.write_len();
+        }
+        let offset = self.offset(index);
+        self.map[offset..offset + self.hash_len].copy_from_slice(&hash);
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<(), Error> {
+        self.len = self.len.min(len);
+        self.write_len();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.map.flush()
+    }
+
+    /// Shrinks the file to the stored nodes, dropping the room reserved for
+    /// appending.
+    fn shrink_to_fit(&mut self) -> Result<(), Error> {
+        if self.capacity == self.len {
+            return Ok(());
+        }
+        self.map.flush()?;
+        self.file.set_len(self.offset(self.len) as u64)?;
+        self.map = map(&self.file)?;
+        self.capacity = self.len;
+        Ok(())
+    }
+}
+
+/// A read-only view of a tree serialized by `HashTree::write_to` or
+/// `HashTree::write_level_order`, serving hashes and proofs straight from a
+/// memory mapping of the file.
+///
+/// Opening only parses the 48-byte header, so it takes the same time for a
+/// tree of any size; hashes are read from the pages they lie on when asked
+/// for, and proofs are identical to those of the deserialized tree. The
+/// hasher is not needed to serve proofs, so it is not checked on opening;
+/// use `is_hashed_with` to check it.
+///
+/// The file must not be modified while it is mapped.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, MmapTree};
+///
+/// let path = std::env::temp_dir().join(format!("mmap-tree-doc-{}", std::process::id()));
+/// let tree = HashTree::new(4).from_slice(b"a stored tree of many blocks").unwrap();
+/// tree.write_to(std::fs::File::create(&path).unwrap()).unwrap();
+///
+/// let mapped = MmapTree::open(&path).unwrap();
+/// assert_eq!(mapped.root_hash(), tree.root_hash());
+/// assert_eq!(mapped.proof(3), tree.proof(3));
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct MmapTree {
+    map: Mmap,
+    padding: Padding,
+    block_size: u64,
+    num_blocks: usize,
+    arity: usize,
+    hash_len: usize,
+    /// Node ranges of every level in storage order, leaves first.
+    levels: Vec<Range<usize>>,
+    /// Byte offset of the first node of every level in the file.
+    offsets: Vec<usize>,
+}
+
+impl MmapTree {
+    /// Maps the serialized tree at `path`.
+    /// Returns an `Error` value if the file could not be opened or mapped, is
+    /// not a valid tree, or was written with an unsupported format version
+    /// or padding strategy.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        // SAFETY: the mapping is read-only and the file must not be modified
+        // while mapped, as documented on `MmapTree`.
+        let map = unsafe { Mmap::map(&file)? };
+        if map.len() < TREE_HEADER_LEN || &map[..4] != TREE_MAGIC {
+            return Err(invalid("not a serialized hash tree"));
+        }
+        let field = |range: Range<usize>| map[range].iter().fold(0u64, |n, &b| n << 8 | b as u64);
+        if field(4..6) != FORMAT_VERSION as u64 {
+            return Err(invalid("unsupported format version"));
+        }
+        let padding = match map[6] {
+            PADDING_DUPLICATE => Padding::Duplicate,
+            PADDING_ZERO => Padding::Zero,
+            PADDING_PROMOTE => Padding::Promote,
+            PADDING_DUPLICATE_UNLESS_SINGLE => Padding::DuplicateUnlessSingle,
+            _ => return Err(invalid("unsupported padding strategy")),
+        };
+        if map[7] & !(FLAG_LEAF_LENGTHS | FLAG_LEVEL_ORDER) != 0 {
+            return Err(invalid("unsupported flags"));
+        }
+        let (block_size, num_blocks) = (field(16..24), to_usize(field(24..32))?);
+        let (arity, hash_len, num_nodes) = (to_usize(field(32..36))?, to_usize(field(36..40))?, to_usize(field(40..48))?);
+        if arity < 2 || (hash_len == 0 && num_nodes > 0) {
+            return Err(invalid("invalid arity or hash length"));
+        }
+
+        let levels = HashTree::new(0).arity(arity).padding(padding).level_ranges(num_blocks);
+        if levels.last().map_or(0, |level| level.end) != num_nodes {
+            return Err(invalid("node count does not match the number of blocks"));
+        }
+        let lengths = if map[7] & FLAG_LEAF_LENGTHS != 0 { num_blocks } else { 0 };
+        let expected = num_nodes
+            .checked_mul(hash_len)
+            .and_then(|nodes| lengths.checked_mul(8).and_then(|lengths| nodes.checked_add(lengths)))
+            .and_then(|body| body.checked_add(TREE_HEADER_LEN));
+        if expected != Some(map.len()) {
+            return Err(invalid("serialized hash tree is truncated"));
+        }
+        let offsets = if map[7] & FLAG_LEVEL_ORDER != 0 {
+            let mut offset = TREE_HEADER_LEN;
+            let mut offsets: Vec<usize> = levels
+                .iter()
+                .rev()
+                .map(|level| {
+                    let start = offset;
+                    offset += level.len() * hash_len;
+                    start
+                })
+                .collect();
+            offsets.reverse();
+            offsets
+        } else {
+            levels.iter().map(|level| TREE_HEADER_LEN + level.start * hash_len).collect()
+        };
+        Ok(Self { map, padding, block_size, num_blocks, arity, hash_len, levels, offsets })
+    }
+
+    /// Returns the root hash, or `None` if the tree is empty.
+    pub fn root_hash(&self) -> Option<RootHash> {
+        self.root_hash_bytes().map(|root| RootHash::new(root.to_vec()))
+    }
+
+    /// Returns the bytes of the root hash in the mapping, or `None` if the
+    /// tree is empty.
+    pub fn root_hash_bytes(&self) -> Option<&[u8]> {
+        let root = self.levels.len().checked_sub(1)?;
+        Some(self.node(root, 0))
+    }
+
+    /// Returns the bytes of the hash of the block at `index` in the mapping,
+    /// or `None` if there is no such block.
+    pub fn leaf_hash(&self, index: usize) -> Option<&[u8]> {
+        if index >= self.num_blocks {
+            return None;
+        }
+        Some(self.node(0, index))
+    }
+
+    /// Returns an inclusion proof for the block at `index`, or `None` if
+    /// there is no such block, see `HashTree::proof`.
+    pub fn proof(&self, index: usize) -> Option<Proof> {
+        if index >= self.num_blocks {
+            return None;
+        }
+        let mut position = index;
+        let mut levels = Vec::with_capacity(self.levels.len() - 1);
+        for level in 0..self.levels.len() - 1 {
+            let group = position / self.arity * self.arity;
+            let siblings = (group..group + self.arity)
+                .filter(|&i| i != position)
+                .map(|i| self.sibling(level, i))
+                .collect();
+            levels.push(ProofLevel { position: position % self.arity, siblings });
+            position /= self.arity;
+        }
+        Some(Proof { leaf_index: index as u64, levels })
+    }
+
+    /// Returns `true` if the tree was built with `hasher`, judged by the
+    /// fingerprint of the hash algorithm in the header.
+    pub fn is_hashed_with(&self, hasher: &dyn PairHasher) -> bool {
+        self.map[8..16] == fingerprint(hasher)
+    }
+
+    /// Returns the number of blocks of the tree.
+    pub fn num_blocks(&self) -> usize {
+        self.num_blocks
+    }
+
+    /// Returns the block size the tree was built with.
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// Returns the arity of the tree.
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Returns the padding strategy of the tree.
+    pub fn padding(&self) -> Padding {
+        self.padding
+    }
+
+    /// Returns the length of the stored hashes.
+    pub fn hash_len(&self) -> usize {
+        self.hash_len
+    }
+
+    /// Returns node `index` of `level` in the mapping.
+    fn node(&self, level: usize, index: usize) -> &[u8] {
+        let offset = self.offsets[level] + index * self.hash_len;
+        &self.map[offset..offset + self.hash_len]
+    }
+
+    /// Returns node `index` of `level` as a proof sibling: placeholders of
+    /// promoted nodes, stored as zeroes, are empty as in the tree itself.
+    fn sibling(&self, level: usize, index: usize) -> Vec<u8> {
+        let hash = self.node(level, index);
+        if self.padding == Padding::Promote && hash.iter().all(|&byte| byte == 0) {
+            return Vec::new();
+        }
+        hash.to_vec()
+    }
+}
+
+fn map(file: &File) -> Result<MmapMut, Error> {
+    // SAFETY: the file is owned by the store and must not be modified
+    // externally while mapped, as documented on `MmapStore`.
+    unsafe { MmapMut::map_mut(file) }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
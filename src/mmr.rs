@@ -0,0 +1,225 @@
+use sha2::{Digest, Sha256};
+
+use crate::root::constant_time_eq;
+
+/// A Merkle Mountain Range.
+///
+/// Nodes are stored in a single vector in insertion (post-order) order.
+/// Appending a leaf only hashes the parents it completes, so appends cost
+/// `O(log n)` and never touch the rest of the structure. The root is
+/// obtained by "bagging" the peaks from right to left.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::Mmr;
+///
+/// let mut mmr = Mmr::new();
+/// for block in [&b"a"[..], b"b", b"c"].iter() {
+///     mmr.append(block);
+/// }
+/// let proof = mmr.proof(1).unwrap();
+/// assert!(proof.verify(&mmr.root().unwrap(), &Mmr::leaf_hash(b"b")));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Mmr {
+    nodes: Vec<[u8; 32]>,
+    num_leaves: u64,
+}
+
+impl Mmr {
+    /// Constructs a new empty `Mmr`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the leaf hash used for `data`.
+    pub fn leaf_hash(data: &[u8]) -> [u8; 32] {
+        Sha256::digest(data).into()
+    }
+
+    /// Appends `data` as a new leaf and returns the leaf index.
+    pub fn append(&mut self, data: &[u8]) -> u64 {
+        self.append_leaf(Self::leaf_hash(data))
+    }
+
+    /// Appends an already computed leaf hash and returns the leaf index.
+    pub fn append_leaf(&mut self, leaf: [u8; 32]) -> u64 {
+        self.nodes.push(leaf);
+        let mut height = 0;
+        while pos_height(self.nodes.len() as u64) > height {
+            let right = self.nodes.len() - 1;
+            let left = self.nodes.len() - (2 << height);
+            let parent = hash_pair(&self.nodes[left], &self.nodes[right]);
+            self.nodes.push(parent);
+            height += 1;
+        }
+        self.num_leaves += 1;
+        self.num_leaves - 1
+    }
+
+    /// Returns the number of leaves.
+    pub fn num_leaves(&self) -> u64 {
+        self.num_leaves
+    }
+
+    /// Returns the total number of nodes, which is also the MMR size.
+    pub fn num_nodes(&self) -> u64 {
+        self.nodes.len() as u64
+    }
+
+    /// Returns `true` if no leaf has been appended.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Returns the hashes of the peaks, from left to right.
+    pub fn peaks(&self) -> Vec<[u8; 32]> {
+        peak_positions(self.num_nodes())
+            .into_iter()
+            .map(|pos| self.nodes[pos as usize])
+            .collect()
+    }
+
+    /// Returns the bagged root of all peaks.
+    pub fn root(&self) -> Option<[u8; 32]> {
+        bag_peaks(&self.peaks())
+    }
+
+    /// Returns the root hash as an `Option<String>`.
+    pub fn root_hash(&self) -> Option<String> {
+        self.root().map(hex::encode)
+    }
+
+    /// Returns an inclusion proof for the leaf at `leaf_index`.
+    pub fn proof(&self, leaf_index: u64) -> Option<MmrProof> {
+        if leaf_index >= self.num_leaves {
+            return None;
+        }
+
+        let size = self.num_nodes();
+        let peaks = peak_positions(size);
+        let mut pos = leaf_to_pos(leaf_index);
+        let mut siblings = Vec::new();
+        let mut height = 0;
+
+        while !peaks.contains(&pos) {
+            let offset = (2u64 << height) - 1;
+            if pos_height(pos + 1) > height {
+                siblings.push(self.nodes[(pos - offset) as usize]);
+                pos += 1;
+            } else {
+                siblings.push(self.nodes[(pos + offset) as usize]);
+                pos += offset + 1;
+            }
+            height += 1;
+        }
+
+        Some(MmrProof {
+            position: leaf_to_pos(leaf_index),
+            mmr_size: size,
+            siblings,
+            peaks: self.peaks(),
+        })
+    }
+}
+
+/// An inclusion proof for a leaf of an `Mmr`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MmrProof {
+    /// Position of the leaf among all MMR nodes.
+    pub position: u64,
+    /// Number of nodes in the MMR the proof was generated from.
+    pub mmr_size: u64,
+    /// Sibling hashes from the leaf up to its peak.
+    pub siblings: Vec<[u8; 32]>,
+    /// Hashes of all peaks, from left to right.
+    pub peaks: Vec<[u8; 32]>,
+}
+
+impl MmrProof {
+    /// Returns `true` if `leaf` is included at the proven position under `root`.
+    /// Positions of internal nodes are rejected, so the hash of a subtree
+    /// cannot pass for a leaf.
+    pub fn verify(&self, root: &[u8; 32], leaf: &[u8; 32]) -> bool {
+        let peak_positions = peak_positions(self.mmr_size);
+        if peak_positions.len() != self.peaks.len() || self.position >= self.mmr_size {
+            return false;
+        }
+        if pos_height(self.position) != 0 {
+            return false;
+        }
+
+        let mut pos = self.position;
+        let mut hash = *leaf;
+        for (height, sibling) in self.siblings.iter().enumerate() {
+            let offset = (2u64 << height) - 1;
+            if pos_height(pos + 1) > height as u32 {
+                hash = hash_pair(sibling, &hash);
+                pos += 1;
+            } else {
+                hash = hash_pair(&hash, sibling);
+                pos += offset + 1;
+            }
+        }
+
+        match peak_positions.iter().position(|&p| p == pos) {
+            Some(i) if constant_time_eq(&self.peaks[i], &hash) => {
+                bag_peaks(&self.peaks).is_some_and(|bag| constant_time_eq(&bag, root))
+            }
+            _ => false,
+        }
+    }
+}
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+fn bag_peaks(peaks: &[[u8; 32]]) -> Option<[u8; 32]> {
+    let mut iter = peaks.iter().rev();
+    let mut bag = *iter.next()?;
+    for peak in iter {
+        bag = hash_pair(peak, &bag);
+    }
+    Some(bag)
+}
+
+/// Returns the height of the node at 0-based position `pos`, leaves being 0.
+fn pos_height(pos: u64) -> u32 {
+    let mut pos = pos + 1;
+    while !all_ones(pos) {
+        pos -= (1 << (63 - pos.leading_zeros())) - 1;
+    }
+    63 - pos.leading_zeros()
+}
+
+fn all_ones(n: u64) -> bool {
+    n != 0 && n & (n + 1) == 0
+}
+
+/// Returns the positions of the peaks of an MMR with `size` nodes.
+fn peak_positions(size: u64) -> Vec<u64> {
+    let mut peaks = Vec::new();
+    if size == 0 {
+        return peaks;
+    }
+    let mut peak_size = u64::MAX >> size.leading_zeros();
+    let (mut offset, mut remaining) = (0, size);
+    while peak_size > 0 {
+        if remaining >= peak_size {
+            peaks.push(offset + peak_size - 1);
+            offset += peak_size;
+            remaining -= peak_size;
+        }
+        peak_size >>= 1;
+    }
+    peaks
+}
+
+fn leaf_to_pos(index: u64) -> u64 {
+    2 * index - index.count_ones() as u64
+}
@@ -0,0 +1,474 @@
+use core::convert::TryInto;
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+
+use crate::root::constant_time_eq;
+
+/// Number of bytes absorbed per Keccak-f permutation by Keccak-256.
+const RATE: usize = 136;
+
+/// Round constants of Keccak-f[1600].
+const ROUND_CONSTANTS: [u64; 24] = [
+    0x0000000000000001,
+    0x0000000000008082,
+    0x800000000000808a,
+    0x8000000080008000,
+    0x000000000000808b,
+    0x0000000080000001,
+    0x8000000080008081,
+    0x8000000000008009,
+    0x000000000000008a,
+    0x0000000000000088,
+    0x0000000080008009,
+    0x000000008000000a,
+    0x000000008000808b,
+    0x800000000000008b,
+    0x8000000000008089,
+    0x8000000000008003,
+    0x8000000000008002,
+    0x8000000000000080,
+    0x000000000000800a,
+    0x800000008000000a,
+    0x8000000080008081,
+    0x8000000000008080,
+    0x0000000080000001,
+    0x8000000080008008,
+];
+/// Rotations of the rho step, in the order lanes are visited by the pi step.
+const ROTATIONS: [u32; 24] = [1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44];
+/// Lanes visited by the pi step, starting after lane 1.
+const PI_LANES: [usize; 24] = [10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1];
+
+/// Returns the Keccak-256 digest of `data`, the hash of Ethereum, which
+/// differs from SHA3-256 in its padding.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::mpt::keccak256;
+///
+/// assert_eq!(
+///     hex::encode(keccak256(b"")),
+///     "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+/// );
+/// ```
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    let mut state = [0u64; 25];
+    let mut blocks = data.chunks_exact(RATE);
+    for block in &mut blocks {
+        absorb(&mut state, block);
+    }
+    let rest = blocks.remainder();
+    let mut last = [0u8; RATE];
+    last[..rest.len()].copy_from_slice(rest);
+    last[rest.len()] ^= 0x01;
+    last[RATE - 1] ^= 0x80;
+    absorb(&mut state, &last);
+
+    let mut digest = [0u8; 32];
+    for (bytes, lane) in digest.chunks_exact_mut(8).zip(state.iter()) {
+        bytes.copy_from_slice(&lane.to_le_bytes());
+    }
+    digest
+}
+
+/// XORs a block of `RATE` bytes into the state and permutes it.
+fn absorb(state: &mut [u64; 25], block: &[u8]) {
+    for (lane, bytes) in state.iter_mut().zip(block.chunks_exact(8)) {
+        *lane ^= u64::from_le_bytes(bytes.try_into().expect("chunks are eight bytes"));
+    }
+    keccak_f(state);
+}
+
+/// The Keccak-f[1600] permutation.
+fn keccak_f(state: &mut [u64; 25]) {
+    for round_constant in ROUND_CONSTANTS {
+        // Theta
+        let parity: [u64; 5] = core::array::from_fn(|x| (0..25).step_by(5).fold(0, |acc, y| acc ^ state[x + y]));
+        for x in 0..5 {
+            let mix = parity[(x + 4) % 5] ^ parity[(x + 1) % 5].rotate_left(1);
+            for y in (0..25).step_by(5) {
+                state[x + y] ^= mix;
+            }
+        }
+        // Rho and pi
+        let mut carried = state[1];
+        for (&lane, &rotation) in PI_LANES.iter().zip(ROTATIONS.iter()) {
+            let next = state[lane];
+            state[lane] = carried.rotate_left(rotation);
+            carried = next;
+        }
+        // Chi
+        for y in (0..25).step_by(5) {
+            let row: [u64; 5] = core::array::from_fn(|x| state[y + x]);
+            for x in 0..5 {
+                state[y + x] = row[x] ^ (!row[(x + 1) % 5] & row[(x + 2) % 5]);
+            }
+        }
+        // Iota
+        state[0] ^= round_constant;
+    }
+}
+
+/// An Ethereum Merkle Patricia Trie, as used for the state, storage,
+/// transaction and receipt tries of the chain (Ethereum Yellow Paper,
+/// appendix D).
+///
+/// Keys are split into nibbles; nodes are RLP-encoded leaves, extensions
+/// and 17-item branches with hex-prefix encoded paths, and a node is
+/// referenced by its Keccak-256 hash unless its encoding is shorter than 32
+/// bytes, in which case it is embedded in its parent. Roots and proofs thus
+/// match those of other implementations, e.g. `stateRoot` of block headers
+/// and `eth_getProof`. Values are stored as given, so RLP-encode accounts
+/// and storage slots first; the "secure" tries of the state hash their keys
+/// with `keccak256` before inserting them.
+///
+/// Entries are kept sorted and the trie is rebuilt from them when its root
+/// or a proof is requested, which takes time linear in the number of
+/// entries.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::mpt::PatriciaTrie;
+///
+/// let mut trie = PatriciaTrie::new();
+/// trie.insert(b"doe", b"reindeer");
+/// trie.insert(b"dog", b"puppy");
+/// trie.insert(b"dogglesworth", b"cat");
+/// assert_eq!(
+///     hex::encode(trie.root()),
+///     "8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3"
+/// );
+///
+/// let proof = trie.proof(b"dog");
+/// let value = PatriciaTrie::verify_proof(&trie.root(), b"dog", &proof).unwrap();
+/// assert_eq!(value.as_deref(), Some(&b"puppy"[..]));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PatriciaTrie {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+/// An entry of the trie with its key split into nibbles.
+struct Entry<'a> {
+    nibbles: Vec<u8>,
+    value: &'a [u8],
+}
+
+impl PatriciaTrie {
+    /// Constructs a new empty `PatriciaTrie`.
+    pub fn new() -> Self {
+        Self { entries: BTreeMap::new() }
+    }
+
+    /// Sets the value of `key`, returning its previous value. As in
+    /// Ethereum, an empty value removes the key.
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        if value.is_empty() {
+            return self.remove(key);
+        }
+        self.entries.insert(key.to_vec(), value.to_vec())
+    }
+
+    /// Returns the value of `key`.
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.entries.get(key).map(Vec::as_slice)
+    }
+
+    /// Removes `key` from the trie, returning its value.
+    pub fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.entries.remove(key)
+    }
+
+    /// Returns the number of keys in the trie.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the trie holds no keys.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the root hash of the trie. The root of the empty trie is the
+    /// hash of the RLP encoding of the empty string.
+    pub fn root(&self) -> [u8; 32] {
+        keccak256(&self.encode_root(None, &mut Vec::new()))
+    }
+
+    /// Returns the proof of `key`: the RLP encodings of the nodes on its
+    /// path, root first, leaving out nodes embedded in their parent, as
+    /// returned by `eth_getProof`. If the key is absent, the nodes show where
+    /// its path ends.
+    pub fn proof(&self, key: &[u8]) -> Vec<Vec<u8>> {
+        let mut proof = Vec::new();
+        let root = self.encode_root(Some(&nibbles(key)), &mut proof);
+        proof.push(root);
+        proof.reverse();
+        proof
+    }
+
+    /// Checks `proof` of `key` against the trusted `root`, returning the
+    /// value of the key or `None` if the proof shows it is absent. Nodes are
+    /// looked up by their hash, so their order does not matter and unused
+    /// nodes are ignored.
+    /// Returns an `Error` value if a node on the path is missing from the
+    /// proof or malformed.
+    pub fn verify_proof(root: &[u8; 32], key: &[u8], proof: &[Vec<u8>]) -> Result<Option<Vec<u8>>, Error> {
+        let nodes: BTreeMap<[u8; 32], &[u8]> = proof.iter().map(|node| (keccak256(node), node.as_slice())).collect();
+        let find = |hash: &[u8]| {
+            nodes
+                .iter()
+                .find(|(node_hash, _)| constant_time_eq(&node_hash[..], hash))
+                .map(|(_, node)| *node)
+                .ok_or_else(|| invalid("proof is missing a node"))
+        };
+        if constant_time_eq(root, &keccak256(&[EMPTY_STRING])) {
+            return Ok(None);
+        }
+
+        let key = nibbles(key);
+        let mut position = 0;
+        let mut node = find(&root[..])?;
+        loop {
+            let items = list_items(node)?;
+            let child = match items.as_slice() {
+                [(Rlp::Bytes(path), _), (value, raw)] => {
+                    let (path, leaf) = decode_hex_prefix(path)?;
+                    let rest = &key[position..];
+                    if leaf {
+                        return match value {
+                            Rlp::Bytes(value) if rest == path.as_slice() => Ok(Some(value.to_vec())),
+                            Rlp::Bytes(_) => Ok(None),
+                            Rlp::List(_) => Err(invalid("leaf value is not a string")),
+                        };
+                    }
+                    if !rest.starts_with(&path) {
+                        return Ok(None);
+                    }
+                    position += path.len();
+                    (value, *raw)
+                }
+                items if items.len() == 17 => {
+                    if position == key.len() {
+                        return match &items[16].0 {
+                            Rlp::Bytes([]) => Ok(None),
+                            Rlp::Bytes(value) => Ok(Some(value.to_vec())),
+                            Rlp::List(_) => Err(invalid("branch value is not a string")),
+                        };
+                    }
+                    let (child, raw) = &items[key[position] as usize];
+                    position += 1;
+                    (child, *raw)
+                }
+                _ => return Err(invalid("node is neither a leaf, an extension nor a branch")),
+            };
+            node = match child {
+                (Rlp::Bytes([]), _) => return Ok(None),
+                (Rlp::Bytes(hash), _) if hash.len() == 32 => find(hash)?,
+                (Rlp::List(_), raw) => raw,
+                _ => return Err(invalid("invalid child reference")),
+            };
+        }
+    }
+
+    /// Returns the encoding of the root node, pushing the encodings of the
+    /// nodes on the path of `key` below the root that are referenced by
+    /// hash to `proof`, deepest first.
+    fn encode_root(&self, key: Option<&[u8]>, proof: &mut Vec<Vec<u8>>) -> Vec<u8> {
+        if self.entries.is_empty() {
+            return vec![EMPTY_STRING];
+        }
+        let entries: Vec<Entry<'_>> =
+            self.entries.iter().map(|(key, value)| Entry { nibbles: nibbles(key), value }).collect();
+        encode_node(&entries, 0, key, proof)
+    }
+}
+
+/// Returns the encoding of the node holding `entries`, which are sorted,
+/// at least one, and share their first `depth` nibbles. Nodes on the path
+/// of `key` below it that are referenced by hash are pushed to `proof`.
+fn encode_node(entries: &[Entry<'_>], depth: usize, key: Option<&[u8]>, proof: &mut Vec<Vec<u8>>) -> Vec<u8> {
+    let first = &entries[0].nibbles[depth..];
+    if entries.len() == 1 {
+        return encode_list(&[encode_bytes(&hex_prefix(first, true)), encode_bytes(entries[0].value)]);
+    }
+
+    let last = &entries[entries.len() - 1].nibbles[depth..];
+    let common = first.iter().zip(last).take_while(|(a, b)| a == b).count();
+    if common > 0 {
+        let on_path = key.filter(|key| key.len() >= depth + common && key[depth..depth + common] == first[..common]);
+        let child = encode_node(entries, depth + common, on_path, proof);
+        return encode_list(&[encode_bytes(&hex_prefix(&first[..common], false)), reference(child, on_path, proof)]);
+    }
+
+    let mut items = Vec::with_capacity(17);
+    // Entries are sorted, so a key ending here comes first
+    let (value, mut rest) = match first.is_empty() {
+        true => (encode_bytes(entries[0].value), &entries[1..]),
+        false => (vec![EMPTY_STRING], entries),
+    };
+    for nibble in 0..16u8 {
+        let len = rest.iter().take_while(|entry| entry.nibbles[depth] == nibble).count();
+        let (children, after) = rest.split_at(len);
+        rest = after;
+        if children.is_empty() {
+            items.push(vec![EMPTY_STRING]);
+            continue;
+        }
+        let on_path = key.filter(|key| key.get(depth) == Some(&nibble));
+        let child = encode_node(children, depth + 1, on_path, proof);
+        items.push(reference(child, on_path, proof));
+    }
+    items.push(value);
+    encode_list(&items)
+}
+
+/// Returns how a parent refers to the child node encoded as `child`: the
+/// encoding itself if it is shorter than 32 bytes, its hash otherwise. A
+/// child on the path of a proven key referenced by hash joins the proof.
+fn reference(child: Vec<u8>, on_path: Option<&[u8]>, proof: &mut Vec<Vec<u8>>) -> Vec<u8> {
+    if child.len() < 32 {
+        return child;
+    }
+    let hash = encode_bytes(&keccak256(&child));
+    if on_path.is_some() {
+        proof.push(child);
+    }
+    hash
+}
+
+/// Splits `key` into nibbles, high nibble first.
+fn nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]).collect()
+}
+
+/// Packs `nibbles` into bytes behind a flag nibble marking leaves and an
+/// odd length, the hex-prefix encoding of the Yellow Paper.
+fn hex_prefix(nibbles: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 2 } else { 0 };
+    let mut packed = Vec::with_capacity(nibbles.len() / 2 + 1);
+    let even = match nibbles.len() % 2 {
+        1 => {
+            packed.push((flag + 1) << 4 | nibbles[0]);
+            &nibbles[1..]
+        }
+        _ => {
+            packed.push(flag << 4);
+            nibbles
+        }
+    };
+    packed.extend(even.chunks(2).map(|pair| pair[0] << 4 | pair[1]));
+    packed
+}
+
+/// Decodes a hex-prefix encoded path into its nibbles and whether it
+/// belongs to a leaf.
+fn decode_hex_prefix(packed: &[u8]) -> Result<(Vec<u8>, bool), Error> {
+    let (&first, rest) = packed.split_first().ok_or_else(|| invalid("empty node path"))?;
+    let flag = first >> 4;
+    if flag > 3 || (flag & 1 == 0 && first & 0x0f != 0) {
+        return Err(invalid("invalid node path"));
+    }
+    let mut path = Vec::with_capacity(rest.len() * 2 + 1);
+    if flag & 1 == 1 {
+        path.push(first & 0x0f);
+    }
+    path.extend(nibbles(rest));
+    Ok((path, flag >= 2))
+}
+
+/// RLP encoding of the empty string.
+const EMPTY_STRING: u8 = 0x80;
+
+/// Returns the RLP encoding of the byte string `data`.
+fn encode_bytes(data: &[u8]) -> Vec<u8> {
+    if data.len() == 1 && data[0] < 0x80 {
+        return data.to_vec();
+    }
+    let mut encoded = encode_header(0x80, data.len());
+    encoded.extend_from_slice(data);
+    encoded
+}
+
+/// Returns the RLP encoding of a list of already encoded `items`.
+fn encode_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let mut encoded = encode_header(0xc0, items.iter().map(Vec::len).sum());
+    for item in items {
+        encoded.extend_from_slice(item);
+    }
+    encoded
+}
+
+/// Returns the RLP header of a string, with `offset` 0x80, or a list, with
+/// `offset` 0xc0, of `len` bytes.
+fn encode_header(offset: u8, len: usize) -> Vec<u8> {
+    if len < 56 {
+        return vec![offset + len as u8];
+    }
+    let len_bytes = len.to_be_bytes();
+    let skip = len_bytes.iter().take_while(|&&byte| byte == 0).count();
+    let mut header = vec![offset + 55 + (len_bytes.len() - skip) as u8];
+    header.extend_from_slice(&len_bytes[skip..]);
+    header
+}
+
+/// A decoded RLP item: a byte string or the payload of a list.
+enum Rlp<'a> {
+    Bytes(&'a [u8]),
+    List(&'a [u8]),
+}
+
+/// Decodes the RLP item at the start of `data`, returning it, its full
+/// encoding and the bytes following it.
+fn decode_item(data: &[u8]) -> Result<(Rlp<'_>, &[u8], &[u8]), Error> {
+    let (&first, rest) = data.split_first().ok_or_else(|| invalid("truncated RLP item"))?;
+    let (header_len, payload_len) = match first {
+        0x00..=0x7f => return Ok((Rlp::Bytes(&data[..1]), &data[..1], rest)),
+        0x80..=0xb7 => (1, (first - 0x80) as usize),
+        0xb8..=0xbf => decode_long_len(data, first - 0xb7)?,
+        0xc0..=0xf7 => (1, (first - 0xc0) as usize),
+        0xf8..=0xff => decode_long_len(data, first - 0xf7)?,
+    };
+    let end = header_len + payload_len;
+    if data.len() < end {
+        return Err(invalid("truncated RLP item"));
+    }
+    let payload = &data[header_len..end];
+    let item = if first < 0xc0 { Rlp::Bytes(payload) } else { Rlp::List(payload) };
+    Ok((item, &data[..end], &data[end..]))
+}
+
+/// Decodes the length of a long string or list whose header has
+/// `len_bytes` length bytes, returning the header and payload lengths.
+fn decode_long_len(data: &[u8], len_bytes: u8) -> Result<(usize, usize), Error> {
+    let len_bytes = len_bytes as usize;
+    let bytes = data.get(1..1 + len_bytes).ok_or_else(|| invalid("truncated RLP item"))?;
+    if len_bytes > core::mem::size_of::<usize>() || bytes[0] == 0 {
+        return Err(invalid("invalid RLP length"));
+    }
+    let len = bytes.iter().fold(0usize, |len, &byte| len << 8 | byte as usize);
+    Ok((1 + len_bytes, len))
+}
+
+/// Decodes `node`, which must be exactly one RLP list, into its items and
+/// their full encodings.
+fn list_items(node: &[u8]) -> Result<Vec<(Rlp<'_>, &[u8])>, Error> {
+    let (item, _, rest) = decode_item(node)?;
+    let mut payload = match item {
+        Rlp::List(payload) if rest.is_empty() => payload,
+        _ => return Err(invalid("node is not an RLP list")),
+    };
+    let mut items = Vec::new();
+    while !payload.is_empty() {
+        let (item, raw, rest) = decode_item(payload)?;
+        items.push((item, raw));
+        payload = rest;
+    }
+    Ok(items)
+}
+
+fn invalid(message: &'static str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
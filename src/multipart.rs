@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::ops::Range;
+
+use crate::HashTree;
+
+/// Highest part number of an S3 multipart upload.
+const MAX_PART_NUMBER: u32 = 10_000;
+
+/// Builds a `HashTree` over the parts of an S3 multipart upload, one leaf
+/// per part, with the length of every part recorded since parts need not
+/// be the same size.
+///
+/// Parts can be added in any order as they are uploaded, possibly by
+/// different workers. The root of the finished tree can be stored as
+/// object metadata, e.g. as `x-amz-meta-hashtree-root` in hex, and the tree
+/// itself next to the object with `write_to`. A reader can then fetch any
+/// part with a ranged `GET`, see `HashTree::part_range`, and verify it on
+/// its own with `HashTree::verify_part`.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, MultipartTree};
+///
+/// let parts: [&[u8]; 3] = [&[1; 100], &[2; 100], &[3; 20]];
+/// let mut upload = MultipartTree::new(HashTree::new(0));
+/// for (number, part) in [(2, parts[1]), (1, parts[0]), (3, parts[2])] {
+///     upload.add_part(number, part).unwrap();
+/// }
+/// let tree = upload.finish().unwrap();
+/// let metadata = tree.root_hash().unwrap().to_hex();
+///
+/// let root = hex::decode(metadata).unwrap();
+/// assert_eq!(tree.part_range(2), Some(100..200));
+/// assert!(tree.verify_part(&root, 2, parts[1]).is_ok());
+/// assert!(tree.verify_part(&root, 3, parts[1]).is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct MultipartTree {
+    template: HashTree,
+    parts: BTreeMap<u32, (Vec<u8>, u64)>,
+}
+
+impl MultipartTree {
+    /// Constructs a new `MultipartTree`. The tree takes its hasher and arity
+    /// from `template`, which should be empty; the block size is unused.
+    pub fn new(template: HashTree) -> Self {
+        Self { template, parts: BTreeMap::new() }
+    }
+
+    /// Hashes `data` as the part numbered `part_number` and returns its leaf
+    /// hash. Adding a part again replaces it, as uploading it again does.
+    /// Returns an `Error` value if the part number is not between 1 and
+    /// 10,000.
+    pub fn add_part(&mut self, part_number: u32, data: &[u8]) -> Result<Vec<u8>, Error> {
+        check_part_number(part_number)?;
+        let hash = self.template.hash_block(data)?;
+        self.parts.insert(part_number, (hash.clone(), data.len() as u64));
+        Ok(hash)
+    }
+
+    /// Adds the part numbered `part_number`, `len` bytes long, whose leaf hash
+    /// was computed elsewhere, e.g. by the worker that uploaded it.
+    /// Returns an `Error` value if the part number is not between 1 and
+    /// 10,000.
+    pub fn add_part_hash(&mut self, part_number: u32, len: u64, leaf_hash: Vec<u8>) -> Result<(), Error> {
+        check_part_number(part_number)?;
+        self.parts.insert(part_number, (leaf_hash, len));
+        Ok(())
+    }
+
+    /// Returns the number of parts added.
+    pub fn num_parts(&self) -> usize {
+        self.parts.len()
+    }
+
+    /// Builds the tree over the parts in order of their numbers.
+    /// Returns an `Error` value if there are no parts, or the parts are not
+    /// numbered from 1 without gaps, which the part numbers of readers rely
+    /// on.
+    pub fn finish(self) -> Result<HashTree, Error> {
+        if self.parts.is_empty() || self.parts.keys().zip(1..).any(|(&number, expected)| number != expected) {
+            return Err(Error::new(ErrorKind::InvalidInput, "parts must be numbered from 1 without gaps"));
+        }
+        self.template.record_lengths().with_sized_leaves(self.parts.into_values())
+    }
+}
+
+impl HashTree {
+    /// Returns the byte range of the object covered by the part numbered
+    /// `part_number` of a tree built by `MultipartTree`, for a ranged `GET`,
+    /// or `None` if there is no such part or its lengths were not recorded.
+    pub fn part_range(&self, part_number: u32) -> Option<Range<u64>> {
+        self.leaf_range((part_number as usize).checked_sub(1)?)
+    }
+
+    /// Verifies `data` as the part numbered `part_number` of a tree built by
+    /// `MultipartTree` against the trusted `root`, e.g. from the object's
+    /// metadata. `self` may come from an untrusted source: the part is
+    /// checked with an inclusion proof from it against `root`.
+    /// Returns an `Error` value if there is no such part or `data` does not
+    /// match the root.
+    pub fn verify_part(&self, root: &[u8], part_number: u32, data: &[u8]) -> Result<(), Error> {
+        let proof = (part_number as usize).checked_sub(1).and_then(|index| self.proof(index));
+        let proof = proof.ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, format!("there is no part {} in the tree", part_number))
+        })?;
+        let leaf_hash = self.get_hasher().hash_leaf(data);
+        if !proof.verify_with(self.get_hasher(), root, &leaf_hash) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("part {} does not match the root", part_number),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn check_part_number(part_number: u32) -> Result<(), Error> {
+    if !(1..=MAX_PART_NUMBER).contains(&part_number) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("part number {} is not between 1 and {}", part_number, MAX_PART_NUMBER),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use alloc::vec::Vec;
+
+use crate::hasher::{hash_group, PairHasher};
+use crate::io::{Error, ErrorKind};
+use crate::root::constant_time_eq;
+use crate::store::NodeStore;
+use crate::HashTree;
+
+/// One element of a streamed multiproof, see `HashTree::multiproof_stream`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofElement {
+    /// The hash of a proven leaf.
+    Leaf {
+        /// Index of the proven block.
+        index: usize,
+        /// Leaf hash of the block.
+        hash: Vec<u8>,
+    },
+    /// The hash of a node none of whose leaves are proven.
+    Node {
+        /// Height of the node above the leaves.
+        level: usize,
+        /// Hash of the node.
+        hash: Vec<u8>,
+    },
+}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Returns a multiproof for the blocks at `indices` as a stream of
+    /// elements for a `StreamingVerifier`, or `None` if the tree is empty
+    /// or an index is out of range.
+    ///
+    /// The elements are the nodes of the tree in depth-first, left-to-right
+    /// order, cut off at every node none of whose leaves are proven. A
+    /// verifier thus only keeps one partial group of siblings per level.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::{HashTree, StreamingVerifier};
+    ///
+    /// let tree = HashTree::new(1).from_data(&mut &b"abcdefgh"[..]).unwrap();
+    /// let mut verifier = StreamingVerifier::new(tree.get_hasher(), tree.get_arity());
+    /// for element in tree.multiproof_stream(&[1, 2, 6]).unwrap() {
+    ///     verifier.push(&element).unwrap();
+    /// }
+    /// assert!(verifier.finish(tree.root_hash_bytes().unwrap()));
+    /// ```
+    pub fn multiproof_stream(&self, indices: &[usize]) -> Option<Vec<ProofElement>> {
+        if self.is_empty() || indices.iter().any(|&index| index >= self.stored_leaves()) {
+            return None;
+        }
+        let mut indices = indices.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+
+        let levels = self.levels();
+        let mut elements = Vec::new();
+        self.stream_node(&levels, levels.len() - 1, 0, &indices, &mut elements);
+        Some(elements)
+    }
+
+    /// Appends the elements for the node at `index` of `level`.
+    pub(crate) fn stream_node(
+        &self,
+        levels: &[core::ops::Range<usize>],
+        level: usize,
+        index: usize,
+        indices: &[usize],
+        elements: &mut Vec<ProofElement>,
+    ) {
+        let arity = self.get_arity();
+        let width = arity.saturating_pow(level as u32);
+        let first = index.saturating_mul(width);
+        let proven = indices.iter().any(|&leaf| leaf >= first && leaf - first < width);
+        let hash = self.node_hash(levels[level].start + index);
+        if !proven {
+            elements.push(ProofElement::Node { level, hash });
+        } else if level == 0 {
+            elements.push(ProofElement::Leaf { index, hash });
+        } else {
+            for child in 0..arity {
+                self.stream_node(levels, level - 1, index * arity + child, indices, elements);
+            }
+        }
+    }
+}
+
+/// Verifies a multiproof streamed by `HashTree::multiproof_stream` one
+/// element at a time, keeping at most `arity - 1` hashes per level of the
+/// tree, so memory grows with the logarithm of the number of blocks.
+///
+/// The verifier checks that the leaves fit together into the expected root;
+/// callers check each `ProofElement::Leaf` against the block they expect at
+/// its index as it arrives.
+#[derive(Debug)]
+pub struct StreamingVerifier<'h> {
+    hasher: &'h dyn PairHasher,
+    arity: usize,
+    /// Hashes waiting for their siblings, with their level.
+    stack: Vec<(usize, Vec<u8>)>,
+    /// Number of leaf positions covered by the elements so far.
+    covered: usize,
+    failed: bool,
+}
+
+impl<'h> StreamingVerifier<'h> {
+    /// Constructs a new `StreamingVerifier` for a tree of the given `arity`
+    /// hashed with `hasher`.
+    pub fn new(hasher: &'h dyn PairHasher, arity: usize) -> Self {
+        Self { hasher, arity: arity.max(2), stack: Vec::new(), covered: 0, failed: false }
+    }
+
+    /// Consumes the next element of the stream.
+    /// Returns an `Error` value if the element cannot follow the previous
+    /// ones, e.g. a leaf at the wrong index; the proof is then invalid.
+    pub fn push(&mut self, element: &ProofElement) -> Result<(), Error> {
+        let result = self.push_element(element);
+        self.failed |= result.is_err();
+        result
+    }
+
+    fn push_element(&mut self, element: &ProofElement) -> Result<(), Error> {
+        if self.failed {
+            return Err(invalid("the stream already failed"));
+        }
+        let (level, hash) = match element {
+            ProofElement::Leaf { index, hash } => {
+                if *index != self.covered {
+                    return Err(invalid("leaf index does not match its position in the stream"));
+                }
+                (0, hash)
+            }
+            ProofElement::Node { level, hash } => (*level, hash),
+        };
+        let width = self.arity.checked_pow(level as u32).ok_or_else(|| invalid("node level is too high"))?;
+        if !self.covered.is_multiple_of(width) {
+            return Err(invalid("node is not aligned to its level"));
+        }
+        if let Some((top, _)) = self.stack.last() {
+            if level > *top {
+                return Err(invalid("node is higher than the group it completes"));
+            }
+        }
+        self.covered += width;
+        self.stack.push((level, hash.clone()));
+
+        // Merge every group of `arity` siblings completed by this element
+        while self.stack.len() >= self.arity {
+            let group = &self.stack[self.stack.len() - self.arity..];
+            let level = group[0].0;
+            if group.iter().any(|(sibling, _)| *sibling != level) {
+                break;
+            }
+            let children: Vec<&[u8]> = group.iter().map(|(_, hash)| hash.as_slice()).collect();
+            let parent = hash_group(self.hasher, &children);
+            self.stack.truncate(self.stack.len() - self.arity);
+            self.stack.push((level + 1, parent));
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the whole stream was consumed without errors and
+    /// reduces to `root`. The roots are compared in constant time.
+    pub fn finish(self, root: &[u8]) -> bool {
+        !self.failed && self.stack.len() == 1 && constant_time_eq(&self.stack[0].1, root)
+    }
+}
+
+fn invalid(message: &'static str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
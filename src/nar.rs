@@ -0,0 +1,138 @@
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::path::Path;
+use sha2::{Digest, Sha256};
+
+const NIX_BASE32: &[u8] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+/// Writes the Nix archive (NAR) serialization of the file, symlink or
+/// directory at `path` to `writer`, byte for byte as `nix-store --dump` does.
+/// Returns an `Error` value of kind `InvalidInput` if `path` is or contains a
+/// FIFO, socket or device node, which NAR cannot represent.
+pub fn serialize<P: AsRef<Path>, W: Write>(path: P, mut writer: W) -> Result<(), io::Error> {
+    write_str(&mut writer, b"nix-archive-1")?;
+    serialize_entry(path.as_ref(), &mut writer)
+}
+
+/// Returns the SHA-256 digest of the NAR serialization of `path`, which is
+/// the hash Nix records for store paths and recursive fixed-output derivations.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::nar;
+///
+/// let dir = std::env::temp_dir().join("hashtree-doc-nar");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let hash = nar::hash(&dir).unwrap();
+/// println!("sha256:{}", nar::to_nix_base32(&hash));
+/// ```
+pub fn hash<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, io::Error> {
+    let mut writer = HashWriter(Sha256::new());
+    serialize(path, &mut writer)?;
+    Ok(writer.0.finalize().to_vec())
+}
+
+/// Encodes `bytes` in the base-32 variant used by Nix for store paths and hashes.
+pub fn to_nix_base32(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return String::new();
+    }
+    let len = (bytes.len() * 8 - 1) / 5 + 1;
+    (0..len)
+        .rev()
+        .map(|n| {
+            let b = n * 5;
+            let (i, j) = (b / 8, b % 8);
+            let mut c = (bytes[i] as u16) >> j;
+            if i + 1 < bytes.len() {
+                c |= (bytes[i + 1] as u16) << (8 - j);
+            }
+            NIX_BASE32[(c & 0x1f) as usize] as char
+        })
+        .collect()
+}
+
+fn serialize_entry<W: Write>(path: &Path, writer: &mut W) -> Result<(), io::Error> {
+    let metadata = fs::symlink_metadata(path)?;
+    let file_type = metadata.file_type();
+    if !file_type.is_file() && !file_type.is_dir() && !file_type.is_symlink() {
+        let message = format!("{} is not a regular file, directory or symlink", path.display());
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+    write_str(writer, b"(")?;
+    write_str(writer, b"type")?;
+
+    if file_type.is_symlink() {
+        write_str(writer, b"symlink")?;
+        write_str(writer, b"target")?;
+        write_str(writer, fs::read_link(path)?.to_string_lossy().as_bytes())?;
+    } else if metadata.is_dir() {
+        write_str(writer, b"directory")?;
+        let mut names = fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.file_name()))
+            .collect::<Result<Vec<_>, _>>()?;
+        names.sort();
+        for name in names {
+            write_str(writer, b"entry")?;
+            write_str(writer, b"(")?;
+            write_str(writer, b"name")?;
+            write_str(writer, name.to_string_lossy().as_bytes())?;
+            write_str(writer, b"node")?;
+            serialize_entry(&path.join(&name), writer)?;
+            write_str(writer, b")")?;
+        }
+    } else {
+        write_str(writer, b"regular")?;
+        if is_executable(&metadata) {
+            write_str(writer, b"executable")?;
+            write_str(writer, b"")?;
+        }
+        write_str(writer, b"contents")?;
+        let len = metadata.len();
+        writer.write_all(&len.to_le_bytes())?;
+        let copied = io::copy(&mut BufReader::new(File::open(path)?), writer)?;
+        if copied != len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file changed while serializing"));
+        }
+        write_padding(writer, len)?;
+    }
+
+    write_str(writer, b")")
+}
+
+fn write_str<W: Write>(writer: &mut W, s: &[u8]) -> Result<(), io::Error> {
+    writer.write_all(&(s.len() as u64).to_le_bytes())?;
+    writer.write_all(s)?;
+    write_padding(writer, s.len() as u64)
+}
+
+fn write_padding<W: Write>(writer: &mut W, len: u64) -> Result<(), io::Error> {
+    let padding = (8 - len % 8) % 8;
+    writer.write_all(&[0u8; 8][..padding as usize])
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o100 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+struct HashWriter(Sha256);
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
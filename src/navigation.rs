@@ -0,0 +1,94 @@
+use alloc::vec::Vec;
+
+use crate::index::{LeafIndex, NodeIndex};
+use crate::store::NodeStore;
+use crate::HashTree;
+
+impl<S: NodeStore> HashTree<S> {
+    /// Returns the level of node `index` (0 for leaves) and its index within
+    /// the level, or `None` if there is no such node.
+    pub fn position(&self, index: NodeIndex) -> Option<(usize, usize)> {
+        let levels = self.levels();
+        let level = levels.iter().position(|range| range.contains(&index.0))?;
+        Some((level, index.0 - levels[level].start))
+    }
+
+    /// Returns the parent of node `index`, or `None` for the root and nodes
+    /// that do not exist. Padding shares the parent of the group it pads.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::{HashTree, LeafIndex, NodeIndex};
+    ///
+    /// let tree = HashTree::new(1).from_slice(b"abcd").unwrap();
+    /// let parent = tree.parent(NodeIndex(3)).unwrap();
+    /// assert_eq!(parent, NodeIndex(5));
+    /// assert_eq!(tree.children(parent), [NodeIndex(2), NodeIndex(3)]);
+    /// assert_eq!(tree.sibling(parent), Some(NodeIndex(4)));
+    /// assert_eq!(tree.path_to_root(LeafIndex(3)), [NodeIndex(3), NodeIndex(5), NodeIndex(6)]);
+    /// assert_eq!(tree.parent(NodeIndex(6)), None);
+    /// ```
+    pub fn parent(&self, index: NodeIndex) -> Option<NodeIndex> {
+        let levels = self.levels();
+        let (level, offset) = self.position(index)?;
+        let above = levels.get(level + 1)?;
+        Some(NodeIndex(above.start + offset / self.get_arity()))
+    }
+
+    /// Returns the children of node `index`, in order. Leaves, padding and
+    /// nodes that do not exist have none.
+    pub fn children(&self, index: NodeIndex) -> Vec<NodeIndex> {
+        let levels = self.levels();
+        match self.position(index) {
+            Some((level, offset)) if level > 0 && offset < self.computed_nodes(&levels, level) => {
+                let start = levels[level - 1].start + offset * self.get_arity();
+                (start..start + self.get_arity()).map(NodeIndex).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the other nodes of the group of node `index`, which are
+    /// hashed together with it into its parent, in order. The root and nodes
+    /// that do not exist have none.
+    pub fn siblings(&self, index: NodeIndex) -> Vec<NodeIndex> {
+        let levels = self.levels();
+        match self.position(index) {
+            Some((level, offset)) if level + 1 < levels.len() => {
+                let group = levels[level].start + offset / self.get_arity() * self.get_arity();
+                (group..group + self.get_arity()).filter(|&node| node != index.0).map(NodeIndex).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the sibling of node `index` in a binary tree, or `None` for
+    /// the root, nodes that do not exist and trees of higher arity, see
+    /// `siblings`.
+    pub fn sibling(&self, index: NodeIndex) -> Option<NodeIndex> {
+        match self.siblings(index)[..] {
+            [sibling] => Some(sibling),
+            _ => None,
+        }
+    }
+
+    /// Returns the nodes from the leaf at `index` up to the root, both
+    /// included, or an empty path if there is no such leaf. The hashes of the
+    /// nodes are read with `node_at`.
+    pub fn path_to_root<I: Into<LeafIndex>>(&self, index: I) -> Vec<NodeIndex> {
+        let mut offset = match self.leaf_node(index.into()) {
+            Some(offset) => offset,
+            None => return Vec::new(),
+        };
+        let levels = self.levels();
+        levels
+            .iter()
+            .map(|range| {
+                let node = NodeIndex(range.start + offset);
+                offset /= self.get_arity();
+                node
+            })
+            .collect()
+    }
+}
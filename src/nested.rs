@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+
+use crate::hasher::{PairHasher, Sha256Hasher};
+use crate::proof::Proof;
+use crate::{HashTree, RootHash};
+
+/// Prefix of the data hashed into the leaf of a nested tree's root, which
+/// marks the leaf as a subtree rather than a block.
+const SUBTREE_TAG: &[u8] = b"hashtree subtree\0";
+
+/// A `HashTree` some of whose leaves are the roots of other trees, which may
+/// be nested in turn, so that a huge dataset can be committed as a tree of
+/// trees built independently, e.g. one per shard and one per file.
+///
+/// A leaf holding a subtree is the leaf hash of the subtree's root prefixed
+/// with a fixed tag, see `NestedTree::subtree_leaf_hash`, so a subtree root is
+/// never taken for a block of the same bytes. All layers must use the same
+/// hasher. A `NestedProof` proves a block through every layer down to it.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, NestedTree, PairHasher, Sha256Hasher};
+///
+/// let shard = |data: &[u8]| HashTree::new(4).from_slice(data).unwrap();
+/// let mut builder = NestedTree::builder(HashTree::new(0));
+/// builder.push_block(b"manifest");
+/// builder.push_tree(shard(b"first shard")).unwrap();
+/// builder.push_tree(shard(b"second shard")).unwrap();
+/// let tree = builder.finish().unwrap();
+///
+/// // Block 1 of the subtree at leaf 2
+/// let proof = tree.proof(&[2, 1]).unwrap();
+/// let root = tree.root_hash().unwrap().into_bytes();
+/// assert!(proof.verify(&root, &Sha256Hasher::new().hash_leaf(b"nd s")));
+/// assert!(tree.proof(&[0, 1]).is_none());
+/// ```
+#[derive(Debug, Clone)]
+pub struct NestedTree {
+    tree: HashTree,
+    roots: BTreeMap<usize, Vec<u8>>,
+    subtrees: BTreeMap<usize, NestedTree>,
+}
+
+/// Collects the leaves of a `NestedTree`, see `NestedTree::builder`.
+#[derive(Debug)]
+pub struct NestedBuilder {
+    template: HashTree,
+    leaves: Vec<Vec<u8>>,
+    roots: BTreeMap<usize, Vec<u8>>,
+    subtrees: BTreeMap<usize, NestedTree>,
+}
+
+/// A proof of a block through every layer of a `NestedTree`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NestedProof {
+    /// Proofs from the outermost tree inwards. Each proof but the last shows
+    /// the root of the next tree to be a leaf of its tree; the last one shows
+    /// the block to be a leaf of the innermost tree.
+    pub proofs: Vec<Proof>,
+    /// Roots of the nested trees the proof passes through, one fewer than
+    /// `proofs`.
+    pub roots: Vec<Vec<u8>>,
+}
+
+impl NestedTree {
+    /// Returns a builder for a tree with the parameters of `template`, which
+    /// should be empty. Its block size is unused.
+    pub fn builder(template: HashTree) -> NestedBuilder {
+        NestedBuilder { template, leaves: Vec::new(), roots: BTreeMap::new(), subtrees: BTreeMap::new() }
+    }
+
+    /// Returns the leaf hash marking `root` as the root of a subtree.
+    pub fn subtree_leaf_hash(hasher: &dyn PairHasher, root: &[u8]) -> Vec<u8> {
+        hasher.hash_leaf(&[SUBTREE_TAG, root].concat())
+    }
+
+    /// Returns the root of the outermost tree, or `None` if it is empty.
+    pub fn root_hash(&self) -> Option<RootHash> {
+        self.tree.root_hash()
+    }
+
+    /// Returns the outermost tree.
+    pub fn tree(&self) -> &HashTree {
+        &self.tree
+    }
+
+    /// Returns `true` if leaf `index` is the root of a subtree.
+    pub fn is_subtree(&self, index: usize) -> bool {
+        self.roots.contains_key(&index)
+    }
+
+    /// Returns the root of the subtree at leaf `index`, or `None` if the leaf
+    /// is a block.
+    pub fn subtree_root(&self, index: usize) -> Option<&[u8]> {
+        self.roots.get(&index).map(Vec::as_slice)
+    }
+
+    /// Returns the subtree at leaf `index`, or `None` if the leaf is a block
+    /// or a subtree added by its root alone.
+    pub fn subtree(&self, index: usize) -> Option<&NestedTree> {
+        self.subtrees.get(&index)
+    }
+
+    /// Returns the number of layers, 1 for a tree without subtrees.
+    pub fn depth(&self) -> usize {
+        1 + self.subtrees.values().map(NestedTree::depth).max().unwrap_or(0)
+    }
+
+    /// Returns the proof of the block reached by following `path`: the index
+    /// of a subtree leaf in every layer from the outermost one, then the
+    /// index of the block in the innermost tree. Returns `None` if the path
+    /// does not end at a block, or passes a subtree added by its root alone.
+    pub fn proof(&self, path: &[usize]) -> Option<NestedProof> {
+        let (&index, rest) = path.split_first()?;
+        let proof = self.tree.proof(index)?;
+        if rest.is_empty() {
+            return (!self.is_subtree(index)).then(|| NestedProof { proofs: vec![proof], roots: Vec::new() });
+        }
+        let mut inner = self.subtrees.get(&index)?.proof(rest)?;
+        inner.proofs.insert(0, proof);
+        inner.roots.insert(0, self.roots[&index].clone());
+        Some(inner)
+    }
+}
+
+impl NestedBuilder {
+    /// Adds a block and returns the index of its leaf.
+    pub fn push_block(&mut self, data: &[u8]) -> usize {
+        let hash = self.template.get_hasher().hash_leaf(data);
+        self.push_leaf_hash(hash)
+    }
+
+    /// Adds a block by its leaf hash and returns the index of its leaf.
+    pub fn push_leaf_hash(&mut self, hash: Vec<u8>) -> usize {
+        self.leaves.push(hash);
+        self.leaves.len() - 1
+    }
+
+    /// Adds `tree` as a subtree and returns the index of its leaf.
+    /// Returns an `Error` value if the tree is empty.
+    pub fn push_tree(&mut self, tree: HashTree) -> Result<usize, Error> {
+        self.push_nested(NestedTree { tree, roots: BTreeMap::new(), subtrees: BTreeMap::new() })
+    }
+
+    /// Adds a `NestedTree` as a subtree and returns the index of its leaf.
+    /// Returns an `Error` value if the tree is empty.
+    pub fn push_nested(&mut self, tree: NestedTree) -> Result<usize, Error> {
+        let root = tree.tree.root().ok_or_else(|| Error::new(ErrorKind::InvalidInput, "subtree is empty"))?;
+        let index = self.push_subtree_root(root);
+        self.subtrees.insert(index, tree);
+        Ok(index)
+    }
+
+    /// Adds a subtree by its root alone, e.g. one kept on another machine,
+    /// and returns the index of its leaf. Proofs cannot pass through it.
+    pub fn push_subtree_root(&mut self, root: Vec<u8>) -> usize {
+        let index = self.push_leaf_hash(NestedTree::subtree_leaf_hash(self.template.get_hasher(), &root));
+        self.roots.insert(index, root);
+        index
+    }
+
+    /// Builds the outermost tree over the leaves.
+    /// Returns an `Error` value if the store could not be written.
+    pub fn finish(self) -> Result<NestedTree, Error> {
+        let tree = self.template.from_leaf_hashes(self.leaves)?;
+        Ok(NestedTree { tree, roots: self.roots, subtrees: self.subtrees })
+    }
+}
+
+impl NestedProof {
+    /// Returns the leaf indices the proof passes through, from the outermost
+    /// tree to the block.
+    pub fn path(&self) -> Vec<u64> {
+        self.proofs.iter().map(|proof| proof.leaf_index).collect()
+    }
+
+    /// Returns `true` if the block with hash `leaf_hash` is included under
+    /// `root`, assuming every layer uses the default SHA-256 hasher.
+    pub fn verify(&self, root: &[u8], leaf_hash: &[u8]) -> bool {
+        self.verify_with(&Sha256Hasher::new(), root, leaf_hash)
+    }
+
+    /// Returns `true` if the block with hash `leaf_hash` is included under
+    /// `root`, with every layer hashed by `hasher`.
+    pub fn verify_with(&self, hasher: &dyn PairHasher, root: &[u8], leaf_hash: &[u8]) -> bool {
+        if self.proofs.is_empty() || self.roots.len() + 1 != self.proofs.len() {
+            return false;
+        }
+        // Evaluate every layer so the running time does not reveal which
+        // one failed; a proof without levels would show a leaf to be the
+        // root itself, which only happens for a lone leaf above no parent
+        let mut valid = true;
+        let mut expected = root;
+        for (proof, inner_root) in self.proofs.iter().zip(self.roots.iter().map(Some).chain([None])) {
+            let leaf = match inner_root {
+                Some(inner_root) => NestedTree::subtree_leaf_hash(hasher, inner_root),
+                None => leaf_hash.to_vec(),
+            };
+            valid &= !proof.levels.is_empty() & proof.verify_with(hasher, expected, &leaf);
+            if let Some(inner_root) = inner_root {
+                expected = inner_root;
+            }
+        }
+        valid
+    }
+}
@@ -0,0 +1,95 @@
+use napi::bindgen_prelude::{AsyncTask, Buffer};
+use napi::{Env, Error, Result, Task};
+use napi_derive::napi;
+
+use crate::hasher::{PairHasher, Sha256Hasher};
+use crate::proof::Proof;
+use crate::{HashTree, RootHash};
+
+/// A `HashTree` exposed to Node.js as `HashTree`. Requires the `node`
+/// feature.
+///
+/// Trees are always binary and hashed with SHA-256, so their roots match
+/// those of `HashTree::new(block_size)`.
+#[napi(js_name = "HashTree")]
+pub struct NodeHashTree {
+    tree: HashTree,
+}
+
+#[napi]
+impl NodeHashTree {
+    /// Builds a tree over `data` split into blocks of `blockSize` bytes.
+    #[napi(factory)]
+    pub fn from_buffer(block_size: u32, data: Buffer) -> Result<Self> {
+        let tree = HashTree::new(block_size as usize).from_slice(&data).map_err(to_js_error)?;
+        Ok(Self { tree })
+    }
+
+    /// The root hash as lowercase hex, or `null` if the tree is empty.
+    #[napi(getter)]
+    pub fn root_hash(&self) -> Option<String> {
+        self.tree.root_hash().map(|root| root.to_hex())
+    }
+
+    /// The number of blocks of the tree.
+    #[napi(getter)]
+    pub fn num_blocks(&self) -> u32 {
+        self.tree.num_blocks() as u32
+    }
+
+    /// Returns the sibling hashes of the inclusion proof for the block at
+    /// `index`, from the leaves up, as accepted by `verifyProof`. Returns
+    /// `null` if there is no such block.
+    #[napi]
+    pub fn proof(&self, index: u32) -> Option<Vec<Buffer>> {
+        let proof = self.tree.proof(index as usize)?;
+        let siblings = proof.levels.into_iter().flat_map(|level| level.siblings);
+        Some(siblings.map(Buffer::from).collect())
+    }
+}
+
+/// Hashes a file on the libuv thread pool, see `fromFile`.
+pub struct FromFileTask {
+    path: String,
+    block_size: usize,
+}
+
+impl Task for FromFileTask {
+    type Output = HashTree;
+    type JsValue = NodeHashTree;
+
+    fn compute(&mut self) -> Result<HashTree> {
+        HashTree::new(self.block_size).from_file(&self.path).map_err(to_js_error)
+    }
+
+    fn resolve(&mut self, _env: Env, tree: HashTree) -> Result<NodeHashTree> {
+        Ok(NodeHashTree { tree })
+    }
+}
+
+/// Builds the tree of the file at `path` without blocking the event loop.
+/// Resolves to a `HashTree`.
+#[napi]
+pub fn from_file(path: String, block_size: u32) -> AsyncTask<FromFileTask> {
+    AsyncTask::new(FromFileTask { path, block_size: block_size as usize })
+}
+
+/// Returns `true` if `block` is the block at `index` of the SHA-256 tree
+/// with the hex `root`. `siblings` holds the `arity - 1` sibling hashes of
+/// every level from the leaves up, as returned by `HashTree.proof`.
+#[napi]
+pub fn verify_proof(root: String, index: u32, block: Buffer, siblings: Vec<Buffer>, arity: u32) -> bool {
+    let root: RootHash = match root.parse() {
+        Ok(root) => root,
+        Err(_) => return false,
+    };
+    let hashes = siblings.into_iter().map(|sibling| sibling.to_vec()).collect();
+    match Proof::from_siblings(index as u64, arity as usize, hashes) {
+        Some(proof) => root == *proof.compute_root(&Sha256Hasher::new().hash_leaf(&block)).as_slice(),
+        None => false,
+    }
+}
+
+fn to_js_error(error: std::io::Error) -> Error {
+    Error::from_reason(error.to_string())
+}
@@ -0,0 +1,95 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::root::RootHash;
+
+/// An event reported to a `BuildObserver` while a `HashTree` is built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildEvent<'a> {
+    /// A leaf was appended to the tree. Reported in index order, as soon as
+    /// the leaf is stored, so before the rest of the input is read.
+    LeafHashed {
+        /// Index of the block the leaf was hashed from.
+        index: usize,
+        /// Hash of the leaf.
+        hash: &'a [u8],
+    },
+    /// Every node of a level, including its padding, was stored. The leaves
+    /// are level 0 and the root is the last level.
+    LevelCompleted {
+        /// Height of the level above the leaves.
+        level: usize,
+    },
+    /// The tree is complete and has this root.
+    RootComputed {
+        /// Hash of the root.
+        hash: &'a [u8],
+    },
+}
+
+/// Receives `BuildEvent`s while a `HashTree` is built, e.g. to stream leaf
+/// hashes to a peer before the whole input has been hashed.
+///
+/// Events are delivered on the building thread, in order, and building
+/// waits for `on_event` to return. Closures taking a `&BuildEvent` are
+/// observers too. Only leaves appended by a build are reported: `update`
+/// and `merge` report the new leaves but rebuild every level.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Mutex;
+/// use hashtree::{BuildEvent, HashTree};
+///
+/// let leaves = std::sync::Arc::new(Mutex::new(Vec::new()));
+/// let sink = leaves.clone();
+/// let tree = HashTree::new(2)
+///     .observer(move |event: &BuildEvent<'_>| {
+///         if let BuildEvent::LeafHashed { index, hash } = event {
+///             sink.lock().unwrap().push((*index, hash.to_vec()));
+///         }
+///     })
+///     .from_data(&mut &b"abcdef"[..])
+///     .unwrap();
+/// assert_eq!(leaves.lock().unwrap().len(), 3);
+/// ```
+pub trait BuildObserver: Send + Sync {
+    /// Called with every event of a build.
+    fn on_event(&self, event: &BuildEvent<'_>);
+}
+
+impl<F: Fn(&BuildEvent<'_>) + Send + Sync> BuildObserver for F {
+    fn on_event(&self, event: &BuildEvent<'_>) {
+        self(event)
+    }
+}
+
+impl fmt::Debug for dyn BuildObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BuildObserver")
+    }
+}
+
+/// The callbacks registered with `HashTree::on_root_changed`.
+#[derive(Clone, Default)]
+pub(crate) struct RootListeners(Vec<Arc<dyn Fn(RootHash, u64) + Send + Sync>>);
+
+impl RootListeners {
+    pub(crate) fn push<F: Fn(RootHash, u64) + Send + Sync + 'static>(&mut self, callback: F) {
+        self.0.push(Arc::new(callback));
+    }
+
+    /// Calls every callback with `root` and `version`, in registration order.
+    pub(crate) fn notify(&self, root: &[u8], version: u64) {
+        for callback in &self.0 {
+            callback(RootHash::new(root.to_vec()), version);
+        }
+    }
+}
+
+impl fmt::Debug for RootListeners {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RootListeners({})", self.0.len())
+    }
+}
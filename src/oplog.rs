@@ -0,0 +1,216 @@
+use std::io::{Error, ErrorKind, Read};
+
+use crate::io::to_usize;
+use crate::store::NodeStore;
+use crate::HashTree;
+
+const TAG_APPEND: u8 = 0;
+const TAG_REPLACE: u8 = 1;
+const TAG_TRUNCATE: u8 = 2;
+const FLAG_DATA_LEN: u8 = 1;
+const FLAG_LENGTHS: u8 = 2;
+const HEADER_LEN: usize = 27;
+
+/// A change of the leaves of a `HashTree`, recorded with `record_ops`.
+///
+/// Operations carry leaf hashes rather than data, so a replica keeps its
+/// copy of a tree in sync by applying the operations of the primary with
+/// `apply_ops`, which only rehashes the right spine of the tree, instead of
+/// fetching the whole tree again. Every operation also carries the length of
+/// the data covered afterwards, see `HashTree::data_len`. Operations are
+/// sent one after another with `to_bytes` and received with `read_from`.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+/// use hashtree::{HashTree, TreeOp};
+///
+/// let mut primary = HashTree::new(4).record_ops().from_slice(b"first entry").unwrap();
+/// let mut replica = primary.clone();
+///
+/// primary.extend_from_reader(Cursor::new(b"first entry, second entry")).unwrap();
+/// primary.truncate(5).unwrap();
+/// let stream: Vec<u8> = primary.take_ops().iter().flat_map(TreeOp::to_bytes).collect();
+///
+/// let mut stream = stream.as_slice();
+/// while let Some(op) = TreeOp::read_from(&mut stream).unwrap() {
+///     replica.apply_ops(Some(op)).unwrap();
+/// }
+/// assert_eq!(replica, primary);
+/// assert_eq!(replica.data_len(), Some(20));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeOp {
+    /// Leaves appended after the last one.
+    Append {
+        leaves: Vec<Vec<u8>>,
+        /// The length of every new leaf, if recorded.
+        lengths: Option<Vec<u64>>,
+        data_len: Option<u64>,
+    },
+    /// The leaves from `index` on replaced with `leaves`, e.g. a partial
+    /// last block that was rehashed with data appended to it.
+    Replace {
+        index: u64,
+        leaves: Vec<Vec<u8>>,
+        /// The length of every new leaf, if recorded.
+        lengths: Option<Vec<u64>>,
+        data_len: Option<u64>,
+    },
+    /// The leaves from `num_blocks` on removed.
+    Truncate { num_blocks: u64, data_len: Option<u64> },
+}
+
+impl TreeOp {
+    /// Returns the operation replacing the leaves of a tree of `num_blocks`
+    /// blocks from index `keep` on with `leaves`, or `None` if it changes
+    /// nothing.
+    pub(crate) fn splice(
+        num_blocks: usize,
+        keep: usize,
+        leaves: Vec<Vec<u8>>,
+        lengths: Option<Vec<u64>>,
+        data_len: Option<u64>,
+    ) -> Option<Self> {
+        match (keep == num_blocks, leaves.is_empty()) {
+            (true, true) => None,
+            (true, false) => Some(TreeOp::Append { leaves, lengths, data_len }),
+            (false, true) => Some(TreeOp::Truncate { num_blocks: keep as u64, data_len }),
+            (false, false) => Some(TreeOp::Replace { index: keep as u64, leaves, lengths, data_len }),
+        }
+    }
+
+    /// Returns the length of the data covered after the operation.
+    pub fn data_len(&self) -> Option<u64> {
+        match self {
+            TreeOp::Append { data_len, .. } | TreeOp::Replace { data_len, .. } | TreeOp::Truncate { data_len, .. } => *data_len,
+        }
+    }
+
+    /// Encodes the operation: a 27-byte header holding its kind, which
+    /// optional fields are present, the index it applies at, the data
+    /// length, the number of leaves and the hash length, followed by the
+    /// leaf hashes and, if recorded, their lengths. Encoded operations can
+    /// be concatenated into a stream.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (tag, index, leaves, lengths) = match self {
+            TreeOp::Append { leaves, lengths, .. } => (TAG_APPEND, 0, &leaves[..], lengths.as_deref()),
+            TreeOp::Replace { index, leaves, lengths, .. } => (TAG_REPLACE, *index, &leaves[..], lengths.as_deref()),
+            TreeOp::Truncate { num_blocks, .. } => (TAG_TRUNCATE, *num_blocks, &[][..], None),
+        };
+        let mut flags = 0;
+        if self.data_len().is_some() {
+            flags |= FLAG_DATA_LEN;
+        }
+        if lengths.is_some() {
+            flags |= FLAG_LENGTHS;
+        }
+        let mut bytes = vec![tag, flags];
+        bytes.extend_from_slice(&index.to_be_bytes());
+        bytes.extend_from_slice(&self.data_len().unwrap_or(0).to_be_bytes());
+        bytes.extend_from_slice(&(leaves.len() as u64).to_be_bytes());
+        bytes.push(leaves.first().map_or(0, Vec::len) as u8);
+        for hash in leaves {
+            bytes.extend_from_slice(hash);
+        }
+        for len in lengths.unwrap_or(&[]) {
+            bytes.extend_from_slice(&len.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Decodes an operation encoded by `to_bytes`.
+    /// Returns an `Error` value if `bytes` is not a valid encoding.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, Error> {
+        let op = Self::read_from(&mut bytes)?.ok_or_else(truncated)?;
+        if !bytes.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "operation has trailing data"));
+        }
+        Ok(op)
+    }
+
+    /// Reads the next operation of a stream of encoded operations, or `None`
+    /// at the end of the stream.
+    /// Returns an `Error` value if `reader` could not be read or does not
+    /// hold a valid encoding.
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Option<Self>, Error> {
+        let mut header = [0; HEADER_LEN];
+        let mut filled = 0;
+        while filled < HEADER_LEN {
+            match reader.read(&mut header[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(truncated()),
+                Ok(len) => filled += len,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            }
+        }
+        let field = |start: usize| header[start..start + 8].iter().fold(0u64, |n, &b| n << 8 | b as u64);
+        let (tag, flags, index, count, hash_len) = (header[0], header[1], field(2), field(18), header[26] as u64);
+        let data_len = (flags & FLAG_DATA_LEN != 0).then(|| field(10));
+        let invalid = || Error::new(ErrorKind::InvalidData, "malformed operation");
+        if flags & !(FLAG_DATA_LEN | FLAG_LENGTHS) != 0 || tag == TAG_TRUNCATE && (count != 0 || flags & FLAG_LENGTHS != 0) {
+            return Err(invalid());
+        }
+        if count > 0 && hash_len == 0 {
+            return Err(invalid());
+        }
+
+        // Read incrementally, so a corrupt count does not allocate up front
+        let mut read = |len: u64| -> Result<Vec<u8>, Error> {
+            let mut buf = Vec::new();
+            (&mut reader).take(len).read_to_end(&mut buf)?;
+            if (buf.len() as u64) < len {
+                return Err(truncated());
+            }
+            Ok(buf)
+        };
+        let leaves: Vec<Vec<u8>> = read(count.saturating_mul(hash_len))?.chunks(hash_len.max(1) as usize).map(<[u8]>::to_vec).collect();
+        let lengths = match flags & FLAG_LENGTHS {
+            0 => None,
+            _ => Some(read(count.saturating_mul(8))?.chunks(8).map(|len| len.iter().fold(0u64, |n, &b| n << 8 | b as u64)).collect()),
+        };
+        match tag {
+            TAG_APPEND if index == 0 => Ok(Some(TreeOp::Append { leaves, lengths, data_len })),
+            TAG_REPLACE => Ok(Some(TreeOp::Replace { index, leaves, lengths, data_len })),
+            TAG_TRUNCATE => Ok(Some(TreeOp::Truncate { num_blocks: index, data_len })),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "operation is truncated")
+}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Applies operations recorded by another tree with `record_ops`, in
+    /// order, so that a copy of it stays in sync. Each operation counts as
+    /// an update, see `track_history`, and is recorded again if this tree
+    /// records operations itself.
+    /// Returns an `Error` value if an operation refers to blocks the tree
+    /// does not have, its leaf lengths do not match its leaves, or the store
+    /// could not be written. The operations before it stay applied.
+    pub fn apply_ops<I: IntoIterator<Item = TreeOp>>(&mut self, ops: I) -> Result<(), Error> {
+        for op in ops {
+            let (keep, leaves, lengths, data_len) = match op {
+                TreeOp::Append { leaves, lengths, data_len } => (self.num_blocks(), leaves, lengths, data_len),
+                TreeOp::Replace { index, leaves, lengths, data_len } => (index, leaves, lengths, data_len),
+                TreeOp::Truncate { num_blocks, data_len } => (num_blocks, Vec::new(), Some(Vec::new()), data_len),
+            };
+            if keep > self.num_blocks() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("operation applies at block {} of a tree of {} blocks", keep, self.num_blocks()),
+                ));
+            }
+            if lengths.as_ref().is_some_and(|lengths| lengths.len() != leaves.len()) {
+                return Err(Error::new(ErrorKind::InvalidInput, "operation does not have a length for every leaf"));
+            }
+            self.replace_tail(to_usize(keep)?, leaves, lengths, data_len)?;
+            self.record_version();
+        }
+        Ok(())
+    }
+}
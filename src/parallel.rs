@@ -0,0 +1,93 @@
+use rayon::prelude::*;
+
+use crate::multiproof::ProofElement;
+use crate::index::LeafIndex;
+use crate::proof::Proof;
+use crate::store::NodeStore;
+use crate::HashTree;
+
+/// Number of blocks below which a subtree is not split further across
+/// threads, so each task does enough work to pay for its scheduling.
+const MIN_SHARD: usize = 1024;
+
+impl<S: NodeStore + Sync> HashTree<S> {
+    /// Returns the proofs of all blocks, in order, like `proofs`, generated
+    /// on the rayon thread pool, see `thread_pool`. The blocks are split into contiguous shards
+    /// that threads prove independently over the shared tree, each reading
+    /// the sibling groups of its shard once.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let tree = HashTree::new(1).from_slice(&[7u8; 5000]).unwrap();
+    /// let proofs = tree.proofs_par();
+    /// assert_eq!(proofs.len(), 5000);
+    /// assert_eq!(proofs, tree.proofs().collect::<Vec<_>>());
+    /// ```
+    pub fn proofs_par(&self) -> Vec<(LeafIndex, Proof)> {
+        self.install(|| {
+            let num_blocks = self.stored_leaves();
+            let shard = num_blocks.div_ceil(rayon::current_num_threads() * 4).max(MIN_SHARD);
+            (0..num_blocks.div_ceil(shard))
+                .into_par_iter()
+                .flat_map_iter(|i| self.proofs_from(i * shard).take(shard))
+                .collect()
+        })
+    }
+
+    /// Returns the same multiproof as `multiproof_stream`, with the subtrees
+    /// holding proven blocks streamed on the rayon thread pool and their
+    /// elements joined in order, see `thread_pool`. Returns `None` if the
+    /// tree is empty or an index is out of range.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let tree = HashTree::new(1).from_slice(&[7u8; 5000]).unwrap();
+    /// let indices: Vec<usize> = (0..5000).step_by(7).collect();
+    /// assert_eq!(tree.multiproof_par(&indices), tree.multiproof_stream(&indices));
+    /// ```
+    pub fn multiproof_par(&self, indices: &[usize]) -> Option<Vec<ProofElement>> {
+        if self.is_empty() || indices.iter().any(|&index| index >= self.stored_leaves()) {
+            return None;
+        }
+        self.install(|| {
+            let mut indices = indices.to_vec();
+            indices.par_sort_unstable();
+            indices.dedup();
+
+            let levels = self.levels();
+            Some(self.stream_node_par(&levels, levels.len() - 1, 0, &indices))
+        })
+    }
+
+    /// Returns the elements for the node at `index` of `level`, proving the
+    /// sorted `indices`, all of which lie below it.
+    fn stream_node_par(&self, levels: &[core::ops::Range<usize>], level: usize, index: usize, indices: &[usize]) -> Vec<ProofElement> {
+        let arity = self.get_arity();
+        let width = arity.saturating_pow(level as u32);
+        let mut elements = Vec::new();
+        if indices.is_empty() || width <= MIN_SHARD {
+            self.stream_node(levels, level, index, indices, &mut elements);
+            return elements;
+        }
+        let first = index * width;
+        let child_width = width / arity;
+        (0..arity)
+            .into_par_iter()
+            .map(|child| {
+                let start = first + child * child_width;
+                let from = indices.partition_point(|&leaf| leaf < start);
+                let to = indices.partition_point(|&leaf| leaf < start.saturating_add(child_width));
+                self.stream_node_par(levels, level - 1, index * arity + child, &indices[from..to])
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::hasher::{hash_group, PairHasher, Sha256Hasher};
+use crate::proof::{positions_match, Proof, ProofLevel};
+use crate::root::constant_time_eq;
+
+/// A partially known `HashTree`, assembled from a trusted root and a set of
+/// inclusion proofs, in the spirit of Bitcoin's `merkleblock` messages.
+///
+/// Every proof is verified against the root before it is merged, so all
+/// hashes held by a `PartialTree` are authenticated. Membership queries can
+/// be answered for every leaf covered by a merged proof, and proofs for
+/// those leaves can be regenerated and served to others.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, PartialTree};
+/// use sha2::{Digest, Sha256};
+///
+/// let tree = HashTree::new(1).from_data(&mut &b"abcdefgh"[..]).unwrap();
+/// let root = tree.root_hash().unwrap().into_bytes();
+///
+/// let mut partial = PartialTree::new(root);
+/// assert!(partial.insert_proof(&Sha256::digest(b"c"), &tree.proof(2).unwrap()));
+/// assert!(partial.contains(2, &Sha256::digest(b"c")));
+/// assert!(partial.leaf_hash(3).is_some());
+/// assert!(partial.leaf_hash(5).is_none());
+/// ```
+#[derive(Debug, Clone)]
+pub struct PartialTree {
+    root: Vec<u8>,
+    hasher: Arc<dyn PairHasher>,
+    arity: Option<usize>,
+    depth: usize,
+    nodes: HashMap<(usize, u64), Vec<u8>>,
+    leaves: BTreeMap<u64, Vec<u8>>,
+}
+
+impl PartialTree {
+    /// Constructs a new `PartialTree` for the trusted `root`.
+    pub fn new(root: Vec<u8>) -> Self {
+        Self {
+            root,
+            hasher: Arc::new(Sha256Hasher::new()),
+            arity: None,
+            depth: 0,
+            nodes: HashMap::new(),
+            leaves: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the hash function the proofs are verified with. The default is SHA-256.
+    pub fn hasher<H: PairHasher + 'static>(mut self, hasher: H) -> Self {
+        self.hasher = Arc::new(hasher);
+        self
+    }
+
+    pub(crate) fn shared_hasher(mut self, hasher: Arc<dyn PairHasher>) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Returns the trusted root.
+    pub fn root(&self) -> &[u8] {
+        &self.root
+    }
+
+    /// Verifies `proof` for the leaf with hash `leaf_hash` and merges it into
+    /// the tree. Returns `false`, leaving the tree untouched, if the proof does
+    /// not verify against the root or does not match the shape of earlier proofs.
+    pub fn insert_proof(&mut self, leaf_hash: &[u8], proof: &Proof) -> bool {
+        if !proof.verify_with(self.hasher.as_ref(), &self.root, leaf_hash) || !positions_match(proof) {
+            return false;
+        }
+        if let Some(arity) = self.arity {
+            if proof.arity() != arity || proof.levels.len() != self.depth {
+                return false;
+            }
+        }
+        self.arity = Some(proof.arity());
+        self.depth = proof.levels.len();
+
+        let mut position = proof.leaf_index;
+        let mut hash = leaf_hash.to_vec();
+        for (height, level) in proof.levels.iter().enumerate() {
+            let group = position - level.position as u64;
+            let mut children: Vec<&[u8]> = level.siblings.iter().map(|s| s.as_slice()).collect();
+            children.insert(level.position.min(children.len()), &hash);
+            for (i, child) in children.iter().enumerate() {
+                self.nodes.insert((height, group + i as u64), child.to_vec());
+            }
+            let parent = hash_group(self.hasher.as_ref(), &children);
+            position /= children.len() as u64;
+            hash = parent;
+        }
+
+        self.leaves.insert(proof.leaf_index, leaf_hash.to_vec());
+        true
+    }
+
+    /// Returns the authenticated hash of the leaf at `index`, if known. This
+    /// includes leaves that only appeared as siblings in merged proofs.
+    pub fn leaf_hash(&self, index: u64) -> Option<&[u8]> {
+        self.nodes.get(&(0, index)).map(|hash| hash.as_slice())
+    }
+
+    /// Returns `true` if the leaf at `index` is known to have the hash `leaf_hash`.
+    pub fn contains(&self, index: u64, leaf_hash: &[u8]) -> bool {
+        self.leaf_hash(index).is_some_and(|hash| constant_time_eq(hash, leaf_hash))
+    }
+
+    /// Returns the indices of the leaves whose proofs have been merged.
+    pub fn proven_leaves(&self) -> impl Iterator<Item = u64> + '_ {
+        self.leaves.keys().copied()
+    }
+
+    /// Returns the number of merged proofs' leaves.
+    pub fn num_proven(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Returns `true` if no proof has been merged.
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Regenerates the proof for the leaf at `index` from the known hashes,
+    /// or returns `None` if some of the required hashes are unknown.
+    pub fn proof(&self, index: u64) -> Option<Proof> {
+        let arity = self.arity? as u64;
+        let mut position = index;
+        let mut levels = Vec::with_capacity(self.depth);
+        for height in 0..self.depth {
+            let group = position / arity * arity;
+            let siblings = (group..group + arity)
+                .filter(|&i| i != position)
+                .map(|i| self.nodes.get(&(height, i)).cloned())
+                .collect::<Option<Vec<_>>>()?;
+            levels.push(ProofLevel { position: (position % arity) as usize, siblings });
+            position /= arity;
+        }
+        self.leaf_hash(index)?;
+        Some(Proof { leaf_index: index, levels })
+    }
+}
@@ -0,0 +1,171 @@
+use std::sync::Arc;
+
+use crate::hasher::{hash_group, PairHasher};
+use crate::proof::{Proof, ProofLevel};
+use crate::root::RootHash;
+use crate::HashTree;
+
+/// A node of a `PersistentTree`, shared between all versions that contain it.
+#[derive(Debug)]
+struct Node {
+    hash: Vec<u8>,
+    children: Vec<Arc<Node>>,
+}
+
+/// An immutable, structurally shared version of a `HashTree`.
+///
+/// Taking a `snapshot` only clones a pointer to the root, and changing a leaf
+/// copies just the nodes on its path to the root; all other nodes are shared
+/// with earlier versions. This allows keeping many historical versions of a
+/// mutating tree at the cost of `O(depth)` nodes per change.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::HashTree;
+/// use sha2::{Digest, Sha256};
+///
+/// let tree = HashTree::new(1).from_data(&mut &b"abcd"[..]).unwrap();
+/// let mut current = tree.to_persistent();
+/// let old = current.snapshot();
+///
+/// assert!(current.set_leaf(1, Sha256::digest(b"X").to_vec()));
+/// assert_eq!(old.root_hash(), tree.root_hash());
+/// assert_eq!(current.root_hash(), HashTree::new(1).from_data(&mut &b"aXcd"[..]).unwrap().root_hash());
+/// ```
+#[derive(Debug, Clone)]
+pub struct PersistentTree {
+    root: Option<Arc<Node>>,
+    real_lens: Vec<usize>,
+    arity: usize,
+    zero_padded: bool,
+    hasher: Arc<dyn PairHasher>,
+}
+
+impl HashTree {
+    /// Returns a `PersistentTree` with the same nodes as `self`.
+    pub fn to_persistent(&self) -> PersistentTree {
+        let arity = self.get_arity();
+        let mut below: Vec<Arc<Node>> = Vec::new();
+        let mut real_lens = Vec::new();
+        let mut real = self.stored_leaves();
+        let zero_hashes = self.zero_hashes();
+        for (height, level) in self.levels().into_iter().enumerate() {
+            let mut nodes: Vec<Arc<Node>> = Vec::with_capacity(level.len());
+            let mut children = below.chunks(arity);
+            for i in level.clone().take(real) {
+                let children = children.next().map(|group| group.to_vec()).unwrap_or_default();
+                nodes.push(Arc::new(Node { hash: self.node_hash(i), children }));
+            }
+            // Padding shares the last real node, or is a zero hash
+            while nodes.len() < level.len() {
+                let padding = match zero_hashes {
+                    Some(zero_hashes) => Arc::new(Node { hash: zero_hashes[height].clone(), children: Vec::new() }),
+                    None => nodes[nodes.len() - 1].clone(),
+                };
+                nodes.push(padding);
+            }
+            real_lens.push(real);
+            real = level.len() / arity;
+            below = nodes;
+        }
+
+        PersistentTree {
+            root: below.pop(),
+            real_lens,
+            arity,
+            zero_padded: zero_hashes.is_some(),
+            hasher: self.shared_hasher(),
+        }
+    }
+}
+
+impl PersistentTree {
+    /// Returns a new handle to the current version. This takes constant time;
+    /// later changes to either handle do not affect the other.
+    pub fn snapshot(&self) -> Self {
+        self.clone()
+    }
+
+    /// Returns the root hash, or `None` if the tree is empty.
+    pub fn root_hash(&self) -> Option<RootHash> {
+        self.root.as_ref().map(|root| RootHash::new(root.hash.clone()))
+    }
+
+    /// Returns the number of blocks of the tree.
+    pub fn num_blocks(&self) -> usize {
+        self.real_lens.first().copied().unwrap_or(0)
+    }
+
+    /// Returns the hash of the leaf at `index`.
+    pub fn leaf_hash(&self, index: usize) -> Option<&[u8]> {
+        if index >= self.num_blocks() {
+            return None;
+        }
+        let mut node = self.root.as_ref()?;
+        for height in (1..self.real_lens.len()).rev() {
+            node = &node.children[self.slot(index, height - 1)];
+        }
+        Some(&node.hash)
+    }
+
+    /// Replaces the hash of the leaf at `index`, copying only the nodes on its
+    /// path. Returns `false` if there is no such leaf.
+    pub fn set_leaf(&mut self, index: usize, hash: Vec<u8>) -> bool {
+        if index >= self.num_blocks() {
+            return false;
+        }
+        let root = self.root.take().unwrap();
+        self.root = Some(self.replace(&root, self.real_lens.len() - 1, index, hash));
+        true
+    }
+
+    /// Returns an inclusion proof for the block at `index`.
+    pub fn proof(&self, index: usize) -> Option<Proof> {
+        if index >= self.num_blocks() {
+            return None;
+        }
+        let mut node = self.root.as_ref()?;
+        let mut levels = Vec::with_capacity(self.real_lens.len() - 1);
+        for height in (1..self.real_lens.len()).rev() {
+            let slot = self.slot(index, height - 1);
+            let siblings = node
+                .children
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i != slot)
+                .map(|(_, child)| child.hash.clone())
+                .collect();
+            levels.push(ProofLevel { position: slot, siblings });
+            node = &node.children[slot];
+        }
+        levels.reverse();
+        Some(Proof { leaf_index: index as u64, levels })
+    }
+
+    /// Returns the position among its siblings of the ancestor at `height` of leaf `index`.
+    fn slot(&self, index: usize, height: usize) -> usize {
+        index / self.arity.pow(height as u32) % self.arity
+    }
+
+    /// Returns a copy of `node`, at `height`, with the leaf `index` below it replaced.
+    fn replace(&self, node: &Arc<Node>, height: usize, index: usize, hash: Vec<u8>) -> Arc<Node> {
+        if height == 0 {
+            return Arc::new(Node { hash, children: Vec::new() });
+        }
+        let slot = self.slot(index, height - 1);
+        let child = self.replace(&node.children[slot], height - 1, index, hash);
+
+        let mut children = node.children.clone();
+        // Padding copies of the last real node change along with it
+        let last_real = index / self.arity.pow(height as u32 - 1) + 1 == self.real_lens[height - 1] && !self.zero_padded;
+        let end = if last_real { children.len() } else { slot + 1 };
+        for sibling in &mut children[slot..end] {
+            *sibling = child.clone();
+        }
+
+        let hashes: Vec<&[u8]> = children.iter().map(|child| child.hash.as_slice()).collect();
+        let hash = hash_group(self.hasher.as_ref(), &hashes);
+        Arc::new(Node { hash, children })
+    }
+}
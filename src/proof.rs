@@ -0,0 +1,433 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::hasher::{hash_group, PairHasher, Sha256Hasher};
+use crate::index::LeafIndex;
+use crate::io::{to_usize, Error, ErrorKind};
+use crate::root::constant_time_eq;
+use crate::store::NodeStore;
+use crate::HashTree;
+
+/// One level of a `Proof`: the position of the proven node among its
+/// siblings and the hashes of those siblings, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofLevel {
+    /// Position of the node within its group of `arity` siblings.
+    pub position: usize,
+    /// Hashes of the other `arity - 1` nodes in the group.
+    pub siblings: Vec<Vec<u8>>,
+}
+
+/// An inclusion proof for a single block of a `HashTree`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proof {
+    /// Index of the proven block.
+    pub leaf_index: u64,
+    /// Proof levels from the leaves up to the root.
+    pub levels: Vec<ProofLevel>,
+}
+
+impl Proof {
+    /// Reassembles the proof for the block at `leaf_index` of a tree with the
+    /// given `arity` from its sibling hashes alone, `arity - 1` per level from
+    /// the leaves up. Returns `None` if the hashes do not form whole levels or
+    /// are too few for `leaf_index`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::{HashTree, Proof};
+    ///
+    /// let tree = HashTree::new(1).from_data(&mut &b"abcde"[..]).unwrap();
+    /// let proof = tree.proof(4).unwrap();
+    /// let siblings = proof.levels.iter().flat_map(|level| level.siblings.clone()).collect();
+    /// assert_eq!(Proof::from_siblings(4, 2, siblings), Some(proof));
+    /// ```
+    pub fn from_siblings(leaf_index: u64, arity: usize, siblings: Vec<Vec<u8>>) -> Option<Self> {
+        if arity < 2 || !siblings.len().is_multiple_of(arity - 1) {
+            return None;
+        }
+        let mut position = leaf_index;
+        let mut levels = Vec::new();
+        for group in siblings.chunks(arity - 1) {
+            levels.push(ProofLevel { position: (position % arity as u64) as usize, siblings: group.to_vec() });
+            position /= arity as u64;
+        }
+        if position != 0 {
+            return None;
+        }
+        Some(Self { leaf_index, levels })
+    }
+
+    /// Returns the root implied by this proof for the given leaf hash,
+    /// assuming the default SHA-256 hasher.
+    pub fn compute_root(&self, leaf_hash: &[u8]) -> Vec<u8> {
+        self.compute_root_with(&Sha256Hasher::new(), leaf_hash)
+    }
+
+    /// Returns the root implied by this proof for the given leaf hash under `hasher`.
+    pub fn compute_root_with(&self, hasher: &dyn PairHasher, leaf_hash: &[u8]) -> Vec<u8> {
+        let mut hash = leaf_hash.to_vec();
+        for level in &self.levels {
+            let mut children: Vec<&[u8]> = level.siblings.iter().map(|s| s.as_slice()).collect();
+            children.insert(level.position.min(children.len()), &hash);
+            hash = hash_group(hasher, &children);
+        }
+        hash
+    }
+
+    /// Returns `true` if the block with hash `leaf_hash` is included under
+    /// `root`, assuming the default SHA-256 hasher. The roots are compared in
+    /// constant time.
+    pub fn verify(&self, root: &[u8], leaf_hash: &[u8]) -> bool {
+        constant_time_eq(&self.compute_root(leaf_hash), root)
+    }
+
+    /// Returns `true` if the block with hash `leaf_hash` is included under `root` using `hasher`.
+    pub fn verify_with(&self, hasher: &dyn PairHasher, root: &[u8], leaf_hash: &[u8]) -> bool {
+        constant_time_eq(&self.compute_root_with(hasher, leaf_hash), root)
+    }
+
+    /// Returns the proof as JSON in the shape used by JavaScript Merkle tree
+    /// libraries such as merkletreejs: the `leaf` and `root` hashes and the
+    /// flat `proof` array of sibling hashes from the leaves up, all as
+    /// 0x-prefixed hex strings. The `index` and `arity` of the proof are
+    /// included too, since siblings are ordered by position rather than
+    /// sorted, so verifiers must know on which side each sibling goes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::{HashTree, PairHasher, Sha256Hasher};
+    ///
+    /// let tree = HashTree::new(1).from_data(&mut &b"ab"[..]).unwrap();
+    /// let leaf = Sha256Hasher::new().hash_leaf(b"b");
+    /// let root = tree.root_hash().unwrap().into_bytes();
+    /// let json = tree.proof(1).unwrap().to_web3_json(&leaf, &root);
+    /// assert!(json.starts_with("{\"root\":\"0x"));
+    /// assert!(json.ends_with(&format!("\"proof\":[\"0x{}\"]}}", hex::encode(Sha256Hasher::new().hash_leaf(b"a")))));
+    /// ```
+    pub fn to_web3_json(&self, leaf_hash: &[u8], root: &[u8]) -> String {
+        let mut json = String::new();
+        // Writing to a `String` cannot fail
+        let _ = write!(
+            json,
+            "{{\"root\":\"0x{}\",\"leaf\":\"0x{}\",\"index\":{},\"arity\":{},\"proof\":[",
+            hex::encode(root),
+            hex::encode(leaf_hash),
+            self.leaf_index,
+            self.arity()
+        );
+        let siblings = self.levels.iter().flat_map(|level| level.siblings.iter());
+        for (i, sibling) in siblings.enumerate() {
+            let _ = write!(json, "{}\"0x{}\"", if i == 0 { "" } else { "," }, hex::encode(sibling));
+        }
+        json.push_str("]}");
+        json
+    }
+
+    /// Encodes the proof compactly: the leaf index, arity, hash length and
+    /// number of levels as LEB128 varints, then the positions of every level
+    /// packed into a bitmap of `ceil(log2(arity))` bits each, one bit per
+    /// level for binary trees, then the sibling hashes back to back without
+    /// lengths. Decode with `from_compact_bytes`.
+    /// Returns an `Error` value if the sibling hashes differ in length or a
+    /// level does not have `arity - 1` siblings.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::{HashTree, Proof};
+    ///
+    /// let tree = HashTree::new(1).from_data(&mut &b"abcdefgh"[..]).unwrap();
+    /// let proof = tree.proof(5).unwrap();
+    /// let bytes = proof.to_compact_bytes().unwrap();
+    /// // Four varints, one byte of direction bits and three 32-byte hashes
+    /// assert_eq!(bytes.len(), 4 + 1 + 3 * 32);
+    /// assert_eq!(Proof::from_compact_bytes(&bytes).unwrap(), proof);
+    /// ```
+    pub fn to_compact_bytes(&self) -> Result<Vec<u8>, Error> {
+        let arity = self.arity();
+        let hash_len = self.levels.first().and_then(|level| level.siblings.first()).map_or(0, |hash| hash.len());
+        let mut bytes = Vec::new();
+        for value in [self.leaf_index, arity as u64, hash_len as u64, self.levels.len() as u64] {
+            write_varint(&mut bytes, value);
+        }
+
+        let bits = position_bits(arity);
+        let mut bitmap = alloc::vec![0u8; (self.levels.len() * bits).div_ceil(8)];
+        for (level, proof_level) in self.levels.iter().enumerate() {
+            if proof_level.siblings.len() != arity - 1 || proof_level.position >= arity {
+                return Err(Error::new(ErrorKind::InvalidInput, "proof levels do not match the arity"));
+            }
+            for bit in 0..bits {
+                if proof_level.position >> bit & 1 == 1 {
+                    let index = level * bits + bit;
+                    bitmap[index / 8] |= 1 << (index % 8);
+                }
+            }
+        }
+        bytes.extend_from_slice(&bitmap);
+
+        for sibling in self.levels.iter().flat_map(|level| level.siblings.iter()) {
+            if sibling.len() != hash_len {
+                return Err(Error::new(ErrorKind::InvalidInput, "sibling hashes differ in length"));
+            }
+            bytes.extend_from_slice(sibling);
+        }
+        Ok(bytes)
+    }
+
+    /// Decodes a proof encoded by `to_compact_bytes`.
+    /// Returns an `Error` value if `bytes` is truncated, has trailing bytes
+    /// or holds an invalid arity, hash length or position.
+    pub fn from_compact_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut rest = bytes;
+        let leaf_index = read_varint(&mut rest)?;
+        let arity = to_usize(read_varint(&mut rest)?)?;
+        let hash_len = to_usize(read_varint(&mut rest)?)?;
+        let num_levels = to_usize(read_varint(&mut rest)?)?;
+        if arity < 2 {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid proof arity"));
+        }
+        // Without empty hashes the length check below bounds the arity by the
+        // input, before anything is allocated for it
+        if hash_len == 0 && num_levels > 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid hash length 0"));
+        }
+
+        let bits = position_bits(arity);
+        let bitmap_len = num_levels.checked_mul(bits).ok_or_else(truncated)?.div_ceil(8);
+        let siblings_len = num_levels
+            .checked_mul(arity - 1)
+            .and_then(|count| count.checked_mul(hash_len))
+            .ok_or_else(truncated)?;
+        if rest.len() != bitmap_len.checked_add(siblings_len).ok_or_else(truncated)? {
+            return Err(truncated());
+        }
+        let (bitmap, mut hashes) = rest.split_at(bitmap_len);
+
+        let mut levels = Vec::with_capacity(num_levels);
+        for level in 0..num_levels {
+            let mut position = 0;
+            for bit in 0..bits {
+                let index = level * bits + bit;
+                position |= ((bitmap[index / 8] >> (index % 8) & 1) as usize) << bit;
+            }
+            if position >= arity {
+                return Err(Error::new(ErrorKind::InvalidData, "invalid proof position"));
+            }
+            let mut siblings = Vec::with_capacity(arity - 1);
+            for _ in 0..arity - 1 {
+                let (hash, rest) = hashes.split_at(hash_len);
+                siblings.push(hash.to_vec());
+                hashes = rest;
+            }
+            levels.push(ProofLevel { position, siblings });
+        }
+        Ok(Self { leaf_index, levels })
+    }
+
+    /// Returns the index of the proven leaf.
+    pub fn leaf(&self) -> LeafIndex {
+        LeafIndex(self.leaf_index)
+    }
+
+    /// Returns the arity of the tree the proof was generated from.
+    pub fn arity(&self) -> usize {
+        self.levels.first().map(|level| level.siblings.len() + 1).unwrap_or(2)
+    }
+}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Returns an iterator over the proofs of all blocks, in order, for
+    /// workflows that publish every proof up front, e.g. airdrops.
+    ///
+    /// The tree is traversed once: each group of siblings is read from the
+    /// store when the first block below it is proven and reused for the
+    /// following ones, so the iterator reads every node once rather than once
+    /// per proof it appears in.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let tree = HashTree::new(1).from_slice(b"abcde").unwrap();
+    /// for (index, proof) in tree.proofs() {
+    ///     assert_eq!(Some(proof), tree.proof(index));
+    /// }
+    /// assert_eq!(tree.proofs().len(), 5);
+    /// ```
+    pub fn proofs(&self) -> Proofs<'_, S> {
+        self.proofs_from(0)
+    }
+
+    /// Returns an iterator over the proofs of the blocks from `start` on.
+    pub(crate) fn proofs_from(&self, start: usize) -> Proofs<'_, S> {
+        let levels = self.levels();
+        let depth = levels.len().saturating_sub(1);
+        Proofs { tree: self, levels, groups: alloc::vec![None; depth], next: start.min(self.stored_leaves()) }
+    }
+}
+
+/// An iterator over the proofs of all blocks of a `HashTree`, see
+/// `HashTree::proofs`.
+#[derive(Debug)]
+pub struct Proofs<'a, S> {
+    tree: &'a HashTree<S>,
+    levels: Vec<core::ops::Range<usize>>,
+    /// The group of siblings last read on every level below the root, by
+    /// the index of its first node.
+    groups: Vec<Option<(usize, Vec<Vec<u8>>)>>,
+    next: usize,
+}
+
+impl<S: NodeStore> Iterator for Proofs<'_, S> {
+    type Item = (LeafIndex, Proof);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.next;
+        if index >= self.tree.stored_leaves() {
+            return None;
+        }
+        self.next += 1;
+
+        let arity = self.tree.get_arity();
+        let mut position = index;
+        let mut levels = Vec::with_capacity(self.groups.len());
+        for (range, cached) in self.levels.iter().zip(self.groups.iter_mut()) {
+            let group = range.start + position / arity * arity;
+            if cached.as_ref().map(|(start, _)| *start) != Some(group) {
+                let nodes = self.tree.store().iter_range(group..group + arity).collect();
+                *cached = Some((group, nodes));
+            }
+            let nodes = cached.as_ref().map(|(_, nodes)| nodes).expect("group was just read");
+            let mut siblings = nodes.clone();
+            siblings.remove(position % arity);
+            levels.push(ProofLevel { position: position % arity, siblings });
+            position /= arity;
+        }
+        Some((LeafIndex::from(index), Proof { leaf_index: index as u64, levels }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.tree.stored_leaves() - self.next;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<S: NodeStore> ExactSizeIterator for Proofs<'_, S> {}
+
+/// Returns the number of bits needed for a position among `arity` siblings.
+fn position_bits(arity: usize) -> usize {
+    (usize::BITS - (arity - 1).leading_zeros()) as usize
+}
+
+/// Appends `value` as an unsigned LEB128 varint.
+pub(crate) fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Reads an unsigned LEB128 varint from the front of `bytes`.
+pub(crate) fn read_varint(bytes: &mut &[u8]) -> Result<u64, Error> {
+    let mut value: u64 = 0;
+    for shift in (0..u64::BITS).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or_else(truncated)?;
+        *bytes = rest;
+        let low = (byte & 0x7f) as u64;
+        if low << shift >> shift != low {
+            break;
+        }
+        value |= low << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::new(ErrorKind::InvalidData, "varint overflows"))
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::InvalidData, "truncated or oversized encoding")
+}
+
+/// Returns `true` if the per-level positions of `proof` agree with its leaf index.
+pub(crate) fn positions_match(proof: &Proof) -> bool {
+    let mut position = proof.leaf_index;
+    for level in &proof.levels {
+        let arity = level.siblings.len() as u64 + 1;
+        if position % arity != level.position as u64 {
+            return false;
+        }
+        position /= arity;
+    }
+    position == 0
+}
+
+/// The children of a parent node and the parent's hash.
+type KnownParent = (Vec<Vec<u8>>, Vec<u8>);
+
+/// Verifies many proofs against the same `root`, assuming the default
+/// SHA-256 hasher. See `verify_proofs_with`.
+pub fn verify_proofs(root: &[u8], proofs: &[(&[u8], u64, &Proof)]) -> Vec<bool> {
+    verify_proofs_with(&Sha256Hasher::new(), root, proofs)
+}
+
+/// Verifies many `(leaf_hash, index, proof)` triples against the same
+/// `root` using `hasher`, returning whether each one is valid.
+///
+/// Proofs of nearby blocks share most of their path to the root, so every
+/// parent is hashed once and reused by later proofs whose children at that
+/// node are identical. A proof whose `leaf_index` differs from `index`, or
+/// whose positions within the levels do not lead to `index`, is invalid.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::proof::verify_proofs;
+/// use hashtree::{HashTree, PairHasher, Sha256Hasher};
+///
+/// let tree = HashTree::new(1).from_data(&mut &b"abcdefgh"[..]).unwrap();
+/// let root = tree.root_hash().unwrap().into_bytes();
+/// let leaves: Vec<Vec<u8>> = [b"a", b"b", b"x"].iter().map(|block| Sha256Hasher::new().hash_leaf(&block[..])).collect();
+/// let proofs: Vec<_> = (0..3).map(|index| tree.proof(index).unwrap()).collect();
+/// let batch: Vec<_> = (0..3).map(|index| (&leaves[index][..], index as u64, &proofs[index])).collect();
+/// assert_eq!(verify_proofs(&root, &batch), vec![true, true, false]);
+/// ```
+pub fn verify_proofs_with(hasher: &dyn PairHasher, root: &[u8], proofs: &[(&[u8], u64, &Proof)]) -> Vec<bool> {
+    // Parents computed so far, by level and index within the level, with
+    // the children they were computed from
+    let mut parents: BTreeMap<(usize, u64), KnownParent> = BTreeMap::new();
+    let mut results = Vec::with_capacity(proofs.len());
+    for &(leaf_hash, index, proof) in proofs {
+        if proof.leaf_index != index || !positions_match(proof) {
+            results.push(false);
+            continue;
+        }
+        let arity = proof.arity();
+        let mut hash = leaf_hash.to_vec();
+        let mut node = index;
+        for (level, proof_level) in proof.levels.iter().enumerate() {
+            let mut children = proof_level.siblings.clone();
+            children.insert(proof_level.position.min(children.len()), hash);
+            node /= arity as u64;
+            hash = match parents.get(&(level, node)) {
+                Some((known, parent)) if *known == children => parent.clone(),
+                _ => {
+                    let refs: Vec<&[u8]> = children.iter().map(|child| child.as_slice()).collect();
+                    let parent = hash_group(hasher, &refs);
+                    parents.entry((level, node)).or_insert_with(|| (children, parent.clone()));
+                    parent
+                }
+            };
+        }
+        results.push(constant_time_eq(&hash, root));
+    }
+    results
+}
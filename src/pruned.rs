@@ -0,0 +1,178 @@
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind, SeekFrom};
+use std::sync::Arc;
+
+use crate::hasher::{block_buf, hash_group, PairHasher};
+use crate::proof::{Proof, ProofLevel};
+use crate::root::RootHash;
+use crate::HashTree;
+
+/// A `HashTree` that only keeps its top levels in memory.
+///
+/// Proofs for a block are completed by re-reading and rehashing the blocks
+/// below the lowest kept node on the path, so the memory needed to serve
+/// proofs for a huge file is bounded by the number of kept levels rather
+/// than by the number of blocks. The recomputed node is checked against the
+/// kept one, so a modified source is detected.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::HashTree;
+/// use std::io::Cursor;
+///
+/// let data = vec![7u8; 64 * 1024];
+/// let tree = HashTree::new(1024).from_data(&mut data.as_slice()).unwrap();
+/// let expected = tree.proof(10).unwrap();
+///
+/// let pruned = tree.into_pruned(3);
+/// assert!(pruned.num_stored_nodes() < 16);
+/// let proof = pruned.proof(10, &mut Cursor::new(&data)).unwrap().unwrap();
+/// assert_eq!(proof, expected);
+/// ```
+#[derive(Debug, Clone)]
+pub struct PrunedTree {
+    kept: Vec<Vec<Vec<u8>>>,
+    base: usize,
+    padded_lens: Vec<usize>,
+    real_lens: Vec<usize>,
+    num_blocks: usize,
+    block_size: usize,
+    arity: usize,
+    hasher: Arc<dyn PairHasher>,
+    zero_hashes: Option<Vec<Vec<u8>>>,
+}
+
+impl HashTree {
+    /// Drops all but the top `levels` levels of the tree (counting the root
+    /// as one level) and returns the resulting `PrunedTree`.
+    pub fn into_pruned(self, levels: usize) -> PrunedTree {
+        let ranges = self.levels();
+        let base = ranges.len().saturating_sub(levels.max(1));
+        let kept = ranges[base..]
+            .iter()
+            .map(|range| range.clone().map(|i| self.node_hash(i)).collect())
+            .collect();
+
+        let padded_lens: Vec<usize> = ranges.iter().map(|range| range.len()).collect();
+        let mut real_lens = vec![self.stored_leaves()];
+        for len in &padded_lens[..padded_lens.len().saturating_sub(1)] {
+            real_lens.push(len / self.get_arity());
+        }
+
+        PrunedTree {
+            kept,
+            base,
+            padded_lens,
+            real_lens,
+            num_blocks: self.stored_leaves(),
+            block_size: self.block_size(),
+            arity: self.get_arity(),
+            hasher: self.shared_hasher(),
+            zero_hashes: self.zero_hashes().map(<[Vec<u8>]>::to_vec),
+        }
+    }
+}
+
+impl PrunedTree {
+    /// Returns the root hash, or `None` if the tree is empty.
+    pub fn root_hash(&self) -> Option<RootHash> {
+        self.kept.last().and_then(|level| level.first()).cloned().map(RootHash::new)
+    }
+
+    /// Returns the number of blocks the tree was built from.
+    pub fn num_blocks(&self) -> usize {
+        self.num_blocks
+    }
+
+    /// Returns the number of levels held in memory.
+    pub fn num_levels(&self) -> usize {
+        self.kept.len()
+    }
+
+    /// Returns the number of node hashes held in memory.
+    pub fn num_stored_nodes(&self) -> usize {
+        self.kept.iter().map(|level| level.len()).sum()
+    }
+
+    /// Returns an inclusion proof for the block at `index`, re-reading the
+    /// blocks it needs from `source`, which must hold the data the tree was
+    /// built from in fixed-size blocks.
+    /// Returns `Ok(None)` if there is no such block, and an `Error` value if
+    /// the source could not be read or does not match the tree.
+    pub fn proof<R: Read + Seek>(&self, index: usize, source: &mut R) -> Result<Option<Proof>, Error> {
+        if index >= self.num_blocks {
+            return Ok(None);
+        }
+
+        let span = self.arity.pow(self.base as u32);
+        let top = index / span;
+        let lower = self.recompute(top, source)?;
+
+        let mut levels = Vec::new();
+        let mut position = index;
+        for (height, nodes) in lower.iter().enumerate() {
+            let first = top * self.arity.pow((self.base - height) as u32);
+            let group = position / self.arity * self.arity;
+            levels.push(self.proof_level(position, |i| nodes[i - first].clone(), group));
+            position /= self.arity;
+        }
+        for nodes in &self.kept[..self.kept.len() - 1] {
+            let group = position / self.arity * self.arity;
+            levels.push(self.proof_level(position, |i| nodes[i].clone(), group));
+            position /= self.arity;
+        }
+
+        Ok(Some(Proof { leaf_index: index as u64, levels }))
+    }
+
+    fn proof_level<F: Fn(usize) -> Vec<u8>>(&self, position: usize, hash: F, group: usize) -> ProofLevel {
+        let siblings = (group..group + self.arity).filter(|&i| i != position).map(hash).collect();
+        ProofLevel { position: position % self.arity, siblings }
+    }
+
+    /// Recomputes the levels below the kept node at `top` on the lowest kept level.
+    fn recompute<R: Read + Seek>(&self, top: usize, source: &mut R) -> Result<Vec<Vec<Vec<u8>>>, Error> {
+        let mut lower: Vec<Vec<Vec<u8>>> = Vec::with_capacity(self.base);
+        for height in 0..=self.base {
+            let width = self.arity.pow((self.base - height) as u32);
+            let first = top * width;
+            let end = (first + width).min(self.padded_lens[height]);
+            let real_end = end.min(self.real_lens[height]);
+
+            let mut nodes = Vec::with_capacity(end - first);
+            if height == 0 {
+                source.seek(SeekFrom::Start(first as u64 * self.block_size as u64))?;
+                let mut buf = block_buf(self.block_size);
+                for _ in first..real_end {
+                    buf.clear();
+                    source.by_ref().take(self.block_size as u64).read_to_end(&mut buf)?;
+                    if buf.is_empty() {
+                        return Err(Error::new(ErrorKind::UnexpectedEof, "source is shorter than the tree"));
+                    }
+                    nodes.push(self.hasher.hash_leaf(&buf));
+                }
+            } else {
+                for group in lower[height - 1][..(real_end - first) * self.arity].chunks(self.arity) {
+                    let children: Vec<&[u8]> = group.iter().map(|h| h.as_slice()).collect();
+                    nodes.push(hash_group(self.hasher.as_ref(), &children));
+                }
+            }
+            // Padding zero hashes or copies of the last real node of the level
+            while nodes.len() < end - first {
+                let padding = match &self.zero_hashes {
+                    Some(zero_hashes) => zero_hashes[height].clone(),
+                    None => nodes.last().unwrap().clone(),
+                };
+                nodes.push(padding);
+            }
+            lower.push(nodes);
+        }
+
+        let node = lower.pop().unwrap();
+        if node[0] != self.kept[0][top] {
+            return Err(Error::new(ErrorKind::InvalidData, "source does not match the tree"));
+        }
+        Ok(lower)
+    }
+}
@@ -0,0 +1,217 @@
+use alloc::format;
+use alloc::string::String;
+use core::fmt::{self, Write};
+
+use crate::store::NodeStore;
+use crate::HashTree;
+
+/// Number of hex digits of a hash shown when rendering a tree.
+const HASH_DIGITS: usize = 8;
+
+/// Renders the tree from the root down, one node per line, with hashes cut
+/// to their first 8 hex digits. Leaves are shown by label, see
+/// `HashTree::set_label`, and copies padding a level are marked.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::HashTree;
+///
+/// let tree = HashTree::new(1).from_slice(b"abc").unwrap().with_labels(["a"]);
+/// let text = tree.to_string();
+/// assert_eq!(text.lines().count(), 7);
+/// assert!(text.lines().nth(2).unwrap().ends_with("── a ca978112"));
+/// assert!(text.lines().nth(3).unwrap().contains("── B1 3e23e816"));
+/// assert!(text.ends_with("(padding)\n"));
+/// ```
+impl<S: NodeStore> fmt::Display for HashTree<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let levels = self.levels();
+        let top = match levels.len().checked_sub(1) {
+            Some(top) => top,
+            None => return writeln!(f, "(empty)"),
+        };
+        writeln!(f, "{}", short_hash(&self.node_hash(levels[top].start)))?;
+        self.fmt_children(f, &levels, top, 0, &mut String::new())
+    }
+}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Returns the tree as a Graphviz DOT digraph, for viewing with e.g.
+    /// `dot -Tsvg`. Nodes are named after their storage index and show the
+    /// first 8 hex digits of their hash; leaves also show their label, see
+    /// `set_label`. Copies padding a level are drawn dashed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let dot = HashTree::new(1).from_slice(b"abc").unwrap().to_dot();
+    /// assert!(dot.starts_with("digraph hashtree {"));
+    /// assert!(dot.contains("n0 [label=\"B0\\nca978112\"];"));
+    /// assert!(dot.contains("n4 -> n0;"));
+    /// ```
+    pub fn to_dot(&self) -> String {
+        let levels = self.levels();
+        let mut dot = String::from("digraph hashtree {\n    node [shape=box, fontname=\"monospace\"];\n");
+        for (level, range) in levels.iter().enumerate() {
+            let computed = self.computed_nodes(&levels, level);
+            for (offset, index) in range.clone().enumerate() {
+                let hash = short_hash(&self.node_hash(index));
+                let label = match level {
+                    0 if offset < self.stored_leaves() => format!("{}\\n{}", escape(&self.leaf_name(offset)), hash),
+                    _ => hash,
+                };
+                let style = if offset < computed { "" } else { ", style=dashed" };
+                // Writing to a `String` cannot fail
+                let _ = writeln!(dot, "    n{} [label=\"{}\"{}];", index, label, style);
+                if level > 0 && offset < computed {
+                    let children = levels[level - 1].start + offset * self.get_arity();
+                    for child in children..children + self.get_arity() {
+                        let _ = writeln!(dot, "    n{} -> n{};", index, child);
+                    }
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Returns the tree as a Mermaid flowchart, to paste into Markdown docs
+    /// and issue reports that render Mermaid. Only the top `max_depth`
+    /// levels below the root are drawn, so large trees stay readable; pass
+    /// `usize::MAX` for the whole tree. Nodes are named after their storage
+    /// index and show the first `hash_digits` hex digits of their hash;
+    /// leaves also show their label, see `set_label`. Copies padding a level
+    /// are drawn dashed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let tree = HashTree::new(1).from_slice(b"abc").unwrap();
+    /// let mermaid = tree.to_mermaid(usize::MAX, 4);
+    /// assert!(mermaid.starts_with("flowchart TD\n"));
+    /// assert!(mermaid.contains("    n0[\"B0<br/>ca97\"]\n"));
+    /// assert!(mermaid.contains("    n4 --> n0\n"));
+    /// assert!(!tree.to_mermaid(0, 4).contains("-->"));
+    /// ```
+    pub fn to_mermaid(&self, max_depth: usize, hash_digits: usize) -> String {
+        let levels = self.levels();
+        let mut mermaid = String::from("flowchart TD\n");
+        let bottom = (levels.len().saturating_sub(1)).saturating_sub(max_depth);
+        let mut padding = false;
+        for level in (bottom..levels.len()).rev() {
+            let computed = self.computed_nodes(&levels, level);
+            for (offset, index) in levels[level].clone().enumerate() {
+                let hash = truncated_hash(&self.node_hash(index), hash_digits);
+                let label = match level {
+                    0 if offset < self.stored_leaves() => format!("{}<br/>{}", escape_mermaid(&self.leaf_name(offset)), hash),
+                    _ => hash,
+                };
+                let class = if offset < computed { "" } else { ":::padding" };
+                padding |= offset >= computed;
+                // Writing to a `String` cannot fail
+                let _ = writeln!(mermaid, "    n{}[\"{}\"]{}", index, label, class);
+                if level > bottom && offset < computed {
+                    let children = levels[level - 1].start + offset * self.get_arity();
+                    for child in children..children + self.get_arity() {
+                        let _ = writeln!(mermaid, "    n{} --> n{}", index, child);
+                    }
+                }
+            }
+        }
+        if padding {
+            mermaid.push_str("    classDef padding stroke-dasharray: 5 5\n");
+        }
+        mermaid
+    }
+
+    /// Writes the children of the node at `offset` of `level`, prefixing
+    /// every line with `indent`.
+    fn fmt_children(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        levels: &[core::ops::Range<usize>],
+        level: usize,
+        offset: usize,
+        indent: &mut String,
+    ) -> fmt::Result {
+        if level == 0 {
+            return Ok(());
+        }
+        let arity = self.get_arity();
+        let computed = self.computed_nodes(levels, level - 1);
+        for child in offset * arity..(offset + 1) * arity {
+            let last = child + 1 == (offset + 1) * arity;
+            let hash = short_hash(&self.node_hash(levels[level - 1].start + child));
+            write!(f, "{}{}", indent, if last { "└── " } else { "├── " })?;
+            match (level - 1, child < computed) {
+                (0, true) => writeln!(f, "{} {}", self.leaf_name(child), hash)?,
+                (_, true) => writeln!(f, "{}", hash)?,
+                (_, false) => writeln!(f, "{} (padding)", hash)?,
+            }
+            if child < computed {
+                let len = indent.len();
+                indent.push_str(if last { "    " } else { "│   " });
+                self.fmt_children(f, levels, level - 1, child, indent)?;
+                indent.truncate(len);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of nodes of `level` that are not padding.
+    pub(crate) fn computed_nodes(&self, levels: &[core::ops::Range<usize>], level: usize) -> usize {
+        match level {
+            0 => self.stored_leaves(),
+            _ => levels[level - 1].len() / self.get_arity(),
+        }
+    }
+
+    /// Returns the label of the leaf at `index`, or `B` and its index.
+    fn leaf_name(&self, index: usize) -> String {
+        self.label(index).map_or_else(|| format!("B{}", index), String::from)
+    }
+}
+
+/// Returns the first hex digits of `hash`.
+fn short_hash(hash: &[u8]) -> String {
+    truncated_hash(hash, HASH_DIGITS)
+}
+
+/// Returns the first `digits` hex digits of `hash`.
+fn truncated_hash(hash: &[u8], digits: usize) -> String {
+    let mut hex = hex::encode(&hash[..hash.len().min(digits.div_ceil(2))]);
+    hex.truncate(digits);
+    hex
+}
+
+/// Escapes `text` for a double-quoted DOT string.
+fn escape(text: &str) -> String {
+    text.chars().fold(String::new(), |mut escaped, c| {
+        match c {
+            '"' | '\\' => escaped.extend(['\\', c]),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+        escaped
+    })
+}
+
+/// Escapes `text` for a double-quoted Mermaid label, where quotes and
+/// markup are written as entity codes.
+fn escape_mermaid(text: &str) -> String {
+    text.chars().fold(String::new(), |mut escaped, c| {
+        match c {
+            '"' => escaped.push_str("#quot;"),
+            '<' => escaped.push_str("#lt;"),
+            '>' => escaped.push_str("#gt;"),
+            '\n' => escaped.push_str("<br/>"),
+            c => escaped.push(c),
+        }
+        escaped
+    })
+}
@@ -0,0 +1,283 @@
+//! Encodings of RFC 9162 (Certificate Transparency version 2) structures
+//! for the proofs and tree heads of a `MerkleLog`, so they can be exchanged
+//! with standard CT tooling.
+//!
+//! Proofs and signed tree heads are encoded as `TransItem`s: the two-byte
+//! `VersionedTransType` followed by the TLS encoding of the structure. A
+//! `LogID` is the DER encoding of the log's OID without its tag and length
+//! bytes, between 2 and 127 bytes long.
+//!
+//! # Examples
+//!
+//! ```
+//! use hashtree::{rfc9162, MerkleLog};
+//!
+//! let mut log = MerkleLog::new();
+//! for entry in ["a", "b", "c"].iter() {
+//!     log.append(entry.as_bytes()).unwrap();
+//! }
+//! let log_id = [0x2b, 0x06, 0x01, 0x04, 0x01];
+//! let item = rfc9162::encode_inclusion_proof(&log_id, &log.inclusion_proof(1, 3).unwrap()).unwrap();
+//!
+//! let (id, proof) = rfc9162::decode_inclusion_proof(&item).unwrap();
+//! assert_eq!(id, log_id);
+//! assert!(proof.verify(&log.root(), b"b"));
+//! ```
+
+use std::convert::{TryFrom, TryInto};
+use std::io::{Error, ErrorKind};
+
+use crate::log::{ConsistencyProof, InclusionProof};
+
+/// `VersionedTransType` of a `SignedTreeHeadDataV2`.
+const SIGNED_TREE_HEAD_V2: u16 = 5;
+/// `VersionedTransType` of a `ConsistencyProofDataV2`.
+const CONSISTENCY_PROOF_V2: u16 = 6;
+/// `VersionedTransType` of an `InclusionProofDataV2`.
+const INCLUSION_PROOF_V2: u16 = 7;
+
+/// A `TreeHeadDataV2`: the size and root of a log at some time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeHead {
+    /// Time the tree head was produced, in milliseconds since the epoch.
+    pub timestamp: u64,
+    /// Number of entries in the log.
+    pub tree_size: u64,
+    /// Root of the log at `tree_size` entries.
+    pub root_hash: [u8; 32],
+    /// Extensions as pairs of `ExtensionType` and data, in order.
+    pub extensions: Vec<(u16, Vec<u8>)>,
+}
+
+impl TreeHead {
+    /// Encodes the tree head. These are the bytes a log signs.
+    /// Returns an `Error` value if an extension is too long to encode.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+        self.encode(&mut out)?;
+        Ok(out)
+    }
+
+    /// Decodes a tree head encoded by `to_bytes`.
+    /// Returns an `Error` value if `bytes` is not a valid encoding.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader(bytes);
+        let head = Self::decode(&mut reader)?;
+        reader.finish()?;
+        Ok(head)
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), Error> {
+        out.extend_from_slice(&self.timestamp.to_be_bytes());
+        out.extend_from_slice(&self.tree_size.to_be_bytes());
+        put_vec8(out, &self.root_hash)?;
+        let mut extensions = Vec::new();
+        for (extension_type, data) in &self.extensions {
+            extensions.extend_from_slice(&extension_type.to_be_bytes());
+            put_vec16(&mut extensions, data)?;
+        }
+        put_vec16(out, &extensions)
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        let timestamp = reader.u64()?;
+        let tree_size = reader.u64()?;
+        let root_hash = node_hash(reader.vec8()?)?;
+        let mut extensions = Reader(reader.vec16()?);
+        let mut parsed = Vec::new();
+        while !extensions.0.is_empty() {
+            parsed.push((extensions.u16()?, extensions.vec16()?.to_vec()));
+        }
+        Ok(Self { timestamp, tree_size, root_hash, extensions: parsed })
+    }
+}
+
+/// A `SignedTreeHeadDataV2`: a `TreeHead` with the log's signature over
+/// its encoding, see `TreeHead::sign`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedTreeHead {
+    /// `LogID` of the log.
+    pub log_id: Vec<u8>,
+    /// The signed tree head.
+    pub tree_head: TreeHead,
+    /// Signature over `tree_head.to_bytes()`, in the log's signature scheme.
+    pub signature: Vec<u8>,
+}
+
+impl SignedTreeHead {
+    /// Encodes the signed tree head as a `TransItem`.
+    /// Returns an `Error` value if a field is too long to encode.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = SIGNED_TREE_HEAD_V2.to_be_bytes().to_vec();
+        put_log_id(&mut out, &self.log_id)?;
+        self.tree_head.encode(&mut out)?;
+        put_vec16(&mut out, &self.signature)?;
+        Ok(out)
+    }
+
+    /// Decodes a signed tree head encoded as a `TransItem`.
+    /// Returns an `Error` value if `bytes` is not a valid encoding.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader(bytes);
+        reader.trans_type(SIGNED_TREE_HEAD_V2)?;
+        let log_id = reader.log_id()?;
+        let tree_head = TreeHead::decode(&mut reader)?;
+        let signature = reader.vec16()?.to_vec();
+        reader.finish()?;
+        Ok(Self { log_id, tree_head, signature })
+    }
+}
+
+/// Encodes `proof` as an `InclusionProofDataV2` `TransItem` of the log
+/// `log_id`.
+/// Returns an `Error` value if `log_id` is not between 2 and 127 bytes long
+/// or the proof is too long to encode.
+pub fn encode_inclusion_proof(log_id: &[u8], proof: &InclusionProof) -> Result<Vec<u8>, Error> {
+    encode_proof(INCLUSION_PROOF_V2, log_id, proof.tree_size, proof.leaf_index, &proof.path)
+}
+
+/// Decodes an `InclusionProofDataV2` `TransItem` into the `LogID` and the
+/// proof.
+/// Returns an `Error` value if `bytes` is not a valid encoding.
+pub fn decode_inclusion_proof(bytes: &[u8]) -> Result<(Vec<u8>, InclusionProof), Error> {
+    let data = decode_proof(INCLUSION_PROOF_V2, bytes)?;
+    Ok((data.log_id, InclusionProof { leaf_index: data.second, tree_size: data.first, path: data.path }))
+}
+
+/// Encodes `proof` as a `ConsistencyProofDataV2` `TransItem` of the log
+/// `log_id`.
+/// Returns an `Error` value if `log_id` is not between 2 and 127 bytes long
+/// or the proof is too long to encode.
+pub fn encode_consistency_proof(log_id: &[u8], proof: &ConsistencyProof) -> Result<Vec<u8>, Error> {
+    encode_proof(CONSISTENCY_PROOF_V2, log_id, proof.old_size, proof.new_size, &proof.path)
+}
+
+/// Decodes a `ConsistencyProofDataV2` `TransItem` into the `LogID` and the
+/// proof.
+/// Returns an `Error` value if `bytes` is not a valid encoding.
+pub fn decode_consistency_proof(bytes: &[u8]) -> Result<(Vec<u8>, ConsistencyProof), Error> {
+    let data = decode_proof(CONSISTENCY_PROOF_V2, bytes)?;
+    Ok((data.log_id, ConsistencyProof { old_size: data.first, new_size: data.second, path: data.path }))
+}
+
+/// The fields shared by both proof structures: a `LogID`, two sizes and a
+/// vector of hashes.
+struct ProofData {
+    log_id: Vec<u8>,
+    first: u64,
+    second: u64,
+    path: Vec<[u8; 32]>,
+}
+
+fn encode_proof(trans_type: u16, log_id: &[u8], first: u64, second: u64, path: &[[u8; 32]]) -> Result<Vec<u8>, Error> {
+    let mut out = trans_type.to_be_bytes().to_vec();
+    put_log_id(&mut out, log_id)?;
+    out.extend_from_slice(&first.to_be_bytes());
+    out.extend_from_slice(&second.to_be_bytes());
+    let mut hashes = Vec::with_capacity(path.len() * 33);
+    for hash in path {
+        put_vec8(&mut hashes, hash)?;
+    }
+    put_vec16(&mut out, &hashes)?;
+    Ok(out)
+}
+
+fn decode_proof(trans_type: u16, bytes: &[u8]) -> Result<ProofData, Error> {
+    let mut reader = Reader(bytes);
+    reader.trans_type(trans_type)?;
+    let log_id = reader.log_id()?;
+    let first = reader.u64()?;
+    let second = reader.u64()?;
+    let mut hashes = Reader(reader.vec16()?);
+    let mut path = Vec::new();
+    while !hashes.0.is_empty() {
+        path.push(node_hash(hashes.vec8()?)?);
+    }
+    reader.finish()?;
+    Ok(ProofData { log_id, first, second, path })
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+/// Converts a `NodeHash`, which the logs of this crate always make 32 bytes
+/// long.
+fn node_hash(bytes: &[u8]) -> Result<[u8; 32], Error> {
+    bytes.try_into().map_err(|_| invalid("RFC 9162 node hash is not 32 bytes long"))
+}
+
+fn put_log_id(out: &mut Vec<u8>, log_id: &[u8]) -> Result<(), Error> {
+    if !(2..=127).contains(&log_id.len()) {
+        return Err(Error::new(ErrorKind::InvalidInput, "RFC 9162 log IDs are 2 to 127 bytes long"));
+    }
+    put_vec8(out, log_id)
+}
+
+fn put_vec8(out: &mut Vec<u8>, bytes: &[u8]) -> Result<(), Error> {
+    let len = u8::try_from(bytes.len()).map_err(|_| Error::new(ErrorKind::InvalidInput, "RFC 9162 field is too long"))?;
+    out.push(len);
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+fn put_vec16(out: &mut Vec<u8>, bytes: &[u8]) -> Result<(), Error> {
+    let len = u16::try_from(bytes.len()).map_err(|_| Error::new(ErrorKind::InvalidInput, "RFC 9162 field is too long"))?;
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+/// Reads TLS-encoded fields from the front of a byte slice.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < len {
+            return Err(invalid("RFC 9162 structure is truncated"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn vec8(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.take(1)?[0];
+        self.take(len as usize)
+    }
+
+    fn vec16(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.u16()?;
+        self.take(len as usize)
+    }
+
+    fn trans_type(&mut self, expected: u16) -> Result<(), Error> {
+        if self.u16()? != expected {
+            return Err(invalid("unexpected RFC 9162 TransItem type"));
+        }
+        Ok(())
+    }
+
+    fn log_id(&mut self) -> Result<Vec<u8>, Error> {
+        let log_id = self.vec8()?;
+        if log_id.len() < 2 || log_id.len() > 127 {
+            return Err(invalid("RFC 9162 log IDs are 2 to 127 bytes long"));
+        }
+        Ok(log_id.to_vec())
+    }
+
+    fn finish(&self) -> Result<(), Error> {
+        if !self.0.is_empty() {
+            return Err(invalid("trailing bytes after RFC 9162 structure"));
+        }
+        Ok(())
+    }
+}
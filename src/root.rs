@@ -0,0 +1,213 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use subtle::ConstantTimeEq;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE64_URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A string encoding for a `RootHash`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Lowercase hex, the default.
+    Hex,
+    /// Uppercase hex.
+    UpperHex,
+    /// Standard base64 with padding (RFC 4648, section 4).
+    Base64,
+    /// URL-safe base64 without padding (RFC 4648, section 5), as used in JWTs.
+    Base64Url,
+    /// Uppercase base32 without padding (RFC 4648, section 6), as used for Tiger tree hashes.
+    Base32,
+}
+
+/// The root hash of a tree.
+///
+/// Displays and parses as lowercase hex and compares in constant time, so
+/// roots can be checked against untrusted input without leaking how many
+/// leading bytes matched. Roots built with the default SHA-256 hasher are
+/// 32 bytes; other hashers may produce roots of other lengths.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, RootHash};
+///
+/// let tree = HashTree::new(1).from_data(&mut &b"ab"[..]).unwrap();
+/// let root = tree.root_hash().unwrap();
+/// let parsed: RootHash = root.to_string().parse().unwrap();
+/// assert_eq!(parsed, root);
+/// assert_eq!(root.as_ref().len(), 32);
+/// ```
+#[derive(Clone, Eq)]
+pub struct RootHash(Vec<u8>);
+
+impl RootHash {
+    /// Constructs a `RootHash` from its raw bytes.
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    /// Returns the raw bytes of the root.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Consumes the root and returns its raw bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    /// Returns the root as lowercase hex.
+    pub fn to_hex(&self) -> String {
+        hex::encode(&self.0)
+    }
+
+    /// Returns the root rendered in the given `encoding`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::{Encoding, RootHash};
+    ///
+    /// let root = RootHash::new(b"hello".to_vec());
+    /// assert_eq!(root.encode(Encoding::UpperHex), "68656C6C6F");
+    /// assert_eq!(root.encode(Encoding::Base64), "aGVsbG8=");
+    /// assert_eq!(root.encode(Encoding::Base32), "NBSWY3DP");
+    /// ```
+    pub fn encode(&self, encoding: Encoding) -> String {
+        match encoding {
+            Encoding::Hex => self.to_hex(),
+            Encoding::UpperHex => hex::encode_upper(&self.0),
+            Encoding::Base64 => encode_base64(&self.0, BASE64, true),
+            Encoding::Base64Url => encode_base64(&self.0, BASE64_URL, false),
+            Encoding::Base32 => encode_base32(&self.0),
+        }
+    }
+}
+
+impl From<Vec<u8>> for RootHash {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<[u8; 32]> for RootHash {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes.to_vec())
+    }
+}
+
+impl AsRef<[u8]> for RootHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for RootHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl fmt::LowerHex for RootHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl fmt::UpperHex for RootHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.encode(Encoding::UpperHex))
+    }
+}
+
+impl fmt::Debug for RootHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RootHash({})", self.to_hex())
+    }
+}
+
+impl FromStr for RootHash {
+    type Err = hex::FromHexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        hex::decode(s).map(Self)
+    }
+}
+
+impl PartialEq for RootHash {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(&self.0, &other.0)
+    }
+}
+
+impl core::hash::Hash for RootHash {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl PartialEq<[u8]> for RootHash {
+    fn eq(&self, other: &[u8]) -> bool {
+        constant_time_eq(&self.0, other)
+    }
+}
+
+impl PartialEq<str> for RootHash {
+    fn eq(&self, other: &str) -> bool {
+        match hex::decode(other) {
+            Ok(bytes) => constant_time_eq(&self.0, &bytes),
+            Err(_) => false,
+        }
+    }
+}
+
+impl PartialEq<&str> for RootHash {
+    fn eq(&self, other: &&str) -> bool {
+        self == *other
+    }
+}
+
+fn encode_base64(bytes: &[u8], alphabet: &[u8; 64], pad: bool) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(alphabet[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else if pad {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn encode_base32(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let (mut buffer, mut bits) = (0u16, 0);
+    for &b in bytes {
+        buffer = buffer << 8 | b as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[(buffer >> bits & 0x1f) as usize] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        out.push(BASE32[(buffer << (5 - bits) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+/// Compares two byte strings in time depending only on their lengths.
+/// Lengths are not secret, hash lengths being fixed by the hasher, so
+/// strings of different lengths are unequal right away.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
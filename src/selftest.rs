@@ -0,0 +1,141 @@
+use alloc::format;
+use alloc::vec::Vec;
+
+use crate::io::{Error, ErrorKind};
+use crate::tree::{HashTree, Preset};
+
+/// Entries of the Merkle tree hash test vectors of Certificate Transparency.
+const RFC6962_ENTRIES: [&str; 8] = ["", "00", "10", "2021", "3031", "40414243", "5051525354555657", "606162636465666768696a6b6c6d6e6f"];
+
+/// Roots of the first 1, 2, 3, 5 and 8 entries of `RFC6962_ENTRIES`.
+const RFC6962_ROOTS: [(usize, &str); 5] = [
+    (1, "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d"),
+    (2, "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"),
+    (3, "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77"),
+    (5, "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4"),
+    (8, "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328"),
+];
+
+/// Txids of Bitcoin block 100000 and its Merkle root, in the reversed byte
+/// order block explorers show.
+const BITCOIN_TXIDS: [&str; 4] = [
+    "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+    "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+    "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+    "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+];
+const BITCOIN_ROOT: &str = "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766";
+
+/// Txid of the coinbase of the Bitcoin genesis block, its only transaction
+/// and so its Merkle root, in the same byte order.
+const BITCOIN_GENESIS_ROOT: &str = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+/// Root of the BitTorrent v2 piece layer of `pattern(3 * 16384 + 100)`:
+/// four blocks, the last one short, padded to four leaves.
+const BEP52_ROOT: &str = "b1962ec2f88a1abe430091423d0d9763a8b5c28c5968fbf85f35b3a967ef9028";
+
+/// Root of the SHA-256 THEX tree of `pattern(5000)`: five segments, the last
+/// one short and promoted twice.
+const THEX_ROOT: &str = "e922d47a3c7ccf1740c0dbe28bceae48523c5b19dbccfee2e6376fec2348a0c2";
+
+/// Root of the plain tree of "The quick brown fox jumps over the lazy dog"
+/// in blocks of 4 bytes, with the last node of odd levels duplicated.
+const PLAIN_ROOT: &str = "abf00c5f7c897cae99e9aaed75f770a22f901b801b18af76a240a183f39996c0";
+
+/// Root of the same plain tree with BLAKE3 instead of SHA-256.
+#[cfg(feature = "blake3")]
+const BLAKE3_ROOT: &str = "51c3bad3ddd2ff3f26a65fdf573c98021974840fc888cc07ca0c4e3cc5571938";
+
+impl Preset {
+    /// All presets, in declaration order.
+    #[cfg(not(feature = "blake3"))]
+    pub const ALL: [Preset; 5] = [Preset::Plain, Preset::Rfc6962, Preset::Bitcoin, Preset::BitTorrentV2, Preset::Thex];
+    /// All presets, in declaration order.
+    #[cfg(feature = "blake3")]
+    pub const ALL: [Preset; 6] =
+        [Preset::Plain, Preset::Rfc6962, Preset::Bitcoin, Preset::BitTorrentV2, Preset::Thex, Preset::Blake3];
+
+    /// Checks that every preset computes the roots of its embedded known
+    /// answers, so integrators can confirm at startup or in CI that their
+    /// build, e.g. with a custom backend or on an unusual target, produces
+    /// roots other implementations of each format agree with.
+    /// Returns an `Error` value naming the first preset that fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::Preset;
+    ///
+    /// Preset::self_test().unwrap();
+    /// ```
+    pub fn self_test() -> Result<(), Error> {
+        Self::ALL.iter().try_for_each(|preset| preset.known_answer_test())
+    }
+
+    /// Checks the roots of the known answers of this preset: the Merkle
+    /// tree hash test vectors of Certificate Transparency, the transactions
+    /// of Bitcoin block 100000 and of the genesis block, and reference roots
+    /// computed independently for BitTorrent v2, THEX over SHA-256, and plain
+    /// trees over SHA-256 and BLAKE3.
+    /// Returns an `Error` value describing the first mismatch.
+    pub fn known_answer_test(self) -> Result<(), Error> {
+        let template = || HashTree::new(0).preset(self);
+        match self {
+            Preset::Plain => {
+                let tree = HashTree::new(4).preset(self).from_slice(b"The quick brown fox jumps over the lazy dog")?;
+                self.check(&tree, PLAIN_ROOT, 0)
+            }
+            Preset::Rfc6962 => {
+                let entries: Vec<Vec<u8>> = RFC6962_ENTRIES.iter().map(|entry| decode(entry)).collect();
+                RFC6962_ROOTS.iter().try_for_each(|&(size, root)| self.check(&template().from_blocks(&entries[..size])?, root, size))
+            }
+            Preset::Bitcoin => {
+                let txids = BITCOIN_TXIDS.iter().map(|txid| reversed(decode(txid)));
+                let tree = template().from_leaf_hashes(txids)?;
+                self.check_bytes(&tree, &reversed(decode(BITCOIN_ROOT)), BITCOIN_TXIDS.len())?;
+                let coinbase = reversed(decode(BITCOIN_GENESIS_ROOT));
+                self.check_bytes(&template().from_leaf_hashes(core::iter::once(coinbase.clone()))?, &coinbase, 1)
+            }
+            Preset::BitTorrentV2 => self.check(&template().from_slice(&pattern(3 * 16384 + 100))?, BEP52_ROOT, 0),
+            Preset::Thex => self.check(&template().from_slice(&pattern(5000))?, THEX_ROOT, 0),
+            #[cfg(feature = "blake3")]
+            Preset::Blake3 => {
+                let tree = HashTree::new(4).preset(self).from_slice(b"The quick brown fox jumps over the lazy dog")?;
+                self.check(&tree, BLAKE3_ROOT, 0)
+            }
+        }
+    }
+
+    /// Compares the root of `tree`, built from `size` entries (0 if the
+    /// vector has only one size), with the hex encoded `expected` root.
+    fn check(self, tree: &HashTree, expected: &str, size: usize) -> Result<(), Error> {
+        self.check_bytes(tree, &decode(expected), size)
+    }
+
+    fn check_bytes(self, tree: &HashTree, expected: &[u8], size: usize) -> Result<(), Error> {
+        if tree.root_hash_bytes() == Some(expected) {
+            return Ok(());
+        }
+        let computed = tree.root_hash_bytes().map_or_else(|| "no root".into(), hex::encode);
+        let vector = if size > 0 { format!(" for {} entries", size) } else { "".into() };
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{:?} known answer test failed{}: computed {}, expected {}", self, vector, computed, hex::encode(expected)),
+        ))
+    }
+}
+
+/// Decodes an embedded hex constant.
+fn decode(hex: &str) -> Vec<u8> {
+    hex::decode(hex).expect("known answers are valid hex")
+}
+
+fn reversed(mut bytes: Vec<u8>) -> Vec<u8> {
+    bytes.reverse();
+    bytes
+}
+
+/// Returns `len` bytes counting up modulo 251, so blocks differ.
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
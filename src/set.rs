@@ -0,0 +1,215 @@
+use alloc::vec::Vec;
+
+use crate::hasher::{PairHasher, Sha256Hasher};
+use crate::io::Error;
+use crate::proof::Proof;
+use crate::store::NodeStore;
+use crate::{HashTree, RootHash};
+
+/// A `HashTree` over a set: the leaf hashes of the items are kept sorted
+/// and free of duplicates, so the root only depends on which items are in
+/// the set, not on the order they were added in.
+///
+/// Sorted leaves allow binary-search membership checks and proofs that an
+/// item is absent, see `NonMembershipProof`.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, PairHasher, Sha256Hasher};
+///
+/// let a = HashTree::new(0).from_set(["carol", "alice", "bob"]).unwrap();
+/// let b = HashTree::new(0).from_set(["bob", "carol", "alice", "bob"]).unwrap();
+/// assert_eq!(a.root_hash(), b.root_hash());
+/// assert!(a.contains("alice"));
+///
+/// let root = a.root_hash().unwrap().into_bytes();
+/// let proof = a.non_membership_proof("mallory").unwrap();
+/// assert!(proof.verify(&root, a.len(), &Sha256Hasher::new().hash_leaf(b"mallory")));
+/// assert!(a.non_membership_proof("bob").is_none());
+/// ```
+#[derive(Debug)]
+pub struct SortedSet<S = Vec<Vec<u8>>> {
+    tree: HashTree<S>,
+    leaves: Vec<Vec<u8>>,
+}
+
+/// A proof that a leaf hash is not in a `SortedSet`: the inclusion proofs
+/// of the adjacent leaves it would sit between. `left` is missing if the
+/// hash would come first, `right` if it would come last.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonMembershipProof {
+    /// The greatest leaf hash below the absent one and its proof.
+    pub left: Option<(Vec<u8>, Proof)>,
+    /// The smallest leaf hash above the absent one and its proof.
+    pub right: Option<(Vec<u8>, Proof)>,
+}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Builds `self`, which should be empty, over the set of `items`: one
+    /// leaf per distinct item, sorted by leaf hash.
+    /// Returns an `Error` value if the store could not be written.
+    pub fn from_set<I>(self, items: I) -> Result<SortedSet<S>, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let leaves = items.into_iter().map(|item| self.hash_block(item.as_ref())).collect::<Result<Vec<_>, _>>()?;
+        self.from_leaf_set(leaves)
+    }
+
+    /// Builds `self`, which should be empty, over the set of already
+    /// computed leaf hashes `leaves`, see `from_set`.
+    /// Returns an `Error` value if the store could not be written.
+    pub fn from_leaf_set<I: IntoIterator<Item = Vec<u8>>>(self, leaves: I) -> Result<SortedSet<S>, Error> {
+        let mut leaves: Vec<Vec<u8>> = leaves.into_iter().collect();
+        leaves.sort_unstable();
+        leaves.dedup();
+        let tree = self.from_leaf_hashes(leaves.iter().cloned())?;
+        Ok(SortedSet { tree, leaves })
+    }
+}
+
+impl<S: NodeStore> SortedSet<S> {
+    /// Returns the tree over the sorted leaves.
+    pub fn tree(&self) -> &HashTree<S> {
+        &self.tree
+    }
+
+    /// Returns the root hash, or `None` if the set is empty.
+    pub fn root_hash(&self) -> Option<RootHash> {
+        self.tree.root_hash()
+    }
+
+    /// Returns the sorted leaf hashes.
+    pub fn leaves(&self) -> &[Vec<u8>] {
+        &self.leaves
+    }
+
+    /// Returns the number of items in the set.
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Returns `true` if the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Returns `true` if `item` is in the set.
+    pub fn contains<D: AsRef<[u8]>>(&self, item: D) -> bool {
+        self.contains_hash(&self.leaf_hash(item))
+    }
+
+    /// Returns `true` if the leaf hash `hash` is in the set.
+    pub fn contains_hash(&self, hash: &[u8]) -> bool {
+        self.search(hash).is_ok()
+    }
+
+    /// Returns the leaf hash of `item`.
+    pub fn leaf_hash<D: AsRef<[u8]>>(&self, item: D) -> Vec<u8> {
+        self.tree.get_hasher().hash_leaf(item.as_ref())
+    }
+
+    /// Returns the inclusion proof for `item`, or `None` if it is not in
+    /// the set.
+    pub fn proof<D: AsRef<[u8]>>(&self, item: D) -> Option<Proof> {
+        self.tree.proof(self.search(&self.leaf_hash(item)).ok()?)
+    }
+
+    /// Returns the proof that `item` is not in the set, or `None` if it is
+    /// or the set is empty.
+    pub fn non_membership_proof<D: AsRef<[u8]>>(&self, item: D) -> Option<NonMembershipProof> {
+        self.non_membership_proof_for_hash(&self.leaf_hash(item))
+    }
+
+    /// Returns the proof that the leaf hash `hash` is not in the set, or
+    /// `None` if it is or the set is empty.
+    pub fn non_membership_proof_for_hash(&self, hash: &[u8]) -> Option<NonMembershipProof> {
+        let position = self.search(hash).err()?;
+        if self.is_empty() {
+            return None;
+        }
+        let neighbor = |index: usize| Some((self.leaves.get(index)?.clone(), self.tree.proof(index)?));
+        Some(NonMembershipProof { left: position.checked_sub(1).and_then(neighbor), right: neighbor(position) })
+    }
+
+    /// Adds `item` to the set, rehashing only the leaves from its position
+    /// on. Returns `false` if it was already in the set.
+    /// Returns an `Error` value if the store could not be written.
+    pub fn insert<D: AsRef<[u8]>>(&mut self, item: D) -> Result<bool, Error> {
+        let hash = self.tree.hash_block(item.as_ref())?;
+        let position = match self.search(&hash) {
+            Ok(_) => return Ok(false),
+            Err(position) => position,
+        };
+        self.leaves.insert(position, hash);
+        self.tree.replace_tail(position, self.leaves[position..].iter().cloned(), None, None)?;
+        Ok(true)
+    }
+
+    /// Removes `item` from the set, rehashing only the leaves from its
+    /// position on. Returns `false` if it was not in the set.
+    /// Returns an `Error` value if the store could not be written.
+    pub fn remove<D: AsRef<[u8]>>(&mut self, item: D) -> Result<bool, Error> {
+        let position = match self.search(&self.leaf_hash(item)) {
+            Ok(position) => position,
+            Err(_) => return Ok(false),
+        };
+        self.leaves.remove(position);
+        self.tree.replace_tail(position, self.leaves[position..].iter().cloned(), None, None)?;
+        Ok(true)
+    }
+
+    /// Finds `hash` among the sorted leaves.
+    fn search(&self, hash: &[u8]) -> Result<usize, usize> {
+        self.leaves.binary_search_by(|leaf| leaf.as_slice().cmp(hash))
+    }
+}
+
+impl NonMembershipProof {
+    /// Returns `true` if the leaf hash `hash` is not in the set of
+    /// `num_leaves` items with root `root`, assuming the default SHA-256
+    /// hasher. `num_leaves` must come from a trusted source along with the
+    /// root, as copies padding the last level would otherwise pass for
+    /// leaves past the end.
+    pub fn verify(&self, root: &[u8], num_leaves: usize, hash: &[u8]) -> bool {
+        self.verify_with(&Sha256Hasher::new(), root, num_leaves, hash)
+    }
+
+    /// Returns `true` if the leaf hash `hash` is not in the set of
+    /// `num_leaves` items with root `root` using `hasher`, see `verify`.
+    pub fn verify_with(&self, hasher: &dyn PairHasher, root: &[u8], num_leaves: usize, hash: &[u8]) -> bool {
+        // The index of a neighbor, if its proof holds
+        let index = |(leaf, proof): &(Vec<u8>, Proof)| {
+            if proof.verify_with(hasher, root, leaf) {
+                proven_index(proof)
+            } else {
+                None
+            }
+        };
+        match (&self.left, &self.right) {
+            (Some(left), Some(right)) => match (index(left), index(right)) {
+                (Some(i), Some(j)) => left.0.as_slice() < hash && hash < right.0.as_slice() && j == i + 1 && j < num_leaves,
+                _ => false,
+            },
+            (Some(left), None) => left.0.as_slice() < hash && index(left).is_some_and(|i| i + 1 == num_leaves),
+            (None, Some(right)) => hash < right.0.as_slice() && index(right) == Some(0) && num_leaves > 0,
+            (None, None) => false,
+        }
+    }
+}
+
+/// Returns the leaf index the positions of `proof` lead to, if it matches
+/// the proof's `leaf_index`.
+fn proven_index(proof: &Proof) -> Option<usize> {
+    let arity = proof.arity();
+    let mut index = 0usize;
+    for level in proof.levels.iter().rev() {
+        if level.position >= arity {
+            return None;
+        }
+        index = index.checked_mul(arity)?.checked_add(level.position)?;
+    }
+    (index as u64 == proof.leaf_index).then_some(index)
+}
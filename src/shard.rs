@@ -0,0 +1,297 @@
+use std::io::{Error, ErrorKind, Read};
+
+use crate::chunker::read_full;
+use crate::hasher::{block_buf, fingerprint, hash_group};
+use crate::store::NodeStore;
+use crate::HashTree;
+
+const MAGIC: &[u8; 4] = b"HTSH";
+const FORMAT_VERSION: u16 = 1;
+const HEADER_LEN: usize = 59;
+
+/// The leaves of an aligned segment of some data, hashed on its own so that
+/// the trees of very large objects can be built map-reduce style: workers,
+/// possibly on different machines, each hash a segment with
+/// `HashTree::hash_shard`, and a coordinator combines the shards into the
+/// tree with `HashTree::from_shards`.
+///
+/// Every shard but the last covers the same number of blocks, a power of the
+/// arity, so the subtree above its leaves is a subtree of the final tree.
+/// Such a complete shard carries that subtree and the coordinator only
+/// hashes the levels above the shard roots and the last, partial, shard.
+/// A shard records the parameters it was hashed with, and shards are
+/// exchanged with `to_bytes` and `from_bytes`.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, Shard};
+///
+/// let data: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+/// let segment = 8 * 16;
+///
+/// // On the workers, each with its own segment
+/// let shards: Vec<Vec<u8>> = data
+///     .chunks(segment)
+///     .enumerate()
+///     .map(|(index, chunk)| HashTree::new(16).hash_shard(8, index as u64, chunk).unwrap().to_bytes())
+///     .collect();
+///
+/// // On the coordinator
+/// let shards = shards.iter().map(|bytes| Shard::from_bytes(bytes).unwrap());
+/// let tree = HashTree::new(16).from_shards(shards).unwrap();
+/// assert_eq!(tree, HashTree::new(16).from_slice(&data).unwrap());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shard {
+    index: u64,
+    shard_blocks: u64,
+    block_size: u64,
+    arity: usize,
+    fingerprint: [u8; 8],
+    data_len: u64,
+    leaves: Vec<Vec<u8>>,
+    /// The levels above the leaves up to the shard root, if complete.
+    levels: Vec<Vec<Vec<u8>>>,
+}
+
+impl Shard {
+    /// Returns the index of the shard within the data.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// Returns the number of blocks of a complete shard.
+    pub fn shard_blocks(&self) -> u64 {
+        self.shard_blocks
+    }
+
+    /// Returns the number of blocks of the shard.
+    pub fn num_blocks(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Returns the number of bytes the shard covers.
+    pub fn data_len(&self) -> u64 {
+        self.data_len
+    }
+
+    /// Returns the offset of the shard within the data.
+    pub fn offset(&self) -> u64 {
+        self.index.saturating_mul(self.shard_blocks).saturating_mul(self.block_size)
+    }
+
+    /// Returns the leaf hashes of the shard.
+    pub fn leaf_hashes(&self) -> &[Vec<u8>] {
+        &self.leaves
+    }
+
+    /// Returns `true` if the shard covers all of its blocks in full, so that
+    /// it is not the last shard or the data ends at its end.
+    pub fn is_complete(&self) -> bool {
+        self.leaves.len() as u64 == self.shard_blocks && self.data_len == self.shard_blocks.saturating_mul(self.block_size)
+    }
+
+    /// Returns the root of the subtree of a complete shard, which is a node
+    /// of the final tree, or `None` if the shard is not complete.
+    pub fn root(&self) -> Option<&[u8]> {
+        if !self.is_complete() {
+            return None;
+        }
+        Some(self.levels.last().map_or(&self.leaves[0], |level| &level[0]))
+    }
+
+    /// Encodes the shard: a 59-byte header holding the magic `HTSH`, the
+    /// format version, the hasher fingerprint, the block size, arity, number
+    /// of blocks of a complete shard, index, data length, number of leaves
+    /// and hash length, followed by the leaf hashes and, for a complete
+    /// shard, the hashes of the levels above them.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let hash_len = self.leaves.first().map_or(0, Vec::len);
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+        bytes.extend_from_slice(&self.fingerprint);
+        bytes.extend_from_slice(&self.block_size.to_be_bytes());
+        bytes.extend_from_slice(&(self.arity as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.shard_blocks.to_be_bytes());
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        bytes.extend_from_slice(&self.data_len.to_be_bytes());
+        bytes.extend_from_slice(&(self.leaves.len() as u64).to_be_bytes());
+        bytes.push(hash_len as u8);
+        for hash in self.leaves.iter().chain(self.levels.iter().flatten()) {
+            bytes.extend_from_slice(hash);
+        }
+        bytes
+    }
+
+    /// Decodes a shard encoded by `to_bytes`.
+    /// Returns an `Error` value if `bytes` is not a valid encoding.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return Err(invalid("not a hash tree shard"));
+        }
+        let field = |start: usize, len: usize| bytes[start..start + len].iter().fold(0u64, |n, &b| n << 8 | b as u64);
+        if field(4, 2) != FORMAT_VERSION as u64 {
+            return Err(invalid("unsupported shard version"));
+        }
+        let mut fingerprint = [0; 8];
+        fingerprint.copy_from_slice(&bytes[6..14]);
+        let mut shard = Self {
+            index: field(34, 8),
+            shard_blocks: field(26, 8),
+            block_size: field(14, 8),
+            arity: field(22, 4) as usize,
+            fingerprint,
+            data_len: field(42, 8),
+            leaves: Vec::new(),
+            levels: Vec::new(),
+        };
+        let num_leaves = field(50, 8);
+        let hash_len = bytes[58] as usize;
+        let depth = shard_depth(shard.shard_blocks, shard.arity).ok_or_else(|| invalid("malformed shard"))?;
+        let full_len = num_leaves.saturating_sub(1).saturating_mul(shard.block_size);
+        let lengths_match = match num_leaves {
+            0 => shard.data_len == 0,
+            _ => shard.data_len > full_len && shard.data_len - full_len <= shard.block_size,
+        };
+        if num_leaves > shard.shard_blocks || !lengths_match || hash_len == 0 && num_leaves > 0 {
+            return Err(invalid("malformed shard"));
+        }
+        let mut hashes = bytes[HEADER_LEN..].chunks(hash_len.max(1));
+        let mut take = |count: u64| -> Result<Vec<Vec<u8>>, Error> {
+            (0..count)
+                .map(|_| hashes.next().filter(|hash| hash.len() == hash_len).map(<[u8]>::to_vec))
+                .collect::<Option<_>>()
+                .ok_or_else(|| invalid("shard is truncated"))
+        };
+        shard.leaves = take(num_leaves)?;
+        if shard.is_complete() {
+            let mut width = shard.shard_blocks;
+            for _ in 0..depth {
+                width /= shard.arity as u64;
+                shard.levels.push(take(width)?);
+            }
+        }
+        if hashes.next().is_some() {
+            return Err(invalid("shard has trailing data"));
+        }
+        Ok(shard)
+    }
+}
+
+/// Returns the number of levels above the leaves of a complete shard of
+/// `shard_blocks` blocks, or `None` if that is not a power of `arity`.
+fn shard_depth(shard_blocks: u64, arity: usize) -> Option<usize> {
+    if shard_blocks == 0 || arity < 2 {
+        return None;
+    }
+    let mut width = shard_blocks;
+    let mut depth = 0;
+    while width.is_multiple_of(arity as u64) {
+        width /= arity as u64;
+        depth += 1;
+    }
+    (width == 1).then_some(depth)
+}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Hashes shard `index` of some data, read from `data`: the segment of
+    /// up to `shard_blocks` blocks starting at byte `index * shard_blocks *
+    /// block_size`. Only the last shard of the data may be shorter. An
+    /// automatic block size, see `BlockSize::Auto`, is resolved for input of
+    /// unknown length, on the workers and the coordinator alike.
+    /// Returns an `Error` value if `shard_blocks` is not a power of the
+    /// arity, the block size is 0, or `data` could not be read.
+    pub fn hash_shard<R: Read>(mut self, shard_blocks: u64, index: u64, mut data: R) -> Result<Shard, Error> {
+        self.resolve_block_size(None);
+        let block_size = self.block_size();
+        let depth = shard_depth(shard_blocks, self.get_arity()).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, format!("shard of {} blocks is not a power of the arity", shard_blocks))
+        })?;
+        if block_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "block size must not be 0"));
+        }
+        let mut shard = Shard {
+            index,
+            shard_blocks,
+            block_size: block_size as u64,
+            arity: self.get_arity(),
+            fingerprint: fingerprint(self.get_hasher()),
+            data_len: 0,
+            leaves: Vec::new(),
+            levels: Vec::new(),
+        };
+        let mut buf = block_buf(block_size);
+        buf.resize(block_size, 0);
+        while (shard.leaves.len() as u64) < shard_blocks {
+            let len = read_full(&mut data, &mut buf)?;
+            if len == 0 {
+                break;
+            }
+            shard.leaves.push(self.hash_block(&buf[..len])?);
+            shard.data_len += len as u64;
+            if len < block_size {
+                break;
+            }
+        }
+        if shard.is_complete() {
+            for _ in 0..depth {
+                let below = shard.levels.last().unwrap_or(&shard.leaves);
+                let level = below
+                    .chunks(shard.arity)
+                    .map(|group| {
+                        let children: Vec<&[u8]> = group.iter().map(Vec::as_slice).collect();
+                        hash_group(self.get_hasher(), &children)
+                    })
+                    .collect();
+                shard.levels.push(level);
+            }
+        }
+        Ok(shard)
+    }
+
+    /// Constructs a new `HashTree` from the shards of some data, see `Shard`,
+    /// in any order. The subtrees of complete shards are taken as they are.
+    /// Returns an `Error` value if a shard is missing, a shard but the last
+    /// is not complete, the shards were hashed with other parameters than
+    /// `self`, or the store could not be written.
+    pub fn from_shards<I: IntoIterator<Item = Shard>>(mut self, shards: I) -> Result<Self, Error> {
+        self.resolve_block_size(None);
+        let block_size = self.block_size() as u64;
+        let hasher = fingerprint(self.get_hasher());
+        let mut shards: Vec<Shard> = shards.into_iter().collect();
+        shards.sort_by_key(|shard| shard.index);
+        let count = shards.len();
+        for (position, shard) in shards.iter().enumerate() {
+            let fail = |message: &str| Error::new(ErrorKind::InvalidInput, format!("shard {} {}", position, message));
+            if shard.index != position as u64 {
+                return Err(fail("is missing"));
+            }
+            if shard.fingerprint != hasher
+                || shard.block_size != block_size
+                || shard.arity != self.get_arity()
+                || shard.shard_blocks != shards[0].shard_blocks
+            {
+                return Err(fail("was hashed with different parameters"));
+            }
+            if position + 1 < count && !shard.is_complete() {
+                return Err(fail("is incomplete but not the last"));
+            }
+        }
+
+        // The parents above complete shards, level by level
+        let complete = shards.iter().take_while(|shard| shard.is_complete()).count();
+        let depth = shards.first().map_or(0, |shard| shard.levels.len());
+        let known: Vec<Vec<Vec<u8>>> = (0..depth)
+            .map(|level| shards[..complete].iter().flat_map(|shard| shard.levels[level].iter().cloned()).collect())
+            .collect();
+
+        let leaves = shards.into_iter().flat_map(|shard| {
+            let num_leaves = shard.leaves.len() as u64;
+            let last_len = shard.data_len - num_leaves.saturating_sub(1) * block_size;
+            shard.leaves.into_iter().zip(1..).map(move |(hash, n)| (hash, if n == num_leaves { last_len } else { block_size }))
+        });
+        self.with_known_parents(leaves, &known)
+    }
+}
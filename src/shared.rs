@@ -0,0 +1,114 @@
+use std::io::prelude::*;
+use std::io::Error;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::chunker::{Chunker, FixedChunker};
+use crate::hasher::block_buf;
+use crate::proof::Proof;
+use crate::root::RootHash;
+use crate::store::NodeStore;
+use crate::HashTree;
+
+/// A `HashTree` that many threads can query while one of them appends to it.
+///
+/// Clones share the same tree. Readers take a shared lock, so any number of
+/// them can generate proofs concurrently; `append` hashes its data without
+/// holding the lock and only locks exclusively to rebuild the tree.
+///
+/// # Consistency
+///
+/// Every call observes a completely built tree: either the one before an
+/// `append` or the one after it, never a tree in between. Separate calls may
+/// observe different versions, so a root and a proof obtained by two calls
+/// need not match. Use `proof_with_root`, or hold the guard returned by
+/// `read` for several queries, to get answers from a single version.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, SharedHashTree};
+/// use sha2::{Digest, Sha256};
+///
+/// let shared = SharedHashTree::new(HashTree::new(1).from_data(&mut &b"abc"[..]).unwrap());
+/// let reader = shared.clone();
+/// let handle = std::thread::spawn(move || reader.proof_with_root(1));
+/// shared.append(&mut &b"de"[..]).unwrap();
+///
+/// let (root, proof) = handle.join().unwrap().unwrap();
+/// assert!(proof.verify(root.as_bytes(), &Sha256::digest(b"b")));
+/// assert_eq!(shared.num_blocks(), 5);
+/// ```
+#[derive(Debug)]
+pub struct SharedHashTree<S = Vec<Vec<u8>>> {
+    tree: Arc<RwLock<HashTree<S>>>,
+}
+
+impl<S> Clone for SharedHashTree<S> {
+    fn clone(&self) -> Self {
+        Self { tree: self.tree.clone() }
+    }
+}
+
+impl<S: NodeStore> SharedHashTree<S> {
+    /// Wraps `tree` for sharing between threads.
+    pub fn new(tree: HashTree<S>) -> Self {
+        Self { tree: Arc::new(RwLock::new(tree)) }
+    }
+
+    /// Locks the tree for reading. Other readers may proceed, while `append`
+    /// waits until the guard is dropped.
+    pub fn read(&self) -> RwLockReadGuard<'_, HashTree<S>> {
+        self.tree.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashTree<S>> {
+        self.tree.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Returns the current root hash, or `None` if the tree is empty.
+    pub fn root_hash(&self) -> Option<RootHash> {
+        self.read().root_hash()
+    }
+
+    /// Returns the current number of blocks.
+    pub fn num_blocks(&self) -> u64 {
+        self.read().num_blocks()
+    }
+
+    /// Returns an inclusion proof for the block at `index` against the
+    /// current root, or `None` if there is no such block.
+    pub fn proof(&self, index: usize) -> Option<Proof> {
+        self.read().proof(index)
+    }
+
+    /// Returns the current root together with an inclusion proof for the
+    /// block at `index`, both taken from the same version of the tree.
+    pub fn proof_with_root(&self, index: usize) -> Option<(RootHash, Proof)> {
+        let tree = self.read();
+        Some((tree.root_hash()?, tree.proof(index)?))
+    }
+
+    /// Reads `data` in blocks of the tree's block size and appends them as
+    /// new blocks, starting a new block, then rebuilds the tree. Readers are
+    /// only blocked during the rebuild, not while `data` is read and hashed.
+    /// Returns an `Error` value if `data` could not be read or the store
+    /// could not be written; the tree is unchanged if reading fails.
+    pub fn append<R: Read>(&self, data: &mut R) -> Result<(), Error> {
+        let (hasher, block_size) = {
+            let tree = self.read();
+            (tree.shared_hasher(), tree.block_size())
+        };
+        let mut chunker = FixedChunker::new(data, block_size);
+        let mut buf = block_buf(block_size);
+        let mut hashes = Vec::new();
+        while chunker.next_chunk(&mut buf)? {
+            hashes.push(hasher.hash_leaf(&buf));
+        }
+
+        let mut tree = self.write();
+        for hash in hashes {
+            tree.queue_leaf_hash(hash);
+        }
+        tree.update()
+    }
+}
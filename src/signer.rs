@@ -0,0 +1,260 @@
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+use hmac::{Mac, SimpleHmac};
+use sha2::Sha256;
+
+use crate::rfc9162::{SignedTreeHead, TreeHead};
+use crate::store::NodeStore;
+use crate::HashTree;
+
+/// Prefix of the message signed for a `SignedRoot`, which keeps its
+/// signatures from being valid for any other kind of message.
+const DOMAIN: &[u8] = b"hashtree signed root v1\0";
+
+/// Produces signatures over tree heads, e.g. with a private key kept in an
+/// HSM or a signing service.
+pub trait Signer: fmt::Debug {
+    /// Signs `message`. Returns an `Error` value if signing failed, e.g.
+    /// because a remote signer is unreachable.
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// Checks signatures produced by a `Signer`.
+pub trait Verifier: fmt::Debug {
+    /// Returns `true` if `signature` is a valid signature over `message`.
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool;
+}
+
+/// A `Signer` and `Verifier` computing HMAC-SHA256 tags with a shared key.
+///
+/// Anyone who can verify can also sign, so this only suits attestations
+/// between parties that trust each other, or tests; use public key
+/// signatures otherwise.
+#[derive(Clone)]
+pub struct HmacSigner {
+    mac: SimpleHmac<Sha256>,
+}
+
+impl HmacSigner {
+    /// Constructs a new `HmacSigner` keyed with `key`, which may have any length.
+    pub fn new(key: &[u8]) -> Self {
+        let mac = <SimpleHmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+        Self { mac }
+    }
+}
+
+impl fmt::Debug for HmacSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("HmacSigner")
+    }
+}
+
+impl Signer for HmacSigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut mac = self.mac.clone();
+        mac.update(message);
+        Ok(mac.finalize().into_bytes().to_vec())
+    }
+}
+
+impl Verifier for HmacSigner {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let mut mac = self.mac.clone();
+        mac.update(message);
+        mac.verify_slice(signature).is_ok()
+    }
+}
+
+/// Signs with an Ed25519 private key, producing 64-byte signatures.
+/// Requires the `ed25519` feature.
+///
+/// # Examples
+///
+/// ```
+/// use ed25519_dalek::SigningKey;
+/// use hashtree::HashTree;
+///
+/// let key = SigningKey::from_bytes(&[7; 32]);
+/// let tree = HashTree::new(4).from_slice(b"release artifact").unwrap();
+/// let signed = tree.sign_root(&key, 1_700_000_000_000).unwrap();
+/// assert!(signed.verify_tree(&key.verifying_key(), &tree));
+/// ```
+#[cfg(feature = "ed25519")]
+impl Signer for ed25519_dalek::SigningKey {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(ed25519_dalek::Signer::sign(self, message).to_bytes().to_vec())
+    }
+}
+
+/// Checks Ed25519 signatures with a public key, rejecting weak keys and
+/// malleable signatures. Requires the `ed25519` feature.
+#[cfg(feature = "ed25519")]
+impl Verifier for ed25519_dalek::VerifyingKey {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        ed25519_dalek::Signature::from_slice(signature).is_ok_and(|signature| self.verify_strict(message, &signature).is_ok())
+    }
+}
+
+/// Signs with an ECDSA P-256 private key over SHA-256, with deterministic
+/// nonces (RFC 6979), producing 64-byte `r || s` signatures. Requires the
+/// `ecdsa` feature.
+///
+/// # Examples
+///
+/// ```
+/// use p256::ecdsa::SigningKey;
+/// use hashtree::HashTree;
+///
+/// let key = SigningKey::from_slice(&[7; 32]).unwrap();
+/// let tree = HashTree::new(4).from_slice(b"release artifact").unwrap();
+/// let signed = tree.sign_root(&key, 1_700_000_000_000).unwrap();
+/// assert!(signed.verify_tree(key.verifying_key(), &tree));
+/// ```
+#[cfg(feature = "ecdsa")]
+impl Signer for p256::ecdsa::SigningKey {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let signature: p256::ecdsa::Signature = p256::ecdsa::signature::Signer::try_sign(self, message)
+            .map_err(|error| Error::other(error.to_string()))?;
+        Ok(signature.to_bytes().to_vec())
+    }
+}
+
+/// Checks ECDSA P-256 signatures over SHA-256 in the `r || s` form. Requires
+/// the `ecdsa` feature.
+#[cfg(feature = "ecdsa")]
+impl Verifier for p256::ecdsa::VerifyingKey {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        p256::ecdsa::Signature::from_slice(signature)
+            .is_ok_and(|signature| p256::ecdsa::signature::Verifier::verify(self, message, &signature).is_ok())
+    }
+}
+
+/// A signed statement that a tree of `tree_size` leaves had root `root` at
+/// `timestamp`, in milliseconds since the epoch.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, HmacSigner, SignedRoot};
+///
+/// let signer = HmacSigner::new(b"attestation key");
+/// let tree = HashTree::new(4).from_slice(b"release artifact").unwrap();
+/// let signed = tree.sign_root(&signer, 1_700_000_000_000).unwrap();
+///
+/// let received = SignedRoot::from_bytes(&signed.to_bytes()).unwrap();
+/// assert!(received.verify_tree(&signer, &tree));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedRoot {
+    /// The signed root hash.
+    pub root: Vec<u8>,
+    /// Number of leaves of the tree.
+    pub tree_size: u64,
+    /// Time of signing, in milliseconds since the epoch.
+    pub timestamp: u64,
+    /// Signature over `message()`.
+    pub signature: Vec<u8>,
+}
+
+impl SignedRoot {
+    /// Signs `root` of a tree of `tree_size` leaves at `timestamp`.
+    /// Returns an `Error` value if the root is longer than 255 bytes or the
+    /// signer fails.
+    pub fn sign(signer: &dyn Signer, root: &[u8], tree_size: u64, timestamp: u64) -> Result<Self, Error> {
+        if root.len() > usize::from(u8::MAX) {
+            return Err(Error::new(ErrorKind::InvalidInput, "root hash is longer than 255 bytes"));
+        }
+        let mut signed = Self { root: root.to_vec(), tree_size, timestamp, signature: Vec::new() };
+        signed.signature = signer.sign(&signed.message())?;
+        Ok(signed)
+    }
+
+    /// Returns the message that is signed: a domain separator, the tree size
+    /// and timestamp as big-endian `u64`s, the length of the root as one byte
+    /// and then the root.
+    pub fn message(&self) -> Vec<u8> {
+        let mut message = DOMAIN.to_vec();
+        message.extend_from_slice(&self.tree_size.to_be_bytes());
+        message.extend_from_slice(&self.timestamp.to_be_bytes());
+        message.push(self.root.len() as u8);
+        message.extend_from_slice(&self.root);
+        message
+    }
+
+    /// Returns `true` if the signature is valid.
+    pub fn verify(&self, verifier: &dyn Verifier) -> bool {
+        self.root.len() <= usize::from(u8::MAX) && verifier.verify(&self.message(), &self.signature)
+    }
+
+    /// Returns `true` if the signature is valid and covers the root and
+    /// size of `tree`.
+    pub fn verify_tree<S: NodeStore>(&self, verifier: &dyn Verifier, tree: &HashTree<S>) -> bool {
+        tree.root_hash_bytes() == Some(&self.root[..])
+            && tree.num_blocks() == self.tree_size
+            && self.verify(verifier)
+    }
+
+    /// Encodes the signed root: `message()` without the domain separator,
+    /// followed by the signature.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.message().split_off(DOMAIN.len());
+        bytes.extend_from_slice(&self.signature);
+        bytes
+    }
+
+    /// Decodes a signed root encoded by `to_bytes`.
+    /// Returns an `Error` value if `bytes` is not a valid encoding.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let invalid = || Error::new(ErrorKind::InvalidData, "malformed signed root");
+        if bytes.len() < 17 {
+            return Err(invalid());
+        }
+        let (tree_size, timestamp) = bytes[..16].split_at(8);
+        let root_len = bytes[16] as usize;
+        let rest = &bytes[17..];
+        if rest.len() < root_len {
+            return Err(invalid());
+        }
+        let mut size = [0; 8];
+        size.copy_from_slice(tree_size);
+        let mut time = [0; 8];
+        time.copy_from_slice(timestamp);
+        Ok(Self {
+            root: rest[..root_len].to_vec(),
+            tree_size: u64::from_be_bytes(size),
+            timestamp: u64::from_be_bytes(time),
+            signature: rest[root_len..].to_vec(),
+        })
+    }
+}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Signs the root and number of leaves of the tree at `timestamp`, in
+    /// milliseconds since the epoch.
+    /// Returns an `Error` value if the tree is empty or the signer fails.
+    pub fn sign_root(&self, signer: &dyn Signer, timestamp: u64) -> Result<SignedRoot, Error> {
+        let root = self.root_hash_bytes().ok_or_else(|| Error::new(ErrorKind::InvalidInput, "tree is empty"))?;
+        SignedRoot::sign(signer, root, self.num_blocks(), timestamp)
+    }
+}
+
+impl TreeHead {
+    /// Signs the tree head for the log `log_id`, see `rfc9162`.
+    /// Returns an `Error` value if the tree head cannot be encoded or the
+    /// signer fails.
+    pub fn sign(self, log_id: &[u8], signer: &dyn Signer) -> Result<SignedTreeHead, Error> {
+        let signature = signer.sign(&self.to_bytes()?)?;
+        Ok(SignedTreeHead { log_id: log_id.to_vec(), tree_head: self, signature })
+    }
+}
+
+impl SignedTreeHead {
+    /// Returns `true` if the signature over the tree head is valid.
+    pub fn verify(&self, verifier: &dyn Verifier) -> bool {
+        match self.tree_head.to_bytes() {
+            Ok(message) => verifier.verify(&message, &self.signature),
+            Err(_) => false,
+        }
+    }
+}
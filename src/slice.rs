@@ -0,0 +1,254 @@
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+use std::ops::Range;
+
+use crate::io::to_usize;
+use crate::multiproof::{ProofElement, StreamingVerifier};
+use crate::proof::{read_varint, write_varint};
+use crate::store::NodeStore;
+use crate::HashTree;
+
+const TAG_NODE: u8 = 0;
+const TAG_LEAF: u8 = 1;
+
+impl<S: NodeStore> HashTree<S> {
+    /// Encodes the bytes of `data` in `range` together with the hashes
+    /// needed to verify them against the root, for `decode_slice`. `data`
+    /// must be the data the tree was built from; `range` is clipped to its
+    /// length. Blocks are located by their recorded lengths, if any, see
+    /// `record_lengths`.
+    ///
+    /// The encoding holds the length of the hashes as a varint, followed by
+    /// the elements of `multiproof_stream` for the blocks overlapping
+    /// `range`: a 0 byte, the level as a varint and the hash for every
+    /// interior node, a 1 byte, the index as a varint and the contents for
+    /// every block. Whole blocks are included, so the slice can be checked
+    /// against their leaf hashes.
+    /// Returns an `Error` value if `range` starts past the end of `data`,
+    /// `data` does not match the size of the tree or could not be read.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use hashtree::HashTree;
+    ///
+    /// let data = b"the quick brown fox jumps over the lazy dog";
+    /// let tree = HashTree::new(4).from_data(&data[..]).unwrap();
+    /// let encoded = tree.encode_slice(Cursor::new(&data[..]), 10..19).unwrap();
+    ///
+    /// let root = tree.root_hash().unwrap().into_bytes();
+    /// let receiver = HashTree::new(4);
+    /// let slice = receiver.decode_slice(&encoded, &root, data.len() as u64, 10..19).unwrap();
+    /// assert_eq!(slice, b"brown fox");
+    /// ```
+    pub fn encode_slice<R: Read + Seek>(&self, mut data: R, range: Range<u64>) -> Result<Vec<u8>, Error> {
+        let data_len = data.seek(SeekFrom::End(0))?;
+        if !self.covers_len(data_len) {
+            return Err(Error::new(ErrorKind::InvalidInput, "data does not match the size of the tree"));
+        }
+        let blocks = self.slice_blocks(data_len, &range)?;
+        let indices: Vec<usize> = blocks.clone().collect();
+        let elements = self.multiproof_stream(&indices).expect("block indices are in range");
+
+        let hash_len = self.root_hash_bytes().map_or(0, |root| root.len());
+        let mut encoded = Vec::new();
+        write_varint(&mut encoded, hash_len as u64);
+        for element in elements {
+            match element {
+                ProofElement::Node { level, hash } => {
+                    encoded.push(TAG_NODE);
+                    write_varint(&mut encoded, level as u64);
+                    encoded.extend_from_slice(&hash);
+                }
+                ProofElement::Leaf { index, .. } => {
+                    encoded.push(TAG_LEAF);
+                    write_varint(&mut encoded, index as u64);
+                    let extent = self.leaf_extent(data_len, index);
+                    data.seek(SeekFrom::Start(extent.start))?;
+                    let end = encoded.len();
+                    encoded.resize(end + (extent.end - extent.start) as usize, 0);
+                    data.read_exact(&mut encoded[end..])?;
+                }
+            }
+        }
+        Ok(encoded)
+    }
+
+    /// Verifies a slice encoded by `encode_slice` against `root` and returns
+    /// the bytes in `range`, clipped to `data_len`. `self` is only used for
+    /// its block size, arity and hasher, which must be those of the tree the
+    /// slice was encoded from; it may be empty. `data_len`, the length of the
+    /// whole data, must come from a trusted source along with the root. For
+    /// blocks of different sizes, `self` must also hold the recorded leaf
+    /// lengths, e.g. as read by `read_from` from a trusted copy of the tree.
+    /// Returns an `Error` value if the encoding is malformed, does not cover
+    /// `range` or does not match `root`.
+    pub fn decode_slice(&self, encoded: &[u8], root: &[u8], data_len: u64, range: Range<u64>) -> Result<Vec<u8>, Error> {
+        if self.leaf_ends().is_some() && !self.covers_len(data_len) {
+            return Err(Error::new(ErrorKind::InvalidInput, "data length does not match the recorded leaf lengths"));
+        }
+        let mut blocks = self.slice_blocks(data_len, &range)?;
+        let first = blocks.start;
+        let mut rest = encoded;
+        let hash_len = to_usize(read_varint(&mut rest)?)?;
+        let mut verifier = StreamingVerifier::new(self.get_hasher(), self.get_arity());
+        let mut data = Vec::new();
+        while let Some((&tag, tail)) = rest.split_first() {
+            rest = tail;
+            let element = match tag {
+                TAG_NODE => {
+                    let level = to_usize(read_varint(&mut rest)?)?;
+                    ProofElement::Node { level, hash: take(&mut rest, hash_len)?.to_vec() }
+                }
+                TAG_LEAF => {
+                    let index = to_usize(read_varint(&mut rest)?)?;
+                    if blocks.next() != Some(index) {
+                        return Err(invalid("slice holds an unexpected block"));
+                    }
+                    let extent = self.leaf_extent(data_len, index);
+                    let block = take(&mut rest, (extent.end - extent.start) as usize)?;
+                    data.extend_from_slice(block);
+                    ProofElement::Leaf { index, hash: self.get_hasher().hash_leaf(block) }
+                }
+                _ => return Err(invalid("invalid slice element")),
+            };
+            verifier.push(&element)?;
+        }
+        if blocks.next().is_some() {
+            return Err(invalid("slice is missing blocks"));
+        }
+        if !verifier.finish(root) {
+            return Err(invalid("slice does not match the root"));
+        }
+
+        let offset = self.leaf_extent(data_len, first).start;
+        let start = (range.start - offset) as usize;
+        let end = (range.end.min(data_len) - offset) as usize;
+        data.truncate(end);
+        data.drain(..start);
+        Ok(data)
+    }
+
+    /// Returns the proof for a ranged response holding the blocks that
+    /// overlap `range` of the data of `data_len` bytes the tree was built
+    /// from, for `verify_range_response`: the elements of
+    /// `multiproof_stream` for those blocks.
+    /// Returns an `Error` value if `range` starts past the end of the data or
+    /// `data_len` does not match the size of the tree.
+    pub fn range_proof(&self, data_len: u64, range: Range<u64>) -> Result<Vec<ProofElement>, Error> {
+        if !self.covers_len(data_len) {
+            return Err(Error::new(ErrorKind::InvalidInput, "data length does not match the size of the tree"));
+        }
+        let indices: Vec<usize> = self.slice_blocks(data_len, &range)?.collect();
+        Ok(self.multiproof_stream(&indices).expect("block indices are in range"))
+    }
+
+    /// Returns `range` widened to whole blocks and clipped to `data_len`:
+    /// the byte range to request from a mirror so that the response can be
+    /// verified with `verify_range_response`.
+    /// Returns an `Error` value if `range` is empty or starts past the end of
+    /// the data.
+    pub fn block_range(&self, data_len: u64, range: Range<u64>) -> Result<Range<u64>, Error> {
+        let blocks = self.slice_blocks(data_len, &range)?;
+        Ok(self.leaf_extent(data_len, blocks.start).start..self.leaf_extent(data_len, blocks.end - 1).end)
+    }
+
+    /// Verifies the response to a ranged request, e.g. an HTTP `GET` with
+    /// a `Range` header sent to an untrusted mirror: `bytes` found at
+    /// `offset` of data of `data_len` bytes, checked with a `proof` from
+    /// `range_proof` against `root`. The leaf hashes in the proof are
+    /// ignored; the blocks are hashed from `bytes` instead. Like for
+    /// `decode_slice`, `self` is only used for its block size, arity,
+    /// hasher and recorded leaf lengths, and `root` and `data_len` must come
+    /// from a trusted source.
+    /// Returns the indices of the verified blocks, or an `Error` value if
+    /// `bytes` does not span whole blocks, see `block_range`, or does not
+    /// match the proof and root.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let data: Vec<u8> = (0..100u8).collect();
+    /// let tree = HashTree::new(16).from_slice(&data).unwrap();
+    /// let root = tree.root_hash().unwrap().into_bytes();
+    ///
+    /// let range = tree.block_range(100, 20..40).unwrap();
+    /// assert_eq!(range, 16..48);
+    /// let proof = tree.range_proof(100, range.clone()).unwrap();
+    /// let response = &data[16..48];
+    ///
+    /// let client = HashTree::new(16);
+    /// let blocks = client.verify_range_response(&root, 100, 16, response, &proof).unwrap();
+    /// assert_eq!(blocks, 1..3);
+    /// assert!(client.verify_range_response(&root, 100, 16, &data[17..49], &proof).is_err());
+    /// ```
+    pub fn verify_range_response(
+        &self,
+        root: &[u8],
+        data_len: u64,
+        offset: u64,
+        bytes: &[u8],
+        proof: &[ProofElement],
+    ) -> Result<Range<usize>, Error> {
+        if self.leaf_ends().is_some() && !self.covers_len(data_len) {
+            return Err(Error::new(ErrorKind::InvalidInput, "data length does not match the recorded leaf lengths"));
+        }
+        let end = offset.saturating_add(bytes.len() as u64);
+        if self.block_range(data_len, offset..end)? != (offset..end) {
+            return Err(Error::new(ErrorKind::InvalidInput, "response does not span whole blocks"));
+        }
+        let blocks = self.slice_blocks(data_len, &(offset..end))?;
+        let mut expected = blocks.clone();
+        let mut verifier = StreamingVerifier::new(self.get_hasher(), self.get_arity());
+        for element in proof {
+            let element = match element {
+                ProofElement::Leaf { index, .. } => {
+                    if expected.next() != Some(*index) {
+                        return Err(invalid("proof holds an unexpected block"));
+                    }
+                    let extent = self.leaf_extent(data_len, *index);
+                    let block = &bytes[(extent.start - offset) as usize..(extent.end - offset) as usize];
+                    &ProofElement::Leaf { index: *index, hash: self.get_hasher().hash_leaf(block) }
+                }
+                node => node,
+            };
+            verifier.push(element)?;
+        }
+        if expected.next().is_some() {
+            return Err(invalid("proof is missing blocks"));
+        }
+        if !verifier.finish(root) {
+            return Err(invalid("response does not match the root"));
+        }
+        Ok(blocks)
+    }
+
+    /// Returns the indices of the blocks overlapping `range` in data of
+    /// `data_len` bytes.
+    fn slice_blocks(&self, data_len: u64, range: &Range<u64>) -> Result<Range<usize>, Error> {
+        if self.leaf_ends().is_none() && self.block_size() == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "block size must not be 0"));
+        }
+        if range.start >= range.end || range.start >= data_len {
+            return Err(Error::new(ErrorKind::InvalidInput, "range is empty or starts past the end of the data"));
+        }
+        let end = range.end.min(data_len);
+        Ok(self.leaf_index_at(range.start)..self.leaf_index_at(end - 1) + 1)
+    }
+}
+
+/// Splits `len` bytes off the front of `bytes`.
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if bytes.len() < len {
+        return Err(invalid("slice is truncated"));
+    }
+    let (head, tail) = bytes.split_at(len);
+    *bytes = tail;
+    Ok(head)
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use sha2::{Digest, Sha256};
+
+use crate::root::constant_time_eq;
+
+/// The depth of a `SparseMerkleTree`, one level per key bit.
+pub const DEPTH: usize = 256;
+
+/// A 256-bit key in a `SparseMerkleTree`.
+pub type Key = [u8; 32];
+
+/// A Sparse Merkle Tree over a fixed 256-bit keyspace.
+///
+/// Every possible key has a leaf; leaves that were never set hold the empty
+/// hash (all zeroes). Subtrees containing only empty leaves are never stored,
+/// their hashes are taken from a precomputed table of default hashes, so
+/// memory use is proportional to the number of set keys and every insert or
+/// removal costs `DEPTH` hash computations.
+///
+/// Key bits are consumed from the least significant bit of the last byte
+/// (next to the leaves) up to the most significant bit of the first byte
+/// (next to the root).
+#[derive(Debug, Clone)]
+pub struct SparseMerkleTree {
+    nodes: HashMap<(u16, Key), [u8; 32]>,
+    len: usize,
+}
+
+impl SparseMerkleTree {
+    /// Constructs a new empty `SparseMerkleTree`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::SparseMerkleTree;
+    ///
+    /// let mut tree = SparseMerkleTree::new();
+    /// let empty_root = tree.root();
+    /// tree.insert([1u8; 32], b"value");
+    /// assert_ne!(tree.root(), empty_root);
+    /// tree.remove(&[1u8; 32]);
+    /// assert_eq!(tree.root(), empty_root);
+    /// ```
+    pub fn new() -> Self {
+        Self {
+            nodes: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    /// Sets the leaf at `key` to the hash of `value`.
+    /// Returns the previous leaf hash, if the key was set.
+    pub fn insert(&mut self, key: Key, value: &[u8]) -> Option<[u8; 32]> {
+        self.insert_leaf(key, Sha256::digest(value).into())
+    }
+
+    /// Sets the leaf at `key` to the given, already computed leaf hash.
+    /// Returns the previous leaf hash, if the key was set.
+    pub fn insert_leaf(&mut self, key: Key, leaf: [u8; 32]) -> Option<[u8; 32]> {
+        let previous = self.get(&key);
+        if leaf == default_hashes()[0] {
+            self.remove(&key);
+        } else {
+            if previous.is_none() {
+                self.len += 1;
+            }
+            self.update(key, leaf);
+        }
+        previous
+    }
+
+    /// Resets the leaf at `key` to the empty hash.
+    /// Returns the previous leaf hash, if the key was set.
+    pub fn remove(&mut self, key: &Key) -> Option<[u8; 32]> {
+        let previous = self.get(key)?;
+        self.len -= 1;
+        self.update(*key, default_hashes()[0]);
+        Some(previous)
+    }
+
+    /// Returns the leaf hash at `key`, or `None` if the key is not set.
+    pub fn get(&self, key: &Key) -> Option<[u8; 32]> {
+        self.nodes.get(&(0, *key)).copied()
+    }
+
+    /// Returns `true` if the leaf at `key` is set.
+    pub fn contains_key(&self, key: &Key) -> bool {
+        self.nodes.contains_key(&(0, *key))
+    }
+
+    /// Returns the root hash.
+    pub fn root(&self) -> [u8; 32] {
+        self.node(DEPTH, &[0u8; 32])
+    }
+
+    /// Returns the root hash as a hex `String`.
+    pub fn root_hash(&self) -> String {
+        hex::encode(self.root())
+    }
+
+    /// Returns the number of set keys.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no key is set.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the hash of the node at `height` (0 for leaves) covering `path`.
+    pub(crate) fn node(&self, height: usize, path: &Key) -> [u8; 32] {
+        match self.nodes.get(&(height as u16, *path)) {
+            Some(hash) => *hash,
+            None => default_hashes()[height],
+        }
+    }
+
+    /// Returns a proof for the leaf at `key`, whether or not it is set.
+    pub fn proof(&self, key: &Key) -> SparseProof {
+        let mut bitmap = [0u8; 32];
+        let mut siblings = Vec::new();
+        let mut path = *key;
+
+        for height in 0..DEPTH {
+            let sibling = self.node(height, &flip_bit(&path, height));
+            if sibling != default_hashes()[height] {
+                bitmap[height / 8] |= 1 << (height % 8);
+                siblings.push(sibling);
+            }
+            path = clear_bit(&path, height);
+        }
+        SparseProof { bitmap, siblings }
+    }
+
+    /// Returns a proof that `key` is not set, or `None` if it is.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::SparseMerkleTree;
+    ///
+    /// let mut tree = SparseMerkleTree::new();
+    /// tree.insert([1u8; 32], b"listed");
+    ///
+    /// let proof = tree.absence_proof(&[2u8; 32]).unwrap();
+    /// assert!(proof.verify_absence(&tree.root(), &[2u8; 32]));
+    /// assert!(tree.absence_proof(&[1u8; 32]).is_none());
+    /// ```
+    pub fn absence_proof(&self, key: &Key) -> Option<SparseProof> {
+        if self.contains_key(key) {
+            return None;
+        }
+        Some(self.proof(key))
+    }
+
+    /// Writes `leaf` at `key` and recomputes the path up to the root.
+    fn update(&mut self, key: Key, leaf: [u8; 32]) {
+        let mut path = key;
+        let mut hash = leaf;
+        self.store(0, path, hash);
+
+        for height in 0..DEPTH {
+            let sibling = self.node(height, &flip_bit(&path, height));
+            hash = if bit(&path, height) {
+                hash_pair(&sibling, &hash)
+            } else {
+                hash_pair(&hash, &sibling)
+            };
+            path = clear_bit(&path, height);
+            self.store(height + 1, path, hash);
+        }
+    }
+
+    /// Stores a node, dropping it instead if it equals the default for its height.
+    fn store(&mut self, height: usize, path: Key, hash: [u8; 32]) {
+        if hash == default_hashes()[height] {
+            self.nodes.remove(&(height as u16, path));
+        } else {
+            self.nodes.insert((height as u16, path), hash);
+        }
+    }
+}
+
+/// A proof for a single leaf of a `SparseMerkleTree`.
+///
+/// Only siblings that differ from the default hash of their height are
+/// stored; a bitmap records which heights they belong to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseProof {
+    bitmap: [u8; 32],
+    siblings: Vec<[u8; 32]>,
+}
+
+impl SparseProof {
+    /// Returns the root implied by this proof if the leaf at `key` had the hash `leaf`.
+    /// Returns `None` if the proof is malformed.
+    pub fn compute_root(&self, key: &Key, leaf: [u8; 32]) -> Option<[u8; 32]> {
+        let mut siblings = self.siblings.iter();
+        let mut hash = leaf;
+
+        for height in 0..DEPTH {
+            let sibling = if (self.bitmap[height / 8] >> (height % 8)) & 1 == 1 {
+                *siblings.next()?
+            } else {
+                default_hashes()[height]
+            };
+            hash = if bit(key, height) {
+                hash_pair(&sibling, &hash)
+            } else {
+                hash_pair(&hash, &sibling)
+            };
+        }
+
+        if siblings.next().is_some() {
+            return None;
+        }
+        Some(hash)
+    }
+
+    /// Returns `true` if the leaf at `key` has the hash `leaf` under `root`.
+    pub fn verify(&self, root: &[u8; 32], key: &Key, leaf: [u8; 32]) -> bool {
+        self.compute_root(key, leaf).is_some_and(|computed| constant_time_eq(&computed, root))
+    }
+
+    /// Returns `true` if the leaf at `key` is empty under `root`, i.e. the key
+    /// is not in the tree.
+    pub fn verify_absence(&self, root: &[u8; 32], key: &Key) -> bool {
+        self.verify(root, key, default_hashes()[0])
+    }
+
+    /// Returns the non-default sibling hashes, ordered from the leaf up.
+    pub fn siblings(&self) -> &[[u8; 32]] {
+        &self.siblings
+    }
+}
+
+impl Default for SparseMerkleTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the default hashes of empty subtrees for every height from 0 to `DEPTH`.
+fn default_hashes() -> &'static [[u8; 32]] {
+    static DEFAULTS: OnceLock<Vec<[u8; 32]>> = OnceLock::new();
+    DEFAULTS.get_or_init(|| {
+        let mut defaults = vec![[0u8; 32]];
+        for height in 0..DEPTH {
+            let below = defaults[height];
+            defaults.push(hash_pair(&below, &below));
+        }
+        defaults
+    })
+}
+
+pub(crate) fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Returns the key bit deciding the direction at `height`; `true` means right.
+pub(crate) fn bit(key: &Key, height: usize) -> bool {
+    (key[31 - height / 8] >> (height % 8)) & 1 == 1
+}
+
+pub(crate) fn flip_bit(key: &Key, height: usize) -> Key {
+    let mut key = *key;
+    key[31 - height / 8] ^= 1 << (height % 8);
+    key
+}
+
+pub(crate) fn clear_bit(key: &Key, height: usize) -> Key {
+    let mut key = *key;
+    key[31 - height / 8] &= !(1 << (height % 8));
+    key
+}
@@ -0,0 +1,250 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+
+use crate::io::{Error, ErrorKind};
+
+/// Storage for the node hashes of a `HashTree`.
+///
+/// Nodes are addressed by their index in the tree's storage order: the
+/// leaves first, then each level above them, with the root last. The tree
+/// only ever appends nodes, overwrites existing ones or truncates the store.
+pub trait NodeStore: fmt::Debug {
+    /// Returns the hash of the node at `index`, or `None` if there is no such node.
+    fn get(&self, index: usize) -> Option<Vec<u8>>;
+
+    /// Stores `hash` at `index`, which must be at most `len()`. Storing at
+    /// `len()` appends a node.
+    fn put(&mut self, index: usize, hash: Vec<u8>) -> Result<(), Error>;
+
+    /// Returns the number of stored nodes.
+    fn len(&self) -> usize;
+
+    /// Returns `true` if the store holds no nodes.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops all nodes from `len` on.
+    fn truncate(&mut self, len: usize) -> Result<(), Error>;
+
+    /// Persists buffered writes. The tree calls this once it is done building.
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Releases storage held beyond the stored nodes, such as spare capacity
+    /// left by appending. Does nothing by default.
+    fn shrink_to_fit(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Returns an iterator over the hashes of the nodes in `range`.
+    fn iter_range(&self, range: core::ops::Range<usize>) -> Box<dyn Iterator<Item = Vec<u8>> + '_> {
+        Box::new(range.map_while(move |index| self.get(index)))
+    }
+
+    /// Returns an iterator over the hashes of all nodes, in storage order.
+    fn iter_nodes(&self) -> Box<dyn Iterator<Item = Vec<u8>> + '_> {
+        self.iter_range(0..self.len())
+    }
+}
+
+impl NodeStore for Vec<Vec<u8>> {
+    fn get(&self, index: usize) -> Option<Vec<u8>> {
+        <[Vec<u8>]>::get(self, index).cloned()
+    }
+
+    fn put(&mut self, index: usize, hash: Vec<u8>) -> Result<(), Error> {
+        match index.cmp(&Vec::len(self)) {
+            core::cmp::Ordering::Less => self[index] = hash,
+            core::cmp::Ordering::Equal => self.push(hash),
+            core::cmp::Ordering::Greater => {
+                return Err(Error::new(ErrorKind::InvalidInput, "node index out of bounds"));
+            }
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<(), Error> {
+        Vec::truncate(self, len);
+        Ok(())
+    }
+
+    fn shrink_to_fit(&mut self) -> Result<(), Error> {
+        self.iter_mut().for_each(Vec::shrink_to_fit);
+        Vec::shrink_to_fit(self);
+        Ok(())
+    }
+
+    fn iter_range(&self, range: core::ops::Range<usize>) -> Box<dyn Iterator<Item = Vec<u8>> + '_> {
+        let end = range.end.min(Vec::len(self));
+        Box::new(self[range.start.min(end)..end].iter().cloned())
+    }
+}
+
+/// A `NodeStore` wrapper keeping the most recently used nodes of another
+/// store in memory.
+///
+/// Nodes are loaded from the wrapped store on first access and evicted in
+/// least-recently-used order once more than `capacity` are cached, so
+/// repeated queries against a huge on-disk tree, such as proofs for nearby
+/// blocks, mostly hit memory. Writes go through to the wrapped store.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{CachedStore, HashTree};
+///
+/// let store = CachedStore::new(Vec::new(), 64);
+/// let tree = HashTree::with_store(1, store).from_data(&mut &b"abcdefgh"[..]).unwrap();
+/// tree.proof(3).unwrap();
+/// tree.proof(3).unwrap();
+/// assert!(tree.store().hits() >= 3);
+/// ```
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct CachedStore<S> {
+    inner: S,
+    cache: Mutex<Lru>,
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug)]
+struct Lru {
+    capacity: usize,
+    entries: HashMap<usize, (Vec<u8>, u64)>,
+    order: BTreeMap<u64, usize>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+#[cfg(feature = "std")]
+impl Lru {
+    fn get(&mut self, index: usize) -> Option<Vec<u8>> {
+        let tick = self.tick;
+        let (hash, used) = self.entries.get_mut(&index)?;
+        self.order.remove(used);
+        *used = tick;
+        self.order.insert(tick, index);
+        self.tick += 1;
+        Some(hash.clone())
+    }
+
+    fn insert(&mut self, index: usize, hash: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some((_, used)) = self.entries.insert(index, (hash, self.tick)) {
+            self.order.remove(&used);
+        }
+        self.order.insert(self.tick, index);
+        self.tick += 1;
+        while self.entries.len() > self.capacity {
+            let (_, evicted) = self.order.pop_first().unwrap();
+            self.entries.remove(&evicted);
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: NodeStore> CachedStore<S> {
+    /// Wraps `inner`, caching up to `capacity` nodes.
+    pub fn new(inner: S, capacity: usize) -> Self {
+        let cache = Lru {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        };
+        Self { inner, cache: Mutex::new(cache) }
+    }
+
+    /// Returns the wrapped store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes the cache and returns the wrapped store.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Returns the number of nodes currently cached.
+    pub fn cached(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Returns the number of reads served from the cache.
+    pub fn hits(&self) -> u64 {
+        self.lock().hits
+    }
+
+    /// Returns the number of reads that had to load from the wrapped store.
+    pub fn misses(&self) -> u64 {
+        self.lock().misses
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Lru> {
+        self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: NodeStore> NodeStore for CachedStore<S> {
+    fn get(&self, index: usize) -> Option<Vec<u8>> {
+        let mut cache = self.lock();
+        if let Some(hash) = cache.get(index) {
+            cache.hits += 1;
+            return Some(hash);
+        }
+        cache.misses += 1;
+        let hash = self.inner.get(index)?;
+        cache.insert(index, hash.clone());
+        Some(hash)
+    }
+
+    fn put(&mut self, index: usize, hash: Vec<u8>) -> Result<(), Error> {
+        self.inner.put(index, hash.clone())?;
+        let cache = self.cache.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if cache.entries.contains_key(&index) {
+            cache.insert(index, hash);
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<(), Error> {
+        self.inner.truncate(len)?;
+        let cache = self.cache.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dropped: Vec<usize> = cache.entries.keys().copied().filter(|&index| index >= len).collect();
+        for index in dropped {
+            let (_, used) = cache.entries.remove(&index).unwrap();
+            cache.order.remove(&used);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+
+    fn shrink_to_fit(&mut self) -> Result<(), Error> {
+        self.cache.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()).entries.shrink_to_fit();
+        self.inner.shrink_to_fit()
+    }
+}
@@ -0,0 +1,97 @@
+use std::io::prelude::*;
+use std::io::Error;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A limit on the rate at which data is read, so hashing large datasets in
+/// the background, e.g. by a backup agent, does not starve foreground
+/// workloads of disk bandwidth.
+///
+/// Clones share the same budget: a `Throttle` passed to every tree built by
+/// a job limits the job as a whole, however many files it reads at once.
+/// Readers are slowed down by sleeping after each read until the bytes read
+/// so far fit the rate, so the limit holds on average over every read, not
+/// just over whole files.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Instant;
+/// use hashtree::{HashTree, Throttle};
+///
+/// let data = vec![0u8; 64 * 1024];
+/// let path = std::env::temp_dir().join(format!("throttle-doc-{}", std::process::id()));
+/// std::fs::write(&path, &data).unwrap();
+///
+/// let start = Instant::now();
+/// let throttle = Throttle::new(1024 * 1024);
+/// let tree = HashTree::new(4096).throttle(throttle).from_file(&path).unwrap();
+/// assert!(start.elapsed().as_millis() >= 50);
+/// assert_eq!(tree, HashTree::new(4096).from_slice(&data).unwrap());
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    bytes_per_second: u64,
+    /// The time at which the bytes read so far will have fit the rate.
+    next: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Throttle {
+    /// Constructs a `Throttle` limiting reads to `bytes_per_second`; values
+    /// below 1 are raised to 1.
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self { bytes_per_second: bytes_per_second.max(1), next: Arc::new(Mutex::new(None)) }
+    }
+
+    /// Returns the limit in bytes per second.
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    /// Wraps `reader` so that reads from it count against this throttle.
+    pub fn reader<R: Read>(&self, reader: R) -> ThrottledReader<R> {
+        ThrottledReader { inner: reader, throttle: self.clone() }
+    }
+
+    /// Accounts for `bytes` read and sleeps until they fit the rate.
+    pub fn consume(&self, bytes: u64) {
+        if bytes == 0 {
+            return;
+        }
+        let cost = Duration::from_secs_f64(bytes as f64 / self.bytes_per_second as f64);
+        let now = Instant::now();
+        // The lock is only held to reserve the slot, so readers sharing the
+        // throttle sleep concurrently
+        let until = {
+            let mut next = self.next.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let until = next.map_or(now, |next| next.max(now)) + cost;
+            *next = Some(until);
+            until
+        };
+        thread::sleep(until.saturating_duration_since(now));
+    }
+}
+
+/// A reader whose reads are limited by a `Throttle`, see `Throttle::reader`.
+#[derive(Debug)]
+pub struct ThrottledReader<R> {
+    inner: R,
+    throttle: Throttle,
+}
+
+impl<R> ThrottledReader<R> {
+    /// Returns the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let read = self.inner.read(buf)?;
+        self.throttle.consume(read as u64);
+        Ok(read)
+    }
+}
@@ -1,31 +1,205 @@
 #![allow(dead_code)]
-use std::collections::VecDeque;
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
 use std::io::prelude::*;
-use sha2::{Digest, Sha256};
+#[cfg(feature = "std")]
+use std::io::SeekFrom;
+#[cfg(all(feature = "std", not(feature = "zeroize")))]
+use std::io::BufReader;
+#[cfg(feature = "std")]
+use std::path::Path;
+#[cfg(all(feature = "std", not(all(target_family = "wasm", not(target_feature = "atomics")))))]
+use std::sync::mpsc;
+#[cfg(all(feature = "std", not(all(target_family = "wasm", not(target_feature = "atomics")))))]
+use std::{mem, thread};
+#[cfg(feature = "std")]
+use std::time::SystemTime;
 
-/// A node from the `HashTree`.
-#[derive(Debug, Clone)]
-struct Node {
-    pub hash: Vec<u8>,
-    pub index: usize,
-    pub left: Option<usize>,
-    pub right: Option<usize>,
-}
+#[cfg(feature = "std")]
+use crate::blocks::BlockStore;
+#[cfg(feature = "std")]
+use crate::chunker::{Chunker, FixedChunker};
+use crate::hasher::fingerprint;
+#[cfg(all(feature = "direct", target_os = "linux"))]
+use crate::direct::{open_direct, DirectReader};
+#[cfg(all(feature = "sparse", unix))]
+use crate::holes::data_region;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+use crate::uring::UringReader;
+#[cfg(feature = "std")]
+use crate::hasher::{block_buf, BlockBuf};
+#[cfg(feature = "blake3")]
+use crate::hasher::Blake3Hasher;
+use crate::block_size::BlockSize;
+use crate::estimate::{count_nodes, memory_bytes};
+use crate::hasher::{
+    hash_group, DoubleSha256Hasher, LengthPrefixedHasher, PairHasher, PrefixedHasher, PreprocessingHasher, SaltedHasher,
+    Sha256Hasher,
+};
+use crate::index::{LeafIndex, NodeIndex};
+use crate::io::{to_usize, Error, ErrorKind};
+use crate::metrics::{Metrics, Phase, Timer};
+use crate::observer::{BuildEvent, BuildObserver, RootListeners};
+use crate::proof::{Proof, ProofLevel};
+use crate::root::{constant_time_eq, RootHash};
+#[cfg(feature = "std")]
+use crate::oplog::TreeOp;
+#[cfg(feature = "std")]
+use crate::throttle::Throttle;
+use crate::store::NodeStore;
+use sha2::Sha256;
+
+pub(crate) const MAGIC: &[u8; 4] = b"HTRE";
+pub(crate) const FORMAT_VERSION: u16 = 1;
+pub(crate) const HEADER_LEN: usize = 48;
+pub(crate) const PADDING_DUPLICATE: u8 = 0;
+pub(crate) const PADDING_ZERO: u8 = 1;
+pub(crate) const PADDING_PROMOTE: u8 = 2;
+pub(crate) const PADDING_DUPLICATE_UNLESS_SINGLE: u8 = 3;
+pub(crate) const FLAG_LEAF_LENGTHS: u8 = 1;
+pub(crate) const FLAG_LEVEL_ORDER: u8 = 2;
 
 // The structure of the HashTree is as follows:
-// The `nodes` VecDeque contains the nodes in this order:
+// The `nodes` store contains the node hashes in this order:
 // * The first `num_blocks` indices represent the actual nodes
 // of the blocks of the file that was hashed.
 // * The subsequent blocks until the last index contain the
 // node parents of the blocks
 // * The last index holds the root of the tree.
+// Every level except the root's is padded with copies of its last node
+// until its length is a multiple of the tree's arity.
 
 /// A Merkle-tree.
+///
+/// The node hashes are kept in a `NodeStore`, by default in memory.
+///
+/// With the `zeroize` feature, the buffers holding block contents while they
+/// are hashed are wiped once no longer needed, also when reading fails.
+/// Buffers of the reader passed in, such as those of a `BufReader`, are not.
 #[derive(Debug, Clone)]
-pub struct HashTree {
-    nodes: VecDeque<Node>,
+pub struct HashTree<S = Vec<Vec<u8>>> {
+    nodes: S,
     num_blocks: usize,
     block_size: usize,
+    auto_block_size: bool,
+    arity: usize,
+    hasher: Arc<dyn PairHasher>,
+    metrics: Option<Arc<dyn Metrics>>,
+    observer: Option<Arc<dyn BuildObserver>>,
+    root_listeners: RootListeners,
+    dedup: Option<BTreeMap<Vec<u8>, Vec<u64>>>,
+    labels: BTreeMap<usize, String>,
+    #[cfg(feature = "std")]
+    blocks: Option<Arc<dyn BlockStore>>,
+    pending: Vec<Vec<u8>>,
+    data_len: Option<u64>,
+    leaf_ends: Option<Vec<u64>>,
+    pending_lens: Option<Vec<u64>>,
+    max_leaves: Option<usize>,
+    max_memory: Option<u64>,
+    padding: Padding,
+    zero_hashes: Vec<Vec<u8>>,
+    #[cfg(all(feature = "direct", target_os = "linux"))]
+    direct_io: bool,
+    #[cfg(feature = "std")]
+    throttle: Option<Throttle>,
+    #[cfg(feature = "std")]
+    double_buffered: bool,
+    #[cfg(feature = "parallel")]
+    thread_pool: Option<Arc<rayon::ThreadPool>>,
+    version: u64,
+    #[cfg(feature = "std")]
+    history: Option<Vec<RootSnapshot>>,
+    #[cfg(feature = "std")]
+    ops: Option<Vec<TreeOp>>,
+    root: Option<Vec<u8>>,
+}
+
+/// How a `HashTree` fills up levels whose length is not a multiple of the
+/// arity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Padding {
+    /// Levels are padded with copies of their last node.
+    #[default]
+    Duplicate,
+    /// The leaves are virtually extended to the next power of the arity with
+    /// zero hashes, as in BitTorrent v2 (BEP 52) and SSZ: padding leaves are
+    /// all-zero hashes, and padding nodes above are the hash of `arity`
+    /// copies of the zero hash of the level below. A single leaf is its own
+    /// root.
+    Zero,
+    /// A node missing its last children takes the hash of its real children
+    /// alone, and a node with a single real child takes that child's hash
+    /// unchanged, so the last node of a level is promoted as is. This gives
+    /// the trees of RFC 6962 and THEX, which split at the largest power of
+    /// two. Padding nodes are stored as empty placeholder hashes; a single
+    /// leaf is its own root.
+    Promote,
+    /// Levels are padded with copies of their last node like `Duplicate`,
+    /// but a single leaf is its own root rather than being hashed with a
+    /// copy of itself, as in the transaction trees of Bitcoin.
+    DuplicateUnlessSingle,
+}
+
+/// A combination of hash function, padding, domain separation prefixes and
+/// block size matching a published tree format, set with `HashTree::preset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Preset {
+    /// The defaults of `HashTree::new`: a binary tree of plain SHA-256
+    /// hashes padded with `Padding::Duplicate`. The block size is kept.
+    Plain,
+    /// The Merkle tree hash of Certificate Transparency logs (RFC 6962 and
+    /// RFC 9162): SHA-256 with `0x00` and `0x01` prefixes for leaves and
+    /// parents, and `Padding::Promote`. Every block is one log entry, so
+    /// build it with `from_blocks`; the root then equals `MerkleLog::root`.
+    /// The block size is kept.
+    Rfc6962,
+    /// The transaction tree of Bitcoin block headers: double SHA-256 of the
+    /// concatenated children, with the last node of odd levels duplicated
+    /// and the txid of a block with a single transaction as its root, see
+    /// `Padding::DuplicateUnlessSingle`. Build it with `from_blocks` over
+    /// serialized transactions or with `from_leaf_hashes` over txids in
+    /// internal byte order. The block size is kept.
+    Bitcoin,
+    /// The piece layers of BitTorrent v2 (BEP 52): plain SHA-256 over
+    /// 16 KiB blocks with `Padding::Zero`.
+    BitTorrentV2,
+    /// Tree Hash Exchange (THEX): 1024-byte segments with `0x00` and `0x01`
+    /// prefixes for leaves and parents, and `Padding::Promote`. THEX leaves
+    /// the hash function open; this preset uses SHA-256, so set e.g.
+    /// `PrefixedHasher::<tiger::Tiger>::new()` afterwards to compute Tiger
+    /// Tree Hashes. THEX hashes empty input to the leaf of an empty
+    /// segment, whereas an empty `HashTree` has no root.
+    Thex,
+    /// A binary tree of BLAKE3 hashes padded with `Padding::Duplicate`, see
+    /// `Blake3Hasher`. The root is not the BLAKE3 hash of the input. The
+    /// block size is kept. Requires the `blake3` feature.
+    #[cfg(feature = "blake3")]
+    Blake3,
+}
+
+/// A record of the root of a `HashTree` after an `update`.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootSnapshot {
+    /// Number of updates applied to the tree when the snapshot was taken.
+    pub version: u64,
+    /// Root hash, or `None` if the tree was empty.
+    pub root_hash: Option<RootHash>,
+    /// Number of blocks covered by the root.
+    pub leaf_count: u64,
+    /// Time at which the update completed.
+    pub timestamp: SystemTime,
 }
 
 impl HashTree {
@@ -41,152 +215,2565 @@ impl HashTree {
     /// let tree = HashTree::new(BLOCK_SIZE);
     /// ```
     pub fn new(block_size: usize) -> Self {
+        Self::with_store(block_size, Vec::new())
+    }
+
+    /// Constructs a new empty binary `HashTree` with room for the nodes of a
+    /// tree over `expected_blocks` blocks, so building it does not reallocate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let data = vec![0u8; 10 * 4096];
+    /// let tree = HashTree::with_capacity(4096, 10).from_data(&mut data.as_slice()).unwrap();
+    /// assert!(tree.store().capacity() >= tree.num_nodes());
+    /// ```
+    pub fn with_capacity(block_size: usize, expected_blocks: usize) -> Self {
+        // Every level but the root's is padded to an even length
+        let mut capacity = 0;
+        let mut len = expected_blocks;
+        while len > 0 {
+            len += len % 2;
+            capacity += len;
+            len /= 2;
+            if len == 1 {
+                capacity += 1;
+                break;
+            }
+        }
+        Self::with_store(block_size, Vec::with_capacity(capacity))
+    }
+
+    /// Constructs a new empty `HashTree` with the given `BlockSize`. With
+    /// `BlockSize::Auto`, the block size is picked from the length of the
+    /// input when the tree is built, and reported by `block_size` and
+    /// written by `write_to` afterwards.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::{BlockSize, HashTree};
+    ///
+    /// let data = vec![0u8; 1 << 20];
+    /// let tree = HashTree::with_block_size(BlockSize::Auto).from_slice(&data).unwrap();
+    /// assert_eq!(tree.block_size(), 1024);
+    /// assert_eq!(tree.num_blocks(), 1024);
+    /// ```
+    pub fn with_block_size<B: Into<BlockSize>>(block_size: B) -> Self {
+        let block_size = block_size.into();
+        let mut tree = Self::new(block_size.resolve(None));
+        tree.auto_block_size = block_size == BlockSize::Auto;
+        tree
+    }
+
+    /// Returns an iterator over every node, padding included, as the level
+    /// (0 for leaves), the index of the node within its level and its hash,
+    /// from the leaves up to the root. Placeholders of `Padding::Promote`
+    /// have empty hashes. Trees with other stores can read the nodes with
+    /// `NodeStore::iter_nodes`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let tree = HashTree::new(1).from_slice(b"abc").unwrap();
+    /// let positions: Vec<(usize, usize)> = tree.iter().map(|(level, index, _)| (level, index)).collect();
+    /// assert_eq!(positions, [(0, 0), (0, 1), (0, 2), (0, 3), (1, 0), (1, 1), (2, 0)]);
+    /// for (level, index, hash) in &tree {
+    ///     assert_eq!(level == 2, hash == tree.root_hash_bytes().unwrap());
+    /// }
+    /// ```
+    pub fn iter(&self) -> Nodes<'_> {
+        Nodes { nodes: &self.nodes, levels: self.levels(), level: 0, next: 0 }
+    }
+}
+
+impl<'a> IntoIterator for &'a HashTree {
+    type Item = (usize, usize, &'a [u8]);
+    type IntoIter = Nodes<'a>;
+
+    fn into_iter(self) -> Nodes<'a> {
+        self.iter()
+    }
+}
+
+impl Default for HashTree {
+    /// Constructs a new empty `HashTree` with 4096-byte blocks.
+    fn default() -> Self {
+        Self::new(4096)
+    }
+}
+
+/// Builds a default-configured tree, see `HashTree::default`, over a slice.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::HashTree;
+///
+/// let tree = HashTree::from(&b"abc"[..]);
+/// assert_eq!(tree, HashTree::default().from_slice(b"abc").unwrap());
+/// ```
+impl From<&[u8]> for HashTree {
+    fn from(data: &[u8]) -> Self {
+        HashTree::default().from_slice(data).expect("the in-memory store cannot fail")
+    }
+}
+
+/// Builds a default-configured tree, see `HashTree::default`, over the file
+/// at a path, like `HashTree::from_file`.
+#[cfg(feature = "std")]
+impl TryFrom<&Path> for HashTree {
+    type Error = Error;
+
+    fn try_from(path: &Path) -> Result<Self, Error> {
+        HashTree::default().from_file(path)
+    }
+}
+
+/// Builds a default-configured tree, see `HashTree::default`, over the rest
+/// of an open file.
+#[cfg(feature = "std")]
+impl TryFrom<File> for HashTree {
+    type Error = Error;
+
+    fn try_from(file: File) -> Result<Self, Error> {
+        HashTree::default().read_file(file)
+    }
+}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Constructs a new empty `HashTree` keeping its nodes in `store`, which
+    /// should be empty.
+    pub fn with_store(block_size: usize, store: S) -> Self {
         Self {
-            nodes: VecDeque::new(), 
+            nodes: store,
             num_blocks: 0,
             block_size,
+            auto_block_size: false,
+            arity: 2,
+            hasher: Arc::new(Sha256Hasher::new()),
+            metrics: None,
+            observer: None,
+            root_listeners: RootListeners::default(),
+            dedup: None,
+            labels: BTreeMap::new(),
+            #[cfg(feature = "std")]
+            blocks: None,
+            pending: Vec::new(),
+            data_len: None,
+            leaf_ends: None,
+            pending_lens: Some(Vec::new()),
+            max_leaves: None,
+            max_memory: None,
+            padding: Padding::Duplicate,
+            zero_hashes: Vec::new(),
+            #[cfg(all(feature = "direct", target_os = "linux"))]
+            direct_io: false,
+            #[cfg(feature = "std")]
+            throttle: None,
+            #[cfg(feature = "std")]
+            double_buffered: false,
+            #[cfg(feature = "parallel")]
+            thread_pool: None,
+            version: 0,
+            #[cfg(feature = "std")]
+            history: None,
+            #[cfg(feature = "std")]
+            ops: None,
+            root: None,
         }
     }
 
-    /// Constructs a new `HashTree` from a mutable object
-    /// that implements the `Read` trait.
-    /// Returns an `Error` value if the function failed to read from
-    /// the given object.
+    /// Sets the hash function used for leaves and interior nodes. The
+    /// default is SHA-256.
     ///
     /// # Examples
     ///
     /// ```
-    /// #![allow(dead_code)]
+    /// use hashtree::{DigestHasher, HashTree};
+    ///
+    /// let data = vec![0u8, 1u8];
+    /// let tree = HashTree::new(1)
+    ///     .hasher(DigestHasher::<sha2::Sha512>::new())
+    ///     .from_data(&mut data.as_slice())
+    ///     .unwrap();
+    /// assert!(tree.root_hash().unwrap().as_bytes().len() == 64);
+    /// ```
+    pub fn hasher<H: PairHasher + 'static>(mut self, hasher: H) -> Self {
+        self.hasher = Arc::new(hasher);
+        self.zero_hashes.clear();
+        self
+    }
+
+    /// Mixes `salt` into every leaf by prefixing the contents of each block
+    /// with it, see `SaltedHasher`. Wraps the hasher set so far, so call this
+    /// after `hasher`.
+    pub fn salt(mut self, salt: &[u8]) -> Self {
+        self.hasher = Arc::new(SaltedHasher::wrap(self.hasher, salt));
+        self.zero_hashes.clear();
+        self
+    }
+
+    /// Frames every leaf as `H(len || data)`, see `LengthPrefixedHasher`, so
+    /// truncated or padded final blocks cannot collide with full blocks.
+    /// Wraps the hasher set so far, so call this after `hasher`.
+    pub fn length_prefixed(mut self) -> Self {
+        self.hasher = Arc::new(LengthPrefixedHasher::wrap(self.hasher));
+        self.zero_hashes.clear();
+        self
+    }
+
+    /// Transforms the contents of every block with `preprocess` before it is
+    /// hashed, see `PreprocessingHasher`. Wraps the hasher set so far, so
+    /// call this after `hasher`.
+    pub fn preprocess<F>(mut self, preprocess: F) -> Self
+    where
+        F: Fn(&[u8]) -> Cow<'_, [u8]> + Send + Sync + 'static,
+    {
+        self.hasher = Arc::new(PreprocessingHasher::wrap(self.hasher, Arc::new(preprocess)));
+        self.zero_hashes.clear();
+        self
+    }
+
+    /// Returns the hash function used by the `HashTree`.
+    pub fn get_hasher(&self) -> &dyn PairHasher {
+        self.hasher.as_ref()
+    }
+
+    pub(crate) fn shared_hasher(&self) -> Arc<dyn PairHasher> {
+        self.hasher.clone()
+    }
+
+    /// Sets a `Metrics` receiving statistics whenever the tree is built.
+    pub fn metrics<M: Metrics + 'static>(mut self, metrics: M) -> Self {
+        self.metrics = Some(Arc::new(metrics));
+        self
+    }
+
+    /// Sets a `BuildObserver` receiving the events of every build of the tree.
+    pub fn observer<O: BuildObserver + 'static>(mut self, observer: O) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Registers `callback` to be called with the new root hash and version
+    /// whenever an update of the tree commits a new version, see `version`:
+    /// after `update`, `extend_from_reader`, `truncate`, `apply_ops`, `merge`
+    /// and the blocks completed by a `TailFollower`, but not after the first
+    /// build. It is not called while the tree is empty. Callbacks are called
+    /// on the updating thread, in registration order, and the update returns
+    /// once they do, so they should hand slow work such as signing and
+    /// publishing the root off to another thread.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
     /// use hashtree::HashTree;
     ///
-    /// const BLOCK_SIZE: usize = 1;
-    /// let mut data = vec![0u8, 1u8];
-    /// let tree = HashTree::new(BLOCK_SIZE).from_data(&mut data.as_slice()).unwrap();
-    /// assert!(tree.num_blocks() == 2);
-    /// assert!(tree.num_nodes() == 3);
+    /// let published = Arc::new(Mutex::new(Vec::new()));
+    /// let sink = published.clone();
+    /// let mut tree = HashTree::new(4)
+    ///     .on_root_changed(move |root, version| sink.lock().unwrap().push((version, root)))
+    ///     .from_slice(b"first entry")
+    ///     .unwrap();
+    /// assert!(published.lock().unwrap().is_empty());
+    ///
+    /// tree.insert(&mut &b"second entry"[..]).unwrap();
+    /// tree.update().unwrap();
+    /// assert_eq!(*published.lock().unwrap(), [(1, tree.root_hash().unwrap())]);
     /// ```
-    /// The example above splits the data into 1-byte blocks and computes 
-    /// their SHA256 digests.
-    pub fn from_data<R: Read>(mut self, data: &mut R) -> Result<Self, std::io::Error> {
-        let mut buf = Vec::<u8>::with_capacity(self.block_size);
-        let mut index = 0;
+    pub fn on_root_changed<F: Fn(RootHash, u64) + Send + Sync + 'static>(mut self, callback: F) -> Self {
+        self.root_listeners.push(callback);
+        self
+    }
 
-        loop {
-            let mut chunk = data.take(self.block_size as u64);
-            if chunk.read_to_end(&mut buf)? == 0 { break; }
+    /// Sets a `BlockStore` receiving the contents of every block hashed into
+    /// the tree, so they can be read back with `get_block`. Blocks are only
+    /// stored when the tree hashes them itself, not for leaf hashes passed in.
+    #[cfg(feature = "std")]
+    pub fn block_store<B: BlockStore + 'static>(mut self, store: B) -> Self {
+        self.blocks = Some(Arc::new(store));
+        self
+    }
+
+    /// Limits the tree to `max_leaves` leaves. Building or growing the tree
+    /// past the limit fails with an `InvalidInput` error instead of
+    /// exhausting memory, e.g. when a tiny block size meets a huge input.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// assert!(HashTree::new(1).max_leaves(4).from_slice(b"abcd").is_ok());
+    /// assert!(HashTree::new(1).max_leaves(4).from_slice(b"abcde").is_err());
+    /// ```
+    pub fn max_leaves(mut self, max_leaves: usize) -> Self {
+        self.max_leaves = Some(max_leaves);
+        self
+    }
 
-            let hash = Sha256::digest(&buf).to_vec();
-            let node = Node { hash, index, left: None, right: None };
-            self.nodes.push_back(node);
-            index += 1;
+    /// Limits the nodes of the tree to about `max_bytes` bytes of memory, as
+    /// counted by `TreeEstimate::memory_bytes`. Building or growing the tree
+    /// past the limit fails with an `InvalidInput` error instead of
+    /// exhausting memory.
+    pub fn max_memory(mut self, max_bytes: u64) -> Self {
+        self.max_memory = Some(max_bytes);
+        self
+    }
 
-            buf.clear();
+    /// Picks the block size for `len` bytes of input, or input of unknown
+    /// length, if it was left to `BlockSize::Auto`.
+    pub(crate) fn resolve_block_size(&mut self, len: Option<u64>) {
+        if self.auto_block_size {
+            self.block_size = BlockSize::Auto.resolve(len);
+            self.auto_block_size = false;
         }
-        // NOTE: When reconstructing the hashtree via the `insert` and `update` methods in the future, 
-        // make sure to drain the nodes from `self.num_blocks + 1` if we read an odd number of blocks
-        self.num_blocks = self.nodes.len();
+    }
 
-        // If there are an odd number of blocks, we need to clone the last block in order to 
-        // build the tree properly
-        if self.nodes.len() % 2 == 1 {
-            self.nodes.push_back(self.nodes.back().unwrap().clone());
+    /// Returns the block size a tree over `len` bytes of input would use.
+    pub(crate) fn block_size_for(&self, len: u64) -> usize {
+        if self.auto_block_size {
+            BlockSize::Auto.resolve(Some(len))
+        } else {
+            self.block_size
         }
+    }
 
-        self.build(self.nodes.clone())?;
-        Ok(self)
+    /// Fails if a tree over `len` bytes of input would exceed the limits set
+    /// by `max_leaves` or `max_memory`, before anything is hashed.
+    fn check_input_len(&self, len: u64) -> Result<(), Error> {
+        if self.max_leaves.is_none() && self.max_memory.is_none() {
+            return Ok(());
+        }
+        let estimate = self.estimate(len);
+        self.check_limits(estimate.leaves, estimate.hash_len)
     }
 
-    fn build(&mut self, mut unprocessed_nodes: VecDeque<Node>) -> Result<(), std::io::Error> {
-        let mut parents = VecDeque::<Node>::new();
-        while !unprocessed_nodes.is_empty() {
-            let mut n1 = unprocessed_nodes.pop_front().unwrap();
-            let mut n2 = unprocessed_nodes.pop_front().unwrap();
+    /// Fails if a tree of `leaves` leaves with hashes of `hash_len` bytes
+    /// would exceed the limits set by `max_leaves` or `max_memory`.
+    fn check_limits(&self, leaves: u64, hash_len: usize) -> Result<(), Error> {
+        if let Some(max_leaves) = self.max_leaves {
+            if leaves > max_leaves as u64 {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("tree exceeds the limit of {} leaves, use a larger block size", max_leaves),
+                ));
+            }
+        }
+        if let Some(max_memory) = self.max_memory {
+            let bytes = memory_bytes(count_nodes(leaves, self.arity as u64).0, hash_len);
+            if bytes > max_memory {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "tree needs about {} bytes, more than the limit of {} bytes, use a larger block size",
+                        bytes, max_memory
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
 
-            n1.hash.append(&mut n2.hash);
-            let merged_hash = n1.hash;
+    /// Makes `from_file` read with `O_DIRECT` into aligned buffers, so
+    /// hashing large files, e.g. to verify backups, does not evict other
+    /// data from the page cache. Files on file systems without direct I/O
+    /// support, such as tmpfs, are read normally.
+    #[cfg(all(feature = "direct", target_os = "linux"))]
+    pub fn direct_io(mut self) -> Self {
+        self.direct_io = true;
+        self
+    }
 
-            let hash = Sha256::digest(&merged_hash).to_vec();
+    /// Limits the rate at which `from_file` reads the file to that of
+    /// `throttle`, so large files can be hashed in the background. The
+    /// sparse and io_uring read paths are not taken while throttled.
+    #[cfg(feature = "std")]
+    pub fn throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
 
-            let index = self.nodes.len();
-            let parent = Node { hash, index, left: Some(n1.index), right: Some(n2.index) };
-            parents.push_back(parent.clone());
-            self.nodes.push_back(parent);
-        }
+    /// Makes `from_data`, `from_chunker` and the plain read path of
+    /// `from_file` hash on a helper thread, so the next blocks are read
+    /// while the previous ones are hashed instead of alternating between
+    /// the two. This overlaps I/O with hashing without the thread pool of
+    /// the `parallel` feature, at the cost of a second set of block
+    /// buffers. On targets without threads, such as wasm32-wasi, reading
+    /// and hashing still alternate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let data = vec![7u8; 100 * 1024];
+    /// let tree = HashTree::new(4096).double_buffered().from_data(data.as_slice()).unwrap();
+    /// assert_eq!(tree, HashTree::new(4096).from_slice(&data).unwrap());
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn double_buffered(mut self) -> Self {
+        self.double_buffered = true;
+        self
+    }
 
-        if parents.len() == 1 {
-            return Ok(());
+    /// Runs the parallel operations of the tree, such as `proofs_par` and
+    /// `multiproof_par`, on `pool` instead of the global rayon thread pool,
+    /// so they stay within the threading budget of the host application.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashtree::HashTree;
+    ///
+    /// let pool = Arc::new(rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap());
+    /// let tree = HashTree::new(1).thread_pool(pool).from_slice(&[7u8; 5000]).unwrap();
+    /// assert_eq!(tree.proofs_par().len(), 5000);
+    /// ```
+    #[cfg(feature = "parallel")]
+    pub fn thread_pool(mut self, pool: Arc<rayon::ThreadPool>) -> Self {
+        self.thread_pool = Some(pool);
+        self
+    }
+
+    /// Runs `op` on the thread pool set with `thread_pool`, or on the
+    /// current thread, whose rayon calls use the global pool.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn install<R: Send, F: FnOnce() -> R + Send>(&self, op: F) -> R {
+        match &self.thread_pool {
+            Some(pool) => pool.install(op),
+            None => op(),
         }
+    }
+
+    /// Returns the number of children of every interior node.
+    pub fn get_arity(&self) -> usize {
+        self.arity
+    }
 
-        self.build(parents)
+    /// Sets the number of children of every interior node. The default is a
+    /// binary tree; larger arities such as 4, 8 or 16 give shallower trees
+    /// and shorter proofs for huge inputs, at the cost of `arity - 1`
+    /// siblings per proof level.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `arity` is smaller than 2.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let data = vec![0u8; 16];
+    /// let tree = HashTree::new(1).arity(4).from_data(&mut data.as_slice()).unwrap();
+    /// assert!(tree.num_nodes() == 16 + 4 + 1);
+    /// ```
+    pub fn arity(mut self, arity: usize) -> Self {
+        assert!(arity >= 2, "arity must be at least 2");
+        self.arity = arity;
+        self.zero_hashes.clear();
+        self
     }
 
-    // TODO: Implement ability to add data manually and reconstruct HashTree on the fly 
-    pub fn insert<R: Read>(&mut self, data: &mut R) {
-        unimplemented!();
+    /// Returns how levels of the tree are padded.
+    pub fn get_padding(&self) -> Padding {
+        self.padding
     }
 
-    /// Recomputes the hashes and nodes of the `HashTree`. This method should be called
-    /// after you are done manually inserting data via the `insert` method.
-    pub fn update(&mut self) {
-        unimplemented!();
+    /// Sets how levels whose length is not a multiple of the arity are
+    /// padded. The default is `Padding::Duplicate`. With `Padding::Zero`,
+    /// the zero hash of every level is computed once and reused by later
+    /// builds and updates.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::{HashTree, Padding};
+    /// use sha2::{Digest, Sha256};
+    ///
+    /// let tree = HashTree::new(1).padding(Padding::Zero).from_slice(b"abc").unwrap();
+    /// let leaves: Vec<Vec<u8>> = [b"a", b"b", b"c"].iter().map(|b| Sha256::digest(b).to_vec()).collect();
+    /// let zeros = vec![0u8; 32];
+    /// let expected = HashTree::new(1).from_leaf_hashes(leaves.into_iter().chain(Some(zeros))).unwrap();
+    /// assert_eq!(tree.root_hash(), expected.root_hash());
+    /// ```
+    pub fn padding(mut self, padding: Padding) -> Self {
+        self.padding = padding;
+        self
     }
 
-    /// Returns `true` if the `HashTree` is empty and `false` otherwise.
+    /// Sets the hash function, padding, domain separation prefixes and, for
+    /// formats that fix one, the block size of a published tree format, so
+    /// the tree interoperates with other implementations of it. Options set
+    /// afterwards override those of the preset.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::{HashTree, MerkleLog, Preset};
+    ///
+    /// let entries = [&b"a"[..], b"b", b"c", b"d", b"e"];
+    /// let tree = HashTree::new(0).preset(Preset::Rfc6962).from_blocks(entries).unwrap();
+    /// let mut log = MerkleLog::new();
+    /// for entry in entries {
+    ///     log.append(entry).unwrap();
+    /// }
+    /// assert_eq!(tree.root_hash_array(), Some(log.root()));
+    ///
+    /// let torrent = HashTree::new(0).preset(Preset::BitTorrentV2);
+    /// assert_eq!(torrent.block_size(), 16 * 1024);
+    /// ```
+    pub fn preset(self, preset: Preset) -> Self {
+        let tree = match preset {
+            Preset::Plain => self.hasher(Sha256Hasher::new()).padding(Padding::Duplicate),
+            Preset::Rfc6962 => self.hasher(PrefixedHasher::<Sha256>::new()).padding(Padding::Promote),
+            Preset::Bitcoin => self.hasher(DoubleSha256Hasher::new()).padding(Padding::DuplicateUnlessSingle),
+            Preset::BitTorrentV2 => self.hasher(Sha256Hasher::new()).padding(Padding::Zero).fixed_block_size(16 * 1024),
+            Preset::Thex => self.hasher(PrefixedHasher::<Sha256>::new()).padding(Padding::Promote).fixed_block_size(1024),
+            #[cfg(feature = "blake3")]
+            Preset::Blake3 => self.hasher(Blake3Hasher::new()).padding(Padding::Duplicate),
+        };
+        tree.arity(2)
+    }
+
+    fn fixed_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size;
+        self.auto_block_size = false;
+        self
+    }
+    /// Constructs a new `HashTree` from an object that implements the
+    /// `Read` trait. Pass `&mut reader` to keep using the reader afterwards.
+    /// Returns an `Error` value if the function failed to read from
+    /// the given object.
     ///
     /// # Examples
     ///
     /// ```
     /// #![allow(dead_code)]
     /// use hashtree::HashTree;
-    /// 
-    /// const BLOCK_SIZE: usize = 4096;
     ///
-    /// let tree = HashTree::new(BLOCK_SIZE);
-    /// assert!(tree.is_empty());
+    /// const BLOCK_SIZE: usize = 1;
+    /// let mut data = vec![0u8, 1u8];
+    /// let tree = HashTree::new(BLOCK_SIZE).from_data(&mut data.as_slice()).unwrap();
+    /// assert!(tree.num_blocks() == 2);
+    /// assert!(tree.num_nodes() == 3);
     /// ```
-    pub fn is_empty(&self) -> bool {
-        if self.nodes.len() == 0 {
-            return true
+    /// The example above splits the data into 1-byte blocks and computes 
+    /// their SHA256 digests.
+    #[cfg(feature = "std")]
+    pub fn from_data<R: Read>(mut self, data: R) -> Result<Self, Error> {
+        self.resolve_block_size(None);
+        let block_size = self.block_size;
+        self.from_chunker(FixedChunker::new(data, block_size))
+    }
+
+    /// Constructs a new `HashTree` from `data` like `from_data`, and checks
+    /// that it has the root `expected_root`, e.g. one published alongside a
+    /// download. The roots are compared in constant time.
+    /// Returns an `Error` value of kind `InvalidData` if the roots differ, or
+    /// if reading fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let root = HashTree::new(4).from_slice(b"downloaded file").unwrap().root_hash().unwrap();
+    /// assert!(HashTree::new(4).from_data_expecting(&b"downloaded file"[..], root.as_bytes()).is_ok());
+    /// assert!(HashTree::new(4).from_data_expecting(&b"downloaded fil3"[..], root.as_bytes()).is_err());
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn from_data_expecting<R: Read>(self, data: R, expected_root: &[u8]) -> Result<Self, Error> {
+        let tree = self.from_data(data)?;
+        match &tree.root {
+            Some(root) if constant_time_eq(root, expected_root) => Ok(tree),
+            _ => Err(Error::new(ErrorKind::InvalidData, "tree does not have the expected root")),
         }
-        false
     }
 
-    /// Returns the root hash of the `HashTree` as an `Option<String>`.
-    pub fn root_hash(&self) -> Option<String> {
-        if let Some(root) = self.nodes.back() {
-            return Some(hex::encode(&root.hash))
+    /// Like `from_data_expecting`, but checks against the whole `expected`
+    /// tree, e.g. one read with `read_from` before the download, so the
+    /// error names the first range of blocks that do not match it.
+    /// `expected` is trusted as far as its root is; its leaves are not
+    /// checked against it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let expected = HashTree::new(4).from_slice(b"downloaded file").unwrap();
+    /// let error = HashTree::new(4).from_data_expecting_tree(&b"downXXaded file"[..], &expected).unwrap_err();
+    /// assert_eq!(error.to_string(), "blocks 1..2 do not match the expected tree");
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn from_data_expecting_tree<R: Read, T: NodeStore>(self, data: R, expected: &HashTree<T>) -> Result<Self, Error> {
+        let tree = self.from_data(data)?;
+        if tree.same_commitment(expected) {
+            return Ok(tree);
+        }
+        let message = match tree.diff(expected).first() {
+            Some(range) => format!("blocks {}..{} do not match the expected tree", range.start, range.end),
+            None => "tree does not have the expected root".to_string(),
         };
-        None
+        Err(Error::new(ErrorKind::InvalidData, message))
     }
 
-    /// Returns the number of nodes in the `HashTree`.
-    pub fn num_nodes(&self) -> usize {
-        self.nodes.len()
+    /// Constructs a new `HashTree` with one leaf per chunk produced by
+    /// the given `Chunker`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::{HashTree, RecordChunker};
+    ///
+    /// let data = "first\nsecond\nthird\n";
+    /// let tree = HashTree::new(0).from_chunker(RecordChunker::lines(data.as_bytes())).unwrap();
+    /// assert!(tree.num_blocks() == 3);
+    /// ```
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "hash", level = "debug", skip_all, fields(block_size = self.block_size)))]
+    pub fn from_chunker<C: Chunker>(mut self, mut chunker: C) -> Result<Self, Error> {
+        let timer = Timer::start();
+        let data_len = if self.double_buffered { self.hash_double_buffered(&mut chunker)? } else { self.hash_chunks(&mut chunker)? };
+        self.data_len = Some(data_len);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(blocks = self.nodes.len(), "hashed leaves");
+        self.phase_completed(Phase::Leaves, timer);
+
+        self.finish()?;
+        Ok(self)
     }
 
-    /// Returns the number of blocks that were used to construct the `HashTree`.
-    pub fn num_blocks(&self) -> usize {
-        self.num_blocks
+    /// Hashes every chunk of `chunker` into a leaf, reading and hashing in
+    /// turn. Returns the number of bytes hashed.
+    #[cfg(feature = "std")]
+    fn hash_chunks<C: Chunker>(&mut self, chunker: &mut C) -> Result<u64, Error> {
+        let mut bufs: Vec<BlockBuf> = (0..self.leaf_batch()).map(|_| block_buf(self.block_size)).collect();
+        let mut data_len = 0;
+        loop {
+            let filled = self.fill_batch(chunker, &mut bufs, self.nodes.len())?;
+            let blocks: Vec<&[u8]> = bufs[..filled].iter().map(|buf| &buf[..]).collect();
+            data_len += self.push_blocks(&blocks)?;
+            if filled < bufs.len() {
+                return Ok(data_len);
+            }
+        }
     }
-}
 
-impl PartialEq for HashTree {
-    fn eq(&self, other: &Self) -> bool {
-        let my_root = match self.root_hash() {
-            Some(v) => v,
-            None => {
-                match other.root_hash() {
-                    Some(_) => { return false },
-                    None => { return true }
-                };
+    /// Like `hash_chunks`, but hashes every batch of chunks on a helper
+    /// thread while the next batch is read, see `double_buffered`.
+    #[cfg(all(feature = "std", not(all(target_family = "wasm", not(target_feature = "atomics")))))]
+    fn hash_double_buffered<C: Chunker>(&mut self, chunker: &mut C) -> Result<u64, Error> {
+        let batch = self.leaf_batch();
+        let hasher = Arc::clone(&self.hasher);
+        thread::scope(|scope| {
+            // Dropping the channels on return, also on errors, stops the helper
+            let (jobs, queued) = mpsc::sync_channel::<Vec<BlockBuf>>(1);
+            let (results, hashed) = mpsc::sync_channel::<(Vec<BlockBuf>, Vec<Vec<u8>>)>(1);
+            scope.spawn(move || {
+                for bufs in queued {
+                    let blocks: Vec<&[u8]> = bufs.iter().map(|buf| &buf[..]).collect();
+                    let hashes = hasher.hash_leaves(&blocks);
+                    if results.send((bufs, hashes)).is_err() {
+                        break;
+                    }
+                }
+            });
+            let mut spare: Vec<BlockBuf> = (0..batch).map(|_| block_buf(self.block_size)).collect();
+            let mut in_flight = 0;
+            let mut data_len = 0;
+            loop {
+                let mut bufs = mem::take(&mut spare);
+                let filled = self.fill_batch(chunker, &mut bufs, self.nodes.len() + in_flight)?;
+                let last = filled < batch;
+                bufs.truncate(filled);
+                // The previous batch was hashed while this one was read
+                if in_flight > 0 {
+                    let (done, hashes) = hashed.recv().map_err(|_| helper_failed())?;
+                    let blocks: Vec<&[u8]> = done.iter().map(|buf| &buf[..]).collect();
+                    data_len += self.push_hashed(&blocks, hashes)?;
+                    spare = done;
+                } else {
+                    spare = (0..batch).map(|_| block_buf(self.block_size)).collect();
+                }
+                in_flight = bufs.len();
+                if in_flight > 0 {
+                    jobs.send(bufs).map_err(|_| helper_failed())?;
+                }
+                if last {
+                    break;
+                }
             }
-        };
+            if in_flight > 0 {
+                let (done, hashes) = hashed.recv().map_err(|_| helper_failed())?;
+                let blocks: Vec<&[u8]> = done.iter().map(|buf| &buf[..]).collect();
+                data_len += self.push_hashed(&blocks, hashes)?;
+            }
+            Ok(data_len)
+        })
+    }
 
-        let other_root = match other.root_hash() {
-            Some(v) => v,
-            None => { return false; }
+    /// Without threads, reading and hashing alternate, see `double_buffered`.
+    #[cfg(all(feature = "std", target_family = "wasm", not(target_feature = "atomics")))]
+    fn hash_double_buffered<C: Chunker>(&mut self, chunker: &mut C) -> Result<u64, Error> {
+        self.hash_chunks(chunker)
+    }
+
+    /// Reads the next chunks of `chunker` into `bufs`, the first of which
+    /// becomes leaf `first`. Returns the number of buffers filled, fewer
+    /// than `bufs.len()` once the input is exhausted.
+    #[cfg(feature = "std")]
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn fill_batch<C: Chunker>(&self, chunker: &mut C, bufs: &mut [BlockBuf], first: usize) -> Result<usize, Error> {
+        let mut filled = 0;
+        while filled < bufs.len() && chunker.next_chunk(&mut bufs[filled])? {
+            #[cfg(feature = "tracing")]
+            tracing::trace!(index = first + filled, len = bufs[filled].len(), "hashing block");
+            filled += 1;
+        }
+        Ok(filled)
+    }
+
+    /// Reopens a tree over `num_blocks` blocks whose nodes were written to the
+    /// store earlier, e.g. by a durable store that survived a restart. The
+    /// arity and hasher must be set to the values the tree was built with.
+    /// Returns an `Error` value if the store does not hold a tree of that size.
+    pub fn open(mut self, num_blocks: u64) -> Result<Self, Error> {
+        let num_blocks = to_usize(num_blocks)?;
+        self.num_blocks = num_blocks;
+        let valid = if num_blocks == 0 || self.nodes.is_empty() {
+            num_blocks == 0 && self.nodes.is_empty()
+        } else {
+            self.levels().last().map(|level| level.end) == Some(self.nodes.len())
         };
+        if !valid {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "node store does not hold a tree with this number of blocks",
+            ));
+        }
+        self.load_root();
+        self.prepare_padding();
+        // The store does not hold the leaf lengths
+        self.leaf_ends = None;
+        if let Some(dedup) = self.dedup.as_mut() {
+            dedup.clear();
+            for (index, hash) in self.nodes.iter_range(0..num_blocks).enumerate() {
+                dedup.entry(hash).or_default().push(index as u64);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Writes the tree to `writer`: a versioned header holding the tree's
+    /// parameters, followed by the node hashes in storage order and, if they
+    /// were recorded, the length of every leaf as a big-endian `u64`. Nodes
+    /// are streamed one at a time, so wrap unbuffered writers in a `BufWriter`.
+    /// Returns an `Error` value if writing fails or the hashes differ in length.
+    /// Placeholders of `Padding::Promote` are written as all-zero hashes.
+    ///
+    /// The header is 48 bytes, all integers big-endian:
+    ///
+    /// | Bytes | Field |
+    /// |-------|-------|
+    /// | 0..4 | magic `HTRE` |
+    /// | 4..6 | format version |
+    /// | 6 | padding strategy, 0 for duplicating the last node, 1 for zero hashes, 2 for promoting it |
+    /// | 7 | flags, bit 0 set if leaf lengths follow the nodes, bit 1 set if the nodes are in level order |
+    /// | 8..16 | fingerprint of the hash algorithm |
+    /// | 16..24 | block size |
+    /// | 24..32 | number of blocks |
+    /// | 32..36 | arity |
+    /// | 36..40 | hash length |
+    /// | 40..48 | number of nodes |
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let tree = HashTree::new(2).from_data(&mut &b"abcdefg"[..]).unwrap();
+    /// let mut bytes = Vec::new();
+    /// tree.write_to(&mut bytes).unwrap();
+    /// let copy = HashTree::new(0).read_from(bytes.as_slice()).unwrap();
+    /// assert_eq!(copy, tree);
+    /// assert_eq!(copy.block_size(), 2);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<(), Error> {
+        self.write_layout(writer, false)
+    }
 
-        my_root == other_root
+    /// Writes the tree to `writer` like `write_to`, but with the nodes in
+    /// level order: the root first, then every level down to the leaves.
+    /// A receiver holding a trusted root can then check each group of
+    /// children against its parent as it arrives with `read_level_order`,
+    /// rejecting a corrupt tree before the rest of it is downloaded.
+    /// Returns an `Error` value if writing fails or the hashes differ in length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let tree = HashTree::new(2).from_data(&mut &b"abcdefg"[..]).unwrap();
+    /// let mut bytes = Vec::new();
+    /// tree.write_level_order(&mut bytes).unwrap();
+    /// assert_eq!(&bytes[48..80], tree.root_hash_bytes().unwrap());
+    ///
+    /// let root = tree.root_hash_bytes().unwrap();
+    /// let copy = HashTree::new(0).read_level_order(bytes.as_slice(), root).unwrap();
+    /// assert_eq!(copy, tree);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn write_level_order<W: Write>(&self, writer: W) -> Result<(), Error> {
+        self.write_layout(writer, true)
     }
+
+    /// Writes the header, nodes and leaf lengths of the tree, with the nodes
+    /// in storage order or, if `level_order` is set, root first.
+    #[cfg(feature = "std")]
+    fn write_layout<W: Write>(&self, mut writer: W, level_order: bool) -> Result<(), Error> {
+        let hash_len = self.root().map(|root| root.len()).unwrap_or(0);
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+        let mut flags = if self.leaf_ends.is_some() { FLAG_LEAF_LENGTHS } else { 0 };
+        if level_order {
+            flags |= FLAG_LEVEL_ORDER;
+        }
+        let padding = match self.padding {
+            Padding::Duplicate => PADDING_DUPLICATE,
+            Padding::Zero => PADDING_ZERO,
+            Padding::Promote => PADDING_PROMOTE,
+            Padding::DuplicateUnlessSingle => PADDING_DUPLICATE_UNLESS_SINGLE,
+        };
+        header.extend_from_slice(&[padding, flags]);
+        header.extend_from_slice(&fingerprint(self.hasher.as_ref()));
+        header.extend_from_slice(&(self.block_size as u64).to_be_bytes());
+        header.extend_from_slice(&self.num_blocks().to_be_bytes());
+        header.extend_from_slice(&(self.arity as u32).to_be_bytes());
+        header.extend_from_slice(&(hash_len as u32).to_be_bytes());
+        header.extend_from_slice(&(self.nodes.len() as u64).to_be_bytes());
+        writer.write_all(&header)?;
+
+        let placeholder = vec![0u8; hash_len];
+        let nodes: Box<dyn Iterator<Item = Vec<u8>> + '_> = if level_order {
+            Box::new(self.levels().into_iter().rev().flat_map(move |level| self.nodes.iter_range(level)))
+        } else {
+            Box::new(self.nodes.iter_nodes())
+        };
+        for hash in nodes {
+            if hash.is_empty() && self.padding == Padding::Promote {
+                writer.write_all(&placeholder)?;
+                continue;
+            }
+            if hash.len() != hash_len {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "node hashes differ in length",
+                ));
+            }
+            writer.write_all(&hash)?;
+        }
+        for len in self.leaf_lengths().into_iter().flatten() {
+            writer.write_all(&len.to_be_bytes())?;
+        }
+        writer.flush()
+    }
+
+    /// Reads a tree written by `write_to` or `write_level_order` from
+    /// `reader` into the store of `self`, replacing its contents. The block
+    /// size and arity are taken from the stream; the hasher must be set to the
+    /// one the tree was built with.
+    /// Returns an `Error` value if reading fails, the stream is not a valid
+    /// tree, or it was written with an unsupported format version, padding
+    /// strategy or a different hash algorithm.
+    #[cfg(feature = "std")]
+    pub fn read_from<R: Read>(self, reader: R) -> Result<Self, Error> {
+        self.read_layout(reader, None)
+    }
+
+    /// Reads a tree written by `write_level_order` from `reader` into the
+    /// store of `self`, like `read_from`, checking the root against the
+    /// trusted `root` and every group of children against its parent as soon
+    /// as it is read. Reading stops at the first mismatch, so a corrupt tree
+    /// is rejected without reading the rest of it.
+    /// Returns an `Error` value if the stream is not in level order, does not
+    /// match `root` or is inconsistent, or for the reasons of `read_from`.
+    #[cfg(feature = "std")]
+    pub fn read_level_order<R: Read>(self, reader: R, root: &[u8]) -> Result<Self, Error> {
+        self.read_layout(reader, Some(root))
+    }
+
+    /// Reads a tree in either layout, see `read_from`, requiring level order
+    /// and checking it against `root` if one is given.
+    #[cfg(feature = "std")]
+    fn read_layout<R: Read>(mut self, mut reader: R, root: Option<&[u8]>) -> Result<Self, Error> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+        let mut header = [0u8; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        let field = |range: Range<usize>| header[range].iter().fold(0u64, |n, &b| n << 8 | b as u64);
+
+        if &header[..4] != MAGIC {
+            return Err(invalid("not a serialized hash tree".to_string()));
+        }
+        let version = field(4..6);
+        if version != FORMAT_VERSION as u64 {
+            return Err(invalid(format!("unsupported format version {}", version)));
+        }
+        self.padding = match header[6] {
+            PADDING_DUPLICATE => Padding::Duplicate,
+            PADDING_ZERO => Padding::Zero,
+            PADDING_PROMOTE => Padding::Promote,
+            PADDING_DUPLICATE_UNLESS_SINGLE => Padding::DuplicateUnlessSingle,
+            padding => return Err(invalid(format!("unsupported padding strategy {}", padding))),
+        };
+        if header[7] & !(FLAG_LEAF_LENGTHS | FLAG_LEVEL_ORDER) != 0 {
+            return Err(invalid(format!("unsupported flags {:#04x}", header[7])));
+        }
+        let level_order = header[7] & FLAG_LEVEL_ORDER != 0;
+        if root.is_some() && !level_order {
+            return Err(invalid("tree is not in level order".to_string()));
+        }
+        if header[8..16] != fingerprint(self.hasher.as_ref()) {
+            return Err(invalid(format!(
+                "tree was built with a different hash algorithm than {:?}",
+                self.hasher
+            )));
+        }
+        let (block_size, num_blocks) = (field(16..24), field(24..32));
+        let (arity, hash_len, num_nodes) = (field(32..36), field(36..40), field(40..48));
+        if arity < 2 {
+            return Err(invalid(format!("invalid arity {}", arity)));
+        }
+        if hash_len == 0 && num_nodes > 0 {
+            return Err(invalid("invalid hash length 0".to_string()));
+        }
+
+        self.block_size = to_usize(block_size)?;
+        self.auto_block_size = false;
+        self.arity = to_usize(arity)?;
+        self.zero_hashes.clear();
+        to_usize(num_nodes)?;
+        self.nodes.truncate(0)?;
+        let hash_len = to_usize(hash_len)?;
+        if level_order {
+            self.read_levels(&mut reader, to_usize(num_blocks)?, num_nodes, hash_len, root)?;
+        } else {
+            for _ in 0..num_nodes {
+                let hash = self.read_node(&mut reader, hash_len)?;
+                self.push(hash)?;
+            }
+        }
+        self.nodes.flush()?;
+        let mut tree = self.open(num_blocks)?;
+
+        if header[7] & FLAG_LEAF_LENGTHS != 0 {
+            let mut ends = Vec::new();
+            let mut end = 0u64;
+            let mut len = [0u8; 8];
+            for _ in 0..num_blocks {
+                reader.read_exact(&mut len)?;
+                end = end
+                    .checked_add(u64::from_be_bytes(len))
+                    .ok_or_else(|| invalid("leaf lengths overflow".to_string()))?;
+                ends.push(end);
+            }
+            tree.data_len = Some(end);
+            tree.leaf_ends = Some(ends);
+        }
+        Ok(tree)
+    }
+
+    /// Reads the `num_nodes` nodes of a tree over `num_blocks` blocks, root
+    /// first, checking the root against `root` if given and every group of
+    /// children against its parent, then stores them in storage order.
+    #[cfg(feature = "std")]
+    fn read_levels<R: Read>(
+        &mut self,
+        reader: &mut R,
+        num_blocks: usize,
+        num_nodes: u64,
+        hash_len: usize,
+        root: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+        let levels = self.level_ranges(num_blocks);
+        if levels.last().map_or(0, |level| level.end) as u64 != num_nodes {
+            return Err(invalid("number of nodes does not match the number of blocks".to_string()));
+        }
+        if root.is_some() && levels.is_empty() {
+            return Err(invalid("tree does not match the root".to_string()));
+        }
+
+        // Levels from the root down
+        let mut read: Vec<Vec<Vec<u8>>> = Vec::with_capacity(levels.len());
+        for (level, range) in levels.iter().enumerate().rev() {
+            let mut nodes = Vec::new();
+            for _ in range.clone() {
+                nodes.push(self.read_node(reader, hash_len)?);
+                let parents = match read.last() {
+                    Some(parents) => parents,
+                    None => {
+                        if root.is_some_and(|root| !constant_time_eq(&nodes[0], root)) {
+                            return Err(invalid("tree does not match the root".to_string()));
+                        }
+                        continue;
+                    }
+                };
+                if nodes.len() % self.arity == 0 {
+                    let group: Vec<&[u8]> = nodes[nodes.len() - self.arity..].iter().map(Vec::as_slice).collect();
+                    if self.hash_group(&group) != parents[nodes.len() / self.arity - 1] {
+                        return Err(invalid(format!("level {} does not match the level above", level)));
+                    }
+                }
+            }
+            read.push(nodes);
+        }
+        for hash in read.into_iter().rev().flatten() {
+            self.push(hash)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a node hash of `hash_len` bytes, turning the all-zero hashes
+    /// written for placeholders of `Padding::Promote` back into placeholders.
+    #[cfg(feature = "std")]
+    fn read_node<R: Read>(&self, reader: &mut R, hash_len: usize) -> Result<Vec<u8>, Error> {
+        let mut hash = vec![0u8; hash_len];
+        reader.read_exact(&mut hash)?;
+        if self.padding == Padding::Promote && hash.iter().all(|&byte| byte == 0) {
+            hash.clear();
+        }
+        Ok(hash)
+    }
+
+    /// Constructs a new `HashTree` from the file at `path`.
+    /// Returns an `Error` value if the file could not be opened or read.
+    ///
+    /// With the `sparse` feature on Unix, holes of sparse files such as VM
+    /// images are found with `SEEK_DATA` and `SEEK_HOLE`, and blocks lying
+    /// entirely in a hole take the cached hash of a zero block instead of
+    /// being read. Otherwise, with the `uring` feature on Linux, many reads
+    /// are kept in flight through io_uring while blocks are hashed, falling
+    /// back to plain reads where io_uring is unavailable. On wasm32-wasi,
+    /// files are read with plain reads from the directories the runtime
+    /// grants access to.
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "read", level = "debug", skip_all, fields(path = %path.as_ref().display())))]
+    pub fn from_file<P: AsRef<Path>>(self, path: P) -> Result<Self, Error> {
+        #[cfg(all(feature = "direct", target_os = "linux"))]
+        if self.direct_io {
+            if let Some(file) = open_direct(path.as_ref())? {
+                return self.read_direct(file);
+            }
+        }
+        self.read_file(File::open(path)?)
+    }
+
+    /// Builds the tree over `file`, opened with `O_DIRECT`.
+    #[cfg(all(feature = "direct", target_os = "linux"))]
+    fn read_direct(mut self, file: File) -> Result<Self, Error> {
+        let len = file.metadata()?.len();
+        self.resolve_block_size(Some(len));
+        self.check_input_len(len)?;
+        match self.throttle.clone() {
+            Some(throttle) => self.from_data(throttle.reader(DirectReader::new(file))),
+            None => self.from_data(DirectReader::new(file)),
+        }
+    }
+
+    /// Builds the tree over the rest of `file`.
+    #[cfg(feature = "std")]
+    fn read_file(mut self, file: File) -> Result<Self, Error> {
+        let metadata = file.metadata()?;
+        let len = metadata.len();
+        self.resolve_block_size(Some(len));
+        self.check_input_len(len)?;
+        if let Some(throttle) = self.throttle.clone() {
+            #[cfg(feature = "zeroize")]
+            return self.from_data(throttle.reader(file));
+            #[cfg(not(feature = "zeroize"))]
+            return self.from_data(BufReader::new(throttle.reader(file)));
+        }
+        #[cfg(all(feature = "sparse", unix))]
+        if metadata.is_file() && self.block_size > 0 {
+            return self.read_sparse_file(file, len);
+        }
+        #[cfg(all(feature = "uring", target_os = "linux"))]
+        if metadata.is_file() && self.block_size > 0 && self.read_uring(&file, len)? {
+            self.finish()?;
+            return Ok(self);
+        }
+        // Blocks are read straight into the wiped block buffer, without a
+        // `BufReader` keeping copies of the contents
+        #[cfg(feature = "zeroize")]
+        return self.from_data(file);
+        #[cfg(not(feature = "zeroize"))]
+        return self.from_data(BufReader::new(file));
+    }
+
+    /// Hashes the rest of the regular `file` of `len` bytes into leaves,
+    /// reading ahead through io_uring. Returns `false`, having read nothing,
+    /// if io_uring is unavailable.
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    fn read_uring(&mut self, mut file: &File, len: u64) -> Result<bool, Error> {
+        let mut reader = match UringReader::new() {
+            Ok(reader) => reader,
+            Err(_) => return Ok(false),
+        };
+        let timer = Timer::start();
+        let start = file.stream_position()?;
+        reader.read(file, start, len, self.block_size, |block| {
+            let hash = self.hash_block(block)?;
+            self.push_leaf(hash)?;
+            self.record_leaf_len(block.len() as u64);
+            Ok(())
+        })?;
+        self.data_len = Some(len.saturating_sub(start));
+        self.phase_completed(Phase::Leaves, timer);
+        Ok(true)
+    }
+
+    /// Builds the tree over the rest of the regular `file` of `len` bytes,
+    /// only reading the blocks that overlap regions of data.
+    #[cfg(all(feature = "sparse", unix))]
+    fn read_sparse_file(mut self, mut file: File, len: u64) -> Result<Self, Error> {
+        let timer = Timer::start();
+        let block_size = self.block_size as u64;
+        let zeros = vec![0u8; self.block_size];
+        let mut zero_hash: Option<Vec<u8>> = None;
+        let mut buf = block_buf(self.block_size);
+        let mut offset = file.stream_position()?;
+        let start = offset;
+        let mut data = data_region(&file, offset, len)?;
+        while offset < len {
+            let end = (offset + block_size).min(len);
+            let block_len = (end - offset) as usize;
+            let hash = if end <= data.start {
+                // The block lies in a hole and reads as zeros
+                match &zero_hash {
+                    Some(hash) if block_len == self.block_size => {
+                        self.count_block(block_len);
+                        hash.clone()
+                    }
+                    _ => {
+                        let hash = self.hash_block(&zeros[..block_len])?;
+                        if block_len == self.block_size {
+                            zero_hash = Some(hash.clone());
+                        }
+                        hash
+                    }
+                }
+            } else {
+                buf.clear();
+                file.seek(SeekFrom::Start(offset))?;
+                (&file).take(block_len as u64).read_to_end(&mut buf)?;
+                if buf.len() != block_len {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "file shrank while it was hashed"));
+                }
+                self.hash_block(&buf)?
+            };
+            self.push_leaf(hash)?;
+            self.record_leaf_len(block_len as u64);
+            offset = end;
+            if offset >= data.end && offset < len {
+                data = data_region(&file, offset, len)?;
+            }
+        }
+        self.data_len = Some(len.saturating_sub(start));
+        self.phase_completed(Phase::Leaves, timer);
+
+        self.finish()?;
+        Ok(self)
+    }
+
+    /// Constructs a new `HashTree` over `data` split into blocks of
+    /// `block_size` bytes, the same tree as `from_data` builds. Does not need
+    /// `std::io` and is available in `no_std` builds.
+    /// Returns an `Error` value if the store could not be written.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let tree = HashTree::new(2).from_slice(b"abcde").unwrap();
+    /// assert_eq!(tree.num_blocks(), 3);
+    /// assert_eq!(tree, HashTree::new(2).from_vec(b"abcde".to_vec()).unwrap());
+    /// ```
+    pub fn from_slice(mut self, data: &[u8]) -> Result<Self, Error> {
+        self.resolve_block_size(Some(data.len() as u64));
+        self.check_input_len(data.len() as u64)?;
+        // Like reading blocks of zero bytes, a zero block size yields no blocks
+        let block_size = self.block_size;
+        let blocks = data.chunks(block_size.max(1)).take(if block_size == 0 { 0 } else { usize::MAX });
+        self.from_blocks(blocks)
+    }
+
+    /// Constructs a new `HashTree` over `data` split into blocks of
+    /// `block_size` bytes, see `from_slice`. With the `zeroize` feature,
+    /// `data` is wiped once hashed.
+    /// Returns an `Error` value if the store could not be written.
+    pub fn from_vec(self, data: Vec<u8>) -> Result<Self, Error> {
+        #[cfg(feature = "zeroize")]
+        let data = zeroize::Zeroizing::new(data);
+        self.from_slice(&data)
+    }
+
+    /// Constructs a new `HashTree` with one leaf per block yielded by
+    /// `blocks`. Unlike `from_data`, this does not need `std::io` and is
+    /// available in `no_std` builds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let data = b"abcdefg";
+    /// let tree = HashTree::new(2).from_blocks(data.chunks(2)).unwrap();
+    /// assert_eq!(tree, HashTree::new(2).from_data(&mut &data[..]).unwrap());
+    /// ```
+    pub fn from_blocks<I>(mut self, blocks: I) -> Result<Self, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let timer = Timer::start();
+        let batch_len = self.leaf_batch();
+        let mut batch = Vec::with_capacity(batch_len);
+        let mut data_len = 0;
+        let mut blocks = blocks.into_iter().peekable();
+        while blocks.peek().is_some() {
+            batch.extend(blocks.by_ref().take(batch_len));
+            let refs: Vec<&[u8]> = batch.iter().map(AsRef::as_ref).collect();
+            data_len += self.push_blocks(&refs)?;
+            batch.clear();
+        }
+        self.data_len = Some(data_len);
+        self.phase_completed(Phase::Leaves, timer);
+
+        self.finish()?;
+        Ok(self)
+    }
+
+    /// Constructs a new `HashTree` whose leaves are the given, already computed hashes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let leaves = vec![vec![0u8; 32], vec![1u8; 32]];
+    /// let tree = HashTree::new(4096).from_leaf_hashes(leaves).unwrap();
+    /// assert!(tree.num_blocks() == 2);
+    /// ```
+    pub fn from_leaf_hashes<I>(mut self, leaves: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        for hash in leaves {
+            self.push_leaf(hash)?;
+        }
+
+        self.finish()?;
+        Ok(self)
+    }
+
+    /// Constructs a new `HashTree` whose leaves are the given hashes of
+    /// blocks of the given lengths, which are recorded if enabled.
+    #[cfg(feature = "std")]
+    pub(crate) fn with_sized_leaves<I>(self, leaves: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = (Vec<u8>, u64)>,
+    {
+        self.with_known_parents(leaves, &[])
+    }
+
+    /// Like `with_sized_leaves`, but takes the first parents of every level
+    /// above the leaves from `known`, one vector per level, instead of
+    /// hashing them, see `from_shards`.
+    #[cfg(feature = "std")]
+    pub(crate) fn with_known_parents<I>(mut self, leaves: I, known: &[Vec<Vec<u8>>]) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = (Vec<u8>, u64)>,
+    {
+        let mut data_len = 0;
+        for (hash, len) in leaves {
+            self.push_leaf(hash)?;
+            self.record_leaf_len(len);
+            data_len += len;
+        }
+        self.data_len = Some(data_len);
+
+        self.finish_reusing(known)?;
+        Ok(self)
+    }
+
+    /// Builds the interior of the tree once all leaves have been pushed.
+    fn finish(&mut self) -> Result<(), Error> {
+        self.finish_reusing(&[])
+    }
+
+    /// Like `finish`, but takes the first parents of every level from
+    /// `known`, see `build`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "build", level = "debug", skip_all, fields(blocks = self.nodes.len())))]
+    fn finish_reusing(&mut self, known: &[Vec<Vec<u8>>]) -> Result<(), Error> {
+        // The store holds exactly the logical leaves here; `update` truncates
+        // the padding and interior of the previous build before appending
+        let timer = Timer::start();
+        self.num_blocks = self.nodes.len();
+        // Leaves pushed without a length leave the lengths incomplete
+        if self.leaf_ends.as_ref().is_some_and(|ends| ends.len() != self.num_blocks) {
+            self.leaf_ends = None;
+        }
+        self.build_above_leaves(known, known.first().map_or(0, Vec::len))?;
+        self.load_root();
+        if let Some(root) = &self.root {
+            self.notify(BuildEvent::RootComputed { hash: root });
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(nodes = self.nodes.len(), "built tree");
+        if let Some(metrics) = &self.metrics {
+            metrics.nodes_built(self.nodes.len() as u64);
+        }
+        self.phase_completed(Phase::Build, timer);
+        self.nodes.flush()
+    }
+
+    /// Hashes the contents of a block into a leaf, storing them in the block
+    /// store if there is one.
+    pub(crate) fn hash_block(&self, block: &[u8]) -> Result<Vec<u8>, Error> {
+        self.count_block(block.len());
+        let hash = self.hasher.hash_leaf(block);
+        #[cfg(feature = "std")]
+        if let Some(blocks) = &self.blocks {
+            blocks.put(&hash, block)?;
+        }
+        Ok(hash)
+    }
+
+    /// Hashes `blocks` into leaves with a single call to the hasher, so that
+    /// multi-buffer hashers can digest them together, and pushes the leaves.
+    /// Returns the number of bytes hashed.
+    pub(crate) fn push_blocks(&mut self, blocks: &[&[u8]]) -> Result<u64, Error> {
+        let hashes = self.hasher.hash_leaves(blocks);
+        self.push_hashed(blocks, hashes)
+    }
+
+    /// Pushes the leaves of `blocks`, whose hashes are `hashes`, and reports
+    /// the blocks to the metrics. Returns the number of bytes in the blocks.
+    fn push_hashed(&mut self, blocks: &[&[u8]], hashes: Vec<Vec<u8>>) -> Result<u64, Error> {
+        for block in blocks {
+            self.count_block(block.len());
+        }
+        let mut len = 0;
+        for (hash, block) in hashes.into_iter().zip(blocks) {
+            #[cfg(feature = "std")]
+            if let Some(store) = &self.blocks {
+                store.put(&hash, block)?;
+            }
+            self.push_leaf(hash)?;
+            self.record_leaf_len(block.len() as u64);
+            len += block.len() as u64;
+        }
+        Ok(len)
+    }
+
+    /// Builds the levels above the leaves pushed with `push_blocks`, which
+    /// cover `data_len` bytes of input, see `HashTreeWriter`.
+    #[cfg(feature = "std")]
+    pub(crate) fn finish_pushed(&mut self, data_len: u64) -> Result<(), Error> {
+        self.data_len = Some(data_len);
+        self.finish()
+    }
+
+    /// Returns the number of blocks handed to the hasher at once.
+    pub(crate) fn leaf_batch(&self) -> usize {
+        self.hasher.leaf_batch(self.block_size).max(1)
+    }
+
+    /// Reports a block of `len` bytes hashed into a leaf to the metrics.
+    fn count_block(&self, len: usize) {
+        if let Some(metrics) = &self.metrics {
+            metrics.bytes_hashed(len as u64);
+            metrics.blocks_processed(1);
+        }
+    }
+
+    /// Reports the end of `phase`, measured by `timer`, to the metrics.
+    fn phase_completed(&self, phase: Phase, timer: Timer) {
+        if let (Some(metrics), Some(elapsed)) = (&self.metrics, timer.elapsed()) {
+            metrics.phase_completed(phase, elapsed);
+        }
+    }
+
+    /// Caches the root, the last node of the store.
+    fn load_root(&mut self) {
+        self.root = self.nodes.len().checked_sub(1).and_then(|index| self.nodes.get(index));
+    }
+
+    /// Reports `event` to the observer, if any.
+    fn notify(&self, event: BuildEvent<'_>) {
+        if let Some(observer) = &self.observer {
+            observer.on_event(&event);
+        }
+    }
+
+    /// Appends a leaf to the store and reports it to the observer.
+    fn push_leaf(&mut self, hash: Vec<u8>) -> Result<(), Error> {
+        let index = self.nodes.len();
+        if self.max_leaves.is_some() || self.max_memory.is_some() {
+            self.check_limits(index as u64 + 1, hash.len())?;
+        }
+        if let Some(observer) = &self.observer {
+            observer.on_event(&BuildEvent::LeafHashed { index, hash: &hash });
+        }
+        if let Some(dedup) = self.dedup.as_mut() {
+            dedup.entry(hash.clone()).or_default().push(index as u64);
+        }
+        self.push(hash)
+    }
+
+    /// Appends a node to the store.
+    fn push(&mut self, hash: Vec<u8>) -> Result<(), Error> {
+        self.nodes.put(self.nodes.len(), hash)
+    }
+
+    /// Returns the hash of the node at `index`, which must exist.
+    fn node(&self, index: usize) -> Vec<u8> {
+        self.nodes.get(index).expect("node store is missing a node of the tree")
+    }
+
+    /// Pads the leaves, which must be the only nodes in the store, and builds
+    /// the levels above them, see `build`.
+    fn build_above_leaves(&mut self, known: &[Vec<Vec<u8>>], reusable: usize) -> Result<(), Error> {
+        if self.nodes.is_empty() {
+            return Ok(());
+        }
+        self.prepare_padding();
+        // A single leaf is its own root unless levels are padded with copies
+        if self.padding != Padding::Duplicate && self.num_blocks == 1 {
+            self.notify(BuildEvent::LevelCompleted { level: 0 });
+            return Ok(());
+        }
+        // If the number of blocks is not a multiple of the arity, we need to pad
+        // the last group of blocks in order to build the tree properly
+        self.pad_level(0, 0)?;
+        self.notify(BuildEvent::LevelCompleted { level: 0 });
+        self.build(0, 1, known, reusable)
+    }
+
+    /// Computes the zero hash of every level of the tree, or the empty
+    /// placeholder with `Padding::Promote`, if they are not known yet.
+    fn prepare_padding(&mut self) {
+        if self.zero_hashes().is_none() {
+            return;
+        }
+        let depth = self.levels().len();
+        while self.zero_hashes.len() < depth {
+            let hash = match self.zero_hashes.last() {
+                None if self.padding == Padding::Promote => Vec::new(),
+                None => vec![0u8; self.hasher.hash_leaf(&[]).len()],
+                Some(below) => self.hash_group(&vec![below.as_slice(); self.arity]),
+            };
+            self.zero_hashes.push(hash);
+        }
+    }
+
+    /// Returns the hashes padding every level from the leaves up, empty
+    /// placeholders with `Padding::Promote`, or `None` if levels are padded
+    /// with copies of their last node.
+    pub(crate) fn zero_hashes(&self) -> Option<&[Vec<u8>]> {
+        match self.padding {
+            Padding::Duplicate | Padding::DuplicateUnlessSingle => None,
+            Padding::Zero | Padding::Promote => Some(&self.zero_hashes),
+        }
+    }
+
+    /// Pads `level`, which starts at node `start` and must be the last level.
+    fn pad_level(&mut self, start: usize, level: usize) -> Result<(), Error> {
+        while !(self.nodes.len() - start).is_multiple_of(self.arity) {
+            let padding = match self.padding {
+                Padding::Duplicate | Padding::DuplicateUnlessSingle => self.node(self.nodes.len() - 1),
+                Padding::Zero | Padding::Promote => self.zero_hashes[level].clone(),
+            };
+            self.push(padding)?;
+        }
+        Ok(())
+    }
+
+    /// Hashes the children of a node into their parent.
+    fn hash_group(&self, children: &[&[u8]]) -> Vec<u8> {
+        hash_group(self.hasher.as_ref(), children)
+    }
+
+    /// Builds the levels above the level starting at node `below`, which must
+    /// be the last level, numbering the first one `level`. The first `reusable` parents are taken from `known`,
+    /// which holds already computed hashes for the next and the following
+    /// levels, instead of being rehashed.
+    fn build(&mut self, below: usize, level: usize, known: &[Vec<Vec<u8>>], reusable: usize) -> Result<(), Error> {
+        let start = self.nodes.len();
+        for (position, group) in (below..start).step_by(self.arity).enumerate() {
+            let hash = if position < reusable {
+                known[0][position].clone()
+            } else {
+                let children: Vec<Vec<u8>> = self.nodes.iter_range(group..group + self.arity).collect();
+                let hashes: Vec<&[u8]> = children.iter().map(|hash| hash.as_slice()).collect();
+                self.hash_group(&hashes)
+            };
+            self.push(hash)?;
+        }
+
+        if self.nodes.len() - start == 1 {
+            self.notify(BuildEvent::LevelCompleted { level });
+            return Ok(());
+        }
+
+        // Levels above the leaves are padded the same way as the leaves themselves
+        self.pad_level(start, level)?;
+        self.notify(BuildEvent::LevelCompleted { level });
+
+        let known = if known.is_empty() { known } else { &known[1..] };
+        self.build(start, level + 1, known, if known.is_empty() { 0 } else { reusable / self.arity })
+    }
+
+    /// Returns the node ranges of every level, from the leaves up to the root.
+    pub(crate) fn levels(&self) -> Vec<Range<usize>> {
+        if self.nodes.is_empty() {
+            return Vec::new();
+        }
+        self.level_ranges(self.num_blocks)
+    }
+
+    /// Returns the node ranges of every level of a tree over `num_blocks`
+    /// blocks with the parameters of `self`.
+    pub(crate) fn level_ranges(&self, num_blocks: usize) -> Vec<Range<usize>> {
+        let mut levels = Vec::new();
+        if num_blocks == 0 {
+            return levels;
+        }
+        let (mut start, mut len) = (0, num_blocks);
+        loop {
+            if len > 1 || (levels.is_empty() && self.padding == Padding::Duplicate) {
+                len = len.div_ceil(self.arity) * self.arity;
+            }
+            levels.push(start..start + len);
+            if len == 1 {
+                return levels;
+            }
+            start += len;
+            len /= self.arity;
+        }
+    }
+
+    /// Returns an inclusion proof for the block at `index`, or `None` if
+    /// there is no such block.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    /// use sha2::{Digest, Sha256};
+    ///
+    /// let data = b"abcdefg";
+    /// let tree = HashTree::new(1).from_data(&mut &data[..]).unwrap();
+    /// let proof = tree.proof(3).unwrap();
+    /// let root = tree.root_hash().unwrap().into_bytes();
+    /// assert!(proof.verify(&root, &Sha256::digest(b"d")));
+    /// ```
+    pub fn proof<I: Into<LeafIndex>>(&self, index: I) -> Option<Proof> {
+        let index = self.leaf_node(index.into())?;
+
+        let mut position = index;
+        let mut levels = Vec::new();
+        let all_levels = self.levels();
+        for level in &all_levels[..all_levels.len() - 1] {
+            let group = level.start + position / self.arity * self.arity;
+            let siblings = (group..group + self.arity)
+                .filter(|&i| i != level.start + position)
+                .map(|i| self.node(i))
+                .collect();
+            levels.push(ProofLevel { position: position % self.arity, siblings });
+            position /= self.arity;
+        }
+
+        Some(Proof { leaf_index: index as u64, levels })
+    }
+
+    /// Concatenates the blocks of `other` after those of `self` and returns
+    /// the tree over the combined data, e.g. to join the halves of a file that
+    /// were hashed by different workers. Interior nodes of `self` that only
+    /// cover its own blocks are reused; only the nodes along the seam and
+    /// above are hashed.
+    ///
+    /// For the result to match a tree built over the whole data, every block
+    /// of `self`, including its last one, must be complete.
+    /// Returns an `Error` value if the trees use different block sizes,
+    /// arities, hashers, salts or paddings.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let left = HashTree::new(2).from_data(&mut &b"abcdef"[..]).unwrap();
+    /// let right = HashTree::new(2).from_data(&mut &b"ghi"[..]).unwrap();
+    /// let whole = HashTree::new(2).from_data(&mut &b"abcdefghi"[..]).unwrap();
+    /// assert_eq!(left.merge(right).unwrap().root_hash(), whole.root_hash());
+    /// ```
+    pub fn merge<T: NodeStore>(mut self, other: HashTree<T>) -> Result<Self, Error> {
+        // The salt is part of the hasher and so of its fingerprint
+        if self.block_size != other.block_size
+            || self.arity != other.arity
+            || self.padding != other.padding
+            || fingerprint(self.hasher.as_ref()) != fingerprint(other.hasher.as_ref())
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "trees must use the same block size, arity, hasher and padding",
+            ));
+        }
+
+        let data_len = self.data_len.zip(other.data_len).map(|(a, b)| a + b);
+        self.replace_tail(self.num_blocks, other.leaf_hashes(), other.leaf_lengths(), data_len)?;
+        self.record_version();
+        Ok(self)
+    }
+
+    /// Replaces the leaves from index `keep` on with `leaves` and rebuilds
+    /// the levels above, reusing the parents that only cover kept leaves, so
+    /// only the right spine of the tree is rehashed. `lengths` holds the
+    /// length of every new leaf, if known, and `data_len` the length of the
+    /// data covered afterwards. The change is recorded as a `TreeOp`, if
+    /// enabled.
+    pub(crate) fn replace_tail<I>(
+        &mut self,
+        keep: usize,
+        leaves: I,
+        lengths: Option<Vec<u64>>,
+        data_len: Option<u64>,
+    ) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        #[cfg(feature = "std")]
+        let (leaves, op) = {
+            let leaves: Vec<Vec<u8>> = leaves.into_iter().collect();
+            let op = match self.ops {
+                Some(_) => TreeOp::splice(self.num_blocks, keep, leaves.clone(), lengths.clone(), data_len),
+                None => None,
+            };
+            (leaves, op)
+        };
+
+        // Hashes of every level above the leaves. Parent `i` at height `h`
+        // only covers kept blocks if `(i + 1) * arity^h <= keep`.
+        let known: Vec<Vec<Vec<u8>>> = self
+            .levels()
+            .iter()
+            .skip(1)
+            .map(|level| self.nodes.iter_range(level.clone()).collect())
+            .collect();
+        let reusable = keep.min(self.num_blocks) / self.arity;
+
+        if let Some(dedup) = self.dedup.as_mut() {
+            dedup.values_mut().for_each(|indices| indices.retain(|&index| index < keep as u64));
+            dedup.retain(|_, indices| !indices.is_empty());
+        }
+        self.nodes.truncate(keep)?;
+        for hash in leaves {
+            self.push_leaf(hash)?;
+        }
+        if let Some(ends) = self.leaf_ends.as_mut() {
+            ends.truncate(keep);
+        }
+        self.labels.split_off(&keep);
+        match lengths {
+            Some(lengths) => lengths.into_iter().for_each(|len| self.record_leaf_len(len)),
+            None => self.leaf_ends = None,
+        }
+        if self.leaf_ends.as_ref().is_some_and(|ends| ends.len() != self.nodes.len()) {
+            self.leaf_ends = None;
+        }
+
+        self.num_blocks = self.nodes.len();
+        self.data_len = data_len;
+        self.build_above_leaves(&known, if known.is_empty() { 0 } else { reusable })?;
+        self.load_root();
+        if let Some(root) = &self.root {
+            self.notify(BuildEvent::RootComputed { hash: root });
+        }
+        #[cfg(feature = "std")]
+        if let (Some(ops), Some(op)) = (self.ops.as_mut(), op) {
+            ops.push(op);
+        }
+        self.nodes.flush()
+    }
+
+    /// Splits the tree into one covering the blocks before `leaf_index` and one
+    /// covering the blocks from `leaf_index` on. The leaf hashes are reused, so
+    /// the data does not have to be read again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `leaf_index` is larger than the number of blocks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let tree = HashTree::new(1).from_data(&mut &b"abcdef"[..]).unwrap();
+    /// let (left, right) = tree.split_at(2);
+    /// assert_eq!(left, HashTree::new(1).from_data(&mut &b"ab"[..]).unwrap());
+    /// assert_eq!(right, HashTree::new(1).from_data(&mut &b"cdef"[..]).unwrap());
+    /// ```
+    pub fn split_at<I: Into<LeafIndex>>(&self, leaf_index: I) -> (HashTree, HashTree) {
+        let leaf_index = usize::try_from(leaf_index.into())
+            .ok()
+            .filter(|&index| index <= self.num_blocks)
+            .expect("leaf index out of bounds");
+        let leaves: Vec<Vec<u8>> = self.leaf_hashes().collect();
+        let (left, right) = leaves.split_at(leaf_index);
+        let lengths = self.leaf_lengths();
+        let (left_lengths, right_lengths) = match &lengths {
+            Some(lengths) => (Some(&lengths[..leaf_index]), Some(&lengths[leaf_index..])),
+            None => (None, None),
+        };
+        let (mut left, mut right) = (self.with_leaves(left, left_lengths), self.with_leaves(right, right_lengths));
+        for (&index, label) in &self.labels {
+            match index.checked_sub(leaf_index) {
+                Some(index) => right.labels.insert(index, label.clone()),
+                None => left.labels.insert(index, label.clone()),
+            };
+        }
+        (left, right)
+    }
+
+    /// Returns an empty tree with the same parameters as `self` built over
+    /// `leaves`, of the given `lengths` if known.
+    fn with_leaves(&self, leaves: &[Vec<u8>], lengths: Option<&[u64]>) -> HashTree {
+        let mut tree = HashTree::new(self.block_size).arity(self.arity).padding(self.padding);
+        if let Some(lengths) = lengths {
+            tree.leaf_ends = Some(Vec::new());
+            lengths.iter().for_each(|&len| tree.record_leaf_len(len));
+            tree.data_len = Some(lengths.iter().sum());
+        }
+        tree.hasher = self.hasher.clone();
+        tree.zero_hashes = self.zero_hashes.clone();
+        if self.dedup.is_some() {
+            tree.dedup = Some(BTreeMap::new());
+        }
+        #[cfg(feature = "std")]
+        {
+            tree.blocks = self.blocks.clone();
+        }
+        // Building from hashes in memory cannot fail
+        tree.from_leaf_hashes(leaves.to_vec()).unwrap()
+    }
+
+    /// Reads `data` in blocks of `block_size` bytes and queues them to be
+    /// appended after the existing blocks. The new data always starts a new
+    /// block. The tree is not modified until `update` is called.
+    /// Returns an `Error` value if `data` could not be read.
+    #[cfg(feature = "std")]
+    pub fn insert<R: Read>(&mut self, data: &mut R) -> Result<(), Error> {
+        self.resolve_block_size(None);
+        let mut chunker = FixedChunker::new(data, self.block_size);
+        let mut buf = block_buf(self.block_size);
+        while chunker.next_chunk(&mut buf)? {
+            let hash = self.hash_block(&buf)?;
+            self.check_limits((self.num_blocks + self.pending.len()) as u64 + 1, hash.len())?;
+            self.pending.push(hash);
+            if let Some(lengths) = self.pending_lens.as_mut() {
+                lengths.push(buf.len() as u64);
+            }
+        }
+        Ok(())
+    }
+
+    /// Queues an already computed leaf hash to be appended by `update`.
+    pub(crate) fn queue_leaf_hash(&mut self, hash: Vec<u8>) {
+        self.pending.push(hash);
+        self.pending_lens = None;
+    }
+
+    /// Recomputes the hashes and nodes of the `HashTree`. This method should be called
+    /// after you are done manually inserting data via the `insert` method.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let mut tree = HashTree::new(1).track_history().from_data(&mut &b"ab"[..]).unwrap();
+    /// tree.insert(&mut &b"cd"[..]).unwrap();
+    /// tree.update().unwrap();
+    /// assert_eq!(tree, HashTree::new(1).from_data(&mut &b"abcd"[..]).unwrap());
+    /// assert_eq!(tree.history()[0].leaf_count, 4);
+    /// ```
+    pub fn update(&mut self) -> Result<(), Error> {
+        // Drop the interior and the padding of the leaf level before appending
+        self.nodes.truncate(self.num_blocks)?;
+        let pending = core::mem::take(&mut self.pending);
+        #[cfg(feature = "std")]
+        let appended = self.ops.is_some().then(|| (self.num_blocks, pending.clone(), self.pending_lens.clone()));
+        for hash in pending {
+            self.push_leaf(hash)?;
+        }
+        let lengths = self.pending_lens.replace(Vec::new());
+        let added = lengths.as_ref().map(|lengths| lengths.iter().sum::<u64>());
+        if let Some(ends) = self.leaf_ends.as_mut() {
+            ends.truncate(self.num_blocks);
+        }
+        match lengths {
+            Some(lengths) => lengths.into_iter().for_each(|len| self.record_leaf_len(len)),
+            None => self.leaf_ends = None,
+        }
+        // Inserted data starts a new block, so after a partial block the
+        // leaves no longer cover contiguous data unless their lengths are
+        // recorded
+        let aligned = self.data_len.is_some_and(|len| self.block_size > 0 && len % self.block_size as u64 == 0);
+        self.data_len = match (self.data_len, added) {
+            (data_len, Some(0)) => data_len,
+            (Some(len), Some(added)) if aligned || self.leaf_ends.is_some() => Some(len + added),
+            _ => None,
+        };
+        self.finish()?;
+        #[cfg(feature = "std")]
+        if let Some((keep, leaves, lengths)) = appended {
+            let op = TreeOp::splice(keep, keep, leaves, lengths, self.data_len);
+            self.ops.as_mut().unwrap().extend(op);
+        }
+        self.record_version();
+        Ok(())
+    }
+
+    /// Hashes the data appended to `reader` since the tree was built and
+    /// appends it, e.g. for a log file that only ever grows. Hashing picks up
+    /// at the recorded data length, see `data_len`: a partial last block is
+    /// read again and rehashed, and only the parents on the right spine of
+    /// the tree are recomputed. Returns the number of bytes appended.
+    /// Returns an `Error` value if the data length is unknown, the tree was
+    /// not built from fixed-size blocks or `reader` could not be read.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use hashtree::HashTree;
+    ///
+    /// let mut log = b"first entry\n".to_vec();
+    /// let mut tree = HashTree::new(8).from_slice(&log).unwrap();
+    /// log.extend_from_slice(b"second entry\n");
+    /// assert_eq!(tree.extend_from_reader(Cursor::new(&log)).unwrap(), 13);
+    /// assert_eq!(tree, HashTree::new(8).from_slice(&log).unwrap());
+    /// assert_eq!(tree.data_len(), Some(25));
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn extend_from_reader<R: Read + Seek>(&mut self, mut reader: R) -> Result<u64, Error> {
+        let data_len = self
+            .data_len
+            .ok_or_else(|| Error::new(ErrorKind::Unsupported, "the length of the data the tree was built from is unknown"))?;
+        let block_size = self.block_size as u64;
+        let lengths = self.leaf_lengths().unwrap_or_default();
+        let uneven = lengths.iter().rev().skip(1).any(|&len| len != block_size);
+        if block_size == 0 || data_len.div_ceil(block_size) != self.num_blocks() || uneven {
+            return Err(Error::new(ErrorKind::Unsupported, "the tree was not built from fixed-size blocks"));
+        }
+
+        // Rehash a partial last block together with the data appended to it
+        let keep = (data_len / block_size) as usize;
+        let start = keep as u64 * block_size;
+        reader.seek(SeekFrom::Start(start))?;
+        let mut leaves = Vec::new();
+        let mut lengths = Vec::new();
+        let mut len = start;
+        let mut chunker = FixedChunker::new(reader, self.block_size);
+        let mut buf = block_buf(self.block_size);
+        while chunker.next_chunk(&mut buf)? {
+            leaves.push(self.hash_block(&buf)?);
+            lengths.push(buf.len() as u64);
+            len += buf.len() as u64;
+        }
+        if len < data_len {
+            return Err(Error::new(ErrorKind::InvalidInput, "the data is shorter than when the tree was built"));
+        }
+        if len > data_len {
+            self.replace_tail(keep, leaves, Some(lengths), Some(len))?;
+            self.record_version();
+        }
+        Ok(len - data_len)
+    }
+
+    /// Removes the blocks from index `num_blocks` on, rehashing only the
+    /// right spine of the tree. The data length shrinks accordingly if the
+    /// leaves can be located in the data, see `leaf_range`, and is forgotten
+    /// otherwise.
+    /// Returns an `Error` value if the tree has fewer blocks or the store
+    /// could not be written.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let mut tree = HashTree::new(4).from_slice(b"first entry, second entry").unwrap();
+    /// tree.truncate(3).unwrap();
+    /// assert_eq!(tree, HashTree::new(4).from_slice(b"first entry,").unwrap());
+    /// assert_eq!(tree.data_len(), Some(12));
+    /// ```
+    pub fn truncate(&mut self, num_blocks: u64) -> Result<(), Error> {
+        if num_blocks > self.num_blocks() {
+            return Err(Error::new(ErrorKind::InvalidInput, "cannot truncate a tree to more blocks than it has"));
+        }
+        let num_blocks = to_usize(num_blocks)?;
+        let data_len = match num_blocks.checked_sub(1) {
+            Some(last) => self.located_len().map(|len| self.leaf_extent(len, last).end),
+            None => Some(0),
+        };
+        self.replace_tail(num_blocks, Vec::new(), Some(Vec::new()), data_len)?;
+        self.record_version();
+        Ok(())
+    }
+
+    /// Returns the number of bytes hashed into the leaves, if the tree was
+    /// built from data rather than from leaf hashes. This is where
+    /// `extend_from_reader` picks up.
+    pub fn data_len(&self) -> Option<u64> {
+        self.data_len
+    }
+
+    /// Sets the number of bytes hashed into the leaves.
+    pub(crate) fn set_data_len(&mut self, data_len: Option<u64>) {
+        self.data_len = data_len;
+    }
+
+    /// Records the length of every leaf as it is hashed, so the data behind
+    /// leaves of different sizes, e.g. from a content-defined `Chunker`, can
+    /// still be located by `leaf_range` and `leaf_at_offset`, and slices and
+    /// layouts of the data can still be read and verified. The lengths are
+    /// kept by `write_to`, but are lost when a leaf is added without one,
+    /// e.g. by `from_leaf_hashes`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::{HashTree, LeafIndex};
+    ///
+    /// let chunks = [&b"GET /\n"[..], b"POST /login\n", b"GET /logout\n"];
+    /// let tree = HashTree::new(0).record_lengths().from_blocks(chunks).unwrap();
+    /// assert_eq!(tree.leaf_lengths(), Some(vec![6, 12, 12]));
+    /// assert_eq!(tree.leaf_at_offset(9), Some(LeafIndex(1)));
+    /// assert_eq!(tree.leaf_range(2), Some(18..30));
+    /// ```
+    pub fn record_lengths(mut self) -> Self {
+        self.leaf_ends.get_or_insert_with(Vec::new);
+        self
+    }
+
+    /// Appends a leaf of `len` bytes to the recorded lengths, if enabled.
+    fn record_leaf_len(&mut self, len: u64) {
+        if let Some(ends) = self.leaf_ends.as_mut() {
+            let end = ends.last().map_or(len, |last| last + len);
+            ends.push(end);
+        }
+    }
+
+    /// Returns the end offset of every leaf within the data, if the leaf
+    /// lengths were recorded.
+    pub(crate) fn leaf_ends(&self) -> Option<&[u64]> {
+        self.leaf_ends.as_deref()
+    }
+
+    /// Returns the length of every leaf, if they were recorded, see
+    /// `record_lengths`.
+    pub fn leaf_lengths(&self) -> Option<Vec<u64>> {
+        let ends = self.leaf_ends()?;
+        Some(ends.iter().scan(0, |start, &end| Some(end - core::mem::replace(start, end))).collect())
+    }
+
+    /// Returns the byte range of the data covered by the leaf at `index`,
+    /// or `None` if there is no such leaf or it cannot be located. Leaves
+    /// are located by their recorded lengths, or else as fixed-size blocks
+    /// of data of known length, see `data_len`.
+    pub fn leaf_range<I: Into<LeafIndex>>(&self, index: I) -> Option<Range<u64>> {
+        let index = self.leaf_node(index.into())?;
+        let data_len = self.located_len()?;
+        Some(self.leaf_extent(data_len, index))
+    }
+
+    /// Returns the index of the leaf covering the byte at `offset` of the
+    /// data, or `None` if `offset` is past the end of the data or the leaves
+    /// cannot be located, see `leaf_range`.
+    pub fn leaf_at_offset(&self, offset: u64) -> Option<LeafIndex> {
+        let data_len = self.located_len()?;
+        (offset < data_len).then(|| LeafIndex::from(self.leaf_index_at(offset)))
+    }
+
+    /// Returns the length of the data if the leaves can be located in it.
+    fn located_len(&self) -> Option<u64> {
+        match self.leaf_ends() {
+            Some(ends) => Some(ends.last().copied().unwrap_or(0)),
+            None => self.data_len.filter(|&data_len| self.covers_len(data_len)),
+        }
+    }
+
+    /// Returns `true` if the leaves cover exactly `data_len` bytes of data:
+    /// by their recorded lengths, or else as blocks of `block_size` bytes.
+    pub(crate) fn covers_len(&self, data_len: u64) -> bool {
+        match self.leaf_ends() {
+            Some(ends) => ends.last().copied().unwrap_or(0) == data_len,
+            None => self.block_size > 0 && data_len.div_ceil(self.block_size as u64) == self.num_blocks(),
+        }
+    }
+
+    /// Returns the byte range of the leaf at `index` in `data_len` bytes of
+    /// data, by the recorded lengths or else as a block of `block_size`
+    /// bytes. Without recorded lengths, the block size must not be zero.
+    pub(crate) fn leaf_extent(&self, data_len: u64, index: usize) -> Range<u64> {
+        match self.leaf_ends() {
+            Some(ends) => index.checked_sub(1).map_or(0, |previous| ends[previous])..ends[index],
+            None => {
+                let start = index as u64 * self.block_size as u64;
+                start..data_len.min(start.saturating_add(self.block_size as u64))
+            }
+        }
+    }
+
+    /// Returns the index of the leaf covering the byte at `offset`, which
+    /// must be within the data, see `leaf_extent`.
+    pub(crate) fn leaf_index_at(&self, offset: u64) -> usize {
+        match self.leaf_ends() {
+            Some(ends) => ends.partition_point(|&end| end <= offset),
+            None => (offset / self.block_size as u64) as usize,
+        }
+    }
+
+    /// Counts an update of the tree, recording a `RootSnapshot` if enabled,
+    /// and reports the new root to the callbacks of `on_root_changed`.
+    pub(crate) fn record_version(&mut self) {
+        self.version += 1;
+        #[cfg(feature = "std")]
+        if let Some(history) = self.history.as_mut() {
+            let root_hash = self.root.clone().map(RootHash::new);
+            history.push(RootSnapshot {
+                version: self.version,
+                root_hash,
+                leaf_count: self.num_blocks as u64,
+                timestamp: SystemTime::now(),
+            });
+        }
+        if let Some(root) = &self.root {
+            self.root_listeners.notify(root, self.version);
+        }
+    }
+
+    /// Enables recording a `RootSnapshot` on every `update`.
+    #[cfg(feature = "std")]
+    pub fn track_history(mut self) -> Self {
+        self.history.get_or_insert_with(Vec::new);
+        self
+    }
+
+    /// Returns the snapshots recorded by `update`, oldest first. This is empty
+    /// unless `track_history` was enabled.
+    #[cfg(feature = "std")]
+    pub fn history(&self) -> &[RootSnapshot] {
+        self.history.as_deref().unwrap_or(&[])
+    }
+
+    /// Enables recording every change of the leaves as a `TreeOp`, so that
+    /// replicas can follow the tree with `apply_ops` instead of fetching it
+    /// again after every change.
+    #[cfg(feature = "std")]
+    pub fn record_ops(mut self) -> Self {
+        self.ops.get_or_insert_with(Vec::new);
+        self
+    }
+
+    /// Returns the operations recorded since the last `take_ops`, oldest
+    /// first. This is empty unless `record_ops` was enabled.
+    #[cfg(feature = "std")]
+    pub fn ops(&self) -> &[TreeOp] {
+        self.ops.as_deref().unwrap_or(&[])
+    }
+
+    /// Removes and returns the recorded operations, e.g. once they were sent
+    /// to the replicas.
+    #[cfg(feature = "std")]
+    pub fn take_ops(&mut self) -> Vec<TreeOp> {
+        self.ops.as_mut().map(core::mem::take).unwrap_or_default()
+    }
+
+    /// Enables an index from every leaf hash to the indices of the blocks
+    /// with that content, kept up to date as leaves are added. The index
+    /// holds a copy of every distinct leaf hash.
+    pub fn dedup_index(mut self) -> Self {
+        self.dedup.get_or_insert_with(BTreeMap::new);
+        self
+    }
+
+    /// Returns the indices of the blocks whose leaf hash is `hash`, in
+    /// ascending order. This is empty unless `dedup_index` was enabled.
+    pub fn blocks_with_hash(&self, hash: &[u8]) -> &[u64] {
+        self.dedup.as_ref().and_then(|dedup| dedup.get(hash)).map_or(&[], |indices| indices.as_slice())
+    }
+
+    /// Returns every leaf hash shared by more than one block together with
+    /// the indices of those blocks, ordered by the first block. This is
+    /// empty unless `dedup_index` was enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let tree = HashTree::new(2).dedup_index().from_data(&mut &b"abcdabab"[..]).unwrap();
+    /// let duplicates = tree.duplicate_blocks();
+    /// assert_eq!(duplicates.len(), 1);
+    /// assert_eq!(duplicates[0].1, &[0, 2, 3]);
+    /// assert_eq!(tree.blocks_with_hash(duplicates[0].0), &[0, 2, 3]);
+    /// ```
+    pub fn duplicate_blocks(&self) -> Vec<(&[u8], &[u64])> {
+        let mut duplicates: Vec<(&[u8], &[u64])> = self
+            .dedup
+            .iter()
+            .flatten()
+            .filter(|(_, indices)| indices.len() > 1)
+            .map(|(hash, indices)| (hash.as_slice(), indices.as_slice()))
+            .collect();
+        duplicates.sort_by_key(|(_, indices)| indices[0]);
+        duplicates
+    }
+
+    /// Labels the leaf at `index`, e.g. with the name of the record it was
+    /// hashed from, to tell leaves apart in `leaves`, the `Display` output
+    /// and `to_dot`. Unlabeled leaves show as `B0`, `B1` and so on there.
+    /// Returns `false` if there is no such leaf.
+    pub fn set_label<I: Into<LeafIndex>, L: Into<String>>(&mut self, index: I, label: L) -> bool {
+        match self.leaf_node(index.into()) {
+            Some(index) => {
+                self.labels.insert(index, label.into());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Labels the leaves in order with `labels`, see `set_label`. Labels
+    /// past the last leaf are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let tree = HashTree::new(1).from_slice(b"abc").unwrap().with_labels(["first", "second"]);
+    /// let labels: Vec<_> = tree.leaves().map(|leaf| leaf.label).collect();
+    /// assert_eq!(labels, [Some("first"), Some("second"), None]);
+    /// ```
+    pub fn with_labels<I>(mut self, labels: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        for (index, label) in labels.into_iter().take(self.num_blocks).enumerate() {
+            self.labels.insert(index, label.into());
+        }
+        self
+    }
+
+    /// Returns the label of the leaf at `index`, if it has one.
+    pub fn label<I: Into<LeafIndex>>(&self, index: I) -> Option<&str> {
+        self.labels.get(&usize::try_from(index.into()).ok()?).map(String::as_str)
+    }
+
+    /// Returns the hash of the leaf at `index`, or `None` if there is no
+    /// such leaf.
+    pub fn leaf_hash<I: Into<LeafIndex>>(&self, index: I) -> Option<Vec<u8>> {
+        self.nodes.get(self.leaf_node(index.into())?)
+    }
+
+    /// Returns the hash of the node at `index` in storage order, padding
+    /// included, or `None` if there is no such node.
+    pub fn node_at(&self, index: NodeIndex) -> Option<Vec<u8>> {
+        self.nodes.get(index.0)
+    }
+
+    /// Returns the index of the leaf stored at node `index`, or `None` if the
+    /// node is an interior node or padding.
+    pub fn leaf_of(&self, index: NodeIndex) -> Option<LeafIndex> {
+        (index.0 < self.num_blocks).then_some(LeafIndex(index.0 as u64))
+    }
+
+    /// Returns the storage index of the leaf at `index`, or `None` if there
+    /// is no such leaf.
+    pub(crate) fn leaf_node(&self, index: LeafIndex) -> Option<usize> {
+        usize::try_from(index).ok().filter(|&index| index < self.num_blocks)
+    }
+
+    /// Returns the leaves, excluding padding, in order.
+    pub fn leaves(&self) -> impl Iterator<Item = Leaf<'_>> + '_ {
+        self.leaf_hashes().enumerate().map(move |(index, hash)| Leaf { index: LeafIndex::from(index), hash, label: self.label(index) })
+    }
+
+    /// Returns the contents of the block at `index` from the block store.
+    /// The contents are checked against the leaf hash before being returned.
+    /// Returns an `Error` value if there is no such block or block store, the
+    /// store does not hold the block, or its contents do not match.
+    #[cfg(feature = "std")]
+    pub fn get_block<I: Into<LeafIndex>>(&self, index: I) -> Result<Vec<u8>, Error> {
+        let index = to_usize(index.into().0)?;
+        let blocks = self
+            .blocks
+            .as_ref()
+            .ok_or_else(|| Error::new(ErrorKind::Unsupported, "the tree has no block store"))?;
+        if index >= self.num_blocks {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("block index {} out of range", index)));
+        }
+        let hash = self.node(index);
+        let data = blocks
+            .get(&hash)?
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("block {} is not in the block store", index)))?;
+        if !constant_time_eq(&self.hasher.hash_leaf(&data), &hash) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("stored block {} does not match its hash", index)));
+        }
+        Ok(data)
+    }
+
+    /// Reassembles the data the tree was built from by writing every block
+    /// from the block store to `writer`, in order.
+    /// Returns an `Error` value if a block cannot be read, see `get_block`,
+    /// or writing fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::{HashTree, MemoryBlockStore};
+    ///
+    /// let tree = HashTree::new(3).block_store(MemoryBlockStore::new()).from_data(&mut &b"abcabcab"[..]).unwrap();
+    /// let mut data = Vec::new();
+    /// tree.write_blocks(&mut data).unwrap();
+    /// assert_eq!(data, b"abcabcab");
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn write_blocks<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        for index in 0..self.num_blocks {
+            writer.write_all(&self.get_block(index)?)?;
+        }
+        writer.flush()
+    }
+
+    /// Returns the number of updates applied to the tree.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns `true` if the `HashTree` is empty and `false` otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #![allow(dead_code)]
+    /// use hashtree::HashTree;
+    /// 
+    /// const BLOCK_SIZE: usize = 4096;
+    ///
+    /// let tree = HashTree::new(BLOCK_SIZE);
+    /// assert!(tree.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        if self.nodes.len() == 0 {
+            return true
+        }
+        false
+    }
+
+    /// Returns the root hash of the `HashTree`, or `None` if it is empty.
+    pub fn root_hash(&self) -> Option<RootHash> {
+        self.root().map(RootHash::new)
+    }
+
+    /// Returns the raw bytes of the root hash, or `None` if the tree is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let tree = HashTree::new(1).from_data(&mut &b"ab"[..]).unwrap();
+    /// assert_eq!(tree.root_hash_bytes().unwrap(), tree.root_hash().unwrap().as_bytes());
+    /// assert!(tree.root_hash_array().is_some());
+    /// ```
+    pub fn root_hash_bytes(&self) -> Option<&[u8]> {
+        self.root.as_deref()
+    }
+
+    /// Returns the root hash as an array, or `None` if the tree is empty or
+    /// its hasher does not produce 32-byte digests, as SHA-256 does.
+    pub fn root_hash_array(&self) -> Option<[u8; 32]> {
+        let root = self.root.as_deref()?;
+        if root.len() != 32 {
+            return None;
+        }
+        let mut array = [0u8; 32];
+        array.copy_from_slice(root);
+        Some(array)
+    }
+
+    /// Returns the raw bytes of the root hash.
+    pub(crate) fn root(&self) -> Option<Vec<u8>> {
+        self.root.clone()
+    }
+
+    /// Returns the hash of the node at `index` in storage order.
+    pub(crate) fn node_hash(&self, index: usize) -> Vec<u8> {
+        self.node(index)
+    }
+
+    /// Returns the hashes of the leaves, excluding any padding.
+    pub(crate) fn leaf_hashes(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        self.nodes.iter_range(0..self.num_blocks)
+    }
+
+    /// Returns the store holding the nodes of the tree.
+    pub fn store(&self) -> &S {
+        &self.nodes
+    }
+
+    /// Releases memory held beyond what the tree needs, such as the spare
+    /// capacity left in the node store and working buffers by appending.
+    /// Worth calling on trees that are kept around once built.
+    /// Returns an `Error` value if the store could not be shrunk.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let mut tree = HashTree::with_capacity(1, 1024).from_data(&mut &b"abc"[..]).unwrap();
+    /// tree.shrink_to_fit().unwrap();
+    /// assert_eq!(tree.store().capacity(), tree.store().len());
+    /// ```
+    pub fn shrink_to_fit(&mut self) -> Result<(), Error> {
+        self.nodes.shrink_to_fit()?;
+        self.pending.shrink_to_fit();
+        self.pending_lens.iter_mut().for_each(Vec::shrink_to_fit);
+        self.leaf_ends.iter_mut().for_each(Vec::shrink_to_fit);
+        if let Some(dedup) = self.dedup.as_mut() {
+            dedup.values_mut().for_each(Vec::shrink_to_fit);
+        }
+        #[cfg(feature = "std")]
+        self.history.iter_mut().for_each(Vec::shrink_to_fit);
+        Ok(())
+    }
+
+    /// Returns the ranges of block indices whose contents differ between
+    /// `self` and `other`. Blocks present in only one of the trees count as
+    /// differing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let a = HashTree::new(1).from_data(&mut &b"abcdef"[..]).unwrap();
+    /// let b = HashTree::new(1).from_data(&mut &b"abXYef!"[..]).unwrap();
+    /// assert_eq!(a.diff(&b), vec![2..4, 6..7]);
+    /// ```
+    pub fn diff<T: NodeStore>(&self, other: &HashTree<T>) -> Vec<Range<u64>> {
+        let mut ranges: Vec<Range<u64>> = Vec::new();
+        let len = self.num_blocks.max(other.num_blocks);
+        let mine = self.leaf_hashes().map(Some).chain(core::iter::repeat(None));
+        let theirs = other.leaf_hashes().map(Some).chain(core::iter::repeat(None));
+
+        for (index, (a, b)) in mine.zip(theirs).take(len).enumerate() {
+            if a == b {
+                continue;
+            }
+            let index = index as u64;
+            match ranges.last_mut() {
+                Some(range) if range.end == index => range.end += 1,
+                _ => ranges.push(index..index + 1),
+            }
+        }
+        ranges
+    }
+
+    /// Returns the indices of the leaves that differ between `self` and
+    /// `other`, see `diff`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::{HashTree, LeafIndex};
+    ///
+    /// let a = HashTree::new(1).from_slice(b"abcd").unwrap();
+    /// let b = HashTree::new(1).from_slice(b"aXcY").unwrap();
+    /// assert_eq!(a.changed_leaves(&b), [LeafIndex(1), LeafIndex(3)]);
+    /// ```
+    pub fn changed_leaves<T: NodeStore>(&self, other: &HashTree<T>) -> Vec<LeafIndex> {
+        self.diff(other).into_iter().flatten().map(LeafIndex).collect()
+    }
+
+    /// Returns `true` if both trees commit to the same data, i.e. have the same
+    /// root. This is what `==` compares; trees with different block sizes or
+    /// arities can never share a root unless the hash collides. The roots
+    /// are compared in constant time.
+    pub fn same_commitment<T: NodeStore>(&self, other: &HashTree<T>) -> bool {
+        match (&self.root, &other.root) {
+            (Some(a), Some(b)) => constant_time_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+
+    /// Returns `true` if both trees are the same tree: equal block size,
+    /// arity and number of blocks, and equal hashes for every node.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let a = HashTree::new(1).from_leaf_hashes(vec![vec![1u8; 32]]).unwrap();
+    /// let b = HashTree::new(4096).from_leaf_hashes(vec![vec![1u8; 32]]).unwrap();
+    /// assert!(a == b);
+    /// assert!(a.same_commitment(&b));
+    /// assert!(!a.eq_structure(&b));
+    /// ```
+    pub fn eq_structure<T: NodeStore>(&self, other: &HashTree<T>) -> bool {
+        self.block_size == other.block_size
+            && self.arity == other.arity
+            && self.num_blocks == other.num_blocks
+            && self.nodes.len() == other.nodes.len()
+            && self.nodes.iter_nodes().eq(other.nodes.iter_nodes())
+    }
+
+    /// Returns the size of the blocks used to construct the `HashTree`.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Returns the number of nodes in the `HashTree`, including the copies
+    /// padding every level to a multiple of the arity.
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns the number of blocks that were used to construct the `HashTree`.
+    pub fn num_blocks(&self) -> u64 {
+        self.num_blocks as u64
+    }
+
+    /// Returns the number of leaves hashed from blocks, excluding padding.
+    /// This is always `num_blocks`.
+    pub fn logical_leaves(&self) -> u64 {
+        self.num_blocks()
+    }
+
+    /// Returns the number of leaves in the store, which bounds their storage
+    /// indices.
+    pub(crate) fn stored_leaves(&self) -> usize {
+        self.num_blocks
+    }
+
+    /// Returns the number of nodes on the leaf level, including the padding
+    /// that fills it to a multiple of the arity: copies of the last leaf with
+    /// `Padding::Duplicate` and `Padding::DuplicateUnlessSingle`, zero hashes
+    /// with `Padding::Zero` and empty placeholders with `Padding::Promote`.
+    /// Only `Padding::Duplicate` pads a single leaf; with the others it is its
+    /// own root and the level holds just that leaf.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hashtree::HashTree;
+    ///
+    /// let mut tree = HashTree::new(1).arity(4).from_data(&mut &b"abcde"[..]).unwrap();
+    /// assert_eq!((tree.logical_leaves(), tree.padded_leaves()), (5, 8));
+    /// tree.insert(&mut &b"f"[..]).unwrap();
+    /// tree.update().unwrap();
+    /// assert_eq!((tree.logical_leaves(), tree.padded_leaves()), (6, 8));
+    /// ```
+    pub fn padded_leaves(&self) -> u64 {
+        self.levels().first().map_or(0, |leaves| leaves.len() as u64)
+    }
+}
+
+/// A leaf of a `HashTree`, as returned by `HashTree::leaves`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leaf<'a> {
+    /// Index of the leaf.
+    pub index: LeafIndex,
+    /// Hash of the leaf.
+    pub hash: Vec<u8>,
+    /// Label of the leaf, see `HashTree::set_label`.
+    pub label: Option<&'a str>,
+}
+
+/// An iterator over the nodes of a `HashTree` with their positions, see
+/// `HashTree::iter`.
+#[derive(Debug, Clone)]
+pub struct Nodes<'a> {
+    nodes: &'a [Vec<u8>],
+    levels: Vec<Range<usize>>,
+    level: usize,
+    next: usize,
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = (usize, usize, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let range = self.levels.get(self.level)?;
+        let item = (self.level, self.next - range.start, &self.nodes[self.next][..]);
+        self.next += 1;
+        if self.next == range.end {
+            self.level += 1;
+        }
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.levels.last().map_or(0, |root| root.end) - self.next;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Nodes<'_> {}
+
+/// Compares the roots only, see `HashTree::same_commitment`. Use
+/// `HashTree::eq_structure` to also compare the parameters and every node.
+impl<S: NodeStore, T: NodeStore> PartialEq<HashTree<T>> for HashTree<S> {
+    fn eq(&self, other: &HashTree<T>) -> bool {
+        self.same_commitment(other)
+    }
+}
+
+impl<S: NodeStore> Eq for HashTree<S> {}
+
+/// Hashes the root only. The parameters are left out so that trees equal
+/// under `==`, which compares roots, always hash alike.
+impl<S: NodeStore> core::hash::Hash for HashTree<S> {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.root.hash(state);
+    }
+}
+
+/// The error of a double-buffered build whose hashing thread stopped early.
+#[cfg(all(feature = "std", not(all(target_family = "wasm", not(target_feature = "atomics")))))]
+fn helper_failed() -> Error {
+    Error::other("hashing thread stopped")
 }
@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::os::unix::io::AsRawFd;
+
+use io_uring::{opcode, types, IoUring};
+
+use crate::hasher::{block_buf, BlockBuf};
+
+/// Number of reads kept in flight.
+const QUEUE_DEPTH: usize = 16;
+/// Size of every read, rounded down to a multiple of the block size.
+const READ_SIZE: usize = 256 * 1024;
+
+/// Reads a file through io_uring, keeping up to `QUEUE_DEPTH` reads in
+/// flight while the blocks already read are handed out in order.
+pub(crate) struct UringReader {
+    ring: IoUring,
+    slots: Vec<Slot>,
+    in_flight: usize,
+}
+
+/// A buffer being filled by a read of `len` bytes at `offset`.
+struct Slot {
+    buf: BlockBuf,
+    offset: u64,
+    len: usize,
+    filled: usize,
+}
+
+impl UringReader {
+    /// Sets up the ring. Fails if io_uring is unavailable, e.g. on older
+    /// kernels or when it is disabled by a seccomp policy.
+    pub(crate) fn new() -> Result<Self, Error> {
+        let ring = IoUring::new(QUEUE_DEPTH as u32)?;
+        Ok(Self { ring, slots: Vec::new(), in_flight: 0 })
+    }
+
+    /// Reads `file` from `start` to `len` and calls `on_block` with every
+    /// block of `block_size` bytes, the last one possibly shorter, in order.
+    pub(crate) fn read<F>(&mut self, file: &File, start: u64, len: u64, block_size: usize, on_block: F) -> Result<(), Error>
+    where
+        F: FnMut(&[u8]) -> Result<(), Error>,
+    {
+        let result = self.read_all(file, start, len, block_size, on_block);
+        // The kernel may still write into the buffers of reads in flight
+        while self.in_flight > 0 {
+            self.ring.submit_and_wait(1)?;
+            self.in_flight -= self.ring.completion().count();
+        }
+        result
+    }
+
+    fn read_all<F>(&mut self, file: &File, start: u64, len: u64, block_size: usize, mut on_block: F) -> Result<(), Error>
+    where
+        F: FnMut(&[u8]) -> Result<(), Error>,
+    {
+        let read_size = (READ_SIZE / block_size).max(1) * block_size;
+        let fd = types::Fd(file.as_raw_fd());
+        self.slots = (0..QUEUE_DEPTH)
+            .map(|_| {
+                let mut buf = block_buf(read_size);
+                buf.resize(read_size, 0);
+                Slot { buf, offset: 0, len: 0, filled: 0 }
+            })
+            .collect();
+
+        // Slots in the order of their offsets, the next one to hand out first
+        let mut order = VecDeque::new();
+        let mut next = start;
+        for index in 0..QUEUE_DEPTH {
+            if next >= len {
+                break;
+            }
+            next = self.assign(fd, index, next, len)?;
+            order.push_back(index);
+        }
+
+        while let Some(&index) = order.front() {
+            while self.slots[index].filled < self.slots[index].len {
+                self.wait(fd)?;
+            }
+            let slot = &self.slots[index];
+            for block in slot.buf[..slot.len].chunks(block_size) {
+                on_block(block)?;
+            }
+            order.pop_front();
+            if next < len {
+                next = self.assign(fd, index, next, len)?;
+                order.push_back(index);
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts reading the next chunk, from `offset` on, into slot `index`.
+    /// Returns the offset following the chunk.
+    fn assign(&mut self, fd: types::Fd, index: usize, offset: u64, len: u64) -> Result<u64, Error> {
+        let slot = &mut self.slots[index];
+        slot.offset = offset;
+        slot.len = (len - offset).min(slot.buf.len() as u64) as usize;
+        slot.filled = 0;
+        self.submit(fd, index)?;
+        Ok(offset + self.slots[index].len as u64)
+    }
+
+    /// Queues a read of the unfilled part of slot `index`.
+    fn submit(&mut self, fd: types::Fd, index: usize) -> Result<(), Error> {
+        let slot = &mut self.slots[index];
+        let rest = &mut slot.buf[slot.filled..slot.len];
+        let entry = opcode::Read::new(fd, rest.as_mut_ptr(), rest.len() as u32)
+            .offset(slot.offset + slot.filled as u64)
+            .build()
+            .user_data(index as u64);
+        // SAFETY: the buffer is owned by `self.slots`, which is neither
+        // resized nor dropped before every read in flight has completed.
+        unsafe { self.ring.submission().push(&entry) }
+            .map_err(|_| Error::other("io_uring submission queue is full"))?;
+        self.in_flight += 1;
+        self.ring.submit()?;
+        Ok(())
+    }
+
+    /// Waits for at least one read to complete, resubmitting short reads.
+    fn wait(&mut self, fd: types::Fd) -> Result<(), Error> {
+        self.ring.submit_and_wait(1)?;
+        let completed: Vec<(usize, i32)> =
+            self.ring.completion().map(|entry| (entry.user_data() as usize, entry.result())).collect();
+        self.in_flight -= completed.len();
+        for (index, result) in completed {
+            if result < 0 {
+                return Err(Error::from_raw_os_error(-result));
+            }
+            if result == 0 {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "file shrank while it was hashed"));
+            }
+            let slot = &mut self.slots[index];
+            slot.filled += result as usize;
+            if slot.filled < slot.len {
+                self.submit(fd, index)?;
+            }
+        }
+        Ok(())
+    }
+}
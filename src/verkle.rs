@@ -0,0 +1,389 @@
+use std::fmt;
+
+use crate::hasher::{PairHasher, Sha256Hasher};
+use crate::proof::Proof;
+use crate::root::constant_time_eq;
+
+/// Number of children of an inner node of a `VerkleTree`, one per value of
+/// a key byte.
+pub const WIDTH: usize = 256;
+
+/// A 256-bit key in a `VerkleTree`.
+pub type Key = [u8; 32];
+
+/// A vector commitment scheme committing an inner node of a `VerkleTree` to
+/// its `WIDTH` child slots, each of which can be opened on its own.
+///
+/// The tree only relies on this trait, so a scheme with constant-size
+/// openings, such as Pedersen commitments with inner product arguments or
+/// KZG, can be plugged in from an external crate to get the short proofs
+/// Verkle trees are meant for.
+pub trait VectorCommitment: fmt::Debug {
+    /// The proof that a slot holds a value.
+    type Opening: fmt::Debug + Clone + PartialEq + Eq;
+
+    /// Commits to `slots`, which has `WIDTH` entries; unused slots are empty.
+    fn commit(&self, slots: &[Vec<u8>]) -> Vec<u8>;
+
+    /// Returns the opening of the slot at `index` of `slots`.
+    fn open(&self, slots: &[Vec<u8>], index: usize) -> Self::Opening;
+
+    /// Returns `true` if `opening` shows the slot at `index` of the vector
+    /// committed to by `commitment` to hold `slot`.
+    fn verify(&self, commitment: &[u8], index: usize, slot: &[u8], opening: &Self::Opening) -> bool;
+
+    /// Hashes a value stored in the tree.
+    fn hash_value(&self, value: &[u8]) -> Vec<u8>;
+
+    /// Returns the slot of the leaf holding the value with hash
+    /// `value_hash` at `key`.
+    fn hash_leaf(&self, key: &Key, value_hash: &[u8]) -> Vec<u8>;
+}
+
+/// The reference `VectorCommitment`: a binary Merkle tree over the slots,
+/// opened with a `Proof` of eight sibling hashes.
+///
+/// Its openings are no shorter than those of a binary tree, so it serves to
+/// test the structure of a `VerkleTree` and to interoperate with it before
+/// a real polynomial commitment scheme is plugged in.
+#[derive(Debug, Clone, Default)]
+pub struct MerkleCommitment<H = Sha256Hasher> {
+    hasher: H,
+}
+
+impl MerkleCommitment {
+    /// Constructs a new `MerkleCommitment` over SHA-256.
+    pub fn new() -> Self {
+        Self::with_hasher(Sha256Hasher::new())
+    }
+}
+
+impl<H: PairHasher> MerkleCommitment<H> {
+    /// Constructs a new `MerkleCommitment` hashing with `hasher`.
+    pub fn with_hasher(hasher: H) -> Self {
+        Self { hasher }
+    }
+
+    /// Returns the levels of the Merkle tree over `slots`, from the leaves up.
+    fn levels(&self, slots: &[Vec<u8>]) -> Vec<Vec<Vec<u8>>> {
+        let mut levels = vec![slots.iter().map(|slot| self.hasher.hash_leaf(slot)).collect::<Vec<_>>()];
+        while levels[levels.len() - 1].len() > 1 {
+            let level = &levels[levels.len() - 1];
+            let parents = level.chunks(2).map(|pair| self.hasher.hash_pair(&pair[0], &pair[1])).collect();
+            levels.push(parents);
+        }
+        levels
+    }
+}
+
+impl<H: PairHasher> VectorCommitment for MerkleCommitment<H> {
+    type Opening = Proof;
+
+    fn commit(&self, slots: &[Vec<u8>]) -> Vec<u8> {
+        self.levels(slots).pop().and_then(|mut root| root.pop()).unwrap_or_default()
+    }
+
+    fn open(&self, slots: &[Vec<u8>], index: usize) -> Proof {
+        let levels = self.levels(slots);
+        let siblings = levels[..levels.len() - 1]
+            .iter()
+            .enumerate()
+            .map(|(height, level)| level[(index >> height) ^ 1].clone())
+            .collect();
+        Proof::from_siblings(index as u64, 2, siblings).expect("every level has one sibling")
+    }
+
+    fn verify(&self, commitment: &[u8], index: usize, slot: &[u8], opening: &Proof) -> bool {
+        let canonical = opening.leaf_index == index as u64
+            && opening.levels.len() == WIDTH.trailing_zeros() as usize
+            && opening
+                .levels
+                .iter()
+                .enumerate()
+                .all(|(height, level)| level.position == (index >> height) & 1 && level.siblings.len() == 1);
+        canonical && opening.verify_with(&self.hasher, commitment, &self.hasher.hash_leaf(slot))
+    }
+
+    fn hash_value(&self, value: &[u8]) -> Vec<u8> {
+        self.hasher.hash_leaf(value)
+    }
+
+    fn hash_leaf(&self, key: &Key, value_hash: &[u8]) -> Vec<u8> {
+        self.hasher.hash_pair(key, value_hash)
+    }
+}
+
+/// An experimental Verkle tree: a 256-ary trie over 256-bit keys whose inner
+/// nodes commit to their children with a `VectorCommitment`.
+///
+/// Each byte of a key selects a child, and a subtree holding a single key is
+/// a leaf, so keys are found after as many levels as it takes to tell them
+/// apart. A proof opens one slot per inner node on the path of its key, so
+/// with a constant-size commitment scheme proofs stay short even over a
+/// huge key space. The root node is always an inner node; the root of the
+/// tree is its commitment.
+///
+/// The layout of nodes and leaves is not that of Ethereum's EIP-6800, and
+/// may change while the type is experimental.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::VerkleTree;
+///
+/// let mut tree = VerkleTree::new();
+/// tree.insert([1u8; 32], b"one");
+/// tree.insert([2u8; 32], b"two");
+///
+/// let root = tree.root();
+/// let proof = tree.proof(&[2u8; 32]);
+/// assert!(proof.verify(tree.scheme(), &root, &[2u8; 32], Some(b"two")));
+/// assert!(tree.proof(&[3u8; 32]).verify(tree.scheme(), &root, &[3u8; 32], None));
+/// ```
+#[derive(Debug, Clone)]
+pub struct VerkleTree<C: VectorCommitment = MerkleCommitment> {
+    scheme: C,
+    root: Inner,
+    len: usize,
+}
+
+/// A node of a `VerkleTree`.
+#[derive(Debug, Clone)]
+enum Node {
+    Empty,
+    Leaf { key: Key, value: Vec<u8>, slot: Vec<u8> },
+    Inner(Box<Inner>),
+}
+
+/// An inner node of a `VerkleTree` with the slots of its children and
+/// their commitment.
+#[derive(Debug, Clone)]
+struct Inner {
+    children: Vec<Node>,
+    slots: Vec<Vec<u8>>,
+    commitment: Vec<u8>,
+}
+
+/// One inner node on the path of a `VerkleProof`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerkleStep<O> {
+    /// The slot of the child on the path: a commitment, a leaf, or empty.
+    pub slot: Vec<u8>,
+    /// The opening of that slot against the commitment of the node.
+    pub opening: O,
+}
+
+/// A proof of the value of a key of a `VerkleTree`, or of its absence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerkleProof<O> {
+    /// Openings of the inner nodes on the path of the key, from the root down.
+    pub steps: Vec<VerkleStep<O>>,
+    /// The key and value hash of the leaf the path ends at, if any. A leaf
+    /// with a different key shows the proven key to be absent.
+    pub leaf: Option<(Key, Vec<u8>)>,
+}
+
+impl VerkleTree {
+    /// Constructs a new empty `VerkleTree` committing with `MerkleCommitment`.
+    pub fn new() -> Self {
+        Self::with_scheme(MerkleCommitment::new())
+    }
+}
+
+impl Default for VerkleTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: VectorCommitment> VerkleTree<C> {
+    /// Constructs a new empty `VerkleTree` committing with `scheme`.
+    pub fn with_scheme(scheme: C) -> Self {
+        let root = Inner::new(&scheme);
+        Self { scheme, root, len: 0 }
+    }
+
+    /// Returns the commitment scheme, which verifiers need as well.
+    pub fn scheme(&self) -> &C {
+        &self.scheme
+    }
+
+    /// Returns the root commitment.
+    pub fn root(&self) -> Vec<u8> {
+        self.root.commitment.clone()
+    }
+
+    /// Returns the number of keys in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the tree holds no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the value of `key`.
+    pub fn get(&self, key: &Key) -> Option<&[u8]> {
+        let mut inner = &self.root;
+        for &byte in key {
+            match &inner.children[byte as usize] {
+                Node::Inner(child) => inner = child,
+                Node::Leaf { key: leaf_key, value, .. } if leaf_key == key => return Some(value),
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    /// Sets the value of `key`, returning its previous value. Only the
+    /// commitments on the path of the key are recomputed.
+    pub fn insert(&mut self, key: Key, value: &[u8]) -> Option<Vec<u8>> {
+        let slot = self.scheme.hash_leaf(&key, &self.scheme.hash_value(value));
+        let leaf = Node::Leaf { key, value: value.to_vec(), slot };
+        let previous = insert(&self.scheme, &mut self.root, 0, leaf);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        previous
+    }
+
+    /// Removes `key` from the tree, returning its value. Inner nodes left
+    /// with a single leaf collapse into it, so the root only depends on the
+    /// keys and values in the tree.
+    pub fn remove(&mut self, key: &Key) -> Option<Vec<u8>> {
+        let previous = remove(&self.scheme, &mut self.root, 0, key);
+        if previous.is_some() {
+            self.len -= 1;
+        }
+        previous
+    }
+
+    /// Returns a proof of the value of `key`, or of its absence.
+    pub fn proof(&self, key: &Key) -> VerkleProof<C::Opening> {
+        let mut steps = Vec::new();
+        let mut inner = &self.root;
+        for &byte in key {
+            let index = byte as usize;
+            steps.push(VerkleStep { slot: inner.slots[index].clone(), opening: self.scheme.open(&inner.slots, index) });
+            match &inner.children[index] {
+                Node::Inner(child) => inner = child,
+                Node::Leaf { key, value, .. } => {
+                    let leaf = Some((*key, self.scheme.hash_value(value)));
+                    return VerkleProof { steps, leaf };
+                }
+                Node::Empty => break,
+            }
+        }
+        VerkleProof { steps, leaf: None }
+    }
+}
+
+impl<O> VerkleProof<O> {
+    /// Returns `true` if `key` holds `value` under `root`, or is absent if
+    /// `value` is `None`, with the commitments opened by `scheme`.
+    pub fn verify<C>(&self, scheme: &C, root: &[u8], key: &Key, value: Option<&[u8]>) -> bool
+    where
+        C: VectorCommitment<Opening = O>,
+    {
+        if self.steps.is_empty() || self.steps.len() > key.len() {
+            return false;
+        }
+        let mut commitment = root;
+        for (depth, step) in self.steps.iter().enumerate() {
+            if !scheme.verify(commitment, key[depth] as usize, &step.slot, &step.opening) {
+                return false;
+            }
+            commitment = &step.slot;
+        }
+        let last = &self.steps[self.steps.len() - 1].slot;
+        match &self.leaf {
+            None => last.is_empty() && value.is_none(),
+            Some((leaf_key, value_hash)) => {
+                if !constant_time_eq(&scheme.hash_leaf(leaf_key, value_hash), last)
+                    || leaf_key[..self.steps.len()] != key[..self.steps.len()]
+                {
+                    return false;
+                }
+                match value {
+                    Some(value) => leaf_key == key && constant_time_eq(&scheme.hash_value(value), value_hash),
+                    None => leaf_key != key,
+                }
+            }
+        }
+    }
+}
+
+impl Inner {
+    /// Returns an inner node with no children.
+    fn new<C: VectorCommitment>(scheme: &C) -> Self {
+        let slots = vec![Vec::new(); WIDTH];
+        let commitment = scheme.commit(&slots);
+        Self { children: vec![Node::Empty; WIDTH], slots, commitment }
+    }
+
+    /// Recomputes the slot of the child at `index` and the commitment.
+    fn refresh<C: VectorCommitment>(&mut self, scheme: &C, index: usize) {
+        self.slots[index] = match &self.children[index] {
+            Node::Empty => Vec::new(),
+            Node::Leaf { slot, .. } => slot.clone(),
+            Node::Inner(child) => child.commitment.clone(),
+        };
+        self.commitment = scheme.commit(&self.slots);
+    }
+}
+
+/// Inserts `leaf` below `inner`, which sits at `depth`, returning the
+/// previous value of its key.
+fn insert<C: VectorCommitment>(scheme: &C, inner: &mut Inner, depth: usize, leaf: Node) -> Option<Vec<u8>> {
+    let Node::Leaf { key, .. } = &leaf else { unreachable!("only leaves are inserted") };
+    let index = key[depth] as usize;
+    let previous = match &mut inner.children[index] {
+        Node::Inner(child) => insert(scheme, child, depth + 1, leaf),
+        slot @ Node::Empty => {
+            *slot = leaf;
+            None
+        }
+        Node::Leaf { key: existing, value, .. } if existing == key => {
+            let previous = core::mem::take(value);
+            inner.children[index] = leaf;
+            Some(previous)
+        }
+        existing => {
+            // Two keys share this slot: push both down a level
+            let mut child = Inner::new(scheme);
+            let existing = core::mem::replace(existing, Node::Empty);
+            insert(scheme, &mut child, depth + 1, existing);
+            insert(scheme, &mut child, depth + 1, leaf);
+            inner.children[index] = Node::Inner(Box::new(child));
+            None
+        }
+    };
+    inner.refresh(scheme, index);
+    previous
+}
+
+/// Removes `key` from below `inner`, which sits at `depth`, returning its
+/// value.
+fn remove<C: VectorCommitment>(scheme: &C, inner: &mut Inner, depth: usize, key: &Key) -> Option<Vec<u8>> {
+    let index = key[depth] as usize;
+    let previous = match &mut inner.children[index] {
+        Node::Inner(child) => {
+            let previous = remove(scheme, child, depth + 1, key)?;
+            let mut remaining = child.children.iter().filter(|node| !matches!(node, Node::Empty));
+            if let (Some(Node::Leaf { .. }), None) = (remaining.next(), remaining.next()) {
+                let position = child.children.iter().position(|node| matches!(node, Node::Leaf { .. }));
+                inner.children[index] = core::mem::replace(&mut child.children[position.unwrap_or(0)], Node::Empty);
+            }
+            previous
+        }
+        Node::Leaf { key: existing, value, .. } if existing == key => {
+            let previous = core::mem::take(value);
+            inner.children[index] = Node::Empty;
+            previous
+        }
+        _ => return None,
+    };
+    inner.refresh(scheme, index);
+    Some(previous)
+}
@@ -0,0 +1,89 @@
+use js_sys::{Array, Uint8Array};
+use wasm_bindgen::prelude::*;
+
+use crate::hasher::{PairHasher, Sha256Hasher};
+use crate::proof::Proof;
+use crate::{HashTree, RootHash};
+
+/// A `HashTree` exposed to JavaScript as `HashTree`. Requires the `wasm`
+/// feature.
+///
+/// Trees are always binary and hashed with SHA-256, so their roots match
+/// those of `HashTree::new(block_size)` built on the server.
+#[wasm_bindgen(js_name = HashTree)]
+pub struct WasmHashTree {
+    tree: HashTree,
+}
+
+#[wasm_bindgen(js_class = HashTree)]
+impl WasmHashTree {
+    /// Builds a tree with one leaf per `Uint8Array` in `chunks`, e.g. the
+    /// blocks of a download as they were received.
+    #[wasm_bindgen(js_name = fromChunks)]
+    pub fn from_chunks(block_size: usize, chunks: Array) -> Result<WasmHashTree, JsError> {
+        let blocks: Vec<Vec<u8>> = chunks.iter().map(|chunk| Uint8Array::new(&chunk).to_vec()).collect();
+        let tree = HashTree::new(block_size).from_blocks(blocks)?;
+        Ok(Self { tree })
+    }
+
+    /// Builds a tree over `data` split into blocks of `block_size` bytes.
+    #[wasm_bindgen(js_name = fromBytes)]
+    pub fn from_bytes(block_size: usize, data: &[u8]) -> Result<WasmHashTree, JsError> {
+        if block_size == 0 {
+            return Err(JsError::new("block size must not be 0"));
+        }
+        let tree = HashTree::new(block_size).from_slice(data)?;
+        Ok(Self { tree })
+    }
+
+    /// The root hash as lowercase hex, or `undefined` if the tree is empty.
+    #[wasm_bindgen(getter, js_name = rootHash)]
+    pub fn root_hash(&self) -> Option<String> {
+        self.tree.root_hash().map(|root| root.to_hex())
+    }
+
+    /// The number of blocks of the tree.
+    #[wasm_bindgen(getter, js_name = numBlocks)]
+    pub fn num_blocks(&self) -> usize {
+        self.tree.stored_leaves()
+    }
+
+    /// Returns the sibling hashes of the inclusion proof for the block at
+    /// `index`, from the leaves up, as accepted by `verifyProof`. Returns
+    /// `undefined` if there is no such block.
+    pub fn proof(&self, index: usize) -> Option<Array> {
+        let proof = self.tree.proof(index)?;
+        let siblings = Array::new();
+        for sibling in proof.levels.iter().flat_map(|level| &level.siblings) {
+            siblings.push(&Uint8Array::from(sibling.as_slice()));
+        }
+        Some(siblings)
+    }
+}
+
+/// Returns `true` if `block` is the block at `index` of the SHA-256 tree
+/// with the hex `root`. `siblings` holds the `arity - 1` sibling hashes of
+/// every level from the leaves up, as returned by `HashTree.proof`.
+#[wasm_bindgen(js_name = verifyProof)]
+pub fn verify_proof(root: &str, index: usize, block: &[u8], siblings: Array, arity: usize) -> bool {
+    let root: RootHash = match root.parse() {
+        Ok(root) => root,
+        Err(_) => return false,
+    };
+    let hashes = siblings.iter().map(|sibling| Uint8Array::new(&sibling).to_vec()).collect();
+    match Proof::from_siblings(index as u64, arity, hashes) {
+        Some(proof) => root == *proof.compute_root(&Sha256Hasher::new().hash_leaf(block)).as_slice(),
+        None => false,
+    }
+}
+
+/// Returns `true` if the `Uint8Array`s in `chunks`, taken as the blocks of
+/// a file in order, hash to the hex `root`. This is how a browser checks a
+/// completed download against a published root.
+#[wasm_bindgen(js_name = verifyChunks)]
+pub fn verify_chunks(root: &str, block_size: usize, chunks: Array) -> bool {
+    match WasmHashTree::from_chunks(block_size, chunks) {
+        Ok(tree) => tree.tree.root_hash().is_some_and(|computed| computed == root),
+        Err(_) => false,
+    }
+}
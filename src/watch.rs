@@ -0,0 +1,159 @@
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::hasher::block_buf;
+use crate::{Chunker, DirTree, FixedChunker, HashTree, RootHash};
+
+/// Keeps a `DirTree` up to date as files below its root directory change.
+///
+/// Only the files named in filesystem events are rehashed; the rest of the
+/// tree is reused. Requires the `watch` feature.
+pub struct DirWatcher {
+    tree: DirTree,
+    root: PathBuf,
+    events: Receiver<notify::Result<Event>>,
+    _watcher: RecommendedWatcher,
+}
+
+impl DirWatcher {
+    /// Starts watching `root`, which `tree` must have been built from.
+    pub fn new<P: AsRef<Path>>(tree: DirTree, root: P) -> Result<Self, Error> {
+        let root = root.as_ref().canonicalize()?;
+        let (events, watcher) = watch(&root, RecursiveMode::Recursive)?;
+        Ok(Self { tree, root, events, _watcher: watcher })
+    }
+
+    /// Returns the current `DirTree`.
+    pub fn tree(&self) -> &DirTree {
+        &self.tree
+    }
+
+    /// Consumes the watcher and returns the current `DirTree`.
+    pub fn into_tree(self) -> DirTree {
+        self.tree
+    }
+
+    /// Blocks until a change alters the directory root, or `timeout` elapses.
+    /// Returns the new root hash, or `None` on timeout.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<Option<String>, Error> {
+        loop {
+            let paths = match next_paths(&self.events, timeout)? {
+                Some(paths) => paths,
+                None => return Ok(None),
+            };
+            let mut changed = false;
+            for path in paths {
+                if path.starts_with(&self.root) {
+                    changed |= self.tree.update_path(&self.root, &path)?;
+                }
+            }
+            if changed {
+                return Ok(self.tree.root_hash());
+            }
+        }
+    }
+}
+
+/// Keeps the `HashTree` of a single file up to date as the file changes.
+///
+/// Requires the `watch` feature.
+pub struct FileWatcher {
+    tree: HashTree,
+    path: PathBuf,
+    events: Receiver<notify::Result<Event>>,
+    _watcher: RecommendedWatcher,
+}
+
+impl FileWatcher {
+    /// Starts watching the file at `path`, which `tree` must have been built from.
+    pub fn new<P: AsRef<Path>>(tree: HashTree, path: P) -> Result<Self, Error> {
+        let path = path.as_ref().canonicalize()?;
+        let (events, watcher) = watch(&path, RecursiveMode::NonRecursive)?;
+        Ok(Self { tree, path, events, _watcher: watcher })
+    }
+
+    /// Returns the current `HashTree`.
+    pub fn tree(&self) -> &HashTree {
+        &self.tree
+    }
+
+    /// Consumes the watcher and returns the current `HashTree`.
+    pub fn into_tree(self) -> HashTree {
+        self.tree
+    }
+
+    /// Blocks until a change alters the file's root, or `timeout` elapses.
+    /// Returns the new root hash, or `None` on timeout or once the file is
+    /// empty.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<Option<RootHash>, Error> {
+        loop {
+            if next_paths(&self.events, timeout)?.is_none() {
+                return Ok(None);
+            }
+            if !self.path.is_file() {
+                continue;
+            }
+            if self.rehash()? {
+                return Ok(self.tree.root_hash());
+            }
+        }
+    }
+
+    /// Hashes the blocks of the file and replaces the leaves from the first
+    /// changed one on in a copy of the tree, which keeps the hasher, arity,
+    /// padding and salt it was built with, so only the parents covering
+    /// changed or appended blocks are recomputed.
+    /// Returns `true` if the tree changed.
+    fn rehash(&mut self) -> Result<bool, Error> {
+        let mut chunker = FixedChunker::new(BufReader::new(File::open(&self.path)?), self.tree.block_size());
+        let mut buf = block_buf(self.tree.block_size());
+        let (mut leaves, mut lengths) = (Vec::new(), Vec::new());
+        while chunker.next_chunk(&mut buf)? {
+            leaves.push(self.tree.hash_block(&buf)?);
+            lengths.push(buf.len() as u64);
+        }
+        let len = lengths.iter().sum();
+        let keep = self.tree.leaf_hashes().zip(&leaves).take_while(|(old, new)| old == *new).count();
+        if keep == leaves.len() && keep == self.tree.stored_leaves() && self.tree.data_len() == Some(len) {
+            return Ok(false);
+        }
+        let mut tree = self.tree.clone();
+        tree.replace_tail(keep, leaves.split_off(keep), Some(lengths.split_off(keep)), Some(len))?;
+        tree.record_version();
+        self.tree = tree;
+        Ok(true)
+    }
+}
+
+fn watch(path: &Path, mode: RecursiveMode) -> Result<(Receiver<notify::Result<Event>>, RecommendedWatcher), Error> {
+    let (tx, rx) = channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(to_io)?;
+    watcher.watch(path, mode).map_err(to_io)?;
+    Ok((rx, watcher))
+}
+
+/// Waits for the next event and drains any events queued behind it, returning all affected paths.
+fn next_paths(events: &Receiver<notify::Result<Event>>, timeout: Duration) -> Result<Option<Vec<PathBuf>>, Error> {
+    let first = match events.recv_timeout(timeout) {
+        Ok(event) => event,
+        Err(RecvTimeoutError::Timeout) => return Ok(None),
+        Err(RecvTimeoutError::Disconnected) => return Err(Error::new(ErrorKind::BrokenPipe, "watcher disconnected")),
+    };
+
+    let mut paths = first.map_err(to_io)?.paths;
+    for event in events.try_iter() {
+        paths.extend(event.map_err(to_io)?.paths);
+    }
+    paths.sort();
+    paths.dedup();
+    Ok(Some(paths))
+}
+
+fn to_io(error: notify::Error) -> Error {
+    Error::other(error)
+}
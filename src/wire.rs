@@ -0,0 +1,237 @@
+//! A stable binary encoding of proofs, for exchanging them between Rust,
+//! WASM and FFI consumers built against different versions of this crate.
+//!
+//! Every encoding starts with the format version, currently `VERSION`, and
+//! a byte naming the kind of proof, so decoders reject what they do not
+//! understand instead of misreading it. All integers are big-endian and of
+//! fixed width: indices and sizes are `u64`s, counts and positions `u32`s.
+//! Hashes are prefixed with their length as a `u16`; the empty siblings of
+//! trees with `Padding::Promote` have length 0.
+//!
+//! * An inclusion `Proof`: the leaf index, the arity and the number of
+//!   levels, then for every level from the leaves up its position followed
+//!   by its `arity - 1` sibling hashes.
+//! * A multiproof, as returned by `HashTree::multiproof_stream`: the number
+//!   of elements, then every element as a tag byte (0 for a leaf, 1 for a
+//!   node), the leaf index or node level and the hash.
+//! * A `ConsistencyProof` of a `MerkleLog`: the old and new sizes and the
+//!   number of hashes, then the 32-byte hashes without length prefixes.
+//!
+//! # Examples
+//!
+//! ```
+//! use hashtree::{wire, HashTree};
+//!
+//! let tree = HashTree::new(4).from_slice(b"shipped to a browser").unwrap();
+//! let proof = tree.proof(2).unwrap();
+//! let bytes = wire::encode_proof(&proof).unwrap();
+//! assert_eq!(bytes[..2], [wire::VERSION, 1]);
+//! assert_eq!(wire::decode_proof(&bytes).unwrap(), proof);
+//! ```
+
+use alloc::vec::Vec;
+use core::convert::{TryFrom, TryInto};
+
+use crate::io::{Error, ErrorKind};
+#[cfg(feature = "std")]
+use crate::log::ConsistencyProof;
+use crate::multiproof::ProofElement;
+use crate::proof::{Proof, ProofLevel};
+
+/// Version of the encoding written by this crate.
+pub const VERSION: u8 = 1;
+
+const KIND_PROOF: u8 = 1;
+const KIND_MULTIPROOF: u8 = 2;
+#[cfg(feature = "std")]
+const KIND_CONSISTENCY_PROOF: u8 = 3;
+
+const TAG_LEAF: u8 = 0;
+const TAG_NODE: u8 = 1;
+
+/// Encodes an inclusion proof.
+/// Returns an `Error` value if a level does not have `arity - 1` siblings or
+/// a hash is longer than 65535 bytes.
+pub fn encode_proof(proof: &Proof) -> Result<Vec<u8>, Error> {
+    let arity = proof.arity();
+    let mut out = header(KIND_PROOF);
+    out.extend_from_slice(&proof.leaf_index.to_be_bytes());
+    put_u32(&mut out, arity)?;
+    put_u32(&mut out, proof.levels.len())?;
+    for level in &proof.levels {
+        if level.siblings.len() != arity - 1 || level.position >= arity {
+            return Err(Error::new(ErrorKind::InvalidInput, "proof levels do not match the arity"));
+        }
+        put_u32(&mut out, level.position)?;
+        for sibling in &level.siblings {
+            put_hash(&mut out, sibling)?;
+        }
+    }
+    Ok(out)
+}
+
+/// Decodes an inclusion proof encoded by `encode_proof`.
+/// Returns an `Error` value if `bytes` is not a valid encoding of a proof or
+/// has an unsupported version.
+pub fn decode_proof(bytes: &[u8]) -> Result<Proof, Error> {
+    let mut reader = Reader::new(bytes, KIND_PROOF)?;
+    let leaf_index = reader.u64()?;
+    let arity = reader.u32()?;
+    let num_levels = reader.u32()?;
+    if arity < 2 {
+        return Err(invalid("invalid proof arity"));
+    }
+    let mut levels = Vec::new();
+    for _ in 0..num_levels {
+        let position = reader.u32()?;
+        if position >= arity {
+            return Err(invalid("invalid proof position"));
+        }
+        let siblings = (1..arity).map(|_| reader.hash()).collect::<Result<_, _>>()?;
+        levels.push(ProofLevel { position, siblings });
+    }
+    reader.finish()?;
+    Ok(Proof { leaf_index, levels })
+}
+
+/// Encodes the elements of a multiproof.
+/// Returns an `Error` value if a hash is longer than 65535 bytes.
+pub fn encode_multiproof(elements: &[ProofElement]) -> Result<Vec<u8>, Error> {
+    let mut out = header(KIND_MULTIPROOF);
+    put_u32(&mut out, elements.len())?;
+    for element in elements {
+        let (tag, value, hash) = match element {
+            ProofElement::Leaf { index, hash } => (TAG_LEAF, *index, hash),
+            ProofElement::Node { level, hash } => (TAG_NODE, *level, hash),
+        };
+        out.push(tag);
+        out.extend_from_slice(&(value as u64).to_be_bytes());
+        put_hash(&mut out, hash)?;
+    }
+    Ok(out)
+}
+
+/// Decodes the elements of a multiproof encoded by `encode_multiproof`.
+/// Returns an `Error` value if `bytes` is not a valid encoding of a
+/// multiproof or has an unsupported version.
+pub fn decode_multiproof(bytes: &[u8]) -> Result<Vec<ProofElement>, Error> {
+    let mut reader = Reader::new(bytes, KIND_MULTIPROOF)?;
+    let count = reader.u32()?;
+    let mut elements = Vec::new();
+    for _ in 0..count {
+        let tag = reader.take(1)?[0];
+        let value = reader.usize64()?;
+        let hash = reader.hash()?;
+        elements.push(match tag {
+            TAG_LEAF => ProofElement::Leaf { index: value, hash },
+            TAG_NODE => ProofElement::Node { level: value, hash },
+            _ => return Err(invalid("invalid multiproof element tag")),
+        });
+    }
+    reader.finish()?;
+    Ok(elements)
+}
+
+/// Encodes a consistency proof of a `MerkleLog`.
+/// Returns an `Error` value if the proof has more than `u32::MAX` hashes.
+#[cfg(feature = "std")]
+pub fn encode_consistency_proof(proof: &ConsistencyProof) -> Result<Vec<u8>, Error> {
+    let mut out = header(KIND_CONSISTENCY_PROOF);
+    out.extend_from_slice(&proof.old_size.to_be_bytes());
+    out.extend_from_slice(&proof.new_size.to_be_bytes());
+    put_u32(&mut out, proof.path.len())?;
+    for hash in &proof.path {
+        out.extend_from_slice(hash);
+    }
+    Ok(out)
+}
+
+/// Decodes a consistency proof encoded by `encode_consistency_proof`.
+/// Returns an `Error` value if `bytes` is not a valid encoding of a
+/// consistency proof or has an unsupported version.
+#[cfg(feature = "std")]
+pub fn decode_consistency_proof(bytes: &[u8]) -> Result<ConsistencyProof, Error> {
+    let mut reader = Reader::new(bytes, KIND_CONSISTENCY_PROOF)?;
+    let old_size = reader.u64()?;
+    let new_size = reader.u64()?;
+    let count = reader.u32()?;
+    let mut path = Vec::new();
+    for _ in 0..count {
+        path.push(reader.take(32)?.try_into().unwrap());
+    }
+    reader.finish()?;
+    Ok(ConsistencyProof { old_size, new_size, path })
+}
+
+fn header(kind: u8) -> Vec<u8> {
+    alloc::vec![VERSION, kind]
+}
+
+fn put_u32(out: &mut Vec<u8>, value: usize) -> Result<(), Error> {
+    let value = u32::try_from(value).map_err(|_| Error::new(ErrorKind::InvalidInput, "proof is too large to encode"))?;
+    out.extend_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+
+fn put_hash(out: &mut Vec<u8>, hash: &[u8]) -> Result<(), Error> {
+    let len = u16::try_from(hash.len()).map_err(|_| Error::new(ErrorKind::InvalidInput, "hash is longer than 65535 bytes"))?;
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(hash);
+    Ok(())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Reads fields from the front of an encoding.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// Checks the version and kind at the front of `bytes`.
+    fn new(bytes: &'a [u8], kind: u8) -> Result<Self, Error> {
+        let mut reader = Reader(bytes);
+        let header = reader.take(2)?;
+        if header[0] != VERSION {
+            return Err(invalid("unsupported proof encoding version"));
+        }
+        if header[1] != kind {
+            return Err(invalid("encoding holds a different kind of proof"));
+        }
+        Ok(reader)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < len {
+            return Err(invalid("proof encoding is truncated"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<usize, Error> {
+        let value = u32::from_be_bytes(self.take(4)?.try_into().unwrap());
+        usize::try_from(value).map_err(|_| invalid("proof is too large for this platform"))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn usize64(&mut self) -> Result<usize, Error> {
+        usize::try_from(self.u64()?).map_err(|_| invalid("proof is too large for this platform"))
+    }
+
+    fn hash(&mut self) -> Result<Vec<u8>, Error> {
+        let len = u16::from_be_bytes(self.take(2)?.try_into().unwrap());
+        Ok(self.take(len as usize)?.to_vec())
+    }
+
+    fn finish(&self) -> Result<(), Error> {
+        if !self.0.is_empty() {
+            return Err(invalid("proof encoding has trailing bytes"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use core::convert::TryFrom;
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+use sha2::Sha256;
+
+use crate::hasher::{fingerprint, PairHasher, PrefixedHasher, Sha256Hasher};
+use crate::log::ConsistencyProof;
+use crate::multiproof::{ProofElement, StreamingVerifier};
+use crate::proof::Proof;
+use crate::root::constant_time_eq;
+use crate::store::NodeStore;
+use crate::HashTree;
+
+const MAGIC: &[u8; 4] = b"HTWT";
+const FORMAT_VERSION: u16 = 1;
+const HEADER_LEN: usize = 35;
+
+/// The root of a `HashTree` with the parameters needed to check proofs
+/// against it, for verifiers that never hold the tree itself, such as light
+/// clients and firmware.
+///
+/// A witness checks inclusion proofs and streamed multiproofs of blocks,
+/// and, for trees built with `Preset::Rfc6962`, consistency proofs of a
+/// `MerkleLog` that let it move on to a larger tree. It serializes to a
+/// few dozen bytes with `to_bytes`.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, Witness};
+///
+/// let tree = HashTree::new(4).from_slice(b"firmware image").unwrap();
+/// let bytes = tree.witness().unwrap().to_bytes().unwrap();
+///
+/// // On the device
+/// let witness = Witness::from_bytes(&bytes).unwrap();
+/// assert!(witness.verify_block(2, b" ima", &tree.proof(2).unwrap()));
+/// assert!(!witness.verify_block(2, b"evil", &tree.proof(2).unwrap()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Witness {
+    root: Vec<u8>,
+    num_blocks: u64,
+    block_size: u64,
+    arity: usize,
+    hasher: Arc<dyn PairHasher>,
+}
+
+impl Witness {
+    /// Constructs a `Witness` of `root` for a binary tree of `num_blocks`
+    /// blocks hashed with SHA-256, the defaults of `HashTree::new`.
+    pub fn new(root: &[u8], num_blocks: u64) -> Self {
+        Self { root: root.to_vec(), num_blocks, block_size: 0, arity: 2, hasher: Arc::new(Sha256Hasher::new()) }
+    }
+
+    /// Sets the hasher the tree was built with.
+    pub fn hasher<H: PairHasher + 'static>(mut self, hasher: H) -> Self {
+        self.hasher = Arc::new(hasher);
+        self
+    }
+
+    /// Sets the arity the tree was built with; values below 2 are raised to 2.
+    pub fn arity(mut self, arity: usize) -> Self {
+        self.arity = arity.max(2);
+        self
+    }
+
+    /// Sets the block size the tree was built with. It is only recorded for
+    /// the verifier to know how to split the data; 0 means unknown.
+    pub fn block_size(mut self, block_size: u64) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Returns the root hash.
+    pub fn root(&self) -> &[u8] {
+        &self.root
+    }
+
+    /// Returns the number of blocks of the tree.
+    pub fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    /// Returns the block size of the tree, 0 if unknown.
+    pub fn get_block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// Returns the arity of the tree.
+    pub fn get_arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Returns `true` if `proof` shows the block with hash `leaf_hash` to be
+    /// block `index` of the tree. The proof must take the path of `index`
+    /// in a tree of `num_blocks()` blocks, so it cannot pass a block off as
+    /// being at another index.
+    pub fn verify(&self, index: u64, leaf_hash: &[u8], proof: &Proof) -> bool {
+        if index >= self.num_blocks || proof.leaf_index != index || proof.levels.len() != self.depth() {
+            return false;
+        }
+        let mut node = index;
+        for level in &proof.levels {
+            if level.siblings.len() != self.arity - 1 || level.position as u64 != node % self.arity as u64 {
+                return false;
+            }
+            node /= self.arity as u64;
+        }
+        proof.verify_with(self.hasher.as_ref(), &self.root, leaf_hash)
+    }
+
+    /// Like `verify`, but hashes the contents of the block first.
+    pub fn verify_block(&self, index: u64, block: &[u8], proof: &Proof) -> bool {
+        self.verify(index, &self.hasher.hash_leaf(block), proof)
+    }
+
+    /// Returns `true` if the elements of a multiproof, as returned by
+    /// `HashTree::multiproof_stream`, fit together into the root. Callers
+    /// check the leaves of the stream against the blocks they expect.
+    pub fn verify_stream(&self, elements: &[ProofElement]) -> bool {
+        let mut verifier = StreamingVerifier::new(self.hasher.as_ref(), self.arity);
+        elements.iter().all(|element| verifier.push(element).is_ok()) && verifier.finish(&self.root)
+    }
+
+    /// Returns `true` if `proof` shows this tree to be a prefix of the tree
+    /// of `proof.new_size` blocks with root `new_root`. Only trees hashed as
+    /// in RFC 6962, e.g. built with `Preset::Rfc6962`, have consistency
+    /// proofs; this returns `false` for any other hasher or arity.
+    pub fn verify_consistency(&self, proof: &ConsistencyProof, new_root: &[u8]) -> bool {
+        let rfc6962 = self.arity == 2 && fingerprint(self.hasher.as_ref()) == fingerprint(&PrefixedHasher::<Sha256>::new());
+        let (Ok(old_root), Ok(new_root)) = (<&[u8; 32]>::try_from(&self.root[..]), <&[u8; 32]>::try_from(new_root)) else {
+            return false;
+        };
+        rfc6962 && proof.old_size == self.num_blocks && proof.verify(old_root, new_root)
+    }
+
+    /// Moves the witness on to the larger tree with `new_root` if `proof`
+    /// shows this tree to be a prefix of it, see `verify_consistency`.
+    /// Returns `false`, leaving the witness unchanged, otherwise.
+    pub fn advance(&mut self, proof: &ConsistencyProof, new_root: &[u8]) -> bool {
+        if !self.verify_consistency(proof, new_root) {
+            return false;
+        }
+        self.root = new_root.to_vec();
+        self.num_blocks = proof.new_size;
+        true
+    }
+
+    /// Encodes the witness: a 35-byte header holding the magic `HTWT`, the
+    /// format version, the hasher fingerprint, the number of blocks, block
+    /// size, arity and root length, followed by the root.
+    /// Returns an `Error` value if the root is longer than 255 bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        if self.root.len() > usize::from(u8::MAX) {
+            return Err(Error::new(ErrorKind::InvalidInput, "root hash is longer than 255 bytes"));
+        }
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+        bytes.extend_from_slice(&fingerprint(self.hasher.as_ref()));
+        bytes.extend_from_slice(&self.num_blocks.to_be_bytes());
+        bytes.extend_from_slice(&self.block_size.to_be_bytes());
+        bytes.extend_from_slice(&(self.arity as u32).to_be_bytes());
+        bytes.push(self.root.len() as u8);
+        bytes.extend_from_slice(&self.root);
+        Ok(bytes)
+    }
+
+    /// Decodes a witness of a tree hashed with SHA-256, see `from_bytes_with`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Self::from_bytes_with(bytes, Sha256Hasher::new())
+    }
+
+    /// Decodes a witness encoded by `to_bytes` of a tree hashed with `hasher`.
+    /// Returns an `Error` value if `bytes` is malformed or the witness was
+    /// taken with a different hash algorithm.
+    pub fn from_bytes_with<H: PairHasher + 'static>(bytes: &[u8], hasher: H) -> Result<Self, Error> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return Err(invalid("not a hash tree witness"));
+        }
+        let field = |start: usize, len: usize| bytes[start..start + len].iter().fold(0u64, |n, &b| n << 8 | b as u64);
+        if field(4, 2) != FORMAT_VERSION as u64 {
+            return Err(invalid("unsupported witness version"));
+        }
+        if bytes[6..14] != fingerprint(&hasher) {
+            return Err(invalid("witness was taken with a different hash algorithm"));
+        }
+        if bytes.len() != HEADER_LEN + bytes[34] as usize {
+            return Err(invalid("witness is truncated"));
+        }
+        let witness = Self::new(&bytes[HEADER_LEN..], field(14, 8)).block_size(field(22, 8)).arity(field(30, 4) as usize);
+        Ok(witness.hasher(hasher))
+    }
+
+    /// Returns the number of levels of proofs in a tree of `num_blocks()` blocks.
+    fn depth(&self) -> usize {
+        let mut nodes = self.num_blocks;
+        let mut depth = 0;
+        while nodes > 1 {
+            nodes = nodes.div_ceil(self.arity as u64);
+            depth += 1;
+        }
+        depth
+    }
+}
+
+impl PartialEq for Witness {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(&self.root, &other.root)
+            && self.num_blocks == other.num_blocks
+            && self.block_size == other.block_size
+            && self.arity == other.arity
+            && fingerprint(self.hasher.as_ref()) == fingerprint(other.hasher.as_ref())
+    }
+}
+
+impl Eq for Witness {}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Returns a `Witness` of the root of the tree, or `None` if the tree is
+    /// empty.
+    pub fn witness(&self) -> Option<Witness> {
+        let root = self.root_hash_bytes()?;
+        let witness = Witness::new(root, self.num_blocks()).block_size(self.block_size() as u64).arity(self.get_arity());
+        Some(Witness { hasher: self.shared_hasher(), ..witness })
+    }
+}
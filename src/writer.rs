@@ -0,0 +1,107 @@
+use std::io::{Error, ErrorKind, Write};
+
+use crate::hasher::{block_buf, BlockBuf};
+use crate::store::NodeStore;
+use crate::HashTree;
+
+/// A sink building a `HashTree` over everything written to it, so code that
+/// already writes to a `Write`, such as an encoder or `std::io::copy`, can
+/// build a tree in-line instead of reading the data back.
+///
+/// Writes of any length are re-chunked into blocks of the tree's block size;
+/// whole blocks within a write are hashed without being copied. `flush` does
+/// not end a block, only `finish` hashes the final partial one and builds
+/// the tree. After a write fails, e.g. because the store could not be
+/// written, the writer should be dropped.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Write;
+/// use hashtree::HashTree;
+///
+/// let data: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+/// let mut writer = HashTree::new(1024).writer();
+/// for chunk in data.chunks(333) {
+///     writer.write_all(chunk).unwrap();
+/// }
+/// assert_eq!(writer.bytes_written(), 10_000);
+/// let tree = writer.finish().unwrap();
+/// assert_eq!(tree, HashTree::new(1024).from_slice(&data).unwrap());
+/// ```
+#[derive(Debug)]
+pub struct HashTreeWriter<S = Vec<Vec<u8>>> {
+    tree: HashTree<S>,
+    /// The start of the next block, shorter than the block size.
+    buf: BlockBuf,
+    written: u64,
+}
+
+impl<S: NodeStore> HashTree<S> {
+    /// Returns a `HashTreeWriter` that builds `self`, which should be empty,
+    /// over the data written to it. An automatic block size, see
+    /// `BlockSize::Auto`, is resolved for input of unknown length.
+    pub fn writer(mut self) -> HashTreeWriter<S> {
+        self.resolve_block_size(None);
+        let buf = block_buf(self.block_size());
+        HashTreeWriter { tree: self, buf, written: 0 }
+    }
+}
+
+impl<S: NodeStore> HashTreeWriter<S> {
+    /// Returns the number of bytes written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.written
+    }
+
+    /// Returns the number of whole blocks hashed so far.
+    pub fn num_blocks(&self) -> usize {
+        // Until `finish`, the store holds the leaves only
+        self.tree.num_nodes()
+    }
+
+    /// Hashes the final partial block, if any, and builds the tree over
+    /// everything written.
+    /// Returns an `Error` value if the store could not be written.
+    pub fn finish(mut self) -> Result<HashTree<S>, Error> {
+        if !self.buf.is_empty() {
+            self.tree.push_blocks(&[&self.buf])?;
+        }
+        self.tree.finish_pushed(self.written)?;
+        Ok(self.tree)
+    }
+}
+
+impl<S: NodeStore> Write for HashTreeWriter<S> {
+    /// Takes all of `data`, hashing every block it completes.
+    /// Returns an `Error` value if the block size is 0 or the store could
+    /// not be written.
+    fn write(&mut self, mut data: &[u8]) -> Result<usize, Error> {
+        let block_size = self.tree.block_size();
+        if block_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "cannot write to a tree with a block size of 0"));
+        }
+        let len = data.len();
+        if !self.buf.is_empty() {
+            let take = (block_size - self.buf.len()).min(data.len());
+            self.buf.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buf.len() == block_size {
+                self.tree.push_blocks(&[&self.buf])?;
+                self.buf.clear();
+            }
+        }
+        let whole = data.len() / block_size * block_size;
+        for batch in data[..whole].chunks(block_size * self.tree.leaf_batch()) {
+            let blocks: Vec<&[u8]> = batch.chunks(block_size).collect();
+            self.tree.push_blocks(&blocks)?;
+        }
+        self.buf.extend_from_slice(&data[whole..]);
+        self.written += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::blocks::BlockStore;
+
+/// Tag of a block stored as it is, because compressing did not shrink it.
+const RAW: u8 = 0;
+/// Tag of a block stored as a zstd frame.
+const ZSTD: u8 = 1;
+/// Largest block decompressed, which guards against decompression bombs
+/// placed in the underlying store.
+const MAX_BLOCK_LEN: u64 = 1 << 30;
+
+/// Decompresses a zstd frame, which must record its content size.
+fn decompress(frame: &[u8]) -> Result<Vec<u8>, Error> {
+    let size = match ::zstd::zstd_safe::get_frame_content_size(frame) {
+        Ok(Some(size)) if size <= MAX_BLOCK_LEN => size,
+        _ => return Err(Error::new(ErrorKind::InvalidData, "stored block is not a valid zstd frame")),
+    };
+    ::zstd::bulk::decompress(frame, size as usize).map_err(|error| Error::new(ErrorKind::InvalidData, error))
+}
+
+/// Sizes of the blocks written through a `ZstdBlockStore`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompressionStats {
+    /// Number of blocks written.
+    pub blocks: u64,
+    /// Length of the blocks before compression.
+    pub plain_bytes: u64,
+    /// Length of the blocks as written to the underlying store.
+    pub stored_bytes: u64,
+}
+
+/// A `BlockStore` compressing blocks with zstd before passing them on to
+/// another store, and decompressing them when they are read back, so
+/// `HashTree::get_block` works unchanged.
+///
+/// Blocks stay keyed by the leaf hash of their plaintext, which is what the
+/// tree commits to. Each stored block starts with a tag byte telling
+/// whether the rest is a zstd frame or, if compressing did not shrink the
+/// block, the block itself.
+///
+/// # Examples
+///
+/// ```
+/// use hashtree::{HashTree, MemoryBlockStore, ZstdBlockStore};
+/// use std::sync::Arc;
+///
+/// let store = Arc::new(ZstdBlockStore::new(MemoryBlockStore::new(), 3));
+/// let tree = HashTree::new(4096).block_store(store.clone()).from_slice(&[0; 65536]).unwrap();
+/// assert_eq!(tree.get_block(3).unwrap(), vec![0; 4096]);
+/// assert!(store.stats().stored_bytes < 1000);
+/// ```
+pub struct ZstdBlockStore<B> {
+    inner: B,
+    level: i32,
+    blocks: AtomicU64,
+    plain_bytes: AtomicU64,
+    stored_bytes: AtomicU64,
+}
+
+impl<B: BlockStore> ZstdBlockStore<B> {
+    /// Wraps `inner`, compressing at `level`, 1 to 22 with 3 the usual
+    /// default of zstd.
+    pub fn new(inner: B, level: i32) -> Self {
+        Self {
+            inner,
+            level,
+            blocks: AtomicU64::new(0),
+            plain_bytes: AtomicU64::new(0),
+            stored_bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the underlying store.
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Returns the sizes of the blocks written so far. Blocks the store
+    /// already held are skipped and not counted.
+    pub fn stats(&self) -> CompressionStats {
+        CompressionStats {
+            blocks: self.blocks.load(Ordering::Relaxed),
+            plain_bytes: self.plain_bytes.load(Ordering::Relaxed),
+            stored_bytes: self.stored_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the number of bytes the block with leaf hash `hash` takes in
+    /// the underlying store, or `None` if it is not stored.
+    pub fn stored_len(&self, hash: &[u8]) -> Result<Option<u64>, Error> {
+        Ok(self.inner.get(hash)?.map(|stored| stored.len() as u64))
+    }
+}
+
+impl<B> fmt::Debug for ZstdBlockStore<B>
+where
+    B: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ZstdBlockStore").field("inner", &self.inner).field("level", &self.level).finish()
+    }
+}
+
+impl<B: BlockStore> BlockStore for ZstdBlockStore<B> {
+    fn put(&self, hash: &[u8], data: &[u8]) -> Result<(), Error> {
+        if self.inner.contains(hash)? {
+            return Ok(());
+        }
+        let mut stored = vec![ZSTD];
+        stored.extend(::zstd::bulk::compress(data, self.level)?);
+        if stored.len() > data.len() {
+            stored.clear();
+            stored.push(RAW);
+            stored.extend_from_slice(data);
+        }
+        self.inner.put(hash, &stored)?;
+        self.blocks.fetch_add(1, Ordering::Relaxed);
+        self.plain_bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+        self.stored_bytes.fetch_add(stored.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn get(&self, hash: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let stored = match self.inner.get(hash)? {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+        match stored.split_first() {
+            Some((&RAW, data)) => Ok(Some(data.to_vec())),
+            Some((&ZSTD, frame)) => decompress(frame).map(Some),
+            _ => Err(Error::new(ErrorKind::InvalidData, "stored block has an unknown encoding")),
+        }
+    }
+
+    fn contains(&self, hash: &[u8]) -> Result<bool, Error> {
+        self.inner.contains(hash)
+    }
+
+    fn remove(&self, hash: &[u8]) -> Result<bool, Error> {
+        self.inner.remove(hash)
+    }
+
+    fn hashes(&self) -> Result<Vec<Vec<u8>>, Error> {
+        self.inner.hashes()
+    }
+}